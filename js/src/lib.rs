@@ -1,179 +1,55 @@
 mod utils;
+mod settings;
 
 use cfg_if::cfg_if;
-use std::mem;
-use std::ops::{Index, IndexMut};
-use lasgun::{self, Pixel};
-use wasm_bindgen::prelude::*;
-use self::utils::Native;
+use std::rc::Rc;
+use wasm_bindgen::{prelude::*, Clamped};
+use self::{utils::{Native, ErrorKind}, settings::Projection};
 
 cfg_if! {
     // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
-    // allocator.
-    if #[cfg(feature = "wee_alloc")] {
+    // allocator. It isn't thread-safe, so threaded builds keep the default.
+    if #[cfg(all(feature = "wee_alloc", not(feature = "threads")))] {
         extern crate wee_alloc;
         #[global_allocator]
         static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
     }
 }
 
+/// Start the given number of web workers for rendering in parallel. Call and
+/// await `initThreadPool(navigator.hardwareConcurrency)` once before
+/// capturing; each capture then splits the image into tiles across them.
+#[cfg(feature = "threads")]
+pub use wasm_bindgen_rayon::init_thread_pool;
+
 #[wasm_bindgen]
 extern {
-    /// Duck-type for Scene settings representation in JavaScript
-    pub type SceneSettings;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn ambient(this: &SceneSettings) -> Option<Box<[JsValue]>>; // Optional vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn smoothing(this: &SceneSettings) -> Option<bool>;
-
-    /// Duck type for Camera settings in JavaScript
-    pub type CameraSettings;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn projection(this: &CameraSettings) -> Option<String>; // "perspective" or "orthographic" (defaults to perspective)
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn fov(this: &CameraSettings) -> Option<f64>;  // for persective only, defaults to 45
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn scale(this: &CameraSettings) -> Option<f64>;  // for orthographic only, defaults to fov x view magic
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn origin(this: &CameraSettings) -> Box<[JsValue]>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn look(this: &CameraSettings) -> Box<[JsValue]>; // Point
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn up(this: &CameraSettings) -> Box<[JsValue]>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn supersampling(this: &CameraSettings) -> Option<u8>;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn aperture(this: &CameraSettings) -> Option<f64>; // Radius
-
-    /// Duck-type Plastic material settings
-    /// For JavaScript objects that have the form
-    /// {
-    ///     kd: [number, number, number],
-    ///     ks: [number, number, number],
-    ///     roughness?: number // f64, with range [0, 1], defaults to 0
-    /// }
-    pub type Plastic;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn kd(this: &Plastic) -> Box<[JsValue]>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn ks(this: &Plastic) -> Box<[JsValue]>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn roughness(this: &Plastic) -> Option<f64>;
-
-    /// Duck-type Matte material settings
-    /// For JavaScript objects that have the form
-    /// {
-    ///     kd: [number, number, number],
-    ///     sigma?: number // f64 with range [0, 1]
-    /// }
-    pub type Matte;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn kd(this: &Matte) -> Box<[JsValue]>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn sigma(this: &Matte) -> Option<f64>;
-
-    /// Duck-type Metal material settings
-    /// For JavaScript objects that have the form
-    /// {
-    ///     eta: [number, number, number],
-    ///     k: [number, number, number],
-    ///     roughness?: number // (f64, with range [0, 1])
-    ///     u_roughness?: number // (f64, with range [0, 1])
-    ///     v_roughness?: number // (f64, with range [0, 1])
-    /// }
-    pub type Metal;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn eta(this: &Metal) -> Box<[JsValue]>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn k(this: &Metal) -> Box<[JsValue]>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn roughness(this: &Metal) -> Option<f64>;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn u_roughness(this: &Metal) -> Option<f64>;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn v_roughness(this: &Metal) -> Option<f64>;
-
-    /// Duck-type Mirror material settings
-    /// For JavaScript objects that have the form
-    /// {
-    ///     kr?: [number, number, number], (defaults to [1, 1, 1])
-    /// }
-    pub type Mirror;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn kr(this: &Mirror) -> Option<Box<[JsValue]>>; // Vector
-
-    /// Duck-type Glass material settings
-    /// For JavaScript objects that have the form
-    /// {
-    ///     kr?: [number, number, number], // defaults to [1, 1, 1]
-    ///     kt?: [number, number, number], // defaults to [1, 1, 1]
-    ///     eta?: number // f64, with range [0, 1], defaults to 1.5
-    /// }
-    pub type Glass;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn kr(this: &Glass) -> Option<Box<[JsValue]>>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn kt(this: &Glass) -> Option<Box<[JsValue]>>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn eta(this: &Glass) -> Option<f64>;
-
-    /// Duck-type Point Light settings
-    /// For JavaScript objects that have the form
-    /// {
-    ///     position: [number, number, number],
-    ///     intensity: [number, number, number],
-    ///     falloff: [number, number, number]
-    /// }
-    pub type PointLight;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn position(this: &PointLight) -> Box<[JsValue]>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn intensity(this: &PointLight) -> Box<[JsValue]>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn falloff(this: &PointLight) -> Box<[JsValue]>; // Vector
-
-    /// Ducktype for Sphere settings
-    pub type Sphere;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn origin(this: &Sphere) -> Box<[JsValue]>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn radius(this: &Sphere) -> f64;
-
-    /// Ducktype for Cube settings
-    pub type Cube;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn origin(this: &Cube) -> Box<[JsValue]>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn dim(this: &Cube) -> f64;
-
-    /// Ducktype for Box settings
-    pub type Cuboid;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn start(this: &Cuboid) -> Box<[JsValue]>; // Vector
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn end(this: &Cuboid) -> Box<[JsValue]>; // Vector
-
-    // Duck type for radial background definition
-    pub type RadialBackground;
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn inner(this: &RadialBackground) -> Box<[JsValue]>; // Color
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn outer(this: &RadialBackground) -> Box<[JsValue]>; // Color
-    #[wasm_bindgen(method, getter, structural)]
-    pub fn scale(this: &RadialBackground) -> Option<f64>; // Color
-
     fn alert(s: &str);
+
+    /// Pixels for a canvas, as created by Film::image_data
+    pub type ImageData;
+    #[wasm_bindgen(constructor, catch)]
+    fn new(data: Clamped<&[u8]>, width: u32, height: u32) -> Result<ImageData, JsValue>;
+
+    /// Context of a canvas or offscreen canvas to draw films onto
+    pub type CanvasRenderingContext2D;
+    #[wasm_bindgen(method, structural, js_name = putImageData)]
+    fn put_image_data(this: &CanvasRenderingContext2D, data: &ImageData, x: f64, y: f64);
+
+    /// Milliseconds on a monotonic clock, in windows and workers alike
+    #[wasm_bindgen(js_namespace = performance)]
+    fn now() -> f64;
 }
 
 /// Alias for the scene constructor
 #[wasm_bindgen]
-pub fn scene(settings: &SceneSettings) -> Scene {
+pub fn scene(settings: JsValue) -> Result<Scene, JsValue> {
     Scene::new(settings)
 }
 
 /// Alias for the Camera constructor
 #[wasm_bindgen]
-pub fn camera(settings: &CameraSettings) -> Camera {
+pub fn camera(settings: JsValue) -> Result<Camera, JsValue> {
     Camera::new(settings)
 }
 
@@ -184,18 +60,53 @@ pub fn film(width: u32, height: u32) -> Film {
     Film::new(width, height)
 }
 
-/// Capture the whole scene onto the given film
+/// Capture the whole scene onto the given film, in parallel on the thread
+/// pool in builds with the threads feature
 #[wasm_bindgen]
 pub fn capture(scene: &Scene, film: &mut Film) {
     let accel = lasgun::Accel::from(scene.as_native());
-    lasgun::capture_subset(0, 1, &accel, film)
+    film.0.tonemap = scene.as_native().tonemap;
+    lasgun::capture_accel(&accel, film.as_native_mut())
 }
 
 /// Capture subset k ∈ [0, n-1] of n of the accelerated scene-structure onto the
 /// given film
 #[wasm_bindgen]
 pub fn capture_subset(k: usize, n: usize, accel: &Accel, film: &mut Film) {
-    lasgun::capture_subset(k, n, accel.as_native(), film)
+    film.0.tonemap = accel.as_native().scene.tonemap;
+    lasgun::capture_subset(k, n, accel.as_native(), film.as_native_mut())
+}
+
+/// Capture subset k ∈ [0, n-1] of n of the accelerated scene-structure onto the
+/// given film, as seen through the given camera instead of the scene's own.
+/// Lets viewers re-render after moving the camera without rebuilding the
+/// acceleration structure.
+#[wasm_bindgen]
+pub fn capture_subset_with_camera(k: usize, n: usize, accel: &Accel, camera: &Camera, film: &mut Film) {
+    film.0.tonemap = accel.as_native().scene.tonemap;
+    lasgun::capture_subset_with_camera(k, n, accel.as_native(), camera.as_native(), film.as_native_mut())
+}
+
+/// Number of hunks that `capture_hunk` splits an image of the given
+/// dimensions into
+#[wasm_bindgen]
+pub fn hunk_count(width: u32, height: u32) -> usize {
+    lasgun::hunk_count(width, height)
+}
+
+/// Capture hunk k of an image of the given dimensions, a tile of 16x16 pixels
+/// counted in row-major order. Each hunk owns its pixels, so workers may post
+/// them to the main thread as transferable buffers as soon as they're done.
+#[wasm_bindgen]
+pub fn capture_hunk(k: usize, accel: &Accel, width: u32, height: u32) -> FilmDataHunk {
+    FilmDataHunk(lasgun::capture_hunk(k, accel.as_native(), width, height))
+}
+
+/// Capture hunk k of an image of the given dimensions as seen through the
+/// given camera instead of the scene's own
+#[wasm_bindgen]
+pub fn capture_hunk_with_camera(k: usize, accel: &Accel, camera: &Camera, width: u32, height: u32) -> FilmDataHunk {
+    FilmDataHunk(lasgun::capture_hunk_with_camera(k, accel.as_native(), camera.as_native(), width, height))
 }
 
 // Triangle mesh reference in a scene
@@ -208,6 +119,26 @@ pub struct ObjRef(lasgun::scene::ObjRef); impl Native for ObjRef {
     #[inline] fn as_native_mut(&mut self) -> &mut Self::Output { &mut self.0 }
 }
 
+// Material added to a scene, for use by shapes
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct MaterialRef(lasgun::MaterialRef); impl Native for MaterialRef {
+    type Output = lasgun::MaterialRef;
+    #[inline] fn into_native(self) -> Self::Output { self.0 }
+    #[inline] fn as_native(&self) -> &Self::Output { &self.0 }
+    #[inline] fn as_native_mut(&mut self) -> &mut Self::Output { &mut self.0 }
+}
+
+// Texture added to a scene, for use by materials
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct TextureRef(lasgun::TextureRef); impl Native for TextureRef {
+    type Output = lasgun::TextureRef;
+    #[inline] fn into_native(self) -> Self::Output { self.0 }
+    #[inline] fn as_native(&self) -> &Self::Output { &self.0 }
+    #[inline] fn as_native_mut(&mut self) -> &mut Self::Output { &mut self.0 }
+}
+
 #[wasm_bindgen]
 pub struct Camera(lasgun::Camera); impl Native for Camera {
     type Output = lasgun::Camera;
@@ -218,23 +149,37 @@ pub struct Camera(lasgun::Camera); impl Native for Camera {
 
 #[wasm_bindgen]
 impl Camera {
-    pub fn new(settings: &CameraSettings) -> Camera {
-        let projection = settings.projection().unwrap_or("perspective".to_string());
-        let fov = settings.fov().unwrap_or(45.);
-        let scale = settings.scale().unwrap_or(1.); // TODO: Default scale from fov
-        let mut camera = match projection.as_str() {
-            "perspective" => lasgun::Camera::perspective(fov),
-            "orthographic" => lasgun::Camera::orthographic(scale),
-            "isometric" => lasgun::Camera::orthographic(scale), // same thing
-            _ => lasgun::Camera::perspective(fov) // TODO: Panic instead?
+    pub fn new(settings: JsValue) -> Result<Camera, JsValue> {
+        let settings: settings::CameraSettings = settings::from_js("camera", settings)?;
+        let fov = settings.fov;
+        if !(fov > 0. && fov < 180.) {
+            return Err(utils::error(ErrorKind::Settings, format!("`fov` must be between 0 and 180 degrees, got {}", fov)))
+        }
+        let (origin, look, up) = (settings.origin.0, settings.look.0, settings.up.0);
+
+        // Orthographic cameras frame as much as the perspective one would at
+        // the look-at point unless given a scale
+        let distance = (0..3).map(|i| (look[i] - origin[i]).powi(2)).sum::<f64>().sqrt();
+        let mut camera = match settings.projection {
+            Projection::Perspective => lasgun::Camera::perspective(fov),
+            Projection::Orthographic | Projection::TrueIsometric => match settings.scale {
+                Some(scale) => lasgun::Camera::orthographic(scale),
+                None => lasgun::Camera::orthographic_fov(fov, distance)
+            },
+            Projection::Isometric => lasgun::Camera::orthographic(settings.scale.unwrap_or(1.))
         };
-        let origin = utils::to_vec3f(settings.origin());
-        let look = utils::to_vec3f(settings.look());
-        let up = utils::to_vec3f(settings.up());
-        camera.look_at(origin, look, up);
-        camera.set_supersampling(settings.supersampling().unwrap_or(0));
-        camera.set_aperture_radius(settings.aperture().unwrap_or(0.));
-        Camera(camera)
+        if settings.projection == Projection::TrueIsometric {
+            camera.look_isometric(look, distance)
+        } else {
+            camera.look_at(origin, look, up)
+        }
+        match settings.samples {
+            Some(samples) => camera.set_samples_per_pixel(samples),
+            None => camera.set_supersampling(settings.supersampling)
+        }
+        camera.set_aperture_radius(settings.aperture);
+        if let Some(focus) = settings.focus { camera.set_focal_distance(focus) }
+        Ok(Camera(camera))
     }
 }
 
@@ -248,16 +193,21 @@ pub struct Scene(lasgun::Scene); impl Native for Scene {
 
 #[wasm_bindgen]
 impl Scene {
-    pub fn new(settings: &SceneSettings) -> Scene {
-        let ambient = if let Some(array) = settings.ambient() {
-            utils::to_vec3f(array)
-        } else {
-            [0.; 3]
-        };
+    pub fn new(settings: JsValue) -> Result<Scene, JsValue> {
+        let settings: settings::SceneSettings = settings::from_js("scene", settings)?;
         let mut scene = lasgun::Scene::new();
-        scene.set_ambient_light(ambient);
-        scene.set_mesh_smoothing(settings.smoothing().unwrap_or(true));
-        Scene(scene)
+        scene.set_ambient_light(settings.ambient.map_or([0.; 3], |ambient| ambient.0));
+        scene.set_mesh_smoothing(settings.smoothing);
+        Ok(Scene(scene))
+    }
+
+    /// Build a scene from the contents of a JSON scene file. Meshes must be
+    /// given inline as .obj source since there's no file system to load from.
+    pub fn from_json(json: &str) -> Result<Scene, JsValue> {
+        let scene = lasgun::scene::io::from_str(json)
+            .and_then(|desc| desc.build(std::path::Path::new("")))
+            .map_err(|err| utils::error(ErrorKind::Scene, err))?;
+        Ok(Scene(scene))
     }
 
     pub fn set_root(&mut self, content: Aggregate) {
@@ -268,32 +218,141 @@ impl Scene {
         self.0.set_camera(camera.into_native());
     }
 
-    pub fn set_solid_background(&mut self, color: Box<[JsValue]>) {
-        let color = utils::to_vec3f(color);
-        self.0.set_solid_background(color)
+    pub fn set_solid_background(&mut self, color: JsValue) -> Result<(), JsValue> {
+        let color = utils::to_vec3f("color", color)?;
+        self.0.set_solid_background(color);
+        Ok(())
     }
 
-    pub fn set_radial_background(&mut self, background: RadialBackground) {
-        let inner = utils::to_vec3f(background.inner());
-        let outer = utils::to_vec3f(background.outer());
-        let scale = background.scale().unwrap_or(0.5);
-
-        self.0.set_radial_background(inner, outer, scale)
+    pub fn set_radial_background(&mut self, background: JsValue) -> Result<(), JsValue> {
+        let background: settings::RadialBackground = settings::from_js("background", background)?;
+        self.0.set_radial_background(background.inner.0, background.outer.0, background.scale);
+        Ok(())
     }
 
     pub fn set_supersampling(&mut self, base: u8) {
         self.0.camera.set_supersampling(base)
     }
 
-    pub fn add_obj(&mut self, obj: &str) -> ObjRef {
-        ObjRef(self.0.parse_obj(obj).unwrap())
+    pub fn set_samples_per_pixel(&mut self, samples: u32) {
+        self.0.camera.set_samples_per_pixel(samples)
+    }
+
+    /// One of "clamp", "reinhard" or "aces"
+    pub fn set_tonemap(&mut self, operator: &str) -> Result<(), JsValue> {
+        self.0.set_tonemap(match operator {
+            "clamp" => lasgun::TonemapOperator::Clamp,
+            "reinhard" => lasgun::TonemapOperator::Reinhard,
+            "aces" => lasgun::TonemapOperator::Aces,
+            other => return Err(utils::error(ErrorKind::Settings, format!(
+                "Unknown tonemap operator \"{}\", expected \"clamp\", \"reinhard\" or \"aces\"",
+                other
+            )))
+        });
+        Ok(())
+    }
+
+    pub fn set_exposure(&mut self, stops: f64) {
+        self.0.set_exposure(stops)
+    }
+
+    pub fn set_gamma(&mut self, gamma: f64) {
+        self.0.set_gamma(gamma)
     }
 
-    pub fn add_point_light(&mut self, settings: &PointLight) {
-        let position = utils::to_vec3f(settings.position());
-        let intensity = utils::to_vec3f(settings.intensity());
-        let falloff = utils::to_vec3f(settings.falloff());
-        self.0.add_point_light(position, intensity, falloff);
+    /// Parse the given .obj source and add its mesh to the scene
+    pub fn add_obj(&mut self, obj: &str) -> Result<ObjRef, JsValue> {
+        let mesh = self.0.parse_obj(obj)
+            .map_err(|err| utils::error(ErrorKind::Obj, format!("Could not parse .obj mesh: {}", err)))?;
+        Ok(ObjRef(mesh))
+    }
+
+    /// Add a triangle mesh from flat arrays, such as the attributes of a
+    /// three.js BufferGeometry: x, y, z for each vertex position and three
+    /// vertex indices for each triangle, or no indices for consecutive
+    /// triangles of positions. Optional normals (x, y, z) and texture
+    /// coordinates (u, v) without an entry for every vertex are dropped.
+    pub fn add_mesh(&mut self, positions: &[f32], indices: &[u32], normals: Option<Box<[f32]>>, uvs: Option<Box<[f32]>>) -> Result<ObjRef, JsValue> {
+        let invalid = |message: &str| utils::error(ErrorKind::Mesh, format!("Invalid mesh: {}", message));
+        if positions.len() % 3 != 0 {
+            return Err(invalid("positions must have three components per vertex"))
+        }
+        let indices = if indices.is_empty() {
+            (0..positions.len() / 3).collect()
+        } else {
+            indices.iter().map(|&i| i as usize).collect()
+        };
+        let widen = |values: &[f32]| values.iter().map(|&v| v as f64).collect::<Vec<_>>();
+        let mesh = lasgun::TriangleMesh {
+            positions: widen(positions),
+            normals: normals.as_deref().map(widen),
+            uvs: uvs.as_deref().map(widen),
+            colors: None,
+            indices
+        };
+        let mesh = mesh.validate()
+            .ok_or_else(|| invalid("indices must make up whole triangles of existing vertices"))?;
+        Ok(ObjRef(self.0.add_mesh(mesh)))
+    }
+
+    /// Add a texture from 8-bit RGBA pixels in row-major order, such as the
+    /// data of an ImageData drawn from an uploaded image
+    pub fn add_image_texture(&mut self, width: u32, height: u32, rgba: &[u8]) -> Result<TextureRef, JsValue> {
+        utils::check_image(width, height, 4, rgba.len())?;
+        let texture = lasgun::ImageTexture::from_rgba(width, height, rgba);
+        Ok(TextureRef(self.0.add_texture(Box::new(texture))))
+    }
+
+    /// Add a material for shapes to refer to
+    pub fn add_material(&mut self, material: &Material) -> MaterialRef {
+        MaterialRef(self.0.add_material(*material.as_native()))
+    }
+
+    /// Replace the given material, changing every shape made of it
+    pub fn set_material(&mut self, reference: &MaterialRef, material: &Material) {
+        self.0.set_material(reference.into_native(), *material.as_native())
+    }
+
+    /// Surround the scene with an equirectangular environment map of linear
+    /// radiance, given as 3 or 4 floats per texel in row-major order, such as
+    /// decoded from an HDR image. The map lights the scene with the given
+    /// number of shadow rays per shading point.
+    pub fn set_environment_map(&mut self, width: u32, height: u32, values: &[f32], samples: usize) -> Result<(), JsValue> {
+        let channels = if values.len() == width as usize * height as usize * 4 { 4 } else { 3 };
+        utils::check_image(width, height, channels, values.len())?;
+        let map = lasgun::EnvironmentMap::from_rgb(width as usize, height as usize, channels, values);
+        self.0.set_environment_map(map, samples);
+        Ok(())
+    }
+
+    /// Surround the scene with an environment map from 8-bit RGBA pixels,
+    /// such as the data of an ImageData. See `set_environment_map`.
+    pub fn set_environment_image(&mut self, width: u32, height: u32, rgba: &[u8], samples: usize) -> Result<(), JsValue> {
+        utils::check_image(width, height, 4, rgba.len())?;
+        let map = lasgun::EnvironmentMap::from_rgba(width as usize, height as usize, rgba);
+        self.0.set_environment_map(map, samples);
+        Ok(())
+    }
+
+    pub fn add_point_light(&mut self, settings: JsValue) -> Result<(), JsValue> {
+        let settings: settings::PointLight = settings::from_js("point light", settings)?;
+        let (position, intensity) = (settings.position.0, settings.intensity.0);
+        let power = match (settings.watts, settings.lumens) {
+            (Some(watts), _) => Some(lasgun::LightPower::Watts(watts)),
+            (None, Some(lumens)) => Some(lasgun::LightPower::Lumens(lumens)),
+            (None, None) => None
+        };
+        match power {
+            Some(power) => self.0.add_point_light_power(position, intensity, power),
+            None => self.0.add_point_light(position, intensity, settings.falloff.0)
+        };
+        Ok(())
+    }
+
+    pub fn add_directional_light(&mut self, settings: JsValue) -> Result<(), JsValue> {
+        let settings: settings::DirectionalLight = settings::from_js("directional light", settings)?;
+        self.0.add_directional_light(settings.direction.0, settings.intensity.0, settings.radius);
+        Ok(())
     }
 }
 
@@ -315,27 +374,40 @@ impl Aggregate {
         self.0.add_group(node.into_native())
     }
 
-    pub fn add_sphere(&mut self, sphere: &Sphere, material: &Material) {
-        let origin = utils::to_vec3f(sphere.origin());
-        let radius = sphere.radius();
-        self.0.add_sphere(origin, radius, *material.as_native())
+    pub fn add_sphere(&mut self, sphere: JsValue, material: &MaterialRef) -> Result<(), JsValue> {
+        let sphere: settings::Sphere = settings::from_js("sphere", sphere)?;
+        self.0.add_sphere(sphere.origin.0, sphere.radius, material.into_native());
+        Ok(())
     }
 
-    pub fn add_cube(&mut self, cube: &Cube, material: &Material) {
-        let origin = utils::to_vec3f(cube.origin());
-        let dim = cube.dim();
-        self.0.add_cube(origin, dim, *material.as_native())
+    pub fn add_cube(&mut self, cube: JsValue, material: &MaterialRef) -> Result<(), JsValue> {
+        let cube: settings::Cube = settings::from_js("cube", cube)?;
+        self.0.add_cube(cube.origin.0, cube.dim, material.into_native());
+        Ok(())
     }
 
-    pub fn add_box(&mut self, cuboid: &Cuboid, material: &Material) {
-        let start = utils::to_vec3f(cuboid.start());
-        let end = utils::to_vec3f(cuboid.end());
-        self.0.add_box(start, end, *material.as_native())
+    pub fn add_box(&mut self, cuboid: JsValue, material: &MaterialRef) -> Result<(), JsValue> {
+        let cuboid: settings::Cuboid = settings::from_js("box", cuboid)?;
+        self.0.add_box(cuboid.start.0, cuboid.end.0, material.into_native());
+        Ok(())
+    }
+
+    pub fn add_plane(&mut self, plane: JsValue, material: &MaterialRef) -> Result<(), JsValue> {
+        let plane: settings::Plane = settings::from_js("plane", plane)?;
+        let size = [plane.width, plane.height];
+        self.0.add_plane(plane.origin.0, plane.normal.0, size, material.into_native());
+        Ok(())
+    }
+
+    pub fn add_disk(&mut self, disk: JsValue, material: &MaterialRef) -> Result<(), JsValue> {
+        let disk: settings::Disk = settings::from_js("disk", disk)?;
+        self.0.add_disk(disk.origin.0, disk.normal.0, disk.radius, material.into_native());
+        Ok(())
     }
 
     // TODO: Implement add_obj and add_obj_of, which takes a material
-    pub fn add_obj(&mut self, mesh: &ObjRef, material: &Material) {
-        self.0.add_obj_of(mesh.into_native(), *material.as_native())
+    pub fn add_obj(&mut self, mesh: &ObjRef, material: &MaterialRef) {
+        self.0.add_obj_of(mesh.into_native(), material.into_native())
     }
 
     /// Translate by the given delta values, x y and z
@@ -360,81 +432,232 @@ impl Aggregate {
         self.0.rotate_z(theta);
     }
 
-    pub fn rotate(&mut self, theta: f64, axis: Box<[JsValue]>) {
-        self.0.rotate(theta, utils::to_vec3f(axis));
+    pub fn rotate(&mut self, theta: f64, axis: JsValue) -> Result<(), JsValue> {
+        self.0.rotate(theta, utils::to_vec3f("axis", axis)?);
+        Ok(())
     }
 }
 
 
-/// Rendering Accelerator primitive. Implementation includes unsafe lifetime
-/// extension, as bindgen does not yet support lifetime constraints. The program
-/// will access invalid memory if the instance is accessed after its referenced
-/// scene is moved/dropped.
+/// Take ownership of the given scene for a structure that refers into it,
+/// since bindgen does not support lifetimes. The scene is kept behind a
+/// reference count so that it stays in place however the owner moves.
+fn own(scene: Scene) -> (Rc<lasgun::Scene>, &'static lasgun::Scene) {
+    let scene = Rc::new(scene.into_native());
+    // Owners hold on to the scene until after what refers into it is dropped
+    let native = unsafe { &*Rc::as_ptr(&scene) };
+    (scene, native)
+}
+
+/// Rendering Accelerator primitive. Takes ownership of the scene it's built
+/// from, so that the scene lives exactly as long as the structure refers into
+/// it; the Scene object passed in can't be used again from JavaScript.
 #[wasm_bindgen]
-pub struct Accel(lasgun::Accel<'static>); impl Native for Accel {
-    type Output = lasgun::Accel<'static>;
-    #[inline] fn into_native(self) -> Self::Output { self.0 }
-    #[inline] fn as_native(&self) -> &Self::Output { &self.0 }
-    #[inline] fn as_native_mut(&mut self) -> &mut Self::Output { &mut self.0 }
+pub struct Accel {
+    // Refers into the scene, so it's declared first to be dropped first
+    root: lasgun::Accel<'static>,
+    _scene: Rc<lasgun::Scene>
+}
+
+impl Accel {
+    /// The native structure, borrowed for no longer than this one so that
+    /// references into the scene can't outlive it
+    #[inline]
+    fn as_native(&self) -> &lasgun::Accel<'_> {
+        &self.root
+    }
 }
 
 #[wasm_bindgen]
 impl Accel {
-    pub fn from(scene: &Scene) -> Accel {
-        // This is necessary because wasm_bindgen does not yet support lifetimes
-        let scene = unsafe { mem::transmute::<&Scene, &'static Scene>(scene) };
-        Accel(lasgun::Accel::from(scene.as_native()))
+    pub fn from(scene: Scene) -> Accel {
+        let (scene, native) = own(scene);
+        Accel { root: lasgun::Accel::from(native), _scene: scene }
+    }
+}
+
+/// Renders the scene onto a film over a series of increasingly-refined passes.
+/// Takes ownership of the scene like Accel.
+#[wasm_bindgen]
+pub struct ProgressiveRenderer {
+    // Refers into the scene, so it's declared first to be dropped first
+    renderer: lasgun::ProgressiveRenderer<'static>,
+    _scene: Rc<lasgun::Scene>
+}
+
+impl ProgressiveRenderer {
+    /// The native renderer, borrowed for no longer than this one. See
+    /// `Accel::as_native`.
+    #[inline]
+    fn as_native(&self) -> &lasgun::ProgressiveRenderer<'_> {
+        &self.renderer
+    }
+}
+
+#[wasm_bindgen]
+impl ProgressiveRenderer {
+    pub fn from(scene: Scene) -> ProgressiveRenderer {
+        let (scene, native) = own(scene);
+        ProgressiveRenderer { renderer: lasgun::ProgressiveRenderer::new(native), _scene: scene }
+    }
+
+    /// Number of samples per pixel taken so far
+    pub fn samples(&self) -> u32 {
+        self.as_native().samples()
+    }
+
+    /// Refine the given film with the next round of samples
+    pub fn next_pass(&mut self, film: &mut Film) {
+        film.0.tonemap = self.as_native().scene().tonemap;
+        self.renderer.next_pass(film.as_native_mut())
+    }
+}
+
+/// Renders the scene onto its own film a few hunks at a time, for render
+/// loops that must hand control back to the browser every frame, such as
+/// from requestAnimationFrame. Takes ownership of the scene like Accel.
+#[wasm_bindgen]
+pub struct Renderer {
+    // Refers into the scene, so it's declared first to be dropped first
+    root: lasgun::Accel<'static>,
+    _scene: Rc<lasgun::Scene>,
+    film: lasgun::Film,
+
+    /// Next hunk to trace, in row-major order
+    next: usize,
+    hunks: usize
+}
+
+#[wasm_bindgen]
+impl Renderer {
+    pub fn new(scene: Scene, width: u32, height: u32) -> Renderer {
+        let (scene, native) = own(scene);
+        Renderer {
+            root: lasgun::Accel::from(native),
+            _scene: scene,
+            film: lasgun::Film::new(width, height),
+            next: 0,
+            hunks: lasgun::hunk_count(width, height)
+        }
+    }
+
+    /// Trace hunks of the image until the given number of milliseconds have
+    /// passed or the image is done, and return the fraction of the image done
+    /// so far. Always traces at least one hunk that's left, so steps may run
+    /// over budget by as long as one hunk takes.
+    pub fn step(&mut self, ms_budget: f64) -> f64 {
+        let start = now();
+        while self.next < self.hunks {
+            lasgun::capture_hunk(self.next, &self.root, self.film.w, self.film.h).copy_into(&mut self.film);
+            self.next += 1;
+            if now() - start >= ms_budget { break }
+        }
+        self.progress()
+    }
+
+    /// Fraction of the image done so far, from 0 to 1
+    #[wasm_bindgen(getter)]
+    pub fn progress(&self) -> f64 {
+        if self.done() { 1.0 } else { self.next as f64 / self.hunks as f64 }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn done(&self) -> bool {
+        self.next >= self.hunks
+    }
+
+    /// New ImageData with a copy of the image so far. See Film::image_data.
+    pub fn image_data(&self) -> Result<ImageData, JsValue> {
+        image_data(&self.film)
+    }
+
+    /// Draw the image so far onto the given 2D canvas context with its
+    /// top-left corner at x, y
+    pub fn render_to_canvas(&self, ctx: &CanvasRenderingContext2D, x: f64, y: f64) -> Result<(), JsValue> {
+        ctx.put_image_data(&self.image_data()?, x, y);
+        Ok(())
     }
 }
 
 /// Captureable film
 #[wasm_bindgen]
-pub struct Film {
-    pub w: u32,
-    pub h: u32,
-    winv: f64,
-    hinv: f64,
-    aspect: f64,
-    output: Vec<Pixel>
+pub struct Film(lasgun::Film); impl Native for Film {
+    type Output = lasgun::Film;
+    #[inline] fn into_native(self) -> Self::Output { self.0 }
+    #[inline] fn as_native(&self) -> &Self::Output { &self.0 }
+    #[inline] fn as_native_mut(&mut self) -> &mut Self::Output { &mut self.0 }
 }
 
 #[wasm_bindgen]
 impl Film {
     pub fn new(width: u32, height: u32) -> Film {
-        Film {
-            w: width,
-            h: height,
-            winv: 1. / width as f64,
-            hinv: 1. / height as f64,
-            aspect: width as f64 / height as f64,
-            output: vec![[0; 4]; width as usize * height as usize]
-        }
+        Film(lasgun::Film::new(width, height))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn w(&self) -> u32 {
+        self.0.w
     }
 
-    pub fn size(&self) -> usize {
-        self.output.len()
+    #[wasm_bindgen(getter)]
+    pub fn h(&self) -> u32 {
+        self.0.h
     }
 
-    pub fn data_ptr(&self) -> *const u8 {
-        unsafe { mem::transmute(self.output[..].as_ptr()) }
+    /// New ImageData with a copy of the film's pixels, ready to put on a
+    /// canvas
+    pub fn image_data(&self) -> Result<ImageData, JsValue> {
+        image_data(&self.0)
+    }
+
+    /// Draw the film onto the given 2D canvas context with its top-left
+    /// corner at x, y
+    pub fn render_to_canvas(&self, ctx: &CanvasRenderingContext2D, x: f64, y: f64) -> Result<(), JsValue> {
+        ctx.put_image_data(&self.image_data()?, x, y);
+        Ok(())
     }
 }
 
-impl Index<usize> for Film {
-    type Output = Pixel;
-    #[inline] fn index(&self, at: usize) -> &Self::Output { &self.output[at] }
+fn image_data(film: &lasgun::Film) -> Result<ImageData, JsValue> {
+    ImageData::new(Clamped(film.buffer().as_flattened()), film.w, film.h)
 }
 
-impl IndexMut<usize> for Film {
-    #[inline] fn index_mut(&mut self, at: usize) -> &mut Self::Output { &mut self.output[at] }
+/// Tonemapped pixels of one hunk of a film, with its position in the film
+#[wasm_bindgen]
+pub struct FilmDataHunk(lasgun::FilmDataHunk); impl Native for FilmDataHunk {
+    type Output = lasgun::FilmDataHunk;
+    #[inline] fn into_native(self) -> Self::Output { self.0 }
+    #[inline] fn as_native(&self) -> &Self::Output { &self.0 }
+    #[inline] fn as_native_mut(&mut self) -> &mut Self::Output { &mut self.0 }
 }
 
-impl lasgun::Img for Film {
-    #[inline] fn w(&self) -> u32 { self.w }
-    #[inline] fn h(&self) -> u32 { self.h }
-    #[inline] fn winv(&self) -> f64 { self.winv }
-    #[inline] fn hinv(&self) -> f64 { self.hinv }
-    #[inline] fn aspect(&self) -> f64 { self.aspect }
+#[wasm_bindgen]
+impl FilmDataHunk {
+    #[wasm_bindgen(getter)]
+    pub fn x(&self) -> u32 {
+        self.0.bounds.x
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn y(&self) -> u32 {
+        self.0.bounds.y
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn w(&self) -> u32 {
+        self.0.bounds.w
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn h(&self) -> u32 {
+        self.0.bounds.h
+    }
+
+    /// RGBA bytes of the pixels in row-major order, copied into a new
+    /// Uint8Array outside of wasm memory whose buffer may be transferred
+    pub fn data(&self) -> Vec<u8> {
+        self.0.data.concat()
+    }
 }
 
 // Lasgun-exposed material
@@ -448,49 +671,47 @@ pub struct Material(lasgun::Material); impl Native for Material {
 
 #[wasm_bindgen]
 impl Material {
-    pub fn plastic(settings: &Plastic) -> Material {
-        let kd = utils::to_vec3f(settings.kd());
-        let ks = utils::to_vec3f(settings.ks());
-        let roughness = settings.roughness().unwrap_or(0.0);
-        Material(lasgun::Material::plastic(kd, ks, roughness))
-    }
-
-    pub fn matte(settings: &Matte) -> Material {
-        let kd = utils::to_vec3f(settings.kd());
-        let sigma = settings.sigma().unwrap_or(0.0);
-        Material(lasgun::Material::matte(kd, sigma))
-    }
-
-    pub fn metal(settings: &Metal) -> Material {
-        let eta = utils::to_vec3f(settings.eta());
-        let k = utils::to_vec3f(settings.k());
-        let (mut u_roughness, mut v_roughness) = (0.0, 0.0);
-        if let Some(roughness) = settings.roughness() {
-            u_roughness = roughness;
-            v_roughness = roughness;
-        }
+    pub fn plastic(settings: JsValue) -> Result<Material, JsValue> {
+        let settings: settings::Plastic = settings::from_js("plastic", settings)?;
+        Ok(Material(lasgun::Material::plastic(settings.kd.0, settings.ks.0, settings.roughness)))
+    }
 
-        if let Some(u) = settings.u_roughness() { u_roughness = u }
-        if let Some(v) = settings.v_roughness() { v_roughness = v }
+    pub fn matte(settings: JsValue) -> Result<Material, JsValue> {
+        let settings: settings::Matte = settings::from_js("matte", settings)?;
+        Ok(Material(lasgun::Material::matte(settings.kd.0, settings.sigma)))
+    }
 
-        Material(lasgun::Material::metal(eta, k, u_roughness, v_roughness))
+    /// Matte material coloured by the given texture
+    pub fn matte_texture(kd: &TextureRef, sigma: f64) -> Material {
+        Material(lasgun::Material::matte(kd.into_native(), sigma))
     }
 
-    pub fn mirror(settings: &Mirror) -> Material {
-        let kr = if let Some(value) = settings.kr() {
-            utils::to_vec3f(value)
-        } else {
-            [1.0, 1.0, 1.0]
-        };
-        Material(lasgun::Material::mirror(kr))
+    /// Plastic material with diffuse colour from the given texture and the
+    /// given specular colour
+    pub fn plastic_texture(kd: &TextureRef, ks: JsValue, roughness: f64) -> Result<Material, JsValue> {
+        let ks = utils::to_vec3f("ks", ks)?;
+        Ok(Material(lasgun::Material::plastic(kd.into_native(), ks, roughness)))
+    }
+
+    pub fn metal(settings: JsValue) -> Result<Material, JsValue> {
+        let settings: settings::Metal = settings::from_js("metal", settings)?;
+        let u_roughness = settings.u_roughness.unwrap_or(settings.roughness);
+        let v_roughness = settings.v_roughness.unwrap_or(settings.roughness);
+        Ok(Material(lasgun::Material::metal(settings.eta.0, settings.k.0, u_roughness, v_roughness)))
+    }
+
+    pub fn mirror(settings: JsValue) -> Result<Material, JsValue> {
+        let settings: settings::Mirror = settings::from_js("mirror", settings)?;
+        Ok(Material(lasgun::Material::mirror(settings.kr.0)))
+    }
+
+    pub fn glass(settings: JsValue) -> Result<Material, JsValue> {
+        let settings: settings::Glass = settings::from_js("glass", settings)?;
+        Ok(Material(lasgun::Material::glass(settings.kr.0, settings.kt.0, settings.eta)))
     }
 
-    pub fn glass(settings: &Glass) -> Material {
-        let kr = if let Some(val) = settings.kr()
-            { utils::to_vec3f(val) } else { [1.0, 1.0, 1.0] };
-        let kt = if let Some(val) = settings.kt()
-            { utils::to_vec3f(val) } else { [1.0, 1.0, 1.0] };
-        let eta = if let Some(val) = settings.eta() { val } else { 1.5 };
-        Material(lasgun::Material::glass(kr, kt, eta))
+    pub fn emissive(settings: JsValue) -> Result<Material, JsValue> {
+        let settings: settings::Emissive = settings::from_js("emissive", settings)?;
+        Ok(Material(lasgun::Material::emissive(settings.radiance.0)))
     }
 }
@@ -0,0 +1,230 @@
+//! Settings objects given to the bindings from JavaScript, deserialized with
+//! serde. Fields are named as in JavaScript; optional ones may be left out,
+//! and unknown ones are an error so that typos don't go unnoticed.
+//!
+//! Vectors and colours are arrays of three finite numbers.
+
+use serde::{Deserialize, Deserializer, de::{self, DeserializeOwned}};
+use wasm_bindgen::JsValue;
+use crate::utils::{self, ErrorKind};
+
+/// Deserialize the given JavaScript settings object, or a settings error
+/// naming what the settings were for
+pub fn from_js<T: DeserializeOwned>(name: &str, settings: JsValue) -> Result<T, JsValue> {
+    serde_wasm_bindgen::from_value(settings)
+        .map_err(|err| utils::error(ErrorKind::Settings, format!("Invalid {} settings: {}", name, err)))
+}
+
+/// Array of three finite numbers
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Vec3f(pub [f64; 3]);
+
+impl<'de> Deserialize<'de> for Vec3f {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Vec3f, D::Error> {
+        let vec = <[f64; 3]>::deserialize(deserializer)?;
+        if vec.iter().all(|v| v.is_finite()) {
+            Ok(Vec3f(vec))
+        } else {
+            Err(de::Error::invalid_value(de::Unexpected::Other("non-finite number"), &"three finite numbers"))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SceneSettings {
+    #[serde(default)]
+    pub ambient: Option<Vec3f>,
+
+    /// Smooth meshes with their vertex normals
+    #[serde(default = "default_smoothing")]
+    pub smoothing: bool
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+
+    /// Plain orthographic camera with a default scale of 1, kept as before
+    /// for existing scenes
+    Isometric,
+
+    /// Orthographic camera looking down at the look-at point along the
+    /// isometric axis
+    TrueIsometric
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CameraSettings {
+    #[serde(default = "default_projection")]
+    pub projection: Projection,
+
+    /// Vertical field of view in degrees
+    #[serde(default = "default_fov")]
+    pub fov: f64,
+
+    /// Height of the focal plane for orthographic projections, defaults to
+    /// the extent of the fov at the look-at point
+    #[serde(default)]
+    pub scale: Option<f64>,
+    pub origin: Vec3f,
+    pub look: Vec3f,
+    pub up: Vec3f,
+    #[serde(default)]
+    pub supersampling: u8,
+
+    /// Samples per pixel, in place of supersampling
+    #[serde(default)]
+    pub samples: Option<u32>,
+
+    /// Radius of the lens
+    #[serde(default)]
+    pub aperture: f64,
+
+    /// Focal distance, defaults to the distance to the look-at point
+    #[serde(default)]
+    pub focus: Option<f64>
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RadialBackground {
+    pub inner: Vec3f,
+    pub outer: Vec3f,
+    #[serde(default = "default_background_scale")]
+    pub scale: f64
+}
+
+/// With watts or lumens, intensity is the colour of the light and it dims
+/// with the square of the distance; otherwise it dims by the falloff
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PointLight {
+    pub position: Vec3f,
+    pub intensity: Vec3f,
+    #[serde(default = "default_falloff")]
+    pub falloff: Vec3f,
+    #[serde(default)]
+    pub watts: Option<f64>,
+    #[serde(default)]
+    pub lumens: Option<f64>
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DirectionalLight {
+    pub direction: Vec3f,
+    pub intensity: Vec3f,
+
+    /// Angular radius in degrees
+    #[serde(default)]
+    pub radius: f64
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Sphere {
+    pub origin: Vec3f,
+    pub radius: f64
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Cube {
+    pub origin: Vec3f,
+    pub dim: f64
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Cuboid {
+    pub start: Vec3f,
+    pub end: Vec3f
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Plane {
+    pub origin: Vec3f,
+    pub normal: Vec3f,
+    pub width: f64,
+    pub height: f64
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Disk {
+    pub origin: Vec3f,
+    pub normal: Vec3f,
+    pub radius: f64
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Plastic {
+    pub kd: Vec3f,
+    pub ks: Vec3f,
+
+    /// In the range [0, 1]
+    #[serde(default)]
+    pub roughness: f64
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Matte {
+    pub kd: Vec3f,
+
+    /// In the range [0, 1]
+    #[serde(default)]
+    pub sigma: f64
+}
+
+/// Roughness sets both directions, which u_roughness and v_roughness override
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Metal {
+    pub eta: Vec3f,
+    pub k: Vec3f,
+    #[serde(default)]
+    pub roughness: f64,
+    #[serde(default)]
+    pub u_roughness: Option<f64>,
+    #[serde(default)]
+    pub v_roughness: Option<f64>
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Mirror {
+    #[serde(default = "default_white")]
+    pub kr: Vec3f
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Glass {
+    #[serde(default = "default_white")]
+    pub kr: Vec3f,
+    #[serde(default = "default_white")]
+    pub kt: Vec3f,
+    #[serde(default = "default_eta")]
+    pub eta: f64
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Emissive {
+    pub radiance: Vec3f
+}
+
+fn default_smoothing() -> bool { true }
+fn default_projection() -> Projection { Projection::Perspective }
+fn default_fov() -> f64 { 45.0 }
+fn default_background_scale() -> f64 { 0.5 }
+fn default_falloff() -> Vec3f { Vec3f([1.0, 0.0, 0.0]) }
+fn default_white() -> Vec3f { Vec3f([1.0, 1.0, 1.0]) }
+fn default_eta() -> f64 { 1.5 }
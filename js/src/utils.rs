@@ -1,5 +1,7 @@
+use std::fmt;
 use cfg_if::cfg_if;
-use wasm_bindgen::JsValue;
+use wasm_bindgen::prelude::*;
+use crate::settings::Vec3f;
 
 cfg_if! {
     // When the `console_error_panic_hook` feature is enabled, we can call the
@@ -13,13 +15,75 @@ cfg_if! {
     }
 }
 
-// Get a [f64; 3] from a JavaScript value
-pub fn to_vec3f(values: Box<[JsValue]>) -> [f64; 3] {
-    [
-        values.get(0).unwrap_or(&JsValue::NULL).as_f64().unwrap_or(0.0),
-        values.get(1).unwrap_or(&JsValue::NULL).as_f64().unwrap_or(0.0),
-        values.get(2).unwrap_or(&JsValue::NULL).as_f64().unwrap_or(0.0)
-    ]
+#[wasm_bindgen]
+extern {
+    /// JavaScript's own Error, thrown with an extra `kind` property so that
+    /// callers can tell what went wrong without matching on messages
+    type Error;
+    #[wasm_bindgen(constructor)]
+    fn new(message: &str) -> Error;
+    #[wasm_bindgen(method, setter, structural)]
+    fn set_kind(this: &Error, kind: &str);
+}
+
+/// What a thrown Error was about, given to JavaScript as its `kind`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Malformed .obj mesh source
+    Obj,
+    /// Mesh arrays that don't make up whole triangles of existing vertices
+    Mesh,
+    /// Something other than an array of three finite numbers given as a
+    /// vector or colour argument
+    Vector,
+    /// Unknown, missing or out-of-range setting
+    Settings,
+    /// Invalid JSON scene description
+    Scene,
+    /// Texture or environment map data that doesn't match its dimensions
+    Image
+}
+
+impl ErrorKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Obj => "obj",
+            ErrorKind::Mesh => "mesh",
+            ErrorKind::Vector => "vector",
+            ErrorKind::Settings => "settings",
+            ErrorKind::Scene => "scene",
+            ErrorKind::Image => "image"
+        }
+    }
+}
+
+/// New JavaScript Error of the given kind, to return as the Err of a bound
+/// function so that bindgen throws it
+pub fn error(kind: ErrorKind, message: impl fmt::Display) -> JsValue {
+    let error = Error::new(&message.to_string());
+    error.set_kind(kind.as_str());
+    error.into()
+}
+
+// Get a [f64; 3] from a JavaScript array, or a vector error naming the given
+// argument if it isn't three finite numbers
+pub fn to_vec3f(name: &str, value: JsValue) -> Result<[f64; 3], JsValue> {
+    serde_wasm_bindgen::from_value::<Vec3f>(value)
+        .map(|vec| vec.0)
+        .map_err(|_| error(ErrorKind::Vector, format!("`{}` must be an array of three numbers", name)))
+}
+
+// Check that image data of the given length holds the given number of
+// channels for each pixel of a non-empty width x height image
+pub fn check_image(width: u32, height: u32, channels: usize, len: usize) -> Result<(), JsValue> {
+    let expected = width as usize * height as usize * channels;
+    if width == 0 || height == 0 || len != expected {
+        return Err(error(ErrorKind::Image, format!(
+            "Expected {} values for a {}x{} image with {} channels, got {}",
+            expected, width, height, channels, len
+        )))
+    }
+    Ok(())
 }
 
 pub trait Native {
@@ -1,27 +1,61 @@
-use std::mem;
+use std::{mem, sync::Arc, collections::HashMap};
 use typed_arena::Arena;
 use partition::partition;
+#[cfg(not(feature = "f32-bvh"))]
+use wide::f64x4;
+#[cfg(feature = "f32-bvh")]
+use wide::f32x8;
+use wide::CmpLe;
 use crate::{
     space::*,
     shape::*,
-    Material,
+    MaterialRef,
     primitive::{Primitive, OptionalPrimitive},
-    interaction::RayIntersection,
-    scene::{Scene, ObjRef, node::{self, SceneNode}}
+    interaction::{RayIntersection, SurfaceInteraction},
+    scene::{Scene, ObjRef, node::{self, SceneNode}},
+    light::{Light, LightVisibility, ShapeLight, tree::LightTree},
+    integrate::{Integrator, irradiance::IrradianceCache},
+    stats::{self, BvhStats}
 };
+use super::{AcceleratorKind, BvhBuildStrategy, instance::Instance, kdtree::KdTreeAccel, mesh::TriangleBVH};
+use crate::space::animated::AnimatedTransform;
 
 // Hiding my ugly dynamic dispatch type.
 // Should have the lifetime of the referenced Scene instance.
-type PrimBox<'s> = Box<dyn Primitive + 's>;
+pub(crate) type PrimBox<'s> = Box<dyn Primitive + 's>;
 
 // (In)convenience types, mostly for documentation
-type BVHSplitAxis = usize;
 type BVHPrimNumber = usize;
 type BVHPrimCount = usize;
 
 // Upper SAH buckets
 const BVH_NBUCKETS: usize = 12;
 
+// SIMD lanes the bounds of the children of a node are stored and tested in,
+// one child per lane. With the f32-bvh feature, bounds are rounded outward to
+// single precision, which fits twice as many children in the same registers.
+#[cfg(not(feature = "f32-bvh"))]
+type Lanes = f64x4;
+#[cfg(not(feature = "f32-bvh"))]
+type Lane = f64;
+#[cfg(feature = "f32-bvh")]
+type Lanes = f32x8;
+#[cfg(feature = "f32-bvh")]
+type Lane = f32;
+
+// Children per node in the linear tree, one per lane
+#[cfg(not(feature = "f32-bvh"))]
+pub(crate) const BVH_WIDTH: usize = 4;
+#[cfg(feature = "f32-bvh")]
+pub(crate) const BVH_WIDTH: usize = 8;
+
+// Cut-out surfaces a ray may pass through before the next hit counts as opaque
+const MAX_CUT_OUT_LAYERS: usize = 64;
+
+// Capacity of the traversal stack. Each visited node pushes all but one of its
+// children, so this allows for trees up to 64 levels deep.
+pub(crate) const BVH_STACK_SIZE: usize = (BVH_WIDTH - 1) * 64;
+
 // Morton enconding constants
 // see PBRT v3 p268
 const MORTON_BITS: u32 = 10;
@@ -39,7 +73,14 @@ const RADIX_BITMASK: u32 = (1 << RADIX_BITS_PER_PASS) - 1;
 /// the scene whose content it holds.
 ///
 /// Uses Linear Bounding Volume hierarchy strategy, as described in the
-/// Physically Based Rendering book.
+/// Physically Based Rendering book. The built binary tree is collapsed into
+/// nodes with four children each, or eight with the f32-bvh feature, whose bounds
+/// are tested against a ray together with SIMD instructions.
+///
+/// The hierarchies of the scene's groups hold the instances of its meshes,
+/// each of which refers to the `TriangleBVH` of its mesh. Those are kept by
+/// the scene, so building this again after moving things around only builds
+/// the small hierarchies over the groups and instances.
 ///
 /// http://www.pbr-book.org/3ed-2018/Primitives_and_Intersection_Acceleration/Bounding_Volume_Hierarchies.html
 pub struct BVHAccel<'s> {
@@ -47,26 +88,103 @@ pub struct BVHAccel<'s> {
 
     primitives: Vec<PrimBox<'s>>,
 
-    /// BVH tree nodes arranged in linear memory
-    nodes: Vec<LinearBVHNode>,
+    /// Nodes of the hierarchy over the primitives, in object space
+    tree: BVHTree,
 
     /// Transform matrix reference
     transform: &'s Transformation,
 
-    // The order in which primitives are accessed following BVH construction.
-    // Each element is an index into the primitives vec. The offset indeces on
-    // each nodes member referes to an index into this vec.
-    order: Vec<BVHPrimNumber>,
+    /// Replaces the transform for groups that move over time
+    motion: Option<AnimatedTransform>,
 
     // The default material shared by all nodes in the tree that don't provide
     // their own via the `Material` method
-    material: Option<Material>,
+    material: Option<MaterialRef>,
+
+    /// Reverses orientation of normal shading vectors for all children.
+    swap_backface: bool,
+
+    /// Whether rays pass through the backs of all children
+    cull_backface: bool,
+
+    /// Whether children block light from reaching other surfaces
+    cast_shadows: bool,
+
+    /// Whether children may be shadowed by other surfaces
+    receive_shadows: bool,
+
+    /// Area lights made from the emissive primitives in the scene. Only set
+    /// on the root of the hierarchy.
+    area_lights: Vec<ShapeLight<'s>>,
+
+    /// Hierarchy over the point lights of scenes that pick a few of them to
+    /// light each hit. Only set on the root of the hierarchy.
+    light_tree: Option<LightTree>,
+
+    /// Hierarchies of the groups among the primitives, by primitive number.
+    /// Kept to find their layouts for refitting.
+    groups: Vec<Option<Arc<BVHAccel<'s>>>>,
+
+    /// Hierarchies of the triangle meshes shared by their instances. Only set
+    /// on the root of the hierarchy.
+    meshes: HashMap<ObjRef, MeshAccel<'s>>,
+
+    /// Whether any material in the scene has an alpha mask or any group culls
+    /// backfaces, in which case occlusion tests have to find the nearest hits.
+    /// Only set on the root of the hierarchy.
+    cut_outs: bool,
+
+    /// Whether hits record the index of the primitive they belong to as their
+    /// object ID. Only set on the root of a bounding volume hierarchy, whose
+    /// primitives are the top-level nodes of the scene.
+    object_ids: bool,
+
+    /// Indirect diffuse light cached for the irradiance integrator. Only set
+    /// on the root of the hierarchy of scenes rendered with it.
+    irradiance: Option<IrradianceCache>
+}
+
+/// Nodes of a four- or eight-wide bounding volume hierarchy over a list of primitives,
+/// which its leaves refer to by number. Used both by the hierarchies of
+/// groups, over their boxed primitives, and by those of meshes, over the
+/// indices of their triangles.
+pub(crate) struct BVHTree {
+    /// Tree nodes arranged in linear memory, starting with the root
+    nodes: Vec<LinearBVHNode>,
+
+    /// Bounds of all primitives in the tree
+    pub bounds: Bounds,
+
+    // The order in which primitives are accessed following BVH construction.
+    // Each element is a primitive number. The offset indeces on each nodes
+    // member referes to an index into this vec.
+    order: Vec<BVHPrimNumber>,
 
     // Limit to how many primitives there may be per node tree
-    max_prims_per_node: u8,
+    max_prims_per_node: u8
+}
 
-    /// Reverses orientation of normal shading vectors for all children.
-    swap_backface: bool
+/// Hierarchy of the triangles of a mesh, shared by its instances
+#[derive(Clone)]
+enum MeshAccel<'s> {
+    Bvh(Arc<TriangleBVH<'s>>),
+    KdTree(Arc<KdTreeAccel<'s>>)
+}
+
+/// Shape of a built hierarchy, without references to the scene it was built
+/// from. When only transformations or vertex positions change between frames
+/// of an animation, refitting the hierarchy to the changed scene with this
+/// layout is much faster than building it again. See `BVHAccel::refit`.
+#[derive(Clone)]
+pub struct BVHLayout {
+    nodes: Vec<LinearBVHNode>,
+    order: Vec<BVHPrimNumber>,
+
+    /// Layouts of the groups among the primitives, by primitive number
+    groups: Vec<Option<BVHLayout>>,
+
+    /// Layouts of the shared triangle mesh hierarchies
+    meshes: HashMap<ObjRef, BVHLayout>
 }
 
 /// Deterministic sorting construct for objects in 3D space
@@ -78,7 +196,7 @@ struct MortonPrimitive {
 }
 
 /// Information about each primitive stored in a BVHAccel
-struct BVHPrimitiveInfo {
+pub(crate) struct BVHPrimitiveInfo {
     number: BVHPrimNumber,
     bounds: Bounds,
     centroid: Point
@@ -99,7 +217,7 @@ enum BVHNodeType<'a> {
     Leaf(BVHPrimNumber, BVHPrimCount),
 
     // i.e., not a child node.
-    Interior(BVHSplitAxis, &'a BVHBuildNode<'a>, &'a BVHBuildNode<'a>)
+    Interior(&'a BVHBuildNode<'a>, &'a BVHBuildNode<'a>)
 }
 
 /// A BVHAccel tree entry. Tied to the lifetime of the memory arena used to
@@ -117,88 +235,405 @@ struct LBVHTreelet<'a> {
 }
 
 #[derive(Copy, Clone)]
-enum LinearBVHNodeType {
-    // First/second child offset and prim count
+enum LinearBVHChild {
+    // Unused slot of a node with fewer than BVH_WIDTH children
+    Empty,
+    // Offset into the order array and prim count
     Leaf(u32, u16),
-    // split axis and offset into parent array
-    Interior(u8, u32)
+    // Offset into the nodes array
+    Interior(u32)
 }
 
+/// A node of the wide tree. Child bounds are stored per axis with one child
+/// in each lane, so that a ray is tested against all of them at once. Empty
+/// slots have inverted bounds that no ray intersects.
 #[derive(Copy, Clone)]
 struct LinearBVHNode {
-    pub bounds: Bounds,
-    pub content: LinearBVHNodeType
+    pub min: [Lanes; 3],
+    pub max: [Lanes; 3],
+    pub children: [LinearBVHChild; BVH_WIDTH]
+}
+
+/// A ray's origin and inverse direction in each lane, for testing against
+/// the children of a LinearBVHNode. The origin is rounded toward the near
+/// planes of the boxes for their entry distances and toward the far planes
+/// for their exit distances, so that rounding it to the lanes' precision
+/// never makes the ray miss a box.
+struct BVHRayLanes {
+    near_origin: [Lanes; 3],
+    far_origin: [Lanes; 3],
+    dinv: [Lanes; 3],
+    dir_is_neg: [bool; 3]
 }
 
 
 impl<'s> BVHAccel<'s> {
     pub fn from(scene: &'s Scene) -> BVHAccel<'s> {
-        BVHAccel::from_aggregate(scene, &scene.root)
+        BVHAccel::from_scene(scene, None)
     }
 
-    /// Create a new BVH structure from the given triangle mesh
-    /// This structure will be composed entirely of Triangles
-    fn from_mesh(scene: &'s Scene, mesh: ObjRef, material: Option<Material>) -> BVHAccel<'s> {
-        let obj = scene.obj(mesh).unwrap();
-        let triangles: Vec<PrimBox<'s>> = TriangleIterator::new(&obj)
-            .map(|t| -> PrimBox<'s> { Box::new(t) })
-            .collect();
-        let per_node = triangles.len();
-        BVHAccel::new(scene, triangles, &transform::ID, material, per_node, false)
-    }
-
-    fn from_aggregate(scene: &'s Scene, aggregate: &'s node::Aggregate) -> BVHAccel<'s> {
-        let primitives: Vec<PrimBox<'s>> = aggregate.contents.iter()
-        .map(|node| match node {
-            SceneNode::Geometry(shape, mat) =>
-                geometry(shape, *mat),
-            SceneNode::Mesh(obj, mat) =>
-                Box::new(BVHAccel::from_mesh(scene, *obj, *mat)),
-            SceneNode::Group(aggregate) =>
-                Box::new(BVHAccel::from_aggregate(scene, aggregate))
-        }).collect();
+    /// Create the hierarchy for the given scene by reusing the layout of one
+    /// built for an earlier state of the scene, updating only the bounds of
+    /// its nodes. Groups and meshes whose number of primitives changed since
+    /// are built again. Refitting is quick, but trees get slower to traverse
+    /// as primitives move away from where they were when it was built.
+    ///
+    /// Only bounding volume hierarchies can be refit; scenes that use another
+    /// accelerator are built again.
+    pub fn refit(scene: &'s Scene, layout: &BVHLayout) -> BVHAccel<'s> {
+        match scene.accelerator {
+            AcceleratorKind::Bvh => BVHAccel::from_scene(scene, Some(layout)),
+            _ => BVHAccel::from(scene)
+        }
+    }
+
+    /// Layout of this hierarchy, for refitting to a later state of the scene
+    pub fn layout(&self) -> BVHLayout {
+        BVHLayout {
+            groups: self.groups.iter()
+                .map(|group| group.as_ref().map(|group| group.layout()))
+                .collect(),
+            meshes: self.meshes.iter()
+                .filter_map(|(obj, mesh)| match mesh {
+                    MeshAccel::Bvh(mesh) => Some((*obj, mesh.layout())),
+                    MeshAccel::KdTree(_) => None
+                })
+                .collect(),
+            ..self.tree.layout()
+        }
+    }
+
+    /// Shape of this hierarchy and those of its groups and meshes
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats::default();
+        self.add_stats(&mut stats);
+        for mesh in self.meshes.values() {
+            if let MeshAccel::Bvh(mesh) = mesh { mesh.add_stats(&mut stats) }
+        }
+        stats
+    }
+
+    fn add_stats(&self, stats: &mut BvhStats) {
+        self.tree.add_stats(stats);
+        for group in self.groups.iter().flatten() { group.add_stats(stats) }
+    }
+
+    fn from_scene(scene: &'s Scene, layout: Option<&BVHLayout>) -> BVHAccel<'s> {
+        let mut meshes = HashMap::new();
+        let mesh_layouts = layout.map(|layout| &layout.meshes);
+        let mut accel = BVHAccel::from_aggregate(scene, &scene.root, layout, &mut meshes, mesh_layouts);
+        accel.area_lights = ShapeLight::collect(scene);
+        if let Some(samples) = scene.light_samples {
+            // Lights that only take part in some of the render are added up
+            let tree = LightTree::new(accel.lights().map(|(light, visibility)| (light, visibility == LightVisibility::ALL)));
+            accel.light_tree = tree.filter(|tree| tree.len() > samples);
+        }
+        accel.meshes = meshes;
+        accel.cut_outs = has_cut_outs(scene, &scene.root);
+        accel.object_ids = scene.accelerator == AcceleratorKind::Bvh;
+        if scene.integrator == Integrator::Irradiance {
+            accel.irradiance = Some(IrradianceCache::new(&accel.bound(), scene.irradiance_samples, scene.irradiance_error));
+        }
+        accel
+    }
+
+    /// All lights in the scene with the parts of the render they take part
+    /// in: those added to the scene directly, followed by area lights for
+    /// primitives with emissive materials
+    pub fn lights(&self) -> impl Iterator<Item = (&dyn Light, LightVisibility)> {
+        self.scene.lights().iter()
+            .map(|light| light.as_ref())
+            .zip(self.scene.light_visibility().iter().copied())
+            .chain(self.area_lights.iter().map(|light| (light as &dyn Light, LightVisibility::ALL)))
+    }
+
+    /// Hierarchy to pick the point lights that light each hit from, if the
+    /// scene has more of them than it samples
+    pub(crate) fn light_tree(&self) -> Option<&LightTree> {
+        self.light_tree.as_ref()
+    }
+
+    /// Cache of the indirect diffuse light in the scene, if rendered with the
+    /// irradiance integrator
+    pub(crate) fn irradiance_cache(&self) -> Option<&IrradianceCache> {
+        self.irradiance.as_ref()
+    }
+
+    /// Find the nearest intersection of the ray with the scene, looking past
+    /// the parts of surfaces cut out by the alpha masks of their materials
+    /// and the backs of surfaces in groups that cull them. Use this rather
+    /// than `intersect` on the root of the hierarchy, where the material of
+    /// each hit is known.
+    pub fn intersect_opaque(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive<'_> {
+        stats::record(|counts| counts.rays += 1);
+        self.intersect_past(ray, isect, false)
+    }
+
+    /// Find the nearest intersection as for `intersect_opaque`, also looking
+    /// past surfaces that don't cast shadows if shadow is true
+    fn intersect_past(&self, ray: &Ray, isect: &mut RayIntersection, shadow: bool) -> OptionalPrimitive<'_> {
+        let start = *isect;
+        let mut ray = *ray;
+        for _ in 0..MAX_CUT_OUT_LAYERS {
+            let hit = self.intersect(&ray, isect)?;
+            let material = self.scene.material(hit.material().unwrap_or(isect.material));
+            let skipped = (shadow && !isect.casts_shadows)
+                || (isect.cull_backface && isect.ns().dot(ray.d) > 0.0);
+            if !skipped && !material.is_cut_out(&SurfaceInteraction::from(&ray, isect), self.scene) {
+                return Some(hit)
+            }
+
+            // Continue just past the cut-out hit
+            ray = ray.within(isect.t, ray.t_max);
+            *isect = start;
+        }
+        self.intersect(&ray, isect)
+    }
+
+    /// Whether anything in the scene blocks the given ray within its range,
+    /// ignoring groups that don't cast shadows. Uses the faster `intersects`
+    /// test unless the scene has surfaces cut out by alpha masks.
+    pub fn occluded(&self, ray: &Ray) -> bool {
+        stats::record(|counts| counts.shadow_rays += 1);
+        if self.cut_outs {
+            self.intersect_past(ray, &mut RayIntersection::default(), true).is_some()
+        } else {
+            self.intersects(ray)
+        }
+    }
+
+    /// Create the structure chosen for the scene over the triangles of the
+    /// given mesh
+    fn from_mesh(scene: &'s Scene, mesh: ObjRef, layout: Option<&BVHLayout>) -> MeshAccel<'s> {
+        match scene.accelerator {
+            AcceleratorKind::Bvh => MeshAccel::Bvh(Arc::new(TriangleBVH::new(scene, mesh, layout))),
+            AcceleratorKind::KdTree => {
+                let triangles: Vec<PrimBox<'s>> = TriangleIterator::new(scene.mesh(mesh).unwrap())
+                    .map(|t| -> PrimBox<'s> { Box::new(t) })
+                    .collect();
+                MeshAccel::KdTree(Arc::new(KdTreeAccel::new(triangles)))
+            }
+        }
+    }
+
+    /// Create an instance of the given mesh, building the hierarchy of its
+    /// triangles the first time the mesh is used. The built hierarchies are
+    /// kept in the given map. Mesh layouts, if any, are refit.
+    fn instance(
+        scene: &'s Scene,
+        mesh: ObjRef,
+        transform: Transformation,
+        material: Option<MaterialRef>,
+        meshes: &mut HashMap<ObjRef, MeshAccel<'s>>,
+        mesh_layouts: Option<&HashMap<ObjRef, BVHLayout>>
+    ) -> PrimBox<'s> {
+        let layout = mesh_layouts.and_then(|layouts| layouts.get(&mesh));
+        let accel = match meshes.entry(mesh).or_insert_with(|| BVHAccel::from_mesh(scene, mesh, layout)) {
+            MeshAccel::Bvh(accel) => accel.clone() as Arc<dyn Primitive + 's>,
+            MeshAccel::KdTree(accel) => accel.clone() as Arc<dyn Primitive + 's>
+        };
+        Box::new(Instance::new(accel, transform, material))
+    }
+
+    /// Arrange the given primitives in the structure chosen for the scene.
+    /// Other structures are placed in a hierarchy of their own, which handles
+    /// the transformation and material of the group.
+    fn accelerate(scene: &'s Scene, primitives: Vec<PrimBox<'s>>) -> Vec<PrimBox<'s>> {
+        match scene.accelerator {
+            AcceleratorKind::Bvh => primitives,
+            AcceleratorKind::KdTree => vec![Box::new(KdTreeAccel::new(primitives))]
+        }
+    }
+
+    /// Create the hierarchy for the given group. If a layout is given, the
+    /// group and its descendants are refit to it where possible.
+    fn from_aggregate(
+        scene: &'s Scene,
+        aggregate: &'s node::Aggregate,
+        layout: Option<&BVHLayout>,
+        meshes: &mut HashMap<ObjRef, MeshAccel<'s>>,
+        mesh_layouts: Option<&HashMap<ObjRef, BVHLayout>>
+    ) -> BVHAccel<'s> {
+        let mut primitives: Vec<PrimBox<'s>> = Vec::with_capacity(aggregate.contents.len());
+        let mut groups = Vec::with_capacity(aggregate.contents.len());
+        BVHAccel::add_contents(scene, aggregate, None, layout, &mut primitives, &mut groups, meshes, mesh_layouts);
+
+        let primitives = BVHAccel::accelerate(scene, primitives);
         let per_node = primitives.len();
-        BVHAccel::new(scene, primitives, &aggregate.transform, None, per_node, aggregate.swap_backface)
+        let mut accel = BVHAccel::new(scene, primitives, &aggregate.transform, None, per_node, aggregate.swap_backface, layout);
+        accel.motion = aggregate.motion();
+        accel.cull_backface = aggregate.cull_backface;
+        accel.cast_shadows = aggregate.cast_shadows;
+        accel.receive_shadows = aggregate.receive_shadows;
+        accel.groups = groups;
+        accel
+    }
+
+    /// Add the primitives for the contents of the given group to the given
+    /// lists, along with the hierarchies of the groups among them. Contents
+    /// of groups flattened into the hierarchy being built are placed in it by
+    /// the given transformation, composed from those of the groups.
+    #[allow(clippy::too_many_arguments)]
+    fn add_contents(
+        scene: &'s Scene,
+        aggregate: &'s node::Aggregate,
+        placement: Option<&Transformation>,
+        layout: Option<&BVHLayout>,
+        primitives: &mut Vec<PrimBox<'s>>,
+        groups: &mut Vec<Option<Arc<BVHAccel<'s>>>>,
+        meshes: &mut HashMap<ObjRef, MeshAccel<'s>>,
+        mesh_layouts: Option<&HashMap<ObjRef, BVHLayout>>
+    ) {
+        let place = |primitive: PrimBox<'s>| -> PrimBox<'s> {
+            match placement {
+                Some(transform) => Box::new(Instance::new(Arc::from(primitive), transform.clone(), None)),
+                None => primitive
+            }
+        };
+        let within = |transform: &Transformation| match placement {
+            Some(placement) => transform.concat(placement),
+            None => transform.clone()
+        };
+
+        for node in aggregate.contents.iter() {
+            let (primitive, group) = match node {
+                SceneNode::Geometry(shape, mat) =>
+                    (place(geometry(shape, *mat)), None),
+                SceneNode::Mesh(obj, mat) =>
+                    (BVHAccel::instance(scene, *obj, within(&transform::ID), *mat, meshes, mesh_layouts), None),
+                SceneNode::Group(aggregate) => match aggregate.contents.as_slice() {
+                    // Place copies of a mesh directly rather than in a hierarchy
+                    // of their own
+                    [SceneNode::Mesh(obj, mat)] if aggregate.is_plain() =>
+                        (BVHAccel::instance(scene, *obj, within(&aggregate.transform), *mat, meshes, mesh_layouts), None),
+                    _ if scene.flatten_transforms && aggregate.is_plain() => {
+                        let placement = within(&aggregate.transform);
+                        BVHAccel::add_contents(scene, aggregate, Some(&placement), layout, primitives, groups, meshes, mesh_layouts);
+                        continue
+                    },
+                    _ => {
+                        let layout = layout.and_then(|layout| layout.groups.get(primitives.len())).and_then(Option::as_ref);
+                        let group = Arc::new(BVHAccel::from_aggregate(scene, aggregate, layout, meshes, mesh_layouts));
+                        (place(Box::new(group.clone())), Some(group))
+                    }
+                }
+            };
+            primitives.push(primitive);
+            groups.push(group);
+        }
     }
 
     fn new(
         scene: &'s Scene,
         primitives: Vec<PrimBox<'s>>,
         transform: &'s Transformation,
-        material: Option<Material>,
+        material: Option<MaterialRef>,
         max_prims_per_node: usize,
-        swap_backface: bool
+        swap_backface: bool,
+        layout: Option<&BVHLayout>
     ) -> BVHAccel<'s> {
-
-        let arena = Arena::with_capacity(1024 * 1024);
-        let nprims = primitives.len();
-        let prim_info: Vec<BVHPrimitiveInfo> = primitives.iter()
+        let mut prim_info: Vec<BVHPrimitiveInfo> = primitives.iter()
             .enumerate()
             .map(|(i, prim)| BVHPrimitiveInfo::new(i, prim.bound()))
             .collect();
+        let tree = BVHTree::new(scene.bvh_build, &mut prim_info, max_prims_per_node, layout);
 
-        let mut accel = BVHAccel {
+        BVHAccel {
             scene,
             primitives,
-            nodes: vec![],
-            order: vec![std::usize::MAX; nprims], // Fill with dummy values
+            tree,
             transform,
+            motion: None,
             material,
-            max_prims_per_node: max_prims_per_node.min(255) as u8,
-            swap_backface
+            swap_backface,
+            cull_backface: false,
+            cast_shadows: true,
+            receive_shadows: true,
+            area_lights: vec![],
+            light_tree: None,
+            groups: vec![],
+            meshes: HashMap::new(),
+            cut_outs: false,
+            object_ids: false,
+            irradiance: None
+        }
+    }
+}
+
+impl BVHTree {
+    /// Build the tree over the primitives with the given info, with up to the
+    /// given number of primitives per leaf where splitting doesn't pay off.
+    /// Reuses the given layout instead if it has as many primitives.
+    pub(crate) fn new(
+        strategy: BvhBuildStrategy,
+        prim_info: &mut Vec<BVHPrimitiveInfo>,
+        max_prims_per_node: usize,
+        layout: Option<&BVHLayout>
+    ) -> BVHTree {
+        let nprims = prim_info.len();
+        let mut tree = BVHTree {
+            nodes: vec![],
+            bounds: Bounds::none(),
+            order: vec![usize::MAX; nprims], // Fill with dummy values
+            max_prims_per_node: max_prims_per_node.min(255) as u8
         };
 
+        // Without primitives, the root is left without children
+        if nprims == 0 {
+            tree.nodes.push(LinearBVHNode::empty());
+            return tree
+        }
+
+        // Reuse the given layout if it still fits the primitives
+        if let Some(layout) = layout.filter(|layout| layout.order.len() == nprims) {
+            tree.nodes = layout.nodes.clone();
+            tree.order = layout.order.clone();
+            tree.bounds = tree.refit_node(0, prim_info);
+            return tree
+        }
+
+        let arena = Arena::with_capacity(1024 * 1024);
         let mut total_nodes = 0;
-        let node = accel.build(&arena, &prim_info, &mut total_nodes);
-        accel.nodes = vec![ // Fill with dummy nodes
-            LinearBVHNode {
-                bounds: Bounds::none(),
-                content: LinearBVHNodeType::Leaf(0, 0)
-            }
-        ; total_nodes];
+        let node = match strategy {
+            BvhBuildStrategy::Hlbvh => tree.build(&arena, prim_info, &mut total_nodes),
+            BvhBuildStrategy::Sah => tree.build_sah(&arena, prim_info, &mut total_nodes, &mut 0)
+        };
 
-        accel.flatten_bvh_tree(node, &mut 0);
-        accel
+        // Each node of the wide tree replaces up to BVH_WIDTH - 1 binary ones
+        tree.nodes.reserve(total_nodes / (BVH_WIDTH - 1) + 1);
+        tree.bounds = node.bounds;
+        tree.flatten_bvh_tree(node);
+        tree
+    }
+
+    /// Layout of this tree, without any groups or meshes
+    pub(super) fn layout(&self) -> BVHLayout {
+        BVHLayout {
+            nodes: self.nodes.clone(),
+            order: self.order.clone(),
+            groups: vec![],
+            meshes: HashMap::new()
+        }
+    }
+
+    pub(super) fn add_stats(&self, stats: &mut BvhStats) {
+        stats.hierarchies += 1;
+        let mut to_visit = vec![(LinearBVHChild::Interior(0), 0)];
+        while let Some((child, depth)) = to_visit.pop() {
+            match child {
+                LinearBVHChild::Leaf(_, nprims) => {
+                    stats.leaves += 1;
+                    stats.primitives += nprims as usize;
+                    stats.max_leaf_primitives = stats.max_leaf_primitives.max(nprims as usize);
+                    stats.max_depth = stats.max_depth.max(depth)
+                },
+                LinearBVHChild::Interior(offset) => {
+                    stats.nodes += 1;
+                    to_visit.extend(self.nodes[offset as usize].children.iter().map(|child| (*child, depth + 1)))
+                },
+                LinearBVHChild::Empty => ()
+            }
+        }
     }
 
     /// Build the BVH tree with the hierarchical linear bounding volume hierachy algorithm
@@ -210,7 +645,7 @@ impl<'s> BVHAccel<'s> {
     ) -> &'a BVHBuildNode<'a> {
         // Compute bounding box of all primitive centroids
         let bounds = prim_info.iter()
-            .fold(Bounds::none(), |bounds, info| bounds.union(&info.bounds));
+            .fold(Bounds::none(), |bounds, info| bounds.point_union(&info.centroid));
 
         // Compute Morton indeces of primitives
         // TODO: Parallelize
@@ -269,7 +704,109 @@ impl<'s> BVHAccel<'s> {
         let mut finished_treelets: Vec<&'a BVHBuildNode<'a>> = treelets.iter()
         .map(|treelet| treelet.node).collect();
 
-        BVHAccel::build_upper_sah(arena, &mut finished_treelets[..], total_nodes)
+        BVHTree::build_upper_sah(arena, &mut finished_treelets[..], total_nodes)
+    }
+
+    /// Build the BVH tree by recursively splitting the given primitives where
+    /// the surface area heuristic is lowest. Ordered prims offset is the next
+    /// free entry in the primitive order.
+    fn build_sah<'a>(
+        &mut self,
+        arena: &'a Arena<BVHBuildNode<'a>>,
+        prim_info: &mut [BVHPrimitiveInfo],
+        total_nodes: &mut BVHPrimCount,
+        ordered_prims_offset: &mut usize
+    ) -> &'a BVHBuildNode<'a> {
+        let node = arena.alloc(BVHBuildNode {
+            content: BVHNodeType::Leaf(0, 0),
+            bounds: Bounds::none()
+        });
+        *total_nodes += 1;
+
+        let nprims = prim_info.len();
+        let bounds = prim_info.iter()
+            .fold(Bounds::none(), |bounds, info| bounds.union(&info.bounds));
+        let centroid_bounds = prim_info.iter()
+            .fold(Bounds::none(), |bounds, info| bounds.point_union(&info.centroid));
+        let dim = centroid_bounds.maximum_extent();
+
+        // Primitives whose centroids coincide cannot be split
+        if nprims == 1 || centroid_bounds.max[dim] == centroid_bounds.min[dim] {
+            self.init_sah_leaf(node, prim_info, bounds, ordered_prims_offset);
+            return node
+        }
+
+        let mid = if nprims <= 4 {
+            // Too few primitives for buckets to matter, split in equal halves
+            let mid = nprims / 2;
+            prim_info.select_nth_unstable_by(mid, |a, b| {
+                a.centroid[dim].partial_cmp(&b.centroid[dim]).unwrap()
+            });
+            mid
+        } else {
+            let bucket = |info: &BVHPrimitiveInfo| {
+                let b0 = (info.centroid[dim] - centroid_bounds.min[dim]) /
+                    (centroid_bounds.max[dim] - centroid_bounds.min[dim]);
+                ((BVH_NBUCKETS as f64 * b0) as usize).min(BVH_NBUCKETS - 1)
+            };
+
+            let mut buckets: [BVHBucketInfo; BVH_NBUCKETS] = [
+                BVHBucketInfo { count: 0, bounds: Bounds::none() }; BVH_NBUCKETS
+            ];
+            for info in prim_info.iter() {
+                let b = bucket(info);
+                buckets[b].count += 1;
+                buckets[b].bounds = buckets[b].bounds.union(&info.bounds);
+            }
+
+            // Compute costs for splitting after each bucket but the last
+            let mut cost: [f64; BVH_NBUCKETS - 1] = [0.0; BVH_NBUCKETS - 1];
+            for (i, split_cost) in cost.iter_mut().enumerate() {
+                let (b0, count0) = (0..=i).fold((Bounds::none(), 0), |(b, count), j| {
+                    (b.union(&buckets[j].bounds), count + buckets[j].count)
+                });
+                let (b1, count1) = ((i+1)..BVH_NBUCKETS).fold((Bounds::none(), 0), |(b, count), j| {
+                    (b.union(&buckets[j].bounds), count + buckets[j].count)
+                });
+                let area = |b: Bounds, count| if count == 0 { 0.0 } else { count as f64 * b.surface_area() };
+                *split_cost = 0.125 + (area(b0, count0) + area(b1, count1)) / bounds.surface_area();
+            }
+
+            let min_cost_split_bucket = cost.iter().enumerate().fold(0, |bucket, (i, c)| {
+                if *c < cost[bucket] { i } else { bucket }
+            });
+
+            // Keep the primitives together if that's cheaper than splitting
+            let leaf_cost = nprims as f64;
+            if nprims < self.max_prims_per_node as usize && cost[min_cost_split_bucket] >= leaf_cost {
+                self.init_sah_leaf(node, prim_info, bounds, ordered_prims_offset);
+                return node
+            }
+
+            let (lo, _) = partition(prim_info, |info| bucket(info) <= min_cost_split_bucket);
+            lo.len()
+        };
+
+        let (lo, hi) = prim_info.split_at_mut(mid);
+        node.init_interior(
+            self.build_sah(arena, lo, total_nodes, ordered_prims_offset),
+            self.build_sah(arena, hi, total_nodes, ordered_prims_offset));
+        node
+    }
+
+    fn init_sah_leaf<'a>(
+        &mut self,
+        node: &mut BVHBuildNode<'a>,
+        prim_info: &[BVHPrimitiveInfo],
+        bounds: Bounds,
+        ordered_prims_offset: &mut usize
+    ) {
+        let first_prim_offset = *ordered_prims_offset;
+        for (i, info) in prim_info.iter().enumerate() {
+            self.order[first_prim_offset + i] = info.number;
+        }
+        *ordered_prims_offset += prim_info.len();
+        node.init_leaf(first_prim_offset, prim_info.len(), bounds);
     }
 
     /// Creates and returns LBVH nodes and returns the the total of nodes
@@ -341,8 +878,7 @@ impl<'s> BVHAccel<'s> {
             prim_info, total_nodes,
             ordered_prims_offset, bit_index - 1);
 
-        let axis = (bit_index % 3) as BVHSplitAxis;
-        node.init_interior(axis, lbvh0, lbvh1);
+        node.init_interior(lbvh0, lbvh1);
         (node, nodes)
     }
 
@@ -419,109 +955,392 @@ impl<'s> BVHAccel<'s> {
             if b == BVH_NBUCKETS { b = BVH_NBUCKETS - 1 };
             b <= min_cost_split_bucket
         });
-        node.init_interior(dim,
-            BVHAccel::build_upper_sah(arena, lo_roots, total_nodes),
-            BVHAccel::build_upper_sah(arena, hi_roots, total_nodes));
+        node.init_interior(
+            BVHTree::build_upper_sah(arena, lo_roots, total_nodes),
+            BVHTree::build_upper_sah(arena, hi_roots, total_nodes));
 
         node
     }
 
+    /// Append the wide node for the given binary build node and its
+    /// descendants. Returns the offset of the node.
     // a is the lifetime of the arena as usual
-    fn flatten_bvh_tree<'a>(
-        &mut self,
-        node: &'a BVHBuildNode<'a>,
-        offset: &mut usize
-    ) -> usize {
-        let my_offset = *offset; *offset += 1;
-        self.nodes[my_offset].bounds = node.bounds;
-        match node.content {
-            BVHNodeType::Leaf(prim_offset, nprims) => {
-                self.nodes[my_offset].content =
-                    LinearBVHNodeType::Leaf(prim_offset as u32, nprims as u16);
-            }
-            BVHNodeType::Interior(axis, c0, c1) => {
-                let (_, second_offset) = (
-                    self.flatten_bvh_tree(c0, offset),
-                    self.flatten_bvh_tree(c1, offset)
-                );
-                self.nodes[my_offset].content
-                    = LinearBVHNodeType::Interior(axis as u8, second_offset as u32)
+    fn flatten_bvh_tree<'a>(&mut self, node: &'a BVHBuildNode<'a>) -> usize {
+        let my_offset = self.nodes.len();
+        self.nodes.push(LinearBVHNode::empty());
+
+        // Pull grandchildren up into this node, largest first, until it is
+        // full or only leaves remain
+        let mut children: Vec<&'a BVHBuildNode<'a>> = Vec::with_capacity(BVH_WIDTH);
+        children.push(node);
+        while children.len() < BVH_WIDTH {
+            let largest = children.iter().enumerate()
+                .filter(|(_, child)| matches!(child.content, BVHNodeType::Interior(..)))
+                .max_by(|(_, a), (_, b)| a.bounds.surface_area().partial_cmp(&b.bounds.surface_area()).unwrap())
+                .map(|(i, _)| i);
+            match largest {
+                Some(i) => if let BVHNodeType::Interior(c0, c1) = children[i].content {
+                    children[i] = c0;
+                    children.insert(i + 1, c1);
+                },
+                None => break
             }
         }
 
+        let mut linear = LinearBVHNode::empty();
+        for (i, child) in children.iter().enumerate() {
+            linear.set_child_bounds(i, &child.bounds);
+            linear.children[i] = match child.content {
+                BVHNodeType::Leaf(prim_offset, nprims) =>
+                    LinearBVHChild::Leaf(prim_offset as u32, nprims as u16),
+                BVHNodeType::Interior(..) =>
+                    LinearBVHChild::Interior(self.flatten_bvh_tree(child) as u32)
+            };
+        }
+
+        self.nodes[my_offset] = linear;
         my_offset
     }
+
+    /// Recompute the bounds of the children of the node at the given offset
+    /// and its descendants from the bounds of their primitives. Returns the
+    /// bounds of the node.
+    fn refit_node(&mut self, offset: usize, prim_info: &[BVHPrimitiveInfo]) -> Bounds {
+        let mut node = self.nodes[offset];
+        let mut bounds = Bounds::none();
+        for i in 0..BVH_WIDTH {
+            let child_bounds = match node.children[i] {
+                LinearBVHChild::Empty => continue,
+                LinearBVHChild::Leaf(prim_offset, nprims) => {
+                    let prims = (prim_offset as usize)..(prim_offset as usize + nprims as usize);
+                    prims.fold(Bounds::none(), |bounds, i| bounds.union(&prim_info[self.order[i]].bounds))
+                },
+                LinearBVHChild::Interior(child) => self.refit_node(child as usize, prim_info)
+            };
+            node.set_child_bounds(i, &child_bounds);
+            bounds = bounds.union(&child_bounds);
+        }
+        self.nodes[offset] = node;
+        bounds
+    }
 }
 
 impl<'s> Primitive for BVHAccel<'s> {
     fn bound(&self) -> Bounds {
-        self.transform.transform_bounds(self.nodes[0].bounds)
+        match &self.motion {
+            Some(motion) => motion.motion_bounds(self.tree.bounds),
+            None => self.transform.transform_bounds(self.tree.bounds)
+        }
     }
 
     fn intersect(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive {
-        let ray = self.transform.inverse_transform_ray(*ray);
-        let dir_is_neg = [ray.dinv.x < 0.0, ray.dinv.y < 0.0, ray.dinv.z < 0.0];
-        let mut isect_inv = self.transform.inverse_transform_ray_intersection(isect);
+        // Moving groups are wherever they are at the time of the ray
+        let animated;
+        let transform = match &self.motion {
+            Some(motion) => { animated = motion.interpolate(ray.time); &animated },
+            None => self.transform
+        };
+
+        let ray = transform.inverse_transform_ray(*ray);
+        let mut isect_inv = transform.inverse_transform_ray_intersection(isect);
 
         let mut hit = None;
-        let mut to_visit_offset = 0;
-        let mut current_node_index = 0;
-        let mut nodes_to_visit: [usize; 64] = [0; 64];
+        self.tree.intersect(&ray, isect_inv.t, |prim_index| {
+            if let Some(primitive) = self.primitives[prim_index].intersect(&ray, &mut isect_inv) {
+                if self.object_ids { isect_inv.id = prim_index as u32 }
+                hit = Some(primitive);
+            }
+            isect_inv.t
+        });
 
-        loop {
-            let node = &self.nodes[current_node_index];
-            if !node.bounds.intersects(&ray) {
-                if to_visit_offset == 0 { break };
-                to_visit_offset -= 1;
-                current_node_index = nodes_to_visit[to_visit_offset];
-                continue;
+        // Transform normal before sending it back
+        if hit.is_some() {
+            *isect = transform.transform_ray_intersection(&isect_inv);
+
+            // Default material, for use when the shape doesn't provide one
+            if let Some(material) = self.material {
+                isect.set_material(material);
             }
 
-            match node.content {
-                LinearBVHNodeType::Leaf(prim_offset, nprims) => {
+            // Swap backfaces, if applicable
+            if self.swap_backface { isect.swap_backface() }
+
+            if self.cull_backface { isect.cull_backface = true }
+            if !self.cast_shadows { isect.casts_shadows = false }
+            if !self.receive_shadows { isect.receives_shadows = false }
+        }
+
+        hit
+    }
+
+    /// Stops at the first primitive hit, in whichever order the nodes are
+    /// visited, rather than looking for the nearest one. Used for shadow rays,
+    /// so groups that don't cast shadows are never hit.
+    fn intersects(&self, ray: &Ray) -> bool {
+        if !self.cast_shadows { return false }
+
+        let animated;
+        let transform = match &self.motion {
+            Some(motion) => { animated = motion.interpolate(ray.time); &animated },
+            None => self.transform
+        };
+
+        let ray = transform.inverse_transform_ray(*ray);
+        self.tree.intersects(&ray, |prim_index| self.primitives[prim_index].intersects(&ray))
+    }
+}
+
+impl BVHTree {
+    /// Visit the leaves the given ray passes through, nearest first, calling
+    /// the given test for each of their primitives. The test returns the
+    /// distance along the ray to the nearest hit so far, starting from t, and
+    /// nodes beyond it are skipped.
+    #[inline]
+    pub(super) fn intersect(&self, ray: &Ray, mut t: f64, mut test: impl FnMut(BVHPrimNumber) -> f64) {
+        let lanes = BVHRayLanes::new(ray);
+        let mut to_visit_offset = 0;
+        let mut nodes_to_visit = [(LinearBVHChild::Empty, 0.0); BVH_STACK_SIZE];
+        let mut current = LinearBVHChild::Interior(0);
+        let (mut node_tests, mut primitive_tests) = (0, 0);
+
+        loop {
+            match current {
+                LinearBVHChild::Leaf(prim_offset, nprims) => {
                     // intersect with primitives in leaf node
+                    primitive_tests += nprims as u64;
                     for i in 0..(nprims as u32) {
-                        let prim_index = self.order[(prim_offset + i) as usize];
-                        if let Some(primitive) = self.primitives[prim_index].intersect(&ray, &mut isect_inv) {
-                            hit = Some(primitive);
-                        }
+                        t = test(self.order[(prim_offset + i) as usize]);
                     }
-                    if to_visit_offset == 0 { break };
-                    to_visit_offset -= 1;
-                    current_node_index = nodes_to_visit[to_visit_offset];
                 }
-                LinearBVHNodeType::Interior(axis, child_offset) => {
-                    // Put far BVH node on nodes_to_visit stack, advance to near
-                    // node. Node direction helps determine which way to go.
-                    if dir_is_neg[axis as usize] {
-                        nodes_to_visit[to_visit_offset] = current_node_index + 1;
-                        current_node_index = child_offset as usize;
-                    } else {
-                        nodes_to_visit[to_visit_offset] = child_offset as usize;
-                        current_node_index += 1;
+                LinearBVHChild::Interior(offset) => {
+                    // Visit the nearest child next and stack the others such
+                    // that the next nearest is on top
+                    let mut near = [(LinearBVHChild::Empty, 0.0); BVH_WIDTH];
+                    let mut nnear = 0;
+                    let node = &self.nodes[offset as usize];
+                    node_tests += 1;
+                    let (mask, tnear) = node.intersect(&lanes, t.min(ray.t_max));
+                    let tnear = tnear.to_array();
+                    for (i, (&child, &tchild)) in node.children.iter().zip(tnear.iter()).enumerate() {
+                        if mask & (1 << i) == 0 { continue }
+                        if let LinearBVHChild::Empty = child { continue }
+                        near[nnear] = (child, widen(tchild));
+                        nnear += 1;
+                    }
+                    let near = &mut near[..nnear];
+                    near.sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+                    if let Some((child, _)) = near.last() {
+                        current = *child;
+                        for entry in near[..(nnear - 1)].iter() {
+                            nodes_to_visit[to_visit_offset] = *entry;
+                            to_visit_offset += 1;
+                        }
+                        continue
                     }
-                    to_visit_offset += 1;
                 }
+                LinearBVHChild::Empty => ()
+            }
+
+            // Continue with the next stacked node, skipping those that are
+            // farther than the nearest hit so far
+            current = LinearBVHChild::Empty;
+            while to_visit_offset > 0 {
+                to_visit_offset -= 1;
+                let (child, tnear) = nodes_to_visit[to_visit_offset];
+                if tnear <= t { current = child; break }
             }
+            if let LinearBVHChild::Empty = current { break }
         }
+        stats::record(|counts| {
+            counts.node_tests += node_tests;
+            counts.primitive_tests += primitive_tests
+        });
+    }
 
-        // Transform normal before sending it back
-        if hit.is_some() {
-            *isect = self.transform.transform_ray_intersection(&isect_inv);
+    /// Whether the given test passes for any primitive in the leaves the
+    /// given ray passes through, stopping at the first one that does
+    #[inline]
+    pub(super) fn intersects(&self, ray: &Ray, mut test: impl FnMut(BVHPrimNumber) -> bool) -> bool {
+        let lanes = BVHRayLanes::new(ray);
+        let mut to_visit_offset = 0;
+        let mut nodes_to_visit = [LinearBVHChild::Empty; BVH_STACK_SIZE];
+        let mut current = LinearBVHChild::Interior(0);
+        let (mut node_tests, mut primitive_tests) = (0, 0);
 
-            // Default material, for use when the shape doesn't provide one
-            if let Some(material) = self.material {
-                isect.set_material(material);
+        let hit = 'traverse: loop {
+            match current {
+                LinearBVHChild::Leaf(prim_offset, nprims) => {
+                    for i in 0..(nprims as u32) {
+                        primitive_tests += 1;
+                        if test(self.order[(prim_offset + i) as usize]) { break 'traverse true }
+                    }
+                }
+                LinearBVHChild::Interior(offset) => {
+                    let node = &self.nodes[offset as usize];
+                    node_tests += 1;
+                    let (mask, _) = node.intersect(&lanes, ray.t_max);
+                    for i in 0..BVH_WIDTH {
+                        if mask & (1 << i) == 0 { continue }
+                        if let LinearBVHChild::Empty = node.children[i] { continue }
+                        nodes_to_visit[to_visit_offset] = node.children[i];
+                        to_visit_offset += 1;
+                    }
+                }
+                LinearBVHChild::Empty => ()
             }
 
-            // Swap backfaces, if applicable
-            if self.swap_backface { isect.swap_backface() }
+            if to_visit_offset == 0 { break false }
+            to_visit_offset -= 1;
+            current = nodes_to_visit[to_visit_offset];
+        };
+        stats::record(|counts| {
+            counts.node_tests += node_tests;
+            counts.primitive_tests += primitive_tests
+        });
+        hit
+    }
+}
+
+/// What a child slot of a node holds, as listed by `BVHTree::children`
+#[cfg(feature = "gpu")]
+pub(crate) enum TreeChild {
+    Empty,
+
+    /// Offset of the leaf's primitives in the order of the tree and their
+    /// count
+    Leaf(usize, usize),
+
+    /// Index of an interior node
+    Interior(usize)
+}
+
+#[cfg(feature = "gpu")]
+impl BVHTree {
+    /// Bounds and contents of every child slot of every node, BVH_WIDTH per
+    /// node starting with the root, for copying the tree to the GPU
+    pub(crate) fn children(&self) -> impl Iterator<Item = (Bounds, TreeChild)> + '_ {
+        self.nodes.iter().flat_map(|node| {
+            let (min, max) = (node.min.map(|lanes| lanes.to_array()), node.max.map(|lanes| lanes.to_array()));
+            (0..BVH_WIDTH).map(move |i| {
+                let bounds = Bounds {
+                    min: Point::new(widen(min[0][i]), widen(min[1][i]), widen(min[2][i])),
+                    max: Point::new(widen(max[0][i]), widen(max[1][i]), widen(max[2][i]))
+                };
+                let child = match node.children[i] {
+                    LinearBVHChild::Empty => TreeChild::Empty,
+                    LinearBVHChild::Leaf(offset, nprims) => TreeChild::Leaf(offset as usize, nprims as usize),
+                    LinearBVHChild::Interior(offset) => TreeChild::Interior(offset as usize)
+                };
+                (bounds, child)
+            })
+        })
+    }
+
+    /// Primitive numbers in the order the leaves refer to them
+    pub(crate) fn order(&self) -> &[BVHPrimNumber] {
+        &self.order
+    }
+}
+
+/// Whether any material in the given group or its children has an alpha mask,
+/// including those of the triangle groups of its meshes, or any of the groups
+/// cull backfaces
+pub(crate) fn has_cut_outs(scene: &Scene, aggregate: &node::Aggregate) -> bool {
+    aggregate.cull_backface || aggregate.contents.iter().any(|node| match node {
+        SceneNode::Geometry(_, material) => scene.material(*material).alpha().is_some(),
+        SceneNode::Mesh(obj, material) =>
+            material.is_some_and(|material| scene.material(material).alpha().is_some())
+            || scene.mesh(*obj).is_some_and(|mesh| mesh.materials().any(|material| scene.material(material).alpha().is_some())),
+        SceneNode::Group(aggregate) => has_cut_outs(scene, aggregate)
+    })
+}
+
+impl LinearBVHNode {
+    fn empty() -> LinearBVHNode {
+        LinearBVHNode {
+            min: [Lanes::splat(Lane::INFINITY); 3],
+            max: [Lanes::splat(Lane::NEG_INFINITY); 3],
+            children: [LinearBVHChild::Empty; BVH_WIDTH]
         }
+    }
 
-        hit
+    fn set_child_bounds(&mut self, i: usize, bounds: &Bounds) {
+        for axis in 0..3 {
+            let (mut min, mut max) = (self.min[axis].to_array(), self.max[axis].to_array());
+            min[i] = round_down(bounds.min[axis]);
+            max[i] = round_up(bounds.max[axis]);
+            self.min[axis] = Lanes::from(min);
+            self.max[axis] = Lanes::from(max);
+        }
+    }
+
+    /// Test the ray against the bounds of all children, ignoring those
+    /// entered beyond tmax. Returns a bit mask of the children hit along with
+    /// the distance along the ray at which each one is entered.
+    #[inline]
+    fn intersect(&self, ray: &BVHRayLanes, tmax: f64) -> (i32, Lanes) {
+        let mut t0 = Lanes::splat(0.0);
+        let mut t1 = Lanes::splat(round_up(tmax));
+
+        // Widen the far distances by their rounding error so that rays
+        // grazing a box are not missed
+        let robust = Lanes::splat(1.0 + 2.0 * gamma::<Lane>(3));
+        for axis in 0..3 {
+            let (near, far) = if ray.dir_is_neg[axis] {
+                (self.max[axis], self.min[axis])
+            } else {
+                (self.min[axis], self.max[axis])
+            };
+            t0 = t0.max((near - ray.near_origin[axis]) * ray.dinv[axis]);
+            t1 = t1.min((far - ray.far_origin[axis]) * ray.dinv[axis] * robust);
+        }
+        (t0.cmp_le(t1).move_mask(), t0)
+    }
+}
+
+impl BVHRayLanes {
+    fn new(ray: &Ray) -> BVHRayLanes {
+        let (mut near_origin, mut far_origin) = ([Lanes::splat(0.0); 3], [Lanes::splat(0.0); 3]);
+        let mut dinv = [Lanes::splat(0.0); 3];
+        let dir_is_neg = [ray.dinv.x < 0.0, ray.dinv.y < 0.0, ray.dinv.z < 0.0];
+        for axis in 0..3 {
+            let (lower, upper) = (round_down(ray.origin[axis]), round_up(ray.origin[axis]));
+            let (near, far) = if dir_is_neg[axis] { (lower, upper) } else { (upper, lower) };
+            near_origin[axis] = Lanes::splat(near);
+            far_origin[axis] = Lanes::splat(far);
+            dinv[axis] = Lanes::splat(ray.dinv[axis] as Lane);
+        }
+        BVHRayLanes { near_origin, far_origin, dinv, dir_is_neg }
     }
 }
 
+/// The given value in the precision of the lanes, rounded down
+#[cfg(not(feature = "f32-bvh"))]
+#[inline]
+fn round_down(x: f64) -> Lane { x }
+
+/// The given value in the precision of the lanes, rounded up
+#[cfg(not(feature = "f32-bvh"))]
+#[inline]
+fn round_up(x: f64) -> Lane { x }
+
+/// The given value of a lane in double precision
+#[cfg(not(feature = "f32-bvh"))]
+#[inline]
+fn widen(x: Lane) -> f64 { x }
+
+#[cfg(feature = "f32-bvh")]
+#[inline]
+fn round_down(x: f64) -> Lane { f32_round_down(x) }
+
+#[cfg(feature = "f32-bvh")]
+#[inline]
+fn round_up(x: f64) -> Lane { f32_round_up(x) }
+
+#[cfg(feature = "f32-bvh")]
+#[inline]
+fn widen(x: Lane) -> f64 { f64::from(x) }
+
 impl BVHPrimitiveInfo {
     pub fn new(number: BVHPrimNumber, bounds: Bounds) -> BVHPrimitiveInfo {
         BVHPrimitiveInfo {
@@ -541,9 +1360,9 @@ impl<'a> BVHBuildNode<'a> {
         }
     }
 
-    pub fn interior(axis: BVHSplitAxis, c0: &'a BVHBuildNode<'a>, c1: &'a BVHBuildNode<'a>) -> BVHBuildNode<'a> {
+    pub fn interior(c0: &'a BVHBuildNode<'a>, c1: &'a BVHBuildNode<'a>) -> BVHBuildNode<'a> {
         BVHBuildNode {
-            content: BVHNodeType::Interior(axis, c0, c1),
+            content: BVHNodeType::Interior(c0, c1),
             bounds: c0.bounds.union(&c1.bounds)
         }
     }
@@ -554,13 +1373,14 @@ impl<'a> BVHBuildNode<'a> {
         self.bounds = bounds;
     }
 
-    pub fn init_interior(&mut self, axis: BVHSplitAxis, c0: &'a BVHBuildNode<'a>, c1: &'a BVHBuildNode<'a>) {
-        self.content = BVHNodeType::Interior(axis, c0, c1);
+    pub fn init_interior(&mut self, c0: &'a BVHBuildNode<'a>, c1: &'a BVHBuildNode<'a>) {
+        self.content = BVHNodeType::Interior(c0, c1);
         self.bounds = c0.bounds.union(&c1.bounds);
     }
 }
 
-fn geometry<'s>(shape: &node::Shape, material: Material) -> PrimBox<'s> {
+/// Build the primitive for a geometry scene node
+pub(crate) fn geometry<'s>(shape: &node::Shape, material: MaterialRef) -> PrimBox<'s> {
     match shape {
         node::Shape::Sphere(o, r) =>
             Box::new(Sphere::new(*o, *r, material)),
@@ -568,14 +1388,24 @@ fn geometry<'s>(shape: &node::Shape, material: Material) -> PrimBox<'s> {
             Box::new(Cuboid::cube(*o, *d, material)),
         node::Shape::Cuboid(c0, c1) =>
             Box::new(Cuboid::new(*c0, *c1, material)),
+        node::Shape::Plane(o, n, s) =>
+            Box::new(Plane::new(*o, *n, *s, material)),
+        node::Shape::Disk(o, n, r) =>
+            Box::new(Disk::new(*o, *n, *r, material)),
+        node::Shape::Sdf(sdf) =>
+            Box::new(SdfPrimitive::new(sdf.clone(), material)),
+        node::Shape::Curve(cp, width, kind) =>
+            Box::new(Curve::new(*cp, *width, *kind, material)),
     }
 }
 
+/// Morton code of a point with coordinates in [0, 1024], interleaving the
+/// bits of x, y and z so that the lowest bit is that of x
 #[inline]
 fn encode_morton_3(v: &Vector) -> u32 {
     (left_shift_3(v.z as u32) << 2)
     | (left_shift_3(v.y as u32) << 1)
-    | (left_shift_3(v.z as u32))
+    | (left_shift_3(v.x as u32))
 }
 
 /// "Spreads" out the bottom 10 bits over the 32 bit range. The
@@ -633,3 +1463,278 @@ fn radix_sort(v: &mut Vec<MortonPrimitive>) {
         mem::swap(v, &mut temp)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{Rng, SeedableRng, rngs::SmallRng};
+    use crate::{Material, interaction::SurfaceInteraction, texture::Texture};
+
+    /// Mask that cuts out the half of UV space where u < 0.5
+    struct HalfMask;
+    impl Texture for HalfMask {
+        fn evaluate(&self, interaction: &SurfaceInteraction) -> Color {
+            Color::from_value(if interaction.uv.x < 0.5 { 0.0 } else { 1.0 })
+        }
+    }
+
+    #[test]
+    fn passes_through_cut_outs() {
+        let mut scene = Scene::new();
+        let card = scene.parse_obj("v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n").unwrap();
+        let mask = scene.add_texture(Box::new(HalfMask));
+        let cut_out = scene.add_material(Material::default().with_alpha(mask));
+        scene.root.add_obj_of(card, cut_out);
+        scene.root.add_plane([0.0, 0.0, -2.0], [0.0, 0.0, 1.0], [4.0, 4.0], MaterialRef::default());
+
+        let accel = BVHAccel::from(&scene);
+        let t = |x: f64| {
+            let mut isect = RayIntersection::default();
+            let ray = Ray::new(Point::new(x, 0.0, 1.0), -Vector::unit_z());
+            accel.intersect_opaque(&ray, &mut isect).map(|_| isect.t)
+        };
+
+        assert_eq!(t(0.5), Some(1.0));
+        assert_eq!(t(-0.5), Some(3.0));
+    }
+
+    #[test]
+    fn sah_build_matches_hlbvh() {
+        let mut scene = Scene::new();
+        for i in 0..64 {
+            let center = [(i % 4) as f64 * 2.0, ((i / 4) % 4) as f64 * 2.0, (i / 16) as f64 * 2.0];
+            scene.root.add_sphere(center, 0.5 + 0.1 * (i % 3) as f64, MaterialRef::default());
+        }
+        let rays: Vec<Ray> = (0..100)
+            .map(|i| Ray::new(Point::new(-5.0, 0.07 * i as f64, 0.13 * i as f64 - 3.0), Vector::new(1.0, 0.1, 0.05)))
+            .collect();
+
+        let hits = |scene: &Scene| -> Vec<f64> {
+            let accel = BVHAccel::from(scene);
+            rays.iter().map(|ray| {
+                let mut isect = RayIntersection::default();
+                accel.intersect(ray, &mut isect);
+                isect.t
+            }).collect()
+        };
+
+        let hlbvh = hits(&scene);
+        scene.set_bvh_build_strategy(BvhBuildStrategy::Sah);
+        assert_eq!(hlbvh, hits(&scene));
+        assert!(hlbvh.iter().any(|t| t.is_finite()));
+    }
+
+    #[test]
+    fn refit_matches_rebuild() {
+        let mut scene = Scene::new();
+        let mut group = node::Aggregate::new();
+        for i in 0..16 {
+            group.add_sphere([(i % 4) as f64 * 2.0, (i / 4) as f64 * 2.0, 0.0], 0.5, MaterialRef::default());
+        }
+        scene.root.add_group(group);
+        scene.root.add_sphere([0.0, 0.0, 5.0], 1.0, MaterialRef::default());
+
+        let layout = BVHAccel::from(&scene).layout();
+        if let SceneNode::Group(group) = &mut scene.root.contents[0] {
+            group.translate([1.0, 0.0, 3.0]).rotate_z(30.0);
+        }
+
+        let refit = BVHAccel::refit(&scene, &layout);
+        let built = BVHAccel::from(&scene);
+        let (a, b) = (refit.bound(), built.bound());
+        assert_eq!((a.min, a.max), (b.min, b.max));
+        for i in 0..100 {
+            let ray = Ray::new(Point::new(0.1 * i as f64 - 2.0, 0.07 * i as f64 - 1.0, -10.0), Vector::new(0.02, 0.01, 1.0));
+            let (mut isect_refit, mut isect_built) = (RayIntersection::default(), RayIntersection::default());
+            refit.intersect(&ray, &mut isect_refit);
+            built.intersect(&ray, &mut isect_built);
+            assert_eq!(isect_refit.t, isect_built.t);
+        }
+    }
+
+    #[test]
+    fn flattened_groups_match_nested() {
+        let mut scene = Scene::new();
+        let quad = scene.parse_obj("v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\nf 1 2 3 4\n").unwrap();
+        let mut group = node::Aggregate::new();
+        for i in 0..4 {
+            let mut inner = node::Aggregate::new();
+            inner.add_sphere([0.0, 0.0, 0.0], 0.5, MaterialRef::default());
+            inner.add_obj_of(quad, MaterialRef::default());
+            inner.translate([i as f64 * 2.0, 0.0, 0.0]).rotate_z(10.0 * i as f64);
+            group.add_group(inner);
+        }
+        // Groups that change shading keep a hierarchy of their own
+        let mut swapped = node::Aggregate::new();
+        swapped.add_sphere([0.0, 3.0, 0.0], 1.0, MaterialRef::default());
+        swapped.swap_backface();
+        group.add_group(swapped);
+        group.translate([-3.0, 0.0, 1.0]).scale(1.0, 1.5, 1.0);
+        scene.root.add_group(group);
+
+        let rays: Vec<Ray> = (0..200)
+            .map(|i| Ray::new(Point::new(0.05 * i as f64 - 5.0, 0.03 * i as f64 - 3.0, -10.0), Vector::new(0.01, 0.02, 1.0)))
+            .collect();
+        let hits = |accel: &BVHAccel| -> Vec<(f64, Normal)> {
+            rays.iter().map(|ray| {
+                let mut isect = RayIntersection::default();
+                accel.intersect(ray, &mut isect);
+                (isect.t, isect.n.unwrap_or(Normal::new(0.0, 0.0, 0.0)))
+            }).collect()
+        };
+
+        let (nested, nested_stats) = {
+            let accel = BVHAccel::from(&scene);
+            (hits(&accel), accel.stats())
+        };
+        scene.set_flatten_transforms(true);
+        let flat = BVHAccel::from(&scene);
+        for ((t0, n0), (t1, n1)) in nested.iter().zip(hits(&flat)) {
+            assert!(*t0 == t1 || (t0 - t1).abs() < 1e-9);
+            assert!((n0.0 - n1.0).magnitude() < 1e-9);
+        }
+        assert!(nested.iter().filter(|(t, _)| t.is_finite()).count() > 20);
+        assert_eq!(nested_stats.hierarchies, 8);
+        assert_eq!(flat.stats().hierarchies, 3);
+    }
+
+    #[test]
+    fn morton_codes_interleave_axes() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let p = [rng.gen_range(0, MORTON_SCALE), rng.gen_range(0, MORTON_SCALE), rng.gen_range(0, MORTON_SCALE)];
+            let code = encode_morton_3(&Vector::new(p[0] as f64, p[1] as f64, p[2] as f64));
+            assert_eq!(code >> RADIX_NBITS, 0);
+            for bit in 0..MORTON_BITS {
+                for axis in 0..3 {
+                    assert_eq!((code >> (3 * bit + axis)) & 1, (p[axis as usize] >> bit) & 1, "{:?}", p);
+                }
+            }
+        }
+
+        // The far edge of the bounds falls in the last cell
+        let far = encode_morton_3(&Vector::from_value(MORTON_SCALE as f64));
+        assert_eq!(far, encode_morton_3(&Vector::from_value((MORTON_SCALE - 1) as f64)));
+        assert_eq!(far, (1 << RADIX_NBITS) - 1);
+    }
+
+    #[test]
+    fn radix_sort_orders_by_code() {
+        let mut rng = SmallRng::seed_from_u64(2);
+        for len in (0..300).step_by(7) {
+            let prims: Vec<MortonPrimitive> = (0..len)
+                // Few distinct codes, so that many are equal
+                .map(|index| MortonPrimitive { index, code: rng.gen_range(0, 16) << rng.gen_range(0, 27) })
+                .collect();
+            let mut sorted = prims.clone();
+            radix_sort(&mut sorted);
+
+            // Sorted, keeping primitives with equal codes in order
+            let mut expected = prims;
+            expected.sort_by_key(|prim| prim.code);
+            assert!(sorted == expected);
+        }
+    }
+
+    #[test]
+    fn every_primitive_is_found() {
+        let mut rng = SmallRng::seed_from_u64(3);
+        for strategy in [BvhBuildStrategy::Hlbvh, BvhBuildStrategy::Sah].iter() {
+            let mut scene = Scene::new();
+            scene.set_bvh_build_strategy(*strategy);
+            let centers: Vec<[f64; 3]> = (0..200)
+                .map(|_| [rng.gen_range(-20.0, 20.0), rng.gen_range(-20.0, 20.0), rng.gen_range(-1.0, 1.0)])
+                .collect();
+            for center in centers.iter() {
+                scene.root.add_sphere(*center, 0.01, MaterialRef::default());
+            }
+            let accel = BVHAccel::from(&scene);
+
+            // Each sphere is too small for another to be in the way
+            for center in centers.iter() {
+                let ray = Ray::new(Point::new(center[0], center[1], 10.0), -Vector::unit_z());
+                let mut isect = RayIntersection::default();
+                assert!(accel.intersect(&ray, &mut isect).is_some(), "{:?} {:?}", strategy, center);
+                assert!((isect.t - (10.0 - center[2] - 0.01)).abs() < 1e-6);
+                assert!(accel.intersects(&ray));
+            }
+        }
+    }
+
+    #[test]
+    fn matches_brute_force_far_from_origin() {
+        // Far enough out that rounding node bounds or ray origins to single
+        // precision would lose hits if not done conservatively
+        let mut rng = SmallRng::seed_from_u64(4);
+        let mut scene = Scene::new();
+        let offset = 1e5;
+        let mut centers = vec![];
+        for _ in 0..300 {
+            let center = Point::new(offset + rng.gen_range(-5.0, 5.0), rng.gen_range(-5.0, 5.0), rng.gen_range(-5.0, 5.0));
+            scene.root.add_sphere(center.into(), rng.gen_range(0.001, 0.01), MaterialRef::default());
+            centers.push(center);
+        }
+        let accel = BVHAccel::from(&scene);
+        let primitives: Vec<PrimBox> = scene.root.contents.iter()
+            .map(|node| match node {
+                SceneNode::Geometry(shape, material) => geometry(shape, *material),
+                _ => unreachable!()
+            })
+            .collect();
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = Point::new(offset + rng.gen_range(-8.0, 8.0), rng.gen_range(-8.0, 8.0), rng.gen_range(-8.0, 8.0));
+            let target = centers[rng.gen_range(0, centers.len())] + Vector::new(0.0, rng.gen_range(-0.01, 0.01), 0.0);
+            let ray = Ray::new(origin, target - origin);
+            let (mut expected, mut isect) = (RayIntersection::default(), RayIntersection::default());
+            for primitive in primitives.iter() { primitive.intersect(&ray, &mut expected); }
+            accel.intersect(&ray, &mut isect);
+            assert_eq!(isect.t, expected.t);
+            assert_eq!(accel.intersects(&ray), expected.t.is_finite());
+            if expected.t.is_finite() { hits += 1 }
+        }
+        assert!(hits > 100);
+    }
+
+    #[test]
+    fn render_matches_double_precision_traversal() {
+        // The kd-tree traverses in double precision with or without the f32-bvh
+        // feature, so matching its render byte for byte in both modes shows
+        // that f32 and f64 traversal render the same image
+        let mut scene = Scene::new();
+        scene.set_perspective_camera(40.0).look_at([1e4 + 1.0, 2.0, 8.0], [1e4, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        scene.add_point_light([1e4 + 2.0, 5.0, 5.0], [0.8, 0.8, 0.8], [1.0, 0.0, 0.0]);
+        let quad = scene.parse_obj("v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\nf 1 2 3 4\n").unwrap();
+        let blue = scene.add_material(Material::matte([0.5, 0.6, 0.7], 0.0));
+        for i in 0..49 {
+            let (x, y) = ((i % 7) as f64 * 0.5 - 1.5 + 1e4, (i / 7) as f64 * 0.5 - 1.5);
+            scene.root.add_sphere([x, y, 0.0], 0.2 + 0.01 * (i % 5) as f64, blue);
+        }
+        let mut group = node::Aggregate::new();
+        group.add_obj_of(quad, MaterialRef::default());
+        group.translate([1e4, 0.0, -1.0]).scale(3.0, 3.0, 1.0);
+        scene.root.add_group(group);
+
+        let render = |scene: &Scene| {
+            let mut film = crate::Film::new(48, 32);
+            crate::capture(scene, &mut film);
+            film.into_buffer()
+        };
+        let bvh = render(&scene);
+        scene.set_accelerator(AcceleratorKind::KdTree);
+        assert!(bvh == render(&scene));
+        assert!(bvh.iter().any(|pixel| pixel[0] != bvh[0][0]));
+    }
+
+    #[test]
+    fn rounds_lanes_outward() {
+        let mut rng = SmallRng::seed_from_u64(5);
+        for _ in 0..1000 {
+            let x = rng.gen_range(-1e6, 1e6);
+            assert!(widen(round_down(x)) <= x && widen(round_up(x)) >= x);
+        }
+        assert_eq!(round_up(f64::INFINITY), Lane::INFINITY);
+        assert_eq!(round_down(f64::NEG_INFINITY), Lane::NEG_INFINITY);
+    }
+}
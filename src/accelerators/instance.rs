@@ -0,0 +1,97 @@
+use std::sync::Arc;
+use crate::{
+    space::*,
+    MaterialRef,
+    primitive::{Primitive, OptionalPrimitive},
+    interaction::RayIntersection
+};
+
+/// A placement of a triangle mesh in the scene. Every instance of the same
+/// mesh shares one hierarchy of its triangles, so scenes with many copies of
+/// the same geometry only build and store it once.
+///
+/// Also places the shapes and groups of groups flattened into their parent's
+/// hierarchy by `Scene::flatten_transforms`, in which case it stands in for
+/// the transformations of all the groups flattened.
+pub struct Instance<'s> {
+    /// Hierarchy of the mesh triangles, or the flattened primitive, in object
+    /// space
+    mesh: Arc<dyn Primitive + 's>,
+
+    /// Object-to-parent transformation of this copy
+    transform: Transformation,
+
+    /// Material for triangles that don't provide their own
+    material: Option<MaterialRef>
+}
+
+impl<'s> Instance<'s> {
+    pub fn new(mesh: Arc<dyn Primitive + 's>, transform: Transformation, material: Option<MaterialRef>) -> Instance<'s> {
+        Instance { mesh, transform, material }
+    }
+}
+
+impl<'s> Primitive for Instance<'s> {
+    fn bound(&self) -> Bounds {
+        self.transform.transform_bounds(self.mesh.bound())
+    }
+
+    fn intersect(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive<'_> {
+        let ray = self.transform.inverse_transform_ray(*ray);
+        let mut isect_inv = self.transform.inverse_transform_ray_intersection(isect);
+        let hit = self.mesh.intersect(&ray, &mut isect_inv);
+
+        if hit.is_some() {
+            *isect = self.transform.transform_ray_intersection(&isect_inv);
+            if let Some(material) = self.material {
+                isect.set_material(material)
+            }
+        }
+
+        hit
+    }
+
+    fn intersects(&self, ray: &Ray) -> bool {
+        self.mesh.intersects(&self.transform.inverse_transform_ray(*ray))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Scene, Material, accelerators::mesh::TriangleBVH};
+
+    #[test]
+    fn instances_share_mesh_hierarchy() {
+        let mut scene = Scene::new();
+        let quad = scene.parse_obj("v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\nf 1 2 3 4\n").unwrap();
+        let red = scene.add_material(Material::matte([0.8, 0.0, 0.0], 0.0));
+        let mesh: Arc<dyn Primitive> = Arc::new(TriangleBVH::new(&scene, quad, None));
+
+        // One copy moved towards the camera, the other off to the side and
+        // scaled up
+        let near = Instance::new(mesh.clone(), Transformation::translate(Vector::new(0.0, 0.0, 1.0)), Some(red));
+        let scaled = Transformation::scale(2.0, 2.0, 2.0).concat(&Transformation::translate(Vector::new(10.0, 0.0, 0.0)));
+        let far = Instance::new(mesh.clone(), scaled, None);
+        assert_eq!(Arc::strong_count(&mesh), 3);
+
+        // Moving and uniformly scaling the quad keeps its normal
+        let mut isect = RayIntersection::default();
+        mesh.intersect(&Ray::new(Point::new(0.5, 0.5, 5.0), -Vector::unit_z()), &mut isect).unwrap();
+        let normal = isect.ng();
+
+        let mut isect = RayIntersection::default();
+        assert!(near.intersect(&Ray::new(Point::new(0.5, 0.5, 5.0), -Vector::unit_z()), &mut isect).is_some());
+        assert!((isect.t - 4.0).abs() < 1e-9);
+        assert!((isect.p - Point::new(0.5, 0.5, 1.0)).magnitude() < 1e-9);
+        assert!((isect.ng() - normal).magnitude() < 1e-9);
+        assert_eq!(isect.material, red);
+
+        // Within the scaled copy, but outside the original quad
+        let mut isect = RayIntersection::default();
+        assert!(far.intersect(&Ray::new(Point::new(11.5, 1.5, 5.0), -Vector::unit_z()), &mut isect).is_some());
+        assert!((isect.t - 5.0).abs() < 1e-9);
+        assert!((isect.ng() - normal).magnitude() < 1e-9);
+        assert!(!far.intersects(&Ray::new(Point::new(12.5, 0.0, 5.0), -Vector::unit_z())));
+    }
+}
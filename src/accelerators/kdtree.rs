@@ -0,0 +1,354 @@
+use crate::{
+    space::*,
+    primitive::{Primitive, OptionalPrimitive},
+    interaction::RayIntersection
+};
+use super::bvh::PrimBox;
+
+// Surface area heuristic costs, relative to each other
+const KD_ISECT_COST: f64 = 80.0;
+const KD_TRAVERSAL_COST: f64 = 1.0;
+const KD_EMPTY_BONUS: f64 = 0.5;
+
+// Nodes with this many primitives or fewer are not split
+const KD_MAX_PRIMS: usize = 1;
+
+// Deepest possible traversal, bounds the traversal stack
+const KD_MAX_DEPTH: usize = 64;
+
+/// kd-tree acceleration structure, which splits space rather than grouping
+/// primitives. Primitives that straddle a split plane are referenced from
+/// both sides. Often faster than a BVH for dense meshes of similarly-sized
+/// triangles, at the cost of a slower build. Its lifetime depends on the scene
+/// whose content it holds.
+///
+/// http://www.pbr-book.org/3ed-2018/Primitives_and_Intersection_Acceleration/Kd-Tree_Accelerator.html
+pub struct KdTreeAccel<'s> {
+    primitives: Vec<PrimBox<'s>>,
+
+    /// Tree nodes arranged in linear memory. The below child of an interior
+    /// node immediately follows it.
+    nodes: Vec<KdNode>,
+
+    /// Primitives of each leaf node, as indices into `primitives`
+    prim_indices: Vec<usize>,
+
+    bounds: Bounds
+}
+
+#[derive(Copy, Clone)]
+enum KdNode {
+    /// Offset into `prim_indices` and number of primitives
+    Leaf(u32, u32),
+
+    /// Split axis, split position and offset of the above child
+    Interior(u8, f64, u32)
+}
+
+/// Start or end of the bounds of a primitive along an axis
+#[derive(Copy, Clone)]
+struct BoundEdge {
+    t: f64,
+    prim: usize,
+    start: bool
+}
+
+/// Node yet to be traversed, along with the parametric range of the ray that
+/// overlaps it
+#[derive(Copy, Clone)]
+struct KdToDo {
+    node: usize,
+    tmin: f64,
+    tmax: f64
+}
+
+impl<'s> KdTreeAccel<'s> {
+    pub fn new(primitives: Vec<PrimBox<'s>>) -> KdTreeAccel<'s> {
+        let prim_bounds: Vec<Bounds> = primitives.iter().map(|prim| prim.bound()).collect();
+        let bounds = prim_bounds.iter().fold(Bounds::none(), |bounds, b| bounds.union(b));
+
+        let mut accel = KdTreeAccel { primitives, nodes: vec![], prim_indices: vec![], bounds };
+        if accel.primitives.is_empty() {
+            accel.nodes.push(KdNode::Leaf(0, 0));
+            return accel
+        }
+
+        let nprims = accel.primitives.len();
+        let max_depth = (8.0 + 1.3 * (nprims as f64).log2()).round() as usize;
+        let mut edges = [
+            Vec::with_capacity(2 * nprims),
+            Vec::with_capacity(2 * nprims),
+            Vec::with_capacity(2 * nprims)
+        ];
+        accel.build(&bounds, &prim_bounds, (0..nprims).collect(), max_depth.min(KD_MAX_DEPTH - 1), &mut edges, 0);
+        accel
+    }
+
+    fn leaf(&mut self, prims: &[usize]) {
+        self.nodes.push(KdNode::Leaf(self.prim_indices.len() as u32, prims.len() as u32));
+        self.prim_indices.extend_from_slice(prims);
+    }
+
+    /// Recursively create the node for the given primitives within the given
+    /// bounds. Bad refines counts splits so far that did not improve the cost.
+    fn build(
+        &mut self,
+        bounds: &Bounds,
+        prim_bounds: &[Bounds],
+        prims: Vec<usize>,
+        depth: usize,
+        edges: &mut [Vec<BoundEdge>; 3],
+        mut bad_refines: usize
+    ) {
+        if prims.len() <= KD_MAX_PRIMS || depth == 0 {
+            return self.leaf(&prims)
+        }
+
+        // Choose the split that minimizes the surface area heuristic, starting
+        // with the longest axis
+        let mut best: Option<(usize, usize)> = None; // (axis, edge offset)
+        let mut best_cost = f64::INFINITY;
+        let old_cost = KD_ISECT_COST * prims.len() as f64;
+        let total_sa = bounds.surface_area();
+        let inv_total_sa = 1.0 / total_sa;
+        let d = bounds.diagonal();
+        let mut axis = bounds.maximum_extent();
+
+        for _ in 0..3 {
+            let edges = &mut edges[axis];
+            edges.clear();
+            for &prim in prims.iter() {
+                let b = &prim_bounds[prim];
+                edges.push(BoundEdge { t: b.min[axis], prim, start: true });
+                edges.push(BoundEdge { t: b.max[axis], prim, start: false });
+            }
+
+            // Starts sort before ends at the same position
+            edges.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap().then(b.start.cmp(&a.start)));
+
+            let (mut below, mut above) = (0, prims.len());
+            let (other0, other1) = ((axis + 1) % 3, (axis + 2) % 3);
+            for (i, edge) in edges.iter().enumerate() {
+                if !edge.start { above -= 1 }
+                let t = edge.t;
+                if t > bounds.min[axis] && t < bounds.max[axis] {
+                    let below_sa = 2.0 * (d[other0] * d[other1] + (t - bounds.min[axis]) * (d[other0] + d[other1]));
+                    let above_sa = 2.0 * (d[other0] * d[other1] + (bounds.max[axis] - t) * (d[other0] + d[other1]));
+                    let (p_below, p_above) = (below_sa * inv_total_sa, above_sa * inv_total_sa);
+                    let bonus = if below == 0 || above == 0 { KD_EMPTY_BONUS } else { 0.0 };
+                    let cost = KD_TRAVERSAL_COST
+                        + KD_ISECT_COST * (1.0 - bonus) * (p_below * below as f64 + p_above * above as f64);
+                    if cost < best_cost {
+                        best_cost = cost;
+                        best = Some((axis, i));
+                    }
+                }
+                if edge.start { below += 1 }
+            }
+
+            if best.is_some() { break }
+            axis = (axis + 1) % 3;
+        }
+
+        if best_cost > old_cost { bad_refines += 1 }
+        let (axis, offset) = match best {
+            Some(split) if (best_cost <= 4.0 * old_cost || prims.len() >= 16) && bad_refines < 3 => split,
+            _ => return self.leaf(&prims)
+        };
+
+        // Split primitives by the chosen edge. Edges are re-sorted further down
+        // the tree, so take what's needed before recursing.
+        let split = &edges[axis];
+        let below: Vec<usize> = split[..offset].iter().filter(|e| e.start).map(|e| e.prim).collect();
+        let above: Vec<usize> = split[(offset + 1)..].iter().filter(|e| !e.start).map(|e| e.prim).collect();
+        let t = split[offset].t;
+
+        let (mut bounds_below, mut bounds_above) = (*bounds, *bounds);
+        bounds_below.max[axis] = t;
+        bounds_above.min[axis] = t;
+
+        let node = self.nodes.len();
+        self.nodes.push(KdNode::Leaf(0, 0)); // Filled in once the above child is placed
+        self.build(&bounds_below, prim_bounds, below, depth - 1, edges, bad_refines);
+        let above_offset = self.nodes.len() as u32;
+        self.build(&bounds_above, prim_bounds, above, depth - 1, edges, bad_refines);
+        self.nodes[node] = KdNode::Interior(axis as u8, t, above_offset);
+    }
+
+    /// Parametric range of the ray within the bounds of the tree up to tmax,
+    /// if any. The far distances are widened by their rounding error so that
+    /// rays grazing the bounds are not missed.
+    fn clip(&self, ray: &Ray, tmax: f64) -> Option<(f64, f64)> {
+        let (mut tmin, mut tmax) = (ray.t_min, tmax);
+        let robust = 1.0 + 2.0 * gamma::<f64>(3);
+        for i in 0..3 {
+            let t0 = (self.bounds.min[i] - ray.origin[i]) * ray.dinv[i];
+            let t1 = (self.bounds.max[i] - ray.origin[i]) * ray.dinv[i];
+            tmin = tmin.max(t0.min(t1));
+            tmax = tmax.min(t0.max(t1) * robust);
+        }
+        if tmin <= tmax { Some((tmin, tmax)) } else { None }
+    }
+}
+
+impl<'s> Primitive for KdTreeAccel<'s> {
+    fn bound(&self) -> Bounds {
+        self.bounds
+    }
+
+    fn intersect(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive<'_> {
+        // Nothing beyond a hit found before reaching the tree can be nearer
+        let (mut tmin, mut tmax) = self.clip(ray, isect.t.min(ray.t_max))?;
+
+        let mut hit = None;
+        let mut todo = [KdToDo { node: 0, tmin: 0.0, tmax: 0.0 }; KD_MAX_DEPTH];
+        let mut todo_offset = 0;
+        let mut node_index = 0;
+
+        loop {
+            // Stop once a hit is found closer than the remaining nodes
+            if isect.t < tmin { break }
+
+            match self.nodes[node_index] {
+                KdNode::Interior(axis, split, above) => {
+                    // Visit the child on the side of the ray origin first
+                    let axis = axis as usize;
+                    let tplane = (split - ray.origin[axis]) * ray.dinv[axis];
+                    let below_first = ray.origin[axis] < split
+                        || (ray.origin[axis] == split && ray.d[axis] <= 0.0);
+                    let (first, second) = if below_first {
+                        (node_index + 1, above as usize)
+                    } else {
+                        (above as usize, node_index + 1)
+                    };
+
+                    if tplane > tmax || tplane <= 0.0 {
+                        node_index = first
+                    } else if tplane < tmin {
+                        node_index = second
+                    } else {
+                        todo[todo_offset] = KdToDo { node: second, tmin: tplane, tmax };
+                        todo_offset += 1;
+                        node_index = first;
+                        tmax = tplane;
+                    }
+                },
+                KdNode::Leaf(offset, count) => {
+                    let (offset, count) = (offset as usize, count as usize);
+                    for &prim in self.prim_indices[offset..(offset + count)].iter() {
+                        if let Some(primitive) = self.primitives[prim].intersect(ray, isect) {
+                            isect.id = prim as u32;
+                            hit = Some(primitive)
+                        }
+                    }
+
+                    if todo_offset == 0 { break }
+                    todo_offset -= 1;
+                    let next = todo[todo_offset];
+                    node_index = next.node;
+                    tmin = next.tmin;
+                    tmax = next.tmax;
+                }
+            }
+        }
+
+        hit
+    }
+
+    /// Stops at the first primitive hit, in the same traversal order as
+    /// `intersect`
+    fn intersects(&self, ray: &Ray) -> bool {
+        let (mut tmin, mut tmax) = match self.clip(ray, ray.t_max) {
+            Some(range) => range,
+            None => return false
+        };
+
+        let mut todo = [KdToDo { node: 0, tmin: 0.0, tmax: 0.0 }; KD_MAX_DEPTH];
+        let mut todo_offset = 0;
+        let mut node_index = 0;
+
+        loop {
+            match self.nodes[node_index] {
+                KdNode::Interior(axis, split, above) => {
+                    let axis = axis as usize;
+                    let tplane = (split - ray.origin[axis]) * ray.dinv[axis];
+                    let below_first = ray.origin[axis] < split
+                        || (ray.origin[axis] == split && ray.d[axis] <= 0.0);
+                    let (first, second) = if below_first {
+                        (node_index + 1, above as usize)
+                    } else {
+                        (above as usize, node_index + 1)
+                    };
+
+                    if tplane > tmax || tplane <= 0.0 {
+                        node_index = first
+                    } else if tplane < tmin {
+                        node_index = second
+                    } else {
+                        todo[todo_offset] = KdToDo { node: second, tmin: tplane, tmax };
+                        todo_offset += 1;
+                        node_index = first;
+                        tmax = tplane;
+                    }
+                },
+                KdNode::Leaf(offset, count) => {
+                    let (offset, count) = (offset as usize, count as usize);
+                    for &prim in self.prim_indices[offset..(offset + count)].iter() {
+                        if self.primitives[prim].intersects(ray) { return true }
+                    }
+
+                    if todo_offset == 0 { return false }
+                    todo_offset -= 1;
+                    let next = todo[todo_offset];
+                    node_index = next.node;
+                    tmin = next.tmin;
+                    tmax = next.tmax;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{shape::sphere::Sphere, MaterialRef};
+
+    #[test]
+    fn finds_nearest_sphere() {
+        let spheres: Vec<PrimBox> = (0..50)
+            .map(|i| -> PrimBox {
+                let center = [(i % 5) as f64 * 3.0, ((i / 5) % 5) as f64 * 3.0, (i / 25) as f64 * 3.0];
+                Box::new(Sphere::new(center, 1.0, MaterialRef::default()))
+            })
+            .collect();
+        let tree = KdTreeAccel::new(spheres);
+
+        let ray = Ray::new(Point::new(6.0, 3.0, -10.0), Vector::new(0.0, 0.0, 1.0));
+        let mut isect = RayIntersection::default();
+        assert!(tree.intersect(&ray, &mut isect).is_some());
+        assert!((isect.t - 9.0).abs() < 1e-9);
+
+        let ray = Ray::new(Point::new(1.5, 1.5, -10.0), Vector::new(0.0, 0.0, 1.0));
+        assert!(tree.intersect(&ray, &mut RayIntersection::default()).is_none());
+    }
+
+    #[test]
+    fn skips_spheres_beyond_nearest_hit() {
+        let spheres: Vec<PrimBox> = (0..10)
+            .map(|i| -> PrimBox { Box::new(Sphere::new([0.0, 0.0, i as f64 * 3.0], 1.0, MaterialRef::default())) })
+            .collect();
+        let tree = KdTreeAccel::new(spheres);
+
+        // A hit found before reaching the tree is nearer than any within it
+        let ray = Ray::new(Point::new(0.0, 0.0, -10.0), Vector::new(0.0, 0.0, 1.0));
+        let mut isect = RayIntersection::default();
+        isect.t = 5.0;
+        assert!(tree.intersect(&ray, &mut isect).is_none());
+        assert_eq!(isect.t, 5.0);
+
+        isect.t = 20.0;
+        assert!(tree.intersect(&ray, &mut isect).is_some());
+        assert!((isect.t - 9.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,207 @@
+use std::{sync::{Arc, Mutex}, collections::HashMap};
+use crate::{
+    space::*,
+    shape::triangle::{Mesh, Triangle},
+    MaterialRef,
+    primitive::{Primitive, OptionalPrimitive},
+    interaction::RayIntersection,
+    scene::{Scene, ObjRef},
+    stats::BvhStats
+};
+use super::{BvhBuildStrategy, bvh::{BVHTree, BVHPrimitiveInfo, BVHLayout}};
+
+/// Primitive returned for hits on triangles of meshes without groups
+static UNGROUPED: TriangleGroup = TriangleGroup(None);
+
+/// Bounding volume hierarchy over the triangles of a mesh. Its leaves hold
+/// only the indices of the triangles, which are read from the mesh as rays
+/// reach them, so large meshes take no more than a few bytes per triangle on
+/// top of the mesh itself. Its lifetime depends on the mesh.
+///
+/// These are the bottom level of the scene's acceleration structure: each
+/// is built once per mesh and kept by the scene in its `MeshCache`, while the
+/// top-level hierarchies over the groups and mesh instances of the scene are
+/// built again whenever the scene changes.
+pub struct TriangleBVH<'s> {
+    mesh: &'s Mesh,
+    tree: Arc<BVHTree>,
+
+    /// What a hit on a triangle of each group returns, in the order of the
+    /// materials of the mesh
+    groups: Vec<TriangleGroup>
+}
+
+/// Triangles of a mesh that share a material, returned by hits on them in
+/// place of the triangles themselves, which aren't stored
+struct TriangleGroup(Option<MaterialRef>);
+
+/// Hierarchies of the triangles of each mesh of a scene, kept across the
+/// acceleration structures built for it so that moving groups and instances
+/// around doesn't build the hierarchies of their meshes again. Entries are
+/// dropped as the scene replaces their meshes.
+#[derive(Default)]
+pub(crate) struct MeshCache {
+    trees: Mutex<HashMap<ObjRef, (BvhBuildStrategy, Arc<BVHTree>)>>
+}
+
+impl MeshCache {
+    /// Hierarchy of the given mesh built with the given strategy, built with
+    /// the given function if there isn't one yet
+    fn tree(&self, obj: ObjRef, strategy: BvhBuildStrategy, build: impl FnOnce() -> BVHTree) -> Arc<BVHTree> {
+        let mut trees = self.trees.lock().unwrap();
+        match trees.get(&obj) {
+            Some((built, tree)) if *built == strategy => tree.clone(),
+            _ => {
+                let tree = Arc::new(build());
+                trees.insert(obj, (strategy, tree.clone()));
+                tree
+            }
+        }
+    }
+
+    /// Drop the hierarchy of the given mesh, such as after its triangles
+    /// change
+    pub(crate) fn invalidate(&mut self, obj: ObjRef) {
+        self.trees.get_mut().unwrap().remove(&obj);
+    }
+}
+
+impl<'s> TriangleBVH<'s> {
+    /// Hierarchy of the given mesh of the scene, built with the strategy
+    /// chosen for the scene the first time the mesh is used. Hierarchies that
+    /// have to be built again refit the given layout instead if it has as
+    /// many triangles.
+    pub fn new(scene: &'s Scene, obj: ObjRef, layout: Option<&BVHLayout>) -> TriangleBVH<'s> {
+        let mesh = scene.mesh(obj).unwrap();
+        let tree = scene.mesh_cache().tree(obj, scene.bvh_build, || build(scene.bvh_build, mesh, layout));
+        TriangleBVH::with_tree(mesh, tree)
+    }
+
+    fn with_tree(mesh: &'s Mesh, tree: Arc<BVHTree>) -> TriangleBVH<'s> {
+        TriangleBVH {
+            mesh,
+            tree,
+            groups: mesh.group_materials().iter().map(|material| TriangleGroup(*material)).collect()
+        }
+    }
+
+    /// Layout of this hierarchy, for refitting to a later state of the mesh
+    pub fn layout(&self) -> BVHLayout {
+        self.tree.layout()
+    }
+
+    pub(super) fn add_stats(&self, stats: &mut BvhStats) {
+        self.tree.add_stats(stats)
+    }
+}
+
+/// Build the tree over the triangles of the given mesh
+fn build(strategy: BvhBuildStrategy, mesh: &Mesh, layout: Option<&BVHLayout>) -> BVHTree {
+    let ntriangles = mesh.triangles.triangle_count();
+    let mut prim_info: Vec<BVHPrimitiveInfo> = (0..ntriangles)
+        .map(|i| BVHPrimitiveInfo::new(i, Triangle::new(mesh, i as u32).bound()))
+        .collect();
+    BVHTree::new(strategy, &mut prim_info, ntriangles, layout)
+}
+
+impl<'s> Primitive for TriangleBVH<'s> {
+    fn bound(&self) -> Bounds {
+        self.tree.bounds
+    }
+
+    fn intersect(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive<'_> {
+        let mut hit = None;
+        self.tree.intersect(ray, isect.t, |index| {
+            if Triangle::new(self.mesh, index as u32).intersect(ray, isect).is_some() {
+                hit = Some(index)
+            }
+            isect.t
+        });
+
+        let index = hit?;
+        match self.mesh.group(index) {
+            Some(group) => Some(&self.groups[group]),
+            None => Some(&UNGROUPED)
+        }
+    }
+
+    fn intersects(&self, ray: &Ray) -> bool {
+        self.tree.intersects(ray, |index| Triangle::new(self.mesh, index as u32).intersects(ray))
+    }
+}
+
+impl Primitive for TriangleGroup {
+    fn bound(&self) -> Bounds {
+        Bounds::none()
+    }
+
+    fn intersect(&self, _ray: &Ray, _isect: &mut RayIntersection) -> OptionalPrimitive<'_> {
+        None
+    }
+
+    /// Material of the group from the loaded .mtl libraries, if any
+    fn material(&self) -> Option<MaterialRef> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Accel, shape::{mesh::TriangleMesh, triangle::parse_obj}};
+
+    #[test]
+    fn hits_return_group_materials() {
+        let quad = parse_obj(r#"v -1 0 -1
+v 1 0 -1
+v 1 0 1
+v -1 0 1
+
+o first
+f 1 2 3
+o second
+f 1 3 4
+"#
+        ).unwrap();
+        let (triangles, groups) = TriangleMesh::from_obj(&quad, &[]);
+        let red = Scene::new().add_material(crate::Material::matte([1.0, 0.0, 0.0], 0.0));
+        let mesh = Mesh::with_materials(triangles, groups, vec![Some(red), None]);
+        let bvh = TriangleBVH::with_tree(&mesh, Arc::new(build(BvhBuildStrategy::Hlbvh, &mesh, None)));
+
+        // Straight down onto the first and second triangle
+        let first = Ray::new(Point::new(0.5, 1.0, 0.0), Vector::new(0.0, -1.0, 0.0));
+        let mut isect = RayIntersection::default();
+        let hit = bvh.intersect(&first, &mut isect).unwrap();
+        assert_eq!(hit.material(), Some(red));
+        assert_eq!(isect.t, 1.0);
+
+        let second = Ray::new(Point::new(-0.5, 2.0, 0.0), Vector::new(0.0, -1.0, 0.0));
+        let mut isect = RayIntersection::default();
+        assert!(bvh.intersect(&second, &mut isect).unwrap().material().is_none());
+        assert_eq!(isect.t, 2.0);
+        assert!(bvh.intersects(&second));
+
+        let miss = Ray::new(Point::new(2.0, 1.0, 0.0), Vector::new(0.0, -1.0, 0.0));
+        assert!(bvh.intersect(&miss, &mut RayIntersection::default()).is_none());
+        assert!(!bvh.intersects(&miss));
+    }
+
+    #[test]
+    fn keeps_mesh_hierarchies_across_builds() {
+        let mut scene = Scene::new();
+        let quad = scene.parse_obj("v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\nf 1 2 3 4\n").unwrap();
+        scene.root.add_obj_of(quad, MaterialRef::default());
+        let cached = |scene: &Scene| scene.mesh_cache().trees.lock().unwrap()[&quad].1.clone();
+
+        Accel::from(&scene);
+        let tree = cached(&scene);
+        scene.root.translate([1.0, 0.0, 0.0]);
+        assert_eq!(Accel::from(&scene).bound().min.x, 0.0);
+        assert!(Arc::ptr_eq(&tree, &cached(&scene)));
+
+        // Building with another strategy builds the hierarchy again
+        scene.set_bvh_build_strategy(BvhBuildStrategy::Sah);
+        Accel::from(&scene);
+        assert!(!Arc::ptr_eq(&tree, &cached(&scene)));
+    }
+}
@@ -1 +1,32 @@
 pub mod bvh;
+pub mod instance;
+pub mod kdtree;
+pub mod mesh;
+
+/// Spatial structure used to find the primitives a ray may hit within each
+/// group and mesh of the scene. Which is faster depends on the scene, so
+/// compare render times when in doubt.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AcceleratorKind {
+    /// Bounding volume hierarchy; quick to build and the default
+    #[default]
+    Bvh,
+
+    /// kd-tree; slower to build, but often faster to traverse for dense
+    /// meshes
+    KdTree
+}
+
+/// Algorithm used to build bounding volume hierarchies
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BvhBuildStrategy {
+    /// Hierarchical linear BVH; sorts primitives along a space-filling curve
+    /// and only applies the surface area heuristic near the root. Quick to
+    /// build and the default
+    #[default]
+    Hlbvh,
+
+    /// Recursive binned surface area heuristic at every level. Slower to
+    /// build, but the tree is usually faster to traverse
+    Sah
+}
@@ -0,0 +1,125 @@
+//! Scene parameters that change over the frames of an animation. Each keyed
+//! parameter has a track of values at given times, in seconds. Values in
+//! between keys are interpolated and values before the first key or after the
+//! last hold the nearest one. Call `Scene::set_time` to pose the scene at a
+//! moment of the animation, or `output::render_sequence` to render frames.
+use crate::space::{*, animated::AnimatedTransform};
+
+/// Values that may be blended between keyframes
+pub trait Interpolate: Clone {
+    /// Blend from self at t = 0 to other at t = 1
+    fn interpolate(&self, other: &Self, t: f64) -> Self;
+}
+
+impl Interpolate for f64 {
+    fn interpolate(&self, other: &f64, t: f64) -> f64 {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Color {
+    fn interpolate(&self, other: &Color, t: f64) -> Color {
+        self.lerp(*other, t)
+    }
+}
+
+impl Interpolate for Point {
+    fn interpolate(&self, other: &Point, t: f64) -> Point {
+        *self + (*other - *self) * t
+    }
+}
+
+/// Translation and scale interpolate linearly, rotation spherically
+impl Interpolate for Transformation {
+    fn interpolate(&self, other: &Transformation, t: f64) -> Transformation {
+        AnimatedTransform::new(self.clone(), 0.0, other.clone(), 1.0).interpolate(t)
+    }
+}
+
+/// Keyframed values of a single scene parameter, sorted by time
+#[derive(Debug, Clone)]
+pub struct Track<T: Interpolate> {
+    keys: Vec<(f64, T)>
+}
+
+impl<T: Interpolate> Track<T> {
+    pub fn new() -> Track<T> {
+        Track { keys: vec![] }
+    }
+
+    /// Set the value at the given time, replacing any key already there
+    pub fn key(&mut self, time: f64, value: T) -> &mut Self {
+        match self.keys.binary_search_by(|(t, _)| t.partial_cmp(&time).unwrap()) {
+            Ok(i) => self.keys[i].1 = value,
+            Err(i) => self.keys.insert(i, (time, value))
+        }
+        self
+    }
+
+    /// Whether the track has no keys
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Value at the given time, if the track has any keys
+    pub fn value_at(&self, time: f64) -> Option<T> {
+        let next = self.keys.iter().position(|(t, _)| *t > time);
+        match next {
+            Some(0) => Some(self.keys[0].1.clone()),
+            Some(i) => {
+                let (t0, v0) = &self.keys[i - 1];
+                let (t1, v1) = &self.keys[i];
+                Some(v0.interpolate(v1, (time - t0) / (t1 - t0)))
+            },
+            None => self.keys.last().map(|(_, v)| v.clone())
+        }
+    }
+}
+
+impl<T: Interpolate> Default for Track<T> {
+    fn default() -> Self { Track::new() }
+}
+
+/// Position and orientation of the camera, as given to `Camera::look_at`
+#[derive(Debug, Clone, Copy)]
+pub struct CameraKey {
+    pub origin: Point,
+    pub look: Point,
+    pub up: Vector
+}
+
+impl CameraKey {
+    pub fn new(origin: [f64; 3], look: [f64; 3], up: [f64; 3]) -> CameraKey {
+        CameraKey { origin: origin.into(), look: look.into(), up: up.into() }
+    }
+}
+
+impl Interpolate for CameraKey {
+    fn interpolate(&self, other: &CameraKey, t: f64) -> CameraKey {
+        CameraKey {
+            origin: self.origin.interpolate(&other.origin, t),
+            look: self.look.interpolate(&other.look, t),
+            up: self.up.lerp(other.up, t)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interpolates_between_keys() {
+        let mut track = Track::new();
+        assert_eq!(track.value_at(1.0), None);
+
+        track.key(2.0, 10.0).key(0.0, 0.0).key(1.0, 4.0);
+        assert_eq!(track.value_at(-1.0), Some(0.0));
+        assert_eq!(track.value_at(0.5), Some(2.0));
+        assert_eq!(track.value_at(1.5), Some(7.0));
+        assert_eq!(track.value_at(3.0), Some(10.0));
+
+        track.key(1.0, 6.0);
+        assert_eq!(track.value_at(1.0), Some(6.0));
+    }
+}
@@ -1,9 +1,254 @@
-#[cfg(feature = "bin")]
+//! Render scene files from the command line. Run with --help for usage.
 
-use ::lasgun::{Scene, output};
+use std::{env, path::Path, process, time::Instant, io::{self, Write}};
+use ::lasgun::{
+    Scene, Film, HdrFilm, Img, Integrator, RenderProgress, Progress, RenderStats,
+    capture_with_progress, capture_with_stats, trace_pixel, scene::io as scene_io, formats::pbrt, output
+};
+
+const USAGE: &str = "\
+Usage: lasgun <scene> [options]
+
+Render a JSON (.json) or PBRT (.pbrt) scene file to an image.
+
+Options:
+  -o, --output <file>        Image to save, as PNG, JPEG or BMP, or OpenEXR
+                             with unclamped radiance for .exr files
+                             [default: the PBRT Film filename, or image.png]
+  -r, --resolution <WxH>     Size of the image in pixels
+                             [default: the PBRT Film size, or 512x512]
+  -s, --spp <n>              Samples per pixel
+  -t, --threads <n>          Render threads, 0 for as many as the system allows
+  -i, --integrator <name>    whitted, path, irradiance, ambientocclusion,
+                             furnace, or debug:<view> for a view of heatmap,
+                             geometricnormal, shadingnormal, uv or depth
+  -d, --depth <n>            Maximum recursion depth
+  -p, --preview              Show the render in a window instead of saving
+                             it, with the mouse and WASD to move the camera.
+                             Needs the preview feature
+  --gpu                      Trace camera and ambient occlusion rays on the
+                             GPU, for the ambientocclusion integrator and
+                             debug views but the heatmap. Needs the gpu
+                             feature
+  --stats                    Print ray counts, intersection tests and timings
+                             after rendering
+  --trace <X,Y>              Print the hits, light and BSDF samples and
+                             radiance of each sample of the pixel at column X
+                             and row Y as JSON instead of rendering
+  -q, --quiet                Don't report progress
+  -h, --help                 Print this message
+";
+
+/// Settings from the command line. Unset options keep the scene file's own.
+#[derive(Debug, Default)]
+struct Options {
+    scene: String,
+    output: Option<String>,
+    resolution: Option<(u32, u32)>,
+    spp: Option<u32>,
+    threads: Option<usize>,
+    integrator: Option<Integrator>,
+    depth: Option<u32>,
+    preview: bool,
+    gpu: bool,
+    stats: bool,
+    trace: Option<(u32, u32)>,
+    quiet: bool
+}
 
 fn main() {
-    // TODO: Make this interface friendlier
-    let scene = Scene::new();
-    output::render(&scene, [512, 512], "image.png")
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => return print!("{}", USAGE),
+        Err(message) => {
+            eprint!("lasgun: {}\n\n{}", message, USAGE);
+            process::exit(2)
+        }
+    };
+    if let Err(message) = run(&options) {
+        eprintln!("lasgun: {}", message);
+        process::exit(1)
+    }
+}
+
+/// Options from the given arguments, or None if help was asked for
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options::default();
+    let mut scene = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-p" | "--preview" => options.preview = true,
+            "--gpu" => options.gpu = true,
+            "--stats" => options.stats = true,
+            "--trace" => options.trace = Some(parse_pixel(&value()?)?),
+            "-q" | "--quiet" => options.quiet = true,
+            "-o" | "--output" => options.output = Some(value()?),
+            "-r" | "--resolution" => options.resolution = Some(parse_resolution(&value()?)?),
+            "-s" | "--spp" => options.spp = Some(parse_number(&arg, &value()?)?),
+            "-t" | "--threads" => options.threads = Some(parse_number(&arg, &value()?)?),
+            "-i" | "--integrator" => options.integrator = Some(parse_integrator(&value()?)?),
+            "-d" | "--depth" => options.depth = Some(parse_number(&arg, &value()?)?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if scene.is_some() => return Err(format!("unexpected argument {}", arg)),
+            _ => scene = Some(arg)
+        }
+    }
+    options.scene = scene.ok_or("missing scene file")?;
+    Ok(Some(options))
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value {} for {}", value, option))
+}
+
+/// Width and height given as WxH, e.g., 1920x1080
+fn parse_resolution(value: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid resolution {}, expected WxH such as 1920x1080", value);
+    let (w, h) = value.split_once('x').ok_or_else(invalid)?;
+    match (w.parse(), h.parse()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err(invalid())
+    }
+}
+
+/// Pixel coordinates given as X,Y, e.g., 256,128
+fn parse_pixel(value: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid pixel {}, expected X,Y such as 256,128", value);
+    let (x, y) = value.split_once(',').ok_or_else(invalid)?;
+    match (x.trim().parse(), y.trim().parse()) {
+        (Ok(x), Ok(y)) => Ok((x, y)),
+        _ => Err(invalid())
+    }
+}
+
+/// Integrator by the name it has in scene files, with the view of the debug
+/// integrator after a colon, e.g., debug:heatmap
+fn parse_integrator(value: &str) -> Result<Integrator, String> {
+    let name = value.to_lowercase();
+    let json = match name.split_once(':') {
+        Some((name, view)) => serde_json::json!({ name: view }),
+        None => serde_json::Value::String(name)
+    };
+    serde_json::from_value(json)
+        .map_err(|_| format!("unknown integrator {}, expected whitted, path, irradiance, ambientocclusion, furnace or debug:<view>", value))
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let path = Path::new(&options.scene);
+    let (mut scene, resolution, filename) = load(path, options.quiet)
+        .map_err(|err| format!("could not load {}: {}", path.display(), err))?;
+
+    if let Some(spp) = options.spp { scene.camera.set_samples_per_pixel(spp) }
+    if let Some(threads) = options.threads { scene.set_threads(threads) }
+    if let Some(integrator) = options.integrator { scene.set_integrator(integrator) }
+    if let Some(depth) = options.depth { scene.set_max_recursion_depth(depth) }
+    let (width, height) = options.resolution.or(resolution).unwrap_or((512, 512));
+    if options.preview {
+        return preview(&scene, width, height)
+    }
+    if let Some((x, y)) = options.trace {
+        if x >= width || y >= height {
+            return Err(format!("pixel {},{} is outside the {}x{} image", x, y, width, height))
+        }
+        println!("{}", trace_pixel(&scene, (width, height), x, y).to_json());
+        return Ok(())
+    }
+    let filename = options.output.clone().or(filename).unwrap_or_else(|| "image.png".to_string());
+
+    let start = Instant::now();
+    let progress = Reporter { quiet: options.quiet };
+    let is_exr = Path::new(&filename).extension().map_or(false, |ext| ext == "exr");
+    let stats = if is_exr {
+        let mut film = HdrFilm::new(width, height);
+        let stats = capture(&scene, &mut film, &progress, options)?;
+        output::save_exr(&film, &filename).map_err(|err| format!("could not save {}: {}", filename, err))?;
+        stats
+    } else {
+        let mut film = Film::new(width, height);
+        film.tonemap = scene.tonemap;
+        let stats = capture(&scene, &mut film, &progress, options)?;
+        film.write(&filename).map_err(|err| format!("could not save {}: {}", filename, err))?;
+        stats
+    };
+
+    if !options.quiet {
+        eprintln!("\nSaved {} ({}x{}) in {:.1}s", filename, width, height, start.elapsed().as_secs_f64());
+    }
+    if let Some(stats) = stats {
+        println!("{}", stats)
+    }
+    Ok(())
+}
+
+/// Capture the scene on the film, on the GPU if asked to, counting the work
+/// done on the CPU if asked to
+fn capture(scene: &Scene, film: &mut impl Img, progress: &Reporter, options: &Options) -> Result<Option<RenderStats>, String> {
+    if options.gpu {
+        capture_gpu(scene, film, options.quiet)?;
+        Ok(None)
+    } else if options.stats {
+        Ok(Some(capture_with_stats(scene, film, Some(progress))))
+    } else {
+        capture_with_progress(scene, film, progress);
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "gpu")]
+fn capture_gpu(scene: &Scene, film: &mut impl Img, quiet: bool) -> Result<(), String> {
+    let renderer = ::lasgun::gpu::GpuRenderer::new().map_err(|err| format!("could not start the GPU backend: {}", err))?;
+    if !renderer.capture(scene, film) && !quiet {
+        eprintln!("The GPU backend doesn't support this scene, rendered it on the CPU")
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "gpu"))]
+fn capture_gpu(_scene: &Scene, _film: &mut impl Img, _quiet: bool) -> Result<(), String> {
+    Err("this build has no GPU backend, rebuild with --features gpu".to_string())
+}
+
+#[cfg(feature = "preview")]
+fn preview(scene: &Scene, width: u32, height: u32) -> Result<(), String> {
+    ::lasgun::preview::show(scene, width, height).map_err(|err| format!("could not open preview window: {}", err))
+}
+
+#[cfg(not(feature = "preview"))]
+fn preview(_scene: &Scene, _width: u32, _height: u32) -> Result<(), String> {
+    Err("this build has no preview window, rebuild with --features preview".to_string())
+}
+
+/// Scene from the file at the given path, along with the resolution and
+/// output filename it asks for, if any
+fn load(path: &Path, quiet: bool) -> Result<(Scene, Option<(u32, u32)>, Option<String>), String> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => {
+            let scene = scene_io::load(path).map_err(|err| err.to_string())?;
+            Ok((scene, None, None))
+        },
+        Some("pbrt") => {
+            let pbrt = pbrt::load(path).map_err(|err| err.to_string())?;
+            if !quiet {
+                for warning in &pbrt.warnings { eprintln!("warning: {}", warning) }
+            }
+            Ok((pbrt.scene, Some(pbrt.resolution), pbrt.filename))
+        },
+        _ => Err("unknown scene format, expected a .json or .pbrt file".to_string())
+    }
+}
+
+/// Reports progress on a single line of stderr
+struct Reporter {
+    quiet: bool
+}
+
+impl RenderProgress for Reporter {
+    fn on_progress(&self, progress: &Progress) {
+        if self.quiet { return }
+        let eta = progress.eta().map_or(String::new(), |eta| format!(", {:.0}s left", eta.as_secs_f64()));
+        eprint!("\rRendering {:5.1}%{}    ", progress.percent(), eta);
+        io::stderr().flush().ok();
+    }
 }
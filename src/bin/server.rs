@@ -0,0 +1,367 @@
+//! Render scenes sent over the network. Run with --help for usage.
+
+use std::{env, io::Read, path::{Component, Path, PathBuf}, process, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, thread};
+use tiny_http::{Header, Method, ReadWrite, Request, Response, Server};
+use tungstenite::{Message, WebSocket, handshake::derive_accept_key, protocol::Role};
+use serde_json::json;
+use ::lasgun::{
+    Scene, Film, HdrFilm, Pixel, RenderProgress, Progress, TileBounds,
+    capture, capture_with_progress, scene::io as scene_io, output
+};
+
+const USAGE: &str = "\
+Usage: lasgun-server [options]
+
+Render JSON scene files (see src/scene/io.rs) sent over HTTP.
+
+Options:
+  -a, --address <host:port>  Address to listen on [default: 127.0.0.1:8080]
+  -d, --dir <dir>            Directory that mesh and texture paths in scenes
+                             are relative to and may not leave [default: .]
+  -h, --help                 Print this message
+
+Endpoints:
+  POST /render?width=W&height=H&format=png|exr
+      Render the scene in the body of the request and respond with the image.
+      The width and height default to 512, the format to png. Images may have
+      at most 4096 × 4096 pixels and scenes at most 64 MiB.
+
+  GET /render?width=W&height=H&format=png|exr
+      WebSocket. Send the scene as the first text message. Each tile is sent
+      back as it finishes in a binary message of its x, y, width and height
+      as little-endian u32s followed by its RGBA pixels in row-major order,
+      and after it a text message such as
+          {\"type\": \"progress\", \"tiles_done\": 3, \"tiles\": 64}
+      Once done, {\"type\": \"done\", \"format\": \"png\"} is sent followed by the
+      image in a binary message, or {\"type\": \"error\", \"message\": ...} if the
+      scene couldn't be rendered.
+";
+
+/// Largest scene accepted, in bytes. Meshes may be embedded in scenes.
+const MAX_SCENE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Largest image rendered, in pixels
+const MAX_PIXELS: u64 = 4096 * 4096;
+
+fn main() {
+    let mut address = "127.0.0.1:8080".to_string();
+    let mut dir = PathBuf::from(".");
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage_error(&format!("missing value for {}", arg)));
+        match arg.as_str() {
+            "-h" | "--help" => return print!("{}", USAGE),
+            "-a" | "--address" => address = value(),
+            "-d" | "--dir" => dir = PathBuf::from(value()),
+            _ => usage_error(&format!("unexpected argument {}", arg))
+        }
+    }
+
+    let server = Server::http(&address).unwrap_or_else(|err| {
+        eprintln!("lasgun-server: could not listen on {}: {}", address, err);
+        process::exit(1)
+    });
+    eprintln!("Listening on http://{}", address);
+
+    let dir = Arc::new(dir);
+    for request in server.incoming_requests() {
+        let dir = dir.clone();
+        thread::spawn(move || handle(request, &dir));
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    eprint!("lasgun-server: {}\n\n{}", message, USAGE);
+    process::exit(2)
+}
+
+/// Image format of a render
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Format { Png, Exr }
+
+impl Format {
+    fn name(self) -> &'static str {
+        match self { Format::Png => "png", Format::Exr => "exr" }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self { Format::Png => "image/png", Format::Exr => "image/x-exr" }
+    }
+}
+
+/// Settings of a render given in the query string of its request
+#[derive(Debug, Copy, Clone)]
+struct Params {
+    width: u32,
+    height: u32,
+    format: Format
+}
+
+impl Params {
+    fn parse(query: &str) -> Result<Params, String> {
+        let mut params = Params { width: 512, height: 512, format: Format::Png };
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let size = || match value.parse() {
+                Ok(size) if size > 0 => Ok(size),
+                _ => Err(format!("invalid {} {}", name, value))
+            };
+            match name {
+                "width" => params.width = size()?,
+                "height" => params.height = size()?,
+                "format" => params.format = match value {
+                    "png" => Format::Png,
+                    "exr" => Format::Exr,
+                    _ => return Err(format!("unknown format {}, expected png or exr", value))
+                },
+                _ => return Err(format!("unknown parameter {}", name))
+            }
+        }
+        if params.width as u64 * params.height as u64 > MAX_PIXELS {
+            return Err(format!("{}×{} image is larger than {} pixels", params.width, params.height, MAX_PIXELS))
+        }
+        Ok(params)
+    }
+}
+
+fn handle(request: Request, dir: &Path) {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    if path != "/render" {
+        return respond_error(request, 404, "not found")
+    }
+    let params = match Params::parse(query) {
+        Ok(params) => params,
+        Err(message) => return respond_error(request, 400, &message)
+    };
+    match request.method() {
+        Method::Post => render_http(request, params, dir),
+        Method::Get => match header(&request, "Sec-WebSocket-Key") {
+            Some(key) => {
+                let accept = derive_accept_key(key.as_bytes());
+                let response = Response::empty(101)
+                    .with_header(make_header("Upgrade", "websocket"))
+                    .with_header(make_header("Connection", "Upgrade"))
+                    .with_header(make_header("Sec-WebSocket-Accept", &accept));
+                let stream = request.upgrade("websocket", response);
+                render_websocket(WebSocket::from_raw_socket(stream, Role::Server, None), params, dir)
+            },
+            None => respond_error(request, 426, "expected a WebSocket request, or POST to render without progress")
+        },
+        _ => respond_error(request, 405, "method not allowed")
+    }
+}
+
+/// Render the scene in the body of the request and respond with the image
+fn render_http(mut request: Request, params: Params, dir: &Path) {
+    // Refuse scenes over the limit, whether or not their size is given up
+    // front, rather than cutting them short
+    let too_large = format!("scene is larger than {} bytes", MAX_SCENE_SIZE);
+    if request.body_length().is_some_and(|length| length as u64 > MAX_SCENE_SIZE) {
+        return respond_error(request, 413, &too_large)
+    }
+    let mut body = Vec::new();
+    if let Err(err) = request.as_reader().take(MAX_SCENE_SIZE + 1).read_to_end(&mut body) {
+        return respond_error(request, 400, &format!("could not read scene: {}", err))
+    }
+    if body.len() as u64 > MAX_SCENE_SIZE {
+        return respond_error(request, 413, &too_large)
+    }
+    let json = match String::from_utf8(body) {
+        Ok(json) => json,
+        Err(err) => return respond_error(request, 400, &format!("could not read scene: {}", err))
+    };
+    let scene = match load(&json, dir) {
+        Ok(scene) => scene,
+        Err(message) => return respond_error(request, 400, &message)
+    };
+    let image = match params.format {
+        Format::Png => {
+            let mut film = Film::new(params.width, params.height);
+            film.tonemap = scene.tonemap;
+            capture(&scene, &mut film);
+            output::encode_png(&film).map_err(|err| err.to_string())
+        },
+        Format::Exr => {
+            let mut film = HdrFilm::new(params.width, params.height);
+            capture(&scene, &mut film);
+            output::encode_exr(&film).map_err(|err| err.to_string())
+        }
+    };
+    match image {
+        Ok(image) => {
+            let response = Response::from_data(image)
+                .with_header(make_header("Content-Type", params.format.content_type()));
+            request.respond(response).ok();
+        },
+        Err(message) => respond_error(request, 500, &format!("could not encode image: {}", message))
+    }
+}
+
+type Socket = WebSocket<Box<dyn ReadWrite + Send>>;
+
+/// Render the scene sent as the first message, streaming tiles and progress
+/// back over the socket and then the finished image
+fn render_websocket(mut socket: Socket, params: Params, dir: &Path) {
+    let scene = match socket.read() {
+        Ok(Message::Text(json)) => load(&json, dir),
+        Ok(_) => Err("expected the scene as a text message".to_string()),
+        Err(_) => return
+    };
+    let scene = match scene {
+        Ok(scene) => scene,
+        Err(message) => {
+            socket.send(Message::Text(json!({ "type": "error", "message": message }).to_string())).ok();
+            socket.close(None).ok();
+            return
+        }
+    };
+
+    let stream = TileStream { socket: Mutex::new(socket), closed: AtomicBool::new(false) };
+    let image = match params.format {
+        Format::Png => {
+            let mut film = Film::new(params.width, params.height);
+            film.tonemap = scene.tonemap;
+            capture_with_progress(&scene, &mut film, &stream);
+            output::encode_png(&film).map_err(|err| err.to_string())
+        },
+        Format::Exr => {
+            let mut film = HdrFilm::new(params.width, params.height);
+            capture_with_progress(&scene, &mut film, &stream);
+            output::encode_exr(&film).map_err(|err| err.to_string())
+        }
+    };
+
+    let mut socket = stream.socket.into_inner().unwrap();
+    match image {
+        Ok(image) => {
+            let done = json!({ "type": "done", "format": params.format.name() });
+            socket.send(Message::Text(done.to_string()))
+                .and_then(|_| socket.send(Message::Binary(image))).ok();
+        },
+        Err(message) => {
+            let message = format!("could not encode image: {}", message);
+            socket.send(Message::Text(json!({ "type": "error", "message": message }).to_string())).ok();
+        }
+    }
+    socket.close(None).ok();
+}
+
+/// Sends each tile and the progress of a render over a WebSocket. Once
+/// sending fails, such as when the client goes away, the rest are dropped.
+struct TileStream {
+    socket: Mutex<Socket>,
+    closed: AtomicBool
+}
+
+impl TileStream {
+    fn send(&self, message: Message) {
+        if self.closed.load(Ordering::Relaxed) { return }
+        if self.socket.lock().unwrap().send(message).is_err() {
+            self.closed.store(true, Ordering::Relaxed)
+        }
+    }
+}
+
+impl RenderProgress for TileStream {
+    fn on_tile_complete(&self, bounds: TileBounds, pixels: &[Pixel]) {
+        let mut data = Vec::with_capacity(16 + pixels.len() * 4);
+        for value in [bounds.x, bounds.y, bounds.w, bounds.h] {
+            data.extend_from_slice(&value.to_le_bytes())
+        }
+        data.extend_from_slice(pixels.as_flattened());
+        self.send(Message::Binary(data))
+    }
+
+    fn on_progress(&self, progress: &Progress) {
+        let progress = json!({ "type": "progress", "tiles_done": progress.tiles_done, "tiles": progress.tiles });
+        self.send(Message::Text(progress.to_string()))
+    }
+}
+
+/// Build the scene described by the given JSON text. Files outside of the
+/// given directory may not be read.
+fn load(json: &str, dir: &Path) -> Result<Scene, String> {
+    let desc = scene_io::from_str(json).map_err(|err| format!("invalid scene: {}", err))?;
+    for path in desc.paths() {
+        check_path(path, dir)?
+    }
+    desc.build(dir).map_err(|err| format!("invalid scene: {}", err))
+}
+
+/// Check that the given relative path of a scene file stays within the
+/// directory, including through symbolic links
+fn check_path(path: &str, dir: &Path) -> Result<(), String> {
+    let outside = || Err(format!("invalid scene: path {} is outside of the scene directory", path));
+    if !Path::new(path).components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+        return outside()
+    }
+    // Missing files are reported when the scene is built
+    match (dir.canonicalize(), dir.join(path).canonicalize()) {
+        (Ok(dir), Ok(file)) if !file.starts_with(&dir) => outside(),
+        _ => Ok(())
+    }
+}
+
+/// Value of the request header with the given name, if any
+fn header<'r>(request: &'r Request, name: &'static str) -> Option<&'r str> {
+    request.headers().iter().find(|header| header.field.equiv(name)).map(|header| header.value.as_str())
+}
+
+fn make_header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).unwrap()
+}
+
+fn respond_error(request: Request, status: u16, message: &str) {
+    let response = Response::from_string(format!("{}\n", message))
+        .with_status_code(status)
+        .with_header(make_header("Content-Type", "text/plain; charset=utf-8"));
+    request.respond(response).ok();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn parses_params() {
+        let params = Params::parse("").unwrap();
+        assert_eq!((params.width, params.height, params.format), (512, 512, Format::Png));
+        let params = Params::parse("width=4096&height=4096&format=exr").unwrap();
+        assert_eq!((params.width, params.height, params.format), (4096, 4096, Format::Exr));
+
+        assert!(Params::parse("width=0").is_err());
+        assert!(Params::parse("height=-1").is_err());
+        assert!(Params::parse("width=4097&height=4096").is_err());
+        assert!(Params::parse("width=4294967295&height=4294967295").is_err());
+        assert!(Params::parse("format=gif").is_err());
+        assert!(Params::parse("size=64").is_err());
+    }
+
+    #[test]
+    fn keeps_paths_within_dir() {
+        let root = env::temp_dir().join(format!("lasgun-server-{}", process::id()));
+        let dir = root.join("scenes");
+        fs::remove_dir_all(&root).ok();
+        fs::create_dir_all(dir.join("meshes")).unwrap();
+        fs::write(dir.join("meshes/cube.obj"), "").unwrap();
+        fs::write(root.join("secret.obj"), "").unwrap();
+
+        assert!(check_path("meshes/cube.obj", &dir).is_ok());
+        assert!(check_path("./meshes/cube.obj", &dir).is_ok());
+        assert!(check_path("meshes/missing.obj", &dir).is_ok());
+        assert!(check_path("../secret.obj", &dir).is_err());
+        assert!(check_path("meshes/../../secret.obj", &dir).is_err());
+        assert!(check_path(root.join("secret.obj").to_str().unwrap(), &dir).is_err());
+
+        // Links may point within the directory but not out of it
+        #[cfg(unix)] {
+            std::os::unix::fs::symlink(root.join("secret.obj"), dir.join("secret.obj")).unwrap();
+            std::os::unix::fs::symlink(dir.join("meshes/cube.obj"), dir.join("cube.obj")).unwrap();
+            assert!(check_path("secret.obj", &dir).is_err());
+            assert!(check_path("cube.obj", &dir).is_ok());
+        }
+        fs::remove_dir_all(&root).ok();
+    }
+}
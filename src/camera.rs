@@ -1,8 +1,11 @@
 use std::f64;
 use crate::space::*;
+use crate::animation::{Track, CameraKey};
 use crate::img::Img;
+use crate::sampler::Sampler;
+use crate::core::bxdf::sampling::concentric_sample_disk;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Camera {
 
     /// The position of the eye/camera in the scene
@@ -21,19 +24,52 @@ pub struct Camera {
     /// Type of projection
     projection: Projection,
 
-    /// Supersampling root; 0 => 1 sample, 1 => 4 samples, 2 => 9, etc.
-    supersampling: Supersampling,
+    /// Number of samples taken for each pixel. Defaults to 1
+    samples: u32,
 
     /// Aperture radius in world size, for lens blur. Defaults to 0 (pinhole, no blur)
     aperture_radius: f64,
 
+    /// Distance from the lens to the plane that is in perfect focus. Defaults
+    /// to the magnitude of the view vector (i.e., the look-at point is sharp)
+    focal_distance: Option<f64>,
+
+    /// Horizontal and vertical offset of the image plane from the view
+    /// direction, in units of its height. Defaults to (0, 0)
+    shift: (f64, f64),
+
+    /// Horizontal and vertical rotation of the plane of focus away from the
+    /// image plane, in degrees. Defaults to (0, 0)
+    tilt: (f64, f64),
+
     /// Vertical extent of image plane
     image_plane_height: f64,
 
     /// Distance between individial photocells on the sensor as a multiple of
     /// the distance between pixels on the image plane. Tweak this value
     /// to change the perspective.
-    pixel_separation: f64
+    pixel_separation: f64,
+
+    /// Times at which the shutter opens and closes. Rays are cast at times
+    /// in between. Defaults to (0, 0), i.e., no motion blur
+    shutter: (f64, f64),
+
+    /// Position and orientation at time 1 of a moving camera. Its pose from
+    /// `look_at` applies at time 0
+    end: Option<Pose>,
+
+    /// Positions and orientations over the frames of an animation
+    animation: Track<CameraKey>
+}
+
+/// Position and orientation of the camera at a moment in time
+#[derive(Clone, Copy, Debug)]
+struct Pose {
+    origin: Point,
+    view: Vector,
+    up: Vector,
+    aux: Vector,
+    image_plane_height: f64
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -44,17 +80,16 @@ enum Projection {
     /// Orthographic camera for isometric rendering w/ a scalar field that
     /// represents vertical height (along the y-axis/up vector) of focal plane
     /// in world units.
-    Orthographic(f64)
-}
+    Orthographic(f64),
 
-#[derive(Clone, Copy, Debug)]
-struct Supersampling {
-    /// Root of how many samples to take. 0 => 1 sample, 1 => 4 samples, 2 => 9,
-    /// etc.
-    pub root: usize,
+    /// Equidistant fisheye camera with a field of view (in degrees) across
+    /// the circle inscribed in the image. The angle from the view direction
+    /// grows in proportion to the distance from the centre of the image.
+    Fisheye(f64),
 
-    /// Distance between samples within a pixel. Must be 1 for root 0
-    distance: f64
+    /// Full 360° panorama mapping longitude across the width of the image
+    /// and latitude down its height
+    Equirectangular
 }
 
 impl Camera {
@@ -65,10 +100,16 @@ impl Camera {
             view: Vector::unit_z(),
             up: Vector::unit_y(),
             aux: Vector::unit_x(),
-            supersampling: Supersampling::new(),
+            samples: 1,
             aperture_radius: 0.,
+            focal_distance: None,
+            shift: (0., 0.),
+            tilt: (0., 0.),
             image_plane_height: projection.image_plane_height(1.),
-            pixel_separation: projection.pixel_separation()
+            pixel_separation: projection.pixel_separation(),
+            shutter: (0., 0.),
+            end: None,
+            animation: Track::new()
         }
     }
 
@@ -82,65 +123,286 @@ impl Camera {
         Camera::new(Projection::Orthographic(height))
     }
 
+    /// Fisheye camera with the given field of view in degrees across the
+    /// circle inscribed in the image, e.g., 180 for a dome master. Pixels
+    /// outside the circle continue the same mapping past the field of view,
+    /// up to looking straight back.
+    pub fn fisheye(fov: f64) -> Self {
+        debug_assert!(fov > 0. && fov <= 360.);
+        Camera::new(Projection::Fisheye(fov))
+    }
+
+    /// Camera that sees in every direction around it, producing a 360°
+    /// panorama (e.g., for VR viewers or environment maps) with the view
+    /// direction at the centre of the image. Images should be twice as wide
+    /// as they are tall.
+    pub fn equirectangular() -> Self {
+        Camera::new(Projection::Equirectangular)
+    }
+
+    /// Orthographic camera that frames as much of the scene as a perspective
+    /// camera with the given field of view (in degrees) does at the given
+    /// distance, e.g., that of the look-at point. Useful for switching a view
+    /// between projections without the subject changing size.
+    pub fn orthographic_fov(fov: f64, distance: f64) -> Self {
+        debug_assert!(fov > 0. && distance > 0.);
+        Camera::orthographic(Projection::Perspective(fov).image_plane_height(distance))
+    }
+
+    /// True isometric camera: an orthographic camera that looks down at the
+    /// given point from the given distance along the diagonal of the x, y and
+    /// z axes, which appear 120° apart with x and z at 30° to the horizontal.
+    /// The image plane has the given height, as for `orthographic`.
+    pub fn isometric(height: f64, look: [f64; 3], distance: f64) -> Self {
+        let mut camera = Camera::orthographic(height);
+        camera.look_isometric(look, distance);
+        camera
+    }
+
     pub fn look_at(&mut self, origin: [f64; 3], look: [f64; 3], up: [f64; 3]) {
+        let pose = self.pose(origin, look, up);
+        self.origin = pose.origin;
+        self.up = pose.up;
+        self.aux = pose.aux;
+        self.view = pose.view;
+        self.image_plane_height = pose.image_plane_height;
+    }
+
+    /// Look down at the given point from the given distance along the
+    /// diagonal of the x, y and z axes, as for an isometric camera
+    pub fn look_isometric(&mut self, look: [f64; 3], distance: f64) {
+        debug_assert!(distance > 0.);
+        let offset = distance / f64::sqrt(3.);
+        let origin = [look[0] + offset, look[1] + offset, look[2] + offset];
+        self.look_at(origin, look, [0., 1., 0.])
+    }
+
+    /// Look from and at the given points at the given time of an animation,
+    /// in seconds. See `Scene::set_time`.
+    pub fn key_look_at(&mut self, time: f64, origin: [f64; 3], look: [f64; 3], up: [f64; 3]) {
+        self.animation.key(time, CameraKey::new(origin, look, up));
+    }
+
+    /// Move to the keyed position and orientation at the given time of the
+    /// animation, if any
+    pub fn set_time(&mut self, time: f64) {
+        if let Some(key) = self.animation.value_at(time) {
+            self.look_at(key.origin.into(), key.look.into(), key.up.into())
+        }
+    }
+
+    /// Move the camera from its `look_at` pose at time 0 to the given pose at
+    /// time 1, blurring the scene along the motion while the shutter is open
+    pub fn move_to(&mut self, origin: [f64; 3], look: [f64; 3], up: [f64; 3]) {
+        self.end = Some(self.pose(origin, look, up))
+    }
+
+    /// Open the shutter over the given interval of time, such that objects
+    /// and cameras moving within it appear blurred. Groups of the scene move
+    /// between their keyframes at times 0 and 1. See `Aggregate::keyframe`.
+    pub fn set_shutter(&mut self, open: f64, close: f64) {
+        debug_assert!(open <= close);
+        self.shutter = (open, close)
+    }
+
+    fn pose(&self, origin: [f64; 3], look: [f64; 3], up: [f64; 3]) -> Pose {
         let origin = Point::from(origin);
         let view = Point::from(look) - origin;
         let aux = view.cross(up.into());
-        self.origin = origin;
-        self.up = aux.cross(view).normalize();
-        self.aux = aux.normalize();
-        self.view = view;
-        self.image_plane_height = self.projection.image_plane_height(view.magnitude());
+        Pose {
+            origin,
+            view,
+            up: aux.cross(view).normalize(),
+            aux: aux.normalize(),
+            image_plane_height: self.projection.image_plane_height(view.magnitude())
+        }
+    }
+
+    /// Pose of the camera at the given time
+    fn pose_at(&self, time: f64) -> Pose {
+        let start = Pose {
+            origin: self.origin,
+            view: self.view,
+            up: self.up,
+            aux: self.aux,
+            image_plane_height: self.image_plane_height
+        };
+        let end = match self.end {
+            Some(end) if time > 0. => end,
+            _ => return start
+        };
+        let t = time.min(1.);
+        Pose {
+            origin: start.origin + (end.origin - start.origin) * t,
+            view: start.view.lerp(end.view, t),
+            up: start.up.lerp(end.up, t).normalize(),
+            aux: start.aux.lerp(end.aux, t).normalize(),
+            image_plane_height: start.image_plane_height + (end.image_plane_height - start.image_plane_height) * t
+        }
     }
 
+    /// Take the given number of samples for each pixel, spread over the
+    /// pixel by the scene's sampler. Counts need not be square, e.g., 24.
+    pub fn set_samples_per_pixel(&mut self, samples: u32) {
+        debug_assert!(samples > 0);
+        self.samples = samples.max(1)
+    }
+
+    /// Take (base + 1)^2 samples for each pixel, on a square grid with the
+    /// default sampler: 0 takes 1 sample, 1 takes 4, 2 takes 9 and so on. See
+    /// `set_samples_per_pixel` for other counts.
     pub fn set_supersampling(&mut self, base: u8) {
-        self.supersampling.set(base)
+        let root = base as u32 + 1;
+        self.set_samples_per_pixel(root * root)
     }
 
     pub fn set_aperture_radius(&mut self, radius: f64) {
         self.aperture_radius = radius
     }
 
+    /// Distance from the camera to the plane in focus when the aperture radius
+    /// is non-zero. Objects nearer or further than this appear blurred.
+    pub fn set_focal_distance(&mut self, distance: f64) {
+        debug_assert!(distance > 0.);
+        self.focal_distance = Some(distance)
+    }
+
+    /// Shift the lens across and up by the given fractions of the image
+    /// height, moving the frame without turning the camera. Keeps vertical
+    /// lines parallel when framing a tall building from the ground: point the
+    /// camera level and shift it up instead of tilting it back.
+    pub fn set_lens_shift(&mut self, horizontal: f64, vertical: f64) {
+        self.shift = (horizontal, vertical)
+    }
+
+    /// Tilt the lens to turn the plane of focus by the given angles in
+    /// degrees, so that a surface at an angle to the camera (e.g., a floor or
+    /// tabletop) can be sharp from front to back. Positive vertical tilt turns
+    /// the top of the plane away from the camera and positive horizontal tilt
+    /// its right side. Only affects cameras with an aperture.
+    pub fn set_lens_tilt(&mut self, horizontal: f64, vertical: f64) {
+        debug_assert!(horizontal.abs() < 90. && vertical.abs() < 90.);
+        self.tilt = (horizontal, vertical)
+    }
+
     #[inline]
+    /// Approximate width in world units covered by one pixel of an image of
+    /// the given height at the given point, as seen from the current pose
+    pub fn pixel_width_at(&self, p: Point, height: u32) -> f64 {
+        self.projection.image_plane_height((p - self.origin).magnitude()) / height.max(1) as f64
+    }
+
+    /// Number of samples taken for each pixel
+    pub fn samples_per_pixel(&self) -> u32 { self.samples }
+
     pub fn num_samples(&self) -> usize {
-        self.supersampling.num_samples()
+        self.samples as usize
+    }
+
+    /// Generate the ray for the current sample of pixel (x, y) of the given
+    /// image. The sampler picks the point within the pixel, the point on the
+    /// lens and the time within the shutter interval.
+    pub fn sample(&self, x: u32, y: u32, img: &impl Img, sampler: &mut Sampler) -> Ray {
+        self.sample_at(x, y, img, sampler).0
+    }
+
+    /// Generate the ray for the current sample of pixel (x, y) as with
+    /// `sample`, along with the point within the pixel that it passes through
+    pub(crate) fn sample_at(&self, x: u32, y: u32, img: &impl Img, sampler: &mut Sampler) -> (Ray, Point2f) {
+        let u = sampler.get_pixel_2d();
+        let lens = if self.aperture_radius > 0. {
+            sampler.get_2d()
+        } else {
+            Point2f::new(0.5, 0.5)
+        };
+        let time = if self.shutter.0 < self.shutter.1 { sampler.get_1d() } else { 0. };
+
+        // Each of many samples covers a smaller part of the pixel, so their
+        // footprints on surfaces shrink to match
+        let spp = sampler.samples_per_pixel().max(1) as f64;
+        (self.ray(x, y, img, &u, &lens, time).scale_differentials(1. / spp.sqrt()), u)
     }
 
-    pub fn allocate_samples(&self) -> Vec<Ray> {
-        vec![Ray::default(); self.num_samples()]
+    /// Generate the ray through point u within pixel (x, y) of the given image,
+    /// where u ranges from (0, 0) at the bottom-left corner of the pixel to
+    /// (1, 1) at its top-right corner. The lens sample in the same range picks
+    /// the origin of the ray on the aperture, and the time sample in [0, 1)
+    /// picks the moment within the shutter interval. The ray carries
+    /// differentials toward the same point of the neighbouring pixels.
+    pub fn ray(&self, x: u32, y: u32, img: &impl Img, u: &Point2f, lens: &Point2f, time: f64) -> Ray {
+        let rx = self.cast(x, y, img, &Point2f::new(u.x + 1., u.y), lens, time);
+        let ry = self.cast(x, y, img, &Point2f::new(u.x, u.y + 1.), lens, time);
+        self.cast(x, y, img, u, lens, time).with_differentials(&rx, &ry)
     }
 
-    pub fn sample(&self, x: u32, y: u32, img: &impl Img, rays: &mut [Ray]) {
-        debug_assert!(self.num_samples() == rays.len());
-        let img_plane_height = self.image_plane_height;
+    /// Ray through point u within pixel (x, y), without differentials. Points
+    /// outside [0, 1] fall in the neighbouring pixels.
+    fn cast(&self, x: u32, y: u32, img: &impl Img, u: &Point2f, lens: &Point2f, time: f64) -> Ray {
+        let time = self.shutter.0 + time * (self.shutter.1 - self.shutter.0);
+        let pose = self.pose_at(time);
+        match self.projection {
+            Projection::Fisheye(_) | Projection::Equirectangular =>
+                return Ray::new(pose.origin, self.panoramic_direction(&pose, x, y, img, u)).at_time(time),
+            _ => ()
+        }
+
+        let img_plane_height = pose.image_plane_height;
         let img_plane_width = img_plane_height * img.aspect();
         let pixel_size = img_plane_height * img.hinv();
-        let sample_separation = self.supersampling.distance() * pixel_size;
-        let sample_origin = Point2f {
-            x: (x as f64 * img.winv() - 0.5) * img_plane_width,
-            y: (0.5 - (y + 1) as f64 * img.hinv()) * img_plane_height
+        let sample = Point2f {
+            x: (x as f64 * img.winv() - 0.5 + self.shift.0 / img.aspect()) * img_plane_width + u.x * pixel_size,
+            y: (0.5 - (y + 1) as f64 * img.hinv() + self.shift.1) * img_plane_height + u.y * pixel_size
         };
 
-        // All sampled rays have the same origin
-        let origin = self.origin
-            + (sample_origin.y * self.pixel_separation * self.up)
-            + (sample_origin.x * self.pixel_separation * self.aux);
-
-        // Target direction at bottom-left corner of target pixel
-        let d = self.view + (sample_origin.y * self.up) + (sample_origin.x * self.aux);
-
-        let updiff = self.up * sample_separation;
-        let auxdiff = self.aux * sample_separation;
-        let halfdiff = updiff * 0.5 + auxdiff * 0.5; // centers the sample
-
-        let dim = self.supersampling.root;
-        for i in 0..dim {
-            for j in 0..dim {
-                let idx = i * dim + j;
-                let (i, j) = (i as f64, j as f64);
-                let d = d + (j * updiff) + (i * auxdiff) + halfdiff;
-                // TODO: Integrate aperture radius
-                rays[idx] = Ray::new(origin, d)
+        let origin = pose.origin
+            + (sample.y * self.pixel_separation * pose.up)
+            + (sample.x * self.pixel_separation * pose.aux);
+
+        // Target direction through the sample on the image plane
+        let d = pose.view + (sample.y * pose.up) + (sample.x * pose.aux);
+
+        if self.aperture_radius <= 0. {
+            return Ray::new(origin, d).at_time(time)
+        }
+
+        // Thin lens model: rays through every point on the lens converge where
+        // the pinhole ray meets the plane of focus, which lens tilt turns
+        // about its centre
+        let view_distance = pose.view.magnitude();
+        let focal_distance = self.focal_distance.unwrap_or(view_distance);
+        let forward = pose.view / view_distance;
+        let normal = forward
+            - pose.up * self.tilt.1.to_radians().tan()
+            - pose.aux * self.tilt.0.to_radians().tan();
+        let t = (forward * focal_distance).dot(normal) / d.dot(normal);
+        let focus = origin + d * t;
+        let lens = self.aperture_radius * concentric_sample_disk(lens);
+        let origin = origin + (lens.x * pose.aux) + (lens.y * pose.up);
+        Ray::new(origin, focus - origin).at_time(time)
+    }
+
+    /// Direction of the ray through point u within pixel (x, y) for the
+    /// fisheye and equirectangular projections, which map pixels to angles
+    /// around the camera rather than to points on an image plane
+    fn panoramic_direction(&self, pose: &Pose, x: u32, y: u32, img: &impl Img, u: &Point2f) -> Vector {
+        // Position of the sample from (0, 0) at the top-left corner of the
+        // image to (1, 1) at its bottom-right
+        let s = Point2f::new((x as f64 + u.x) * img.winv(), ((y + 1) as f64 - u.y) * img.hinv());
+        let forward = pose.view.normalize();
+        match self.projection {
+            Projection::Fisheye(fov) => {
+                // Offset from the centre of the image, in units of its height
+                let (dx, dy) = ((s.x - 0.5) * img.aspect(), 0.5 - s.y);
+                let r = (dx * dx + dy * dy).sqrt();
+                if r == 0. { return forward }
+                let theta = (r * fov.to_radians()).min(f64::consts::PI);
+                let side = (dx / r) * pose.aux + (dy / r) * pose.up;
+                forward * theta.cos() + side * theta.sin()
+            },
+            _ => {
+                let phi = (s.x - 0.5) * 2. * f64::consts::PI;
+                let lat = (0.5 - s.y) * f64::consts::PI;
+                (forward * phi.cos() + pose.aux * phi.sin()) * lat.cos() + pose.up * lat.sin()
             }
         }
     }
@@ -159,7 +421,8 @@ impl Projection {
         match self {
             Self::Perspective(fov) =>
                 focal_distance * f64::tan(*fov * f64::consts::PI / 360.) * 2.,
-            Self::Orthographic(height) => *height
+            Self::Orthographic(height) => *height,
+            Self::Fisheye(_) | Self::Equirectangular => focal_distance
         }
     }
 
@@ -167,28 +430,91 @@ impl Projection {
     /// sample centres on the image plane.
     pub fn pixel_separation(&self) -> f64 {
         match self {
-            Self::Perspective(_) => 0.,
-            Self::Orthographic(_) => 1.
+            Self::Orthographic(_) => 1.,
+            _ => 0.
         }
     }
 }
 
-impl Supersampling {
-    pub fn new() -> Supersampling {
-        Supersampling { root: 1, distance: 1. }
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn isometric_axes_at_thirty_degrees() {
+        let camera = Camera::isometric(10., [1., 2., 3.], 20.);
+        assert!((camera.view.magnitude() - 20.).abs() < 1e-9);
+
+        // Angle of each world axis on the image plane, from the horizontal
+        let angle = |axis: Vector| axis.dot(camera.up).atan2(axis.dot(camera.aux)).to_degrees();
+        assert!((angle(Vector::unit_x()) + 30.).abs() < 1e-9);
+        assert!((angle(Vector::unit_z()) + 150.).abs() < 1e-9);
+        assert!((angle(Vector::unit_y()) - 90.).abs() < 1e-9);
     }
 
-    #[inline]
-    pub fn distance(&self) -> f64 { self.distance }
+    #[test]
+    fn panoramas_cover_every_direction() {
+        let film = crate::film::HdrFilm::new(40, 20);
+        let mut camera = Camera::equirectangular();
+        camera.look_at([0., 0., 0.], [0., 0., -1.], [0., 1., 0.]);
+        let centre = Point2f::new(0., 0.);
+        let direction = |x, y| camera.ray(x, y, &film, &centre, &centre, 0.).d.normalize();
+
+        // The centre of the image looks ahead, its edges straight back and its
+        // top straight up
+        assert!((direction(20, 9) - Vector::new(0., 0., -1.)).magnitude() < 0.2);
+        assert!((direction(0, 9) - Vector::new(0., 0., 1.)).magnitude() < 0.2);
+        assert!((direction(10, 9) - Vector::new(-1., 0., 0.)).magnitude() < 0.2);
+        assert!(direction(20, 0).y > 0.95);
+
+        // A 180° fisheye sees sideways at the edge of its circle
+        let mut camera = Camera::fisheye(180.);
+        camera.look_at([0., 0., 0.], [0., 0., -1.], [0., 1., 0.]);
+        let film = crate::film::HdrFilm::new(20, 20);
+        let direction = camera.ray(19, 10, &film, &Point2f::new(1., 1.), &centre, 0.).d.normalize();
+        assert!((direction - Vector::new(1., 0., 0.)).magnitude() < 1e-9);
+    }
 
-    #[inline]
-    pub fn num_samples(&self) -> usize {
-        self.root * self.root
+    #[test]
+    fn differentials_reach_neighbouring_pixels() {
+        let film = crate::film::HdrFilm::new(10, 10);
+        let mut camera = Camera::perspective(60.);
+        camera.look_at([0., 1., 4.], [0., 0., 0.], [0., 1., 0.]);
+        let centre = Point2f::new(0.5, 0.5);
+        let ray = |x, y| camera.ray(x, y, &film, &centre, &centre, 0.);
+        let diff = ray(5, 5).differentials.unwrap();
+        assert!((diff.rx_d - ray(6, 5).d).magnitude() < 1e-9);
+        assert!((diff.ry_d - ray(5, 4).d).magnitude() < 1e-9);
+        assert_eq!(diff.rx_origin, ray(6, 5).origin);
+    }
+
+    #[test]
+    fn lens_shift_and_tilt() {
+        let film = crate::film::HdrFilm::new(10, 10);
+        let mut camera = Camera::perspective(90.);
+        camera.look_at([0., 0., 0.], [0., 0., -1.], [0., 1., 0.]);
+        camera.set_lens_shift(0., 0.5);
+        let centre = Point2f::new(0.5, 0.5);
+        let d = camera.ray(5, 4, &film, &centre, &centre, 0.).d;
+        assert!((d.normalize() - Vector::new(0., 1., -1.).normalize()).magnitude() < 0.15);
+
+        // Rays through the top of the image focus farther away when the plane
+        // of focus is tilted back
+        camera.set_lens_shift(0., 0.);
+        camera.set_aperture_radius(0.1);
+        camera.set_focal_distance(2.);
+        let focus = |camera: &Camera| {
+            let ray = camera.ray(5, 0, &film, &centre, &Point2f::new(0.2, 0.7), 0.);
+            -(ray.origin + ray.d).z
+        };
+        assert!((focus(&camera) - 2.).abs() < 1e-9);
+        camera.set_lens_tilt(0., 20.);
+        assert!(focus(&camera) > 2.5);
     }
 
-    pub fn set(&mut self, base: u8) {
-        debug_assert!(base < 255);
-        self.root = base as usize + 1;
-        self.distance = 1. / self.root as f64;
+    #[test]
+    fn orthographic_frames_perspective() {
+        let camera = Camera::orthographic_fov(90., 3.);
+        assert!((camera.image_plane_height - 6.).abs() < 1e-9);
     }
 }
@@ -0,0 +1,157 @@
+//! Checkpoints of progressive renders, saved to disk so that long renders may
+//! pick up where they left off after a crash or interruption.
+//!
+//! A checkpoint holds the sum of the samples taken so far for each pixel and
+//! the number of samples and passes taken. Samplers are seeded by pixel and
+//! sample index, so resumed renders continue the same sample sequences.
+
+use std::{fs, io::{self, Read, Write, BufReader, BufWriter}, path::Path};
+use crate::{space::*, img::Img, Scene, ProgressiveRenderer};
+
+/// Identifies checkpoint files and the version of their layout
+const MAGIC: &[u8; 8] = b"LSGCKPT1";
+
+/// Number of passes a resumable render is split into, each followed by a
+/// checkpoint
+const CHECKPOINT_PASSES: u32 = 16;
+
+/// Record an image of the scene on the given film with the samples per pixel
+/// set by the camera, saving a checkpoint to the given file after each of a
+/// series of passes. If the file already holds a checkpoint, the render
+/// resumes from it. The file is removed once the render completes.
+///
+/// Checkpoints don't record the scene; resuming from a checkpoint of another
+/// scene mixes the two. Samples are placed as by a `ProgressiveRenderer`.
+pub fn capture_resume(scene: &Scene, film: &mut impl Img, filename: &str) -> io::Result<()> {
+    let target = scene.camera.num_samples() as u32;
+    let step = (target / CHECKPOINT_PASSES).max(1);
+
+    let mut renderer = ProgressiveRenderer::new(scene);
+    if Path::new(filename).exists() {
+        renderer.load_checkpoint(filename, film.w(), film.h())?;
+        renderer.develop(film);
+    } else {
+        renderer.accumulated = vec![Color::zero(); film.w() as usize * film.h() as usize];
+        renderer.size = (film.w(), film.h());
+    }
+
+    while renderer.samples < target {
+        renderer.take_samples(film, step.min(target - renderer.samples));
+        renderer.save_checkpoint(filename)?;
+    }
+    fs::remove_file(filename)
+}
+
+impl<'s> ProgressiveRenderer<'s> {
+    /// Save the samples taken so far to the given file. The file is written
+    /// in full before replacing any previous checkpoint, so that a crash while
+    /// saving leaves the previous one intact.
+    pub fn save_checkpoint(&self, filename: &str) -> io::Result<()> {
+        let partial = format!("{}.partial", filename);
+        {
+            let mut out = BufWriter::new(fs::File::create(&partial)?);
+            out.write_all(MAGIC)?;
+            for value in &[self.accumulated.len() as u32, self.samples, self.pass] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            for sum in &self.accumulated {
+                for channel in &[sum.x, sum.y, sum.z] {
+                    out.write_all(&channel.to_le_bytes())?;
+                }
+            }
+            out.flush()?;
+        }
+        fs::rename(partial, filename)
+    }
+
+    /// Restore the samples saved to the given file by `save_checkpoint`, for
+    /// a film of the given dimensions. Following passes continue from there.
+    pub fn load_checkpoint(&mut self, filename: &str, width: u32, height: u32) -> io::Result<()> {
+        let mut input = BufReader::new(fs::File::open(filename)?);
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC { return Err(invalid("not a render checkpoint")) }
+
+        let area = read_u32(&mut input)? as usize;
+        if area != width as usize * height as usize {
+            return Err(invalid("checkpoint does not match film dimensions"))
+        }
+        let samples = read_u32(&mut input)?;
+        let pass = read_u32(&mut input)?;
+
+        let mut accumulated = Vec::with_capacity(area);
+        for _ in 0..area {
+            let (x, y, z) = (read_f64(&mut input)?, read_f64(&mut input)?, read_f64(&mut input)?);
+            accumulated.push(Color::new(x, y, z))
+        }
+
+        self.accumulated = accumulated;
+        self.size = (width, height);
+        self.samples = samples;
+        self.pass = pass;
+        Ok(())
+    }
+
+    /// Write the average of all samples taken so far to the given film
+    fn develop(&self, film: &mut impl Img) {
+        if self.samples == 0 { return }
+        let weight = 1. / self.samples as f64;
+        for y in 0..film.h() {
+            for x in 0..film.w() {
+                let offset = film.offset(x, y);
+                film.set(x, y, &(self.accumulated[offset] * weight).into())
+            }
+        }
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f64(input: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{film::HdrFilm, MaterialRef};
+
+    #[test]
+    fn resumes_where_it_left_off() {
+        let mut scene = Scene::new();
+        scene.set_perspective_camera(60.).look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.]);
+        scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, MaterialRef::default());
+        scene.add_point_light([0.0, 3.0, 3.0], [0.9, 0.9, 0.9], [1.0, 0.0, 0.0]);
+        let filename = std::env::temp_dir().join("lasgun-checkpoint-test").to_string_lossy().into_owned();
+
+        // Two passes in one go
+        let mut film = HdrFilm::new(8, 8);
+        let mut renderer = ProgressiveRenderer::new(&scene);
+        renderer.next_pass(&mut film);
+        renderer.next_pass(&mut film);
+
+        // One pass, then the other after resuming from a checkpoint
+        let mut resumed = HdrFilm::new(8, 8);
+        let mut renderer = ProgressiveRenderer::new(&scene);
+        renderer.next_pass(&mut resumed);
+        renderer.save_checkpoint(&filename).unwrap();
+
+        let mut renderer = ProgressiveRenderer::new(&scene);
+        renderer.load_checkpoint(&filename, 8, 8).unwrap();
+        renderer.next_pass(&mut resumed);
+        fs::remove_file(&filename).unwrap();
+
+        assert_eq!(renderer.samples(), 2);
+        assert_eq!(film.data(), resumed.data());
+    }
+}
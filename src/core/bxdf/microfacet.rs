@@ -65,9 +65,10 @@ impl Distribution {
         ((1.0 + alpha2_tan2_theta).sqrt() - 1.0) / 2.0
     }
 
-    /// Compute Probability distribution function
+    /// Compute Probability distribution function of the microfacet normals
+    /// visible from wo, as sampled by `sample_wh`
     fn pdf(&self, wo: &Vector, wh: &Vector) -> f64 {
-        self.d(wh) * self.g1(wo) * wo.dot(*wh).abs() / abs_cos_theta(wh)
+        self.d(wh) * self.g1(wo) * wo.dot(*wh).abs() / abs_cos_theta(wo)
     }
 
     // Get sample reflected direction
@@ -39,12 +39,13 @@ pub struct LightSample {
     pub spectrum: Color,
     pub wi: Vector,
     pub pdf: f64,
-    // pub t: BxDFType // for future use?
+    /// Type of the BxDF that was sampled, if known
+    pub t: BxDFType
 }
 impl LightSample {
     #[inline]
     pub fn new(spectrum: Color, wi: Vector, pdf: f64) -> LightSample {
-        LightSample { spectrum, wi, pdf }
+        LightSample { spectrum, wi, pdf, t: BxDFType::NONE }
     }
 
     #[inline]
@@ -204,22 +205,46 @@ impl BxDF {
         }
     }
 
-    /*
     /// Hemispherical-Directional Reflectance funtion gives total reflection in
     /// a given direction due to constant illumination over the hemisphere
     /// (which happens to also be equivalent to reflection in all directions
-    /// based in light from a single incoming direction).
-    pub fn rho_hd(&self, wo: &Vector, wi: &Vector, samples: &[Point2f]) -> Color {
-        Color::zero()
+    /// based in light from a single incoming direction). Exact for Lambertian
+    /// reflection, otherwise estimated with a sample of the BxDF for each of
+    /// the given sample points. Above one for BxDFs that create energy.
+    pub fn rho_hd(&self, wo: &Vector, samples: &[Point2f]) -> Color {
+        if let BxDF::QuickDiffuse(d) = self { return d.rho() }
+        let sum = samples.iter().fold(Color::zero(), |sum, u| {
+            let sample = self.sample_f(wo, u);
+            if sample.pdf > 0.0 {
+                sum + sample.spectrum * util::abs_cos_theta(&sample.wi) / sample.pdf
+            } else {
+                sum
+            }
+        });
+        sum / samples.len().max(1) as f64
     }
 
     /// Hemispherical-Hemispherical Reflectance funtion gives fraction of light
     /// reflected by a surface when incident light is the same from all
-    /// directions.
+    /// directions. Exact for Lambertian reflection, otherwise estimated with
+    /// outgoing directions spread uniformly over the hemisphere by the first
+    /// sample points, and a sample of the BxDF in each of those directions
+    /// from the second.
     pub fn rho_hh(&self, samples1: &[Point2f], samples2: &[Point2f]) -> Color {
-        Color::zero()
+        if let BxDF::QuickDiffuse(d) = self { return d.rho() }
+        let pdf_o = sampling::uniform_hemisphere_pdf();
+        let sum = samples1.iter().zip(samples2.iter()).fold(Color::zero(), |sum, (u1, u2)| {
+            let wo = sampling::uniform_sample_hemisphere(u1);
+            let sample = self.sample_f(&wo, u2);
+            if sample.pdf > 0.0 {
+                let cos = util::abs_cos_theta(&sample.wi) * util::abs_cos_theta(&wo);
+                sum + sample.spectrum * cos / (pdf_o * sample.pdf)
+            } else {
+                sum
+            }
+        });
+        sum / (std::f64::consts::PI * samples1.len().min(samples2.len()).max(1) as f64)
     }
-    */
 }
 
 /// Utility functions
@@ -288,11 +313,11 @@ pub mod util {
     }
 }
 
-// Private sampling utilities used to determine light distribution
-mod sampling {
+// Sampling utilities used to determine light distribution
+pub mod sampling {
     use super::util::*;
     use crate::space::*;
-    use std::f64::consts::{FRAC_1_PI, FRAC_PI_2, FRAC_PI_4};
+    use std::f64::consts::{PI, FRAC_1_PI, FRAC_PI_2, FRAC_PI_4};
 
     /// Default PDF given incoming vector for BxDFs
     #[inline] pub fn pdf(wo: &Vector, wi: &Vector) -> f64 {
@@ -307,6 +332,18 @@ mod sampling {
     }
     */
 
+    /// Map the sample point to a direction in the hemisphere around the normal,
+    /// with every direction equally likely
+    #[inline] pub fn uniform_sample_hemisphere(u: &Point2f) -> Vector {
+        let z = u.x;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.y;
+        Vector::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    /// PDF of directions from `uniform_sample_hemisphere`
+    #[inline] pub fn uniform_hemisphere_pdf() -> f64 { 0.5 * FRAC_1_PI }
+
     /// Take a concentric circle sample and "project" it upward into the
     /// hemispherical dome that encompasses the circle. It's not *quite* uniform
     /// over the surface of the half-sphere, but good enough.
@@ -320,7 +357,7 @@ mod sampling {
     /// mapping from the 1x1 square of possible samples to a uniform [r, Θ]
     /// sample around the circle.
     /// https://blog.thomaspoulet.fr/uniform-sampling-on-unit-hemisphere/
-    fn concentric_sample_disk(u: &Point2f) -> Point2f {
+    pub fn concentric_sample_disk(u: &Point2f) -> Point2f {
         // Map uniform random numbers to $[-1,1]^2$
         let u_offset = 2.0 * u - Vector2f::new(1.0, 1.0);
 
@@ -49,8 +49,10 @@ impl Transmission {
             (self.eta_b, self.eta_a)
         };
 
-        // Compute ray direction for specular transmission
-        if let Some(wi) = refract(wo, &Normal::new(0.0, 0.0, 1.0), eta_i / eta_t) {
+        // Compute ray direction for specular transmission, refracting about
+        // the normal on the side of wo
+        let n = Normal::new(0.0, 0.0, 1.0).face_forward(*wo);
+        if let Some(wi) = refract(wo, &n, eta_i / eta_t) {
             // TODO: Acount for non-symmetry w/ transmission to different medium
             let spectrum = self.t
                 .mul_element_wise(Color::from_value(1.0) - self.substance.evaluate(cos_theta(&wi)))
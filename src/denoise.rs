@@ -0,0 +1,180 @@
+//! Denoising of finished renders, guided by the albedo and normal passes of a
+//! `FilmSet`. The passes are noise-free for most scenes, so they show the
+//! denoiser where edges and texture details are that must survive filtering.
+
+use crate::film::{FilmSet, Aovs};
+
+#[cfg(feature = "threads")]
+use rayon::prelude::*;
+
+/// Filter that removes noise from the radiance of a film set, using its
+/// auxiliary passes as a guide. Implement this to plug in an external
+/// denoiser such as Open Image Denoise through `FilmSet::denoise_with`.
+pub trait Denoiser {
+    /// Denoised radiance of every pixel of the given film set, in row-major
+    /// order
+    fn denoise(&self, film: &FilmSet) -> Vec<[f32; 3]>;
+}
+
+/// Joint non-local means filter. Replaces each pixel with a weighted average
+/// of the pixels around it, weighing most those whose neighbourhoods look
+/// alike and which see surfaces of a similar albedo and orientation.
+///
+/// See Buades et al., "A non-local algorithm for image denoising" (2005) and
+/// Rousselle et al., "Robust Denoising using Feature and Color Information"
+/// (2013).
+#[derive(Debug, Copy, Clone)]
+pub struct NlMeans {
+    /// Half the width of the window of pixels averaged into each pixel
+    pub radius: usize,
+
+    /// Half the width of the neighbourhoods compared between pixels
+    pub patch: usize,
+
+    /// Filter strength. Larger values remove more noise and more detail
+    pub strength: f64,
+
+    /// Largest albedo and normal differences between pixels that still get
+    /// averaged together
+    pub albedo_tolerance: f64,
+    pub normal_tolerance: f64
+}
+
+impl Default for NlMeans {
+    fn default() -> NlMeans {
+        NlMeans { radius: 6, patch: 1, strength: 0.1, albedo_tolerance: 0.1, normal_tolerance: 0.2 }
+    }
+}
+
+impl Denoiser for NlMeans {
+    fn denoise(&self, film: &FilmSet) -> Vec<[f32; 3]> {
+        let (w, h) = (film.beauty.w as usize, film.beauty.h as usize);
+
+        // Compare colours compressed into [0, 1) so that the brightest pixels
+        // don't dominate the neighbourhood distances
+        let compressed: Vec<[f64; 3]> = film.beauty.data().iter()
+            .map(|c| [compress(c[0]), compress(c[1]), compress(c[2])])
+            .collect();
+
+        let row = |y: usize| -> Vec<[f32; 3]> {
+            (0..w).map(|x| self.filter_pixel(film, &compressed, w, h, x, y)).collect()
+        };
+        rows(h, row).into_iter().flatten().collect()
+    }
+}
+
+impl NlMeans {
+    /// Weighted average of the radiance of the pixels in the window around
+    /// the pixel at x/y
+    fn filter_pixel(&self, film: &FilmSet, compressed: &[[f64; 3]], w: usize, h: usize, x: usize, y: usize) -> [f32; 3] {
+        let color = film.beauty.data();
+        let aovs = film.aovs();
+        let p = y * w + x;
+
+        let inv_strength2 = 1.0 / (self.strength * self.strength);
+        let inv_albedo2 = 1.0 / (self.albedo_tolerance * self.albedo_tolerance);
+        let inv_normal2 = 1.0 / (self.normal_tolerance * self.normal_tolerance);
+
+        let (mut sum, mut total) = ([0.0f64; 3], 0.0);
+        for qy in y.saturating_sub(self.radius)..(y + self.radius + 1).min(h) {
+            for qx in x.saturating_sub(self.radius)..(x + self.radius + 1).min(w) {
+                let q = qy * w + qx;
+                let features = distance2(&aovs[p].albedo, &aovs[q].albedo) * inv_albedo2
+                    + distance2(&aovs[p].normal, &aovs[q].normal) * inv_normal2;
+                if !same_surface(&aovs[p], &aovs[q]) || features > 1.0 { continue }
+
+                let patch = self.patch_distance2(compressed, w, h, (x, y), (qx, qy));
+                let weight = (-patch * inv_strength2 - features).exp();
+                for (c, sum) in sum.iter_mut().enumerate() {
+                    *sum += weight * color[q][c] as f64
+                }
+                total += weight;
+            }
+        }
+
+        // The pixel itself always counts, so the total is never zero
+        [(sum[0] / total) as f32, (sum[1] / total) as f32, (sum[2] / total) as f32]
+    }
+
+    /// Mean squared difference between the neighbourhoods around the pixels
+    /// at p and q. Neighbourhoods are clamped to the edges of the image.
+    fn patch_distance2(&self, compressed: &[[f64; 3]], w: usize, h: usize, p: (usize, usize), q: (usize, usize)) -> f64 {
+        let r = self.patch as isize;
+        let at = |x: usize, dx: isize, max: usize| (x as isize + dx).max(0).min(max as isize - 1) as usize;
+        let mut sum = 0.0;
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let a = compressed[at(p.1, dy, h) * w + at(p.0, dx, w)];
+                let b = compressed[at(q.1, dy, h) * w + at(q.0, dx, w)];
+                sum += (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2);
+            }
+        }
+        sum / (3 * (2 * self.patch + 1).pow(2)) as f64
+    }
+}
+
+/// Whether both pixels see a surface, or both see none
+#[inline]
+fn same_surface(p: &Aovs, q: &Aovs) -> bool {
+    (p.id == Aovs::NO_ID) == (q.id == Aovs::NO_ID)
+}
+
+#[inline]
+fn compress(c: f32) -> f64 {
+    let c = c.max(0.0) as f64;
+    c / (1.0 + c)
+}
+
+#[inline]
+fn distance2(a: &[f32; 3], b: &[f32; 3]) -> f64 {
+    a.iter().zip(b.iter()).map(|(a, b)| (*a as f64 - *b as f64).powi(2)).sum()
+}
+
+/// Compute each of the given number of rows with f, in parallel when the
+/// threads feature is enabled
+#[cfg(feature = "threads")]
+fn rows<T: Send, F: Fn(usize) -> T + Send + Sync>(h: usize, f: F) -> Vec<T> {
+    (0..h).into_par_iter().map(f).collect()
+}
+
+#[cfg(not(feature = "threads"))]
+fn rows<T: Send, F: Fn(usize) -> T + Send + Sync>(h: usize, f: F) -> Vec<T> {
+    (0..h).map(f).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::img::Img;
+    use rand::{Rng, SeedableRng, rngs::SmallRng};
+
+    #[test]
+    fn smooths_noise_but_keeps_edges() {
+        // Two flat halves of different albedo, lit with noisy radiance
+        let (w, h) = (24, 24);
+        let mut film = FilmSet::new(w, h);
+        let mut rng = SmallRng::seed_from_u64(3);
+        for y in 0..h {
+            for x in 0..w {
+                let albedo = if x < w / 2 { 0.2 } else { 0.8 };
+                let noisy = albedo * (1.0 + rng.gen_range(-0.5, 0.5));
+                film.set(x, y, &[noisy; 3]);
+                film.set_aovs(x, y, &Aovs { normal: [0.0, 0.0, 1.0], depth: 1.0, albedo: [albedo as f32; 3], id: 0 });
+            }
+        }
+
+        let rmse = |film: &FilmSet| {
+            let error2: f64 = (0..h).flat_map(|y| (0..w).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let expected = if x < w / 2 { 0.2 } else { 0.8 };
+                    (film.beauty.get(x, y)[0] as f64 - expected).powi(2)
+                })
+                .sum();
+            (error2 / (w * h) as f64).sqrt()
+        };
+
+        let noisy = rmse(&film);
+        film.denoise();
+        assert!(rmse(&film) < noisy / 4.0, "error {} left after denoising from {}", rmse(&film), noisy);
+    }
+}
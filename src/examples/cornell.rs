@@ -14,12 +14,12 @@ fn cornell() -> Scene {
     camera.set_supersampling(2);
 
     // Add materials to the scene
-    let white = Material::plastic([0.9, 0.9, 0.9], [0.5, 0.7, 0.5], 0.25);
-    let r = Material::plastic([1.0, 0.0, 0.0], [0.5, 0.7, 0.5], 0.25);
-    let g = Material::plastic([0.0, 1.0, 0.0], [0.5, 0.7, 0.5], 0.25);
-    // let b = Material::plastic([0.0, 0.0, 1.0], [0.5, 0.4, 0.8], 0.25);
+    let white = scene.add_material(Material::plastic([0.9, 0.9, 0.9], [0.5, 0.7, 0.5], 0.25));
+    let r = scene.add_material(Material::plastic([1.0, 0.0, 0.0], [0.5, 0.7, 0.5], 0.25));
+    let g = scene.add_material(Material::plastic([0.0, 1.0, 0.0], [0.5, 0.7, 0.5], 0.25));
+    // let b = scene.add_material(Material::plastic([0.0, 0.0, 1.0], [0.5, 0.4, 0.8], 0.25));
     // let glass = scene.add_mirror_material([0.0, 0.0, 0.0]);
-    let glass = Material::glass([1.0, 0.7, 1.0], [0.7, 1.0, 0.7], 1.25);
+    let glass = scene.add_material(Material::glass([1.0, 0.7, 1.0], [0.7, 1.0, 0.7], 1.25));
 
     // Instantiate meshes to be shown in the scene
     let plane = scene.load_obj(meshes::path("plane").as_path()).unwrap();
@@ -12,7 +12,7 @@ fn playground() -> Scene {
     camera.look_at([0., 1., 4.], [-0.1, 1., 3.], [0., 1., 0.]);
     camera.set_supersampling(2);
 
-    let mat0 = Material::metal([0.9, 0.1, 0.9], [0.7, 1.0, 0.7], 0.25, 0.25);
+    let mat0 = scene.add_material(Material::metal([0.9, 0.1, 0.9], [0.7, 1.0, 0.7], 0.25, 0.25));
 
     let bunny = scene.load_obj(meshes::path("bunny").as_path()).unwrap();
 
@@ -16,10 +16,10 @@ fn simple() -> Scene {
     camera.set_supersampling(2);
 
     // Add materials to the scene
-    let mat0 = Material::plastic([0.7, 1.0, 0.7], [0.5, 0.7, 0.5], 0.25);
-    let mat1 = Material::plastic([0.5, 0.5, 0.5], [0.5, 0.7, 0.5], 0.25);
-    let mat2 = Material::plastic([1.0, 0.6, 0.1], [0.5, 0.7, 0.5], 0.25);
-    let mat3 = Material::plastic([0.7, 0.6, 1.0], [0.5, 0.4, 0.8], 0.25);
+    let mat0 = scene.add_material(Material::plastic([0.7, 1.0, 0.7], [0.5, 0.7, 0.5], 0.25));
+    let mat1 = scene.add_material(Material::plastic([0.5, 0.5, 0.5], [0.5, 0.7, 0.5], 0.25));
+    let mat2 = scene.add_material(Material::plastic([1.0, 0.6, 0.1], [0.5, 0.7, 0.5], 0.25));
+    let mat3 = scene.add_material(Material::plastic([0.7, 0.6, 1.0], [0.5, 0.4, 0.8], 0.25));
 
     // Instantiate meshes to be shown in the scene
     let smstdodeca = scene.load_obj(meshes::path("smstdodeca").as_path()).unwrap();
@@ -15,9 +15,9 @@ fn simplecows() -> Scene {
     scene.add_point_light([200.0, 202.0, 430.0], [0.8, 0.8, 0.8], [1.0, 0.0, 0.0]);
 
     // Materials
-    let stone = Material::metal([0.0, 0.0, 0.0], [0.7, 0.7, 0.7], 0.5, 0.5);
-    let grass = Material::plastic([0.1, 0.7, 0.1], [0.0, 0.0, 0.0], 0.0);
-    let hide = Material::plastic([0.84, 0.6, 0.53], [0.3, 0.3, 0.3], 0.2);
+    let stone = scene.add_material(Material::metal([0.0, 0.0, 0.0], [0.7, 0.7, 0.7], 0.5, 0.5));
+    let grass = scene.add_material(Material::plastic([0.1, 0.7, 0.1], [0.0, 0.0, 0.0], 0.0));
+    let hide = scene.add_material(Material::plastic([0.84, 0.6, 0.53], [0.3, 0.3, 0.3], 0.2));
 
     // Meshes
     let planemesh = scene.load_obj(meshes::path("plane").as_path()).unwrap();
@@ -15,10 +15,10 @@ fn simple() -> Scene {
     camera.set_supersampling(2);
 
     // Add materials to the scene
-    let mat0 = Material::glass([0.7, 1.0, 0.7], [0.5, 0.7, 0.5], 1.333);
-    let mat1 = Material::mirror([0.5, 0.5, 0.5]);
-    let mat2 = Material::glass([1.0, 0.6, 0.1], [0.7, 0.7, 1.0], 1.75);
-    let mat3 = Material::glass([0.7, 0.6, 1.0], [0.5, 0.4, 0.8], 1.5);
+    let mat0 = scene.add_material(Material::glass([0.7, 1.0, 0.7], [0.5, 0.7, 0.5], 1.333));
+    let mat1 = scene.add_material(Material::mirror([0.5, 0.5, 0.5]));
+    let mat2 = scene.add_material(Material::glass([1.0, 0.6, 0.1], [0.7, 0.7, 1.0], 1.75));
+    let mat3 = scene.add_material(Material::glass([0.7, 0.6, 1.0], [0.5, 0.4, 0.8], 1.5));
 
     // Instantiate meshes to be shown in the scene
     let smstdodeca = scene.load_obj(meshes::path("smstdodeca").as_path()).unwrap();
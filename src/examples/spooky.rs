@@ -16,10 +16,10 @@ fn spooky() -> Scene {
     let plane = scene.load_obj(obj_path("plane").as_path()).unwrap();
 
     // Add materials to the scene
-    let floor = Material::plastic([0.8, 0.7, 0.7], [0.0, 0.0, 0.0], 0.0);
-    let bone = Material::plastic([0.7, 0.7, 0.5], [0.3, 0.3, 0.3], 0.20);
-    let purple = Material::plastic([0.7, 0.6, 1.0], [0.8, 0.8, 0.8], 0.25);
-    let glass = Material::glass([0.7, 0.6, 1.0], [0.8, 0.8, 0.8], 1.333);
+    let floor = scene.add_material(Material::plastic([0.8, 0.7, 0.7], [0.0, 0.0, 0.0], 0.0));
+    let bone = scene.add_material(Material::plastic([0.7, 0.7, 0.5], [0.3, 0.3, 0.3], 0.20));
+    let purple = scene.add_material(Material::plastic([0.7, 0.6, 1.0], [0.8, 0.8, 0.8], 0.25));
+    let glass = scene.add_material(Material::glass([0.7, 0.6, 1.0], [0.8, 0.8, 0.8], 1.333));
 
     // Set up scene lights
     scene.add_point_light([-20.0, 15.0, 0.0], [0.9, 0.9, 0.9], [1.0, 0.0, 0.0]);
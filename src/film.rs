@@ -1,18 +1,27 @@
-use std::ops::{Index, IndexMut};
+use std::ops::{Index, IndexMut, DerefMut};
 use crate::img::*;
+use crate::{tonemap::Tonemap, filter::Filter, space::Color, progress::TileBounds};
+#[cfg(feature = "images")]
+use ::image::{RgbaImage, Rgba, ImageResult};
+#[cfg(feature = "denoise")]
+use crate::denoise::{Denoiser, NlMeans};
 
-/// Queriable store of pixels that will eventually be saved to a file. By
-/// default, pixel data is internally represented by a Vector of pixels arranged
-/// in row-major order.
-pub struct Film {
+/// Queriable store of pixels that will eventually be saved to a file. Pixels
+/// are kept in any buffer that dereferences to a `PixelBuffer` arranged in
+/// row-major order: by default an owned Vector, but also a borrowed slice or
+/// an external buffer such as an image that saves itself to disk.
+pub struct Film<B = Vec<Pixel>> {
     pub w: u32,
     pub h: u32,
     pub winv: f64,
     pub hinv: f64,
     pub aspect: f64,
 
+    /// Conversion from radiance to pixel colours. Defaults to a hard clamp
+    pub tonemap: Tonemap,
+
     /// Output pixel buffer that eventually gets written out to disk or wherever
-    output: Box<dyn PixelBuffer<Output = Pixel>>,
+    output: B,
 }
 
 impl Film {
@@ -21,47 +30,536 @@ impl Film {
     /// initialized to Black
     pub fn new(width: u32, height: u32) -> Film {
         let area = (width as usize) * (height as usize);
-        let output = Box::new(vec![[0, 0, 0, 0]; area]);
-        Film::new_with_output(width, height, output)
+        Film::with_buffer(width, height, vec![[0, 0, 0, 0]; area])
     }
+}
 
-    /// Create a new film with a pre-allocated box of data/ Use this when a
-    /// buffer for an image has already been allocated externally and you want
-    /// to avoid using extra memory for caching pixel data.
-    ///
-    /// Can use this with a Vec or slice of pixels.
+impl Film<Box<dyn PixelBuffer<Output = Pixel>>> {
+    /// Create a new film over a boxed buffer allocated elsewhere, such as an
+    /// image that knows how to save itself. See `with_buffer`.
+    pub fn new_with_output(width: u32, height: u32, output: Box<dyn PixelBuffer<Output = Pixel>>) -> Self {
+        Film::with_buffer(width, height, output)
+    }
+}
+
+impl<B> Film<B> where B: DerefMut, B::Target: PixelBuffer {
+    /// Create a new film over the given buffer. Use this when a buffer for an
+    /// image has already been allocated, such as a borrowed slice of pixels
+    /// shared with a front-end, to avoid using extra memory for caching pixel
+    /// data.
     ///
-    /// Assumes that that data has room for width * height * 4 bytes worth of
-    /// pixels.
-    pub fn new_with_output(width: u32, height: u32, output: Box<dyn PixelBuffer<Output = Pixel>>) -> Film {
+    /// Assumes that that data has room for width * height pixels.
+    pub fn with_buffer(width: u32, height: u32, output: B) -> Film<B> {
         Film {
             w: width,
             h: height,
             winv: 1. / width as f64,
             hinv: 1. / height as f64,
             aspect: width as f64 / height as f64,
+            tonemap: Tonemap::default(),
             output
         }
     }
+
+    /// The buffer that holds the film's pixels
+    #[inline]
+    pub fn buffer(&self) -> &B { &self.output }
+
+    /// Take back the buffer that holds the film's pixels
+    #[inline]
+    pub fn into_buffer(self) -> B { self.output }
+
+    /// Copy of the film's pixels as an RGBA image
+    #[cfg(feature = "images")]
+    pub fn to_image(&self) -> RgbaImage {
+        RgbaImage::from_fn(self.w, self.h, |x, y| Rgba(self[self.offset(x, y)]))
+    }
+
+    /// Encode the film's pixels to the given file, in the format given by its
+    /// extension: PNG, JPEG or BMP. JPEG drops the alpha channel.
+    #[cfg(feature = "images")]
+    pub fn write(&self, filename: &str) -> ImageResult<()> {
+        self.to_image().save(filename)
+    }
 }
 
-impl Index<usize> for Film {
+impl<B> Index<usize> for Film<B> where B: DerefMut, B::Target: PixelBuffer {
     type Output = Pixel;
     #[inline] fn index(&self, at: usize) -> &Self::Output { &self.output[at] }
 }
 
-impl IndexMut<usize> for Film {
+impl<B> IndexMut<usize> for Film<B> where B: DerefMut, B::Target: PixelBuffer {
     #[inline] fn index_mut(&mut self, at: usize) -> &mut Self::Output { &mut self.output[at] }
 }
 
-impl PixelBuffer for Film {
+impl<B> PixelBuffer for Film<B> where B: DerefMut, B::Target: PixelBuffer {
+    /// Encode the pixels to the given file with `write`, panicking on failure
+    #[cfg(feature = "images")]
+    fn save(&self, filename: &str) { self.write(filename).unwrap() }
+
+    /// Leave saving to the buffer, since there are no image encoders
+    #[cfg(not(feature = "images"))]
     #[inline] fn save(&self, filename: &str) { self.output.save(filename) }
 }
 
-impl Img for Film {
+/// A rectangular region of a larger film, rendered independently into its own
+/// pixel buffer. Coordinates are given in the space of the full film, so the
+/// camera samples the tile's pixels exactly as it would the film's.
+pub(crate) struct Tile {
+    /// Top-left corner of the tile in the film
+    pub x: u32,
+    pub y: u32,
+
+    /// Dimensions of the tile, in pixels. Tiles along the right and bottom
+    /// edges of the film may be smaller than the rest.
+    pub tile_w: u32,
+    pub tile_h: u32,
+
+    /// Dimensions of the full film
+    film_w: u32,
+    film_h: u32,
+
+    /// Radiance arriving at each pixel in the tile, in row-major order
+    radiance: Vec<[f64; 3]>,
+
+    /// Auxiliary pass values of each pixel in the tile, in row-major order.
+    /// Empty unless the film records them.
+    aovs: Vec<Aovs>,
+
+    /// Weighted sums of the samples splatted by a reconstruction filter onto
+    /// each pixel within its radius of the tile, in row-major order, followed
+    /// by the sum of the weights. Empty unless the filter spans more than a
+    /// pixel.
+    splats: Vec<[f64; 4]>,
+
+    /// Region of the film covered by the splats: the tile grown by the filter
+    /// radius, clipped to the film
+    splat_x: u32,
+    splat_y: u32,
+    splat_w: u32,
+    splat_h: u32
+}
+
+impl Tile {
+    /// Split a film of the given dimensions into tiles of at most size x size
+    /// pixels, in row-major order. Tiles record auxiliary passes if aovs is
+    /// set.
+    pub fn split(film_w: u32, film_h: u32, size: u32, aovs: bool) -> Vec<Tile> {
+        (0..film_h).step_by(size as usize)
+            .flat_map(|y| Tile::split_row(film_w, film_h, y, size, aovs))
+            .collect()
+    }
+
+    /// Split the row of tiles starting at scanline y of a film as with
+    /// `split`, in order from left to right
+    pub fn split_row(film_w: u32, film_h: u32, y: u32, size: u32, aovs: bool) -> Vec<Tile> {
+        (0..film_w).step_by(size as usize).map(|x| Tile::new(film_w, film_h, x, y, size, aovs)).collect()
+    }
+
+    /// The tile of at most size x size pixels with its top-left corner at the
+    /// given point of a film of the given dimensions
+    pub fn new(film_w: u32, film_h: u32, x: u32, y: u32, size: u32, aovs: bool) -> Tile {
+        let (tile_w, tile_h) = (size.min(film_w - x), size.min(film_h - y));
+        let radiance = vec![[0., 0., 0.]; (tile_w * tile_h) as usize];
+        let aovs = if aovs { vec![Aovs::none(); (tile_w * tile_h) as usize] } else { vec![] };
+        Tile { x, y, tile_w, tile_h, film_w, film_h, radiance, aovs, splats: vec![], splat_x: x, splat_y: y, splat_w: 0, splat_h: 0 }
+    }
+
+    /// Region of the film covered by the tile
+    #[inline]
+    pub fn bounds(&self) -> TileBounds {
+        TileBounds { x: self.x, y: self.y, w: self.tile_w, h: self.tile_h }
+    }
+
+    /// Make room to splat samples with the given filter onto the pixels
+    /// around the tile that it reaches
+    pub fn start_splats(&mut self, filter: &Filter) {
+        let margin = (filter.radius() - 0.5).max(0.).ceil() as u32;
+        self.splat_x = self.x.saturating_sub(margin);
+        self.splat_y = self.y.saturating_sub(margin);
+        self.splat_w = (self.x + self.tile_w + margin).min(self.film_w) - self.splat_x;
+        self.splat_h = (self.y + self.tile_h + margin).min(self.film_h) - self.splat_y;
+        self.splats = vec![[0.; 4]; (self.splat_w * self.splat_h) as usize]
+    }
+
+    /// Add a sample of the given radiance at the given point on the film, in
+    /// pixels from its top-left corner, to the pixels within the filter
+    /// radius, weighted by their distance from it
+    pub fn splat(&mut self, px: f64, py: f64, color: &Color, filter: &Filter) {
+        let radius = filter.radius();
+        let (x0, x1) = self.splat_range(px, radius, self.splat_x, self.splat_w);
+        let (y0, y1) = self.splat_range(py, radius, self.splat_y, self.splat_h);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let weight = filter.evaluate(px - (x as f64 + 0.5), py - (y as f64 + 0.5));
+                if weight == 0. { continue }
+                let splat = &mut self.splats[((y - self.splat_y) * self.splat_w + (x - self.splat_x)) as usize];
+                splat[0] += color.x * weight;
+                splat[1] += color.y * weight;
+                splat[2] += color.z * weight;
+                splat[3] += weight
+            }
+        }
+    }
+
+    /// Pixels along one axis whose centres lie within the given radius of p,
+    /// clipped to the given start and length of the splats
+    fn splat_range(&self, p: f64, radius: f64, start: u32, length: u32) -> (u32, u32) {
+        let from = (p - 0.5 - radius).ceil().max(start as f64) as u32;
+        let to = ((p - 0.5 + radius).floor() + 1.).min((start + length) as f64).max(from as f64) as u32;
+        (from, to)
+    }
+
+    /// Set the radiance of the tile's pixels to their filtered splats, which
+    /// only count the samples of this tile. See `Splats` for the whole film.
+    pub fn resolve_splats(&mut self) {
+        for y in self.y..(self.y + self.tile_h) {
+            for x in self.x..(self.x + self.tile_w) {
+                let splat = self.splats[((y - self.splat_y) * self.splat_w + (x - self.splat_x)) as usize];
+                if let Some(color) = resolve_splat(&splat) {
+                    let offset = self.offset(x, y);
+                    self.radiance[offset] = color
+                }
+            }
+        }
+    }
+
+    /// Copy the rendered pixels of this tile into the given film
+    pub fn merge_into(&self, film: &mut impl Img) {
+        for y in self.y..(self.y + self.tile_h) {
+            for x in self.x..(self.x + self.tile_w) {
+                let offset = self.offset(x, y);
+                film.set(x, y, &self.radiance[offset]);
+                if self.has_aovs() { film.set_aovs(x, y, &self.aovs[offset]) }
+            }
+        }
+    }
+
+    /// Rendered pixels of this tile in row-major order, tonemapped for display
+    pub fn pixels(&self, tonemap: &Tonemap) -> Vec<Pixel> {
+        self.radiance.iter().map(|color| {
+            let mut pixel = [0; 4];
+            set_pixel_color(&mut pixel, color, tonemap);
+            pixel
+        }).collect()
+    }
+}
+
+/// Tonemapped pixels of a square region of a film, captured on their own with
+/// `capture_hunk`. Front-ends without threads, such as the WebAssembly build,
+/// may hand out hunks to separate workers and show each as soon as it's done.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilmDataHunk {
+    /// Region of the film covered by the hunk
+    pub bounds: TileBounds,
+
+    /// Pixels in the hunk, in row-major order
+    pub data: Vec<Pixel>
+}
+
+impl FilmDataHunk {
+    /// Copy the pixels of the hunk to their place in the given film, which
+    /// must have the dimensions the hunk was captured for
+    pub fn copy_into<B>(&self, film: &mut Film<B>) where B: DerefMut, B::Target: PixelBuffer {
+        let bounds = self.bounds;
+        for (i, pixel) in self.data.iter().enumerate() {
+            let (x, y) = (bounds.x + i as u32 % bounds.w, bounds.y + i as u32 / bounds.w);
+            let offset = film.offset(x, y);
+            film[offset] = *pixel
+        }
+    }
+}
+
+impl Img for Tile {
+    #[inline] fn w(&self) -> u32 { self.film_w }
+    #[inline] fn h(&self) -> u32 { self.film_h }
+
+    #[inline]
+    fn offset(&self, x: u32, y: u32) -> usize {
+        debug_assert!(x >= self.x && x < self.x + self.tile_w);
+        debug_assert!(y >= self.y && y < self.y + self.tile_h);
+        (self.tile_w * (y - self.y) + (x - self.x)) as usize
+    }
+
+    #[inline]
+    fn set(&mut self, x: u32, y: u32, color: &[f64; 3]) {
+        let offset = self.offset(x, y);
+        self.radiance[offset] = *color
+    }
+
+    #[inline] fn has_aovs(&self) -> bool { !self.aovs.is_empty() }
+
+    #[inline]
+    fn set_aovs(&mut self, x: u32, y: u32, aovs: &Aovs) {
+        let offset = self.offset(x, y);
+        self.aovs[offset] = *aovs
+    }
+}
+
+impl<B> Img for Film<B> where B: DerefMut, B::Target: PixelBuffer {
+    #[inline] fn w(&self) -> u32{ self.w }
+    #[inline] fn h(&self) -> u32 { self.h }
+    #[inline] fn winv(&self) -> f64 { self.winv }
+    #[inline] fn hinv(&self) -> f64 { self.hinv }
+    #[inline] fn aspect(&self) -> f64 { self.aspect }
+
+    #[inline]
+    fn set(&mut self, x: u32, y: u32, color: &[f64; 3]) {
+        let offset = self.offset(x, y);
+        set_pixel_color(&mut self.output[offset], color, &self.tonemap)
+    }
+}
+
+/// Filtered radiance of a pixel with the given weighted sum and total weight
+/// of the samples splatted onto it, if any. Negative lobes of the filter may
+/// leave channels below zero, which are clamped.
+#[inline]
+fn resolve_splat(splat: &[f64; 4]) -> Option<[f64; 3]> {
+    if splat[3] <= 0. { return None }
+    Some([0, 1, 2].map(|i| (splat[i] / splat[3]).max(0.)))
+}
+
+/// Weighted sums of the samples splatted onto every pixel of a film by the
+/// tiles of a render, for filters that span more than a pixel. Tiles splat
+/// onto their neighbours' pixels, so each pixel is final once the tiles
+/// around it are added.
+pub(crate) struct Splats {
+    w: u32,
+    sums: Vec<[f64; 4]>
+}
+
+impl Splats {
+    pub fn new(w: u32, h: u32) -> Splats {
+        Splats { w, sums: vec![[0.; 4]; (w * h) as usize] }
+    }
+
+    /// Add the splats of the given tile
+    pub fn add(&mut self, tile: &Tile) {
+        for y in 0..tile.splat_h {
+            for x in 0..tile.splat_w {
+                let splat = &tile.splats[(y * tile.splat_w + x) as usize];
+                let sum = &mut self.sums[((tile.splat_y + y) * self.w + tile.splat_x + x) as usize];
+                for i in 0..4 { sum[i] += splat[i] }
+            }
+        }
+    }
+
+    /// Set each pixel of the given film that received splats to their
+    /// filtered radiance
+    pub fn write_into(&self, film: &mut impl Img) {
+        for (offset, splat) in self.sums.iter().enumerate() {
+            if let Some(color) = resolve_splat(splat) {
+                let (x, y) = (offset as u32 % self.w, offset as u32 / self.w);
+                film.set(x, y, &color)
+            }
+        }
+    }
+}
+
+/// Film that records the unclamped radiance arriving at each pixel as 32-bit
+/// floats, so that renders may be saved without banding and post-processed
+/// (e.g., with the OpenEXR and 16-bit PNG writers in the output module).
+pub struct HdrFilm {
+    pub w: u32,
+    pub h: u32,
+    pub winv: f64,
+    pub hinv: f64,
+    pub aspect: f64,
+
+    /// Linear RGB radiance of each pixel, in row-major order
+    data: Vec<[f32; 3]>
+}
+
+impl HdrFilm {
+    /// Initialize a new film with the given dimensions, with each pixel
+    /// initialized to Black
+    pub fn new(width: u32, height: u32) -> HdrFilm {
+        let area = (width as usize) * (height as usize);
+        HdrFilm {
+            w: width,
+            h: height,
+            winv: 1. / width as f64,
+            hinv: 1. / height as f64,
+            aspect: width as f64 / height as f64,
+            data: vec![[0., 0., 0.]; area]
+        }
+    }
+
+    /// Radiance of the pixel at the given x/y position
+    #[inline]
+    pub fn get(&self, x: u32, y: u32) -> [f32; 3] {
+        self.data[self.offset(x, y)]
+    }
+
+    /// Radiance of every pixel, in row-major order
+    pub fn data(&self) -> &[[f32; 3]] { &self.data }
+}
+
+impl Index<usize> for HdrFilm {
+    type Output = [f32; 3];
+    #[inline] fn index(&self, at: usize) -> &Self::Output { &self.data[at] }
+}
+
+impl IndexMut<usize> for HdrFilm {
+    #[inline] fn index_mut(&mut self, at: usize) -> &mut Self::Output { &mut self.data[at] }
+}
+
+impl Img for HdrFilm {
     #[inline] fn w(&self) -> u32{ self.w }
     #[inline] fn h(&self) -> u32 { self.h }
     #[inline] fn winv(&self) -> f64 { self.winv }
     #[inline] fn hinv(&self) -> f64 { self.hinv }
     #[inline] fn aspect(&self) -> f64 { self.aspect }
+
+    #[inline]
+    fn set(&mut self, x: u32, y: u32, color: &[f64; 3]) {
+        let offset = self.offset(x, y);
+        self.data[offset] = [color[0] as f32, color[1] as f32, color[2] as f32]
+    }
+}
+
+/// Auxiliary values recorded for a pixel alongside its radiance (arbitrary
+/// output values, or AOVs), describing the first surface seen through it.
+/// Averaged over the samples of the pixel, except for the object ID.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aovs {
+    /// World-space shading normal, facing the camera. Zero where the pixel
+    /// sees no surface
+    pub normal: [f32; 3],
+
+    /// Distance from the camera. Infinite where the pixel sees no surface
+    pub depth: f32,
+
+    /// Colour of the surface material, independent of lighting: the light it
+    /// scatters toward the camera when lit evenly by white light, or the
+    /// colour of the light of emitters. Zero where the pixel sees no surface
+    pub albedo: [f32; 3],
+
+    /// Index of the node at the top level of the scene that holds the
+    /// surface, as seen by the first sample of the pixel to hit one.
+    /// `Aovs::NO_ID` where the pixel sees no surface
+    pub id: u32
+}
+
+impl Aovs {
+    /// Object ID of pixels that see no surface
+    pub const NO_ID: u32 = u32::MAX;
+
+    /// Values for a pixel that sees no surface
+    pub fn none() -> Aovs {
+        Aovs { normal: [0.0; 3], depth: f32::INFINITY, albedo: [0.0; 3], id: Aovs::NO_ID }
+    }
+}
+
+/// Film that records the radiance of each pixel along with auxiliary passes:
+/// the normal, depth, albedo and object ID of the first surface seen through
+/// it. Denoisers use the normal and albedo passes to tell noise from surface
+/// detail; compositors use the depth and object ID passes to select and
+/// adjust parts of the image after rendering.
+pub struct FilmSet {
+    /// Unclamped radiance of each pixel, as for an HdrFilm
+    pub beauty: HdrFilm,
+
+    /// Auxiliary pass values of each pixel, in row-major order
+    aovs: Vec<Aovs>
+}
+
+impl FilmSet {
+    /// Initialize a new film set with the given dimensions, with each pixel
+    /// black and seeing no surface
+    pub fn new(width: u32, height: u32) -> FilmSet {
+        let area = (width as usize) * (height as usize);
+        FilmSet { beauty: HdrFilm::new(width, height), aovs: vec![Aovs::none(); area] }
+    }
+
+    /// Auxiliary pass values of the pixel at the given x/y position
+    #[inline]
+    pub fn aovs_at(&self, x: u32, y: u32) -> Aovs {
+        self.aovs[self.offset(x, y)]
+    }
+
+    /// Auxiliary pass values of every pixel, in row-major order
+    pub fn aovs(&self) -> &[Aovs] { &self.aovs }
+
+    /// Remove noise from the radiance with the built-in non-local means
+    /// filter, guided by the albedo and normal passes
+    #[cfg(feature = "denoise")]
+    pub fn denoise(&mut self) {
+        self.denoise_with(&NlMeans::default())
+    }
+
+    /// Replace the radiance with the output of the given denoiser
+    #[cfg(feature = "denoise")]
+    pub fn denoise_with(&mut self, denoiser: &impl Denoiser) {
+        let data = denoiser.denoise(self);
+        assert_eq!(data.len(), self.beauty.data.len());
+        self.beauty.data = data
+    }
+}
+
+impl Img for FilmSet {
+    #[inline] fn w(&self) -> u32 { self.beauty.w }
+    #[inline] fn h(&self) -> u32 { self.beauty.h }
+
+    #[inline]
+    fn set(&mut self, x: u32, y: u32, color: &[f64; 3]) {
+        self.beauty.set(x, y, color)
+    }
+
+    #[inline] fn has_aovs(&self) -> bool { true }
+
+    #[inline]
+    fn set_aovs(&mut self, x: u32, y: u32, aovs: &Aovs) {
+        let offset = self.offset(x, y);
+        self.aovs[offset] = *aovs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_covers_film() {
+        let tiles = Tile::split(70, 40, 32, false);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!((tiles[2].x, tiles[2].y, tiles[2].tile_w, tiles[2].tile_h), (64, 0, 6, 32));
+        assert_eq!((tiles[5].x, tiles[5].y, tiles[5].tile_w, tiles[5].tile_h), (64, 32, 6, 8));
+
+        let area: u32 = tiles.iter().map(|tile| tile.tile_w * tile.tile_h).sum();
+        assert_eq!(area, 70 * 40);
+    }
+
+    #[test]
+    fn hdr_film_keeps_radiance() {
+        let mut film = HdrFilm::new(4, 2);
+        film.set(3, 1, &[4.0, 0.5, -1.0]);
+        assert_eq!(film.get(3, 1), [4.0, 0.5, -1.0]);
+        assert_eq!(film[7], [4.0, 0.5, -1.0]);
+    }
+
+    #[test]
+    fn film_writes_borrowed_pixels() {
+        let mut pixels = vec![[0, 0, 0, 0]; 6];
+        let mut film = Film::with_buffer(3, 2, &mut pixels[..]);
+        film.set(1, 1, &[1.0, 0.0, 0.0]);
+        assert_eq!(film[4], [255, 0, 0, 255]);
+        drop(film);
+        assert_eq!(pixels[4], [255, 0, 0, 255]);
+    }
+
+    #[test]
+    #[cfg(feature = "images")]
+    fn film_encodes_images() {
+        let mut film = Film::new(3, 2);
+        film.set(2, 0, &[0.0, 1.0, 0.0]);
+        for ext in ["png", "bmp", "jpg"] {
+            let path = std::env::temp_dir().join(format!("lasgun-film-{}.{}", std::process::id(), ext));
+            film.write(path.to_str().unwrap()).unwrap();
+            let image = ::image::open(&path).unwrap().into_rgba8();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(image.dimensions(), (3, 2));
+            if ext != "jpg" { assert_eq!(image.get_pixel(2, 0).0, [0, 255, 0, 255]) }
+        }
+    }
 }
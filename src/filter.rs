@@ -0,0 +1,112 @@
+//! Pixel reconstruction filters, which weigh how much each sample contributes
+//! to the pixels around it. Filters wider than a pixel blend neighbouring
+//! samples for smoother antialiasing at the same number of samples.
+
+/// Filter over the samples near each pixel, selected with `Scene::set_filter`.
+/// Each weighs a sample by its distance from the centre of the pixel along x
+/// and y, out to the filter radius in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Equal weight for every sample within the radius. The default, with a
+    /// radius of half a pixel, averages the samples of each pixel alone.
+    Box { radius: f64 },
+
+    /// Weight falling linearly to zero at the radius
+    Triangle { radius: f64 },
+
+    /// Gaussian falloff with the given standard deviation, shifted down to
+    /// reach zero at the radius
+    Gaussian { radius: f64, sigma: f64 },
+
+    /// Mitchell-Netravali cubic with the given B and C parameters. Sharper
+    /// than the Gaussian, with slightly negative lobes that may ring around
+    /// high-contrast edges.
+    Mitchell { radius: f64, b: f64, c: f64 }
+}
+
+impl Default for Filter {
+    fn default() -> Self { Filter::Box { radius: 0.5 } }
+}
+
+impl Filter {
+    /// Triangle filter covering the neighbouring pixels
+    pub fn triangle() -> Filter { Filter::Triangle { radius: 1.0 } }
+
+    /// Gaussian filter with the parameters used by PBRT
+    pub fn gaussian() -> Filter { Filter::Gaussian { radius: 1.5, sigma: 0.5 } }
+
+    /// Mitchell filter with B = C = 1/3, as recommended by Mitchell and
+    /// Netravali
+    pub fn mitchell() -> Filter { Filter::Mitchell { radius: 2.0, b: 1. / 3., c: 1. / 3. } }
+
+    /// Distance from the centre of a pixel past which samples have no weight
+    #[inline]
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+                | Filter::Triangle { radius }
+                | Filter::Gaussian { radius, .. }
+                | Filter::Mitchell { radius, .. } => radius
+        }
+    }
+
+    /// Whether each sample only counts toward the pixel it was taken in, with
+    /// the same weight as the rest. Such filters need no splatting.
+    #[inline]
+    pub(crate) fn is_pixel_box(&self) -> bool {
+        matches!(*self, Filter::Box { radius } if radius <= 0.5)
+    }
+
+    /// Weight of a sample at the given offset in pixels from the centre of a
+    /// pixel
+    #[inline]
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        let radius = self.radius();
+        if dx.abs() >= radius || dy.abs() >= radius { return 0. }
+        self.evaluate_1d(dx.abs()) * self.evaluate_1d(dy.abs())
+    }
+
+    /// Weight along one axis at distance d within the radius
+    #[inline]
+    fn evaluate_1d(&self, d: f64) -> f64 {
+        match *self {
+            Filter::Box { .. } => 1.,
+            Filter::Triangle { radius } => radius - d,
+            Filter::Gaussian { radius, sigma } => {
+                let gaussian = |x: f64| (-x * x / (2. * sigma * sigma)).exp();
+                (gaussian(d) - gaussian(radius)).max(0.)
+            },
+            Filter::Mitchell { radius, b, c } => {
+                let x = 2. * d / radius;
+                if x > 1. {
+                    ((-b - 6. * c) * x * x * x + (6. * b + 30. * c) * x * x + (-12. * b - 48. * c) * x + (8. * b + 24. * c)) / 6.
+                } else {
+                    ((12. - 9. * b - 6. * c) * x * x * x + (-18. + 12. * b + 6. * c) * x * x + (6. - 2. * b)) / 6.
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filters_fall_off_to_radius() {
+        for filter in [Filter::default(), Filter::triangle(), Filter::gaussian(), Filter::mitchell()] {
+            let r = filter.radius();
+            assert!(filter.evaluate(0., 0.) > 0.);
+            assert_eq!(filter.evaluate(r, 0.), 0.);
+            assert_eq!(filter.evaluate(0., -r), 0.);
+            assert!(filter.evaluate(0.25 * r, 0.) <= filter.evaluate(0., 0.));
+        }
+        assert!(Filter::default().is_pixel_box());
+        assert!(!Filter::Box { radius: 1. }.is_pixel_box());
+
+        // The Mitchell filter is continuous where its pieces meet
+        let filter = Filter::mitchell();
+        assert!((filter.evaluate(1. - 1e-9, 0.) - filter.evaluate(1. + 1e-9, 0.)).abs() < 1e-6);
+        assert!(filter.evaluate(1.5, 0.) < 0.);
+    }
+}
@@ -0,0 +1,789 @@
+//! Importer for glTF 2.0 scenes (https://www.khronos.org/gltf/), the format
+//! most modelling tools export for real-time use. Reads both .gltf files with
+//! separate or embedded buffers and binary .glb files.
+//!
+//! Each glTF node becomes a nested `Aggregate` with the node's transformation,
+//! and each triangle primitive becomes a mesh. Metallic-roughness materials
+//! map onto the closest lasgun material:
+//!
+//! - Emissive materials become area lights
+//! - Materials with `KHR_materials_transmission` become glass
+//! - Mostly-metallic materials become metal, with an index of refraction that
+//!   reflects the base colour head-on
+//! - Everything else becomes plastic with a diffuse base colour
+//!
+//! Roughness is squared, as in the glTF reference shading model. The first
+//! camera in the scene replaces the scene camera, and `KHR_lights_punctual`
+//! point and directional lights are added to the scene. Spot lights become
+//! point lights. Animations, skins, morph targets, normal maps and the
+//! metallic-roughness and occlusion textures are ignored.
+
+use std::{fmt, fs, io, f64, path::{Path, PathBuf}, collections::HashMap};
+use cgmath::{Matrix4, Quaternion};
+use serde::Deserialize;
+
+use crate::{
+    space::*,
+    camera::Camera,
+    material::{Material, MaterialRef},
+    texture::{Param, TextureRef},
+    scene::{Scene, ObjRef, node::Aggregate}
+};
+use crate::shape::mesh::TriangleMesh;
+
+/// Problems reading a glTF file
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+
+    /// Structurally invalid or unsupported content, such as an accessor that
+    /// reads past the end of its buffer
+    Format(String)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Json(err) => write!(f, "invalid glTF: {}", err),
+            Error::Format(message) => write!(f, "invalid glTF: {}", message)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::Io(err) }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error { Error::Json(err) }
+}
+
+/// Import the .gltf or .glb file at the given path into the given scene.
+/// External buffers and images are loaded relative to its directory.
+pub fn load(scene: &mut Scene, path: &Path) -> Result<(), Error> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse(scene, &fs::read(path)?, dir)
+}
+
+/// Import the given contents of a .gltf or .glb file into the given scene.
+/// External buffers and images are resolved relative to the given directory.
+pub fn parse(scene: &mut Scene, bytes: &[u8], dir: &Path) -> Result<(), Error> {
+    let (json, bin) = split_glb(bytes)?;
+    let document: Document = serde_json::from_slice(json)?;
+    let buffers = document.buffers.iter()
+        .map(|buffer| read_buffer(buffer, bin, dir))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut importer = Importer {
+        document: &document,
+        buffers,
+        dir: dir.to_path_buf(),
+        scene,
+        meshes: HashMap::new(),
+        materials: HashMap::new(),
+        textures: HashMap::new(),
+        has_camera: false
+    };
+    importer.run()
+}
+
+// Subset of the glTF JSON schema. See
+// https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Document {
+    scene: Option<usize>,
+    scenes: Vec<SceneDef>,
+    nodes: Vec<Node>,
+    meshes: Vec<MeshDef>,
+    accessors: Vec<Accessor>,
+    buffer_views: Vec<BufferView>,
+    buffers: Vec<Buffer>,
+    materials: Vec<MaterialDef>,
+    textures: Vec<TextureDef>,
+    images: Vec<Image>,
+    cameras: Vec<CameraDef>,
+    extensions: DocumentExtensions
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SceneDef {
+    nodes: Vec<usize>
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Node {
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    camera: Option<usize>,
+    matrix: Option<[f64; 16]>,
+    translation: Option<[f64; 3]>,
+    rotation: Option<[f64; 4]>,
+    scale: Option<[f64; 3]>,
+    extensions: NodeExtensions
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct MeshDef {
+    primitives: Vec<Primitive>
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    mode: Option<u32>
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Accessor {
+    buffer_view: Option<usize>,
+    byte_offset: usize,
+    component_type: u32,
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    ty: String
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct BufferView {
+    buffer: usize,
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Buffer {
+    uri: Option<String>,
+    byte_length: usize
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct MaterialDef {
+    pbr_metallic_roughness: Pbr,
+    emissive_factor: [f64; 3],
+    extensions: MaterialExtensions
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct Pbr {
+    base_color_factor: [f64; 4],
+    base_color_texture: Option<TextureInfo>,
+    metallic_factor: f64,
+    roughness_factor: f64
+}
+
+impl Default for Pbr {
+    fn default() -> Self {
+        Pbr { base_color_factor: [1.0; 4], base_color_texture: None, metallic_factor: 1.0, roughness_factor: 1.0 }
+    }
+}
+
+#[derive(Deserialize)]
+struct TextureInfo {
+    index: usize
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct MaterialExtensions {
+    #[serde(rename = "KHR_materials_transmission")]
+    transmission: Option<Transmission>,
+    #[serde(rename = "KHR_materials_ior")]
+    ior: Option<Ior>,
+    #[serde(rename = "KHR_materials_emissive_strength")]
+    emissive_strength: Option<EmissiveStrength>
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Transmission {
+    transmission_factor: f64
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct Ior {
+    ior: f64
+}
+
+impl Default for Ior {
+    fn default() -> Self { Ior { ior: 1.5 } }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct EmissiveStrength {
+    emissive_strength: f64
+}
+
+impl Default for EmissiveStrength {
+    fn default() -> Self { EmissiveStrength { emissive_strength: 1.0 } }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct TextureDef {
+    source: Option<usize>
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Image {
+    uri: Option<String>,
+    buffer_view: Option<usize>
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct CameraDef {
+    #[serde(rename = "type")]
+    ty: String,
+    perspective: Option<Perspective>,
+    orthographic: Option<Orthographic>
+}
+
+#[derive(Deserialize)]
+struct Perspective {
+    yfov: f64
+}
+
+#[derive(Deserialize)]
+struct Orthographic {
+    ymag: f64
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct DocumentExtensions {
+    #[serde(rename = "KHR_lights_punctual")]
+    lights: Option<Lights>
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Lights {
+    lights: Vec<LightDef>
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct LightDef {
+    #[serde(rename = "type")]
+    ty: String,
+    color: [f64; 3],
+    intensity: f64
+}
+
+impl Default for LightDef {
+    fn default() -> Self { LightDef { ty: String::new(), color: [1.0; 3], intensity: 1.0 } }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct NodeExtensions {
+    #[serde(rename = "KHR_lights_punctual")]
+    light: Option<LightRef>
+}
+
+#[derive(Deserialize)]
+struct LightRef {
+    light: usize
+}
+
+struct Importer<'d, 's> {
+    document: &'d Document,
+    buffers: Vec<Vec<u8>>,
+    dir: PathBuf,
+    scene: &'s mut Scene,
+
+    /// Meshes and materials for each primitive of each glTF mesh imported so
+    /// far, shared between the nodes that use them
+    meshes: HashMap<usize, Vec<(ObjRef, MaterialRef)>>,
+    materials: HashMap<usize, MaterialRef>,
+    textures: HashMap<usize, Option<TextureRef>>,
+
+    /// Whether a glTF camera has replaced the scene camera
+    has_camera: bool
+}
+
+impl<'d, 's> Importer<'d, 's> {
+    fn run(&mut self) -> Result<(), Error> {
+        let document = self.document;
+        let roots: Vec<usize> = match document.scenes.get(document.scene.unwrap_or(0)) {
+            Some(scene) => scene.nodes.clone(),
+            // Without scenes, every node that isn't a child is a root
+            None => (0..document.nodes.len())
+                .filter(|i| !document.nodes.iter().any(|node| node.children.contains(i)))
+                .collect()
+        };
+
+        for root in roots {
+            if let Some(group) = self.node(root, Matrix4::identity(), 0)? {
+                self.scene.root.add_group(group)
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the aggregate for the given node and its descendants, given the
+    /// node-to-world transformation of its parent. Returns None for nodes
+    /// without geometry, such as cameras and lights, and for those that can't
+    /// be transformed, such as those scaled to nothing.
+    fn node(&mut self, index: usize, parent: Matrix4<f64>, depth: usize) -> Result<Option<Aggregate>, Error> {
+        let document = self.document;
+        let node = document.nodes.get(index).ok_or_else(|| format_error("node", index))?;
+        if depth > document.nodes.len() {
+            return Err(Error::Format("node hierarchy contains a cycle".into()))
+        }
+
+        let local = local_transform(node);
+        let world = parent * local;
+        let mut group = Aggregate::new();
+        group.transform = match local.invert() {
+            Some(inverse) => Transformation::new(local, inverse),
+            None => return Ok(None)
+        };
+
+        if let Some(camera) = node.camera {
+            self.camera(camera, &world)?
+        }
+        if let Some(light) = &node.extensions.light {
+            self.light(light.light, &world)?
+        }
+        if let Some(mesh) = node.mesh {
+            for (obj, material) in self.mesh(mesh)? {
+                group.add_obj_of(obj, material)
+            }
+        }
+        for child in node.children.iter() {
+            if let Some(child) = self.node(*child, world, depth + 1)? {
+                group.add_group(child)
+            }
+        }
+        Ok(if group.contents.is_empty() { None } else { Some(group) })
+    }
+
+    /// Use the first camera found as the scene camera
+    fn camera(&mut self, index: usize, world: &Matrix4<f64>) -> Result<(), Error> {
+        if self.has_camera { return Ok(()) }
+        let def = self.document.cameras.get(index).ok_or_else(|| format_error("camera", index))?;
+        let mut camera = match (def.ty.as_str(), &def.perspective, &def.orthographic) {
+            ("perspective", Some(perspective), _) => Camera::perspective(perspective.yfov.to_degrees()),
+            ("orthographic", _, Some(orthographic)) => Camera::orthographic(2.0 * orthographic.ymag),
+            _ => return Err(Error::Format(format!("invalid camera {}", index)))
+        };
+
+        // glTF cameras look down -z with +y up
+        let eye = world.transform_point(Point::new(0.0, 0.0, 0.0));
+        let look = eye + world.transform_vector(-Vector::unit_z()).normalize();
+        let up = world.transform_vector(Vector::unit_y());
+        camera.look_at(eye.into(), look.into(), up.into());
+        self.scene.set_camera(camera);
+        self.has_camera = true;
+        Ok(())
+    }
+
+    fn light(&mut self, index: usize, world: &Matrix4<f64>) -> Result<(), Error> {
+        let def = self.document.extensions.lights.as_ref()
+            .and_then(|lights| lights.lights.get(index))
+            .ok_or_else(|| format_error("light", index))?;
+
+        // Lasgun's point-light shading includes a factor of π that photometric
+        // intensities leave out
+        let intensity = [0, 1, 2].map(|c| def.color[c] * def.intensity / f64::consts::PI);
+        match def.ty.as_str() {
+            "directional" => {
+                let direction = world.transform_vector(-Vector::unit_z());
+                self.scene.add_directional_light(direction.into(), intensity, 0.0);
+            },
+            _ => {
+                let position = world.transform_point(Point::new(0.0, 0.0, 0.0));
+                self.scene.add_point_light(position.into(), intensity, [0.0, 0.0, 1.0]);
+            }
+        }
+        Ok(())
+    }
+
+    fn mesh(&mut self, index: usize) -> Result<Vec<(ObjRef, MaterialRef)>, Error> {
+        if let Some(primitives) = self.meshes.get(&index) {
+            return Ok(primitives.clone())
+        }
+
+        let document = self.document;
+        let mesh = document.meshes.get(index).ok_or_else(|| format_error("mesh", index))?;
+        let mut primitives = vec![];
+        for primitive in mesh.primitives.iter() {
+            // Only plain triangle lists are supported
+            if primitive.mode.unwrap_or(4) != 4 { continue }
+            let position = match primitive.attributes.get("POSITION") {
+                Some(position) => *position,
+                None => continue
+            };
+
+            let attribute = |importer: &Self, name: &str, components: usize| -> Result<Option<Vec<f64>>, Error> {
+                match primitive.attributes.get(name) {
+                    Some(accessor) => Ok(Some(importer.read(*accessor, components)?)),
+                    None => Ok(None)
+                }
+            };
+            let positions = self.read(position, 3)?;
+            let indices = match primitive.indices {
+                Some(indices) => self.read(indices, 1)?.iter().map(|i| *i as usize).collect(),
+                None => (0..positions.len() / 3).collect()
+            };
+
+            // glTF puts the origin of texture space at the top-left corner
+            let mut uvs = attribute(self, "TEXCOORD_0", 2)?;
+            if let Some(uvs) = &mut uvs {
+                for v in uvs.iter_mut().skip(1).step_by(2) { *v = 1.0 - *v }
+            }
+
+            let mesh = TriangleMesh {
+                positions,
+                normals: attribute(self, "NORMAL", 3)?,
+                uvs,
+                colors: attribute(self, "COLOR_0", 3)?,
+                indices
+            }.validate().ok_or_else(|| Error::Format(format!("invalid indices in mesh {}", index)))?;
+
+            let obj = self.scene.add_mesh(mesh);
+            let material = match primitive.material {
+                Some(material) => self.material(material)?,
+                None => MaterialRef::default()
+            };
+            primitives.push((obj, material))
+        }
+
+        self.meshes.insert(index, primitives.clone());
+        Ok(primitives)
+    }
+
+    fn material(&mut self, index: usize) -> Result<MaterialRef, Error> {
+        if let Some(material) = self.materials.get(&index) {
+            return Ok(*material)
+        }
+
+        let def = self.document.materials.get(index).ok_or_else(|| format_error("material", index))?;
+        let pbr = &def.pbr_metallic_roughness;
+        let base = [pbr.base_color_factor[0], pbr.base_color_factor[1], pbr.base_color_factor[2]];
+        let roughness = pbr.roughness_factor * pbr.roughness_factor;
+        let strength = def.extensions.emissive_strength.as_ref().map_or(1.0, |e| e.emissive_strength);
+        let emission = def.emissive_factor.map(|c| c * strength);
+        let transmission = def.extensions.transmission.as_ref().map_or(0.0, |t| t.transmission_factor);
+
+        let material = if emission != [0.0; 3] {
+            Material::emissive(emission)
+        } else if transmission > 0.0 {
+            let eta = def.extensions.ior.as_ref().map_or(1.5, |ior| ior.ior);
+            Material::glass([1.0; 3], base, eta)
+        } else if pbr.metallic_factor >= 0.5 {
+            // Index of refraction whose Fresnel reflectance at normal incidence
+            // matches the base colour
+            let eta = base.map(|f0| {
+                let r = f0.clamp(0.0, 0.99).sqrt();
+                (1.0 + r) / (1.0 - r)
+            });
+            Material::metal(eta, [0.0; 3], roughness, roughness)
+        } else {
+            let kd = match &pbr.base_color_texture {
+                Some(info) => self.texture(info.index)?.map(Param::from),
+                None => None
+            }.unwrap_or(Param::from(base));
+
+            // Dielectric Fresnel reflectance provides the 4% specular of the
+            // glTF reference model
+            Material::plastic(kd, [1.0; 3], roughness)
+        };
+
+        let material = self.scene.add_material(material);
+        self.materials.insert(index, material);
+        Ok(material)
+    }
+
+    fn texture(&mut self, index: usize) -> Result<Option<TextureRef>, Error> {
+        if let Some(texture) = self.textures.get(&index) {
+            return Ok(*texture)
+        }
+
+        let document = self.document;
+        let image = document.textures.get(index)
+            .ok_or_else(|| format_error("texture", index))?
+            .source
+            .and_then(|source| document.images.get(source));
+        let texture = match image {
+            Some(Image { uri: Some(uri), .. }) if uri.starts_with("data:") =>
+                self.image_texture(&decode_data_uri(uri)?),
+            Some(Image { uri: Some(uri), .. }) => {
+                let path = self.dir.join(uri);
+                self.image_file_texture(&path)
+            },
+            Some(Image { buffer_view: Some(view), .. }) => {
+                let bytes = self.view(*view)?.to_vec();
+                self.image_texture(&bytes)
+            },
+            _ => None
+        };
+
+        self.textures.insert(index, texture);
+        Ok(texture)
+    }
+
+    #[cfg(feature = "bin")]
+    fn image_file_texture(&mut self, path: &Path) -> Option<TextureRef> {
+        self.scene.load_texture(path).ok()
+    }
+
+    #[cfg(feature = "bin")]
+    fn image_texture(&mut self, bytes: &[u8]) -> Option<TextureRef> {
+        let rgba = ::image::load_from_memory(bytes).ok()?.into_rgba8();
+        let (width, height) = rgba.dimensions();
+        let texture = crate::texture::ImageTexture::from_rgba(width, height, &rgba.into_raw());
+        Some(self.scene.add_texture(Box::new(texture)))
+    }
+
+    /// Image textures cannot be loaded without the bin feature
+    #[cfg(not(feature = "bin"))]
+    fn image_file_texture(&mut self, _path: &Path) -> Option<TextureRef> {
+        None
+    }
+
+    #[cfg(not(feature = "bin"))]
+    fn image_texture(&mut self, _bytes: &[u8]) -> Option<TextureRef> {
+        None
+    }
+
+    /// Bytes of the given buffer view
+    fn view(&self, index: usize) -> Result<&[u8], Error> {
+        let view = self.document.buffer_views.get(index).ok_or_else(|| format_error("buffer view", index))?;
+        self.buffers.get(view.buffer)
+            .and_then(|buffer| buffer.get(view.byte_offset..view.byte_offset + view.byte_length))
+            .ok_or_else(|| Error::Format(format!("buffer view {} is out of bounds", index)))
+    }
+
+    /// Read the given accessor as a flat array of floats with the given number
+    /// of components per element. Wider elements are truncated, such as the
+    /// alpha channel of RGBA colours.
+    fn read(&self, index: usize, components: usize) -> Result<Vec<f64>, Error> {
+        let accessor = self.document.accessors.get(index).ok_or_else(|| format_error("accessor", index))?;
+        let width = match accessor.ty.as_str() {
+            "SCALAR" => 1, "VEC2" => 2, "VEC3" => 3, "VEC4" => 4,
+            ty => return Err(Error::Format(format!("unsupported accessor type {}", ty)))
+        };
+        let size = match accessor.component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            ty => return Err(Error::Format(format!("unsupported component type {}", ty)))
+        };
+        if width < components {
+            return Err(Error::Format(format!("accessor {} has too few components", index)))
+        }
+
+        // Accessors without a buffer view are all zeros
+        let view = match accessor.buffer_view {
+            Some(view) => view,
+            None => return Ok(vec![0.0; accessor.count * components])
+        };
+        let bytes = self.view(view)?;
+        let stride = self.document.buffer_views[view].byte_stride.unwrap_or(size * width);
+        if accessor.count > 0 && accessor.byte_offset + stride * (accessor.count - 1) + size * width > bytes.len() {
+            return Err(Error::Format(format!("accessor {} is out of bounds", index)))
+        }
+
+        let mut values = Vec::with_capacity(accessor.count * components);
+        for i in 0..accessor.count {
+            for c in 0..components {
+                let at = accessor.byte_offset + i * stride + c * size;
+                let b = &bytes[at..at + size];
+                let value = match accessor.component_type {
+                    5120 => b[0] as i8 as f64,
+                    5121 => b[0] as f64,
+                    5122 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    5123 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+                };
+                values.push(if accessor.normalized { normalize(value, accessor.component_type) } else { value })
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// Map a normalized integer component onto [0, 1] or [-1, 1]
+#[inline]
+fn normalize(value: f64, component_type: u32) -> f64 {
+    match component_type {
+        5120 => (value / 127.0).max(-1.0),
+        5121 => value / 255.0,
+        5122 => (value / 32767.0).max(-1.0),
+        5123 => value / 65535.0,
+        5125 => value / u32::MAX as f64,
+        _ => value
+    }
+}
+
+fn format_error(kind: &str, index: usize) -> Error {
+    Error::Format(format!("missing {} {}", kind, index))
+}
+
+/// Transformation of the given node relative to its parent, either as a
+/// matrix or as translation, rotation and scale
+fn local_transform(node: &Node) -> Matrix4<f64> {
+    if let Some(m) = node.matrix {
+        return Matrix4::new(
+            m[0], m[1], m[2], m[3],
+            m[4], m[5], m[6], m[7],
+            m[8], m[9], m[10], m[11],
+            m[12], m[13], m[14], m[15])
+    }
+    let t = node.translation.unwrap_or([0.0; 3]);
+    let r = node.rotation.unwrap_or([0.0, 0.0, 0.0, 1.0]);
+    let s = node.scale.unwrap_or([1.0; 3]);
+    Matrix4::from_translation(t.into())
+        * Matrix4::from(Quaternion::new(r[3], r[0], r[1], r[2]))
+        * Matrix4::from_nonuniform_scale(s[0], s[1], s[2])
+}
+
+/// Split a binary .glb container into its JSON and binary chunks. Plain
+/// .gltf JSON is returned as is.
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), Error> {
+    if !bytes.starts_with(b"glTF") { return Ok((bytes, None)) }
+    let u32_at = |at: usize| bytes.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    let invalid = || Error::Format("truncated .glb file".into());
+
+    let mut json = None;
+    let mut bin = None;
+    let mut at = 12;
+    while at < bytes.len() {
+        let length = u32_at(at).ok_or_else(invalid)?;
+        let kind = u32_at(at + 4).ok_or_else(invalid)?;
+        let chunk = bytes.get(at + 8..at + 8 + length).ok_or_else(invalid)?;
+        match kind {
+            0x4E4F534A => json = Some(chunk),
+            0x004E4942 => bin = Some(chunk),
+            _ => ()
+        }
+        at += 8 + length;
+    }
+    Ok((json.ok_or_else(invalid)?, bin))
+}
+
+fn read_buffer(buffer: &Buffer, bin: Option<&[u8]>, dir: &Path) -> Result<Vec<u8>, Error> {
+    let bytes = match &buffer.uri {
+        Some(uri) if uri.starts_with("data:") => decode_data_uri(uri)?,
+        Some(uri) => fs::read(dir.join(uri))?,
+        None => bin.ok_or_else(|| Error::Format("missing binary chunk".into()))?.to_vec()
+    };
+    if bytes.len() < buffer.byte_length {
+        return Err(Error::Format("buffer is shorter than its declared length".into()))
+    }
+    Ok(bytes)
+}
+
+/// Contents of a base64 `data:` URI
+fn decode_data_uri(uri: &str) -> Result<Vec<u8>, Error> {
+    let invalid = || Error::Format("invalid data URI".into());
+    let (header, data) = uri.split_at(uri.find(',').ok_or_else(invalid)?);
+    if !header.ends_with(";base64") { return Err(invalid()) }
+
+    let mut bytes = Vec::with_capacity(data.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in data[1..].bytes().filter(|c| *c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err(invalid())
+        };
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scene::node::SceneNode;
+
+    // One triangle in a node moved up by 1, under a camera and a point light.
+    // The buffer holds three float positions followed by three short indices.
+    const GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0, 2, 3] }],
+        "nodes": [
+            { "translation": [0, 1, 0], "children": [1] },
+            { "mesh": 0 },
+            { "camera": 0, "translation": [0, 0, 5] },
+            { "extensions": { "KHR_lights_punctual": { "light": 0 } }, "translation": [0, 4, 0] }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1, "material": 0 }] }],
+        "materials": [{ "pbrMetallicRoughness": { "baseColorFactor": [0.8, 0.1, 0.1, 1], "metallicFactor": 0 } }],
+        "cameras": [{ "type": "perspective", "perspective": { "yfov": 0.8, "znear": 0.1 } }],
+        "extensions": { "KHR_lights_punctual": { "lights": [{ "type": "point", "intensity": 10 }] } },
+        "buffers": [{ "byteLength": 44, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAABAAIAAAA=" }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 6 }
+        ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" },
+            { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }
+        ]
+    }"#;
+
+    #[test]
+    fn imports_hierarchy() {
+        let mut scene = Scene::new();
+        parse(&mut scene, GLTF.as_bytes(), Path::new("")).unwrap();
+        assert_eq!(scene.lights().len(), 1);
+        assert_eq!(scene.root.contents.len(), 1);
+
+        let parent = match &scene.root.contents[0] {
+            SceneNode::Group(group) => group,
+            _ => panic!("expected a group")
+        };
+        assert_eq!(parent.transform.transform_point(Point::new(0.0, 0.0, 0.0)), Point::new(0.0, 1.0, 0.0));
+        match &parent.contents[0] {
+            SceneNode::Group(child) => match child.contents[0] {
+                SceneNode::Mesh(obj, Some(material)) if matches!(scene.material(material), Material::Plastic(_)) =>
+                    assert_eq!(scene.mesh(obj).unwrap().triangles.positions[3..6], [1.0, 0.0, 0.0]),
+                _ => panic!("expected a plastic mesh")
+            },
+            _ => panic!("expected a group")
+        }
+    }
+
+    #[test]
+    fn decodes_base64() {
+        let bytes = decode_data_uri("data:application/octet-stream;base64,bGFzZ3Vu").unwrap();
+        assert_eq!(bytes, b"lasgun");
+    }
+}
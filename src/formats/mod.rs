@@ -0,0 +1,7 @@
+//! Importers for scene files written for other renderers
+
+pub mod pbrt;
+pub mod gltf;
+pub mod nanovdb;
+pub mod ply;
+pub mod stl;
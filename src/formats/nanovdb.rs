@@ -0,0 +1,320 @@
+//! Reader for density grids in the NanoVDB format (.nvdb), the flat form of
+//! OpenVDB grids, for rendering smoke and clouds simulated in Houdini or
+//! Blender as a `GridMedium`. OpenVDB files (.vdb) can be converted with the
+//! `nanovdb_convert` tool that comes with OpenVDB.
+//!
+//! Only uncompressed files of version 32 holding float grids are read, such
+//! as the `density` grid of a fog volume. Grids must map to the world by a
+//! scale and translation; rotated and sheared grids are rejected. Values in
+//! the leaves of the tree and the active tiles of its two levels of internal
+//! nodes are read; tiles of the root, which cover 4096³ voxels, are not.
+
+use std::{convert::TryInto, fmt, fs, io, path::Path};
+
+use crate::{space::*, medium::DensityGrid};
+
+/// First seven bytes of the magic numbers of NanoVDB files and grids,
+/// "NanoVDB" followed by a digit
+const MAGIC_PREFIX: &[u8] = b"NanoVDB";
+
+/// Major version of the layout read here
+const MAJOR_VERSION: u32 = 32;
+
+/// Bounds on the voxel indices of grids read, so that corrupt headers fail
+/// rather than overflow or exhaust memory: indices within ±2³⁰, spanning at
+/// most 2²⁴ bricks of 8³ voxels, e.g., 2048³ voxels
+const MAX_INDEX: i32 = 1 << 30;
+const MAX_BRICKS: usize = 1 << 24;
+
+/// Sizes of the file header and of the metadata ahead of each grid
+const FILE_HEADER_SIZE: usize = 16;
+const FILE_METADATA_SIZE: usize = 176;
+
+/// Offsets within a grid of the parts read, and the type tag of float grids
+const GRID_MAP_MATRIX: usize = 384;
+const GRID_MAP_TRANSLATION: usize = 528;
+const GRID_TYPE: usize = 636;
+const GRID_DATA_SIZE: usize = 672;
+const GRID_TYPE_FLOAT: u32 = 1;
+
+/// Layout of the nodes of float grids: the size of each, where the values or
+/// table of tiles start, and the log2 of their width in children
+const LEAF_SIZE: usize = 2144;
+const LEAF_VALUES: usize = 96;
+const LOWER_SIZE: usize = 33856;
+const LOWER_LOG2DIM: u32 = 4;
+const LOWER_TABLE: usize = 1088;
+const UPPER_SIZE: usize = 270400;
+const UPPER_LOG2DIM: u32 = 5;
+const UPPER_TABLE: usize = 8256;
+
+/// Problems reading a NanoVDB file
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+
+    /// Malformed or unsupported content, such as a compressed grid
+    Format(String)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Format(message) => write!(f, "invalid NanoVDB file: {}", message)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::Io(err) }
+}
+
+/// Read the float grid of the given name from the .nvdb file at the given
+/// path, or the first float grid if no name is given
+pub fn load(path: &Path, name: Option<&str>) -> Result<DensityGrid, Error> {
+    parse(&fs::read(path)?, name)
+}
+
+/// Read the float grid of the given name from the given contents of a .nvdb
+/// file, or the first float grid if no name is given
+pub fn parse(bytes: &[u8], name: Option<&str>) -> Result<DensityGrid, Error> {
+    if bytes.get(..MAGIC_PREFIX.len()) != Some(MAGIC_PREFIX) {
+        return Err(format_error("not a NanoVDB file"))
+    }
+    let major = read_u32(bytes, 8)? >> 21;
+    if major != MAJOR_VERSION {
+        return Err(format_error(format!("version {} is not supported, only {}", major, MAJOR_VERSION)))
+    }
+    let count = read_u16(bytes, 12)?;
+    if read_u16(bytes, 14)? != 0 {
+        return Err(format_error("compressed files are not supported; save without compression"))
+    }
+
+    let mut offset = FILE_HEADER_SIZE;
+    for _ in 0..count {
+        let metadata = slice(bytes, offset, FILE_METADATA_SIZE)?;
+        let size = read_u64(metadata, 8)? as usize;
+        let grid_type = read_u32(metadata, 32)?;
+        let index_min = [read_i32(metadata, 88)?, read_i32(metadata, 92)?, read_i32(metadata, 96)?];
+        let index_max = [read_i32(metadata, 100)?, read_i32(metadata, 104)?, read_i32(metadata, 108)?];
+        let name_size = read_u32(metadata, 136)? as usize;
+        let codec = read_u16(metadata, 168)?;
+        offset += FILE_METADATA_SIZE;
+
+        let grid_name = slice(bytes, offset, name_size)?;
+        let grid_name = String::from_utf8_lossy(grid_name.split(|c| *c == 0).next().unwrap_or(&[]));
+        offset += name_size;
+        let grid = slice(bytes, offset, size)?;
+        offset += size;
+
+        if name.map_or(grid_type != GRID_TYPE_FLOAT, |name| name != grid_name) { continue }
+        if grid_type != GRID_TYPE_FLOAT {
+            return Err(format_error(format!("grid \"{}\" does not hold floats", grid_name)))
+        }
+        if codec != 0 {
+            return Err(format_error(format!("grid \"{}\" is compressed; save without compression", grid_name)))
+        }
+        return read_grid(grid, index_min, index_max)
+    }
+    Err(format_error(match name {
+        Some(name) => format!("no grid named \"{}\"", name),
+        None => "no float grid".to_string()
+    }))
+}
+
+/// Read the voxels of the given float grid between the given indices
+fn read_grid(grid: &[u8], min: [i32; 3], max: [i32; 3]) -> Result<DensityGrid, Error> {
+    if read_u32(grid, GRID_TYPE)? != GRID_TYPE_FLOAT {
+        return Err(format_error("grid does not hold floats"))
+    }
+    let mut matrix = [0.0; 9];
+    for (i, value) in matrix.iter_mut().enumerate() {
+        *value = read_f64(grid, GRID_MAP_MATRIX + 8 * i)?
+    }
+    if [1, 2, 3, 5, 6, 7].iter().any(|i| matrix[*i] != 0.0) {
+        return Err(format_error("rotated or sheared grids are not supported"))
+    }
+    let translation = Vector::new(
+        read_f64(grid, GRID_MAP_TRANSLATION)?,
+        read_f64(grid, GRID_MAP_TRANSLATION + 8)?,
+        read_f64(grid, GRID_MAP_TRANSLATION + 16)?
+    );
+
+    let mut bricks = Some(1usize);
+    for i in 0..3 {
+        let range = -MAX_INDEX..=MAX_INDEX;
+        if !range.contains(&min[i]) || !range.contains(&max[i]) {
+            return Err(format_error("grid bounds are out of range"))
+        }
+        let extent = (max[i].div_euclid(8) - min[i].div_euclid(8) + 1).max(0) as usize;
+        bricks = bricks.and_then(|n| n.checked_mul(extent));
+    }
+    if !bricks.is_some_and(|n| n <= MAX_BRICKS) {
+        return Err(format_error("grid is too large"))
+    }
+
+    let mut density = DensityGrid::empty(min, max);
+    density.set_transform(Vector::new(matrix[0], matrix[4], matrix[8]), translation);
+
+    // Nodes of each level are stored one after the other, at offsets from
+    // the start of the tree
+    let tree = GRID_DATA_SIZE;
+    let nodes = |level: usize, size: usize| -> Result<Vec<&[u8]>, Error> {
+        let start = read_u64(grid, tree + 8 * level)? as usize;
+        let count = read_u32(grid, tree + 32 + 4 * level)? as usize;
+        (0..count).map(|i| {
+            let offset = i.checked_mul(size).and_then(|offset| offset.checked_add(start)?.checked_add(tree));
+            slice(grid, offset.ok_or_else(|| format_error("unexpected end of file"))?, size)
+        }).collect()
+    };
+
+    // Tiles first, so that leaves within their regions take precedence
+    for (level, size, log2dim, table) in [(2, UPPER_SIZE, UPPER_LOG2DIM, UPPER_TABLE), (1, LOWER_SIZE, LOWER_LOG2DIM, LOWER_TABLE)] {
+        let child_log2 = if level == 2 { LOWER_LOG2DIM + 3 } else { 3 };
+        let mask = !((1i32 << (log2dim + child_log2)) - 1);
+        let tiles = 1usize << (3 * log2dim);
+        for node in nodes(level, size)? {
+            let origin = [read_i32(node, 0)? & mask, read_i32(node, 4)? & mask, read_i32(node, 8)? & mask];
+            let (value_mask, child_mask) = (32, 32 + tiles / 8);
+            for n in 0..tiles {
+                let bit = |at: usize| node[at + n / 8] >> (n % 8) & 1 == 1;
+                if !bit(value_mask) || bit(child_mask) { continue }
+                let value = read_f32(node, table + 8 * n)?;
+                let dim = (1 << log2dim) - 1;
+                let local = [(n >> (2 * log2dim)) as i32, ((n >> log2dim) & dim) as i32, (n & dim) as i32];
+                // Origins come from the file; skip tiles outside the grid
+                let tile = [0, 1, 2].map(|i| origin[i] as i64 + ((local[i] as i64) << child_log2));
+                let width = 1 << child_log2;
+                if (0..3).any(|i| tile[i] > max[i] as i64 || tile[i] + width <= min[i] as i64) { continue }
+                density.fill(tile.map(|index| index as i32), width as i32, value.max(0.0));
+            }
+        }
+    }
+
+    let mut values = [0.0; 512];
+    for leaf in nodes(0, LEAF_SIZE)? {
+        let origin = [read_i32(leaf, 0)? & !7, read_i32(leaf, 4)? & !7, read_i32(leaf, 8)? & !7];
+        if (0..3).any(|i| origin[i] > max[i] || origin[i] + 8 <= min[i]) { continue }
+        for (n, value) in values.iter_mut().enumerate() {
+            *value = read_f32(leaf, LEAF_VALUES + 4 * n)?.max(0.0)
+        }
+        density.set_brick(origin, &values);
+    }
+    Ok(density)
+}
+
+fn format_error(message: impl Into<String>) -> Error {
+    Error::Format(message.into())
+}
+
+/// The given number of bytes at the given offset
+fn slice(bytes: &[u8], offset: usize, len: usize) -> Result<&[u8], Error> {
+    offset.checked_add(len)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| format_error("unexpected end of file"))
+}
+
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N], Error> {
+    Ok(slice(bytes, offset, N)?.try_into().unwrap())
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, Error> { read(bytes, offset).map(u16::from_le_bytes) }
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, Error> { read(bytes, offset).map(u32::from_le_bytes) }
+fn read_i32(bytes: &[u8], offset: usize) -> Result<i32, Error> { read(bytes, offset).map(i32::from_le_bytes) }
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, Error> { read(bytes, offset).map(u64::from_le_bytes) }
+fn read_f32(bytes: &[u8], offset: usize) -> Result<f32, Error> { read(bytes, offset).map(f32::from_le_bytes) }
+fn read_f64(bytes: &[u8], offset: usize) -> Result<f64, Error> { read(bytes, offset).map(f64::from_le_bytes) }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Write the given bytes into the buffer at the given offset, growing it
+    /// as needed
+    fn put(buffer: &mut Vec<u8>, offset: usize, bytes: &[u8]) {
+        if buffer.len() < offset + bytes.len() { buffer.resize(offset + bytes.len(), 0) }
+        buffer[offset..offset + bytes.len()].copy_from_slice(bytes)
+    }
+
+    /// File with one float grid named "density" of half-unit voxels offset by
+    /// (1, 2, 3): a leaf at (8, 0, 0) whose voxels are their x index within
+    /// it, and a constant tile of 2 over the 8³ voxels at (0, 8, 0)
+    fn file() -> Vec<u8> {
+        let mut grid = vec![];
+        put(&mut grid, 0, b"NanoVDB0");
+        put(&mut grid, GRID_TYPE, &GRID_TYPE_FLOAT.to_le_bytes());
+        for (i, value) in [0.5, 0.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.0, 0.5].iter().enumerate() {
+            put(&mut grid, GRID_MAP_MATRIX + 8 * i, &f64::to_le_bytes(*value));
+        }
+        for (i, value) in [1.0, 2.0, 3.0].iter().enumerate() {
+            put(&mut grid, GRID_MAP_TRANSLATION + 8 * i, &f64::to_le_bytes(*value));
+        }
+
+        // One node of each level, upper then lower then leaf
+        let (upper, lower, leaf) = (64, 64 + UPPER_SIZE, 64 + UPPER_SIZE + LOWER_SIZE);
+        let tree = GRID_DATA_SIZE;
+        for (level, offset) in [leaf, lower, upper].iter().enumerate() {
+            put(&mut grid, tree + 8 * level, &(*offset as u64).to_le_bytes());
+            put(&mut grid, tree + 32 + 4 * level, &1u32.to_le_bytes());
+        }
+        let tile = 1 << LOWER_LOG2DIM; // (0, 1, 0) within the node
+        put(&mut grid, tree + lower + 32 + tile / 8, &[1 << (tile % 8)]);
+        put(&mut grid, tree + lower + LOWER_TABLE + 8 * tile, &2f32.to_le_bytes());
+        put(&mut grid, tree + leaf, &8i32.to_le_bytes());
+        for n in 0..512 {
+            put(&mut grid, tree + leaf + LEAF_VALUES + 4 * n, &((n >> 6) as f32).to_le_bytes());
+        }
+
+        let mut file = vec![];
+        put(&mut file, 0, b"NanoVDB0");
+        put(&mut file, 8, &(MAJOR_VERSION << 21).to_le_bytes());
+        put(&mut file, 12, &1u16.to_le_bytes());
+        let metadata = FILE_HEADER_SIZE;
+        put(&mut file, metadata + 8, &(grid.len() as u64).to_le_bytes());
+        put(&mut file, metadata + 32, &GRID_TYPE_FLOAT.to_le_bytes());
+        for (i, value) in [0, 0, 0, 15, 15, 7].iter().enumerate() {
+            put(&mut file, metadata + 88 + 4 * i, &i32::to_le_bytes(*value));
+        }
+        put(&mut file, metadata + 136, &8u32.to_le_bytes());
+        put(&mut file, metadata + FILE_METADATA_SIZE, b"density\0");
+        file.extend_from_slice(&grid);
+        file
+    }
+
+    #[test]
+    fn reads_leaves_and_tiles() {
+        let grid = parse(&file(), Some("density")).unwrap();
+        assert_eq!(grid.voxel([11, 4, 2]), 3.0);
+        assert_eq!(grid.voxel([3, 12, 5]), 2.0);
+        assert_eq!(grid.voxel([3, 3, 3]), 0.0);
+        assert_eq!(grid.max_density(), 7.0);
+
+        // Voxel (11, 4, 2) is at (1 + 5.5, 2 + 2, 3 + 1) in the world
+        assert!((grid.density(Point::new(6.5, 4.0, 4.0)) - 3.0).abs() < 1e-9);
+        assert!((grid.density(Point::new(6.75, 4.0, 4.0)) - 3.5).abs() < 1e-9);
+        let bounds = grid.bounds();
+        assert_eq!((bounds.min, bounds.max), (Point::new(0.5, 1.5, 2.5), Point::new(9.0, 10.0, 7.0)));
+    }
+
+    #[test]
+    fn reports_missing_and_compressed_grids() {
+        assert!(matches!(parse(&file(), Some("temperature")), Err(Error::Format(_))));
+        let mut compressed = file();
+        compressed[14] = 1;
+        assert!(matches!(parse(&compressed, None), Err(Error::Format(_))));
+        assert!(matches!(parse(b"OpenVDB", None), Err(Error::Format(_))));
+    }
+
+    #[test]
+    fn reports_oversized_grids() {
+        let mut file = file();
+        let index_max = FILE_HEADER_SIZE + 100;
+        file[index_max..index_max + 12].copy_from_slice(&[i32::MAX.to_le_bytes(); 3].concat());
+        assert!(matches!(parse(&file, None), Err(Error::Format(_))));
+        file[index_max..index_max + 12].copy_from_slice(&[(MAX_INDEX - 1).to_le_bytes(); 3].concat());
+        assert!(matches!(parse(&file, None), Err(Error::Format(_))));
+    }
+}
@@ -0,0 +1,1092 @@
+//! Importer for scene files in the format of the PBRT renderer
+//! (https://pbrt.org/fileformat-v3), so that existing test scenes can be
+//! rendered with lasgun.
+//!
+//! Only a subset of the format translates to lasgun. The importer reads
+//!
+//! - Transformations: `Identity`, `Translate`, `Scale`, `Rotate`, `LookAt`,
+//!   `Transform`, `ConcatTransform`, `CoordinateSystem`, `CoordSysTransform`
+//!   and the `AttributeBegin`/`TransformBegin` stacks
+//! - `Camera` (perspective, orthographic and environment), `Film`,
+//!   `Sampler`, `PixelFilter` (box, triangle, Gaussian and Mitchell) and
+//!   `Integrator` (path, Whitted-style or ambient occlusion)
+//! - `Shape` spheres, disks, triangle meshes and curves, with alpha textures
+//! - `Material`, `MakeNamedMaterial` and `NamedMaterial` of the matte,
+//!   plastic, substrate, metal, glass and mirror types, with bump maps
+//! - `Texture` checkerboards, constants and image maps
+//! - `LightSource` point, distant and infinite lights and diffuse
+//!   `AreaLightSource`s
+//! - `Include`
+//!
+//! Anything else is skipped with a warning rather than an error, since most
+//! scenes still render recognizably without it. PBRT uses a left-handed
+//! coordinate system, so the whole scene is mirrored across the x axis to
+//! produce the same image in lasgun's right-handed one. Light intensities are
+//! converted to lasgun's units.
+
+use std::{fmt, fs, io, f64, path::{Path, PathBuf}, collections::{HashMap, VecDeque}};
+use cgmath::{Matrix4, Deg};
+
+use crate::{
+    space::*,
+    camera::Camera,
+    integrate::Integrator,
+    filter::Filter,
+    light::EnvironmentMap,
+    material::{Material, MaterialRef},
+    texture::{Param, TextureRef, ConstantTexture},
+    scene::{Scene, node::Aggregate}
+};
+use crate::shape::{mesh::TriangleMesh, curve::CurveKind};
+
+/// Number of shadow rays traced toward infinite lights from each shading point
+const INFINITE_LIGHT_SAMPLES: usize = 16;
+
+/// A scene read from a PBRT file, along with the settings from the file that
+/// lasgun keeps outside of the scene
+pub struct PbrtScene {
+    pub scene: Scene,
+
+    /// Image width and height from the `Film` directive
+    pub resolution: (u32, u32),
+
+    /// Samples per pixel from the `Sampler` directive
+    pub samples: u32,
+
+    /// Output image file name from the `Film` directive, if any
+    pub filename: Option<String>,
+
+    /// Descriptions of the parts of the file that were skipped
+    pub warnings: Vec<String>
+}
+
+/// Problems reading a PBRT file
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+
+    /// Malformed input at the given line
+    Syntax { line: usize, message: String }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Syntax { line, message } => write!(f, "line {}: {}", line, message)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::Io(err) }
+}
+
+/// Read the PBRT file at the given path. Included files, textures and
+/// environment maps are loaded relative to its directory.
+pub fn load(path: &Path) -> Result<PbrtScene, Error> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    import(&fs::read_to_string(path)?, dir, Some(path.canonicalize()?))
+}
+
+/// Read a scene from the given contents of a PBRT file. Paths within are
+/// resolved relative to the given directory.
+pub fn parse(source: &str, dir: &Path) -> Result<PbrtScene, Error> {
+    import(source, dir, None)
+}
+
+/// Read a scene from the given contents of the PBRT file at the given path,
+/// if it has one
+fn import(source: &str, dir: &Path, path: Option<PathBuf>) -> Result<PbrtScene, Error> {
+    let mut importer = Importer::new(dir);
+    importer.includes.extend(path);
+    importer.tokens = tokenize(source)?.into();
+    importer.run()?;
+    Ok(importer.finish())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Open,
+    Close,
+
+    /// End of the tokens of an included file
+    EndInclude
+}
+
+/// Split the file into tokens, each tagged with its line number
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, Error> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => (),
+            '#' => while let Some(c) = chars.peek() {
+                if *c == '\n' { break }
+                chars.next();
+            },
+            '[' => tokens.push((Token::Open, line)),
+            ']' => tokens.push((Token::Close, line)),
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\n') | None =>
+                            return Err(Error::Syntax { line, message: "unterminated string".into() }),
+                        Some(c) => string.push(c)
+                    }
+                }
+                tokens.push((Token::Str(string), line))
+            },
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || ['"', '[', ']', '#'].contains(c) { break }
+                    word.push(*c);
+                    chars.next();
+                }
+                let token = if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' {
+                    word.parse().map(Token::Num).map_err(|_| Error::Syntax {
+                        line, message: format!("invalid number \"{}\"", word)
+                    })?
+                } else {
+                    Token::Ident(word)
+                };
+                tokens.push((token, line))
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// A directive argument. Lists are bracketed values.
+#[derive(Debug, Clone)]
+enum Arg {
+    Num(f64),
+    Str(String),
+    List(Vec<Arg>)
+}
+
+impl Arg {
+    fn values(&self) -> &[Arg] {
+        match self {
+            Arg::List(values) => values,
+            value => std::slice::from_ref(value)
+        }
+    }
+}
+
+/// A typed parameter such as `"rgb Kd" [0.5 0.5 0.5]`
+#[derive(Debug, Clone)]
+struct Parameter {
+    ty: String,
+    name: String,
+    values: Vec<Arg>
+}
+
+/// Parameters given to a directive after its positional arguments
+#[derive(Debug, Clone, Default)]
+struct Params(Vec<Parameter>);
+
+impl Params {
+    fn get(&self, name: &str) -> Option<&Parameter> {
+        self.0.iter().find(|param| param.name == name)
+    }
+
+    fn floats(&self, name: &str) -> Option<Vec<f64>> {
+        let values = &self.get(name)?.values;
+        values.iter().map(|value| match value { Arg::Num(n) => Some(*n), _ => None }).collect()
+    }
+
+    fn float(&self, name: &str, default: f64) -> f64 {
+        self.floats(name).and_then(|values| values.first().copied()).unwrap_or(default)
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        match self.get(name)?.values.first()? {
+            Arg::Str(string) => Some(string),
+            _ => None
+        }
+    }
+
+    /// A colour given as RGB or as a single float for all three channels
+    fn rgb(&self, name: &str) -> Option<[f64; 3]> {
+        let param = self.get(name)?;
+        let values = self.floats(name)?;
+        match (param.ty.as_str(), values.len()) {
+            ("rgb", 3) | ("color", 3) => Some([values[0], values[1], values[2]]),
+            ("float", 1) => Some([values[0]; 3]),
+            _ => None
+        }
+    }
+
+    fn point(&self, name: &str, default: [f64; 3]) -> [f64; 3] {
+        match self.floats(name) {
+            Some(values) if values.len() == 3 => [values[0], values[1], values[2]],
+            _ => default
+        }
+    }
+}
+
+/// Attributes that apply to shapes, saved and restored by `AttributeBegin`
+/// and `AttributeEnd`
+#[derive(Clone)]
+struct Attributes {
+    /// Object-to-world transformation in PBRT's coordinate system
+    ctm: Matrix4<f64>,
+    material: MaterialRef,
+
+    /// Radiance of the area light applied to shapes, if any
+    emission: Option<[f64; 3]>,
+    reverse_orientation: bool
+}
+
+/// Camera settings, applied once the film resolution is known
+struct CameraSettings {
+    kind: String,
+    params: Params,
+    camera_to_world: Matrix4<f64>
+}
+
+struct Importer {
+    dir: PathBuf,
+    tokens: VecDeque<(Token, usize)>,
+    line: usize,
+
+    scene: Scene,
+    resolution: (u32, u32),
+    samples: u32,
+    filename: Option<String>,
+    warnings: Vec<String>,
+
+    attributes: Attributes,
+    attribute_stack: Vec<Attributes>,
+    transform_stack: Vec<Matrix4<f64>>,
+    coordinate_systems: HashMap<String, Matrix4<f64>>,
+
+    /// Files being included, innermost last, to catch files that include
+    /// themselves
+    includes: Vec<PathBuf>,
+    camera: Option<CameraSettings>,
+    materials: HashMap<String, MaterialRef>,
+    textures: HashMap<String, TextureRef>
+}
+
+impl Importer {
+    fn new(dir: &Path) -> Importer {
+        let mut scene = Scene::new();
+        scene.set_max_recursion_depth(5);
+        Importer {
+            dir: dir.to_path_buf(),
+            tokens: VecDeque::new(),
+            line: 0,
+            scene,
+            resolution: (640, 480),
+            samples: 16,
+            filename: None,
+            warnings: vec![],
+            attributes: Attributes {
+                ctm: Matrix4::identity(),
+                material: MaterialRef::default(),
+                emission: None,
+                reverse_orientation: false
+            },
+            attribute_stack: vec![],
+            transform_stack: vec![],
+            coordinate_systems: HashMap::new(),
+            includes: vec![],
+            camera: None,
+            materials: HashMap::new(),
+            textures: HashMap::new()
+        }
+    }
+
+    fn finish(mut self) -> PbrtScene {
+        self.apply_camera();
+        PbrtScene {
+            scene: self.scene,
+            resolution: self.resolution,
+            samples: self.samples,
+            filename: self.filename,
+            warnings: self.warnings
+        }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, Error> {
+        Err(Error::Syntax { line: self.line, message: message.into() })
+    }
+
+    fn warn(&mut self, message: impl Into<String>) {
+        let warning = format!("line {}: {}", self.line, message.into());
+        self.warnings.push(warning)
+    }
+
+    fn run(&mut self) -> Result<(), Error> {
+        while let Some((token, line)) = self.tokens.pop_front() {
+            self.line = line;
+            match token {
+                Token::Ident(directive) => {
+                    let args = self.args()?;
+                    self.directive(&directive, args)?
+                },
+                Token::EndInclude => { self.includes.pop(); },
+                token => return self.error(format!("expected a directive, found {:?}", token))
+            }
+        }
+        Ok(())
+    }
+
+    /// Collect the arguments that follow a directive, up to the next one
+    fn args(&mut self) -> Result<Vec<Arg>, Error> {
+        let mut args = vec![];
+        let mut list: Option<Vec<Arg>> = None;
+        loop {
+            let arg = match self.tokens.front() {
+                Some((Token::Ident(word), _)) if word == "true" || word == "false" => Arg::Str(word.clone()),
+                Some((Token::Ident(_), _)) | Some((Token::EndInclude, _)) | None => break,
+                Some((Token::Num(n), _)) => Arg::Num(*n),
+                Some((Token::Str(s), _)) => Arg::Str(s.clone()),
+                Some((Token::Open, _)) if list.is_none() => {
+                    list = Some(vec![]);
+                    self.tokens.pop_front();
+                    continue
+                },
+                Some((Token::Close, _)) if list.is_some() => {
+                    args.push(Arg::List(list.take().unwrap()));
+                    self.tokens.pop_front();
+                    continue
+                },
+                Some((token, line)) =>
+                    return Err(Error::Syntax { line: *line, message: format!("unexpected {:?}", token) })
+            };
+            self.tokens.pop_front();
+            match &mut list {
+                Some(list) => list.push(arg),
+                None => args.push(arg)
+            }
+        }
+        if list.is_some() { return self.error("unterminated list") }
+        Ok(args)
+    }
+
+    /// Split the given arguments into the given number of positional arguments
+    /// followed by the parameter list
+    fn split(&self, args: Vec<Arg>, positional: usize) -> Result<(Vec<Arg>, Params), Error> {
+        if args.len() < positional { return self.error("missing arguments") }
+        let mut args = args.into_iter();
+        let head: Vec<Arg> = args.by_ref().take(positional).collect();
+        let mut params = Params::default();
+        while let Some(decl) = args.next() {
+            let decl = match decl {
+                Arg::Str(decl) => decl,
+                arg => return self.error(format!("expected a parameter declaration, found {:?}", arg))
+            };
+            let mut words = decl.split_whitespace();
+            let (ty, name) = match (words.next(), words.next()) {
+                (Some(ty), Some(name)) => (ty.to_string(), name.to_string()),
+                _ => return self.error(format!("invalid parameter declaration \"{}\"", decl))
+            };
+            let values = match args.next() {
+                Some(value) => value.values().to_vec(),
+                None => return self.error(format!("missing value for parameter \"{}\"", name))
+            };
+            params.0.push(Parameter { ty, name, values })
+        }
+        Ok((head, params))
+    }
+
+    fn numbers(&self, args: &[Arg], n: usize) -> Result<Vec<f64>, Error> {
+        let values: Vec<f64> = args.iter()
+            .flat_map(|arg| arg.values().iter())
+            .filter_map(|arg| match arg { Arg::Num(n) => Some(*n), _ => None })
+            .collect();
+        if values.len() != n { return self.error(format!("expected {} numbers", n)) }
+        Ok(values)
+    }
+
+    fn name(&self, args: &[Arg]) -> Result<String, Error> {
+        match args.first() {
+            Some(Arg::Str(name)) => Ok(name.clone()),
+            _ => self.error("expected a name")
+        }
+    }
+
+    fn directive(&mut self, directive: &str, args: Vec<Arg>) -> Result<(), Error> {
+        match directive {
+            "Identity" => self.attributes.ctm = Matrix4::identity(),
+            "Translate" => {
+                let v = self.numbers(&args, 3)?;
+                self.concat(Matrix4::from_translation(Vector::new(v[0], v[1], v[2])))
+            },
+            "Scale" => {
+                let v = self.numbers(&args, 3)?;
+                self.concat(Matrix4::from_nonuniform_scale(v[0], v[1], v[2]))
+            },
+            "Rotate" => {
+                let v = self.numbers(&args, 4)?;
+                let axis = Vector::new(v[1], v[2], v[3]);
+                if axis.magnitude2() == 0.0 { return self.error("rotation about a zero axis") }
+                self.concat(Matrix4::from_axis_angle(axis.normalize(), Deg(v[0])))
+            },
+            "LookAt" => {
+                let v = self.numbers(&args, 9)?;
+                match look_at(&v) {
+                    Some(m) => self.concat(m),
+                    None => self.warn("degenerate LookAt")
+                }
+            },
+            "Transform" => {
+                let v = self.numbers(&args, 16)?;
+                self.attributes.ctm = matrix(&v)
+            },
+            "ConcatTransform" => {
+                let v = self.numbers(&args, 16)?;
+                self.concat(matrix(&v))
+            },
+            "CoordinateSystem" => {
+                let name = self.name(&args)?;
+                self.coordinate_systems.insert(name, self.attributes.ctm);
+            },
+            "CoordSysTransform" => {
+                let name = self.name(&args)?;
+                match self.coordinate_systems.get(&name) {
+                    Some(m) => self.attributes.ctm = *m,
+                    None => self.warn(format!("unknown coordinate system \"{}\"", name))
+                }
+            },
+            "TransformBegin" => self.transform_stack.push(self.attributes.ctm),
+            "TransformEnd" => match self.transform_stack.pop() {
+                Some(ctm) => self.attributes.ctm = ctm,
+                None => return self.error("unmatched TransformEnd")
+            },
+            "AttributeBegin" => self.attribute_stack.push(self.attributes.clone()),
+            "AttributeEnd" => match self.attribute_stack.pop() {
+                Some(attributes) => self.attributes = attributes,
+                None => return self.error("unmatched AttributeEnd")
+            },
+            "ReverseOrientation" => {
+                self.attributes.reverse_orientation = !self.attributes.reverse_orientation
+            },
+            "WorldBegin" => {
+                // Shapes and lights are placed relative to the world origin
+                self.coordinate_systems.insert("world".into(), Matrix4::identity());
+                self.attributes.ctm = Matrix4::identity();
+            },
+            "WorldEnd" => (),
+            "Camera" => {
+                let (head, params) = self.split(args, 1)?;
+                let kind = self.name(&head)?;
+                let camera_to_world = match self.attributes.ctm.invert() {
+                    Some(m) => m,
+                    None => return self.error("camera transformation is not invertible")
+                };
+                self.coordinate_systems.insert("camera".into(), camera_to_world);
+                self.camera = Some(CameraSettings { kind, params, camera_to_world })
+            },
+            "Film" => {
+                let (_, params) = self.split(args, 1)?;
+                let x = params.float("xresolution", 640.0).max(1.0);
+                let y = params.float("yresolution", 480.0).max(1.0);
+                self.resolution = (x as u32, y as u32);
+                self.filename = params.string("filename").map(String::from)
+            },
+            "Sampler" => {
+                let (_, params) = self.split(args, 1)?;
+                self.samples = params.float("pixelsamples", 16.0).max(1.0) as u32;
+            },
+            "PixelFilter" => {
+                let (head, params) = self.split(args, 1)?;
+                let radius = |default: f64| params.float("xradius", params.float("xwidth", default));
+                let filter = match self.name(&head)?.as_str() {
+                    "box" => Filter::Box { radius: radius(0.5) },
+                    "triangle" => Filter::Triangle { radius: radius(2.0) },
+                    "gaussian" => Filter::Gaussian { radius: radius(1.5), sigma: params.float("sigma", 0.5) },
+                    "mitchell" => Filter::Mitchell { radius: radius(2.0), b: params.float("B", 1.0 / 3.0), c: params.float("C", 1.0 / 3.0) },
+                    name => {
+                        let name = name.to_string();
+                        self.warn(format!("unsupported pixel filter \"{}\", using box", name));
+                        Filter::default()
+                    }
+                };
+                self.scene.set_filter(filter)
+            },
+            "Integrator" => {
+                let (head, params) = self.split(args, 1)?;
+                match self.name(&head)?.as_str() {
+                    "path" | "volpath" | "bdpt" | "mlt" => self.scene.set_integrator(Integrator::Path),
+                    "whitted" | "directlighting" => self.scene.set_integrator(Integrator::Whitted),
+                    "irradiancecache" => {
+                        self.scene.set_integrator(Integrator::Irradiance);
+                        let samples = params.float("nsamples", 4096.0).max(1.0) as u32;
+                        let error = params.float("maxerror", 0.2);
+                        self.scene.set_irradiance_cache(samples, if error > 0.0 { error } else { 0.2 })
+                    },
+                    "ambientocclusion" => {
+                        self.scene.set_integrator(Integrator::AmbientOcclusion);
+                        if let Some(distance) = params.floats("maxdistance").and_then(|d| d.first().copied()) {
+                            self.scene.set_ao_distance(distance)
+                        }
+                    },
+                    name => {
+                        let name = name.to_string();
+                        self.warn(format!("unsupported integrator \"{}\", using path tracing", name));
+                        self.scene.set_integrator(Integrator::Path)
+                    }
+                }
+                self.scene.set_max_recursion_depth(params.float("maxdepth", 5.0).max(0.0) as u32)
+            },
+            "Include" | "Import" => {
+                let name = self.name(&args)?;
+                let path = self.dir.join(&name).canonicalize()?;
+                if self.includes.contains(&path) {
+                    return self.error(format!("\"{}\" includes itself", name))
+                }
+                let source = fs::read_to_string(&path)?;
+                self.tokens.push_front((Token::EndInclude, self.line));
+                for token in tokenize(&source)?.into_iter().rev() {
+                    self.tokens.push_front(token)
+                }
+                self.includes.push(path)
+            },
+            "Texture" => {
+                let (head, params) = self.split(args, 3)?;
+                let name = self.name(&head)?;
+                let class = self.name(&head[2..])?;
+                if let Some(texture) = self.texture(&class, &params) {
+                    self.textures.insert(name, texture);
+                }
+            },
+            "Material" => {
+                let (head, params) = self.split(args, 1)?;
+                let kind = self.name(&head)?;
+                let material = self.material(&kind, &params);
+                self.attributes.material = self.scene.add_material(material)
+            },
+            "MakeNamedMaterial" => {
+                let (head, params) = self.split(args, 1)?;
+                let name = self.name(&head)?;
+                let kind = params.string("type").unwrap_or("matte").to_string();
+                let material = self.material(&kind, &params);
+                let material = self.scene.add_material(material);
+                self.materials.insert(name, material);
+            },
+            "NamedMaterial" => {
+                let name = self.name(&args)?;
+                match self.materials.get(&name) {
+                    Some(material) => self.attributes.material = *material,
+                    None => self.warn(format!("unknown material \"{}\"", name))
+                }
+            },
+            "LightSource" => {
+                let (head, params) = self.split(args, 1)?;
+                let kind = self.name(&head)?;
+                self.light(&kind, &params)
+            },
+            "AreaLightSource" => {
+                let (head, params) = self.split(args, 1)?;
+                let kind = self.name(&head)?;
+                if kind != "diffuse" { self.warn(format!("unsupported area light \"{}\"", kind)) }
+                let l = params.rgb("L").unwrap_or([1.0; 3]);
+                let scale = params.float("scale", 1.0);
+                self.attributes.emission = Some([l[0] * scale, l[1] * scale, l[2] * scale])
+            },
+            "Shape" => {
+                let (head, params) = self.split(args, 1)?;
+                let kind = self.name(&head)?;
+                self.shape(&kind, &params)?
+            },
+            directive => self.warn(format!("unsupported directive {}", directive))
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn concat(&mut self, m: Matrix4<f64>) {
+        self.attributes.ctm = self.attributes.ctm * m
+    }
+
+    fn apply_camera(&mut self) {
+        let settings = match self.camera.take() {
+            Some(settings) => settings,
+            None => return
+        };
+
+        // Mirror the camera along with the rest of the scene
+        let m = mirror() * settings.camera_to_world;
+        let eye = m.transform_point(Point::new(0.0, 0.0, 0.0));
+        let view = m.transform_vector(Vector::unit_z());
+        let up = m.transform_vector(Vector::unit_y());
+
+        // PBRT's screen window spans [-1, 1] along the shorter image axis
+        let (width, height) = (self.resolution.0 as f64, self.resolution.1 as f64);
+        let aspect = width / height;
+        let params = &settings.params;
+        let mut camera = match settings.kind.as_str() {
+            "orthographic" => Camera::orthographic(match params.floats("screenwindow") {
+                Some(window) if window.len() == 4 => window[3] - window[2],
+                _ => if aspect >= 1.0 { 2.0 } else { 2.0 / aspect }
+            }),
+            "environment" => Camera::equirectangular(),
+            kind => {
+                if kind != "perspective" {
+                    let kind = kind.to_string();
+                    self.warn(format!("unsupported camera \"{}\", using perspective", kind))
+                }
+                let fov = params.float("fov", 90.0);
+                if aspect >= 1.0 {
+                    Camera::perspective(fov)
+                } else {
+                    let half = (fov.to_radians() / 2.0).tan() / aspect;
+                    Camera::perspective(2.0 * half.atan().to_degrees())
+                }
+            }
+        };
+        let target = eye + view.normalize();
+        camera.look_at(eye.into(), target.into(), up.into());
+
+        let lens_radius = params.float("lensradius", 0.0);
+        if lens_radius > 0.0 {
+            camera.set_aperture_radius(lens_radius);
+            let distance = params.float("focaldistance", 1e6);
+            if distance > 0.0 && distance.is_finite() {
+                camera.set_focal_distance(distance)
+            } else {
+                self.warn(format!("invalid focal distance {}, focusing at infinity", distance));
+                camera.set_focal_distance(1e6)
+            }
+        }
+
+        camera.set_samples_per_pixel(self.samples);
+        self.scene.set_camera(camera);
+    }
+
+    fn texture(&mut self, class: &str, params: &Params) -> Option<TextureRef> {
+        match class {
+            "constant" => {
+                let value = params.rgb("value").unwrap_or([1.0; 3]);
+                Some(self.scene.add_texture(Box::new(ConstantTexture(value.into()))))
+            },
+            "checkerboard" => {
+                if params.get("tex1").is_some_and(|p| p.ty == "texture")
+                || params.get("tex2").is_some_and(|p| p.ty == "texture") {
+                    self.warn("nested checkerboard textures are not supported");
+                }
+                let even = params.rgb("tex1").unwrap_or([1.0; 3]);
+                let odd = params.rgb("tex2").unwrap_or([0.0; 3]);
+                let scale = params.float("uscale", 1.0);
+                Some(self.scene.add_checkerboard_texture(even, odd, scale))
+            },
+            "imagemap" => {
+                let filename = params.string("filename")?.to_string();
+                self.image_texture(&self.dir.join(&filename))
+            },
+            class => {
+                self.warn(format!("unsupported texture \"{}\"", class));
+                None
+            }
+        }
+    }
+
+    #[cfg(feature = "bin")]
+    fn image_texture(&mut self, path: &Path) -> Option<TextureRef> {
+        match self.scene.load_texture(path) {
+            Ok(texture) => Some(texture),
+            Err(err) => {
+                self.warn(format!("could not load texture {}: {}", path.display(), err));
+                None
+            }
+        }
+    }
+
+    /// Image textures cannot be loaded without the bin feature
+    #[cfg(not(feature = "bin"))]
+    fn image_texture(&mut self, path: &Path) -> Option<TextureRef> {
+        self.warn(format!("could not load texture {}: image textures require the bin feature", path.display()));
+        None
+    }
+
+    /// A colour parameter that may reference a texture
+    fn param(&mut self, params: &Params, name: &str, default: [f64; 3]) -> Param {
+        match params.get(name) {
+            Some(param) if param.ty == "texture" => {
+                let texture = params.string(name).and_then(|texture| self.textures.get(texture));
+                match texture {
+                    Some(texture) => Param::from(*texture),
+                    None => {
+                        self.warn(format!("unknown texture for \"{}\"", name));
+                        Param::from(default)
+                    }
+                }
+            },
+            Some(_) => Param::from(self.rgb(params, name, default)),
+            None => Param::from(default)
+        }
+    }
+
+    fn rgb(&mut self, params: &Params, name: &str, default: [f64; 3]) -> [f64; 3] {
+        match params.rgb(name) {
+            Some(rgb) => rgb,
+            None => {
+                if let Some(param) = params.get(name) {
+                    let ty = param.ty.clone();
+                    self.warn(format!("unsupported {} value for \"{}\"", ty, name))
+                }
+                default
+            }
+        }
+    }
+
+    fn material(&mut self, kind: &str, params: &Params) -> Material {
+        let material = match kind {
+            "matte" => {
+                let kd = self.param(params, "Kd", [0.5; 3]);
+                Material::matte(kd, params.float("sigma", 0.0))
+            },
+            "plastic" | "substrate" | "uber" => {
+                let kd = self.param(params, "Kd", [0.25; 3]);
+                let ks = self.rgb(params, "Ks", [0.25; 3]);
+                let roughness = params.float("roughness", 0.1);
+                Material::plastic(kd, ks, roughness)
+            },
+            "metal" | "conductor" => {
+                // Copper by default
+                let eta = self.rgb(params, "eta", [0.2, 0.92, 1.1]);
+                let k = self.rgb(params, "k", [3.91, 2.45, 2.14]);
+                let roughness = params.float("roughness", 0.01);
+                let u = params.float("uroughness", roughness);
+                let v = params.float("vroughness", roughness);
+                Material::metal(eta, k, u, v)
+            },
+            "glass" | "dielectric" => {
+                let kr = self.rgb(params, "Kr", [1.0; 3]);
+                let kt = self.rgb(params, "Kt", [1.0; 3]);
+                let eta = params.float("eta", params.float("index", 1.5));
+                Material::glass(kr, kt, eta)
+            },
+            "mirror" => Material::mirror(self.rgb(params, "Kr", [0.9; 3])),
+            "" | "none" | "interface" => Material::default(),
+            kind => {
+                self.warn(format!("unsupported material \"{}\", using matte", kind));
+                Material::matte(self.param(params, "Kd", [0.5; 3]), 0.0)
+            }
+        };
+
+        match params.get("bumpmap").and(params.string("bumpmap")) {
+            Some(name) => match self.textures.get(name) {
+                Some(texture) => material.with_bump(*texture, 1.0),
+                None => {
+                    let name = name.to_string();
+                    self.warn(format!("unknown bump map \"{}\"", name));
+                    material
+                }
+            },
+            None => material
+        }
+    }
+
+    fn light(&mut self, kind: &str, params: &Params) {
+        // Lasgun's point-light shading includes a factor of π that PBRT's
+        // intensities leave out
+        let scale = params.float("scale", 1.0);
+        let world = mirror() * self.attributes.ctm;
+        match kind {
+            "point" | "spot" | "goniometric" | "projection" => {
+                if kind != "point" {
+                    self.warn(format!("unsupported light \"{}\", using a point light", kind))
+                }
+                let i = self.rgb(params, "I", [1.0; 3]);
+                let from = world.transform_point(params.point("from", [0.0; 3]).into());
+                let intensity = [0, 1, 2].map(|c| i[c] * scale / f64::consts::PI);
+                self.scene.add_point_light(from.into(), intensity, [0.0, 0.0, 1.0]);
+            },
+            "distant" => {
+                let l = self.rgb(params, "L", [1.0; 3]);
+                let from = Point::from(params.point("from", [0.0; 3]));
+                let to = Point::from(params.point("to", [0.0, 0.0, 1.0]));
+                let direction = world.transform_vector(to - from);
+                let intensity = [0, 1, 2].map(|c| l[c] * scale / f64::consts::PI);
+                self.scene.add_directional_light(direction.into(), intensity, 0.0);
+            },
+            "infinite" => {
+                let samples = params.float("nsamples", params.float("samples", INFINITE_LIGHT_SAMPLES as f64));
+                let samples = (samples as usize).max(1);
+                match params.string("filename").or(params.string("mapname")) {
+                    Some(filename) => {
+                        let path = self.dir.join(filename);
+                        self.environment_map(&path, samples)
+                    },
+                    None => {
+                        let l = self.rgb(params, "L", [1.0; 3]);
+                        let radiance = Color::new(l[0], l[1], l[2]) * scale;
+                        let map = EnvironmentMap::new(1, 1, vec![radiance]);
+                        self.scene.set_environment_map(map, samples)
+                    }
+                }
+            },
+            kind => self.warn(format!("unsupported light \"{}\"", kind))
+        }
+    }
+
+    #[cfg(feature = "bin")]
+    fn environment_map(&mut self, path: &Path, samples: usize) {
+        if let Err(err) = self.scene.load_environment_map(path, samples) {
+            self.warn(format!("could not load environment map {}: {}", path.display(), err))
+        }
+    }
+
+    /// Environment maps cannot be loaded without the bin feature
+    #[cfg(not(feature = "bin"))]
+    fn environment_map(&mut self, path: &Path, _samples: usize) {
+        self.warn(format!("could not load environment map {}: requires the bin feature", path.display()))
+    }
+
+    fn shape(&mut self, kind: &str, params: &Params) -> Result<(), Error> {
+        let material = self.attributes.emission.map(Material::emissive);
+
+        // Alpha textures cut out parts of the shape; a constant alpha of zero
+        // leaves nothing to render
+        let material = match params.get("alpha") {
+            Some(param) if param.ty == "texture" => {
+                match params.string("alpha").and_then(|name| self.textures.get(name)) {
+                    Some(mask) => {
+                        let material = material.unwrap_or_else(|| self.scene.material(self.attributes.material));
+                        Some(material.with_alpha(*mask))
+                    },
+                    None => {
+                        self.warn("unknown alpha texture");
+                        material
+                    }
+                }
+            },
+            Some(_) if params.float("alpha", 1.0) == 0.0 => return Ok(()),
+            _ => material
+        };
+
+        // Shapes with an emission or alpha mask of their own get a material
+        // of their own; the rest share the current one
+        let material = match material {
+            Some(material) => self.scene.add_material(material),
+            None => self.attributes.material
+        };
+        let transform = match (mirror() * self.attributes.ctm).invert() {
+            Some(inverse) => Transformation::new(mirror() * self.attributes.ctm, inverse),
+            None => {
+                self.warn("shape transformation is not invertible");
+                return Ok(())
+            }
+        };
+
+        let mut group = Aggregate::new();
+        group.transform = transform;
+        if self.attributes.reverse_orientation { group.swap_backface() }
+
+        match kind {
+            "sphere" => group.add_sphere([0.0; 3], params.float("radius", 1.0), material),
+            "disk" => {
+                let center = [0.0, 0.0, params.float("height", 0.0)];
+                group.add_disk(center, [0.0, 0.0, 1.0], params.float("radius", 1.0), material)
+            },
+            "trianglemesh" => {
+                let mesh = match triangle_mesh(params) {
+                    Some(mesh) => mesh,
+                    None => return self.error("triangle mesh needs indices and positions")
+                };
+                let mesh = self.scene.add_mesh(mesh);
+                group.add_obj_of(mesh, material)
+            },
+            "curve" => {
+                let points = match params.floats("P") {
+                    Some(p) if p.len() % 3 == 0 && p.len() >= 12 =>
+                        p.chunks(3).map(|p| [p[0], p[1], p[2]]).collect::<Vec<_>>(),
+                    _ => return self.error("curve needs at least 4 control points")
+                };
+                if params.float("degree", 3.0) != 3.0 {
+                    self.warn("only cubic curves are supported");
+                    return Ok(())
+                }
+                let kind = match params.string("type").unwrap_or("flat") {
+                    "cylinder" => CurveKind::Cylinder,
+                    "flat" => CurveKind::Flat,
+                    kind => {
+                        self.warn(format!("unsupported curve type \"{}\", using flat", kind));
+                        CurveKind::Flat
+                    }
+                };
+                let width = params.float("width", 1.0);
+                let width = [params.float("width0", width), params.float("width1", width)];
+                match params.string("basis").unwrap_or("bezier") {
+                    "bspline" => group.add_bspline_curves(&points, width, kind, material),
+                    _ if (points.len() - 1) % 3 == 0 => group.add_curves(&points, width, kind, material),
+                    _ => return self.error("Bezier curves need 3n + 1 control points")
+                }
+            },
+            kind => {
+                self.warn(format!("unsupported shape \"{}\"", kind));
+                return Ok(())
+            }
+        }
+        self.scene.root.add_group(group);
+        Ok(())
+    }
+}
+
+/// Reflection across the x axis, which converts PBRT's left-handed world
+/// into lasgun's right-handed one
+#[inline]
+fn mirror() -> Matrix4<f64> {
+    Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0)
+}
+
+/// Matrix from 16 values listed in column-major order
+fn matrix(v: &[f64]) -> Matrix4<f64> {
+    Matrix4::new(
+        v[0], v[1], v[2], v[3],
+        v[4], v[5], v[6], v[7],
+        v[8], v[9], v[10], v[11],
+        v[12], v[13], v[14], v[15])
+}
+
+/// World-to-camera transformation for a camera at the given eye point looking
+/// toward the given target, with camera +z pointing forward. See PBRT v3
+/// section 2.7.7
+fn look_at(v: &[f64]) -> Option<Matrix4<f64>> {
+    let eye = Vector::new(v[0], v[1], v[2]);
+    let dir = (Vector::new(v[3], v[4], v[5]) - eye).normalize();
+    let up = Vector::new(v[6], v[7], v[8]).normalize();
+    let right = up.cross(dir);
+    if right.magnitude2() == 0.0 || !right.x.is_finite() { return None }
+    let right = right.normalize();
+    let up = dir.cross(right);
+    let camera_to_world = Matrix4::from_cols(
+        right.extend(0.0), up.extend(0.0), dir.extend(0.0), eye.extend(1.0));
+    camera_to_world.invert()
+}
+
+/// Triangles of the given trianglemesh parameters
+fn triangle_mesh(params: &Params) -> Option<TriangleMesh> {
+    let indices = params.floats("indices")?;
+    if indices.iter().any(|i| *i < 0.0) { return None }
+    TriangleMesh {
+        positions: params.floats("P")?,
+        normals: params.floats("N"),
+        uvs: params.floats("uv").or_else(|| params.floats("st")),
+        colors: None,
+        indices: indices.iter().map(|i| *i as usize).collect()
+    }.validate()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scene::node::{SceneNode, Shape};
+
+    const SCENE: &str = r#"
+        # A sphere and a triangle under a point light
+        LookAt 0 0 -5  0 0 0  0 1 0
+        Camera "perspective" "float fov" [ 45 ]
+        Film "image" "integer xresolution" [ 320 ] "integer yresolution" 240
+            "string filename" "out.exr"
+        Sampler "halton" "integer pixelsamples" 4
+        PixelFilter "gaussian" "float xradius" 2 "float sigma" 0.75
+        Integrator "path" "integer maxdepth" [ 4 ]
+        WorldBegin
+        LightSource "point" "rgb I" [ 10 10 10 ] "point from" [ 0 4 0 ]
+        MakeNamedMaterial "red" "string type" "plastic" "rgb Kd" [ 0.8 0.1 0.1 ]
+        AttributeBegin
+            NamedMaterial "red"
+            Translate 1 0 0
+            Shape "sphere" "float radius" 0.5
+        AttributeEnd
+        Shape "trianglemesh" "integer indices" [ 0 1 2 ]
+            "point P" [ -1 -1 0  1 -1 0  0 1 0 ]
+        Shape "curve" "point P" [ 0 0 0  1 1 0  2 -1 0  3 0 0  4 1 0  5 0 0  6 0 0 ]
+            "float width0" 0.1 "float width1" 0.02 "string type" "cylinder"
+        Shape "cylinder"
+        WorldEnd
+    "#;
+
+    #[test]
+    fn imports_scene() {
+        let pbrt = parse(SCENE, Path::new("")).unwrap();
+        assert_eq!(pbrt.resolution, (320, 240));
+        assert_eq!(pbrt.samples, 4);
+        assert_eq!(pbrt.scene.camera.samples_per_pixel(), 4);
+        assert_eq!(pbrt.scene.filter, Filter::Gaussian { radius: 2.0, sigma: 0.75 });
+        assert_eq!(pbrt.filename.as_deref(), Some("out.exr"));
+        assert_eq!(pbrt.scene.integrator, Integrator::Path);
+        assert_eq!(pbrt.scene.recursion, 4);
+        assert_eq!(pbrt.scene.lights().len(), 1);
+        assert_eq!(pbrt.warnings.len(), 1, "{:?}", pbrt.warnings);
+
+        let contents = &pbrt.scene.root.contents;
+        assert_eq!(contents.len(), 3);
+        match &contents[2] {
+            // A strand of two curves, narrowing toward its end
+            SceneNode::Group(group) => match group.contents.as_slice() {
+                [SceneNode::Geometry(Shape::Curve(_, w0, CurveKind::Cylinder), _),
+                 SceneNode::Geometry(Shape::Curve(_, w1, CurveKind::Cylinder), _)] => {
+                    assert_eq!((w0[0], w1[1]), (0.1, 0.02));
+                    assert!((w0[1] - w1[0]).abs() < 1e-12)
+                },
+                _ => panic!("expected two cylinder curves")
+            },
+            _ => panic!("expected a group")
+        }
+        match &contents[0] {
+            SceneNode::Group(group) => {
+                // Mirrored into lasgun's coordinate system
+                let center = group.transform.transform_point(Point::new(0.0, 0.0, 0.0));
+                assert_eq!(center, Point::new(-1.0, 0.0, 0.0));
+                match group.contents[0] {
+                    SceneNode::Geometry(Shape::Sphere(_, radius), material) if matches!(pbrt.scene.material(material), Material::Plastic(_)) =>
+                        assert_eq!(radius, 0.5),
+                    _ => panic!("expected a plastic sphere")
+                }
+            },
+            _ => panic!("expected a group")
+        }
+    }
+
+    #[test]
+    fn reports_line_of_syntax_error() {
+        match parse("WorldBegin\nShape \"sphere\" \"float radius\" [ 1", Path::new("")) {
+            Err(Error::Syntax { line: 2, .. }) => (),
+            _ => panic!("expected a syntax error on line 2")
+        }
+    }
+
+    #[test]
+    fn reports_include_cycle() {
+        let dir = std::env::temp_dir().join(format!("lasgun-pbrt-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.pbrt"), "Include \"b.pbrt\"\n").unwrap();
+        fs::write(dir.join("b.pbrt"), "Shape \"sphere\"\nInclude \"a.pbrt\"\n").unwrap();
+        fs::write(dir.join("sphere.pbrt"), "Shape \"sphere\"\n").unwrap();
+        let cycle = load(&dir.join("a.pbrt"));
+        let repeated = parse("Include \"sphere.pbrt\"\nInclude \"sphere.pbrt\"", &dir);
+        fs::remove_dir_all(&dir).ok();
+        match cycle {
+            Err(Error::Syntax { line: 2, message }) => assert!(message.contains("includes itself"), "{}", message),
+            _ => panic!("expected an include cycle on line 2 of b.pbrt")
+        }
+        assert_eq!(repeated.unwrap().scene.root.contents.len(), 2);
+    }
+
+    #[test]
+    fn warns_of_invalid_focal_distance() {
+        let pbrt = parse(
+            "Camera \"perspective\" \"float lensradius\" 0.1 \"float focaldistance\" -2\nWorldBegin",
+            Path::new("")
+        ).unwrap();
+        assert_eq!(pbrt.warnings.len(), 1, "{:?}", pbrt.warnings);
+        assert!(pbrt.warnings[0].contains("focal distance"));
+    }
+}
@@ -0,0 +1,271 @@
+//! Loader for meshes in the Polygon File Format, also known as the Stanford
+//! triangle format, in its ASCII and binary encodings. Vertex positions,
+//! normals, texture coordinates and colours are read; polygons with more than
+//! three vertices are split into fans of triangles.
+
+use std::{fs, path::Path};
+use crate::shape::mesh::{TriangleMesh, Error, format_error};
+
+/// Load the ASCII or binary .ply file at the given path
+pub fn load_ply(path: &Path) -> Result<TriangleMesh, Error> {
+    match parse_ply(&fs::read(path)?)?.validate() {
+        Some(mesh) => Ok(mesh),
+        None => format_error("face refers to a missing vertex")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar { I8, U8, I16, U16, I32, U32, F32, F64 }
+
+impl Scalar {
+    fn from_name(name: &str) -> Option<Scalar> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Property {
+    Scalar(String, Scalar),
+
+    /// List with the type of its length and the type of its items
+    List(String, Scalar, Scalar)
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding { Ascii, LittleEndian, BigEndian }
+
+/// Reads the values of the body of a .ply file one at a time
+struct PlyReader<'a> {
+    encoding: Encoding,
+    bytes: &'a [u8],
+    offset: usize,
+    words: std::str::SplitAsciiWhitespace<'a>
+}
+
+impl<'a> PlyReader<'a> {
+    fn read(&mut self, ty: Scalar) -> Result<f64, Error> {
+        if self.encoding == Encoding::Ascii {
+            return match self.words.next().map(str::parse::<f64>) {
+                Some(Ok(value)) => Ok(value),
+                _ => format_error("truncated or malformed ASCII data")
+            }
+        }
+
+        let bytes = match self.bytes.get(self.offset..self.offset + ty.size()) {
+            Some(bytes) => bytes,
+            None => return format_error("truncated binary data")
+        };
+        self.offset += ty.size();
+
+        let mut b = [0u8; 8];
+        b[..bytes.len()].copy_from_slice(bytes);
+        if self.encoding == Encoding::BigEndian { b[..bytes.len()].reverse() }
+        Ok(match ty {
+            Scalar::I8 => b[0] as i8 as f64,
+            Scalar::U8 => b[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(b)
+        })
+    }
+}
+
+/// Read the triangles of the given contents of a .ply file. Polygons with
+/// more than three vertices are split into fans of triangles.
+pub fn parse_ply(bytes: &[u8]) -> Result<TriangleMesh, Error> {
+    // The header is always text, ending at the first end_header line
+    let end = match find(bytes, b"end_header") {
+        Some(end) => end,
+        None => return format_error("missing end_header")
+    };
+    let header = String::from_utf8_lossy(&bytes[..end]);
+    let mut body = end + b"end_header".len();
+    while body < bytes.len() && bytes[body] != b'\n' { body += 1 }
+    let body = &bytes[(body + 1).min(bytes.len())..];
+
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") { return format_error("missing ply signature") }
+
+    let mut encoding = None;
+    let mut elements: Vec<Element> = vec![];
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", _] => encoding = Some(Encoding::Ascii),
+            ["format", "binary_little_endian", _] => encoding = Some(Encoding::LittleEndian),
+            ["format", "binary_big_endian", _] => encoding = Some(Encoding::BigEndian),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().or_else(|_| format_error(format!("invalid count in \"{}\"", line)))?,
+                properties: vec![]
+            }),
+            ["property", "list", count, item, name] => {
+                let (count, item) = match (Scalar::from_name(count), Scalar::from_name(item)) {
+                    (Some(count), Some(item)) => (count, item),
+                    _ => return format_error(format!("invalid property \"{}\"", line))
+                };
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(Property::List(name.to_string(), count, item)),
+                    None => return format_error("property before element")
+                }
+            },
+            ["property", ty, name] => {
+                let ty = match Scalar::from_name(ty) {
+                    Some(ty) => ty,
+                    None => return format_error(format!("invalid property \"{}\"", line))
+                };
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(Property::Scalar(name.to_string(), ty)),
+                    None => return format_error("property before element")
+                }
+            },
+            _ => () // comments and obj_info
+        }
+    }
+
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => return format_error("missing format")
+    };
+    let mut reader = PlyReader {
+        encoding,
+        bytes: body,
+        offset: 0,
+        words: match encoding {
+            Encoding::Ascii => std::str::from_utf8(body)
+                .or_else(|_| format_error("ASCII data is not text"))?
+                .split_ascii_whitespace(),
+            _ => "".split_ascii_whitespace()
+        }
+    };
+
+    let mut mesh = TriangleMesh::default();
+    let (mut normals, mut uvs, mut colors) = (vec![], vec![], vec![]);
+    for element in elements.iter() {
+        // Where each vertex attribute is found among the element's properties
+        let index = |names: &[&str]| element.properties.iter().position(|property| match property {
+            Property::Scalar(name, _) => names.contains(&name.as_str()),
+            _ => false
+        });
+        let position = [index(&["x"]), index(&["y"]), index(&["z"])];
+        let normal = [index(&["nx"]), index(&["ny"]), index(&["nz"])];
+        let uv = [index(&["u", "s", "texture_u", "texture_s"]), index(&["v", "t", "texture_v", "texture_t"])];
+        let color = [index(&["red", "r"]), index(&["green", "g"]), index(&["blue", "b"])];
+
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            for (i, property) in element.properties.iter().enumerate() {
+                match property {
+                    Property::Scalar(_, ty) => {
+                        values[i] = reader.read(*ty)?;
+
+                        // Integer colour channels span the range of the type
+                        if color.contains(&Some(i)) {
+                            values[i] /= match ty { Scalar::U8 => 255.0, Scalar::U16 => 65535.0, _ => 1.0 }
+                        }
+                    },
+                    Property::List(name, count, item) => {
+                        // Not preallocated, since the count comes from the file
+                        let count = reader.read(*count)? as usize;
+                        let mut list = vec![];
+                        for _ in 0..count { list.push(reader.read(*item)?) }
+                        if element.name == "face" && (name == "vertex_indices" || name == "vertex_index") {
+                            for k in 1..count.saturating_sub(1) {
+                                mesh.indices.extend([list[0], list[k], list[k + 1]].iter().map(|i| *i as usize))
+                            }
+                        }
+                    }
+                }
+            }
+
+            if element.name == "vertex" {
+                let get = |indices: &[Option<usize>]| -> Option<Vec<f64>> {
+                    indices.iter().map(|i| i.map(|i| values[i])).collect()
+                };
+                match get(&position) {
+                    Some(p) => mesh.positions.extend(p),
+                    None => return format_error("vertex without a position")
+                }
+                if let Some(n) = get(&normal) { normals.extend(n) }
+                if let Some(uv) = get(&uv) { uvs.extend(uv) }
+                if let Some(c) = get(&color) { colors.extend(c) }
+            }
+        }
+    }
+
+    // Attributes only some vertices have are dropped by validation
+    mesh.normals = Some(normals).filter(|n| !n.is_empty());
+    mesh.uvs = Some(uvs).filter(|uv| !uv.is_empty());
+    mesh.colors = Some(colors).filter(|c| !c.is_empty());
+    Ok(mesh)
+}
+
+/// Offset of the first occurrence of the given needle in the haystack
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ascii_ply_with_colors() {
+        let ply = b"ply\nformat ascii 1.0\ncomment a quad\nelement vertex 4\n\
+            property float x\nproperty float y\nproperty float z\n\
+            property uchar red\nproperty uchar green\nproperty uchar blue\n\
+            element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+            0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n\
+            4 0 1 2 3\n";
+        let mesh = parse_ply(ply).unwrap();
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.colors.as_ref().unwrap()[3..6], [0.0, 1.0, 0.0]);
+
+        assert_eq!(mesh.validate().unwrap().colors.unwrap()[6..9], [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn binary_ply() {
+        let mut ply = b"ply\nformat binary_big_endian 1.0\nelement vertex 3\n\
+            property float x\nproperty float y\nproperty float z\n\
+            element face 1\nproperty list uchar uint vertex_index\nend_header\n".to_vec();
+        for v in [0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0].iter() {
+            ply.extend_from_slice(&v.to_be_bytes())
+        }
+        ply.push(3);
+        for i in [0u32, 1, 2].iter() { ply.extend_from_slice(&i.to_be_bytes()) }
+
+        let mesh = parse_ply(&ply).unwrap();
+        assert_eq!(mesh.positions[3..6], [1.0, 0.0, 0.0]);
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,71 @@
+//! Loader for meshes in the STL format of 3D printing and CAD tools, in its
+//! ASCII and binary encodings. Only the triangle positions are read.
+
+use std::{fs, path::Path};
+use crate::shape::mesh::{TriangleMesh, Error, format_error};
+
+/// Load the ASCII or binary .stl file at the given path
+pub fn load_stl(path: &Path) -> Result<TriangleMesh, Error> {
+    parse_stl(&fs::read(path)?)
+}
+
+/// Read the triangles of the given contents of a .stl file. STL files don't
+/// share vertices between triangles, so each gets three of its own.
+pub fn parse_stl(bytes: &[u8]) -> Result<TriangleMesh, Error> {
+    // Binary files may also start with "solid", so tell them apart by size
+    let binary_count = bytes.get(80..84).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    let is_binary = binary_count.is_some_and(|count| bytes.len() == 84 + 50 * count);
+
+    let mut mesh = TriangleMesh::default();
+    if is_binary {
+        for triangle in bytes[84..].as_chunks::<50>().0 {
+            // Skip the facet normal, which is often missing or wrong anyway
+            for v in triangle[12..48].as_chunks::<4>().0 {
+                mesh.positions.push(f32::from_le_bytes(*v) as f64)
+            }
+        }
+    } else {
+        let text = match std::str::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(_) => return format_error("not an ASCII or binary STL file")
+        };
+        if !text.trim_start().starts_with("solid") { return format_error("missing solid") }
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            if words.next() != Some("vertex") { continue }
+            for _ in 0..3 {
+                match words.next().map(str::parse::<f64>) {
+                    Some(Ok(value)) => mesh.positions.push(value),
+                    _ => return format_error(format!("invalid vertex \"{}\"", line.trim()))
+                }
+            }
+        }
+        if !mesh.positions.len().is_multiple_of(9) { return format_error("facet without three vertices") }
+    }
+
+    mesh.indices = (0..mesh.positions.len() / 3).collect();
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ascii_and_binary_stl() {
+        let ascii = b"solid tri\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\n\
+            vertex 0 1 0\nendloop\nendfacet\nendsolid tri\n";
+        let mut binary = vec![0u8; 80];
+        binary.extend_from_slice(&1u32.to_le_bytes());
+        for v in [0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0].iter() {
+            binary.extend_from_slice(&v.to_le_bytes())
+        }
+        binary.extend_from_slice(&[0, 0]);
+
+        for bytes in [&ascii[..], &binary[..]].iter() {
+            let mesh = parse_stl(bytes).unwrap();
+            assert_eq!(mesh.positions, vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+            assert_eq!(mesh.indices, vec![0, 1, 2]);
+        }
+    }
+}
@@ -0,0 +1,571 @@
+//! Experimental backend that traces rays on the GPU with wgpu compute
+//! shaders, a first step toward interactive renders.
+//!
+//! The triangles of the scene are placed in world space and uploaded along
+//! with a bounding volume hierarchy over them. The GPU then finds what each
+//! camera ray hits and whether each ambient occlusion ray is blocked, in
+//! single precision. Surfaces the camera sees are found again on the CPU in
+//! double precision, close to where the GPU found them, and shaded there as
+//! usual. Images match those of `capture` but for the odd pixel along the
+//! silhouettes of objects.
+//!
+//! Only the ambient occlusion integrator and the debug views other than the
+//! heatmap are supported, for scenes of triangle meshes in plain groups,
+//! without alpha masks. Other scenes, and films with auxiliary passes,
+//! adaptive sampling or filters wider than a pixel, are rendered on the CPU.
+
+use std::{fmt, sync::mpsc, mem::size_of_val};
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+use crate::{
+    space::*,
+    film::Tile,
+    img::Img,
+    interaction::RayIntersection,
+    integrate::{Integrator, DebugView, ao::AoIntegrator, debug::DebugIntegrator},
+    sampler::Sampler,
+    scene::{Scene, node::{Aggregate, SceneNode}},
+    shape::triangle::Triangle,
+    accelerators::bvh::{BVHTree, BVHPrimitiveInfo, TreeChild, BVH_WIDTH, BVH_STACK_SIZE, has_cut_outs},
+    Accel, TILE_SIZE, capture_accel, map_tiles, new_sampler
+};
+
+/// Source of the compute shader, before the width of the hierarchy and the
+/// size of its traversal stack are filled in
+const SHADER: &str = include_str!("trace.wgsl");
+
+/// Threads per workgroup, as declared by the shader
+const WORKGROUP_SIZE: usize = 64;
+
+/// Most rays traced by each dispatch of the shader, to keep its buffers
+/// within the limits of most adapters
+const MAX_RAYS: usize = 1 << 20;
+
+/// Count of the children of interior nodes in the uploaded hierarchy
+const INTERIOR: u32 = u32::MAX;
+
+/// Distance written for rays that hit nothing
+const MISS: f32 = -1.0;
+
+/// How far past the GPU's hit, relative to its distance, the CPU looks for
+/// the surface it hit
+const HIT_TOLERANCE: f64 = 1e-4;
+
+/// Distance occlusion rays skip, relative to the magnitude of their origin,
+/// so that they don't hit the surface they leave in single precision
+const OCCLUSION_EPSILON: f64 = 1e-5;
+
+/// Problems starting the GPU backend
+#[derive(Debug)]
+pub enum Error {
+    /// No graphics adapter could be found
+    NoAdapter,
+
+    /// The adapter could not provide a device for compute shaders
+    Device(wgpu::RequestDeviceError)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NoAdapter => write!(f, "no graphics adapter found"),
+            Error::Device(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A GPU device set up to trace rays, for rendering any number of scenes.
+/// See the module documentation for what it supports.
+pub struct GpuRenderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline
+}
+
+/// Which rays the shader looks for hits along
+#[derive(Copy, Clone)]
+enum Mode {
+    /// Nearest hit, for camera rays
+    Nearest = 0,
+
+    /// Any hit, for occlusion rays
+    Any = 1
+}
+
+/// How camera samples are shaded once the surfaces they see are known
+#[derive(Copy, Clone)]
+enum Shading {
+    Occlusion(AoIntegrator),
+    Debug(DebugIntegrator)
+}
+
+/// The triangles of a scene and the hierarchy over them, in GPU memory
+struct Geometry {
+    children: wgpu::Buffer,
+    triangles: wgpu::Buffer
+}
+
+/// A child slot of a node of the uploaded hierarchy, as the shader reads it
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct GpuChild {
+    min: [f32; 3],
+    /// Index of the child's node, or of the first triangle of a leaf
+    offset: u32,
+    max: [f32; 3],
+    /// INTERIOR, the number of triangles of a leaf, or zero if empty
+    count: u32
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct GpuTriangle {
+    p: [[f32; 4]; 3]
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct GpuRay {
+    origin: [f32; 3],
+    t_min: f32,
+    dir: [f32; 3],
+    t_max: f32
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct Params {
+    mode: u32,
+    count: u32,
+    pad: [u32; 2]
+}
+
+/// The samples of the pixels of a tile, shaded but for the occlusion rays
+/// they wait on
+struct ShadedTile {
+    tile: Tile,
+
+    /// Colour of each sample, pixel by pixel
+    samples: Vec<Color>,
+
+    /// Occlusion rays, with the index of the sample each one opens up
+    occlusion: Vec<(usize, GpuRay)>
+}
+
+impl GpuRenderer {
+    /// Set up the fastest graphics adapter available
+    pub fn new() -> Result<GpuRenderer, Error> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        })).ok_or(Error::NoAdapter)?;
+
+        // Ask for the largest buffers the adapter allows, for large scenes
+        let descriptor = wgpu::DeviceDescriptor {
+            label: Some("lasgun"),
+            features: wgpu::Features::empty(),
+            limits: adapter.limits()
+        };
+        let (device, queue) = pollster::block_on(adapter.request_device(&descriptor, None)).map_err(Error::Device)?;
+
+        let source = SHADER
+            .replace("{WIDTH}", &BVH_WIDTH.to_string())
+            .replace("{STACK_SIZE}", &BVH_STACK_SIZE.to_string());
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("trace"),
+            source: wgpu::ShaderSource::Wgsl(source.into())
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("trace"),
+            layout: None,
+            module: &module,
+            entry_point: "main"
+        });
+        Ok(GpuRenderer { device, queue, pipeline })
+    }
+
+    /// Record an image of the scene on the given film, as with `capture`.
+    /// Returns whether rays were traced on the GPU, or false if the scene or
+    /// film isn't supported and the image was rendered on the CPU instead.
+    pub fn capture(&self, scene: &Scene, film: &mut impl Img) -> bool {
+        let root = Accel::from(scene);
+        let gpu = supported(scene, film).and_then(|shading| Some((shading, self.upload(scene)?)));
+        let (shading, geometry) = match gpu {
+            Some(gpu) => gpu,
+            None => {
+                capture_accel(&root, film);
+                return false
+            }
+        };
+
+        // Trace as many tiles at once as fit in a few dispatches
+        let spp = new_sampler(scene, &scene.camera).samples_per_pixel() as usize;
+        let per_batch = (MAX_RAYS / (TILE_SIZE * TILE_SIZE) as usize / spp).max(1);
+        let mut tiles = Tile::split(film.w(), film.h(), TILE_SIZE, false).into_iter().peekable();
+        while tiles.peek().is_some() {
+            let batch = tiles.by_ref().take(per_batch).collect();
+            for tile in self.capture_tiles(&root, &geometry, shading, batch) {
+                tile.merge_into(film)
+            }
+        }
+        true
+    }
+
+    /// Render every pixel of the given tiles
+    fn capture_tiles(&self, root: &Accel, geometry: &Geometry, shading: Shading, tiles: Vec<Tile>) -> Vec<Tile> {
+        let scene = root.scene;
+        let tiles = map_tiles(scene.threads, tiles, |tile| {
+            let mut rays = vec![];
+            for_each_sample(scene, &tile, |ray, _| rays.push(GpuRay::new(&ray, ray.t_min)));
+            (tile, rays)
+        });
+        let rays: Vec<GpuRay> = tiles.iter().flat_map(|(_, rays)| rays.iter().copied()).collect();
+        let mut hits = self.trace(geometry, &rays, Mode::Nearest).into_iter();
+        let tiles = tiles.into_iter()
+            .map(|(tile, rays)| (tile, hits.by_ref().take(rays.len()).collect::<Vec<f32>>()))
+            .collect();
+
+        // Shade the samples, replaying the sampler so that each one draws the
+        // same numbers as it would on the CPU
+        let mut tiles = map_tiles(scene.threads, tiles, |(tile, hits)| {
+            let (mut samples, mut occlusion) = (Vec::with_capacity(hits.len()), vec![]);
+            for_each_sample(scene, &tile, |ray, sampler| {
+                let isect = refine(root, &ray, hits[samples.len()]);
+                let color = match shading {
+                    Shading::Debug(debug) => debug.shade(root, &ray, isect.as_ref()),
+                    Shading::Occlusion(ao) => match isect {
+                        None => scene.background.bg(&ray.d.normalize()),
+                        Some(isect) => {
+                            if let Some(ray) = ao.occlusion_ray(&ray, &isect, sampler) {
+                                let origin = ray.origin.x.abs().max(ray.origin.y.abs()).max(ray.origin.z.abs());
+                                let t_min = ray.t_min.max(OCCLUSION_EPSILON * (1.0 + origin));
+                                occlusion.push((samples.len(), GpuRay::new(&ray, t_min)))
+                            }
+                            Color::zero()
+                        }
+                    }
+                };
+                samples.push(color)
+            });
+            ShadedTile { tile, samples, occlusion }
+        });
+
+        let rays: Vec<GpuRay> = tiles.iter().flat_map(|tile| tile.occlusion.iter().map(|(_, ray)| *ray)).collect();
+        let mut hits = self.trace(geometry, &rays, Mode::Any).into_iter();
+        for shaded in tiles.iter_mut() {
+            for ((sample, _), hit) in shaded.occlusion.iter().zip(hits.by_ref()) {
+                if hit == MISS { shaded.samples[*sample] = Color::from_value(1.0) }
+            }
+        }
+
+        let spp = new_sampler(scene, &scene.camera).samples_per_pixel() as usize;
+        tiles.into_iter().map(|ShadedTile { mut tile, samples, .. }| {
+            let mut pixels = samples.chunks(spp);
+            for y in tile.y..(tile.y + tile.tile_h) {
+                for x in tile.x..(tile.x + tile.tile_w) {
+                    let color = pixels.next().unwrap().iter().fold(Color::zero(), |sum, sample| sum + *sample);
+                    tile.set(x, y, &(color / spp as f64).into())
+                }
+            }
+            tile
+        }).collect()
+    }
+
+    /// Upload the triangles of the scene along with a hierarchy over them,
+    /// unless the scene has anything but triangles in plain groups or is too
+    /// large for the device
+    fn upload(&self, scene: &Scene) -> Option<Geometry> {
+        let mut triangles = vec![];
+        if has_cut_outs(scene, &scene.root) || !collect(scene, &scene.root, &Transformation::identity(), &mut triangles) {
+            return None
+        }
+        if triangles.is_empty() { return None }
+
+        let (children, triangles) = pack(scene, &triangles);
+        let limit = self.device.limits().max_storage_buffer_binding_size as usize;
+        if size_of_val(children.as_slice()).max(size_of_val(triangles.as_slice())) > limit { return None }
+
+        Some(Geometry {
+            children: self.storage("children", bytemuck::cast_slice(&children)),
+            triangles: self.storage("triangles", bytemuck::cast_slice(&triangles))
+        })
+    }
+
+    /// Storage buffer holding the given bytes
+    fn storage(&self, label: &str, contents: &[u8]) -> wgpu::Buffer {
+        self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents,
+            usage: wgpu::BufferUsages::STORAGE
+        })
+    }
+
+    /// Distance along each of the given rays to what it hits, or MISS
+    fn trace(&self, geometry: &Geometry, rays: &[GpuRay], mode: Mode) -> Vec<f32> {
+        let mut hits = Vec::with_capacity(rays.len());
+        for rays in rays.chunks(MAX_RAYS) {
+            hits.extend(self.dispatch(geometry, rays, mode))
+        }
+        hits
+    }
+
+    /// Trace the given rays, no more than MAX_RAYS, in a single dispatch
+    fn dispatch(&self, geometry: &Geometry, rays: &[GpuRay], mode: Mode) -> Vec<f32> {
+        let device = &self.device;
+        let size = (rays.len() * std::mem::size_of::<f32>()) as u64;
+        let hits = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("hits"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+        let params = Params { mode: mode as u32, count: rays.len() as u32, pad: [0; 2] };
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("params"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM
+        });
+        let rays_buffer = self.storage("rays", bytemuck::cast_slice(rays));
+
+        let buffers = [&geometry.children, &geometry.triangles, &rays_buffer, &hits, &params];
+        let entries: Vec<_> = buffers.iter().enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry { binding: binding as u32, resource: buffer.as_entire_binding() })
+            .collect();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("trace"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &entries
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("trace") });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("trace") });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(rays.len().div_ceil(WORKGROUP_SIZE) as u32, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&hits, 0, &readback, 0, size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| { sender.send(result).ok(); });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv().unwrap().expect("could not read ray hits back from the GPU");
+        let hits = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        readback.unmap();
+        hits
+    }
+}
+
+/// How to shade the samples of the scene on the given film, if the GPU can
+/// trace its rays
+fn supported(scene: &Scene, film: &impl Img) -> Option<Shading> {
+    if film.has_aovs() || scene.adaptive.is_some() || !scene.filter.is_pixel_box() { return None }
+    match scene.integrator {
+        Integrator::AmbientOcclusion => Some(Shading::Occlusion(AoIntegrator::new(scene.ao_distance))),
+        Integrator::Debug(DebugView::Heatmap) => None,
+        Integrator::Debug(view) => Some(Shading::Debug(DebugIntegrator::new(view))),
+        _ => None
+    }
+}
+
+/// Add the world-space vertices of the triangles in the given group and its
+/// children to the list. Returns false if the group isn't plain or holds
+/// anything but meshes and groups.
+fn collect(scene: &Scene, aggregate: &Aggregate, parent: &Transformation, triangles: &mut Vec<[Point; 3]>) -> bool {
+    if !aggregate.is_plain() { return false }
+    let transform = aggregate.transform.concat(parent);
+    aggregate.contents.iter().all(|node| match node {
+        SceneNode::Geometry(..) => false,
+        SceneNode::Mesh(obj, _) => {
+            let mesh = scene.mesh(*obj).unwrap();
+            triangles.extend((0..mesh.triangles.triangle_count()).map(|i| {
+                let triangle = Triangle::new(mesh, i as u32);
+                [triangle.p0(), triangle.p1(), triangle.p2()].map(|p| transform.transform_point(p))
+            }));
+            true
+        },
+        SceneNode::Group(aggregate) => collect(scene, aggregate, &transform, triangles)
+    })
+}
+
+/// The children of the nodes of a hierarchy over the given triangles, and
+/// the triangles in the order its leaves refer to them
+fn pack(scene: &Scene, triangles: &[[Point; 3]]) -> (Vec<GpuChild>, Vec<GpuTriangle>) {
+    let mut prim_info: Vec<BVHPrimitiveInfo> = triangles.iter()
+        .enumerate()
+        .map(|(i, [p0, p1, p2])| BVHPrimitiveInfo::new(i, Bounds::new(*p0, *p1).point_union(p2)))
+        .collect();
+    let tree = BVHTree::new(scene.bvh_build, &mut prim_info, triangles.len(), None);
+    let children = tree.children().map(|(bounds, child)| GpuChild::new(&bounds, child)).collect();
+    let triangles = tree.order().iter().map(|i| GpuTriangle::new(&triangles[*i])).collect();
+    (children, triangles)
+}
+
+/// Call f with the camera ray of each sample of each pixel of the tile, pixel
+/// by pixel, and the sampler as the camera left it. Pixels are seeded as
+/// `capture_pixel` seeds them.
+fn for_each_sample(scene: &Scene, tile: &Tile, mut f: impl FnMut(Ray, &mut Sampler)) {
+    let camera = &scene.camera;
+    let mut sampler = new_sampler(scene, camera);
+    for y in tile.y..(tile.y + tile.tile_h) {
+        for x in tile.x..(tile.x + tile.tile_w) {
+            sampler.start_pixel((y as usize * tile.w() as usize + x as usize) as u64);
+            for i in 0..sampler.samples_per_pixel() {
+                sampler.start_sample(i);
+                let (ray, _) = camera.sample_at(x, y, tile, &mut sampler);
+                f(ray, &mut sampler)
+            }
+        }
+    }
+}
+
+/// The surface the given ray hits, found in double precision near the
+/// distance t at which the GPU found a hit, if any. Looks along the whole ray
+/// if the GPU's distance was too short.
+fn refine(root: &Accel, ray: &Ray, t: f32) -> Option<RayIntersection> {
+    if t == MISS { return None }
+    let t_max = (f64::from(t) * (1.0 + HIT_TOLERANCE)).min(ray.t_max);
+    let mut isect = RayIntersection::default();
+    if root.intersect_opaque(&ray.within(ray.t_min, t_max), &mut isect).is_some() { return Some(isect) }
+
+    let mut isect = RayIntersection::default();
+    root.intersect_opaque(ray, &mut isect).map(|_| isect)
+}
+
+impl GpuChild {
+    fn new(bounds: &Bounds, child: TreeChild) -> GpuChild {
+        let (offset, count) = match child {
+            TreeChild::Empty => return GpuChild::zeroed(),
+            TreeChild::Leaf(offset, count) => (offset as u32, count as u32),
+            TreeChild::Interior(node) => (node as u32, INTERIOR)
+        };
+        let (min, max): ([f64; 3], [f64; 3]) = (bounds.min.into(), bounds.max.into());
+        GpuChild { min: min.map(f32_round_down), offset, max: max.map(f32_round_up), count }
+    }
+}
+
+impl GpuTriangle {
+    fn new(p: &[Point; 3]) -> GpuTriangle {
+        GpuTriangle { p: p.map(|p| [p.x as f32, p.y as f32, p.z as f32, 0.0]) }
+    }
+}
+
+impl GpuRay {
+    /// The given ray in single precision, starting at t_min
+    fn new(ray: &Ray, t_min: f64) -> GpuRay {
+        GpuRay {
+            origin: [ray.origin.x as f32, ray.origin.y as f32, ray.origin.z as f32],
+            t_min: t_min as f32,
+            dir: [ray.d.x as f32, ray.d.y as f32, ray.d.z as f32],
+            // Shaders may not handle infinity
+            t_max: ray.t_max.min(f64::from(f32::MAX)) as f32
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{MaterialRef, HdrFilm, capture};
+
+    /// A floor of many triangles seen from above, with a box on it
+    fn scene(integrator: Integrator) -> Scene {
+        let mut scene = Scene::new();
+        scene.set_perspective_camera(60.0).look_at([0.0, 4.0, 3.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let mut obj = String::new();
+        for i in 0..=8 {
+            for j in 0..=8 { obj += &format!("v {} 0 {}\n", i as f64 - 4.0, j as f64 - 4.0) }
+        }
+        for i in 0..8 {
+            for j in 0..8 {
+                let v = i * 9 + j + 1;
+                obj += &format!("f {} {} {} {}\n", v, v + 1, v + 10, v + 9)
+            }
+        }
+        let floor = scene.parse_obj(&obj).unwrap();
+        let cube = scene.parse_obj("v -0.5 0 -0.5\nv 0.5 0 -0.5\nv 0.5 0 0.5\nv -0.5 0 0.5\nv -0.5 1 -0.5\nv 0.5 1 -0.5\nv 0.5 1 0.5\nv -0.5 1 0.5\n\
+            f 1 2 3 4\nf 5 8 7 6\nf 1 5 6 2\nf 2 6 7 3\nf 3 7 8 4\nf 4 8 5 1\n").unwrap();
+        scene.root.add_obj_of(floor, MaterialRef::default());
+        let mut group = Aggregate::new();
+        group.translate([1.0, 0.0, 0.0]).add_obj_of(cube, MaterialRef::default());
+        scene.root.add_group(group);
+        scene.set_integrator(integrator);
+        scene
+    }
+
+    #[test]
+    fn packs_every_triangle_within_its_leaf() {
+        let scene = scene(Integrator::AmbientOcclusion);
+        let mut triangles = vec![];
+        assert!(collect(&scene, &scene.root, &Transformation::identity(), &mut triangles));
+        assert_eq!(triangles.len(), 128 + 12);
+        let (children, packed) = pack(&scene, &triangles);
+        assert_eq!(children.len() % BVH_WIDTH, 0);
+
+        // Every triangle is reached from the root once, within the bounds of
+        // every node along the way
+        let mut seen = vec![0; packed.len()];
+        let mut to_visit = vec![(0, [f32::MIN; 3], [f32::MAX; 3])];
+        while let Some((node, min, max)) = to_visit.pop() {
+            for child in &children[node * BVH_WIDTH..(node + 1) * BVH_WIDTH] {
+                assert!((0..3).all(|axis| min[axis] <= child.min[axis] && child.max[axis] <= max[axis]) || child.count == 0);
+                match child.count {
+                    0 => (),
+                    INTERIOR => to_visit.push((child.offset as usize, child.min, child.max)),
+                    count => for i in child.offset..(child.offset + count) {
+                        seen[i as usize] += 1;
+                        for p in packed[i as usize].p.iter() {
+                            assert!((0..3).all(|axis| child.min[axis] <= p[axis] && p[axis] <= child.max[axis]))
+                        }
+                    }
+                }
+            }
+        }
+        assert!(seen.iter().all(|count| *count == 1));
+    }
+
+    #[test]
+    fn falls_back_for_unsupported_scenes() {
+        let mut scene = scene(Integrator::Path);
+        assert!(supported(&scene, &HdrFilm::new(4, 4)).is_none());
+
+        scene.set_integrator(Integrator::AmbientOcclusion);
+        assert!(supported(&scene, &HdrFilm::new(4, 4)).is_some());
+        scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, MaterialRef::default());
+        assert!(!collect(&scene, &scene.root, &Transformation::identity(), &mut vec![]));
+    }
+
+    #[test]
+    fn matches_cpu_render() {
+        // Most machines running the tests have no adapter to try
+        let renderer = match GpuRenderer::new() {
+            Ok(renderer) => renderer,
+            Err(_) => return
+        };
+        for integrator in [Integrator::AmbientOcclusion, Integrator::Debug(DebugView::GeometricNormal)] {
+            let scene = scene(integrator);
+            let (mut gpu, mut cpu) = (HdrFilm::new(32, 32), HdrFilm::new(32, 32));
+            assert!(renderer.capture(&scene, &mut gpu));
+            capture(&scene, &mut cpu);
+
+            let mut differences = 0;
+            for y in 0..32 {
+                for x in 0..32 {
+                    if gpu.get(x, y) != cpu.get(x, y) { differences += 1 }
+                }
+            }
+            assert!(differences < 16, "{} pixels differ", differences);
+        }
+    }
+}
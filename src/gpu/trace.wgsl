@@ -0,0 +1,156 @@
+// Nearest or any hit of each ray with the triangles of the scene, found by
+// walking the bounding volume hierarchy built on the CPU. Writes the distance
+// along each ray to the hit, or MISS. WIDTH and STACK_SIZE are filled in from
+// the CPU hierarchy before the shader is compiled.
+
+// A child slot of a node. Interior children have a count of INTERIOR and the
+// offset of their node, leaves the offset and count of their triangles and
+// empty slots a count of zero.
+struct Child {
+    min: vec3<f32>,
+    offset: u32,
+    max: vec3<f32>,
+    count: u32,
+}
+
+struct Triangle {
+    p0: vec4<f32>,
+    p1: vec4<f32>,
+    p2: vec4<f32>,
+}
+
+struct Ray {
+    origin: vec3<f32>,
+    t_min: f32,
+    dir: vec3<f32>,
+    t_max: f32,
+}
+
+struct Params {
+    mode: u32,
+    count: u32,
+    pad0: u32,
+    pad1: u32,
+}
+
+@group(0) @binding(0) var<storage, read> children: array<Child>;
+@group(0) @binding(1) var<storage, read> triangles: array<Triangle>;
+@group(0) @binding(2) var<storage, read> rays: array<Ray>;
+@group(0) @binding(3) var<storage, read_write> hits: array<f32>;
+@group(0) @binding(4) var<uniform> params: Params;
+
+const WIDTH: u32 = {WIDTH}u;
+const STACK_SIZE: u32 = {STACK_SIZE}u;
+const INTERIOR: u32 = 0xffffffffu;
+const ANY_HIT: u32 = 1u;
+const MISS: f32 = -1.0;
+
+// Distance at which the ray enters the bounds of the child within
+// [t_min, t_max], or MISS
+fn enter(child: Child, origin: vec3<f32>, dinv: vec3<f32>, t_min: f32, t_max: f32) -> f32 {
+    let t0 = (child.min - origin) * dinv;
+    let t1 = (child.max - origin) * dinv;
+    let near = min(t0, t1);
+    let far = max(t0, t1);
+    let t_near = max(max(near.x, near.y), max(near.z, t_min));
+
+    // Widen the exit distance by its rounding error so that rays grazing a
+    // box are not missed
+    let t_far = min(min(far.x, far.y), min(far.z, t_max)) * 1.0000004;
+    return select(MISS, t_near, t_near <= t_far);
+}
+
+// Distance along the ray to the triangle within (t_min, t_max), or MISS
+fn intersect(triangle: Triangle, ray: Ray, t_max: f32) -> f32 {
+    let e1 = triangle.p1.xyz - triangle.p0.xyz;
+    let e2 = triangle.p2.xyz - triangle.p0.xyz;
+    let p = cross(ray.dir, e2);
+    let det = dot(e1, p);
+    if (det == 0.0) {
+        return MISS;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = ray.origin - triangle.p0.xyz;
+    let u = dot(s, p) * inv_det;
+    let q = cross(s, e1);
+    let v = dot(ray.dir, q) * inv_det;
+    let t = dot(e2, q) * inv_det;
+    if (u < 0.0 || v < 0.0 || u + v > 1.0 || t <= ray.t_min || t >= t_max) {
+        return MISS;
+    }
+    return t;
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.count) {
+        return;
+    }
+    let ray = rays[id.x];
+
+    // Keep the inverse direction finite along axes the ray doesn't move along
+    let tiny = select(vec3<f32>(1e-20), vec3<f32>(-1e-20), ray.dir < vec3<f32>(0.0));
+    let dinv = 1.0 / select(ray.dir, tiny, abs(ray.dir) < vec3<f32>(1e-20));
+
+    // Interior nodes left to visit, with the distance at which the ray enters
+    // them
+    var stack: array<u32, STACK_SIZE>;
+    var near: array<f32, STACK_SIZE>;
+    var size = 0u;
+    var node = 0u;
+    var t = ray.t_max;
+    var hit = MISS;
+    loop {
+        for (var i = 0u; i < WIDTH; i = i + 1u) {
+            let child = children[node * WIDTH + i];
+            if (child.count == 0u) {
+                continue;
+            }
+            let t_enter = enter(child, ray.origin, dinv, ray.t_min, t);
+            if (t_enter < 0.0) {
+                continue;
+            }
+            if (child.count == INTERIOR) {
+                if (size < STACK_SIZE) {
+                    stack[size] = child.offset;
+                    near[size] = t_enter;
+                    size = size + 1u;
+                }
+                continue;
+            }
+
+            for (var j = child.offset; j < child.offset + child.count; j = j + 1u) {
+                let t_hit = intersect(triangles[j], ray, t);
+                if (t_hit < 0.0) {
+                    continue;
+                }
+                if (params.mode == ANY_HIT) {
+                    hits[id.x] = t_hit;
+                    return;
+                }
+                t = t_hit;
+                hit = t_hit;
+            }
+        }
+
+        // Continue with the last node stacked, skipping those that the ray
+        // enters beyond the nearest hit so far
+        var found = false;
+        loop {
+            if (size == 0u) {
+                break;
+            }
+            size = size - 1u;
+            if (near[size] <= t) {
+                node = stack[size];
+                found = true;
+                break;
+            }
+        }
+        if (!found) {
+            break;
+        }
+    }
+    hits[id.x] = hit;
+}
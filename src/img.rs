@@ -1,4 +1,5 @@
 use std::ops::{Index, IndexMut};
+use crate::{tonemap::Tonemap, film::Aovs};
 
 /// RGBA pixel representation, with A being the Alpha channel
 /// Each item has a color value between 0 and 255
@@ -13,7 +14,9 @@ pub trait PixelBuffer: Index<usize, Output = Pixel> + IndexMut<usize> {
 impl PixelBuffer for Vec<Pixel> {}
 impl PixelBuffer for [Pixel] {}
 
-pub trait Img: Index<usize, Output = Pixel> + IndexMut<usize> {
+/// An image that may be rendered onto. Implementors decide how the radiance
+/// reaching each pixel is stored.
+pub trait Img {
 
     /// Width of the image, in pixels
     fn w(&self) -> u32;
@@ -39,29 +42,33 @@ pub trait Img: Index<usize, Output = Pixel> + IndexMut<usize> {
         self.w() as usize * y as usize + x as usize
     }
 
-    /// Assign the pixel at the given x/y position to the given color. Default
-    /// implementation expects each RGB color channel in color to have range
-    /// [0,1]
-    #[inline]
-    fn set(&mut self, x: u32, y: u32, color: &[f64; 3]) {
-        debug_assert!(x < self.w());
-        debug_assert!(y < self.h());
-        let offset = self.offset(x, y);
-        set_pixel_color(&mut self[offset], color)
-    }
+    /// Assign the pixel at the given x/y position to the given color, the
+    /// linear RGB radiance arriving at that pixel. Images with 8-bit pixels
+    /// may use `set_pixel_color`.
+    fn set(&mut self, x: u32, y: u32, color: &[f64; 3]);
+
+    /// Whether the image records auxiliary passes with `set_aovs`. Renderers
+    /// skip computing them for images that don't.
+    #[inline] fn has_aovs(&self) -> bool { false }
+
+    /// Record the auxiliary pass values of the pixel at the given x/y
+    /// position. Ignored by default.
+    #[inline] fn set_aovs(&mut self, _x: u32, _y: u32, _aovs: &Aovs) {}
 }
 
-/// Set the color of the given pixel
+/// Set the color of the given pixel to the given linear radiance, mapped into
+/// display range with the given tone mapping settings
 #[inline]
-pub fn set_pixel_color(pixel: &mut Pixel, color: &[f64; 3]) {
+pub fn set_pixel_color(pixel: &mut Pixel, color: &[f64; 3], tonemap: &Tonemap) {
+    let color = tonemap.apply(color);
     pixel[0] = to_byte(color[0]);
     pixel[1] = to_byte(color[1]);
     pixel[2] = to_byte(color[2]);
     pixel[3] = 255;
 }
 
-/// Convert a colour channel from betheen 0 and 1 to an interger between 0 and y55
+/// Convert a colour channel from between 0 and 1 to an integer between 0 and 255
 #[inline]
 fn to_byte(channel: f64) -> u8 {
-    (channel.max(0.0).min(1.0) * 255.0).round() as u8
+    (channel * 255.0).round() as u8
 }
@@ -0,0 +1,87 @@
+use crate::{
+    space::{*, normal::Normal3},
+    core::bxdf::sampling::cosine_sample_hemisphere,
+    interaction::{SurfaceInteraction, RayIntersection},
+    sampler::Sampler,
+    Accel,
+};
+
+/// Ambient occlusion. Shades each surface seen by the camera with the
+/// fraction of its cosine-weighted hemisphere that is open within some
+/// distance, ignoring materials and lights. Useful for quick clay previews of
+/// the scene geometry and for baking occlusion maps.
+///
+/// Each camera sample casts a single occlusion ray, so supersampling averages
+/// out the noise.
+#[derive(Debug, Copy, Clone)]
+pub struct AoIntegrator {
+    /// Distance beyond which surfaces no longer occlude, if any
+    distance: Option<f64>
+}
+
+impl AoIntegrator {
+    pub fn new(distance: Option<f64>) -> AoIntegrator {
+        AoIntegrator { distance }
+    }
+
+    /// Estimate the occlusion of the surface seen along the given ray. Rays
+    /// that miss the scene pick up the background.
+    pub fn li(&self, root: &Accel, ray: &Ray, sampler: &mut Sampler) -> Color {
+        let mut isect = RayIntersection::default();
+        if root.intersect_opaque(ray, &mut isect).is_none() {
+            return root.scene.background.bg(&ray.d.normalize())
+        }
+
+        match self.occlusion_ray(ray, &isect, sampler) {
+            Some(ray) if !root.occluded(&ray) => Color::from_value(1.0),
+            _ => Color::zero()
+        }
+    }
+
+    /// Sample a direction around the surface the given ray hits and return
+    /// the ray along it, which counts as open if nothing blocks it. Returns
+    /// None for directions the surface itself blocks.
+    pub(crate) fn occlusion_ray(&self, ray: &Ray, isect: &RayIntersection, sampler: &mut Sampler) -> Option<Ray> {
+        // Sample around the shading normal on the side of the camera
+        let interaction = SurfaceInteraction::from(ray, isect);
+        let ng = interaction.ng();
+        let n = Normal3(interaction.ns()).face_forward(ng).0;
+        let (s, t) = coordinate_system(&n);
+        let w = cosine_sample_hemisphere(&sampler.get_2d());
+        let wi = s * w.x + t * w.y + n * w.z;
+
+        // Directions that the shading normal tilts below the surface are
+        // blocked by the surface itself
+        if wi.dot(ng) <= 0.0 { return None }
+
+        Some(interaction.spawn_ray(wi).within(0.0, self.distance.unwrap_or(f64::INFINITY)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{scene::Scene, MaterialRef, SamplerKind};
+
+    /// Average occlusion at the centre of a floor, over many samples
+    fn openness(scene: &Scene, distance: Option<f64>) -> f64 {
+        let root = Accel::from(scene);
+        let mut sampler = Sampler::new(SamplerKind::Uniform, 1);
+        sampler.start_pixel(0);
+        let ray = Ray::new(Point::new(0.0, 1.0, 0.0), -Vector::unit_y());
+        let ao = AoIntegrator::new(distance);
+        (0..1000).map(|_| ao.li(&root, &ray, &mut sampler).x).sum::<f64>() / 1000.0
+    }
+
+    #[test]
+    fn occludes_within_distance() {
+        let mut scene = Scene::new();
+        scene.root.add_plane([0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [10.0, 10.0], MaterialRef::default());
+        assert_eq!(openness(&scene, None), 1.0);
+
+        // A ceiling covers most of the hemisphere, unless it's out of reach
+        scene.root.add_plane([0.0, 2.0, 0.0], [0.0, -1.0, 0.0], [100.0, 100.0], MaterialRef::default());
+        assert!(openness(&scene, None) < 0.05);
+        assert_eq!(openness(&scene, Some(1.5)), 1.0);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{
+    space::{*, normal::Normal3},
+    interaction::{SurfaceInteraction, RayIntersection},
+    film::Aovs,
+    Accel,
+};
+
+/// Sample points for the BSDF estimates of the albedo pass. Few and fixed, so
+/// that the pass is quick and free of noise.
+const ALBEDO_SAMPLES: [Point2f; 4] = [
+    Point2f { x: 0.25, y: 0.25 },
+    Point2f { x: 0.75, y: 0.25 },
+    Point2f { x: 0.25, y: 0.75 },
+    Point2f { x: 0.75, y: 0.75 }
+];
+
+/// Sum of the auxiliary pass values of the first surfaces seen by the camera
+/// samples of a pixel. See `Aovs`.
+#[derive(Debug)]
+pub struct AovSum {
+    normal: Vector,
+    albedo: Color,
+    depth: f64,
+
+    /// Number of samples added, and how many of those saw a surface
+    samples: u32,
+    hits: u32,
+
+    /// Object ID of the first sample to see a surface
+    id: Option<u32>
+}
+
+impl AovSum {
+    pub fn new() -> AovSum {
+        AovSum { normal: Vector::zero(), albedo: Color::zero(), depth: 0.0, samples: 0, hits: 0, id: None }
+    }
+
+    /// Add the values of the first surface seen along the given camera ray
+    pub fn add(&mut self, root: &Accel, ray: &Ray) {
+        self.samples += 1;
+        let mut isect = RayIntersection::default();
+        let shape = match root.intersect_opaque(ray, &mut isect) {
+            Some(shape) => shape,
+            None => return
+        };
+
+        let material = root.scene.material(shape.material().unwrap_or(isect.material));
+        let mut interaction = SurfaceInteraction::from(ray, &isect);
+        self.normal += Normal3(interaction.ns()).face_forward(interaction.wo).0;
+        self.albedo += if material.is_emissive() {
+            material.albedo(&interaction, root.scene)
+        } else {
+            // Light the surface scatters toward the camera under even white
+            // light, which sums up all its layers and blends
+            material.scattering(&mut interaction, root.scene).rho_hd(&interaction.wo, &ALBEDO_SAMPLES)
+        };
+        self.depth += isect.t * ray.d.magnitude();
+        self.hits += 1;
+        self.id.get_or_insert(isect.id);
+    }
+
+    /// Average of the values added so far. Normals and albedo are averaged
+    /// over all samples, so they fade out at the edges of objects; depth is
+    /// averaged over the samples that saw a surface.
+    pub fn average(&self) -> Aovs {
+        if self.hits == 0 { return Aovs::none() }
+        let weight = 1.0 / self.samples as f64;
+        let (n, a) = (self.normal * weight, self.albedo * weight);
+        Aovs {
+            normal: [n.x as f32, n.y as f32, n.z as f32],
+            depth: (self.depth / self.hits as f64) as f32,
+            albedo: [a.x as f32, a.y as f32, a.z as f32],
+            id: self.id.unwrap_or(Aovs::NO_ID)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{scene::Scene, Material, MaterialRef, FilmSet, Img, Aovs, capture};
+
+    #[test]
+    fn records_first_surface() {
+        let mut scene = Scene::new();
+        let camera = scene.set_perspective_camera(30.0);
+        camera.look_at([0.0, 0.0, 5.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        scene.root.add_sphere([10.0, 0.0, 0.0], 1.0, MaterialRef::default());
+        let blue = scene.add_material(Material::matte([0.2, 0.4, 0.6], 0.0));
+        scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, blue);
+
+        let mut film = FilmSet::new(9, 9);
+        capture(&scene, &mut film);
+
+        let centre = film.aovs_at(4, 4);
+        assert_eq!(centre.id, 1);
+        assert!((centre.depth - 4.0).abs() < 0.05);
+        assert!((centre.normal[2] - 1.0).abs() < 0.01);
+        assert!((centre.albedo[1] - 0.4).abs() < 1e-6);
+        assert_eq!(film.aovs_at(0, 0), Aovs::none());
+        assert_eq!((film.w(), film.h()), (9, 9));
+    }
+}
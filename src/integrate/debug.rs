@@ -0,0 +1,147 @@
+use serde::{Serialize, Deserialize};
+use crate::{
+    space::*,
+    interaction::{SurfaceInteraction, RayIntersection},
+    primitive::Primitive,
+    stats,
+    Accel,
+};
+
+/// Number of intersection tests shown in red by the heatmap view. Rays that
+/// take more are clamped to red.
+const HEATMAP_MAX_TESTS: f64 = 100.0;
+
+/// What the debug integrator shows of the first surface seen by the camera
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DebugView {
+    /// Number of bounding volume hierarchy nodes and primitives tested to find
+    /// the surface, from blue for none through green and yellow to red for
+    /// 100 or more. Shows the parts of the scene that are slow to trace.
+    Heatmap,
+
+    /// Geometric normal, with each component mapped from [-1, 1] to [0, 1]
+    GeometricNormal,
+
+    /// Shading normal, interpolated and bump mapped, mapped as the geometric
+    /// normal is. Compare to it to find faulty mesh normals.
+    ShadingNormal,
+
+    /// Surface coordinates, u in red and v in green. Coordinates outside
+    /// [0, 1] wrap around, so that tiled textures show as repeating ramps.
+    Uv,
+
+    /// Distance to the surface, from white at the camera to black at the far
+    /// side of the scene bounds
+    Depth
+}
+
+/// False-colour views of the first surface seen along each camera ray,
+/// ignoring materials and lights. Rays that miss the scene are black, except
+/// in the heatmap view.
+#[derive(Debug, Copy, Clone)]
+pub struct DebugIntegrator {
+    view: DebugView
+}
+
+impl DebugIntegrator {
+    pub fn new(view: DebugView) -> DebugIntegrator {
+        DebugIntegrator { view }
+    }
+
+    pub fn li(&self, root: &Accel, ray: &Ray) -> Color {
+        let mut isect = RayIntersection::default();
+        let tests = || {
+            let counts = stats::current();
+            counts.node_tests + counts.primitive_tests
+        };
+        let before = tests();
+        let hit = root.intersect_opaque(ray, &mut isect);
+        if self.view == DebugView::Heatmap {
+            return heatmap((tests() - before) as f64 / HEATMAP_MAX_TESTS)
+        }
+        self.shade(root, ray, hit.map(|_| &isect))
+    }
+
+    /// Colour of the surface the given ray hits, if any, in any view but the
+    /// heatmap
+    pub(crate) fn shade(&self, root: &Accel, ray: &Ray, isect: Option<&RayIntersection>) -> Color {
+        let isect = match isect {
+            Some(isect) => isect,
+            None => return Color::zero()
+        };
+        let interaction = SurfaceInteraction::from(ray, isect);
+        match self.view {
+            DebugView::GeometricNormal => interaction.ng() * 0.5 + Color::from_value(0.5),
+            DebugView::ShadingNormal => interaction.ns() * 0.5 + Color::from_value(0.5),
+            DebugView::Uv => Color::new(interaction.uv.x.rem_euclid(1.0), interaction.uv.y.rem_euclid(1.0), 0.0),
+            DebugView::Depth => {
+                let depth = isect.t * ray.d.magnitude();
+                let bounds = root.bound();
+                let far = (0..8)
+                    .map(|corner| (bounds.corner(corner) - ray.origin).magnitude())
+                    .fold(0.0, f64::max);
+                Color::from_value(1.0 - (depth / far).min(1.0))
+            },
+            DebugView::Heatmap => unreachable!()
+        }
+    }
+}
+
+/// Colour of the given value in [0, 1] on a ramp from blue through cyan,
+/// green and yellow to red. Values above 1 are red.
+fn heatmap(value: f64) -> Color {
+    let x = value.clamp(0.0, 1.0) * 4.0;
+    match x as u32 {
+        0 => Color::new(0.0, x, 1.0),
+        1 => Color::new(0.0, 1.0, 2.0 - x),
+        2 => Color::new(x - 2.0, 1.0, 0.0),
+        _ => Color::new(1.0, (4.0 - x).max(0.0), 0.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{scene::Scene, MaterialRef, Integrator, HdrFilm, capture};
+
+    fn render(view: DebugView) -> HdrFilm {
+        let mut scene = Scene::new();
+        scene.set_perspective_camera(30.0).look_at([0.0, 0.0, 5.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        // A grid of spheres, which rays through the corners of the image miss
+        for i in 0..25 {
+            let (x, y) = ((i % 5) as f64 * 0.4 - 0.8, (i / 5) as f64 * 0.4 - 0.8);
+            scene.root.add_sphere([x, y, 0.0], 0.15, MaterialRef::default());
+        }
+        scene.set_integrator(Integrator::Debug(view));
+        let mut film = HdrFilm::new(9, 9);
+        capture(&scene, &mut film);
+        film
+    }
+
+    #[test]
+    fn shows_first_surface() {
+        let normal = render(DebugView::GeometricNormal);
+        let [r, g, b] = normal.get(4, 4);
+        assert!((r - 0.5).abs() < 0.05 && (g - 0.5).abs() < 0.05 && (b - 1.0).abs() < 0.01);
+        assert_eq!(normal.get(0, 0), [0.0, 0.0, 0.0]);
+
+        let depth = render(DebugView::Depth);
+        assert!(depth.get(4, 4)[0] > 0.0 && depth.get(4, 4)[0] < 1.0);
+        assert_eq!(depth.get(0, 0), [0.0, 0.0, 0.0]);
+
+        // Rays that miss the bounds of the scene take fewer tests than those
+        // that hit
+        let heat = render(DebugView::Heatmap);
+        assert!(heat.get(0, 0)[2] > 0.0);
+        assert_ne!(heat.get(0, 0), heat.get(4, 4));
+    }
+
+    #[test]
+    fn ramps_from_blue_to_red() {
+        assert_eq!(heatmap(0.0), Color::new(0.0, 0.0, 1.0));
+        assert_eq!(heatmap(0.5), Color::new(0.0, 1.0, 0.0));
+        assert_eq!(heatmap(1.0), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(heatmap(3.0), Color::new(1.0, 0.0, 0.0));
+    }
+}
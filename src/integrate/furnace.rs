@@ -0,0 +1,141 @@
+use crate::{
+    space::*,
+    interaction::{SurfaceInteraction, RayIntersection},
+    sampler::Sampler,
+    scene::Scene,
+    Accel, Material,
+};
+
+/// Sample points along each axis of the grid used by `audit` in each direction
+const AUDIT_GRID: usize = 64;
+
+/// White furnace test for debugging materials. Shades each surface seen by
+/// the camera with the light it scatters toward the camera when lit evenly
+/// by white light from every direction, ignoring the lights and the rest of
+/// the scene. Surfaces that scatter all the light they receive come out
+/// white, those that absorb some come out darker and those that create
+/// energy come out brighter than one. Emitters add the light they emit.
+///
+/// Each camera sample takes one sample of the BSDF, so supersampling averages
+/// out the noise. See `Material::audit_energy` for the same measure as
+/// numbers.
+#[derive(Debug, Copy, Clone)]
+pub struct FurnaceIntegrator;
+
+/// Light scattered by a material under even white light, as measured by
+/// `Material::audit_energy`. Materials that neither absorb nor create
+/// energy scatter exactly one in every direction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnergyAudit {
+    /// Fraction of light scattered toward a viewer at each of `ANGLES`
+    pub directional: Vec<Color>,
+
+    /// Fraction of light scattered in all, i.e., the average over the
+    /// directions of the hemisphere weighed by their cosine
+    pub albedo: Color
+}
+
+impl FurnaceIntegrator {
+    pub fn new() -> FurnaceIntegrator {
+        FurnaceIntegrator
+    }
+
+    /// Estimate the light scattered toward the ray origin by the surface seen
+    /// along the given ray. Rays that miss the scene see the white furnace.
+    pub fn li(&self, root: &Accel, ray: &Ray, sampler: &mut Sampler) -> Color {
+        let mut isect = RayIntersection::default();
+        let shape = match root.intersect_opaque(ray, &mut isect) {
+            Some(shape) => shape,
+            None => return Color::from_value(1.0)
+        };
+
+        let material = root.scene.material(shape.material().unwrap_or(isect.material));
+        let mut interaction = SurfaceInteraction::from(ray, &isect);
+        let emitted = material.emitted(&interaction);
+        let bsdf = material.scattering(&mut interaction, root.scene);
+        emitted + bsdf.rho_hd(&interaction.wo, &[sampler.get_2d()])
+    }
+
+    /// Measure the light the given material scatters under even white light,
+    /// on the front of a flat surface with texture coordinates at the centre
+    /// of any textures, which are looked up in the given scene.
+    pub fn audit(&self, material: &Material, scene: &Scene) -> EnergyAudit {
+        let samples: Vec<Point2f> = (0..AUDIT_GRID * AUDIT_GRID).map(|i| Point2f::new(
+            ((i % AUDIT_GRID) as f64 + 0.5) / AUDIT_GRID as f64,
+            ((i / AUDIT_GRID) as f64 + 0.5) / AUDIT_GRID as f64
+        )).collect();
+        let rho = |cos_theta: f64| {
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let wo = Vector::new(sin_theta, 0.0, cos_theta);
+            let ray = Ray::new(Point::from_vec(wo), -wo);
+            let isect = RayIntersection::new(1.0, Point::origin(), Point2f::new(0.5, 0.5), Vector::unit_x(), Vector::unit_y());
+            let mut interaction = SurfaceInteraction::from(&ray, &isect);
+            material.scattering(&mut interaction, scene).rho_hd(&wo, &samples)
+        };
+
+        let directional = EnergyAudit::ANGLES.iter().map(|angle| rho(angle.to_radians().cos())).collect();
+
+        // Viewing directions spread in proportion to their cosine, at the
+        // centre of equal bands of cos²θ
+        let bands = 16;
+        let albedo = (0..bands)
+            .map(|i| rho(((i as f64 + 0.5) / bands as f64).sqrt()))
+            .sum::<Color>() / bands as f64;
+        EnergyAudit { directional, albedo }
+    }
+}
+
+impl Default for FurnaceIntegrator {
+    fn default() -> Self { FurnaceIntegrator::new() }
+}
+
+impl EnergyAudit {
+    /// Angles from the surface normal, in degrees, at which the light
+    /// scattered toward the viewer is measured
+    pub const ANGLES: [f64; 7] = [0.0, 15.0, 30.0, 45.0, 60.0, 75.0, 85.0];
+
+    /// Most energy gained at any angle in any channel, zero for materials
+    /// that conserve energy
+    pub fn gain(&self) -> f64 {
+        self.directional.iter().map(|c| c.x.max(c.y).max(c.z) - 1.0).fold(0.0, f64::max)
+    }
+
+    /// Most energy lost at any angle in any channel, such as by absorption
+    pub fn loss(&self) -> f64 {
+        self.directional.iter().map(|c| 1.0 - c.x.min(c.y).min(c.z)).fold(0.0, f64::max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::bxdf::{BxDF, Substance};
+
+    #[test]
+    fn audits_energy() {
+        let scene = Scene::new();
+
+        // White diffuse surfaces scatter all the light they receive
+        let white = Material::matte([1.0, 1.0, 1.0], 0.0).audit_energy(&scene);
+        assert!(white.gain() < 0.01 && white.loss() < 0.01, "{:?}", white);
+        assert!((white.albedo.x - 1.0).abs() < 0.01);
+
+        // Rough metal and glass lose some at most, as do darker materials
+        let metal = Material::metal([0.2, 0.9, 1.1], [3.9, 2.4, 2.2], 0.3, 0.3).audit_energy(&scene);
+        assert!(metal.gain() < 0.01, "{:?}", metal);
+        let glass = Material::glass([1.0, 1.0, 1.0], [1.0, 1.0, 1.0], 1.5).audit_energy(&scene);
+        assert!(glass.gain() < 0.01, "{:?}", glass);
+        let grey = Material::matte([0.5, 0.5, 0.5], 0.0).audit_energy(&scene);
+        assert!((grey.loss() - 0.5).abs() < 0.01);
+
+        // As do the bxdfs on their own, over the whole hemisphere
+        let grid: Vec<Point2f> = (0..1024).map(|i| Point2f::new((i % 32) as f64 / 32.0 + 0.01, (i / 32) as f64 / 32.0 + 0.01)).collect();
+        let shuffled: Vec<Point2f> = grid.iter().rev().cloned().collect();
+        let rho = BxDF::diffuse(Color::from_value(1.0), 0.5).rho_hh(&grid, &shuffled);
+        assert!(rho.x < 1.0 && rho.x > 0.8, "{:?}", rho);
+        let r = Color::new(0.2, 0.4, 0.6);
+        assert_eq!(BxDF::quick_diffuse(r).rho_hh(&grid, &shuffled), r);
+        let mirror = BxDF::specular_reflection(r, Substance::NoOp).rho_hd(&Vector::new(0.6, 0.0, 0.8), &grid);
+        assert!((mirror - r).magnitude() < 1e-9, "{:?}", mirror);
+    }
+}
@@ -4,50 +4,184 @@ use crate::{
     space::*,
     core::bxdf,
     core::bxdf::BxDFType,
-    primitive::Primitive,
-    interaction::{BSDF, SurfaceInteraction, RayIntersection},
+    interaction::{BSDF, Interaction, SurfaceInteraction, MediumInteraction, RayIntersection},
+    sampler::Sampler,
+    light::{Light, LightVisibility},
+    spectrum::Wavelengths,
+    trace::{self, TraceEvent, RadianceSource},
     Accel,
 };
+use super::{Integrator, path::PathIntegrator, ao::AoIntegrator, furnace::FurnaceIntegrator, debug::DebugIntegrator, nested::{Interior, Crossing}};
 
 /**
- * Integrate the given sample rays for a single pixel, with each ray contributing
- * weight to the final image.
+ * Integrate the radiance arriving along the given camera ray, generated for
+ * the current sample of the sampler. The sampler provides the sample points
+ * for light and BSDF sampling, and the wavelengths of spectral renders.
  */
-pub fn integrate(root: &Accel, samples: &[Ray], weight: f64) -> Color {
-    let mut color = Color::zero();
-    for ray in samples { color += li(root, ray, 0) }
-    color * weight
+pub fn integrate(root: &Accel, ray: &Ray, sampler: &mut Sampler) -> Color {
+    let spectral = root.scene.spectral
+        && !matches!(root.scene.integrator, Integrator::AmbientOcclusion | Integrator::Furnace | Integrator::Debug(_));
+    let wavelengths = if spectral { Some(Wavelengths::sample(sampler.get_1d())) } else { None };
+    let ray = match wavelengths {
+        Some(wavelengths) => ray.at_wavelength(wavelengths.hero()),
+        None => *ray
+    };
+
+    let l = match root.scene.integrator {
+        Integrator::Whitted => li(root, &ray, 0, sampler, &Interior::default(), wavelengths),
+        Integrator::Path => PathIntegrator::new(root.scene.recursion, root.scene.roulette_depth, root.scene.max_radiance).li(root, &ray, wavelengths, sampler),
+        Integrator::Irradiance => PathIntegrator::new(root.scene.recursion, root.scene.roulette_depth, root.scene.max_radiance)
+            .with_irradiance_cache(root.irradiance_cache())
+            .li(root, &ray, wavelengths, sampler),
+        Integrator::AmbientOcclusion => AoIntegrator::new(root.scene.ao_distance).li(root, &ray, sampler),
+        Integrator::Furnace => FurnaceIntegrator::new().li(root, &ray, sampler),
+        Integrator::Debug(view) => DebugIntegrator::new(view).li(root, &ray)
+    };
+
+    match wavelengths {
+        Some(wavelengths) => l.mul_element_wise(wavelengths.filter()),
+        None => l
+    }
 }
 
-/// Whitted colorization strategy
-fn li(root: &Accel, ray: &Ray, depth: u32) -> Color {
+/// Whitted colorization strategy, for a ray travelling within the given
+/// dielectric volumes and carrying the given wavelengths, if spectral
+fn li(root: &Accel, ray: &Ray, depth: u32, sampler: &mut Sampler, interior: &Interior, wavelengths: Option<Wavelengths>) -> Color {
     let mut isect = RayIntersection::default();
-    let shape = root.intersect(&ray, &mut isect);
+    let shape = root.intersect_opaque(ray, &mut isect);
+
+    // Light may scatter toward the camera within a medium on the way to the
+    // surface, in which case only its direct lighting is counted
+    let mut beta = Color::from_value(1.0);
+    if let Some(medium) = &root.scene.medium {
+        let (tr, mi) = medium.sample(&ray.within(ray.t_min, isect.t.min(ray.t_max)), sampler);
+        if let Some(mi) = mi {
+            trace::record(|| TraceEvent::Scatter { depth, point: mi.p.into() });
+            let ld = medium_direct_lighting(root, &mi, depth, sampler);
+            record_contribution(depth, RadianceSource::DirectLighting, ld);
+            return tr.mul_element_wise(ld)
+        }
+        beta = tr
+    }
+
     if shape.is_none() {
-        return root.scene.background.bg(&ray.d.normalize())
+        let background = root.scene.background.bg(&ray.d.normalize());
+        trace::record(|| TraceEvent::Miss {
+            depth,
+            origin: ray.origin.into(),
+            direction: ray.d.normalize().into(),
+            background: background.into()
+        });
+        record_contribution(depth, RadianceSource::Background, background);
+        return beta.mul_element_wise(background)
     }
     let shape = shape.unwrap();
-    let material = shape.material().unwrap_or(isect.material);
+    let material = root.scene.material(shape.material().unwrap_or(isect.material));
 
     // Calculates the actual intersection point and normalizes.
     // Required before getting p(), d(), etc.
-    let interaction = SurfaceInteraction::from(ray, &isect);
+    let mut interaction = SurfaceInteraction::from(ray, &isect);
+    trace::record(|| TraceEvent::hit(depth, ray, &isect, &interaction));
+
+    // Find the media on either side of dielectric surfaces, passing through
+    // those hidden within volumes of higher priority
+    let (outside, transmitted) = match material.dielectric() {
+        Some((priority, eta)) => match interior.crossing(priority, eta, !interaction.is_backface()) {
+            Crossing::Hidden(interior) => {
+                let r = interaction.spawn_ray(ray.d);
+                return beta.mul_element_wise(li(root, &r, depth, sampler, &interior, wavelengths))
+            },
+            Crossing::Visible { outside, transmitted } => (outside, transmitted)
+        },
+        None => (1.0, *interior)
+    };
+
+    // Compute scattering functions. May perturb the shading normal.
+    let bsdf = material.scattering_between(&mut interaction, root.scene, outside);
 
     // Compute emitted and reflected light at intersection point
     // Initialize common vars
-    let n = interaction.ns.0; // Geometric shading normal vector
+    let n = interaction.ns.face_forward(interaction.wo).0; // Shading normal on the side of the viewer
     let wo = interaction.wo; // Outgoing direction
-    let p = interaction.p + interaction.p_err;
 
-    // Compute scattering functions
-    let bsdf = material.scattering(&interaction);
+    // Add light emitted by the surface and the contribution of each light
+    // source
+    let emitted = material.emitted(&interaction);
+    record_contribution(depth, RadianceSource::Emission, emitted);
+    let direct = direct_lighting(root, &interaction, &bsdf, depth, sampler);
+    record_contribution(depth, RadianceSource::DirectLighting, direct);
+    let ambient = root.scene.ambient.mul_element_wise(bsdf.f(&wo, &n));
+    record_contribution(depth, RadianceSource::Ambient, ambient);
+    let output = emitted + direct + ambient;
+
+    let (refracted, reflected) = if depth < material.max_depth().unwrap_or(root.scene.recursion) {
+        // Add reflection/transmission contribution
+        let max = root.scene.max_radiance;
+        // Light passing through dispersive surfaces is left with the hero
+        // wavelength
+        let (dispersed, dispersion) = match wavelengths {
+            Some(wavelengths) if material.is_dispersive() => {
+                let (dispersed, ratio) = wavelengths.disperse();
+                (Some(dispersed), ratio)
+            },
+            _ => (wavelengths, Color::from_value(1.0))
+        };
+        let transmitted = specular_transmit(root, &interaction, &bsdf, depth, sampler, &transmitted, dispersed);
+        (
+            clamp_radiance(transmitted.mul_element_wise(dispersion), max),
+            clamp_radiance(specular_reflect(root, &interaction, &bsdf, depth, sampler, interior, wavelengths), max)
+        )
+    } else {
+        trace::record(|| TraceEvent::MaxDepth { depth });
+        (Color::zero(), Color::zero())
+    };
+
+    beta.mul_element_wise(output + reflected + refracted)
+}
+
+/// Record the given radiance leaving the surface hit by the Whitted
+/// integrator at the given depth, unless there's none
+fn record_contribution(depth: u32, source: RadianceSource, l: Color) {
+    if l != Color::zero() {
+        trace::record(|| TraceEvent::Contribution { depth, source, radiance: l.into() })
+    }
+}
+
+/// Sum of the light received directly from each light source in the scene at
+/// the given interaction, scattered in the outgoing direction by the BSDF. The
+/// sampler picks sample points on area and environment lights. The depth is
+/// the number of bounces from the camera to the interaction.
+pub(super) fn direct_lighting(root: &Accel, interaction: &SurfaceInteraction, bsdf: &BSDF, depth: u32, sampler: &mut Sampler) -> Color {
+    let n = interaction.ns.0;
+    let wo = interaction.wo;
+    sample_lights(root, &Interaction::Surface(interaction), depth, sampler, |wi| bsdf.f(&wo, wi) * wi.dot(n).abs())
+}
+
+/// Sum of the light received directly from each light source in the scene at
+/// the given point in a medium, scattered in the outgoing direction by the
+/// phase function
+pub(super) fn medium_direct_lighting(root: &Accel, interaction: &MediumInteraction, depth: u32, sampler: &mut Sampler) -> Color {
+    let phase = interaction.phase;
+    let wo = interaction.wo;
+    sample_lights(root, &Interaction::Medium(interaction), depth, sampler, |wi| Color::from_value(phase.p(&wo, wi)))
+}
 
-    // Add contribution of each light source
-    // For each scene light, sample point lights from it
-    let output = root.scene.lights().iter().fold(Color::zero(), |output, light| {
-        // For each sampled point light, add its contribution to the the
-        // final colour output
-        light.iter_samples(root, p).fold(output, |output, light| {
+/// Sum of the light received from each light source at the given interaction,
+/// scaled by f for the normalized direction toward each light sample. Lights
+/// that don't take part at the given depth are left out. Scenes with a light
+/// tree pick the set number of lights from it instead of adding up those in
+/// it, weighing each by the chance of picking it.
+fn sample_lights<F>(root: &Accel, interaction: &Interaction, depth: u32, sampler: &mut Sampler, f: F) -> Color
+where F: Fn(&Vector) -> Color {
+    let p = interaction.spawn_origin(&interaction.wo());
+    let rays = if depth == 0 { LightVisibility::CAMERA } else { LightVisibility::REFLECTIONS };
+    let tree = root.light_tree();
+
+    // Add the contribution of each point light sampled from the given light,
+    // divided by the given probability of picking the light
+    let add = |output: Color, index: usize, light: &dyn Light, shadows: bool, pdf: f64, sampler: &mut Sampler| {
+        let samples = light.iter_samples(root, p, interaction.time(), sampler).casting_shadows(shadows);
+        samples.fold(output, |output, light| {
 
             // vector to light and its length (distance to the light from q)
             let wi = light.position - p;
@@ -58,34 +192,61 @@ fn li(root: &Accel, ray: &Ray, depth: u32) -> Color {
             if f_att == 0.0 { return output }; // No contribution
 
             let wi = wi.normalize();
-            let wi_dot_n = wi.dot(n);
-
-            let f = bsdf.f(&wo, &wi);
-
-            output + ((f64::consts::PI * light.intensity).mul_element_wise(f) * wi_dot_n / f_att)
+            let l = (f64::consts::PI * light.intensity).mul_element_wise(f(&wi)) / (f_att * pdf);
+            trace::record(|| TraceEvent::Light { depth, light: index, position: light.position.into(), radiance: l.into() });
+            output + l
         })
-    }) + root.scene.ambient.mul_element_wise(bsdf.f(&wo, &n));
+    };
 
-    let (refracted, reflected) = if depth < root.scene.recursion {
-        // Add reflection/transmission contribution
-        (
-            specular_transmit(root, &interaction, &bsdf, depth),
-            specular_reflect(root, &interaction, &bsdf, depth)
-        )
-    } else {
-        (Color::zero(), Color::zero())
+    // For each scene light outside the tree, sample point lights from it
+    let output = root.lights().enumerate().fold(Color::zero(), |output, (index, (light, visibility))| {
+        if !visibility.intersects(rays) || tree.is_some_and(|tree| tree.contains(index)) { return output }
+        let shadows = visibility.contains(LightVisibility::SHADOWS) && interaction.receives_shadows();
+        add(output, index, light, shadows, 1.0, sampler)
+    });
+
+    // The tree only holds point lights added to the scene, which take part
+    // in every part of the render
+    let tree = match tree {
+        Some(tree) => tree,
+        None => return output
     };
+    let n = root.scene.light_samples.unwrap_or(1);
+    (0..n).fold(output, |output, _| match tree.sample(&p, sampler.get_1d()) {
+        Some((index, pdf)) => {
+            let light = root.scene.lights()[index].as_ref();
+            add(output, index, light, interaction.receives_shadows(), pdf * n as f64, sampler)
+        },
+        None => output
+    })
+}
 
-    output + reflected + refracted
+/// Scale down the given radiance such that none of its channels exceed the
+/// given maximum, if any. Keeps the hue of clamped samples.
+pub(super) fn clamp_radiance(l: Color, max: Option<f64>) -> Color {
+    let max = match max {
+        Some(max) => max,
+        None => return l
+    };
+    let brightest = l.x.max(l.y).max(l.z);
+    if brightest > max { l * (max / brightest) } else { l }
 }
 
-fn specular_reflect(root: &Accel, interaction: &SurfaceInteraction, bsdf: &BSDF, depth: u32) -> Color {
+fn specular_reflect(
+    root: &Accel,
+    interaction: &SurfaceInteraction,
+    bsdf: &BSDF,
+    depth: u32,
+    sampler: &mut Sampler,
+    interior: &Interior,
+    wavelengths: Option<Wavelengths>
+) -> Color {
     // Compute specular reflection direction wi and BSDF value
     let wo = interaction.wo;
     let flags = BxDFType::REFLECTION | BxDFType::SPECULAR;
 
-    // TODO: Use actual sample point instead of (0.5, 0.5)
-    let sample = bsdf.sample_f(&wo, &Point2f::new(0.5, 0.5), flags);
+    let sample = bsdf.sample_f(&wo, &sampler.get_2d(), flags);
+    record_bsdf(depth, &sample, false);
 
     // Return contribution of specular reflection
     let ns = interaction.ns.0;
@@ -98,20 +259,28 @@ fn specular_reflect(root: &Accel, interaction: &SurfaceInteraction, bsdf: &BSDF,
 
     // Compute ray for specular reflection
     let wr = bxdf::util::reflect(&wo, &ns);
-    let r = Ray::new(interaction.p + interaction.p_err, wr);
-    let li = li(root, &r, depth + 1);
+    let r = interaction.spawn_reflected_ray(wr);
+    let li = li(root, &r, depth + 1, sampler, interior, wavelengths);
     let output = sample.spectrum.mul_element_wise(li);
 
     output
 }
 
-fn specular_transmit(root: &Accel, interaction: &SurfaceInteraction, bsdf: &BSDF, depth: u32) -> Color {
+fn specular_transmit(
+    root: &Accel,
+    interaction: &SurfaceInteraction,
+    bsdf: &BSDF,
+    depth: u32,
+    sampler: &mut Sampler,
+    interior: &Interior,
+    wavelengths: Option<Wavelengths>
+) -> Color {
     // Compute specular reflection direction wi and BSDF value
     let wo = interaction.wo;
     let flags = BxDFType::TRANSMISSION | BxDFType::SPECULAR;
 
-    // TODO: Use actual sample point instead of (0.5, 0.5)
-    let sample = bsdf.sample_f(&wo, &Point2f::new(0.5, 0.5), flags);
+    let sample = bsdf.sample_f(&wo, &sampler.get_2d(), flags);
+    record_bsdf(depth, &sample, true);
     let (spectrum, wi, pdf) = (sample.spectrum, sample.wi, sample.pdf);
 
     let ns = interaction.ns.0;
@@ -124,9 +293,108 @@ fn specular_transmit(root: &Accel, interaction: &SurfaceInteraction, bsdf: &BSDF
     }
 
     // Compute ray for specular refraction
-    let r = Ray::new(interaction.p - interaction.p_err, wi);
-    let li = li(root, &r, depth + 1);
+    let r = interaction.spawn_ray(wi);
+    let li = li(root, &r, depth + 1, sampler, interior, wavelengths);
     let output = spectrum.mul_element_wise(li) * wi.dot(ns).abs() / sample.pdf;
 
     output
 }
+
+/// Record the direction sampled from the BSDF at the given depth for a
+/// specular reflection or transmission of the Whitted integrator
+fn record_bsdf(depth: u32, sample: &bxdf::LightSample, transmission: bool) {
+    trace::record(|| TraceEvent::Bsdf {
+        depth,
+        direction: sample.wi.into(),
+        f: sample.spectrum.into(),
+        pdf: sample.pdf,
+        specular: true,
+        transmission
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{scene::Scene, light::LightPower, Material, SamplerKind};
+
+    #[test]
+    fn clamps_radiance_keeping_hue() {
+        let l = Color::new(8.0, 4.0, 2.0);
+        assert_eq!(clamp_radiance(l, None), l);
+        assert_eq!(clamp_radiance(l, Some(10.0)), l);
+        assert_eq!(clamp_radiance(l, Some(2.0)), Color::new(2.0, 1.0, 0.5));
+    }
+
+    #[test]
+    fn samples_many_lights_without_bias() {
+        let mut scene = Scene::new();
+        let grey = scene.add_material(Material::matte([0.5, 0.5, 0.5], 0.0));
+        scene.root.add_plane([0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [100.0, 100.0], grey);
+        for i in 0..400 {
+            let (x, z) = ((i % 20) as f64 * 2.0 - 19.0, (i / 20) as f64 * 2.0 - 19.0);
+            scene.add_point_light([x, 1.0 + (i % 7) as f64, z], [1.0, 0.5 + (i % 3) as f64, 1.0], [0.0, 0.0, 1.0]);
+        }
+
+        let direct = |scene: &Scene, n: usize| {
+            let root = Accel::from(scene);
+            let mut sampler = Sampler::new(SamplerKind::Uniform, 1);
+            sampler.start_pixel(0);
+            let ray = Ray::new(Point::new(3.0, 1.0, -2.0), -Vector::unit_y());
+            let mut isect = RayIntersection::default();
+            let shape = root.intersect_opaque(&ray, &mut isect).unwrap();
+            let mut interaction = SurfaceInteraction::from(&ray, &isect);
+            let bsdf = scene.material(shape.material().unwrap_or(isect.material)).scattering(&mut interaction, scene);
+            let sum = (0..n).fold(Color::zero(), |sum, _| sum + direct_lighting(&root, &interaction, &bsdf, 0, &mut sampler));
+            (sum / n as f64, root.light_tree().is_some())
+        };
+
+        let (all, sampled) = direct(&scene, 1);
+        assert!(!sampled);
+        scene.set_light_samples(4);
+        let (estimate, sampled) = direct(&scene, 20_000);
+        assert!(sampled);
+        for i in 0..3 {
+            assert!((estimate[i] / all[i] - 1.0).abs() < 0.02, "{:?} {:?}", estimate, all);
+        }
+    }
+
+    #[test]
+    fn lights_with_physical_power() {
+        let mut scene = Scene::new();
+        let white = scene.add_material(Material::matte([1.0, 1.0, 1.0], 0.0));
+        scene.root.add_plane([0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [100.0, 100.0], white);
+        scene.add_point_light_power([0.0, 1.0, 0.0], [1.0, 1.0, 1.0], LightPower::Watts(4.0 * f64::consts::PI));
+
+        // One watt per square metre reaches the plane below the light, and
+        // the plane reflects 1/π of it towards every direction
+        let root = Accel::from(&scene);
+        let mut sampler = Sampler::new(SamplerKind::Uniform, 1);
+        sampler.start_pixel(0);
+        let ray = Ray::new(Point::new(0.0, 2.0, 0.0), -Vector::unit_y());
+        let mut isect = RayIntersection::default();
+        let shape = root.intersect_opaque(&ray, &mut isect).unwrap();
+        let mut interaction = SurfaceInteraction::from(&ray, &isect);
+        let bsdf = scene.material(shape.material().unwrap_or(isect.material)).scattering(&mut interaction, &scene);
+        let direct = direct_lighting(&root, &interaction, &bsdf, 0, &mut sampler);
+        for i in 0..3 {
+            assert!((direct[i] - f64::consts::FRAC_1_PI).abs() < 1e-9, "{:?}", direct);
+        }
+    }
+
+    #[test]
+    fn lights_ambient_inside_surfaces() {
+        let mut scene = Scene::new();
+        scene.set_ambient_light([1.0, 1.0, 1.0]);
+        let grey = scene.add_material(Material::matte([0.5, 0.5, 0.5], 0.0));
+        scene.root.add_sphere([0.0, 0.0, 0.0], 2.0, grey);
+        let root = Accel::from(&scene);
+        let mut sampler = Sampler::new(SamplerKind::Uniform, 1);
+        sampler.start_pixel(0);
+
+        let outside = integrate(&root, &Ray::new(Point::new(0.0, 0.0, 5.0), -Vector::unit_z()), &mut sampler);
+        let inside = integrate(&root, &Ray::new(Point::new(0.0, 0.0, 0.0), -Vector::unit_z()), &mut sampler);
+        assert!(outside.x > 0.0);
+        assert!((inside - outside).magnitude() < 1e-9, "{:?} {:?}", inside, outside);
+    }
+}
@@ -0,0 +1,235 @@
+use std::{f64, collections::HashMap, sync::RwLock};
+use crate::{
+    space::{*, normal::Normal3},
+    core::bxdf::sampling::cosine_sample_hemisphere,
+    interaction::{SurfaceInteraction, RayIntersection},
+    sampler::Sampler,
+    Accel,
+};
+use super::path::PathIntegrator;
+
+/// Smallest and largest distance to the surrounding surfaces that records
+/// are kept for, as fractions of the size of the scene. Keeps records from
+/// piling up in corners and from stretching across open spaces.
+const MIN_SPACING: f64 = 0.05;
+const MAX_SPACING: f64 = 0.5;
+
+/// Irradiance cache for the indirect diffuse light of mostly diffuse scenes,
+/// such as interiors. Indirect light changes slowly across diffuse surfaces,
+/// so rather than tracing paths from every point, it gathers the light
+/// arriving from the hemisphere above a few points and interpolates between
+/// those nearby elsewhere. Each record is valid within a distance that grows
+/// with how far away the surfaces around it are, so records bunch up in
+/// corners and crevices and spread out over open floors.
+///
+/// Records are added as the render finds points that no record covers, and
+/// kept for later renders with the same acceleration structure, such as from
+/// other viewpoints. Which points get records depends on the order in which
+/// threads reach them, so renders may differ slightly from run to run.
+///
+/// See Ward et al., "A Ray Tracing Solution for Diffuse Interreflection",
+/// 1988, and http://www.pbr-book.org/3ed-2018/Light_Transport_I_Surface_Reflection/Path_Tracing.html
+#[derive(Debug)]
+pub struct IrradianceCache {
+    /// Rays cast from each new record, rounded to a square number
+    samples: u32,
+
+    /// Largest error allowed when interpolating a record, which bounds the
+    /// distance it is valid within as a fraction of its radius
+    error: f64,
+
+    min_radius: f64,
+    max_radius: f64,
+
+    /// Width of the cells of the grid that records are filed in, the most a
+    /// record reaches
+    cell: f64,
+
+    grid: RwLock<Grid>
+}
+
+/// Records filed in each cell of a grid that they reach into
+#[derive(Debug, Default)]
+struct Grid {
+    records: Vec<Record>,
+    cells: HashMap<[i64; 3], Vec<usize>>
+}
+
+/// Irradiance gathered at a point
+#[derive(Debug, Copy, Clone)]
+struct Record {
+    p: Point,
+
+    /// Normal on the side of the surface the light was gathered from
+    n: Vector,
+
+    e: Color,
+
+    /// Harmonic mean distance to the surfaces around the point
+    radius: f64
+}
+
+impl IrradianceCache {
+    /// New empty cache for a scene with the given bounds, which casts the
+    /// given number of rays from each record and interpolates records within
+    /// the given error
+    pub fn new(bounds: &Bounds, samples: u32, error: f64) -> IrradianceCache {
+        debug_assert!(error > 0.0);
+        let size = bounds.diagonal().magnitude();
+        let size = if size.is_finite() && size > 0.0 { size } else { 1.0 };
+        IrradianceCache {
+            samples: samples.max(1),
+            error,
+            min_radius: MIN_SPACING * size,
+            max_radius: MAX_SPACING * size,
+            cell: error * MAX_SPACING * size,
+            grid: RwLock::new(Grid::default())
+        }
+    }
+
+    /// Irradiance arriving at the surface of the given interaction from the
+    /// hemisphere on the side it's seen from, interpolated from nearby
+    /// records. Where none are close enough, gathers it with the given path
+    /// integrator and adds a record.
+    pub fn irradiance(&self, root: &Accel, interaction: &SurfaceInteraction, path: &PathIntegrator, sampler: &mut Sampler) -> Color {
+        let p = interaction.p;
+        let n = Normal3(interaction.ns()).face_forward(interaction.wo).0;
+        if let Some(e) = self.lookup(&p, &n) { return e }
+
+        let (e, radius) = self.gather(root, interaction, &n, path, sampler);
+        self.insert(Record { p, n, e, radius });
+        e
+    }
+
+    /// Number of records in the cache
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.grid.read().unwrap().records.len()
+    }
+
+    /// Weighted average of the records valid at the given point and normal
+    fn lookup(&self, p: &Point, n: &Vector) -> Option<Color> {
+        let grid = self.grid.read().unwrap();
+        let cell = grid.cells.get(&self.cell_of(p))?;
+        let (sum, weights) = cell.iter().fold((Color::zero(), 0.0), |(sum, weights), &i| {
+            let record = &grid.records[i];
+            match record.weight(p, n, self.error) {
+                Some(w) => (sum + record.e * w, weights + w),
+                None => (sum, weights)
+            }
+        });
+        if weights > 0.0 { Some(sum / weights) } else { None }
+    }
+
+    fn insert(&self, record: Record) {
+        let reach = Vector::from_value(self.error * record.radius);
+        let (lo, hi) = (self.cell_of(&(record.p - reach)), self.cell_of(&(record.p + reach)));
+
+        let mut grid = self.grid.write().unwrap();
+        let i = grid.records.len();
+        grid.records.push(record);
+        for x in lo[0]..=hi[0] {
+            for y in lo[1]..=hi[1] {
+                for z in lo[2]..=hi[2] {
+                    grid.cells.entry([x, y, z]).or_default().push(i)
+                }
+            }
+        }
+    }
+
+    /// Irradiance from stratified cosine-weighted directions about n, with
+    /// the distance the record for it is valid over
+    fn gather(&self, root: &Accel, interaction: &SurfaceInteraction, n: &Vector, path: &PathIntegrator, sampler: &mut Sampler) -> (Color, f64) {
+        let path = path.with_irradiance_cache(None);
+        let ng = Normal3(interaction.ng()).face_forward(interaction.wo).0;
+        let (s, t) = coordinate_system(n);
+        let strata = ((self.samples as f64).sqrt().round() as u32).max(1);
+        let count = strata * strata;
+
+        let mut e = Color::zero();
+        let mut inverse_distance = 0.0;
+        for i in 0..count {
+            let u = sampler.get_2d();
+            let u = Point2f::new(
+                ((i % strata) as f64 + u.x) / strata as f64,
+                ((i / strata) as f64 + u.y) / strata as f64
+            );
+            let w = cosine_sample_hemisphere(&u);
+            let wi = s * w.x + t * w.y + *n * w.z;
+
+            // Directions that the shading normal tilts below the surface are
+            // blocked by the surface itself
+            if wi.dot(ng) <= 0.0 { continue }
+
+            let ray = interaction.spawn_ray(wi);
+            let mut isect = RayIntersection::default();
+            if root.intersect_opaque(&ray, &mut isect).is_some() {
+                inverse_distance += 1.0 / isect.t;
+            }
+            e += path.li_from(root, &ray, 1, None, sampler);
+        }
+
+        let radius = if inverse_distance > 0.0 { count as f64 / inverse_distance } else { self.max_radius };
+        (e * f64::consts::PI / count as f64, radius.max(self.min_radius).min(self.max_radius))
+    }
+
+    #[inline]
+    fn cell_of(&self, p: &Point) -> [i64; 3] {
+        [
+            (p.x / self.cell).floor() as i64,
+            (p.y / self.cell).floor() as i64,
+            (p.z / self.cell).floor() as i64
+        ]
+    }
+}
+
+impl Record {
+    /// Weight of the record when interpolating at the given point and normal,
+    /// the inverse of the estimated error, if below the given error
+    fn weight(&self, p: &Point, n: &Vector, error: f64) -> Option<f64> {
+        let d = p - self.p;
+
+        // Records in front of the point see surfaces that it doesn't
+        if d.dot(self.n + *n) * 0.5 < -0.01 * self.radius { return None }
+
+        let e = d.magnitude() / self.radius + (1.0 - n.dot(self.n)).max(0.0).sqrt();
+        if e < error { Some(1.0 / e.max(1e-6)) } else { None }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{scene::Scene, Material, SamplerKind};
+
+    #[test]
+    fn interpolates_nearby_records() {
+        let mut scene = Scene::new();
+        scene.set_solid_background([1.0, 1.0, 1.0]);
+        let grey = scene.add_material(Material::matte([0.5, 0.5, 0.5], 0.0));
+        scene.root.add_plane([0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [10.0, 10.0], grey);
+        let root = Accel::from(&scene);
+        let cache = IrradianceCache::new(&Bounds::new(Point::new(-5.0, 0.0, -5.0), Point::new(5.0, 1.0, 5.0)), 64, 0.5);
+        let path = PathIntegrator::new(3, 3, None);
+        let mut sampler = Sampler::new(SamplerKind::Uniform, 1);
+        sampler.start_pixel(0);
+
+        let irradiance = |x: f64, sampler: &mut Sampler| {
+            let ray = Ray::new(Point::new(x, 1.0, 0.0), -Vector::unit_y());
+            let mut isect = RayIntersection::default();
+            root.intersect_opaque(&ray, &mut isect).unwrap();
+            cache.irradiance(&root, &SurfaceInteraction::from(&ray, &isect), &path, sampler)
+        };
+
+        // The open sky above the floor lights it with π
+        let e = irradiance(0.0, &mut sampler);
+        assert!((e.x - f64::consts::PI).abs() < 1e-6, "{:?}", e);
+        assert_eq!(cache.len(), 1);
+
+        // Nearby points reuse the record, those further away add their own
+        assert_eq!(irradiance(0.1, &mut sampler), e);
+        assert_eq!(cache.len(), 1);
+        irradiance(4.0, &mut sampler);
+        assert_eq!(cache.len(), 2);
+    }
+}
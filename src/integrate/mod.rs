@@ -1,3 +1,53 @@
+use serde::{Serialize, Deserialize};
+
 pub mod integrate;
+pub mod path;
+pub mod ao;
+pub mod furnace;
+pub mod irradiance;
+pub mod aov;
+pub mod debug;
+mod nested;
 
 pub use self::integrate::integrate;
+pub use self::debug::DebugView;
+
+/// Light transport strategy used to compute the radiance arriving at the
+/// camera along each sample ray.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Integrator {
+    /// Direct lighting from scene lights plus recursive perfect specular
+    /// reflection and transmission. Fast, but does not capture indirect
+    /// diffuse or glossy illumination.
+    #[default]
+    Whitted,
+
+    /// Monte-Carlo path tracing with importance-sampled BSDF bounces and
+    /// Russian roulette termination. Captures glossy interreflection and colour
+    /// bleeding at the cost of noise that converges with more samples.
+    Path,
+
+    /// Path tracing that interpolates the indirect light of diffuse surfaces
+    /// from an irradiance cache filled as the render goes, within the scene's
+    /// `irradiance_error`. Much faster than path tracing for scenes lit
+    /// mostly by diffuse interreflection, such as interiors, but smooths out
+    /// fine detail of the indirect light.
+    Irradiance,
+
+    /// Grayscale ambient occlusion of the surfaces seen by the camera, within
+    /// the scene's `ao_distance`. Ignores materials and lights, for quick clay
+    /// previews of the geometry.
+    AmbientOcclusion,
+
+    /// White furnace test of the materials seen by the camera: the light each
+    /// surface scatters when lit evenly by white light from every direction.
+    /// Ignores lights and the rest of the scene. Surfaces brighter than white
+    /// create energy, for debugging materials.
+    Furnace,
+
+    /// False-colour view of the first surface seen by the camera, such as
+    /// its normals or the cost of tracing it, for diagnosing acceleration and
+    /// shading bugs. Written `{ "debug": "heatmap" }` in scene files.
+    Debug(DebugView)
+}
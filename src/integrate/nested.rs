@@ -0,0 +1,139 @@
+//! Nested dielectrics, such as glass in water or ice cubes in a drink. Paths
+//! keep track of the dielectric volumes they are inside of, so that rays
+//! crossing from one volume into another refract by the ratio of the indices
+//! of both. Where volumes overlap, the one with the highest priority fills the
+//! overlap and the surfaces of the others inside it are ignored.
+//!
+//! See "Simple Nested Dielectrics in Ray Traced Images" by Schmidt and Budge.
+
+/// Most volumes a path may be inside of at once. Volumes entered beyond this
+/// are ignored.
+const MAX_NESTING: usize = 8;
+
+/// Refractive index outside of all volumes
+const ETA_VACUUM: f64 = 1.0;
+
+/// Dielectric volumes that a path is inside of, each with its priority and
+/// refractive index, in the order entered
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct Interior {
+    volumes: [(u32, f64); MAX_NESTING],
+    len: usize
+}
+
+/// How a path crosses the surface of a dielectric volume
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Crossing {
+    /// The surface lies within a volume of higher priority. The path passes
+    /// straight through into the given interior.
+    Hidden(Interior),
+
+    /// The surface separates the volume from a medium with the given
+    /// refractive index. Paths transmitted through it continue in the given
+    /// interior; reflected ones stay in the current one.
+    Visible { outside: f64, transmitted: Interior }
+}
+
+impl Interior {
+    /// Volume with the highest priority, the most recently entered among
+    /// those of equal priority
+    fn top(&self) -> Option<(u32, f64)> {
+        self.volumes[..self.len].iter()
+            .fold(None, |top: Option<(u32, f64)>, &volume| match top {
+                Some(top) if top.0 > volume.0 => Some(top),
+                _ => Some(volume)
+            })
+    }
+
+    /// Refractive index of the medium the path is in
+    fn eta(&self) -> f64 {
+        self.top().map_or(ETA_VACUUM, |(_, eta)| eta)
+    }
+
+    fn entered(&self, priority: u32, eta: f64) -> Interior {
+        let mut interior = *self;
+        if interior.len < MAX_NESTING {
+            interior.volumes[interior.len] = (priority, eta);
+            interior.len += 1;
+        }
+        interior
+    }
+
+    fn exited(&self, priority: u32, eta: f64) -> Interior {
+        let mut interior = *self;
+        let volumes = &interior.volumes[..interior.len];
+        if let Some(i) = volumes.iter().rposition(|&volume| volume == (priority, eta)) {
+            interior.volumes.copy_within((i + 1)..interior.len, i);
+            interior.len -= 1;
+        }
+        interior
+    }
+
+    /// Find how the path crosses the surface of a volume with the given
+    /// priority and refractive index, entering it if entering is true and
+    /// leaving it otherwise
+    pub fn crossing(&self, priority: u32, eta: f64, entering: bool) -> Crossing {
+        let (rest, transmitted) = if entering {
+            (*self, self.entered(priority, eta))
+        } else {
+            let rest = self.exited(priority, eta);
+            (rest, rest)
+        };
+
+        match rest.top() {
+            Some((top, _)) if top > priority => Crossing::Hidden(transmitted),
+            _ => Crossing::Visible { outside: rest.eta(), transmitted }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn outside(crossing: Crossing) -> Option<f64> {
+        match crossing {
+            Crossing::Visible { outside, .. } => Some(outside),
+            Crossing::Hidden(_) => None
+        }
+    }
+
+    fn transmitted(crossing: Crossing) -> Interior {
+        match crossing {
+            Crossing::Visible { transmitted, .. } => transmitted,
+            Crossing::Hidden(transmitted) => transmitted
+        }
+    }
+
+    #[test]
+    fn glass_in_water() {
+        let air = Interior::default();
+        let water = air.crossing(0, 1.33, true);
+        assert_eq!(outside(water), Some(1.0));
+
+        let water = transmitted(water);
+        let glass = water.crossing(0, 1.5, true);
+        assert_eq!(outside(glass), Some(1.33));
+
+        let glass = transmitted(glass);
+        assert_eq!(outside(glass.crossing(0, 1.5, false)), Some(1.33));
+        assert_eq!(transmitted(glass.crossing(0, 1.5, false)).eta(), 1.33);
+    }
+
+    #[test]
+    fn overlapping_volumes() {
+        // An ice cube with higher priority poking out of a drink
+        let drink = transmitted(Interior::default().crossing(1, 1.33, true));
+        let ice = drink.crossing(2, 1.31, true);
+        assert_eq!(outside(ice), Some(1.33));
+
+        // The surface of the drink is hidden within the ice
+        let ice = transmitted(ice);
+        let hidden = ice.crossing(1, 1.33, false);
+        assert!(outside(hidden).is_none());
+
+        // Leaving the ice into the air above the drink
+        let ice = transmitted(hidden);
+        assert_eq!(outside(ice.crossing(2, 1.31, false)), Some(1.0));
+    }
+}
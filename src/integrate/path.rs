@@ -0,0 +1,298 @@
+use crate::{
+    space::*,
+    core::bxdf::BxDFType,
+    interaction::{SurfaceInteraction, RayIntersection},
+    sampler::Sampler,
+    spectrum::Wavelengths,
+    trace::{self, TraceEvent, RadianceSource},
+    Accel,
+};
+use super::{
+    integrate::{direct_lighting, medium_direct_lighting, clamp_radiance},
+    irradiance::IrradianceCache,
+    nested::{Interior, Crossing}
+};
+
+/// Lower bound on the probability of terminating a path with Russian roulette,
+/// so that low-throughput paths still get cut short.
+const RR_MIN_PROBABILITY: f64 = 0.05;
+
+/// Unidirectional Monte-Carlo path tracer. At each intersection, adds the
+/// direct lighting contribution of the scene lights and continues the path in
+/// a direction sampled from the BSDF. Paths through a participating medium
+/// may also scatter within it, in a direction sampled from its phase function.
+/// Paths are terminated with Russian roulette after a few bounces, or when the
+/// maximum depth is reached, which materials may raise or lower for the
+/// bounces off their surfaces.
+///
+/// With an irradiance cache, the indirect diffuse light at the first diffuse
+/// surface along each path comes from the cache instead of further bounces,
+/// while glossy and specular reflection carry on as usual.
+///
+/// See http://www.pbr-book.org/3ed-2018/Light_Transport_I_Surface_Reflection/Path_Tracing.html
+#[derive(Debug, Copy, Clone)]
+pub struct PathIntegrator<'c> {
+    /// Maximum number of bounces for paths, unless overridden by the material
+    /// at a bounce
+    max_depth: u32,
+
+    /// Number of bounces after which paths become candidates for Russian
+    /// roulette termination
+    roulette_depth: u32,
+
+    /// Largest radiance that light from beyond the first bounce may
+    /// contribute, if any
+    max_radiance: Option<f64>,
+
+    /// Indirect diffuse light cached across the render, if any
+    irradiance: Option<&'c IrradianceCache>
+}
+
+impl<'c> PathIntegrator<'c> {
+    pub fn new(max_depth: u32, roulette_depth: u32, max_radiance: Option<f64>) -> PathIntegrator<'c> {
+        PathIntegrator { max_depth, roulette_depth, max_radiance, irradiance: None }
+    }
+
+    /// Look up the indirect diffuse light at the first diffuse surface of each
+    /// path in the given cache, if any
+    pub fn with_irradiance_cache(self, irradiance: Option<&'c IrradianceCache>) -> PathIntegrator<'c> {
+        PathIntegrator { irradiance, ..self }
+    }
+
+    /// Possibly terminate the path at the given depth with Russian roulette.
+    /// Surviving paths make up for the others with increased throughput.
+    fn survives(&self, depth: u32, beta: &mut Color, sampler: &mut Sampler) -> bool {
+        if depth < self.roulette_depth { return true }
+        let q = RR_MIN_PROBABILITY.max(1.0 - max_component(beta));
+        if sampler.get_1d() < q {
+            trace::record(|| TraceEvent::Roulette { depth });
+            return false
+        }
+        *beta /= 1.0 - q;
+        true
+    }
+
+    /// Clamp the contribution of light found at the given depth of a path.
+    /// Light seen directly by the camera or lighting the first surface is
+    /// left as is.
+    #[inline]
+    fn contribution(&self, depth: u32, source: RadianceSource, l: Color) -> Color {
+        let l = if depth == 0 { l } else { clamp_radiance(l, self.max_radiance) };
+        if l != Color::zero() {
+            trace::record(|| TraceEvent::Contribution { depth, source, radiance: l.into() });
+        }
+        l
+    }
+
+    /// Estimate the radiance arriving at the ray origin along the given ray,
+    /// carrying the given wavelengths if spectral
+    pub fn li(&self, root: &Accel, ray: &Ray, wavelengths: Option<Wavelengths>, sampler: &mut Sampler) -> Color {
+        self.li_from(root, ray, 0, wavelengths, sampler)
+    }
+
+    /// Estimate the radiance arriving along a ray that continues a path after
+    /// the given number of bounces, leaving out light emitted by the surface
+    /// the ray hits. Such light is found by sampling the lights at the bounce
+    /// the ray leaves from instead.
+    pub(super) fn li_from(&self, root: &Accel, ray: &Ray, depth: u32, wavelengths: Option<Wavelengths>, sampler: &mut Sampler) -> Color {
+        let mut l = Color::zero(); // Radiance accumulated so far
+        let mut beta = Color::from_value(1.0); // Path throughput
+        let mut ray = *ray;
+        let mut depth = depth;
+
+        // Cache to look up the indirect diffuse light in, until the path
+        // reaches its first diffuse surface
+        let mut irradiance = self.irradiance;
+
+        // Dielectric volumes the path is inside of
+        let mut interior = Interior::default();
+        let mut wavelengths = wavelengths;
+
+        // Whether the last bounce was off a perfectly specular surface
+        let mut specular_bounce = false;
+
+        loop {
+            let mut isect = RayIntersection::default();
+            let hit = root.intersect_opaque(&ray, &mut isect);
+
+            // Sample the medium, if any, up to the surface. Paths that scatter
+            // within it continue in a direction sampled from the phase function.
+            if let Some(medium) = &root.scene.medium {
+                let (tr, mi) = medium.sample(&ray.within(ray.t_min, isect.t.min(ray.t_max)), sampler);
+                beta = beta.mul_element_wise(tr);
+                if beta == Color::zero() { break }
+
+                if let Some(mi) = mi {
+                    trace::record(|| TraceEvent::Scatter { depth, point: mi.p.into() });
+                    let ld = medium_direct_lighting(root, &mi, depth, sampler);
+                    l += self.contribution(depth, RadianceSource::DirectLighting, beta.mul_element_wise(ld));
+                    if depth >= self.max_depth {
+                        trace::record(|| TraceEvent::MaxDepth { depth });
+                        break
+                    }
+
+                    // Sampled in proportion to the phase function, which
+                    // leaves the throughput as is
+                    let (wi, _) = mi.phase.sample_p(&mi.wo, &sampler.get_2d());
+                    ray = mi.spawn_ray(wi);
+                    specular_bounce = false;
+                    if !self.survives(depth, &mut beta, sampler) { break }
+                    depth += 1;
+                    continue
+                }
+            }
+
+            let shape = match hit {
+                Some(shape) => shape,
+                None => {
+                    // Escaped rays pick up the background, unless it was
+                    // already accounted for by sampling it as a light
+                    let background = &root.scene.background;
+                    trace::record(|| TraceEvent::Miss {
+                        depth,
+                        origin: ray.origin.into(),
+                        direction: ray.d.normalize().into(),
+                        background: background.bg(&ray.d.normalize()).into()
+                    });
+                    if depth == 0 || specular_bounce || !background.is_light() {
+                        let lb = background.bg(&ray.d.normalize());
+                        l += self.contribution(depth, RadianceSource::Background, beta.mul_element_wise(lb));
+                    }
+                    break
+                }
+            };
+
+            let material = root.scene.material(shape.material().unwrap_or(isect.material));
+            let mut interaction = SurfaceInteraction::from(&ray, &isect);
+            trace::record(|| TraceEvent::hit(depth, &ray, &isect, &interaction));
+
+            // Find the media on either side of dielectric surfaces, passing
+            // through those hidden within volumes of higher priority
+            let (outside, transmitted) = match material.dielectric() {
+                Some((priority, eta)) => match interior.crossing(priority, eta, !interaction.is_backface()) {
+                    Crossing::Hidden(hidden) => {
+                        interior = hidden;
+                        ray = interaction.spawn_ray(ray.d);
+                        continue
+                    },
+                    Crossing::Visible { outside, transmitted } => (outside, transmitted)
+                },
+                None => (1.0, interior)
+            };
+
+            // Emitted light, unless it was already accounted for by sampling
+            // the emitter as an area light
+            if depth == 0 || specular_bounce {
+                l += self.contribution(depth, RadianceSource::Emission, beta.mul_element_wise(material.emitted(&interaction)));
+            }
+
+            let bsdf = material.scattering_between(&mut interaction, root.scene, outside);
+
+            // Sample illumination from lights to find path contribution
+            let ld = direct_lighting(root, &interaction, &bsdf, depth, sampler);
+            l += self.contribution(depth, RadianceSource::DirectLighting, beta.mul_element_wise(ld));
+
+            if depth >= material.max_depth().unwrap_or(self.max_depth) {
+                trace::record(|| TraceEvent::MaxDepth { depth });
+                break
+            }
+
+            // Light reflected diffusely comes from the cache, so the path only
+            // goes on from glossy and specular reflection
+            let wo = interaction.wo;
+            let mut flags = BxDFType::ALL;
+            let diffuse = BxDFType::REFLECTION | BxDFType::DIFFUSE;
+            if let Some(cache) = irradiance.filter(|_| bsdf.num_matching_components(diffuse) > 0) {
+                let e = trace::untraced(|| cache.irradiance(root, &interaction, self, sampler));
+                let rho = bsdf.rho_hd_matching(&wo, &[sampler.get_2d()], diffuse);
+                let li = beta.mul_element_wise(rho).mul_element_wise(e) * std::f64::consts::FRAC_1_PI;
+                l += self.contribution(depth, RadianceSource::IrradianceCache, li);
+                flags -= BxDFType::DIFFUSE;
+                irradiance = None;
+            }
+
+            // Sample BSDF to get new path direction
+            let sample = bsdf.sample_f(&wo, &sampler.get_2d(), flags);
+            trace::record(|| TraceEvent::Bsdf {
+                depth,
+                direction: sample.wi.into(),
+                f: sample.spectrum.into(),
+                pdf: sample.pdf,
+                specular: sample.t.contains(BxDFType::SPECULAR),
+                transmission: sample.wi.dot(interaction.ng.0) < 0.0
+            });
+            if sample.pdf <= 0.0 || sample.spectrum == Color::zero() { break }
+
+            specular_bounce = sample.t.contains(BxDFType::SPECULAR);
+            let cos = sample.wi.dot(interaction.ns.0).abs();
+            beta = beta.mul_element_wise(sample.spectrum) * cos / sample.pdf;
+
+            // Spawn the next ray on the side of the surface it leaves from.
+            // Only mirror reflections keep track of the footprint of the path
+            ray = if sample.t.contains(BxDFType::SPECULAR | BxDFType::REFLECTION) {
+                interaction.spawn_reflected_ray(sample.wi)
+            } else {
+                interaction.spawn_ray(sample.wi)
+            };
+            if sample.wi.dot(interaction.ng.0) < 0.0 {
+                interior = transmitted;
+
+                // Light passing through dispersive surfaces is left with the
+                // hero wavelength
+                if let Some(spectrum) = wavelengths.filter(|_| material.is_dispersive()) {
+                    let (dispersed, ratio) = spectrum.disperse();
+                    beta = beta.mul_element_wise(ratio);
+                    wavelengths = Some(dispersed);
+                }
+            }
+
+            if !self.survives(depth, &mut beta, sampler) { break }
+            depth += 1;
+        }
+
+        l
+    }
+}
+
+#[inline]
+fn max_component(c: &Color) -> f64 {
+    c.x.max(c.y).max(c.z)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{scene::Scene, Material, SamplerKind};
+
+    #[test]
+    fn clamps_only_indirect_light() {
+        let integrator = PathIntegrator::new(10, 3, Some(1.0));
+        let l = Color::new(8.0, 4.0, 2.0);
+        assert_eq!(integrator.contribution(0, RadianceSource::Background, l), l);
+        assert_eq!(integrator.contribution(1, RadianceSource::Background, l), Color::new(1.0, 0.5, 0.25));
+    }
+
+    #[test]
+    fn diffuse_sphere_in_furnace() {
+        // A convex diffuse sphere lit evenly from every direction reflects
+        // its albedo times the incoming light, with or without roulette
+        let mut scene = Scene::new();
+        scene.set_solid_background([1.0, 1.0, 1.0]);
+        let grey = scene.add_material(Material::matte([0.5, 0.5, 0.5], 0.0));
+        scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, grey);
+        let root = Accel::from(&scene);
+        let ray = Ray::new(Point::new(0.0, 0.0, 3.0), -Vector::unit_z());
+
+        for roulette_depth in [0, 1, 10] {
+            let integrator = PathIntegrator::new(10, roulette_depth, None);
+            let mut sampler = Sampler::new(SamplerKind::Uniform, 1);
+            sampler.start_pixel(0);
+            let n = 20_000;
+            let sum = (0..n).fold(Color::zero(), |sum, _| sum + integrator.li(&root, &ray, None, &mut sampler));
+            let estimate = sum / n as f64;
+            for i in 0..3 {
+                assert!((estimate[i] - 0.5).abs() < 0.02, "{:?} at roulette depth {}", estimate, roulette_depth);
+            }
+        }
+    }
+}
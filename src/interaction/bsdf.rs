@@ -1,5 +1,5 @@
 use crate::space::*;
-use crate::core::bxdf::{BxDFType, BxDF, LightSample};
+use crate::core::bxdf::{BxDFType, BxDF, LightSample, Substance};
 use super::SurfaceInteraction;
 
 /// Collection of BRDF and BTDF, allowing system to work with composite BxDFs.
@@ -12,9 +12,8 @@ pub struct BSDF {
     /// Shading normal
     ns: Normal,
 
-    // Orthogonal shading differential vectors (normalized). As we currently
-    // don't have shading normals, these are generated from the geometric
-    // normal.
+    // Orthonormal shading tangents. ss follows the direction of increasing u
+    // texture coordinate, so anisotropic BxDFs line up with surface UVs.
     ss: Vector,
     ts: Vector,
 
@@ -22,7 +21,40 @@ pub struct BSDF {
     bxdfs: [BxDF; MAX_BXDFS],
 
     /// Current actual number of bxdfs (must be below 8)
-    num_bxdfs: usize
+    num_bxdfs: usize,
+
+    /// Fraction of the light scattered by each bxdf, for blends of materials
+    weights: [Color; MAX_BXDFS],
+
+    /// Optional dielectric coat over each bxdf
+    coats: [Option<Layer>; MAX_BXDFS]
+}
+
+/// Dielectric coat over some of the bxdfs, such as clearcoat over car paint.
+/// Light reaching the bxdfs underneath passes through the coat twice, dimmed
+/// by what the coat reflects and tinted by what it absorbs on the way.
+#[derive(Copy, Clone)]
+struct Layer {
+    /// Refractive index of the coat
+    eta: f64,
+
+    /// Colour of light after passing straight through a coat of thickness one
+    tint: Color,
+
+    thickness: f64
+}
+
+impl Layer {
+    /// Fraction of light passing through the coat in the given local
+    /// direction, both into and out of it
+    fn transmittance(&self, w: &Vector) -> Color {
+        let cos_theta = w.z.abs();
+        let fresnel = Substance::Dielectric(1.0, self.eta).evaluate(cos_theta);
+        let transmitted = Color::from_value(1.0) - fresnel;
+        if self.thickness == 0.0 || cos_theta == 0.0 { return transmitted }
+        let path = self.thickness / cos_theta;
+        transmitted.mul_element_wise(self.tint.map(|c| c.max(0.0).powf(path)))
+    }
 }
 
 impl BSDF {
@@ -31,7 +63,11 @@ impl BSDF {
 
         let ng = si.ng;
         let ns = si.ns;
-        let ss = si.surface.dpdu;
+
+        // Project ∂p/∂u onto the shading plane, falling back to an arbitrary
+        // tangent if the two are parallel
+        let ss = si.surface.dpdu - ns.0 * ns.0.dot(si.surface.dpdu);
+        let ss = if ss.magnitude2() > 0.0 { ss.normalize() } else { coordinate_system(&ns.0).0 };
         let ts = ns.0.cross(ss);
 
         // Allocate initial scattering functions
@@ -42,7 +78,8 @@ impl BSDF {
             num_bxdfs += 1;
         }
 
-        BSDF { eta, ns, ng, ss, ts, bxdfs, num_bxdfs }
+        let weights = [Color::from_value(1.0); MAX_BXDFS];
+        BSDF { eta, ns, ng, ss, ts, bxdfs, num_bxdfs, weights, coats: [None; MAX_BXDFS] }
     }
 
     /// Simple in that it doesn't include eta
@@ -57,9 +94,36 @@ impl BSDF {
     pub fn add(&mut self, b: BxDF) {
         debug_assert!(self.num_bxdfs < MAX_BXDFS);
         self.bxdfs[self.num_bxdfs] = b;
+        self.weights[self.num_bxdfs] = Color::from_value(1.0);
+        self.coats[self.num_bxdfs] = None;
         self.num_bxdfs += 1;
     }
 
+    /// Lay a dielectric coat with the given refractive index over the bxdfs
+    /// added so far, scattering light off its top with the given bxdf. The
+    /// coat tints light passing through it by `tint` for each unit of
+    /// `thickness` crossed. Replaces any previous coat.
+    pub fn add_coat(&mut self, coat: BxDF, eta: f64, tint: Color, thickness: f64) {
+        let layer = Layer { eta, tint, thickness };
+        for i in 0..self.num_bxdfs { self.coats[i] = Some(layer) }
+        self.add(coat);
+    }
+
+    /// Blend in the bxdfs of another BSDF at the same point, such that it
+    /// scatters the given fraction of light of each colour and this one
+    /// scatters the rest. Bxdfs beyond the most a BSDF holds are left out.
+    pub fn mix(&mut self, other: &BSDF, t: Color) {
+        let s = Color::from_value(1.0) - t;
+        for i in 0..self.num_bxdfs { self.weights[i] = self.weights[i].mul_element_wise(s) }
+        for i in 0..other.num_bxdfs.min(MAX_BXDFS - self.num_bxdfs) {
+            let j = self.num_bxdfs;
+            self.bxdfs[j] = other.bxdfs[i];
+            self.weights[j] = other.weights[i].mul_element_wise(t);
+            self.coats[j] = other.coats[i];
+            self.num_bxdfs += 1;
+        }
+    }
+
     #[inline]
     pub fn num_components(&self) -> usize {
         self.num_bxdfs
@@ -81,10 +145,10 @@ impl BSDF {
         if wo_local.z == 0.0 { return Color::zero() };
 
         // Calculate result of all the BxDFs
-        self.iter().fold(Color::zero(), |f, bxdf| {
+        self.iter().enumerate().fold(Color::zero(), |f, (i, bxdf)| {
             if (reflect && bxdf.has_t(BxDFType::REFLECTION))
             || (!reflect && bxdf.has_t(BxDFType::TRANSMISSION)) {
-                f + bxdf.f(&wo_local, &wi_local)
+                f + bxdf.f(&wo_local, &wi_local).mul_element_wise(self.scale(i, &wo_local, &wi_local))
             } else {
                 f
             }
@@ -100,8 +164,8 @@ impl BSDF {
             .min(matching_comps - 1);
 
         // Get BxDF reference for chosen component
-        let bxdf = self.iter().filter(|bxdf| bxdf.matches(flags)).nth(comp);
-        debug_assert!(bxdf.is_some()); let bxdf = bxdf.unwrap();
+        let bxdf = self.iter().enumerate().filter(|(_, bxdf)| bxdf.matches(flags)).nth(comp);
+        debug_assert!(bxdf.is_some()); let (index, bxdf) = bxdf.unwrap();
 
         // Remap BxDF sample to [0,1)^2
         let sample = Point2f::new(
@@ -120,16 +184,18 @@ impl BSDF {
 
         // Compute value of BSDF for sampled direction
         let spectrum = if bxdf.has_t(BxDFType::SPECULAR) {
-            f_sample.spectrum
+            f_sample.spectrum.mul_element_wise(self.scale(index, &wo_local, &wi_local))
         } else {
             // Add contribution from each matching component
             let reflect = wi.dot(self.ng.0) * wo.dot(self.ng.0) > 0.0;
-            self.iter().filter(|bxdf| bxdf.matches(flags))
-            .filter(|bxdf| //
+            self.iter().enumerate().filter(|(_, bxdf)| bxdf.matches(flags))
+            .filter(|(_, bxdf)| //
                 (reflect && bxdf.has_t(BxDFType::REFLECTION)) ||
                 (!reflect && bxdf.has_t(BxDFType::TRANSMISSION))
             )
-            .fold(Color::zero(), |f, bxdf| f + bxdf.f(&wo_local, &wi_local))
+            .fold(Color::zero(), |f, (i, bxdf)| {
+                f + bxdf.f(&wo_local, &wi_local).mul_element_wise(self.scale(i, &wo_local, &wi_local))
+            })
         }.map(|i| i.max(0.0).min(1.0)); // Clamp
 
         // Compute overall PDF with all _other_ matching BxDFs
@@ -141,7 +207,49 @@ impl BSDF {
             f_sample.pdf
         } / matching_comps as f64; // Scale by contribution of each comp
 
-        LightSample::new(spectrum, wi, pdf)
+        LightSample { t: bxdf.t(), ..LightSample::new(spectrum, wi, pdf) }
+    }
+
+    /// Fraction of light arriving evenly from every direction that the BSDF
+    /// scatters toward wo, from `BxDF::rho_hd` of each bxdf with the given
+    /// sample points. Unlike the spectrum of `sample_f` the estimate isn't
+    /// clamped, so BSDFs that create energy come out above one.
+    pub fn rho_hd(&self, wo: &Vector, samples: &[Point2f]) -> Color {
+        self.rho_hd_matching(wo, samples, BxDFType::ALL)
+    }
+
+    /// Fraction of light arriving evenly from every direction that the bxdfs
+    /// matching the given flags scatter toward wo. See `rho_hd`.
+    pub fn rho_hd_matching(&self, wo: &Vector, samples: &[Point2f], flags: BxDFType) -> Color {
+        let wo_local = self.to_local(wo);
+        if wo_local.z == 0.0 || samples.is_empty() { return Color::zero() }
+
+        let n = samples.len() as f64;
+        self.iter().enumerate().filter(|(_, bxdf)| bxdf.matches(flags)).fold(Color::zero(), |rho, (i, bxdf)| {
+            if self.coats[i].is_none() {
+                return rho + bxdf.rho_hd(&wo_local, samples).mul_element_wise(self.weights[i])
+            }
+
+            // Coats dim light depending on the direction it arrives from
+            samples.iter().fold(rho, |rho, u| {
+                let sample = bxdf.sample_f(&wo_local, u);
+                if sample.pdf <= 0.0 { return rho }
+                let scale = self.scale(i, &wo_local, &sample.wi);
+                rho + sample.spectrum.mul_element_wise(scale) * sample.wi.z.abs() / (sample.pdf * n)
+            })
+        })
+    }
+
+    /// Fraction of the light scattered by the bxdf at the given index that
+    /// the BSDF scatters, after its weight and any coat over it
+    #[inline]
+    fn scale(&self, index: usize, wo: &Vector, wi: &Vector) -> Color {
+        match self.coats[index] {
+            Some(coat) => self.weights[index]
+                .mul_element_wise(coat.transmittance(wo))
+                .mul_element_wise(coat.transmittance(wi)),
+            None => self.weights[index]
+        }
     }
 
     #[inline]
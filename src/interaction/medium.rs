@@ -0,0 +1,36 @@
+use crate::{space::*, medium::HenyeyGreenstein};
+
+/// Describes light scattering at point p within a participating medium, in
+/// the outgoing direction wo. Unlike surfaces, media scatter light into all
+/// directions, as given by the phase function.
+#[derive(Debug, Copy, Clone)]
+pub struct MediumInteraction {
+    /// Point of interaction in world coordinates
+    pub p: Point,
+
+    /// Normalized direction from p back toward the origin of the ray that
+    /// scattered
+    pub wo: Vector,
+
+    /// Time of the ray that scattered. Rays spawned from here are cast at the
+    /// same time.
+    pub time: f64,
+
+    /// Wavelength of the ray that scattered, for spectral renders
+    pub wavelength: f64,
+
+    /// Distribution of directions into which light scatters at p
+    pub phase: HenyeyGreenstein
+}
+
+impl MediumInteraction {
+    pub fn new(p: Point, wo: Vector, time: f64, phase: HenyeyGreenstein) -> MediumInteraction {
+        MediumInteraction { p, wo, time, wavelength: 0.0, phase }
+    }
+
+    /// Ray leaving the interaction in the given direction. Points in a medium
+    /// lie on no surface, so rays start right at p.
+    pub fn spawn_ray(&self, d: Vector) -> Ray {
+        Ray::new(self.p, d).at_time(self.time).at_wavelength(self.wavelength)
+    }
+}
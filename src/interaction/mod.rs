@@ -1,25 +1,51 @@
-// use crate::space::*;
+use crate::space::*;
 
-/*
-enum Interaction {
-    Surface(SurfaceInteraction)
-    // TODO:
-    // Medium(medium::MediumInteraction)
+/// Point at which light scatters, either off a surface or within a medium
+pub enum Interaction<'a> {
+    Surface(&'a SurfaceInteraction),
+    Medium(&'a MediumInteraction)
 }
 
-impl Interaction {
-    fn p(&self) -> Point {
-        match self { Interaction::Surface(interaction) => interaction.p }
+impl<'a> Interaction<'a> {
+    /// Origin for rays leaving the interaction toward wo. Starts past the
+    /// error bounds of surface points.
+    pub fn spawn_origin(&self, wo: &Vector) -> Point {
+        match self {
+            Interaction::Surface(interaction) => interaction.spawn_origin(wo),
+            Interaction::Medium(interaction) => interaction.p
+        }
     }
 
-    fn n(&self) -> Normal {
-        match self { Interaction::Surface(interaction) => interaction.n }
+    /// Normalized direction toward the origin of the ray that found the
+    /// interaction
+    pub fn wo(&self) -> Vector {
+        match self {
+            Interaction::Surface(interaction) => interaction.wo,
+            Interaction::Medium(interaction) => interaction.wo
+        }
+    }
+
+    pub fn time(&self) -> f64 {
+        match self {
+            Interaction::Surface(interaction) => interaction.time,
+            Interaction::Medium(interaction) => interaction.time
+        }
+    }
+
+    /// Whether objects in the scene may block light from reaching the
+    /// interaction. Always true within a medium.
+    pub fn receives_shadows(&self) -> bool {
+        match self {
+            Interaction::Surface(interaction) => interaction.receives_shadows,
+            Interaction::Medium(_) => true
+        }
     }
 }
-*/
 
 pub mod surface;
 pub mod bsdf;
+pub mod medium;
 pub type SurfaceInteraction = surface::SurfaceInteraction<f64>;
 pub type RayIntersection = surface::RayIntersection<f64>;
 pub use self::bsdf::BSDF;
+pub use self::medium::MediumInteraction;
@@ -1,5 +1,5 @@
-use cgmath::{prelude::*, Point2, Point3, Vector3, BaseFloat };
-use crate::{space::{normal::Normal3, ray::Ray3}, Material};
+use cgmath::{prelude::*, Point2, Point3, Vector2, Vector3, BaseFloat };
+use crate::{space::{normal::Normal3, ray::{Ray3, RayDifferentials}, offset_ray_origin}, MaterialRef};
 
 /// Collection of shading parameters, used for either geometry or surface
 /// shading.
@@ -34,7 +34,25 @@ pub struct RayIntersection<N: BaseFloat> {
     /// Ray equation parameter used to determine point of intersection
     pub t: N,
 
-    /// Texture UV, each in range [0, 1] coordinates. TODO: Actually use this
+    /// Point of intersection, computed by the shape more accurately than by
+    /// evaluating the ray equation at t
+    pub p: Point3<N>,
+
+    /// Conservative bound on the absolute floating-point error in each
+    /// component of p
+    pub p_err: Vector3<N>,
+
+    /// Point of intersection in the space of the group that holds the
+    /// intersected primitive, which stays put as the group moves. Looked up
+    /// by procedural textures.
+    pub p_object: Point3<N>,
+
+    /// Shading normal in the same space as `p_object`, for texture
+    /// projections. Kept by the first transformation applied to the
+    /// intersection; until then the normal is `ns()`.
+    pub n_object: Option<Normal3<N>>,
+
+    /// Texture UV, each in range [0, 1] coordinates
     pub uv: Point2<N>,
 
     /// Base geometry shading
@@ -46,25 +64,55 @@ pub struct RayIntersection<N: BaseFloat> {
 
     /// Material at surface interaction. Use this when the shape doesn't provide
     /// a material on its own.
-    pub material: Material,
+    pub material: MaterialRef,
 
     /// Optional authoritative shading normal, to be used instead of surface
     /// shading parameters for some shapes
     pub n: Option<Normal3<N>>,
+
+    /// Colour interpolated from mesh vertex colours, if any. Multiplied into
+    /// the albedo of the material. White for shapes without vertex colours.
+    pub color: Vector3<N>,
+
+    /// Index of the node at the top level of the scene that holds the
+    /// intersected primitive, for object ID passes. Set by the acceleration
+    /// structure at the root of the scene.
+    pub id: u32,
+
+    /// Whether hits on the back of the intersected primitive are ignored.
+    /// True for primitives in groups with backface culling.
+    pub cull_backface: bool,
+
+    /// Whether the intersected primitive blocks light from reaching other
+    /// surfaces. False for primitives in groups that don't cast shadows.
+    pub casts_shadows: bool,
+
+    /// Whether light reaching the intersected primitive may be blocked by
+    /// other surfaces. False for primitives in groups that don't receive
+    /// shadows.
+    pub receives_shadows: bool
 }
 
 impl<N: BaseFloat> RayIntersection<N> {
-    pub fn new(t: N, uv: Point2<N>, dpdu: Vector3<N>, dpdv: Vector3<N>) -> Self {
+    /// Intersection at ray parameter t and point p, which is assumed exact.
+    /// Shapes that compute p with rounding error set `p_err` afterwards.
+    pub fn new(t: N, p: Point3<N>, uv: Point2<N>, dpdu: Vector3<N>, dpdv: Vector3<N>) -> Self {
         let geometry = Shading { dpdu, dpdv };
-        let material = Material::default();
+        let material = MaterialRef::default();
+        let p_err = Vector3::zero();
         // Surface shading is copied geometry
-        RayIntersection { t, uv, geometry, surface: geometry, material, n: None }
+        let color = Vector3::from_value(N::one());
+        RayIntersection {
+            t, p, p_err, p_object: p, n_object: None, uv, geometry, surface: geometry, material, n: None, color, id: 0,
+            cull_backface: false, casts_shadows: true, receives_shadows: true
+        }
     }
 
     /// Create a non-existent ray intersection that will be populated later
     pub fn default() -> Self {
         Self::new(
             N::infinity(),
+            Point3::origin(),
             Point2::new(N::zero(), N::zero()),
             Vector3::zero(),
             Vector3::zero()
@@ -79,7 +127,7 @@ impl<N: BaseFloat> RayIntersection<N> {
 
     /// Reset the default material, to use when the shape of intersection
     /// doesn't provide one.
-    pub fn set_material(&mut self, material: Material) {
+    pub fn set_material(&mut self, material: MaterialRef) {
         self.material = material
     }
 
@@ -126,15 +174,26 @@ pub struct SurfaceInteraction<N: BaseFloat> {
     /// Point of interaction in world coordinates
     pub p: Point3<N>,
 
-    /// A small vector used to offset floating-point error from the point of
-    /// interaction. Used to avoid speckling during the lighting/integration
-    /// step. Parallel to the normal vector n.
+    /// Conservative bound on the absolute floating-point error in each
+    /// component of p. Rays leaving the surface start just past it to avoid
+    /// speckling from intersecting the surface they leave.
     pub p_err: Vector3<N>,
 
     /// Outgoing direction vector at point of interaction based on ray
     /// definition. Points from `p` to the ray's origin; reversed `ray.d`.
     pub wo: Vector3<N>,
 
+    /// Point of interaction in object coordinates. See
+    /// `RayIntersection::p_object`
+    pub p_object: Point3<N>,
+
+    /// Unit shading normal in object coordinates. See
+    /// `RayIntersection::n_object`
+    pub n_object: Normal3<N>,
+
+    /// Texture UV coordinates at the point of interaction
+    pub uv: Point2<N>,
+
     /// Geometric shading normal. e.g., perpendicular to plane that a triangle
     /// lies in. Always points in the same hemisphere as ray origin.
     pub ng: Normal3<N>,
@@ -148,6 +207,40 @@ pub struct SurfaceInteraction<N: BaseFloat> {
 
     /// Normalized surface shading parameters
     pub surface: Shading<N>,
+
+    /// Lengths of the surface's ∂p/∂u and ∂p/∂v before they were normalized,
+    /// i.e., the distance the point moves per unit of u and v
+    pub uv_scale: Vector2<N>,
+
+    /// Vertex colour multiplier at the point of interaction. See
+    /// `RayIntersection::color`
+    pub color: Vector3<N>,
+
+    /// Time of the ray that found this interaction. Rays spawned from here
+    /// are cast at the same time.
+    pub time: N,
+
+    /// Wavelength of the ray that found this interaction, for spectral
+    /// renders. See `Ray3::wavelength`
+    pub wavelength: N,
+
+    /// Whether other surfaces may shadow this one. See
+    /// `RayIntersection::receives_shadows`
+    pub receives_shadows: bool,
+
+    /// Change in the point of interaction and its UV coordinates from one
+    /// pixel of the image to the next along x and y, estimated from the ray
+    /// differentials. Zero for rays without differentials. Textures filter
+    /// their lookups over this footprint.
+    pub dpdx: Vector3<N>,
+    pub dpdy: Vector3<N>,
+    pub duvdx: Vector2<N>,
+    pub duvdy: Vector2<N>,
+
+    /// Change in the outgoing direction from one pixel to the next, for
+    /// carrying differentials through specular reflection
+    pub dwodx: Vector3<N>,
+    pub dwody: Vector3<N>
 }
 
 impl<N: BaseFloat> SurfaceInteraction<N> {
@@ -162,15 +255,17 @@ impl<N: BaseFloat> SurfaceInteraction<N> {
         let ng = Normal3(isect.ng()).face_forward(wo);
         let ns = Normal3(isect.ns());
 
-        // Add a small fraction of the normal to avoid speckling due to
-        // floating point errors (the calculated point ends up inside the
-        // geometric primitive).
-        let err = N::epsilon() * (N::one() + N::one()).powi(16);
-        let p = ray.origin + ray.d*isect.t;
-        let p_err = ng.0 * err;
-
-        SurfaceInteraction {
-            p, p_err, wo, ng, ns,
+        let mut interaction = SurfaceInteraction {
+            p: isect.p,
+            p_err: isect.p_err,
+            wo, ng, ns,
+            p_object: isect.p_object,
+            n_object: isect.n_object.map_or(ns, |n| Normal3(n.0.normalize())),
+            uv: isect.uv,
+            color: isect.color,
+            time: ray.time,
+            wavelength: ray.wavelength,
+            receives_shadows: isect.receives_shadows,
             geometry: Shading {
                 dpdu: isect.geometry.dpdu.normalize(),
                 dpdv: isect.geometry.dpdv.normalize(),
@@ -178,12 +273,96 @@ impl<N: BaseFloat> SurfaceInteraction<N> {
             surface: Shading {
                 dpdu: isect.surface.dpdu.normalize(),
                 dpdv: isect.surface.dpdv.normalize(),
-            }
+            },
+            uv_scale: Vector2::new(isect.surface.dpdu.magnitude(), isect.surface.dpdv.magnitude()),
+            dpdx: Vector3::zero(),
+            dpdy: Vector3::zero(),
+            duvdx: Vector2::zero(),
+            duvdy: Vector2::zero(),
+            dwodx: Vector3::zero(),
+            dwody: Vector3::zero()
+        };
+
+        if let Some(diff) = ray.differentials {
+            interaction.compute_differentials(&diff, &isect.geometry);
         }
+        interaction
+    }
+
+    /// Find the footprint of the ray on the surface, where the differential
+    /// rays meet the tangent plane at the point of interaction, and the change
+    /// in UV coordinates across it. See PBRT v3 section 10.1.1
+    fn compute_differentials(&mut self, diff: &RayDifferentials<N>, geometry: &Shading<N>) {
+        let n = self.ng.0;
+        let d = n.dot(self.p.to_vec());
+        let (nx, ny) = (n.dot(diff.rx_d), n.dot(diff.ry_d));
+        if nx == N::zero() || ny == N::zero() { return }
+        let tx = (d - n.dot(diff.rx_origin.to_vec())) / nx;
+        let ty = (d - n.dot(diff.ry_origin.to_vec())) / ny;
+        if !(tx.is_finite() && ty.is_finite()) { return }
+        self.dpdx = (diff.rx_origin + diff.rx_d * tx) - self.p;
+        self.dpdy = (diff.ry_origin + diff.ry_d * ty) - self.p;
+        self.dwodx = -diff.rx_d.normalize() - self.wo;
+        self.dwody = -diff.ry_d.normalize() - self.wo;
+
+        // Solve dp = ∂p/∂u du + ∂p/∂v dv by least squares in the two
+        // dimensions in which the surface is largest
+        let (dpdu, dpdv) = (geometry.dpdu, geometry.dpdv);
+        let (a, b) = if n.x.abs() > n.y.abs() && n.x.abs() > n.z.abs() {
+            (1, 2)
+        } else if n.y.abs() > n.z.abs() {
+            (0, 2)
+        } else {
+            (0, 1)
+        };
+        let det = dpdu[a] * dpdv[b] - dpdv[a] * dpdu[b];
+        if det == N::zero() { return }
+        let solve = |dp: Vector3<N>| {
+            let du = (dpdv[b] * dp[a] - dpdv[a] * dp[b]) / det;
+            let dv = (dpdu[a] * dp[b] - dpdu[b] * dp[a]) / det;
+            if du.is_finite() && dv.is_finite() { Vector2::new(du, dv) } else { Vector2::zero() }
+        };
+        self.duvdx = solve(self.dpdx);
+        self.duvdy = solve(self.dpdy);
     }
 
     #[inline] pub fn ng(&self) -> Vector3<N> { self.ng.0 }
     #[inline] pub fn ns(&self) -> Vector3<N> { self.ns.0 }
+
+    /// Whether the interaction is on the back of the surface, away from the
+    /// shading normal
+    #[inline]
+    pub fn is_backface(&self) -> bool {
+        self.ns.0.dot(self.wo) < N::zero()
+    }
+}
+
+impl SurfaceInteraction<f64> {
+    /// Origin for rays leaving the surface in direction w. See
+    /// `offset_ray_origin`.
+    #[inline]
+    pub fn spawn_origin(&self, w: &Vector3<f64>) -> Point3<f64> {
+        offset_ray_origin(&self.p, &self.p_err, &self.ng.0, w)
+    }
+
+    /// Ray leaving the surface in direction d, cast at the same time
+    #[inline]
+    pub fn spawn_ray(&self, d: Vector3<f64>) -> Ray3<f64> {
+        Ray3::new(self.spawn_origin(&d), d).at_time(self.time).at_wavelength(self.wavelength)
+    }
+
+    /// Ray leaving the surface in the mirror direction wi, with differentials
+    /// reflected the same way if the ray that found this interaction had any.
+    /// Treats the surface as locally flat, since curvature isn't tracked.
+    pub fn spawn_reflected_ray(&self, wi: Vector3<f64>) -> Ray3<f64> {
+        let ray = self.spawn_ray(wi);
+        if self.dpdx == Vector3::zero() && self.dpdy == Vector3::zero() { return ray }
+        let n = self.ns.0;
+        let reflect = |dwo: Vector3<f64>| wi - dwo + n * (2.0 * dwo.dot(n));
+        let rx = Ray3::new(ray.origin + self.dpdx, reflect(self.dwodx));
+        let ry = Ray3::new(ray.origin + self.dpdy, reflect(self.dwody));
+        ray.with_differentials(&rx, &ry)
+    }
 }
 
 #[cfg(test)]
@@ -193,9 +372,24 @@ mod test {
     #[test]
     fn simple() {
         let ray: Ray3<f64> = Ray3::new(Point3::new(0.0, 0.0, 1.0), -Vector3::unit_z());
-        let isect = RayIntersection::new(1.0, Point2::new(0.0, 0.0), Vector3::unit_x(), Vector3::unit_y());
+        let isect = RayIntersection::new(1.0, Point3::origin(), Point2::new(0.0, 0.0), Vector3::unit_x(), Vector3::unit_y());
         let interaction = SurfaceInteraction::from(&ray, &isect);
 
         assert_eq!(interaction.ng(), Vector3::unit_z());
     }
+
+    #[test]
+    fn spawns_rays_past_error_bounds() {
+        let ray: Ray3<f64> = Ray3::new(Point3::new(0.0, 0.0, 1.0), -Vector3::unit_z());
+        let mut isect = RayIntersection::new(1.0, Point3::new(0.0, 0.0, 0.5), Point2::new(0.0, 0.0), Vector3::unit_x(), Vector3::unit_y());
+        isect.p_err = Vector3::new(1e-3, 1e-3, 1e-3);
+        let interaction = SurfaceInteraction::from(&ray, &isect);
+
+        let reflected = interaction.spawn_ray(Vector3::new(0.0, 1.0, 1.0));
+        assert!(reflected.origin.z > 0.501);
+        assert_eq!(reflected.origin.y, 0.0);
+
+        let transmitted = interaction.spawn_ray(-Vector3::unit_z());
+        assert!(transmitted.origin.z < 0.499);
+    }
 }
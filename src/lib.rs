@@ -1,5 +1,5 @@
-#[cfg(feature = "bin")]
-use num_cpus;
+#[cfg(feature = "threads")]
+use rayon::prelude::*;
 
 #[macro_use]
 extern crate bitflags;
@@ -16,23 +16,60 @@ pub(crate) mod material;
 pub(crate) mod shape;
 pub(crate) mod primitive;
 pub(crate) mod light;
+pub(crate) mod texture;
+pub(crate) mod tonemap;
+pub(crate) mod filter;
+pub(crate) mod medium;
+pub(crate) mod spectrum;
 mod accelerators;
 mod integrate;
+mod sampler;
+mod progress;
+mod checkpoint;
+mod stats;
+mod trace;
+#[cfg(feature = "denoise")]
+mod denoise;
 
 pub mod scene;
+pub mod formats;
+pub mod animation;
+pub mod scenes;
 
 #[cfg(feature = "bin")]
 pub mod output;
+#[cfg(feature = "preview")]
+pub mod preview;
+#[cfg(feature = "gpu")]
+pub mod gpu;
 
-use std::thread;
-use std::ptr::NonNull;
+use std::time::Instant;
+use crate::{film::{Tile, Splats}, space::*, sampler::{Sampler, PixelVariance}, integrate::aov::AovSum, progress::ProgressTracker, stats::Tally};
 
 pub use crate::scene::Scene;
 pub use crate::camera::Camera;
-pub use crate::img::{Pixel, PixelBuffer, Img};
-pub use crate::film::Film;
+pub use crate::img::{Pixel, PixelBuffer, Img, set_pixel_color};
+pub use crate::film::{Film, HdrFilm, FilmSet, Aovs, FilmDataHunk};
 pub use crate::primitive::Primitive;
-pub use crate::material::Material;
+pub use crate::material::{Material, MaterialRef};
+pub use crate::integrate::{Integrator, DebugView, furnace::EnergyAudit};
+pub use crate::light::{EnvironmentMap, LightPower, LightVisibility};
+pub use crate::tonemap::{Tonemap, Operator as TonemapOperator};
+pub use crate::texture::{Texture, TextureRef, ConstantTexture, ImageTexture, TextureFilter, Checkerboard, Noise, NoisePattern, Projection, Axis};
+pub use crate::shape::mesh::{TriangleMesh, Smoothing};
+pub use crate::shape::sdf::Sdf;
+pub use crate::shape::subdiv::SubdivMesh;
+pub use crate::shape::curve::CurveKind;
+pub use crate::accelerators::{AcceleratorKind, BvhBuildStrategy};
+pub use crate::sampler::{SamplerKind, AdaptiveSampling};
+pub use crate::filter::Filter;
+pub use crate::medium::{Medium, HomogeneousMedium, GridMedium, DensityGrid};
+pub use crate::progress::{RenderProgress, Progress, TileBounds, RenderHandle};
+pub use crate::checkpoint::capture_resume;
+pub use crate::stats::{RenderStats, BvhStats};
+pub use crate::trace::{trace_pixel, PixelTrace, SampleTrace, TraceEvent, RadianceSource};
+#[cfg(feature = "denoise")]
+pub use crate::denoise::{Denoiser, NlMeans};
 
 /// An acceleration structure to reduce the number of ray-object intersection
 /// tests. Call the associated `from` method with a scene reference to get back
@@ -41,73 +78,434 @@ pub use crate::material::Material;
 /// Internally implemented as a Bounding-Volume Hierarchy
 pub type Accel<'s> = self::accelerators::bvh::BVHAccel<'s>;
 
+/// Shape of an acceleration structure, kept to refit it to a later state of
+/// the same scene. See `Accel::refit`.
+pub type AccelLayout = self::accelerators::bvh::BVHLayout;
+
 /// Render the given scene. Returns a Film instance, over you may iterate with
 /// the foreach method.
 pub fn render(scene: &Scene, resolution: (u32, u32)) -> Film {
     let mut film = Film::new(resolution.0, resolution.1);
+    film.tonemap = scene.tonemap;
     capture(scene, &mut film);
     film
 }
 
-/// Record an image of the scene on the given film. The film must have at least
-/// (scene.width * scene.height) pixels reserved in the Film
-/// data field.
-pub fn capture(scene: &Scene, film: &mut Film) {
+/// Width and height of the tiles the film is split into for rendering
+const TILE_SIZE: u32 = 32;
+
+/// Record an image of the scene on the given film or other image, such as an
+/// HdrFilm. The film must have at least (scene.width * scene.height) pixels
+/// reserved in the Film data field.
+///
+/// The film is split into square tiles, which are rendered in parallel when
+/// the threads feature is enabled. Tiles keep each thread working on neighbouring
+/// pixels, which tend to touch the same primitives.
+pub fn capture(scene: &Scene, film: &mut impl Img) {
+    capture_accel(&Accel::from(scene), film)
+}
+
+/// Record an image of the scene held by the given acceleration structure, such
+/// as one refit to the scene with `Accel::refit`. See `capture`.
+pub fn capture_accel(root: &Accel, film: &mut impl Img) {
+    capture_tiles(root, &root.scene.camera, film, None, None, None);
+}
 
-    // Get number of threads to use. Uses one by default
-    let barrel_count = if scene.threads == 0 {
-        get_max_threads()
-    } else {
-        scene.threads
-    };
+/// Record an image of the scene held by the given acceleration structure as
+/// seen through the given camera instead of the scene's own. Moving the camera
+/// doesn't change the scene geometry, so interactive front-ends may build the
+/// acceleration structure once and re-render from each new viewpoint.
+pub fn capture_with_camera(root: &Accel, camera: &Camera, film: &mut impl Img) {
+    capture_tiles(root, camera, film, None, None, None);
+}
+
+/// Record an image of the scene as with `capture`, reporting each finished
+/// tile and the overall progress to the given listener as the render goes.
+pub fn capture_with_progress(scene: &Scene, film: &mut impl Img, progress: &dyn RenderProgress) {
+    capture_tiles(&Accel::from(scene), &scene.camera, film, Some(progress), None, None);
+}
 
+/// Record an image of the scene as with `capture`, stopping early if the
+/// given handle is canceled. Tiles already started still finish and are
+/// recorded on the film; the rest are left as they were. Returns whether the
+/// whole image was captured.
+pub fn capture_cancelable(scene: &Scene, film: &mut impl Img, handle: &RenderHandle) -> bool {
+    capture_tiles(&Accel::from(scene), &scene.camera, film, None, Some(handle), None)
+}
+
+/// Record an image of the scene as with `capture`, counting the rays traced
+/// and the intersection tests made along the way. Reports progress to the
+/// given listener, if any. Returns the counts along with the shape of the
+/// acceleration structure and the time taken by each stage, which displays as
+/// a report.
+pub fn capture_with_stats(scene: &Scene, film: &mut impl Img, progress: Option<&dyn RenderProgress>) -> RenderStats {
+    let start = Instant::now();
     let root = Accel::from(scene);
-    let mut threads = Vec::with_capacity(barrel_count - 1);
-
-    for i in 1..barrel_count {
-
-        // This weird unsafe pointer casting is to allow multiple instances of
-        // the constant scene reference and the mutable pixel buffer reference
-        // to be used by the tracing code.
-        //
-        // This allows the various capture_subset calls to operate on the same
-        // block of memory concurrently without having to break it up, rearrange
-        // it, and put it back together at the end.
-        //
-        // It's very important that the threads join the main thread before
-        // this function call ends, otherwise very bad things will happen.
-        //
-        // TODO: Pls. make this less terrifying
-        let sendable_film_ptr = UnsafeThreadWrapperMut(NonNull::new(film as *mut Film).unwrap());
-        let sendable_root_ptr = UnsafeThreadWrapper(unsafe {
-            // I am so, so, so sorry, I need this to get sendable_root_ptr
-            // across the thread boundary. I promise I super-quadruple-checked
-            // that this is safe.
-            std::mem::transmute::<&Accel<'_>, &Accel<'static>>(&root)
-        } as *const Accel);
-
-        let handle = thread::spawn(move || {
-            let root: &Accel = unsafe { &*sendable_root_ptr.0 };
-            let film: &mut Film = unsafe { &mut *sendable_film_ptr.0.as_ptr() };
-            capture_subset(i, barrel_count, root, film)
+    let build_time = start.elapsed();
+
+    let start = Instant::now();
+    let tally = Tally::default();
+    capture_tiles(&root, &scene.camera, film, progress, None, Some(&tally));
+    tally.stats(root.stats(), build_time, start.elapsed())
+}
+
+/// Render the scene at the given resolution without recording it on a film,
+/// only reporting each finished tile to the given listener. Rows of tiles are
+/// rendered one after the other, so that no more than a row is held in memory
+/// at once and listeners such as `output::StreamingWriter` may write images
+/// too large to keep whole. With a filter wider than a pixel, pixels along the
+/// edges of tiles only count the samples of their own tile.
+pub fn capture_streaming(scene: &Scene, resolution: (u32, u32), progress: &dyn RenderProgress) {
+    let root = Accel::from(scene);
+    let (width, height) = resolution;
+    let count = |length: u32| length.div_ceil(TILE_SIZE) as usize;
+    let tracker = ProgressTracker::new(progress, scene.tonemap, count(width) * count(height));
+    for y in (0..height).step_by(TILE_SIZE as usize) {
+        let tiles = Tile::split_row(width, height, y, TILE_SIZE, false);
+        map_tiles(scene.threads, tiles, |mut tile| {
+            capture_tile(&root, &scene.camera, &mut tile);
+            tracker.tile_complete(&tile)
         });
+    }
+}
+
+/// Render the film tile by tile through the given camera, reporting to the
+/// given listener, if any. Tiles not yet started when the given handle, if
+/// any, is canceled are skipped. The work done for each tile is added to the
+/// given tally, if any. Returns whether every tile was rendered.
+fn capture_tiles(
+    root: &Accel,
+    camera: &Camera,
+    film: &mut impl Img,
+    progress: Option<&dyn RenderProgress>,
+    handle: Option<&RenderHandle>,
+    tally: Option<&Tally>
+) -> bool {
+    let tiles = Tile::split(film.w(), film.h(), TILE_SIZE, film.has_aovs());
+    let tracker = progress.map(|progress| ProgressTracker::new(progress, root.scene.tonemap, tiles.len()));
+    let tiles = map_tiles(root.scene.threads, tiles, |mut tile| {
+        if handle.is_some_and(RenderHandle::is_canceled) { return None }
+        // Leave out whatever else this thread counted before the tile
+        stats::take();
+        capture_tile(root, camera, &mut tile);
+        if let Some(tally) = tally { tally.add(stats::take()) }
+        if let Some(tracker) = &tracker { tracker.tile_complete(&tile) }
+        Some(tile)
+    });
 
-        threads.push(handle)
+    let mut complete = true;
+    let mut splats = if root.scene.filter.is_pixel_box() { None } else { Some(Splats::new(film.w(), film.h())) };
+    for tile in tiles {
+        match tile {
+            Some(tile) => {
+                tile.merge_into(film);
+                if let Some(splats) = &mut splats { splats.add(&tile) }
+            },
+            None => complete = false
+        }
     }
+    if let Some(splats) = splats { splats.write_into(film) }
+    complete
+}
+
+/// Apply f to each of the given tiles, or work on tiles, on a pool of the
+/// given number of threads and collect the results, in order. Zero threads
+/// means use rayon's global pool, which has as many threads as the system
+/// allows.
+#[cfg(feature = "threads")]
+fn map_tiles<I, T, F>(threads: usize, tiles: Vec<I>, f: F) -> Vec<T>
+where I: Send, T: Send, F: Fn(I) -> T + Send + Sync {
+    // Wasm can't spawn threads of its own, so in browsers this is the pool
+    // started from JavaScript with wasm-bindgen-rayon's initThreadPool
+    if threads == 0 { return tiles.into_par_iter().map(f).collect() }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .expect("could not start render threads");
+
+    pool.install(|| tiles.into_par_iter().map(f).collect())
+}
 
-    // Ensure main thread does processing
-    capture_subset(0, barrel_count, &root, film);
+#[cfg(not(feature = "threads"))]
+fn map_tiles<I, T, F>(_threads: usize, tiles: Vec<I>, f: F) -> Vec<T>
+where I: Send, T: Send, F: Fn(I) -> T + Send + Sync {
+    tiles.into_iter().map(f).collect()
+}
+
+/// Render every pixel in the given tile. With a filter wider than a pixel,
+/// samples are also splatted onto the pixels around them, and the tile's own
+/// pixels are set from the splats of this tile alone.
+fn capture_tile(root: &Accel, camera: &Camera, tile: &mut Tile) {
+    let mut sampler = new_sampler(root.scene, camera);
+    let filter = root.scene.filter;
+    if filter.is_pixel_box() {
+        for y in tile.y..(tile.y + tile.tile_h) {
+            for x in tile.x..(tile.x + tile.tile_w) {
+                let offset = (y as usize) * (tile.w() as usize) + (x as usize);
+                capture_pixel(root, camera, x, y, offset, tile, &mut sampler)
+            }
+        }
+        return
+    }
 
-    // IMPORTANT: Ensure the threads join before the function returns. Otherwise
-    // the Scene reference might disappear and everything will explode.
-    for thread in threads { thread.join().unwrap() }
+    tile.start_splats(&filter);
+    let mut samples = vec![];
+    for y in tile.y..(tile.y + tile.tile_h) {
+        for x in tile.x..(tile.x + tile.tile_w) {
+            let offset = (y as usize) * (tile.w() as usize) + (x as usize);
+            sampler.start_pixel(offset as u64);
+            let (_, aovs) = sample_pixel(root, camera, x, y, tile, &mut sampler, |u, sample| samples.push((*u, *sample)));
+            if let Some(aovs) = aovs { tile.set_aovs(x, y, &aovs) }
+
+            // Points within the pixel count up from its bottom-left corner
+            for (u, sample) in samples.drain(..) {
+                tile.splat(x as f64 + u.x, (y + 1) as f64 - u.y, &sample, &filter)
+            }
+        }
+    }
+    tile.resolve_splats()
+}
+
+/// Sampler of the given scene for the samples per pixel set by the given
+/// camera
+fn new_sampler(scene: &Scene, camera: &Camera) -> Sampler {
+    Sampler::new(scene.sampler, camera.num_samples() as u32)
+}
+
+/// Capture the pixel at the given x/y coordinates through the given camera,
+/// where offset is the pixel's
+/// row-major index into the full image. With adaptive sampling, keeps taking
+/// as many samples again until the pixel converges or reaches the maximum.
+#[inline]
+fn capture_pixel(root: &Accel, camera: &Camera, x: u32, y: u32, offset: usize, img: &mut impl Img, sampler: &mut Sampler) {
+    // Seed per pixel so that output does not depend on the thread count
+    sampler.start_pixel(offset as u64);
+    let (color, aovs) = sample_pixel(root, camera, x, y, img, sampler, |_, _| ());
+    img.set(x, y, &color.into());
+    if let Some(aovs) = aovs { img.set_aovs(x, y, &aovs) }
+}
+
+/// Take the samples of the pixel at the given x/y coordinates as with
+/// `capture_pixel`, once the sampler has started the pixel, passing each to f
+/// along with its point within the pixel. Returns the average of the samples
+/// and, if the image records them, the pixel's auxiliary pass values.
+#[inline]
+fn sample_pixel<F>(root: &Accel, camera: &Camera, x: u32, y: u32, img: &impl Img, sampler: &mut Sampler, mut f: F) -> (Color, Option<Aovs>)
+where F: FnMut(&Point2f, &Color) {
+    let spp = sampler.samples_per_pixel();
+    let adaptive = root.scene.adaptive;
+    let max_samples = adaptive.map_or(spp, |adaptive| adaptive.max_samples.max(spp));
+
+    let mut color = Color::zero();
+    let mut variance = PixelVariance::default();
+    let mut aovs = if img.has_aovs() { Some(AovSum::new()) } else { None };
+    let mut n = 0;
+    while n < max_samples {
+        for i in n..(n + spp).min(max_samples) {
+            sampler.start_sample(i);
+            let (ray, u) = camera.sample_at(x, y, img, sampler);
+            if let Some(aovs) = &mut aovs { aovs.add(root, &ray) }
+            let sample = integrate::integrate(root, &ray, sampler);
+            if adaptive.is_some() { variance.add(&sample) }
+            f(&u, &sample);
+            color += sample;
+        }
+        n = (n + spp).min(max_samples);
+        match adaptive {
+            Some(adaptive) if !variance.converged(&adaptive) => continue,
+            _ => break
+        }
+    }
+    stats::record(|counts| counts.primary_rays += n as u64);
+    (color / n as f64, aovs.map(|aovs| aovs.average()))
+}
+
+/// Renders a scene over a series of passes, each of which refines the image with
+/// more samples per pixel. The first pass takes a single sample per pixel and
+/// each subsequent pass doubles the total, so front-ends may display a noisy
+/// preview that converges to the final image.
+///
+/// Samples are placed by the scene sampler, continuing its sequence from one
+/// pass to the next. With the default uniform sampler, samples are jittered
+/// within each pixel; the camera supersampling setting is not used.
+pub struct ProgressiveRenderer<'s> {
+    root: Accel<'s>,
+
+    /// Camera to render through in place of the scene's own, if any
+    camera: Option<Camera>,
+
+    /// Sum of all samples taken so far for each pixel, in row-major order
+    accumulated: Vec<Color>,
+
+    /// Width and height of the film the samples were taken for
+    size: (u32, u32),
+
+    /// Number of samples taken so far for each pixel
+    samples: u32,
+
+    /// Number of completed passes
+    pass: u32
+}
+
+impl<'s> ProgressiveRenderer<'s> {
+    pub fn new(scene: &'s Scene) -> ProgressiveRenderer<'s> {
+        ProgressiveRenderer {
+            root: Accel::from(scene),
+            camera: None,
+            accumulated: vec![],
+            size: (0, 0),
+            samples: 0,
+            pass: 0
+        }
+    }
+
+    /// The scene being rendered
+    pub fn scene(&self) -> &'s Scene { self.root.scene }
+
+    /// Render through the given camera from now on, e.g., as the user orbits
+    /// around the scene in a viewer. Starts over from the next pass without
+    /// rebuilding the acceleration structure.
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = Some(camera);
+        self.accumulated = vec![];
+        self.size = (0, 0);
+        self.samples = 0;
+        self.pass = 0;
+    }
+
+    /// Number of samples per pixel taken so far
+    pub fn samples(&self) -> u32 { self.samples }
+
+    /// Number of completed passes
+    pub fn passes(&self) -> u32 { self.pass }
+
+    /// Take the next round of samples and write the average of all samples
+    /// taken so far to the given film. Starts over if the film dimensions
+    /// changed since the previous pass.
+    pub fn next_pass(&mut self, film: &mut impl Img) {
+        let size = (film.w(), film.h());
+        if self.size != size || self.accumulated.is_empty() {
+            self.accumulated = vec![Color::zero(); size.0 as usize * size.1 as usize];
+            self.size = size;
+            self.samples = 0;
+            self.pass = 0;
+        }
+        self.take_samples(film, self.samples.max(1))
+    }
+
+    /// Take the given number of samples for each pixel as one pass and write
+    /// the average of all samples taken so far to the given film, which must
+    /// match the dimensions of the accumulated samples
+    fn take_samples(&mut self, film: &mut impl Img, spp: u32) {
+        let (root, first) = (&self.root, self.samples);
+        let camera = self.camera.as_ref().unwrap_or(&root.scene.camera);
+        let tiles = Tile::split(film.w(), film.h(), TILE_SIZE, false);
+        let tiles = map_tiles(root.scene.threads, tiles, |tile| {
+            let sums = capture_tile_samples(root, camera, &tile, spp, first);
+            (tile, sums)
+        });
+
+        self.samples += spp;
+        self.pass += 1;
+
+        let weight = 1. / self.samples as f64;
+        for (tile, sums) in tiles {
+            let mut sums = sums.into_iter();
+            for y in tile.y..(tile.y + tile.tile_h) {
+                for x in tile.x..(tile.x + tile.tile_w) {
+                    let offset = film.offset(x, y);
+                    self.accumulated[offset] += sums.next().unwrap();
+                    film.set(x, y, &(self.accumulated[offset] * weight).into())
+                }
+            }
+        }
+    }
+}
+
+/// Take spp samples for each pixel in the given tile, following the first
+/// samples taken by previous passes. Returns the sum of the samples for each
+/// pixel, in row-major order within the tile.
+fn capture_tile_samples(root: &Accel, camera: &Camera, tile: &Tile, spp: u32, first: u32) -> Vec<Color> {
+    let mut sampler = Sampler::progressive(root.scene.sampler, spp);
+    let mut sums = Vec::with_capacity((tile.tile_w * tile.tile_h) as usize);
+    for y in tile.y..(tile.y + tile.tile_h) {
+        for x in tile.x..(tile.x + tile.tile_w) {
+            // Seed per pixel; the sample index moves on with each pass
+            let offset = (y as u64) * (tile.w() as u64) + (x as u64);
+            sampler.start_pixel(offset);
+            let mut sum = Color::zero();
+            for i in first..(first + spp) {
+                sampler.start_sample(i);
+                let ray = camera.sample(x, y, tile, &mut sampler);
+                sum += integrate::integrate(root, &ray, &mut sampler);
+            }
+            sums.push(sum)
+        }
+    }
+    sums
 }
 
 /// Capture subset k of n for the given scene. That is, every kth pixel in the
 /// pixel buffer, arranged in row-major order. The pixel pointer is the start of
 /// the image buffer. The pointer must allow data access into
 /// (scene.width * scene.height) pixels.
+///
+/// Intended for front-ends that schedule their own render workers (e.g., the
+/// WebAssembly build); `capture` handles parallelism on its own.
 pub fn capture_subset(k: usize, n: usize, root: &Accel, img: &mut impl Img) {
+    capture_pixels(k, n, root, &root.scene.camera, img, &RenderHandle::new());
+}
+
+/// Capture subset k of n as with `capture_subset`, stopping at the next
+/// scanline once the given handle is canceled. Returns whether the whole
+/// subset was captured.
+pub fn capture_subset_cancelable(k: usize, n: usize, root: &Accel, img: &mut impl Img, handle: &RenderHandle) -> bool {
+    capture_pixels(k, n, root, &root.scene.camera, img, handle)
+}
+
+/// Capture subset k of n as with `capture_subset`, through the given camera
+/// instead of the scene's own. See `capture_with_camera`.
+pub fn capture_subset_with_camera(k: usize, n: usize, root: &Accel, camera: &Camera, img: &mut impl Img) {
+    capture_pixels(k, n, root, camera, img, &RenderHandle::new());
+}
+
+/// Width and height of the hunks captured by `capture_hunk`
+pub const HUNK_SIZE: u32 = 16;
+
+/// Number of hunks that `capture_hunk` splits an image of the given
+/// dimensions into
+pub fn hunk_count(width: u32, height: u32) -> usize {
+    (width.div_ceil(HUNK_SIZE) as usize) * (height.div_ceil(HUNK_SIZE) as usize)
+}
+
+/// Capture hunk k of an image of the given dimensions and return its pixels,
+/// tonemapped for display. Hunks are HUNK_SIZE x HUNK_SIZE pixels counted in
+/// row-major order, except along the right and bottom edges where they may
+/// be smaller. With a filter wider than a pixel, pixels along the edges of
+/// hunks only count the samples of their own hunk.
+///
+/// Like `capture_subset`, intended for front-ends that schedule their own
+/// render workers, which may send each hunk back as soon as it's done rather
+/// than sharing a film.
+pub fn capture_hunk(k: usize, root: &Accel, width: u32, height: u32) -> FilmDataHunk {
+    capture_hunk_with_camera(k, root, &root.scene.camera, width, height)
+}
+
+/// Capture hunk k as with `capture_hunk`, through the given camera instead
+/// of the scene's own. See `capture_with_camera`.
+pub fn capture_hunk_with_camera(k: usize, root: &Accel, camera: &Camera, width: u32, height: u32) -> FilmDataHunk {
+    debug_assert!(k < hunk_count(width, height));
+    let columns = width.div_ceil(HUNK_SIZE) as usize;
+    let (x, y) = ((k % columns) as u32 * HUNK_SIZE, (k / columns) as u32 * HUNK_SIZE);
+    let mut tile = Tile::new(width, height, x, y, HUNK_SIZE, false);
+    capture_tile(root, camera, &mut tile);
+    FilmDataHunk { bounds: tile.bounds(), data: tile.pixels(&root.scene.tonemap) }
+}
+
+/// Capture every kth of n pixels through the given camera until the given
+/// handle is canceled. Returns whether all were captured.
+fn capture_pixels(k: usize, n: usize, root: &Accel, camera: &Camera, img: &mut impl Img, handle: &RenderHandle) -> bool {
     let scene = root.scene;
     let (width, height) = (img.w() as usize, img.h() as usize);
 
@@ -136,48 +534,379 @@ pub fn capture_subset(k: usize, n: usize, root: &Accel, img: &mut impl Img) {
     //  [3][7]
     // And so on
     //
-    // This pattern guarantees the best possible resource usage for most images
-    // (as opposed to splitting the pixel buffer into chunks - some chunks will
-    // end up touching more primitives than others!)
+    // This pattern spreads the work evenly between workers that cannot share
+    // a queue of tiles (as opposed to splitting the pixel buffer into chunks -
+    // some chunks will end up touching more primitives than others!)
 
     // Calculate the chunk size such that we can yield n chunks,
     // where n is the number of threads
     let area = width * height; // total image area
-    let mut samples = scene.camera.allocate_samples();
-    let weight = 1. / samples.len() as f64;
+    let mut sampler = new_sampler(scene, camera);
 
     // Skip over chunks that other threads are processing/ Assuming
     // capture_subset is never called concurrently with the same k and n values,
     // this will never cause contention/race conditions.
+    let mut row = None;
     for offset in ((k as usize)..area).step_by(n as usize) {
         debug_assert!(offset < area);
         let x = (offset % width) as u32;
         let y = (offset / width) as u32;
         debug_assert!(x < img.w());
         debug_assert!(y < img.h());
-        scene.camera.sample(x, y, img, &mut samples);
-        let color = integrate::integrate(root, &samples, weight);
-        img.set(x, y, &color.into())
+
+        // Check for cancellation once per scanline
+        if row != Some(y) {
+            if handle.is_canceled() { return false }
+            row = Some(y);
+        }
+        capture_pixel(root, camera, x, y, offset, img, &mut sampler)
     }
+    true
 }
 
-#[cfg(feature = "bin")]
-fn get_max_threads() -> usize { num_cpus::get() }
-#[cfg(not(feature = "bin"))]
-fn get_max_threads() -> usize { 1 }
-
-// Funky Pointer containers to allow sharing pointers between threads
-// Need this for the capture function.
-#[derive(Copy, Clone)] struct UnsafeThreadWrapper<T>(*const T);
-#[derive(Copy, Clone)] struct UnsafeThreadWrapperMut<T>(NonNull<T>);
-unsafe impl<T> std::marker::Send for UnsafeThreadWrapper<T> {}
-unsafe impl<T> std::marker::Send for UnsafeThreadWrapperMut<T> {}
-
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
     #[test]
     fn it_works() {
         assert!(true);
     }
+
+    #[test]
+    fn renders_through_given_camera() {
+        let mut scene = Scene::new();
+        scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, MaterialRef::default());
+        scene.add_point_light([0.0, 3.0, 3.0], [0.9, 0.9, 0.9], [1.0, 0.0, 0.0]);
+        scene.set_perspective_camera(60.).look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.]);
+        let side = || {
+            let mut camera = Camera::perspective(60.);
+            camera.look_at([5., 0., 0.], [0., 0., 0.], [0., 1., 0.]);
+            camera
+        };
+
+        let mut moved = HdrFilm::new(8, 8);
+        capture_with_camera(&Accel::from(&scene), &side(), &mut moved);
+
+        scene.set_camera(side());
+        let mut film = HdrFilm::new(8, 8);
+        capture(&scene, &mut film);
+        assert_eq!(moved.data(), film.data());
+    }
+
+    #[test]
+    fn counts_rays_and_tests() {
+        let mut scene = Scene::new();
+        scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, MaterialRef::default());
+        scene.root.add_sphere([2.0, 0.0, 0.0], 1.0, MaterialRef::default());
+        scene.add_point_light([0.0, 3.0, 3.0], [0.9, 0.9, 0.9], [1.0, 0.0, 0.0]);
+        scene.set_perspective_camera(60.).look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.]);
+        scene.camera.set_samples_per_pixel(2);
+        let mut film = Film::new(40, 20);
+        let stats = capture_with_stats(&scene, &mut film, None);
+
+        assert_eq!(stats.primary_rays, 40 * 20 * 2);
+        assert!(stats.shadow_rays > 0);
+        assert!(stats.node_tests >= stats.primary_rays);
+        assert!(stats.primitive_tests > 0);
+        assert_eq!(stats.bvh.hierarchies, 1);
+        assert_eq!(stats.bvh.primitives, 2);
+        assert!(stats.to_string().contains("Primary            1600"));
+    }
+
+    #[test]
+    fn progressive_passes() {
+        let mut scene = Scene::new();
+        scene.set_solid_background([0.5, 0.25, 1.0]);
+        let mut expected = HdrFilm::new(8, 4);
+        capture(&scene, &mut expected);
+
+        // Each pass doubles the samples taken so far, starting from one
+        let mut renderer = ProgressiveRenderer::new(&scene);
+        let mut film = HdrFilm::new(8, 4);
+        for samples in [1, 2, 4, 8] {
+            renderer.next_pass(&mut film);
+            assert_eq!(renderer.samples(), samples);
+        }
+        assert_eq!(renderer.passes(), 4);
+        for (a, b) in film.data().iter().zip(expected.data().iter()) {
+            assert!(a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-9), "{:?} {:?}", a, b);
+        }
+
+        // A film of the same area but another shape starts over
+        renderer.next_pass(&mut HdrFilm::new(4, 8));
+        assert_eq!((renderer.samples(), renderer.passes()), (1, 1));
+    }
+
+    #[test]
+    fn hunks_cover_film() {
+        let mut scene = Scene::new();
+        scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, MaterialRef::default());
+        scene.add_point_light([0.0, 3.0, 3.0], [0.9, 0.9, 0.9], [1.0, 0.0, 0.0]);
+        scene.set_perspective_camera(60.).look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.]);
+        let mut film = Film::new(40, 20);
+        capture(&scene, &mut film);
+
+        // Hunks along the right and bottom edges are cut short
+        let root = Accel::from(&scene);
+        assert_eq!(hunk_count(40, 20), 6);
+        let mut hunks = Film::new(40, 20);
+        for k in 0..6 { capture_hunk(k, &root, 40, 20).copy_into(&mut hunks) }
+        assert_eq!(capture_hunk(5, &root, 40, 20).bounds, TileBounds { x: 32, y: 16, w: 8, h: 4 });
+        assert_eq!(hunks.buffer(), film.buffer());
+    }
+
+    #[test]
+    fn filters_splat_across_tiles() {
+        let mut scene = Scene::new();
+        scene.set_solid_background([0.5, 0.25, 1.0]);
+        scene.camera.set_supersampling(2);
+        for filter in [Filter::triangle(), Filter::gaussian(), Filter::mitchell(), Filter::Box { radius: 1.5 }] {
+            scene.set_filter(filter);
+            let mut film = HdrFilm::new(70, 40);
+            capture(&scene, &mut film);
+            assert!(film.data().iter().all(|p| p.iter().zip([0.5, 0.25, 1.0]).all(|(a, b)| (a - b).abs() < 1e-6)));
+        }
+
+        // Wider filters blur the edge of a sphere
+        let edge = |filter: Filter| {
+            let mut scene = Scene::new();
+            scene.set_filter(filter);
+            scene.set_solid_background([1.0, 1.0, 1.0]);
+            scene.set_perspective_camera(60.).look_at([0., 0., 0.], [0., 0., -1.], [0., 1., 0.]);
+            let black = scene.add_material(Material::matte([0.0, 0.0, 0.0], 0.0));
+            scene.root.add_sphere([0.0, 0.0, -3.0], 1.0, black);
+            let mut film = HdrFilm::new(40, 40);
+            capture(&scene, &mut film);
+            film.data().iter().filter(|p| p[0] > 0.0 && p[0] < 1.0).count()
+        };
+        assert!(edge(Filter::Box { radius: 2.0 }) > edge(Filter::default()));
+    }
+
+    #[test]
+    fn light_visibility_flags() {
+        // A sphere lit from above through a blocking plane
+        let render = |visibility| {
+            let mut scene = Scene::new();
+            scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, MaterialRef::default());
+            scene.root.add_box([-3.0, 2.0, -3.0], [3.0, 2.2, 3.0], MaterialRef::default());
+            let light = scene.add_point_light([0.0, 4.0, 0.0], [0.9, 0.9, 0.9], [1.0, 0.0, 0.0]);
+            scene.set_light_visibility(light, visibility);
+            scene.set_perspective_camera(60.).look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.]);
+            let mut film = HdrFilm::new(8, 8);
+            capture(&scene, &mut film);
+            film.get(4, 3)
+        };
+
+        assert_eq!(render(LightVisibility::ALL), [0.0, 0.0, 0.0]);
+        assert_eq!(render(LightVisibility::REFLECTIONS), [0.0, 0.0, 0.0]);
+        assert!(render(LightVisibility::CAMERA)[0] > 0.0);
+    }
+
+    #[test]
+    fn shadow_toggles() {
+        // A sphere lit from above through a blocking plane
+        let render = |cast: bool, receive: bool| {
+            let mut scene = Scene::new();
+            let mut sphere = scene::Aggregate::new();
+            sphere.add_sphere([0.0, 0.0, 0.0], 1.0, MaterialRef::default());
+            sphere.set_receive_shadows(receive);
+            scene.root.add_group(sphere);
+            let mut blocker = scene::Aggregate::new();
+            blocker.add_box([-3.0, 2.0, -3.0], [3.0, 2.2, 3.0], MaterialRef::default());
+            blocker.set_cast_shadows(cast);
+            scene.root.add_group(blocker);
+            scene.add_point_light([0.0, 4.0, 0.0], [0.9, 0.9, 0.9], [1.0, 0.0, 0.0]);
+            scene.set_perspective_camera(60.).look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.]);
+            let mut film = HdrFilm::new(8, 8);
+            capture(&scene, &mut film);
+            film.get(4, 3)
+        };
+
+        assert_eq!(render(true, true), [0.0, 0.0, 0.0]);
+        assert!(render(false, true)[0] > 0.0);
+        assert!(render(true, false)[0] > 0.0);
+    }
+
+    #[test]
+    fn backface_options() {
+        // A sphere behind a wall facing away from the camera, with a light on
+        // each side of the wall
+        let render = |two_sided: bool, cull: bool| {
+            let mut scene = Scene::new();
+            scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, MaterialRef::default());
+            let mut wall = scene::Aggregate::new();
+            let material = scene.add_material(Material::default().with_two_sided(two_sided));
+            wall.add_plane([0.0, 0.0, 2.0], [0.0, 0.0, -1.0], [8.0, 8.0], material);
+            wall.set_cull_backface(cull);
+            scene.root.add_group(wall);
+            scene.add_point_light([0.0, 0.0, 1.8], [0.9, 0.9, 0.9], [1.0, 0.0, 0.0]);
+            scene.add_point_light([0.0, 0.0, 4.0], [0.9, 0.9, 0.9], [1.0, 0.0, 0.0]);
+            scene.set_perspective_camera(60.).look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.]);
+            let mut film = HdrFilm::new(8, 8);
+            capture(&scene, &mut film);
+            film.get(4, 4)
+        };
+
+        assert!(render(true, false)[0] > 0.0);
+        assert_eq!(render(false, false), [0.0, 0.0, 0.0]);
+        assert!(render(false, true)[0] > 0.0);
+    }
+
+    #[test]
+    fn named_nodes() {
+        let mut scene = Scene::new();
+        let mut props = scene::Aggregate::new();
+        props.add_named("ball", scene::SceneNode::Geometry(scene::Shape::Sphere([0.0, 0.0, 0.0], 1.0), MaterialRef::default()));
+        scene.root.add_named("props", scene::SceneNode::Group(props));
+        scene.set_perspective_camera(60.).look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.]);
+        let render = |scene: &Scene| {
+            let mut film = HdrFilm::new(8, 8);
+            capture(scene, &mut film);
+            film.get(4, 4)
+        };
+
+        assert!(scene.find_node("props/missing").is_none());
+        assert!(scene.find_node("props/ball/inside").is_none());
+        let ball = scene.find_node("/props/ball").unwrap();
+        assert_eq!(render(&scene), [0.0, 0.0, 0.0]);
+        let light = scene.add_material(Material::emissive([1.0, 1.0, 1.0]));
+        scene.set_node_material(&ball, light);
+        assert!(render(&scene)[0] > 0.0);
+        scene.node_group(&ball).unwrap().translate([10.0, 0.0, 0.0]);
+        assert_eq!(render(&scene), [0.0, 0.0, 0.0]);
+        assert_eq!(scene.find_node("props/ball"), Some(ball));
+    }
+
+    #[test]
+    fn matrix_transforms() {
+        let mut steps = scene::Aggregate::new();
+        steps.translate([1.0, 2.0, 3.0]).scale(2.0, 2.0, 2.0);
+        let mut matrices = scene::Aggregate::new();
+        matrices.rotate_y(30.0);
+        matrices.set_transform(&[[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [1.0, 2.0, 3.0, 1.0]]);
+        matrices.apply_matrix(&[[2.0, 0.0, 0.0, 0.0], [0.0, 2.0, 0.0, 0.0], [0.0, 0.0, 2.0, 0.0], [0.0, 0.0, 0.0, 1.0]]);
+        assert_eq!(matrices.transform.matrix(), steps.transform.matrix());
+    }
+
+    #[test]
+    fn look_at_and_orbit() {
+        let mut group = scene::Aggregate::new();
+        group.look_at([1.0, 2.0, 3.0], [1.0, 2.0, -5.0], [0.0, 1.0, 0.0]);
+        let p = group.transform.transform_point(Point::new(0.0, 1.0, 2.0));
+        assert!((p - Point::new(1.0, 3.0, 1.0)).magnitude() < 1e-12);
+
+        let mut group = scene::Aggregate::new();
+        group.orbit([1.0, 0.0, 0.0], [0.0, 1.0, 0.0], 90.0);
+        let p = group.transform.transform_point(Point::new(2.0, 0.0, 0.0));
+        assert!((p - Point::new(1.0, 0.0, -1.0)).magnitude() < 1e-12);
+    }
+
+    #[test]
+    fn edits_registered_material() {
+        let mut scene = Scene::new();
+        let paint = scene.add_material(Material::matte([0.0, 0.0, 0.0], 0.0));
+        let blend = scene.add_material(Material::mix(paint, paint, [0.0, 0.0, 0.0]));
+        scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, paint);
+        scene.root.add_sphere([0.0, -2.5, 0.0], 1.0, blend);
+        scene.add_point_light([0.0, 0.0, 5.0], [0.9, 0.9, 0.9], [1.0, 0.0, 0.0]);
+        scene.set_perspective_camera(60.).look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.]);
+        let render = |scene: &Scene| {
+            let mut film = HdrFilm::new(8, 8);
+            capture(scene, &mut film);
+            [film.get(4, 4), film.get(4, 7)]
+        };
+
+        assert_eq!(render(&scene), [[0.0; 3]; 2]);
+        scene.set_material(paint, Material::matte([0.8, 0.8, 0.8], 0.0));
+        let [shape, blend] = render(&scene);
+        assert!(shape[0] > 0.0 && blend[0] > 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "blends a blend")]
+    fn rejects_blend_of_blends() {
+        let mut scene = Scene::new();
+        let paint = scene.add_material(Material::default());
+        let blend = scene.add_material(Material::mix(paint, paint, [0.5, 0.5, 0.5]));
+        scene.add_material(Material::mix(paint, blend, [0.5, 0.5, 0.5]));
+    }
+
+    #[test]
+    fn layered_material() {
+        // A sphere lit from the camera, with the highlight in the middle
+        let render = |material: Material| {
+            let mut scene = Scene::new();
+            let material = scene.add_material(material);
+            scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, material);
+            scene.add_point_light([0.0, 0.0, 5.0], [0.9, 0.9, 0.9], [1.0, 0.0, 0.0]);
+            scene.set_perspective_camera(60.).look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.]);
+            let mut film = HdrFilm::new(8, 8);
+            capture(&scene, &mut film);
+            film.get(4, 4)
+        };
+
+        let base = Material::matte([0.5, 0.5, 0.5], 0.0);
+        let clear = Material::glass([1.0, 1.0, 1.0], [1.0, 1.0, 1.0], 1.5);
+        let amber = Material::glass([1.0, 1.0, 1.0], [1.0, 0.5, 0.1], 1.5);
+        let gloss = Material::plastic([0.0, 0.0, 0.0], [1.0, 1.0, 1.0], 0.3);
+
+        let bare = render(base);
+        let thin = render(Material::layered(base, clear, 0.0));
+        let tinted = render(Material::layered(base, amber, 1.0));
+        let glossy = render(Material::layered(base, gloss, 0.0));
+
+        // Light reflecting off the coat doesn't reach the base, and only glossy
+        // coats show the highlight
+        assert!(thin[0] < bare[0]);
+        assert!(tinted[2] < tinted[0]);
+        assert!(glossy[0] > thin[0]);
+
+        // Materials that can't coat leave the base as is
+        assert_eq!(render(Material::layered(base, Material::default(), 1.0)), bare);
+    }
+
+    #[test]
+    fn mix_material() {
+        let render = |factor: [f64; 3]| {
+            let mut scene = Scene::new();
+            let red = scene.add_material(Material::matte([0.8, 0.0, 0.0], 0.0));
+            let blue = scene.add_material(Material::matte([0.0, 0.0, 0.8], 0.0));
+            let blend = scene.add_material(Material::mix(red, blue, factor));
+            scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, blend);
+            scene.add_point_light([0.0, 0.0, 5.0], [0.9, 0.9, 0.9], [1.0, 0.0, 0.0]);
+            scene.set_perspective_camera(60.).look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.]);
+            let mut film = HdrFilm::new(8, 8);
+            capture(&scene, &mut film);
+            film.get(4, 4)
+        };
+
+        let (red, blue, half) = (render([0.0; 3]), render([1.0; 3]), render([0.5; 3]));
+        assert!(red[0] > 0.0 && red[2] == 0.0);
+        assert!(blue[2] > 0.0 && blue[0] == 0.0);
+        assert!((half[0] - red[0] / 2.0).abs() < 1e-4);
+        assert!((half[2] - blue[2] / 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn material_recursion_depth() {
+        // A mirror reflecting a white background, seen by the camera
+        let render = |integrator: Integrator, mirror: Material| {
+            let mut scene = Scene::new();
+            scene.set_integrator(integrator);
+            scene.set_max_recursion_depth(0);
+            scene.set_solid_background([1.0, 1.0, 1.0]);
+            let mirror = scene.add_material(mirror);
+            scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, mirror);
+            scene.set_perspective_camera(30.).look_at([0., 0., 5.], [0., 0., 0.], [0., 1., 0.]);
+            let mut film = HdrFilm::new(8, 8);
+            capture(&scene, &mut film);
+            film.get(4, 4)
+        };
+
+        let mirror = Material::mirror([0.9, 0.9, 0.9]);
+        for integrator in [Integrator::Whitted, Integrator::Path, Integrator::Irradiance] {
+            assert_eq!(render(integrator, mirror), [0.0, 0.0, 0.0]);
+            assert!(render(integrator, mirror.with_max_depth(1))[0] > 0.8);
+        }
+    }
 }
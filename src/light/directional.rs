@@ -0,0 +1,105 @@
+use std::f64;
+use crate::{
+    space::*,
+    sampler::Sampler,
+    Accel
+};
+
+use super::{Light, LightSampleIterator, PointLight};
+
+/// Number of shadow rays traced toward lights with a non-zero angular radius.
+/// More samples give smoother penumbras.
+const SOFT_SHADOW_SAMPLES: usize = 16;
+
+/// Light arriving from infinitely far away in parallel rays, such as sunlight.
+/// An angular radius greater than zero spreads the light over a cone of
+/// directions, like the disc of the sun, which softens shadow edges.
+#[derive(Debug, Copy, Clone)]
+pub struct DirectionalLight {
+    /// Normalized direction in which the light travels
+    pub direction: Vector,
+    pub intensity: Color,
+
+    /// Cosine of the angular radius of the cone of incoming directions
+    cos_max: f64
+}
+
+impl DirectionalLight {
+    /// Create a light that shines in the given direction. The angular radius is
+    /// given in degrees; zero gives hard shadows.
+    pub fn new(direction: [f64; 3], intensity: [f64; 3], angular_radius: f64) -> DirectionalLight {
+        debug_assert!((0. ..90.).contains(&angular_radius));
+        DirectionalLight {
+            direction: Vector::from(direction).normalize(),
+            intensity: intensity.into(),
+            cos_max: (angular_radius * f64::consts::PI / 180.).cos()
+        }
+    }
+
+    #[inline]
+    fn num_samples(&self) -> usize {
+        if self.cos_max < 1. { SOFT_SHADOW_SAMPLES } else { 1 }
+    }
+
+    /// Direction toward the light for the given sample point, uniformly
+    /// distributed over the cone subtended by the light
+    fn sample_wi(&self, u: &Point2f) -> Vector {
+        let wi = -self.direction;
+        if self.cos_max >= 1. { return wi }
+
+        let cos_theta = 1. - u.x * (1. - self.cos_max);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * f64::consts::PI * u.y;
+
+        // Orthonormal basis around the central direction
+        let a = if wi.x.abs() > 0.9 { Vector::unit_y() } else { Vector::unit_x() };
+        let s = wi.cross(a).normalize();
+        let t = wi.cross(s);
+        (s * (sin_theta * phi.cos()) + t * (sin_theta * phi.sin()) + wi * cos_theta).normalize()
+    }
+}
+
+impl Light for DirectionalLight {
+
+    /// Returns a point light one unit away from p in the direction of the
+    /// light, if unobstructed. Soft lights split their intensity evenly
+    /// between samples.
+    fn sample(&self, root: &Accel, p: &Point, time: f64, u: &Point2f, shadows: bool) -> Option<PointLight> {
+        let wi = self.sample_wi(u);
+        let ray = Ray::new(*p, wi).at_time(time);
+        if shadows && root.occluded(&ray) {
+            return None
+        }
+
+        let tr = root.scene.transmittance(&ray);
+        Some(PointLight {
+            position: p + wi,
+            intensity: self.intensity.mul_element_wise(tr) / self.num_samples() as f64,
+            falloff: [1.0, 0.0, 0.0]
+        })
+    }
+
+    fn iter_samples<'l, 's, 'r>(&'l self, root: &'s Accel<'s>, p: Point, time: f64, sampler: &'r mut Sampler)
+    -> LightSampleIterator<'l, 's, 'r> {
+        LightSampleIterator::new(self, root, p, time, sampler, self.num_samples())
+    }
+
+    fn set_intensity(&mut self, intensity: Color) {
+        self.intensity = intensity
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn samples_within_cone() {
+        let light = DirectionalLight::new([0., -1., 0.], [1., 1., 1.], 5.);
+        let cos_max = (5f64).to_radians().cos();
+        for &(x, y) in [(0., 0.), (0.5, 0.25), (0.999, 0.75)].iter() {
+            let wi = light.sample_wi(&Point2f::new(x, y));
+            assert!(wi.dot(Vector::unit_y()) >= cos_max - 1e-9);
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use std::{f64, sync::Arc};
+use crate::{
+    space::*,
+    sampler::Sampler,
+    shape::plane::tangent_basis,
+    Accel
+};
+
+use super::{Light, LightSampleIterator, PointLight};
+
+/// Radiance arriving from every direction at an infinite distance, stored as an
+/// equirectangular (latitude-longitude) image. The top row of the image is
+/// straight up (+y) and the centre of the image is straight ahead (-z).
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+
+    /// Radiance texels in row-major order, with the first row at the top
+    texels: Vec<Color>,
+
+    /// Distribution over the image proportional to the power each texel
+    /// contributes, for importance sampling
+    distribution: Distribution2D
+}
+
+impl EnvironmentMap {
+    /// Create an environment map from linear RGB radiance values arranged in
+    /// row-major order. Must contain exactly width * height texels.
+    pub fn new(width: usize, height: usize, texels: Vec<Color>) -> EnvironmentMap {
+        debug_assert!(width > 0 && height > 0);
+        debug_assert!(texels.len() == width * height);
+
+        // Weigh each texel by the solid angle it covers, which shrinks toward
+        // the poles
+        let weights: Vec<f64> = texels.iter().enumerate().map(|(i, texel)| {
+            let theta = ((i / width) as f64 + 0.5) / height as f64 * f64::consts::PI;
+            luminance(texel) * theta.sin()
+        }).collect();
+        let distribution = Distribution2D::new(&weights, width, height);
+
+        EnvironmentMap { width, height, texels, distribution }
+    }
+
+    /// Create an environment map from linear radiance values in row-major
+    /// order with the given number of channels per texel, such as RGB or RGBA
+    /// floats decoded from an HDR image. Channels past the third are ignored.
+    pub fn from_rgb(width: usize, height: usize, channels: usize, values: &[f32]) -> EnvironmentMap {
+        debug_assert!(channels >= 3);
+        let texels = values.chunks_exact(channels)
+            .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect();
+        EnvironmentMap::new(width, height, texels)
+    }
+
+    /// Create an environment map from 8-bit RGBA pixel data in row-major
+    /// order, mapped to radiance in [0, 1] the same way as
+    /// `ImageTexture::from_rgba`
+    pub fn from_rgba(width: usize, height: usize, rgba: &[u8]) -> EnvironmentMap {
+        let texels = rgba.as_chunks::<4>().0.iter()
+            .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64) / 255.0)
+            .collect();
+        EnvironmentMap::new(width, height, texels)
+    }
+
+    /// Load an OpenEXR image from the file system
+    #[cfg(feature = "bin")]
+    pub fn open(path: &std::path::Path) -> Result<EnvironmentMap, exr::error::Error> {
+        use exr::prelude::*;
+        let image = read_first_rgba_layer_from_file(
+            path,
+            |resolution, _| (resolution.width(), vec![Color::zero(); resolution.area()]),
+            |(width, texels), position, (r, g, b, _a): (f32, f32, f32, f32)| {
+                texels[position.y() * *width + position.x()] = Color::new(r as f64, g as f64, b as f64)
+            }
+        )?;
+        let height = image.layer_data.size.height();
+        let (width, texels) = image.layer_data.channel_data.pixels;
+        Ok(EnvironmentMap::new(width, height, texels))
+    }
+
+    /// Radiance arriving from the given direction
+    pub fn radiance(&self, d: &Vector) -> Color {
+        let uv = direction_to_uv(&d.normalize());
+        let x = ((uv.x * self.width as f64) as usize).min(self.width - 1);
+        let y = ((uv.y * self.height as f64) as usize).min(self.height - 1);
+        self.texels[y * self.width + x]
+    }
+
+    /// Choose an incident direction with probability roughly proportional to
+    /// the radiance arriving from it. Returns the direction with its radiance
+    /// and probability density with respect to solid angle.
+    pub fn sample(&self, u: &Point2f) -> (Vector, Color, f64) {
+        let (uv, pdf) = self.distribution.sample(u);
+        let theta = uv.y * f64::consts::PI;
+        let sin_theta = theta.sin();
+        if pdf == 0.0 || sin_theta == 0.0 {
+            return (Vector::unit_y(), Color::zero(), 0.0)
+        }
+
+        // Convert the density over the image to a density over the sphere
+        let pdf = pdf / (2.0 * f64::consts::PI * f64::consts::PI * sin_theta);
+        let wi = uv_to_direction(&uv);
+        (wi, self.radiance(&wi), pdf)
+    }
+}
+
+/// Light cast onto the scene by an environment map. Each shading point takes
+/// a number of samples of directions toward bright parts of the map.
+pub struct EnvironmentLight {
+    map: Arc<EnvironmentMap>,
+    samples: usize
+}
+
+impl EnvironmentLight {
+    pub fn new(map: Arc<EnvironmentMap>, samples: usize) -> EnvironmentLight {
+        debug_assert!(samples > 0);
+        EnvironmentLight { map, samples }
+    }
+}
+
+impl Light for EnvironmentLight {
+
+    /// Returns a point light one unit away from p in a sampled direction, with
+    /// intensity scaled such that the integrator's point-light shading computes
+    /// the Monte-Carlo estimate L / pdf of the light arriving at p.
+    ///
+    /// Points inside of any of the scene's light portals sample directions
+    /// through those portals instead of toward bright parts of the map.
+    fn sample(&self, root: &Accel, p: &Point, time: f64, u: &Point2f, shadows: bool) -> Option<PointLight> {
+        let (wi, radiance, pdf) = match sample_portals(root.scene.portals(), p, u) {
+            Some((wi, pdf)) => (wi, self.map.radiance(&wi), pdf),
+            None => self.map.sample(u)
+        };
+        if pdf == 0.0 || radiance == Color::zero() { return None }
+
+        // Visible only if nothing is in the way
+        let ray = Ray::new(*p, wi).at_time(time);
+        if shadows && root.occluded(&ray) {
+            return None
+        }
+
+        // Each sample contributes an equal share of the estimate, dimmed by
+        // any medium along the way
+        let radiance = radiance.mul_element_wise(root.scene.transmittance(&ray));
+        let intensity = radiance / (pdf * self.samples as f64 * f64::consts::PI);
+        Some(PointLight {
+            position: p + wi,
+            intensity,
+            falloff: [1.0, 0.0, 0.0]
+        })
+    }
+
+    fn iter_samples<'l, 's, 'r>(&'l self, root: &'s Accel<'s>, p: Point, time: f64, sampler: &'r mut Sampler)
+    -> LightSampleIterator<'l, 's, 'r> {
+        LightSampleIterator::new(self, root, p, time, sampler, self.samples)
+    }
+}
+
+/// Rectangular opening through which an environment map lights an interior,
+/// such as a window or a doorway. See `Scene::add_light_portal`.
+#[derive(Debug, Copy, Clone)]
+pub struct Portal {
+    center: Point,
+
+    /// Unit normal facing the interior
+    normal: Vector,
+
+    /// Offsets from the centre to the middle of two adjacent edges
+    du: Vector,
+    dv: Vector
+}
+
+impl Portal {
+    /// Portal over the same rectangle as a plane with the given centre,
+    /// normal and size, facing the interior
+    pub fn new(center: [f64; 3], normal: [f64; 3], size: [f64; 2]) -> Portal {
+        let normal = Vector::from(normal).normalize();
+        let (s, t) = tangent_basis(&normal);
+        Portal { center: Point::from(center), normal, du: s * (0.5 * size[0]), dv: t * (0.5 * size[1]) }
+    }
+
+    #[inline]
+    fn area(&self) -> f64 {
+        4.0 * self.du.magnitude() * self.dv.magnitude()
+    }
+
+    /// Whether the given point is on the interior side of the portal
+    #[inline]
+    fn faces(&self, p: &Point) -> bool {
+        (p - self.center).dot(self.normal) > 0.0
+    }
+
+    /// Direction from p toward the point on the portal at the given sample
+    /// point, with the solid angle it covers for each unit area of portal
+    fn sample(&self, p: &Point, u: &Point2f) -> (Vector, f64) {
+        let q = self.center + self.du * (2.0 * u.x - 1.0) + self.dv * (2.0 * u.y - 1.0);
+        let d = q - p;
+        let dist2 = d.magnitude2();
+        let wi = d / dist2.sqrt();
+        (wi, wi.dot(self.normal).abs() / dist2)
+    }
+}
+
+/// Sample a direction from p through one of the given portals that it's
+/// inside of, chosen in proportion to their areas. Returns the direction
+/// with its probability density with respect to solid angle, which assumes
+/// that the portals don't overlap as seen from p. None if p is inside none
+/// of the portals.
+fn sample_portals(portals: &[Portal], p: &Point, u: &Point2f) -> Option<(Vector, f64)> {
+    let total: f64 = portals.iter().filter(|portal| portal.faces(p)).map(Portal::area).sum();
+    if total == 0.0 { return None }
+
+    // Pick a portal with the first dimension and reuse what's left of it
+    let mut x = u.x * total;
+    let mut chosen = None;
+    for portal in portals.iter().filter(|portal| portal.faces(p)) {
+        let area = portal.area();
+        chosen = Some((portal, (x / area).min(ONE_MINUS_EPSILON)));
+        if x < area { break }
+        x -= area;
+    }
+
+    let (portal, ux) = chosen?;
+    let (wi, solid_angle) = portal.sample(p, &Point2f::new(ux, u.y));
+    let pdf = if solid_angle > 0.0 { 1.0 / (solid_angle * total) } else { 0.0 };
+    Some((wi, pdf))
+}
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - <f64>::EPSILON;
+
+/// Spherical coordinates of the given unit direction, mapped to the image
+/// ranges u = phi / 2π and v = theta / π
+#[inline]
+fn direction_to_uv(d: &Vector) -> Point2f {
+    let theta = d.y.clamp(-1.0, 1.0).acos();
+    let phi = d.x.atan2(-d.z);
+    Point2f::new(0.5 + phi / (2.0 * f64::consts::PI), theta / f64::consts::PI)
+}
+
+/// Inverse of `direction_to_uv`
+#[inline]
+fn uv_to_direction(uv: &Point2f) -> Vector {
+    let theta = uv.y * f64::consts::PI;
+    let phi = (uv.x - 0.5) * 2.0 * f64::consts::PI;
+    Vector::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+}
+
+/// Piecewise-constant probability distribution over [0, 1), built from the
+/// given function values. See PBRT v3 section 13.3.1
+struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64
+}
+
+impl Distribution1D {
+    fn new(func: &[f64]) -> Distribution1D {
+        let n = func.len();
+        let func: Vec<f64> = func.iter().map(|f| f.max(0.0)).collect();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f64;
+        }
+
+        // Fall back to a uniform distribution if the function is zero
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral == 0.0 { i as f64 / n as f64 } else { *c / integral }
+        }
+
+        Distribution1D { func, cdf, integral }
+    }
+
+    /// Sample a value in [0, 1). Returns the value, its probability density
+    /// and the index of the segment it falls in.
+    fn sample(&self, u: f64) -> (f64, f64, usize) {
+        // Last segment whose cdf value is at most u
+        let offset = self.cdf.partition_point(|c| *c <= u)
+            .max(1)
+            .min(self.func.len()) - 1;
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 { (u - self.cdf[offset]) / width } else { 0.0 };
+        let pdf = if self.integral > 0.0 { self.func[offset] / self.integral } else { 1.0 };
+        ((offset as f64 + du) / self.func.len() as f64, pdf, offset)
+    }
+}
+
+/// Piecewise-constant distribution over [0, 1)^2, made up of a marginal
+/// distribution over rows and a conditional distribution within each row
+struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D
+}
+
+impl Distribution2D {
+    fn new(func: &[f64], width: usize, height: usize) -> Distribution2D {
+        let conditional: Vec<Distribution1D> = func.chunks(width)
+            .map(Distribution1D::new)
+            .collect();
+        let marginal: Vec<f64> = conditional.iter().map(|row| row.integral).collect();
+        debug_assert!(conditional.len() == height);
+        Distribution2D { conditional, marginal: Distribution1D::new(&marginal) }
+    }
+
+    /// Sample a point in [0, 1)^2 and return it with its probability density
+    fn sample(&self, u: &Point2f) -> (Point2f, f64) {
+        let (v, pdf_v, row) = self.marginal.sample(u.y);
+        let (u, pdf_u, _) = self.conditional[row].sample(u.x);
+        (Point2f::new(u, v), pdf_u * pdf_v)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn uv_direction_round_trip() {
+        let uv = Point2f::new(0.3, 0.7);
+        let back = direction_to_uv(&uv_to_direction(&uv));
+        assert!((back.x - uv.x).abs() < 1e-9 && (back.y - uv.y).abs() < 1e-9);
+    }
+
+    #[test]
+    fn samples_bright_texels() {
+        // Dark map with a single bright texel
+        let mut texels = vec![Color::new(0.01, 0.01, 0.01); 8 * 4];
+        texels[8 + 5] = Color::new(100.0, 100.0, 100.0);
+        let map = EnvironmentMap::new(8, 4, texels);
+
+        let (wi, radiance, pdf) = map.sample(&Point2f::new(0.5, 0.5));
+        assert_eq!(radiance, Color::new(100.0, 100.0, 100.0));
+        assert_eq!(map.radiance(&wi), radiance);
+        assert!(pdf > 0.0);
+    }
+
+    #[test]
+    fn replaces_earlier_maps() {
+        let mut scene = crate::Scene::new();
+        scene.set_environment_map(EnvironmentMap::new(1, 1, vec![Color::new(1.0, 0.0, 0.0)]), 1);
+        scene.set_environment_map(EnvironmentMap::new(1, 1, vec![Color::new(0.0, 0.0, 1.0)]), 1);
+        assert_eq!(scene.lights().len(), 1);
+
+        let root = crate::Accel::from(&scene);
+        let light = scene.lights()[0].sample(&root, &Point::origin(), 0.0, &Point2f::new(0.5, 0.5), false).unwrap();
+        assert_eq!(light.intensity.x, 0.0);
+        assert!(light.intensity.z > 0.0);
+    }
+
+    #[test]
+    fn from_rgb_skips_extra_channels() {
+        let map = EnvironmentMap::from_rgb(2, 1, 4, &[1.0, 2.0, 3.0, 0.0, 4.0, 5.0, 6.0, 0.0]);
+        assert_eq!(map.texels, vec![Color::new(1.0, 2.0, 3.0), Color::new(4.0, 5.0, 6.0)]);
+    }
+
+    #[test]
+    fn samples_through_portals() {
+        // Two windows in a wall facing +z, seen from in front of it
+        let portals = [
+            Portal::new([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 1.0]),
+            Portal::new([2.0, 0.0, 0.0], [0.0, 0.0, 1.0], [2.0, 1.0])
+        ];
+        let p = Point::new(0.0, 0.0, 2.0);
+        for i in 0..16 {
+            let u = Point2f::new((i as f64 + 0.5) / 16.0, 0.3);
+            let (wi, pdf) = sample_portals(&portals, &p, &u).unwrap();
+
+            // Each direction passes through one of the portals
+            let t = -p.z / wi.z;
+            let hit = p + wi * t;
+            let inside = |c: f64, w: f64| (hit.x - c).abs() <= w / 2.0 + 1e-9 && hit.y.abs() <= 0.5 + 1e-9;
+            assert!(inside(-1.0, 1.0) || inside(2.0, 2.0), "{:?}", hit);
+            assert!(pdf > 0.0);
+        }
+
+        // Points outside fall back to sampling the map
+        assert!(sample_portals(&portals, &Point::new(0.0, 0.0, -1.0), &Point2f::new(0.5, 0.5)).is_none());
+    }
+}
@@ -1,51 +1,108 @@
-use super::space::Point;
-use crate::Accel;
+use super::space::{Point, Point2f, Color};
+use crate::{Accel, sampler::Sampler};
 
 pub mod point;
-pub use self::point::PointLight;
+pub mod directional;
+pub mod environment;
+pub mod shape;
+pub(crate) mod tree;
+pub use self::point::{PointLight, LightPower};
+pub use self::directional::DirectionalLight;
+pub use self::environment::{EnvironmentMap, EnvironmentLight, Portal};
+pub use self::shape::ShapeLight;
 
-pub trait Light {
+/// Fraction of the distance to a sampled point on a light beyond which
+/// intersections do not block it. Keeps the surface of an area light from
+/// shadowing its own samples.
+pub(crate) const SHADOW_EPSILON: f64 = 1e-4;
+
+bitflags! {
+    /// Which parts of a render a light takes part in. Lights take part in all
+    /// of them by default; leaving some out helps art-direct a render, e.g.,
+    /// to add a highlight to a product without brightening its reflections.
+    pub struct LightVisibility: u8 {
+        /// Lights the surfaces seen directly by the camera
+        const CAMERA = 1 << 0;
+
+        /// Lights the surfaces seen in reflections and refractions, and those
+        /// reached by indirect bounces of light
+        const REFLECTIONS = 1 << 1;
+
+        /// Is blocked by objects in the scene, which cast shadows. Lights
+        /// without it shine through everything
+        const SHADOWS = 1 << 2;
+
+        const ALL = Self::CAMERA.bits | Self::REFLECTIONS.bits | Self::SHADOWS.bits;
+    }
+}
+
+pub trait Light: Send + Sync {
 
     /// Sample the light received by the given point in the scene. The returned
     /// point light is to be used in shading calculations. A None is returned if
     /// an internally-calculated PointLight sample is not visible from the given
-    /// point. Depending on the Light implementation
-    fn sample(&self, root: &Accel, p: &Point) -> Option<PointLight>;
+    /// point. Depending on the Light implementation, the sample point u in
+    /// [0, 1)^2 chooses where on the light to sample. Visibility is tested at
+    /// the given time, which matters for scenes with moving objects, unless
+    /// shadows is false.
+    fn sample(&self, root: &Accel, p: &Point, time: f64, u: &Point2f, shadows: bool) -> Option<PointLight>;
 
     /// Create an iterator that yields point lights that are visible from the
     /// given point in the given scene. Most implementations return
     /// LightSampleIterator instances initialized as are required given the
-    /// scene parameters for a nice rendering
-    fn iter_samples<'l, 's>(&'l self, root: &'s Accel<'s>, p: Point) -> LightSampleIterator<'l, 's>;
+    /// scene parameters for a nice rendering. The sampler provides sample
+    /// points.
+    fn iter_samples<'l, 's, 'r>(&'l self, root: &'s Accel<'s>, p: Point, time: f64, sampler: &'r mut Sampler)
+    -> LightSampleIterator<'l, 's, 'r>;
+
+    /// Change the intensity of the light, e.g., between frames of an
+    /// animation. Lights whose emission comes from elsewhere, such as an
+    /// environment map, ignore it.
+    fn set_intensity(&mut self, _intensity: Color) {}
+
+    /// The point light this is, if it is one. Scenes that sample a few of
+    /// their point lights at each hit pick among those that are, see
+    /// `Scene::set_light_samples`.
+    fn point(&self) -> Option<&PointLight> { None }
 }
 
 /// An iteratator for conveniently looping through samples taken from a given
 /// light that are visible from the given point. The number of iterations
 /// depends on the type of light and the sampling settings on the scene
-pub struct LightSampleIterator<'l, 's> {
+pub struct LightSampleIterator<'l, 's, 'r> {
     light: &'l dyn Light,
     root: &'s Accel<'s>,
     point: Point,
+    time: f64,
+    sampler: &'r mut Sampler,
     /// Number of samples remaning
     remaining: usize,
+    /// Whether samples blocked by objects in the scene are skipped
+    shadows: bool
 }
 
-impl<'l, 's> LightSampleIterator<'l, 's> {
-    pub fn new(light: &'l dyn Light, root: &'s Accel, point: Point, samples: usize)
-    -> LightSampleIterator<'l, 's> {
+impl<'l, 's, 'r> LightSampleIterator<'l, 's, 'r> {
+    pub fn new(light: &'l dyn Light, root: &'s Accel, point: Point, time: f64, sampler: &'r mut Sampler, samples: usize)
+    -> LightSampleIterator<'l, 's, 'r> {
         LightSampleIterator {
-            light, root, point, remaining: samples
+            light, root, point, time, sampler, remaining: samples, shadows: true
         }
     }
+
+    /// Whether objects in the scene block the light. Defaults to true
+    pub fn casting_shadows(self, shadows: bool) -> Self {
+        LightSampleIterator { shadows, ..self }
+    }
 }
 
-impl<'l, 's> Iterator for LightSampleIterator<'l, 's> {
+impl<'l, 's, 'r> Iterator for LightSampleIterator<'l, 's, 'r> {
     type Item = PointLight;
 
     fn next(&mut self) -> Option<PointLight> {
         while self.remaining > 0 {
             self.remaining -= 1;
-            if let Some(light) = self.light.sample(self.root, &self.point) {
+            let u = self.sampler.get_2d();
+            if let Some(light) = self.light.sample(self.root, &self.point, self.time, &u, self.shadows) {
                 return Some(light)
             }
         }
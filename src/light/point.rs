@@ -1,12 +1,12 @@
 use std::f64;
+use serde::{Serialize, Deserialize};
 use crate::{
     space::*,
-    primitive::Primitive,
-    interaction::RayIntersection,
+    sampler::Sampler,
     Accel
 };
 
-use super::{Light, LightSampleIterator};
+use super::{Light, LightSampleIterator, SHADOW_EPSILON};
 
 /// A Point Light has no surface area an emits in all directions
 /// These don't exist in real life but are a good approximation
@@ -17,7 +17,36 @@ pub struct PointLight {
     pub falloff: [f64; 3]
 }
 
+/// Luminous efficacy of light at the peak of human sensitivity (555nm), in
+/// lumens per watt
+const LUMENS_PER_WATT: f64 = 683.;
+
+/// Total power given off by a light, in physical units
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightPower {
+    /// Radiant flux, in watts
+    Watts(f64),
+
+    /// Luminous flux, in lumens, as printed on light bulb packaging.
+    /// Converted to watts at 683 lumens per watt.
+    Lumens(f64)
+}
+
+impl LightPower {
+    /// The power in watts
+    pub fn watts(&self) -> f64 {
+        match self {
+            LightPower::Watts(watts) => *watts,
+            LightPower::Lumens(lumens) => *lumens / LUMENS_PER_WATT
+        }
+    }
+}
+
 impl PointLight {
+    /// Point light with the given intensity, dimmed over distance d by
+    /// `falloff[0] + falloff[1]*d + falloff[2]*d*d`. Kept for compatibility
+    /// with older scenes; `with_power` gives physically-based lights.
     pub fn new(position: [f64; 3], intensity: [f64; 3], falloff: [f64; 3]) -> PointLight {
         PointLight {
             position: position.into(),
@@ -25,6 +54,17 @@ impl PointLight {
             falloff
         }
     }
+
+    /// Point light that gives off the given power in the given colour, spread
+    /// evenly in every direction and dimming with the square of the distance
+    /// as real lights do. Scene units are taken as metres.
+    pub fn with_power(position: [f64; 3], color: [f64; 3], power: LightPower) -> PointLight {
+        // Radiant intensity is Φ/4π, and shading multiplies the intensity of
+        // point lights by π, as the PBRT and glTF importers also account for
+        let pi = f64::consts::PI;
+        let intensity = Color::from(color) * (power.watts() / (4. * pi * pi));
+        PointLight { position: position.into(), intensity, falloff: [0., 0., 1.] }
+    }
 }
 
 impl Light for PointLight {
@@ -39,23 +79,31 @@ impl Light for PointLight {
     ///     let f_att = falloff[0] + falloff[1]*d + falloff[2]*d*d;
     ///     println!("{}", f_att);
     ///
-    fn sample(&self, root: &Accel, p: &Point) -> Option<PointLight> {
+    fn sample(&self, root: &Accel, p: &Point, time: f64, _u: &Point2f, shadows: bool) -> Option<PointLight> {
         let d = self.position - p; // direction from p to light
-        let ray = Ray::new(*p, d);
-
-        // See if there's anything that intersects
-        let mut isect = RayIntersection::default();
-        root.intersect(&ray, &mut isect);
-        if isect.t < 1.0 {
-            None
-        } else {
-            Some(*self)
+        let ray = Ray::new(*p, d).at_time(time).within(0.0, 1.0 - SHADOW_EPSILON);
+
+        // See if there's anything in between
+        if shadows && root.occluded(&ray) {
+            return None
         }
+
+        // Dimmed by any medium along the way
+        let intensity = self.intensity.mul_element_wise(root.scene.transmittance(&ray));
+        Some(PointLight { intensity, ..*self })
     }
 
-    fn iter_samples<'l, 's>(&'l self, root: &'s Accel<'s>, p: Point)
-    -> LightSampleIterator<'l, 's> {
+    fn iter_samples<'l, 's, 'r>(&'l self, root: &'s Accel<'s>, p: Point, time: f64, sampler: &'r mut Sampler)
+    -> LightSampleIterator<'l, 's, 'r> {
         // Point lights only require one sample
-        LightSampleIterator::new(self, root, p, 1)
+        LightSampleIterator::new(self, root, p, time, sampler, 1)
+    }
+
+    fn set_intensity(&mut self, intensity: Color) {
+        self.intensity = intensity
+    }
+
+    fn point(&self) -> Option<&PointLight> {
+        Some(self)
     }
 }
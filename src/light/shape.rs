@@ -0,0 +1,189 @@
+use std::f64;
+use crate::{
+    space::{*, normal::Normal3},
+    primitive::Primitive,
+    accelerators::bvh::{PrimBox, geometry},
+    shape::TriangleIterator,
+    scene::{Scene, node::{Aggregate, SceneNode}},
+    Material,
+    sampler::Sampler,
+    Accel
+};
+
+use super::{Light, LightSampleIterator, PointLight, SHADOW_EPSILON};
+
+/// Number of shadow rays traced toward each area light from each shading
+/// point. More samples give smoother penumbras.
+const AREA_LIGHT_SAMPLES: usize = 16;
+
+/// Adapts primitives made of an emissive material, such as a sphere or the
+/// triangles of a mesh, into an area light. Points on the surface are sampled
+/// uniformly by area in object space and moved into world space with the
+/// transformation of the scene node.
+pub struct ShapeLight<'s> {
+    /// Emitting primitives in object space
+    shapes: Vec<PrimBox<'s>>,
+
+    /// Radiance emitted by each primitive
+    radiance: Vec<Color>,
+
+    /// Whether each primitive emits from its back as well as its front
+    two_sided: Vec<bool>,
+
+    /// Running total of object-space area up to and including each primitive,
+    /// for choosing primitives in proportion to their area
+    cdf: Vec<f64>,
+
+    /// Object-to-world transformation
+    transform: Transformation
+}
+
+impl<'s> ShapeLight<'s> {
+    /// Create a light from the given emitting primitives and the emissive
+    /// material of each. Returns None if there's nothing to sample.
+    pub fn new(shapes: Vec<PrimBox<'s>>, materials: Vec<Material>, transform: Transformation) -> Option<ShapeLight<'s>> {
+        debug_assert!(shapes.len() == materials.len());
+        let mut total = 0.0;
+        let cdf: Vec<f64> = shapes.iter().map(|shape| { total += shape.area(); total }).collect();
+        if total == 0.0 { return None }
+        let radiance = materials.iter().map(|material| material.emission()).collect();
+        let two_sided = materials.iter().map(|material| material.is_two_sided()).collect();
+        Some(ShapeLight { shapes, radiance, two_sided, cdf, transform })
+    }
+
+    /// Find all primitives with emissive materials in the given scene. Returns
+    /// one light for each geometry node and one for all the emitting triangles
+    /// of each mesh node.
+    pub fn collect(scene: &'s Scene) -> Vec<ShapeLight<'s>> {
+        let mut lights = vec![];
+        collect(scene, &scene.root, &Transformation::identity(), &mut lights);
+        lights
+    }
+
+    /// Total object-space area of the emitting primitives
+    #[inline]
+    fn area(&self) -> f64 {
+        self.cdf[self.cdf.len() - 1]
+    }
+}
+
+fn collect<'s>(scene: &'s Scene, aggregate: &'s Aggregate, parent: &Transformation, lights: &mut Vec<ShapeLight<'s>>) {
+    // Moving groups emit from where they are at time 0
+    let transform = aggregate.start.as_ref().unwrap_or(&aggregate.transform).concat(parent);
+    for node in aggregate.contents.iter() {
+        let light = match node {
+            SceneNode::Geometry(shape, material) if scene.material(*material).is_emissive() =>
+                ShapeLight::new(vec![geometry(shape, *material)], vec![scene.material(*material)], transform.clone()),
+            SceneNode::Geometry(..) => None,
+            SceneNode::Mesh(obj, material) => {
+                // Triangles use their group's material from the .mtl library
+                // before falling back to the node's material
+                let (shapes, materials) = TriangleIterator::new(scene.mesh(*obj).unwrap())
+                    .filter_map(|triangle| {
+                        let material = scene.material(triangle.material().or(*material)?);
+                        if !material.is_emissive() { return None }
+                        Some((Box::new(triangle) as PrimBox<'s>, material))
+                    })
+                    .unzip();
+                ShapeLight::new(shapes, materials, transform.clone())
+            },
+            SceneNode::Group(aggregate) => {
+                collect(scene, aggregate, &transform, lights);
+                None
+            }
+        };
+        if let Some(light) = light { lights.push(light) }
+    }
+}
+
+impl<'s> Light for ShapeLight<'s> {
+
+    /// Returns a point light at a sampled point on the surface of the light,
+    /// with intensity scaled such that the integrator's point-light shading
+    /// computes the Monte-Carlo estimate of the light arriving at p.
+    fn sample(&self, root: &Accel, p: &Point, time: f64, u: &Point2f, shadows: bool) -> Option<PointLight> {
+        // Choose a primitive in proportion to its area and stretch the part of
+        // u.x that fell within it back to [0, 1)
+        let x = u.x * self.area();
+        let i = self.cdf.partition_point(|c| *c <= x).min(self.cdf.len() - 1);
+        let start = if i == 0 { 0.0 } else { self.cdf[i - 1] };
+        let u = Point2f::new(((x - start) / (self.cdf[i] - start)).min(1.0), u.y);
+        let (q, n) = self.shapes[i].sample_area(&u)?;
+
+        // Density of the sample with respect to world-space area. The
+        // transformation stretches the surface around q by the area of the
+        // transformed tangent plane.
+        let (s, t) = coordinate_system(&n);
+        let stretch = self.transform.transform_vector(s)
+            .cross(self.transform.transform_vector(t))
+            .magnitude();
+        let pdf = 1.0 / (self.area() * stretch);
+
+        let q = self.transform.transform_point(q);
+        let n = self.transform.transform_normal(Normal3(n)).0.normalize();
+        let wi = q - p;
+        let d = wi.magnitude();
+        if d == 0.0 { return None }
+        let cos_light = n.dot(wi).abs() / d;
+        if cos_light == 0.0 { return None }
+
+        // One-sided lights only shine toward the side the normal points to
+        if !self.two_sided[i] && n.dot(wi) > 0.0 { return None }
+
+        // See if anything is in the way
+        let ray = Ray::new(*p, wi).at_time(time).within(0.0, 1.0 - SHADOW_EPSILON);
+        if shadows && root.occluded(&ray) { return None }
+
+        // Each sample contributes an equal share of the estimate. The point
+        // light falls off with the square of the distance and is dimmed by any
+        // medium along the way.
+        let radiance = self.radiance[i].mul_element_wise(root.scene.transmittance(&ray)) * cos_light;
+        Some(PointLight {
+            position: q,
+            intensity: radiance / (pdf * AREA_LIGHT_SAMPLES as f64 * f64::consts::PI),
+            falloff: [0.0, 0.0, 1.0]
+        })
+    }
+
+    fn iter_samples<'l, 'a, 'r>(&'l self, root: &'a Accel<'a>, p: Point, time: f64, sampler: &'r mut Sampler)
+    -> LightSampleIterator<'l, 'a, 'r> {
+        LightSampleIterator::new(self, root, p, time, sampler, AREA_LIGHT_SAMPLES)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Material;
+
+    #[test]
+    fn irradiance_from_scaled_disk() {
+        // Disk of radius 1 in world space, 2 units above the origin
+        let mut scene = Scene::new();
+        let light = scene.add_material(Material::emissive([1.0, 1.0, 1.0]));
+        let mut group = Aggregate::new();
+        group.add_disk([0.0, 0.0, 0.0], [0.0, -1.0, 0.0], 0.5, light);
+        group.translate([0.0, 2.0, 0.0]).scale(2.0, 1.0, 2.0);
+        scene.root.add_group(group);
+
+        let root = Accel::from(&scene);
+        let lights = ShapeLight::collect(&scene);
+        assert_eq!(lights.len(), 1);
+
+        // Average the estimates over a grid of sample points
+        let n = 64;
+        let p = Point::new(0.0, 0.0, 0.0);
+        let mut irradiance = 0.0;
+        for i in 0..(n * n) {
+            let u = Point2f::new(((i % n) as f64 + 0.5) / n as f64, ((i / n) as f64 + 0.5) / n as f64);
+            let light = lights[0].sample(&root, &p, 0.0, &u, true).unwrap();
+            let wi = light.position - p;
+            let cos = wi.y / wi.magnitude();
+            irradiance += f64::consts::PI * light.intensity.x * cos / wi.magnitude2();
+        }
+        irradiance *= AREA_LIGHT_SAMPLES as f64 / (n * n) as f64;
+
+        let expected = f64::consts::PI / 5.0;
+        assert!((irradiance - expected).abs() < 0.01 * expected, "{} != {}", irradiance, expected);
+    }
+}
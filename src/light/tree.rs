@@ -0,0 +1,207 @@
+use crate::space::*;
+use super::{Light, PointLight};
+
+/// Hierarchy over the point lights of a scene that picks one of them for a
+/// point being lit, in proportion to an estimate of how much light each part
+/// of the hierarchy gives off toward it. Sampling a few lights this way and
+/// dividing their contributions by the probability of picking them lights
+/// scenes of thousands of point lights at the cost of a few.
+///
+/// Lights are compared by their power dimmed by their falloff at the distance
+/// to the bounds of their node, so nearby and bright lights are picked most
+/// often. Every light that gives off any light may be picked from anywhere.
+pub(crate) struct LightTree {
+    /// Tree nodes arranged in linear memory, starting with the root. The
+    /// first child of an interior node immediately follows it.
+    nodes: Vec<LightNode>,
+
+    /// Whether each light of the scene, in the order of `Accel::lights`, is
+    /// in the tree
+    contains: Vec<bool>
+}
+
+struct LightNode {
+    bounds: Bounds,
+
+    /// Sum of the power of the lights within, averaged over the channels
+    power: f64,
+
+    /// Least falloff coefficients of the lights within, which dim them least
+    falloff: [f64; 3],
+
+    kind: LightNodeKind
+}
+
+enum LightNodeKind {
+    /// Index of the light in the order of `Accel::lights`
+    Leaf(usize),
+
+    /// Offset of the second child
+    Interior(usize)
+}
+
+/// Light added to the tree, while it's built
+struct LightInfo {
+    index: usize,
+    light: PointLight
+}
+
+impl LightTree {
+    /// Build the tree over those of the given lights for which take is true
+    /// and that are point lights. Returns None if there are none.
+    pub(crate) fn new<'l>(lights: impl Iterator<Item = (&'l dyn Light, bool)>) -> Option<LightTree> {
+        let mut contains = vec![];
+        let mut info = vec![];
+        for (index, (light, take)) in lights.enumerate() {
+            let point = if take { light.point() } else { None };
+            contains.push(point.is_some());
+            if let Some(light) = point { info.push(LightInfo { index, light: *light }) }
+        }
+        if info.is_empty() { return None }
+
+        let mut tree = LightTree { nodes: Vec::with_capacity(2 * info.len() - 1), contains };
+        tree.build(&mut info);
+        Some(tree)
+    }
+
+    /// Number of lights in the tree
+    pub(crate) fn len(&self) -> usize {
+        self.nodes.len().div_ceil(2)
+    }
+
+    /// Whether the light at the given index of `Accel::lights` is in the
+    /// tree, in which case it's only lit by sampling the tree
+    #[inline]
+    pub(crate) fn contains(&self, index: usize) -> bool {
+        self.contains.get(index).copied().unwrap_or(false)
+    }
+
+    /// Pick a light for the given point with u in [0, 1). Returns its index
+    /// in the order of `Accel::lights` and the probability of picking it, or
+    /// None if no light in the tree gives off any light.
+    pub(crate) fn sample(&self, p: &Point, u: f64) -> Option<(usize, f64)> {
+        let mut u = u;
+        let mut pdf = 1.0;
+        let mut offset = 0;
+        loop {
+            match self.nodes[offset].kind {
+                LightNodeKind::Leaf(index) => return if pdf > 0.0 { Some((index, pdf)) } else { None },
+                LightNodeKind::Interior(second) => {
+                    let near = self.nodes[offset + 1].importance(p);
+                    let far = self.nodes[second].importance(p);
+                    if near + far <= 0.0 { return None }
+
+                    // Choose a child and stretch u back over [0, 1)
+                    let p_near = near / (near + far);
+                    if u < p_near {
+                        u = (u / p_near).min(1.0 - f64::EPSILON);
+                        pdf *= p_near;
+                        offset += 1
+                    } else {
+                        u = ((u - p_near) / (1.0 - p_near)).min(1.0 - f64::EPSILON);
+                        pdf *= 1.0 - p_near;
+                        offset = second
+                    }
+                }
+            }
+        }
+    }
+
+    /// Add the nodes over the given lights, splitting them at the middle of
+    /// the longest axis of their positions. Returns the offset of the node.
+    fn build(&mut self, info: &mut [LightInfo]) -> usize {
+        let offset = self.nodes.len();
+        if let [info] = info {
+            let light = &info.light;
+            self.nodes.push(LightNode {
+                bounds: Bounds::new(light.position, light.position),
+                power: (light.intensity.x + light.intensity.y + light.intensity.z).max(0.0) / 3.0,
+                falloff: light.falloff,
+                kind: LightNodeKind::Leaf(info.index)
+            });
+            return offset
+        }
+
+        let centroids = info.iter().fold(Bounds::none(), |bounds, info| bounds.point_union(&info.light.position));
+        let axis = centroids.maximum_extent();
+        let mid = info.len() / 2;
+        info.select_nth_unstable_by(mid, |a, b| a.light.position[axis].total_cmp(&b.light.position[axis]));
+
+        // Fill in the node once its children are built
+        self.nodes.push(LightNode { bounds: centroids, power: 0.0, falloff: [0.0; 3], kind: LightNodeKind::Interior(0) });
+        let (near, far) = info.split_at_mut(mid);
+        let first = self.build(near);
+        let second = self.build(far);
+        let (a, b) = (&self.nodes[first], &self.nodes[second]);
+        let node = LightNode {
+            bounds: a.bounds.union(&b.bounds),
+            power: a.power + b.power,
+            falloff: [0, 1, 2].map(|i| a.falloff[i].min(b.falloff[i])),
+            kind: LightNodeKind::Interior(second)
+        };
+        self.nodes[offset] = node;
+        offset
+    }
+}
+
+impl LightNode {
+    /// Estimate of the light the lights within give off toward the given
+    /// point. Lights are taken to be no nearer than half the size of the
+    /// bounds, so that nodes around the point aren't always picked.
+    fn importance(&self, p: &Point) -> f64 {
+        let center = self.bounds.min + self.bounds.diagonal() / 2.0;
+        let d = (p - center).magnitude().max(self.bounds.diagonal().magnitude() / 2.0);
+        let f_att = self.falloff[0] + self.falloff[1]*d + self.falloff[2]*d*d;
+        self.power / f_att.max(f64::EPSILON)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    use crate::{space::*, light::{Light, PointLight}};
+    use super::LightTree;
+
+    fn lights() -> Vec<PointLight> {
+        let mut rng = StdRng::seed_from_u64(7);
+        (0..200).map(|_| PointLight::new(
+            [rng.gen_range(-10.0, 10.0), rng.gen_range(-10.0, 10.0), rng.gen_range(-10.0, 10.0)],
+            [rng.gen(), rng.gen(), rng.gen()],
+            [1.0, 0.0, 1.0]
+        )).collect()
+    }
+
+    #[test]
+    fn picks_every_light_with_its_pdf() {
+        let lights = lights();
+        let tree = LightTree::new(lights.iter().map(|light| (light as &dyn Light, true))).unwrap();
+        let p = Point::new(1.0, 2.0, -3.0);
+
+        // The pdf of each light is the measure of the values of u that pick it
+        let n = 200_000;
+        let mut picked = vec![0; lights.len()];
+        let mut pdfs = vec![0.0; lights.len()];
+        for i in 0..n {
+            let (index, pdf) = tree.sample(&p, (i as f64 + 0.5) / n as f64).unwrap();
+            picked[index] += 1;
+            pdfs[index] = pdf;
+        }
+        assert!(picked.iter().all(|&count| count > 0));
+        for (count, pdf) in picked.iter().zip(pdfs.iter()) {
+            assert!((*count as f64 / n as f64 - pdf).abs() < 2.0 / n as f64);
+        }
+        assert!((pdfs.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn skips_lights_left_out() {
+        let lights = lights();
+        let tree = LightTree::new(lights.iter().enumerate().map(|(i, light)| (light as &dyn Light, i % 2 == 0))).unwrap();
+        assert!(tree.contains(0) && !tree.contains(1) && !tree.contains(lights.len()));
+        for i in 0..100 {
+            let (index, _) = tree.sample(&Point::new(0.0, 0.0, 0.0), i as f64 / 100.0).unwrap();
+            assert_eq!(index % 2, 0)
+        }
+        assert!(LightTree::new(lights.iter().map(|light| (light as &dyn Light, false))).is_none())
+    }
+}
@@ -1,11 +1,16 @@
-use std::f64;
+use std::{f64, sync::Arc};
 use crate::space::*;
+use crate::light::EnvironmentMap;
 
-#[derive(Debug, Copy, Clone)]
-pub struct Background {
-    pub inner: Color,
-    pub outer: Color,
-    scale: f64
+#[derive(Clone)]
+pub enum Background {
+    /// Gradient from the inner colour straight ahead and behind to the outer
+    /// colour around the sides
+    Radial { inner: Color, outer: Color, scale: f64 },
+
+    /// Equirectangular environment map. Set with
+    /// `Scene::set_environment_map`, which also adds the map as a light source.
+    Environment(Arc<EnvironmentMap>)
 }
 
 impl Background {
@@ -13,23 +18,38 @@ impl Background {
     /// ranges from 0 to 1. It is used to determine the extent of the gradient
     /// projected onto the "front and back" of the world sphere.
     pub fn radial(inner: Color, outer: Color, scale: f64) -> Background {
-        Background { inner, outer, scale }
+        Background::Radial { inner, outer, scale }
     }
 
     pub fn solid(color: Color) -> Background {
         Background::radial(color, color, 1.0)
     }
 
+    /// Whether the background is sampled directly as a light source, such that
+    /// integrators need not account for it when rays escape the scene after
+    /// diffuse or glossy scattering
+    pub fn is_light(&self) -> bool {
+        match self {
+            Background::Radial { .. } => false,
+            Background::Environment(_) => true
+        }
+    }
+
     /// Compute the background colour based on the direction vector
     /// Assume d is normalized
     pub fn bg(&self, d: &Vector) -> Color {
-        // Even gradient based on the equation of a unit circle y = sqrt(1 - x^2)
-        // Modified by scale [0, 1].
-        let t = ((1. - Vector::unit_z().dot(*d).abs().powf(2.)).sqrt() / self.scale).min(1.);
-        Color {
-            x: lerp(t, self.inner.x, self.outer.x),
-            y: lerp(t, self.inner.y, self.outer.y),
-            z: lerp(t, self.inner.z, self.outer.z)
+        match self {
+            Background::Radial { inner, outer, scale } => {
+                // Even gradient based on the equation of a unit circle y = sqrt(1 - x^2)
+                // Modified by scale [0, 1].
+                let t = ((1. - Vector::unit_z().dot(*d).abs().powf(2.)).sqrt() / scale).min(1.);
+                Color {
+                    x: lerp(t, inner.x, outer.x),
+                    y: lerp(t, inner.y, outer.y),
+                    z: lerp(t, inner.z, outer.z)
+                }
+            },
+            Background::Environment(map) => map.radiance(d)
         }
     }
 }
@@ -0,0 +1,104 @@
+use crate::space::*;
+use crate::{interaction::SurfaceInteraction, space::normal::Normal3, texture::TextureRef, scene::Scene};
+
+/// Offset in UV space between height samples used to estimate the slope of the
+/// height map
+const BUMP_DELTA: f64 = 0.0005;
+
+/// Grayscale height map that perturbs shading normals to give the appearance
+/// of surface detail without adding geometry. See PBRT v3 section 9.3
+#[derive(Debug, Copy, Clone)]
+pub struct Bump {
+    /// Texture whose average channel value gives the height at each point.
+    /// Always looked up by surface UVs, since slopes are taken along them;
+    /// any projection on the reference is ignored.
+    height: TextureRef,
+
+    /// Multiplier taking height values to the distance the surface appears
+    /// displaced, in world units. Larger values give deeper bumps
+    scale: f64
+}
+
+impl Bump {
+    pub fn new(height: TextureRef, scale: f64) -> Bump {
+        Bump { height, scale }
+    }
+
+    /// Tilt the shading normal and surface shading differentials of the
+    /// interaction toward the downward slope of the height map
+    pub fn apply(&self, interaction: &mut SurfaceInteraction, scene: &Scene) {
+        let texture = scene.texture(self.height);
+        let mut shifted = *interaction;
+        let mut height = |uv: Point2f| {
+            shifted.uv = uv;
+            let c = texture.evaluate(&shifted);
+            self.scale * (c.x + c.y + c.z) / 3.0
+        };
+
+        // Forward differences of the height along u and v
+        let uv = interaction.uv;
+        let h = height(uv);
+        let dhdu = (height(uv + Vector2f::new(BUMP_DELTA, 0.0)) - h) / BUMP_DELTA;
+        let dhdv = (height(uv + Vector2f::new(0.0, BUMP_DELTA)) - h) / BUMP_DELTA;
+
+        // Displace the surface along the shading normal and take the normal of
+        // the displaced surface, keeping it on the same side as before. The
+        // differentials are scaled back to their lengths on the surface, so
+        // that bumps are as deep whatever the size of the object or the
+        // density of its UVs.
+        let ns = interaction.ns();
+        let dpdu = interaction.surface.dpdu * interaction.uv_scale.x + ns * dhdu;
+        let dpdv = interaction.surface.dpdv * interaction.uv_scale.y + ns * dhdv;
+        let n = dpdu.cross(dpdv);
+        if n.magnitude2() == 0.0 { return }
+
+        interaction.ns = Normal3(n.normalize()).face_forward(ns);
+        interaction.uv_scale = Vector2f::new(dpdu.magnitude(), dpdv.magnitude());
+        interaction.surface.dpdu = dpdu.normalize();
+        interaction.surface.dpdv = dpdv.normalize();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{interaction::RayIntersection, texture::Texture};
+
+    /// Height increases linearly along u
+    struct Ramp;
+    impl Texture for Ramp {
+        fn evaluate(&self, interaction: &SurfaceInteraction) -> Color {
+            Color::from_value(interaction.uv.x)
+        }
+    }
+
+    #[test]
+    fn tilts_away_from_slope() {
+        let mut scene = Scene::new();
+        let ramp = scene.add_texture(Box::new(Ramp));
+
+        let ray = Ray::new(Point::new(0.5, 0.5, 1.0), -Vector::unit_z());
+        let isect = RayIntersection::new(1.0, Point::new(0.5, 0.5, 0.0), Point2f::new(0.5, 0.5), Vector::unit_x(), Vector::unit_y());
+        let mut interaction = SurfaceInteraction::from(&ray, &isect);
+
+        Bump::new(ramp, 0.5).apply(&mut interaction, &scene);
+        let expected = Vector::new(-0.5, 0.0, 1.0).normalize();
+        assert!((interaction.ns() - expected).magnitude() < 1e-6);
+        assert!(interaction.ns().dot(interaction.surface.dpdu).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tilts_by_slope_in_world_units() {
+        let mut scene = Scene::new();
+        let ramp = scene.add_texture(Box::new(Ramp));
+
+        // UVs stretched ten times as far, so height rises ten times slower
+        let ray = Ray::new(Point::new(5.0, 5.0, 1.0), -Vector::unit_z());
+        let isect = RayIntersection::new(1.0, Point::new(5.0, 5.0, 0.0), Point2f::new(0.5, 0.5), Vector::unit_x() * 10.0, Vector::unit_y() * 10.0);
+        let mut interaction = SurfaceInteraction::from(&ray, &isect);
+
+        Bump::new(ramp, 0.5).apply(&mut interaction, &scene);
+        let expected = Vector::new(-0.05, 0.0, 1.0).normalize();
+        assert!((interaction.ns() - expected).magnitude() < 1e-6);
+    }
+}
@@ -0,0 +1,40 @@
+use crate::space::*;
+use crate::{core::bxdf::*, interaction::BSDF};
+
+/// Dielectric layer over another material, such as the clearcoat on car paint
+/// or the lacquer on wood. Light reflects off the top of the coat; what passes
+/// through is tinted on its way to and from the material underneath.
+#[derive(Debug, Copy, Clone)]
+pub struct Coat {
+    /// Reflection coefficient of the top of the coat
+    kr: Color,
+
+    /// Colour of light after passing straight through a coat of thickness one
+    tint: Color,
+
+    /// Refractive index of the coat
+    eta: f64,
+
+    /// Optional microfacet distribution for glossy coats; smooth coats
+    /// reflect specularly
+    distribution: Option<MicrofacetDistribution>,
+
+    thickness: f64
+}
+
+impl Coat {
+    pub fn new(kr: Color, tint: Color, eta: f64, distribution: Option<MicrofacetDistribution>, thickness: f64) -> Coat {
+        Coat { kr, tint, eta, distribution, thickness: thickness.max(0.0) }
+    }
+
+    /// Lay the coat over the scattering functions of the material underneath
+    pub fn apply(&self, bsdf: &mut BSDF) {
+        let substance = Substance::Dielectric(1.0, self.eta);
+        let bxdf = if let Some(distribution) = self.distribution {
+            BxDF::microfacet_reflection(self.kr, substance, distribution)
+        } else {
+            BxDF::specular_reflection(self.kr, substance)
+        };
+        bsdf.add_coat(bxdf, self.eta, self.tint, self.thickness)
+    }
+}
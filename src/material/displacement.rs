@@ -0,0 +1,32 @@
+use crate::space::*;
+use crate::{interaction::{RayIntersection, SurfaceInteraction}, texture::TextureRef, scene::Scene};
+
+/// Grayscale height map that moves the surface along its normals, so that
+/// the detail shows in silhouettes and shadows, unlike bump maps. Only the
+/// vertices of triangle meshes move, once `Scene::displace_meshes` has split
+/// their triangles finely enough for the detail to show. Other shapes ignore
+/// displacement.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Displacement {
+    /// Texture whose average channel value gives the height at each point
+    height: TextureRef,
+
+    /// Distance in object space that surfaces move at a height of one
+    scale: f64
+}
+
+impl Displacement {
+    pub fn new(height: TextureRef, scale: f64) -> Displacement {
+        Displacement { height, scale }
+    }
+
+    /// Distance to move the point p with the given unit normal and UV
+    /// coordinates along the normal
+    pub fn offset(&self, p: Point, n: Vector, uv: Point2f, scene: &Scene) -> f64 {
+        let (dpdu, dpdv) = coordinate_system(&n);
+        let isect = RayIntersection::new(1.0, p, uv, dpdu, dpdv);
+        let interaction = SurfaceInteraction::from(&Ray::new(p + n, -n), &isect);
+        let c = self.height.evaluate(&interaction, scene);
+        self.scale * (c.x + c.y + c.z) / 3.0
+    }
+}
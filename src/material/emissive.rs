@@ -0,0 +1,27 @@
+use crate::space::*;
+use crate::{interaction::{SurfaceInteraction, BSDF}};
+use super::SurfaceOptions;
+
+/// A surface that gives off light, such as a lamp or a glowing panel. Light is
+/// emitted from both sides of the surface unless it is one-sided; none is
+/// reflected.
+#[derive(Debug, Copy, Clone)]
+pub struct Emissive {
+    /// Radiance emitted in every direction from each point on the surface
+    radiance: Color,
+
+    pub(super) options: SurfaceOptions
+}
+
+impl Emissive {
+    pub fn new(radiance: Color) -> Emissive {
+        Emissive { radiance, options: SurfaceOptions::default() }
+    }
+
+    #[inline]
+    pub fn radiance(&self) -> Color { self.radiance }
+
+    pub fn scattering(&self, interaction: &SurfaceInteraction) -> BSDF {
+        BSDF::empty(interaction)
+    }
+}
@@ -1,5 +1,12 @@
 use crate::space::*;
 use crate::{core::bxdf::*, interaction::{SurfaceInteraction, BSDF}};
+use super::{SurfaceOptions, coat::Coat};
+
+/// Wavelengths in nanometres of the Fraunhofer d, F and C lines, at which the
+/// refractive index and Abbe number of glasses are given
+const LAMBDA_D: f64 = 587.56;
+const LAMBDA_F: f64 = 486.13;
+const LAMBDA_C: f64 = 656.27;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Glass {
@@ -12,9 +19,17 @@ pub struct Glass {
     /// Refractive index. Typical for glass is 1.5
     eta: f64,
 
+    /// Abbe number of dispersive glass, lower for more dispersion
+    pub(super) abbe: Option<f64>,
+
+    /// Which of overlapping volumes fills the overlap, the highest first
+    pub(super) priority: u32,
+
     /// Optional microfacet distribution depending on given roughness parameters
     /// TODO: This isn't working
-    distribution: Option<MicrofacetDistribution>
+    distribution: Option<MicrofacetDistribution>,
+
+    pub(super) options: SurfaceOptions
 }
 
 impl Glass {
@@ -27,14 +42,45 @@ impl Glass {
             Some(MicrofacetDistribution::new(alphax, alphay))
         };
 
-        Glass { kr, kt, eta, distribution }
+        Glass { kr, kt, eta, distribution, abbe: None, priority: 0, options: SurfaceOptions::default() }
+    }
+
+    #[inline]
+    pub fn eta(&self) -> f64 { self.eta }
+
+    /// Refractive index for light of the given wavelength in nanometres, from
+    /// Cauchy's equation fit to the refractive index and Abbe number. Zero
+    /// wavelengths, as carried by RGB renders, and glass without dispersion
+    /// give the refractive index at the d line.
+    pub fn eta_at(&self, wavelength: f64) -> f64 {
+        match self.abbe {
+            Some(abbe) if wavelength > 0.0 && abbe > 0.0 => {
+                let b = (self.eta - 1.0) / (abbe * (LAMBDA_F.powi(-2) - LAMBDA_C.powi(-2)));
+                let a = self.eta - b / (LAMBDA_D * LAMBDA_D);
+                a + b / (wavelength * wavelength)
+            },
+            _ => self.eta
+        }
     }
 
-    pub fn scattering(&self, interaction: &SurfaceInteraction) -> BSDF {
+    /// Coat of this glass with the given thickness, over another material
+    pub(super) fn coat(&self, thickness: f64) -> Coat {
+        Coat::new(self.kr, self.kt, self.eta, self.distribution, thickness)
+    }
+
+    /// Overall colour of light passing through or reflecting off the glass.
+    /// Clear glass is white.
+    #[inline]
+    pub fn albedo(&self) -> Color { self.kt }
+
+    /// Scattering at the boundary between the glass and a medium with the
+    /// given refractive index outside of it
+    pub fn scattering(&self, interaction: &SurfaceInteraction, outside: f64) -> BSDF {
         let mut bsdf = BSDF::empty(interaction);
+        let eta = self.eta_at(interaction.wavelength);
 
         if self.kr != Color::zero() {
-            let substance = Substance::Dielectric(1.0, self.eta);
+            let substance = Substance::Dielectric(outside, eta);
             let bxdf = if let Some(distribution) = self.distribution {
                 BxDF::microfacet_reflection(self.kr, substance, distribution)
             } else {
@@ -45,9 +91,9 @@ impl Glass {
 
         if self.kt != Color::zero() {
             let bxdf = if let Some(distribution) = self.distribution {
-                BxDF::microfacet_transmission(self.kt, 1.0, self.eta, TransportMode::Importance, distribution)
+                BxDF::microfacet_transmission(self.kt, outside, eta, TransportMode::Importance, distribution)
             } else {
-                BxDF::specular_transmission(self.kt, 1.0, self.eta)
+                BxDF::specular_transmission(self.kt, outside, eta)
             };
             bsdf.add(bxdf)
         };
@@ -1,26 +1,36 @@
-use crate::space::Color;
-use crate::{core::bxdf::BxDF, interaction::{SurfaceInteraction, BSDF}};
+use crate::space::*;
+use crate::{core::bxdf::BxDF, interaction::{SurfaceInteraction, BSDF}, texture::Param, scene::Scene};
+use super::SurfaceOptions;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Matte {
     /// Surface reflection value
-    kd: Color,
+    kd: Param,
 
     /// Scalar roughness value, for Oren-Nayar model
-    sigma: f64
+    sigma: f64,
+
+    pub(super) options: SurfaceOptions
 }
 
 impl Matte {
-    pub fn new(kd: Color, sigma: f64) -> Matte {
-        Matte { kd, sigma: sigma.max(0.0).min(90.0) }
+    pub fn new(kd: Param, sigma: f64) -> Matte {
+        Matte { kd, sigma: sigma.clamp(0.0, 90.0), options: SurfaceOptions::default() }
+    }
+
+    /// Overall reflectance at the given point of interaction
+    pub fn albedo(&self, interaction: &SurfaceInteraction, scene: &Scene) -> Color {
+        self.kd.evaluate(interaction, scene).mul_element_wise(interaction.color)
     }
 
-    pub fn scattering(&self, interaction: &SurfaceInteraction) -> BSDF {
+    pub fn scattering(&self, interaction: &SurfaceInteraction, scene: &Scene) -> BSDF {
+        let kd: Color = self.kd.evaluate(interaction, scene)
+            .mul_element_wise(interaction.color);
         BSDF::new(interaction, &[
             if self.sigma == 0.0 {
-                BxDF::quick_diffuse(self.kd)
+                BxDF::quick_diffuse(kd)
             } else {
-                BxDF::diffuse(self.kd, self.sigma)
+                BxDF::diffuse(kd, self.sigma)
             }
         ])
     }
@@ -1,17 +1,29 @@
 use crate::space::*;
 use crate::{core::bxdf::*, interaction::{SurfaceInteraction, BSDF}};
+use super::SurfaceOptions;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Metal {
     eta: Color,
     k: Color,
     u_roughness: f64,
-    v_roughness: f64
+    v_roughness: f64,
+
+    pub(super) options: SurfaceOptions
 }
 
 impl Metal {
     pub fn new(eta: Color, k: Color, u_roughness: f64, v_roughness: f64) -> Metal {
-        Metal { eta, k, u_roughness, v_roughness }
+        Metal { eta, k, u_roughness, v_roughness, options: SurfaceOptions::default() }
+    }
+
+    /// Reflectance at normal incidence
+    pub fn albedo(&self) -> Color {
+        let one = Color::from_value(1.0);
+        let k2 = self.k.mul_element_wise(self.k);
+        let num = (self.eta - one).mul_element_wise(self.eta - one) + k2;
+        let den = (self.eta + one).mul_element_wise(self.eta + one) + k2;
+        num.div_element_wise(den)
     }
 
     pub fn scattering(&self, interaction: &SurfaceInteraction) -> BSDF {
@@ -1,17 +1,24 @@
 use crate::space::*;
 use crate::{core::bxdf::*, interaction::{SurfaceInteraction, BSDF}};
+use super::SurfaceOptions;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Mirror {
     /// Reflection coefficient
-    kr: Color
+    kr: Color,
+
+    pub(super) options: SurfaceOptions
 }
 
 impl Mirror {
     pub fn new(kr: Color) -> Mirror {
-        Mirror { kr }
+        Mirror { kr, options: SurfaceOptions::default() }
     }
 
+    /// Overall reflectance
+    #[inline]
+    pub fn albedo(&self) -> Color { self.kr }
+
     pub fn scattering(&self, interaction: &SurfaceInteraction) -> BSDF {
         BSDF::new(interaction, &[BxDF::specular_reflection(self.kr, Substance::NoOp)])
     }
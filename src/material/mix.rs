@@ -0,0 +1,59 @@
+use crate::space::*;
+use crate::{interaction::{SurfaceInteraction, BSDF}, texture::Param, scene::Scene};
+use super::{MaterialRef, SurfaceOptions};
+
+/// Blend of two materials of a scene, such as patches of rust on metal or the
+/// line where wet sand meets dry. The factor gives how much of the second
+/// material shows at each point; the first fills the rest.
+#[derive(Debug, Copy, Clone)]
+pub struct Mix {
+    a: MaterialRef,
+    b: MaterialRef,
+
+    /// Fraction of light of each colour scattered by the second material
+    factor: Param,
+
+    pub(super) options: SurfaceOptions
+}
+
+impl Mix {
+    pub fn new(a: MaterialRef, b: MaterialRef, factor: Param) -> Mix {
+        Mix { a, b, factor, options: SurfaceOptions::default() }
+    }
+
+    /// The two blended materials
+    pub(crate) fn members(&self) -> [MaterialRef; 2] {
+        [self.a, self.b]
+    }
+
+    /// Fraction of the second material at the given point of interaction
+    fn factor(&self, interaction: &SurfaceInteraction, scene: &Scene) -> Color {
+        self.factor.evaluate(interaction, scene).map(|t| t.clamp(0.0, 1.0))
+    }
+
+    /// Blend of the overall colours of both materials
+    pub fn albedo(&self, interaction: &SurfaceInteraction, scene: &Scene) -> Color {
+        let t = self.factor(interaction, scene);
+        let a = scene.material(self.a).albedo(interaction, scene);
+        let b = scene.material(self.b).albedo(interaction, scene);
+        a.mul_element_wise(Color::from_value(1.0) - t) + b.mul_element_wise(t)
+    }
+
+    /// Blend of the scattering of both materials, each of which perturbs the
+    /// shading normal with its own bump map, if any. The BSDF takes its
+    /// shading frame from the first material unless only the second shows.
+    pub fn scattering(&self, interaction: &SurfaceInteraction, scene: &Scene, outside: f64) -> BSDF {
+        let t = self.factor(interaction, scene);
+        let scattering = |material: MaterialRef| {
+            let mut interaction = *interaction;
+            scene.material(material).scattering_between(&mut interaction, scene, outside)
+        };
+
+        if t == Color::zero() { return scattering(self.a) }
+        if t == Color::from_value(1.0) { return scattering(self.b) }
+
+        let mut bsdf = scattering(self.a);
+        bsdf.mix(&scattering(self.b), t);
+        bsdf
+    }
+}
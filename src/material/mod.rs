@@ -1,4 +1,8 @@
-use crate::{space::*, interaction::{SurfaceInteraction, BSDF}};
+use crate::{space::*, interaction::{SurfaceInteraction, BSDF}, texture::{Param, TextureRef}, scene::Scene};
+use crate::integrate::furnace::{FurnaceIntegrator, EnergyAudit};
+
+/// Alpha mask values below which surfaces are cut out
+const ALPHA_CUTOFF: f64 = 0.5;
 
 #[derive(Debug, Copy, Clone)]
 pub enum Material {
@@ -6,7 +10,48 @@ pub enum Material {
     Plastic(plastic::Plastic),
     Metal(metal::Metal),
     Glass(glass::Glass),
-    Mirror(mirror::Mirror)
+    Mirror(mirror::Mirror),
+    Emissive(emissive::Emissive),
+    Mix(mix::Mix)
+}
+
+/// Opaque reference to a material added to a scene with
+/// `Scene::add_material`. Shapes hold references, so that changing a material
+/// with `Scene::set_material` changes every surface made of it. The default
+/// reference is the grey matte `Material::default()` that every scene starts
+/// with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MaterialRef(pub(crate) usize);
+
+/// Options that apply to every kind of material, set with the `with_`
+/// methods of `Material`
+#[derive(Debug, Copy, Clone)]
+struct SurfaceOptions {
+    /// Optional height map for bump mapping
+    bump: Option<Bump>,
+
+    /// Optional mask that cuts out parts of the surface
+    alpha: Option<TextureRef>,
+
+    /// Optional dielectric layer over the surface
+    coat: Option<Coat>,
+
+    /// Optional height map that moves mesh vertices before rendering
+    displacement: Option<Displacement>,
+
+    /// Whether the back of the surface scatters and emits light like the
+    /// front
+    two_sided: bool,
+
+    /// Number of bounces light may take off the surface, in place of the
+    /// scene's recursion depth
+    max_depth: Option<u32>
+}
+
+impl Default for SurfaceOptions {
+    fn default() -> Self {
+        SurfaceOptions { bump: None, alpha: None, coat: None, displacement: None, two_sided: true, max_depth: None }
+    }
 }
 
 impl Material {
@@ -16,13 +61,15 @@ impl Material {
         Self::matte([0.5, 0.5, 0.5], 0.0)
     }
 
-    pub fn matte(kd: [f64; 3], sigma: f64) -> Material {
-        let kd = Color::new(kd[0], kd[1], kd[2]);
-        Material::Matte(matte::Matte::new(kd, sigma))
+    /// Diffuse material. `kd` is either an RGB colour or a scene texture
+    pub fn matte(kd: impl Into<Param>, sigma: f64) -> Material {
+        Material::Matte(matte::Matte::new(kd.into(), sigma))
     }
 
-    pub fn plastic(kd: [f64; 3], ks: [f64; 3], roughness: f64) -> Material {
-        let kd = Color::new(kd[0], kd[1], kd[2]);
+    /// Diffuse material with a glossy coat. `kd` is either an RGB colour or a
+    /// scene texture
+    pub fn plastic(kd: impl Into<Param>, ks: [f64; 3], roughness: f64) -> Material {
+        let kd = kd.into();
         let ks = Color::new(ks[0], ks[1], ks[2]);
         Material::Plastic(plastic::Plastic::new(kd, ks, roughness))
     }
@@ -45,24 +92,290 @@ impl Material {
         Material::Mirror(mirror::Mirror::new(kr))
     }
 
-    /// Computes the function for how light is handled at the material at the
-    /// given point of interaction.
-    pub fn scattering(&self, interaction: &SurfaceInteraction) -> BSDF {
+    /// Light-emitting material. Primitives made of it light the scene as area
+    /// lights with the given radiance.
+    pub fn emissive(radiance: [f64; 3]) -> Material {
+        Material::Emissive(emissive::Emissive::new(radiance.into()))
+    }
+
+    /// Blend of materials `a` and `b` of a scene, such as patches of rust on
+    /// metal or wet sand meeting dry. `factor` is either an RGB colour or a
+    /// scene texture giving how much of `b` shows at each point, from zero
+    /// for only `a` to one for only `b`. Blends don't give off light, and
+    /// neither `a` nor `b` may be a blend.
+    pub fn mix(a: MaterialRef, b: MaterialRef, factor: impl Into<Param>) -> Material {
+        Material::Mix(mix::Mix::new(a, b, factor.into()))
+    }
+
+    /// Whether surfaces of this material give off light
+    #[inline]
+    pub fn is_emissive(&self) -> bool {
+        matches!(self, Material::Emissive(_))
+    }
+
+    /// Radiance emitted from surfaces of this material. Zero for all but
+    /// emissive materials.
+    #[inline]
+    pub fn emission(&self) -> Color {
+        match self {
+            Material::Emissive(mat) => mat.radiance(),
+            _ => Color::zero()
+        }
+    }
+
+    /// Radiance emitted toward the outgoing direction of the given point of
+    /// interaction. Zero on the back of one-sided materials.
+    #[inline]
+    pub fn emitted(&self, interaction: &SurfaceInteraction) -> Color {
+        if self.is_two_sided() || !interaction.is_backface() {
+            self.emission()
+        } else {
+            Color::zero()
+        }
+    }
+
+    /// Options of this material that apply to every kind of material
+    fn options(&self) -> &SurfaceOptions {
+        match self {
+            Material::Matte(mat) => &mat.options,
+            Material::Plastic(mat) => &mat.options,
+            Material::Metal(mat) => &mat.options,
+            Material::Glass(mat) => &mat.options,
+            Material::Mirror(mat) => &mat.options,
+            Material::Emissive(mat) => &mat.options,
+            Material::Mix(mat) => &mat.options,
+        }
+    }
+
+    fn options_mut(&mut self) -> &mut SurfaceOptions {
+        match self {
+            Material::Matte(mat) => &mut mat.options,
+            Material::Plastic(mat) => &mut mat.options,
+            Material::Metal(mat) => &mut mat.options,
+            Material::Glass(mat) => &mut mat.options,
+            Material::Mirror(mat) => &mut mat.options,
+            Material::Emissive(mat) => &mut mat.options,
+            Material::Mix(mat) => &mut mat.options,
+        }
+    }
+
+    /// Perturb the shading normals of this material with the given grayscale
+    /// height map texture. The scale takes height values to world units, so
+    /// bumps are as deep on large objects as on small ones. Larger scales give
+    /// more pronounced bumps; negative scales invert the height map.
+    pub fn with_bump(self, height: TextureRef, scale: f64) -> Material {
+        let mut material = self;
+        material.options_mut().bump = Some(Bump::new(height, scale));
+        material
+    }
+
+    /// Lay a coat of the given material and thickness over the base material,
+    /// such as clearcoat over car paint or lacquer over wood. Light reflects
+    /// off the top of the coat as it would off the coat material; the rest
+    /// reaches the base through the coat, tinted by the coat's transmission
+    /// colour once for each unit of thickness crossed. Glass gives smooth
+    /// coats and plastic glossy ones from its specular part; other coat
+    /// materials leave the base as is. Replaces any coat the base already
+    /// has.
+    pub fn layered(base: Material, coat: Material, thickness: f64) -> Material {
+        let coat = match coat {
+            Material::Glass(mat) => mat.coat(thickness),
+            Material::Plastic(mat) => mat.coat(thickness),
+            _ => return base
+        };
+        let mut material = base;
+        material.options_mut().coat = Some(coat);
+        material
+    }
+
+    /// Move the surfaces of triangle meshes of this material along their
+    /// normals by the given grayscale height map texture, scaled to object
+    /// space by the given factor. Takes effect once the meshes are split
+    /// finely enough for the image with `Scene::displace_meshes`.
+    pub fn with_displacement(self, height: TextureRef, scale: f64) -> Material {
+        let mut material = self;
+        material.options_mut().displacement = Some(Displacement::new(height, scale));
+        material
+    }
+
+    /// Height map that moves the surfaces of meshes of this material, if any
+    pub(crate) fn displacement(&self) -> Option<Displacement> {
+        self.options().displacement
+    }
+
+    /// Cut out the parts of surfaces of this material where the given mask
+    /// texture averages below one half, such as around the leaves painted on
+    /// a card. Rays, including shadow rays, pass through cut-out parts as if
+    /// the surface wasn't there.
+    pub fn with_alpha(self, mask: TextureRef) -> Material {
+        let mut material = self;
+        material.options_mut().alpha = Some(mask);
+        material
+    }
+
+    /// Set the priority of volumes of this material where they overlap other
+    /// volumes, such as an ice cube poking out of a drink. The volume with the
+    /// highest priority fills the overlap. Only glass uses priorities; other
+    /// materials are returned as is.
+    pub fn with_priority(self, priority: u32) -> Material {
+        match self {
+            Material::Glass(mut mat) => { mat.priority = priority; Material::Glass(mat) },
+            material => material
+        }
+    }
+
+    /// Spread light passing through glass of this material into its colours,
+    /// as in a prism or a diamond, with the given Abbe number. Common glass
+    /// has an Abbe number around 60, dense flint glass around 30 and diamond
+    /// 55; lower numbers disperse more. Only shows in spectral renders. Only
+    /// glass disperses light; other materials are returned as is.
+    pub fn with_dispersion(self, abbe: f64) -> Material {
+        match self {
+            Material::Glass(mut mat) => { mat.abbe = Some(abbe); Material::Glass(mat) },
+            material => material
+        }
+    }
+
+    /// Whether light passing through surfaces of this material bends by
+    /// different amounts depending on its wavelength
+    #[inline]
+    pub fn is_dispersive(&self) -> bool {
+        match self {
+            Material::Glass(mat) => mat.abbe.is_some(),
+            _ => false
+        }
+    }
+
+    /// Priority and refractive index of the volumes enclosed by surfaces of
+    /// this material, for dielectrics that light may pass through
+    pub(crate) fn dielectric(&self) -> Option<(u32, f64)> {
+        match self {
+            Material::Glass(mat) => Some((mat.priority, mat.eta())),
+            _ => None
+        }
+    }
+
+    /// Choose whether the back of surfaces of this material scatters and
+    /// emits light like the front, which is the default. The backs of
+    /// one-sided surfaces are black, such as for a panel light that only
+    /// shines forward. The front is the side the shading normal points to.
+    pub fn with_two_sided(self, two_sided: bool) -> Material {
+        let mut material = self;
+        material.options_mut().two_sided = two_sided;
+        material
+    }
+
+    /// Whether the back of surfaces of this material scatters and emits light
+    pub fn is_two_sided(&self) -> bool {
+        self.options().two_sided
+    }
+
+    /// Let light bounce off surfaces of this material up to the given depth,
+    /// in place of the scene's recursion depth, such as for long chains of
+    /// mirrors or the many surfaces of a glass of water. Depth counts the
+    /// bounces so far, so an override only takes effect on paths that reach
+    /// the surface. Path tracing still cuts long paths short with Russian
+    /// roulette.
+    pub fn with_max_depth(self, max_depth: u32) -> Material {
+        let mut material = self;
+        material.options_mut().max_depth = Some(max_depth);
+        material
+    }
+
+    /// Depth of recursion allowed at surfaces of this material, if it
+    /// overrides the scene's
+    pub fn max_depth(&self) -> Option<u32> {
+        self.options().max_depth
+    }
+
+    /// Mask texture that cuts out parts of surfaces of this material, if any
+    pub fn alpha(&self) -> Option<TextureRef> {
+        self.options().alpha
+    }
+
+    /// Whether the alpha mask of this material, if any, cuts out the surface
+    /// at the given point of interaction
+    pub(crate) fn is_cut_out(&self, interaction: &SurfaceInteraction, scene: &Scene) -> bool {
+        match self.alpha() {
+            Some(mask) => {
+                let c = mask.evaluate(interaction, scene);
+                (c.x + c.y + c.z) / 3.0 < ALPHA_CUTOFF
+            },
+            None => false
+        }
+    }
+
+    /// Overall colour of the surface of this material at the given point of
+    /// interaction, independent of lighting. Emissive materials give the
+    /// colour of their light.
+    pub fn albedo(&self, interaction: &SurfaceInteraction, scene: &Scene) -> Color {
         match self {
-            Material::Matte(mat) => mat.scattering(interaction),
-            Material::Plastic(mat) => mat.scattering(interaction),
+            Material::Matte(mat) => mat.albedo(interaction, scene),
+            Material::Plastic(mat) => mat.albedo(interaction, scene),
+            Material::Metal(mat) => mat.albedo(),
+            Material::Glass(mat) => mat.albedo(),
+            Material::Mirror(mat) => mat.albedo(),
+            Material::Mix(mat) => mat.albedo(interaction, scene),
+            Material::Emissive(mat) => {
+                let radiance = mat.radiance();
+                let max = radiance.x.max(radiance.y).max(radiance.z);
+                if max > 0.0 { radiance / max } else { radiance }
+            }
+        }
+    }
+
+    /// Light scattered by this material when lit evenly by white light from
+    /// every direction, as in a white furnace test, to catch materials that
+    /// create energy or lose more than they should. Textured parameters are
+    /// looked up in the given scene at the centre of the texture. See
+    /// `Integrator::Furnace` to see the same on rendered objects.
+    pub fn audit_energy(&self, scene: &Scene) -> EnergyAudit {
+        FurnaceIntegrator::new().audit(self, scene)
+    }
+
+    /// Computes the function for how light is handled at the material at the
+    /// given point of interaction. Textured parameters are looked up in the
+    /// given scene. Materials with a bump map first perturb the shading
+    /// normal of the interaction. Coats scatter light over the rest. The backs
+    /// of one-sided materials don't scatter any light.
+    pub fn scattering(&self, interaction: &mut SurfaceInteraction, scene: &Scene) -> BSDF {
+        self.scattering_between(interaction, scene, 1.0)
+    }
+
+    /// Scattering as for `scattering`, at the boundary with a medium of the
+    /// given refractive index on the outside of dielectric surfaces
+    pub(crate) fn scattering_between(&self, interaction: &mut SurfaceInteraction, scene: &Scene, outside: f64) -> BSDF {
+        if !self.is_two_sided() && interaction.is_backface() { return BSDF::empty(interaction) }
+        if let Some(bump) = self.options().bump { bump.apply(interaction, scene) }
+        let interaction = &*interaction;
+
+        let mut bsdf = match self {
+            Material::Matte(mat) => mat.scattering(interaction, scene),
+            Material::Plastic(mat) => mat.scattering(interaction, scene),
             Material::Metal(mat) => mat.scattering(interaction),
-            Material::Glass(mat) => mat.scattering(interaction),
+            Material::Glass(mat) => mat.scattering(interaction, outside),
             Material::Mirror(mat) => mat.scattering(interaction),
-        }
+            Material::Emissive(mat) => mat.scattering(interaction),
+            Material::Mix(mat) => mat.scattering(interaction, scene, outside),
+        };
+        if let Some(coat) = self.options().coat { coat.apply(&mut bsdf) }
+        bsdf
     }
 }
 
 pub use background::Background;
+pub(crate) use displacement::Displacement;
+use bump::Bump;
+use coat::Coat;
 
 mod background;
+mod bump;
+mod coat;
+mod displacement;
 mod matte;
 mod plastic;
 mod metal;
 mod glass;
 mod mirror;
+mod emissive;
+mod mix;
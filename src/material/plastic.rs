@@ -1,28 +1,47 @@
 use crate::space::*;
-use crate::{core::bxdf::*, interaction::{SurfaceInteraction, BSDF}};
+use crate::{core::bxdf::*, interaction::{SurfaceInteraction, BSDF}, texture::Param, scene::Scene};
+use super::{SurfaceOptions, coat::Coat};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Plastic {
     /// Diffuse coefficient
-    kd: Color,
+    kd: Param,
 
     /// Specular coefficient
     ks: Color,
 
-    roughness: f64
+    roughness: f64,
+
+    pub(super) options: SurfaceOptions
 }
 
 impl Plastic {
-    pub fn new(kd: Color, ks: Color, roughness: f64) -> Plastic {
-        Plastic { kd, ks, roughness }
+    pub fn new(kd: Param, ks: Color, roughness: f64) -> Plastic {
+        Plastic { kd, ks, roughness, options: SurfaceOptions::default() }
+    }
+
+    /// Overall reflectance at the given point of interaction: the diffuse
+    /// colour plus the glossy coat
+    pub fn albedo(&self, interaction: &SurfaceInteraction, scene: &Scene) -> Color {
+        let kd = self.kd.evaluate(interaction, scene).mul_element_wise(interaction.color);
+        (kd + self.ks).map(|c| c.min(1.0))
+    }
+
+    /// Coat of the glossy part of this plastic with the given thickness, over
+    /// another material
+    pub(super) fn coat(&self, thickness: f64) -> Coat {
+        let distribution = MicrofacetDistribution::new(self.roughness, self.roughness);
+        Coat::new(self.ks, Color::from_value(1.0), 1.5, Some(distribution), thickness)
     }
 
-    pub fn scattering(&self, interaction: &SurfaceInteraction) -> BSDF {
+    pub fn scattering(&self, interaction: &SurfaceInteraction, scene: &Scene) -> BSDF {
         let mut bsdf = BSDF::empty(interaction);
 
         // Diffuse component
-        if self.kd != Color::zero() {
-            bsdf.add(BxDF::quick_diffuse(self.kd))
+        let kd = self.kd.evaluate(interaction, scene)
+            .mul_element_wise(interaction.color);
+        if kd != Color::zero() {
+            bsdf.add(BxDF::quick_diffuse(kd))
         };
 
         // Don't add ks if it doesn't contrinbute
@@ -0,0 +1,613 @@
+use std::f64;
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+use crate::{
+    space::*,
+    interaction::MediumInteraction,
+    sampler::Sampler
+};
+
+/// Edge length in voxels of the cubic bricks that density grids are stored in
+const BRICK_SIZE: i32 = 8;
+const BRICK_VOXELS: usize = 512;
+
+/// Tracking steps after which a ray through a grid medium gives up, for rays
+/// that would otherwise take very many steps through a dense grid
+const MAX_TRACKING_STEPS: usize = 1 << 16;
+
+/// Participating medium that fills the scene: either fog of constant density,
+/// or a volume of density that varies over a grid, such as smoke
+#[derive(Debug, Clone)]
+pub enum Medium {
+    Homogeneous(HomogeneousMedium),
+    Grid(GridMedium)
+}
+
+impl Medium {
+    /// Fraction of light that makes it through the medium along the given ray,
+    /// between its t_min and t_max
+    pub fn tr(&self, ray: &Ray) -> Color {
+        match self {
+            Medium::Homogeneous(medium) => medium.tr(ray),
+            Medium::Grid(medium) => medium.tr(ray)
+        }
+    }
+
+    /// Sample a point along the given ray at which light scatters. See
+    /// `HomogeneousMedium::sample`
+    pub fn sample(&self, ray: &Ray, sampler: &mut Sampler) -> (Color, Option<MediumInteraction>) {
+        match self {
+            Medium::Homogeneous(medium) => medium.sample(ray, sampler),
+            Medium::Grid(medium) => medium.sample(ray, sampler)
+        }
+    }
+}
+
+/// Participating medium of constant density, such as fog or haze, which
+/// absorbs and scatters light travelling through it. Fills all of space unless
+/// given bounds.
+///
+/// http://www.pbr-book.org/3ed-2018/Volume_Scattering/Media.html#HomogeneousMedium
+#[derive(Debug, Copy, Clone)]
+pub struct HomogeneousMedium {
+    /// Fraction of light scattered per unit distance
+    sigma_s: Color,
+
+    /// Fraction of light absorbed or scattered per unit distance
+    sigma_t: Color,
+
+    /// Distribution of directions into which light scatters
+    phase: HenyeyGreenstein,
+
+    /// Region filled by the medium
+    bounds: Bounds
+}
+
+impl HomogeneousMedium {
+    /// Create a medium with the given absorption and scattering coefficients,
+    /// per unit distance. The asymmetry g in (-1, 1) of the phase function
+    /// sets whether light mostly scatters backward (negative), forward
+    /// (positive) or equally in all directions (zero).
+    pub fn new(absorption: [f64; 3], scattering: [f64; 3], g: f64) -> HomogeneousMedium {
+        let (sigma_a, sigma_s) = (Color::from(absorption), Color::from(scattering));
+        HomogeneousMedium {
+            sigma_s,
+            sigma_t: sigma_a + sigma_s,
+            phase: HenyeyGreenstein::new(g),
+            bounds: Bounds::infinite()
+        }
+    }
+
+    /// Confine the medium to the box between the given corners. Light from
+    /// directional and environment lights only reaches into bounded media.
+    pub fn set_bounds(&mut self, min: [f64; 3], max: [f64; 3]) {
+        self.bounds = Bounds::new(min.into(), max.into())
+    }
+
+    /// Range of parameters of the given ray within the medium, if any
+    fn clip(&self, ray: &Ray) -> Option<(f64, f64)> {
+        clip(&self.bounds, ray)
+    }
+
+    /// Fraction of light that makes it through the medium along the given ray,
+    /// between its t_min and t_max
+    pub fn tr(&self, ray: &Ray) -> Color {
+        match self.clip(ray) {
+            Some((t0, t1)) => self.attenuation((t1 - t0) * ray.d.magnitude()),
+            None => Color::from_value(1.0)
+        }
+    }
+
+    #[inline]
+    fn attenuation(&self, distance: f64) -> Color {
+        // Avoid 0 * inf for channels that don't attenuate at all
+        self.sigma_t.map(|sigma| if sigma > 0.0 { (-sigma * distance).exp() } else { 1.0 })
+    }
+
+    /// Sample a point along the given ray, up to its t_max, at which light
+    /// scatters in the medium. Returns the factor by which the ray throughput
+    /// changes and the interaction at the sampled point, if the ray scatters
+    /// before reaching t_max.
+    pub fn sample(&self, ray: &Ray, sampler: &mut Sampler) -> (Color, Option<MediumInteraction>) {
+        let u = sampler.get_2d();
+        let (t0, t1) = match self.clip(ray) {
+            Some(range) => range,
+            None => return (Color::from_value(1.0), None)
+        };
+
+        // Pick a colour channel and sample a distance in proportion to its
+        // attenuation
+        let channel = ((u.x * 3.0) as usize).min(2);
+        let length = ray.d.magnitude();
+        let distance = -(1.0 - u.y).ln() / self.sigma_t[channel];
+        let t = if distance.is_finite() { t1.min(t0 + distance / length) } else { t1 };
+        let scattered = t < t1;
+
+        // Weigh by the probability of sampling this distance over all channels
+        let tr = self.attenuation((t - t0) * length);
+        let density = if scattered { self.sigma_t.mul_element_wise(tr) } else { tr };
+        let pdf = (density.x + density.y + density.z) / 3.0;
+        if pdf == 0.0 { return (Color::zero(), None) }
+
+        if scattered {
+            let mi = MediumInteraction::new(ray.origin + ray.d * t, -ray.d.normalize(), ray.time, self.phase);
+            let mi = MediumInteraction { wavelength: ray.wavelength, ..mi };
+            (tr.mul_element_wise(self.sigma_s) / pdf, Some(mi))
+        } else {
+            (tr / pdf, None)
+        }
+    }
+}
+
+/// Participating medium whose density varies over a grid of voxels, such as
+/// smoke or a cloud from a simulation. Unlike fog, light can't be attenuated
+/// in closed form through it, so rays are tracked through it in random steps
+/// against the grid's greatest density: delta tracking to find where they
+/// scatter and ratio tracking for the light that makes it through.
+///
+/// http://www.pbr-book.org/3ed-2018/Light_Transport_II_Volume_Rendering/Sampling_Volume_Scattering.html#HeterogeneousMedium
+#[derive(Debug, Clone)]
+pub struct GridMedium {
+    grid: DensityGrid,
+
+    /// Fraction of light absorbed or scattered per unit distance where the
+    /// density is one
+    sigma_t: f64,
+
+    /// Fraction of absorbed or scattered light that scatters, by colour
+    albedo: Color,
+
+    /// Distribution of directions into which light scatters
+    phase: HenyeyGreenstein,
+
+    /// Region outside of which the density is zero
+    bounds: Bounds
+}
+
+impl GridMedium {
+    /// Create a medium of the given grid of densities. The extinction is the
+    /// fraction of light absorbed or scattered per unit distance where the
+    /// density is one, the same for all colours, and the albedo is the
+    /// fraction of that which scatters rather than being absorbed, by colour.
+    /// Dark smoke has a low albedo and clouds one close to one. The asymmetry
+    /// g of the phase function is as for `HomogeneousMedium::new`.
+    pub fn new(grid: DensityGrid, extinction: f64, albedo: [f64; 3], g: f64) -> GridMedium {
+        let bounds = grid.bounds();
+        GridMedium { grid, sigma_t: extinction.max(0.0), albedo: albedo.into(), phase: HenyeyGreenstein::new(g), bounds }
+    }
+
+    /// The grid of densities
+    pub fn grid(&self) -> &DensityGrid {
+        &self.grid
+    }
+
+    /// Greatest fraction of light absorbed or scattered per unit distance
+    /// anywhere in the medium, which tracking steps are taken against
+    #[inline]
+    fn majorant(&self) -> f64 {
+        self.sigma_t * self.grid.max_density()
+    }
+
+    /// Step through the medium along the given ray by random distances drawn
+    /// from the given source of random numbers, in proportion to the
+    /// majorant. Calls the given function at each step with the ray parameter,
+    /// the density there relative to the greatest density, and the source of
+    /// random numbers, until the ray leaves the medium or the function returns
+    /// false.
+    fn track<U: FnMut() -> f64>(&self, ray: &Ray, u: &mut U, mut step: impl FnMut(f64, f64, &mut U) -> bool) {
+        let (t0, t1) = match self.clip(ray) {
+            Some(range) => range,
+            None => return
+        };
+        let majorant = self.majorant();
+        if majorant <= 0.0 { return }
+        let length = ray.d.magnitude();
+        let mut t = t0;
+        for _ in 0..MAX_TRACKING_STEPS {
+            t -= (1.0 - u()).ln() / (majorant * length);
+            if t >= t1 { return }
+            let density = self.grid.density(ray.origin + ray.d * t) / self.grid.max_density();
+            if !step(t, density, u) { return }
+        }
+    }
+
+    #[inline]
+    fn clip(&self, ray: &Ray) -> Option<(f64, f64)> {
+        clip(&self.bounds, ray)
+    }
+
+    /// Fraction of light that makes it through the medium along the given ray,
+    /// between its t_min and t_max, estimated by ratio tracking. Steps are
+    /// taken with random numbers seeded from the ray, so the estimate for a
+    /// given ray is always the same.
+    pub fn tr(&self, ray: &Ray) -> Color {
+        let mut rng = SmallRng::seed_from_u64(ray_seed(ray));
+        let mut tr = 1.0;
+        self.track(ray, &mut || rng.gen(), |_, density, _| {
+            tr *= 1.0 - density;
+            // Little light is left; stop early
+            tr > 1e-4
+        });
+        Color::from_value(tr.max(0.0))
+    }
+
+    /// Sample a point along the given ray, up to its t_max, at which light
+    /// scatters in the medium by delta tracking. Returns the factor by which
+    /// the ray throughput changes, the albedo if the ray scatters, and the
+    /// interaction at the sampled point, if any.
+    pub fn sample(&self, ray: &Ray, sampler: &mut Sampler) -> (Color, Option<MediumInteraction>) {
+        // Each step is a real collision with probability of the density
+        // relative to the majorant, and otherwise a null one that the ray
+        // passes through
+        let mut scattered = None;
+        self.track(ray, &mut || sampler.get_1d(), |t, density, u| {
+            if u() < density { scattered = Some(t) }
+            scattered.is_none()
+        });
+        match scattered {
+            Some(t) => {
+                let mi = MediumInteraction::new(ray.origin + ray.d * t, -ray.d.normalize(), ray.time, self.phase);
+                (self.albedo, Some(MediumInteraction { wavelength: ray.wavelength, ..mi }))
+            },
+            None => (Color::from_value(1.0), None)
+        }
+    }
+}
+
+/// Densities over a box of voxels, such as from a smoke simulation. Voxel
+/// values sit at the integer coordinates of the grid's index space, which
+/// maps to the world by a scale and translation. Densities between voxels are
+/// interpolated and those outside the box are zero. Values are stored in
+/// bricks of 8³ voxels, and bricks of a single value are stored once, so that
+/// sparse volumes such as a cloud in a mostly empty box take little memory.
+#[derive(Debug, Clone)]
+pub struct DensityGrid {
+    /// Indices of the first and last voxels along each axis
+    min: [i32; 3],
+    max: [i32; 3],
+
+    /// Number of bricks along each axis, counted from the brick holding the
+    /// first voxel
+    bricks_size: [usize; 3],
+
+    /// Brick at each position, as an index into `bricks`, x varying fastest
+    brick_map: Vec<u32>,
+    bricks: Vec<Brick>,
+
+    /// Greatest density in the grid
+    max_density: f64,
+
+    /// World size of a voxel along each axis, and world position of the voxel
+    /// at index zero
+    voxel_size: Vector,
+    translation: Vector
+}
+
+#[derive(Debug, Clone)]
+enum Brick {
+    Constant(f32),
+
+    /// Values of each voxel, z varying fastest
+    Voxels(Box<[f32; BRICK_VOXELS]>)
+}
+
+impl DensityGrid {
+    /// Grid of the given number of voxels along x, y and z with the given
+    /// densities, x varying fastest, then y, then z. Voxels are one unit wide
+    /// with the first at the origin; see `place` to move them. Panics if the
+    /// number of densities doesn't match the number of voxels.
+    pub fn new(size: [usize; 3], densities: &[f32]) -> DensityGrid {
+        assert_eq!(densities.len(), size[0] * size[1] * size[2], "expected a density for every voxel");
+        let max = [size[0] as i32 - 1, size[1] as i32 - 1, size[2] as i32 - 1];
+        let mut grid = DensityGrid::empty([0, 0, 0], max);
+        let bricks = |n: usize| n.div_ceil(BRICK_SIZE as usize);
+        for bz in 0..bricks(size[2]) as i32 {
+            for by in 0..bricks(size[1]) as i32 {
+                for bx in 0..bricks(size[0]) as i32 {
+                    let mut values = [0.0; BRICK_VOXELS];
+                    for (n, value) in values.iter_mut().enumerate() {
+                        let (x, y, z) = (bx * BRICK_SIZE + (n >> 6) as i32, by * BRICK_SIZE + ((n >> 3) & 7) as i32, bz * BRICK_SIZE + (n & 7) as i32);
+                        if x <= max[0] && y <= max[1] && z <= max[2] {
+                            *value = densities[(z as usize * size[1] + y as usize) * size[0] + x as usize]
+                        }
+                    }
+                    grid.set_brick([bx * BRICK_SIZE, by * BRICK_SIZE, bz * BRICK_SIZE], &values)
+                }
+            }
+        }
+        grid
+    }
+
+    /// Empty grid over the voxels between the given indices, inclusive, to be
+    /// filled with `set_brick` and `fill`
+    pub(crate) fn empty(min: [i32; 3], max: [i32; 3]) -> DensityGrid {
+        let bricks_size = [0, 1, 2].map(|i| (max[i].div_euclid(BRICK_SIZE) - min[i].div_euclid(BRICK_SIZE) + 1).max(0) as usize);
+        DensityGrid {
+            min, max, bricks_size,
+            brick_map: vec![0; bricks_size[0] * bricks_size[1] * bricks_size[2]],
+            bricks: vec![Brick::Constant(0.0)],
+            max_density: 0.0,
+            voxel_size: Vector::from_value(1.0),
+            translation: Vector::zero()
+        }
+    }
+
+    /// Set the values of the brick of 8³ voxels starting at the given index,
+    /// which must be a multiple of eight, z varying fastest. Bricks outside
+    /// the grid are dropped.
+    pub(crate) fn set_brick(&mut self, origin: [i32; 3], values: &[f32; BRICK_VOXELS]) {
+        let first = values[0];
+        if values.iter().all(|value| *value == first) {
+            return self.fill(origin, BRICK_SIZE, first)
+        }
+        if let Some(index) = self.brick_index(origin) {
+            self.max_density = values.iter().fold(self.max_density, |max, value| max.max(*value as f64));
+            self.brick_map[index] = self.bricks.len() as u32;
+            self.bricks.push(Brick::Voxels(Box::new(*values)));
+        }
+    }
+
+    /// Set every voxel of the cube of the given edge length starting at the
+    /// given index, both multiples of eight, to the given value
+    pub(crate) fn fill(&mut self, origin: [i32; 3], size: i32, value: f32) {
+        let brick = self.bricks.len() as u32;
+        let mut used = false;
+        for bz in (0..size).step_by(BRICK_SIZE as usize) {
+            for by in (0..size).step_by(BRICK_SIZE as usize) {
+                for bx in (0..size).step_by(BRICK_SIZE as usize) {
+                    if let Some(index) = self.brick_index([origin[0] + bx, origin[1] + by, origin[2] + bz]) {
+                        self.brick_map[index] = if value == 0.0 { 0 } else { brick };
+                        used = true;
+                    }
+                }
+            }
+        }
+        if used && value != 0.0 {
+            self.max_density = self.max_density.max(value as f64);
+            self.bricks.push(Brick::Constant(value));
+        }
+    }
+
+    /// Place the voxel at index zero at the given world position, with voxels
+    /// of the given world size along each axis
+    pub(crate) fn set_transform(&mut self, voxel_size: Vector, translation: Vector) {
+        self.voxel_size = voxel_size;
+        self.translation = translation;
+    }
+
+    /// Stretch the grid over the box between the given corners, so that each
+    /// voxel fills an equal part of it
+    pub fn place(&mut self, min: [f64; 3], max: [f64; 3]) {
+        let voxel_size = Vector::new(
+            (max[0] - min[0]) / (self.max[0] - self.min[0] + 1) as f64,
+            (max[1] - min[1]) / (self.max[1] - self.min[1] + 1) as f64,
+            (max[2] - min[2]) / (self.max[2] - self.min[2] + 1) as f64
+        );
+        let first = Vector::new(self.min[0] as f64, self.min[1] as f64, self.min[2] as f64);
+        let translation = Vector::from(min) + voxel_size * 0.5 - voxel_size.mul_element_wise(first);
+        self.set_transform(voxel_size, translation)
+    }
+
+    /// Greatest density in the grid
+    #[inline]
+    pub fn max_density(&self) -> f64 {
+        self.max_density
+    }
+
+    /// World box outside of which the density is zero
+    pub fn bounds(&self) -> Bounds {
+        let corner = |index: [i32; 3], offset: f64| {
+            let index = Vector::new(index[0] as f64 + offset, index[1] as f64 + offset, index[2] as f64 + offset);
+            Point::from_vec(self.translation + self.voxel_size.mul_element_wise(index))
+        };
+        Bounds::new(corner(self.min, -1.0), corner(self.max, 1.0))
+    }
+
+    /// Density at the given point in world space, interpolated between the
+    /// eight nearest voxels
+    pub fn density(&self, p: Point) -> f64 {
+        let q = (p.to_vec() - self.translation).div_element_wise(self.voxel_size);
+        let base = q.map(f64::floor);
+        let (i, j, k) = (base.x as i32, base.y as i32, base.z as i32);
+        let d = q - base;
+        let lerp = |t: f64, a: f64, b: f64| a + t * (b - a);
+        let v = |x, y, z| self.voxel([i + x, j + y, k + z]) as f64;
+        let d00 = lerp(d.x, v(0, 0, 0), v(1, 0, 0));
+        let d10 = lerp(d.x, v(0, 1, 0), v(1, 1, 0));
+        let d01 = lerp(d.x, v(0, 0, 1), v(1, 0, 1));
+        let d11 = lerp(d.x, v(0, 1, 1), v(1, 1, 1));
+        lerp(d.z, lerp(d.y, d00, d10), lerp(d.y, d01, d11))
+    }
+
+    /// Value of the voxel at the given index, zero outside of the grid
+    pub fn voxel(&self, index: [i32; 3]) -> f32 {
+        if (0..3).any(|i| index[i] < self.min[i] || index[i] > self.max[i]) { return 0.0 }
+        match &self.bricks[self.brick_map[self.brick_index(index).unwrap()] as usize] {
+            Brick::Constant(value) => *value,
+            Brick::Voxels(values) => {
+                let n = (index[0] & 7) << 6 | (index[1] & 7) << 3 | (index[2] & 7);
+                values[n as usize]
+            }
+        }
+    }
+
+    /// Position in the brick map of the brick holding the voxel at the given
+    /// index, if within the grid's bricks
+    fn brick_index(&self, index: [i32; 3]) -> Option<usize> {
+        let mut position = [0; 3];
+        for i in 0..3 {
+            let brick = index[i].div_euclid(BRICK_SIZE) - self.min[i].div_euclid(BRICK_SIZE);
+            if brick < 0 || brick as usize >= self.bricks_size[i] { return None }
+            position[i] = brick as usize;
+        }
+        Some((position[2] * self.bricks_size[1] + position[1]) * self.bricks_size[0] + position[0])
+    }
+}
+
+/// Range of parameters of the given ray within the given box, if any
+fn clip(bounds: &Bounds, ray: &Ray) -> Option<(f64, f64)> {
+    let (mut t0, mut t1) = (ray.t_min, ray.t_max);
+    for i in 0..3 {
+        let near = (bounds.min[i] - ray.origin[i]) * ray.dinv[i];
+        let far = (bounds.max[i] - ray.origin[i]) * ray.dinv[i];
+        // Rays parallel to and within a slab give NaN; skip those
+        if near.is_nan() || far.is_nan() { continue }
+        t0 = t0.max(near.min(far));
+        t1 = t1.min(near.max(far));
+    }
+    if t0 < t1 { Some((t0, t1)) } else { None }
+}
+
+/// Seed for the random numbers of tracking along the given ray
+fn ray_seed(ray: &Ray) -> u64 {
+    let values = [ray.origin.x, ray.origin.y, ray.origin.z, ray.d.x, ray.d.y, ray.d.z, ray.t_min];
+    values.iter().fold(0xcbf29ce484222325, |hash: u64, value| (hash ^ value.to_bits()).wrapping_mul(0x100000001b3))
+}
+
+/// Henyey-Greenstein phase function, the distribution of directions into which
+/// light scatters at a point in a medium
+///
+/// http://www.pbr-book.org/3ed-2018/Volume_Scattering/Phase_Functions.html
+#[derive(Debug, Copy, Clone)]
+pub struct HenyeyGreenstein {
+    /// Asymmetry parameter in (-1, 1), the average cosine of the scattering
+    /// angle
+    g: f64
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> HenyeyGreenstein {
+        debug_assert!(g > -1.0 && g < 1.0);
+        HenyeyGreenstein { g }
+    }
+
+    /// Density of light arriving from wi that scatters toward wo. Both point
+    /// away from the scattering point.
+    pub fn p(&self, wo: &Vector, wi: &Vector) -> f64 {
+        phase_hg(wo.dot(*wi), self.g)
+    }
+
+    /// Sample an incident direction for the given outgoing direction, in
+    /// proportion to the phase function. Returns the direction and its density,
+    /// which equals the phase function value.
+    pub fn sample_p(&self, wo: &Vector, u: &Point2f) -> (Vector, f64) {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u.x
+        } else {
+            let sqr = (1.0 - g * g) / (1.0 + g - 2.0 * g * u.x);
+            -(1.0 + g * g - sqr * sqr) / (2.0 * g)
+        };
+
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * f64::consts::PI * u.y;
+        let (v1, v2) = coordinate_system(wo);
+        let wi = v1 * (sin_theta * phi.cos()) + v2 * (sin_theta * phi.sin()) + wo * cos_theta;
+        (wi, phase_hg(cos_theta, g))
+    }
+}
+
+#[inline]
+fn phase_hg(cos_theta: f64, g: f64) -> f64 {
+    let denom = 1.0 + g * g + 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * f64::consts::PI * denom * denom.sqrt())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sampler::SamplerKind;
+
+    #[test]
+    fn scatters_forward_on_average() {
+        let phase = HenyeyGreenstein::new(0.6);
+        let wo = Vector::new(0.0, 0.0, 1.0);
+        let n = 64;
+        let mut mean_cos = 0.0;
+        for i in 0..(n * n) {
+            let u = Point2f::new(((i % n) as f64 + 0.5) / n as f64, ((i / n) as f64 + 0.5) / n as f64);
+            let (wi, pdf) = phase.sample_p(&wo, &u);
+            assert!((pdf - phase.p(&wo, &wi)).abs() < 1e-9 * pdf.max(1.0));
+            // Light continues along -wo
+            mean_cos += -wo.dot(wi);
+        }
+        mean_cos /= (n * n) as f64;
+        assert!((mean_cos - 0.6).abs() < 0.01, "{}", mean_cos);
+    }
+
+    #[test]
+    fn attenuates_within_bounds() {
+        let mut medium = HomogeneousMedium::new([0.5, 0.0, 0.0], [0.5, 0.0, 1.0], 0.0);
+        medium.set_bounds([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0]);
+        let ray = Ray::new(Point::new(0.0, 0.0, -5.0), Vector::new(0.0, 0.0, 2.0));
+        let tr = medium.tr(&ray);
+        assert!((tr.x - (-2.0f64).exp()).abs() < 1e-9);
+        assert_eq!(tr.y, 1.0);
+        assert!((tr.z - (-2.0f64).exp()).abs() < 1e-9);
+
+        // Rays that miss are not attenuated
+        let ray = Ray::new(Point::new(0.0, 2.0, -5.0), Vector::new(0.0, 0.0, 1.0));
+        assert_eq!(medium.tr(&ray), Color::from_value(1.0));
+    }
+
+    #[test]
+    fn stores_uniform_bricks_once() {
+        let mut densities = vec![0.0; 20 * 16 * 16];
+        densities[(3 * 16 + 2) * 20 + 17] = 4.0;
+        let grid = DensityGrid::new([20, 16, 16], &densities);
+        // The empty brick and the one holding the dense voxel
+        assert_eq!(grid.bricks.len(), 2);
+        assert_eq!(grid.voxel([17, 2, 3]), 4.0);
+        assert_eq!(grid.voxel([16, 2, 3]), 0.0);
+        assert_eq!(grid.voxel([20, 2, 3]), 0.0);
+        assert_eq!(grid.max_density(), 4.0);
+        assert!((grid.density(Point::new(16.5, 2.0, 3.0)) - 2.0).abs() < 1e-9);
+        assert!((grid.density(Point::new(17.0, 2.5, 3.5)) - 1.0).abs() < 1e-9);
+    }
+
+    /// Medium of constant density 0.5 placed over [-1, 1]³, which falls off
+    /// to zero over half a voxel on each side, so that rays through it are
+    /// attenuated by exp(-extinction) as through fog two units across of
+    /// half the extinction
+    fn constant_medium(extinction: f64) -> GridMedium {
+        let mut grid = DensityGrid::new([4, 4, 4], &[0.5; 64]);
+        grid.place([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0]);
+        GridMedium::new(grid, extinction, [0.8, 0.8, 0.8], 0.0)
+    }
+
+    #[test]
+    fn ratio_tracks_like_fog() {
+        let medium = constant_medium(1.0);
+        let n = 4000;
+        let mut tr = 0.0;
+        for i in 0..n {
+            let x = (i as f64 + 0.5) / n as f64 - 0.5;
+            tr += medium.tr(&Ray::new(Point::new(x, 0.0, -5.0), Vector::new(0.0, 0.0, 1.0))).x;
+        }
+        tr /= n as f64;
+        assert!((tr - (-1.0f64).exp()).abs() < 0.03, "{}", tr);
+    }
+
+    #[test]
+    fn delta_tracks_like_fog() {
+        let medium = constant_medium(1.0);
+        let mut sampler = Sampler::new(SamplerKind::Uniform, 1);
+        let n = 4000;
+        let mut passed = 0;
+        for i in 0..n {
+            sampler.start_pixel(i);
+            let ray = Ray::new(Point::new(0.0, 0.0, -5.0), Vector::new(0.0, 0.0, 1.0));
+            match medium.sample(&ray, &mut sampler) {
+                (beta, Some(mi)) => {
+                    assert_eq!(beta, Color::from_value(0.8));
+                    assert!(mi.p.z > -1.25 && mi.p.z < 1.25, "{:?}", mi.p);
+                },
+                (beta, None) => {
+                    assert_eq!(beta, Color::from_value(1.0));
+                    passed += 1;
+                }
+            }
+        }
+        let tr = passed as f64 / n as f64;
+        assert!((tr - (-1.0f64).exp()).abs() < 0.03, "{}", tr);
+    }
+}
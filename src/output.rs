@@ -1,57 +1,354 @@
-use std::ops::{Index, IndexMut};
-use ::image::RgbaImage;
-use crate::{capture, Scene, Film, Pixel, PixelBuffer};
+use std::{path::Path, fs::{self, File}, io::{self, Write, BufWriter}, sync::Mutex};
+use ::image::{ImageBuffer, Rgb, ImageResult, ColorType, png::PngEncoder};
+#[cfg(feature = "denoise")]
+use crate::Img;
+use crate::{capture, capture_accel, capture_streaming, Accel, Scene, Film, HdrFilm, FilmSet, Aovs, Pixel, PixelBuffer, RenderProgress, TileBounds};
 
 pub fn render(scene: &Scene, resolution: [u32; 2], filename: &str) {
-    let (width, height) = (resolution[0], resolution[1]);
-
-    // Pre-allocate traced image data
-    let rgba = RgbaImage::new(width, height);
-    let image = Box::new(Image(rgba));
-    let mut film = Film::new_with_output(width, height, image);
+    let mut film = film(resolution);
+    film.tonemap = scene.tonemap;
 
     // Capture the image
+    #[cfg(feature = "denoise")]
+    {
+        if scene.denoise {
+            return render_denoised(scene, film, filename)
+        }
+    }
     capture(&scene, &mut film);
 
     // Save the film
     film.save(filename)
 }
 
-/// Create a film in the correct x/y dimensions for the given scene
+/// Capture the scene with its auxiliary passes, then denoise it into the
+/// given film and save that
+#[cfg(feature = "denoise")]
+fn render_denoised(scene: &Scene, mut film: Film, filename: &str) {
+    let mut passes = FilmSet::new(film.w, film.h);
+    capture(scene, &mut passes);
+    passes.denoise();
+
+    for y in 0..film.h {
+        for x in 0..film.w {
+            let [r, g, b] = passes.beauty.get(x, y);
+            film.set(x, y, &[r as f64, g as f64, b as f64])
+        }
+    }
+    film.save(filename)
+}
+
+/// Render the given number of frames of the scene's animation at the given
+/// frames per second, posing the scene with `Scene::set_time` before each one.
+/// Frames are saved to the given directory as numbered PNG files, starting at
+/// 0000.png, which video encoders accept as an image sequence.
+///
+/// The acceleration structure built for the first frame is refit to each
+/// following one rather than built again.
+pub fn render_sequence(scene: &mut Scene, resolution: [u32; 2], frames: u32, fps: f64, dir: &str) -> io::Result<()> {
+    debug_assert!(fps > 0.);
+    let dir = Path::new(dir);
+    fs::create_dir_all(dir)?;
+
+    let mut layout = None;
+    for frame in 0..frames {
+        scene.set_time(frame as f64 / fps);
+        let root = match &layout {
+            Some(layout) => Accel::refit(scene, layout),
+            None => Accel::from(scene)
+        };
+
+        let mut film = film(resolution);
+        film.tonemap = scene.tonemap;
+        capture_accel(&root, &mut film);
+        film.save(&dir.join(format!("{:04}.png", frame)).to_string_lossy());
+        layout = Some(root.layout());
+    }
+    Ok(())
+}
+
+/// Create a film in the correct x/y dimensions for the given scene. Films
+/// save themselves as PNG, JPEG or BMP, depending on the file extension.
 pub fn film(resolution: [u32; 2]) -> Film {
-    let (width, height) = (resolution[0], resolution[1]);
+    Film::new(resolution[0], resolution[1])
+}
+
+/// Render the given scene straight to the given file with a
+/// `StreamingWriter`, without keeping the whole image in memory. For images
+/// too large to render with `render`.
+pub fn render_streaming(scene: &Scene, resolution: [u32; 2], filename: &str) -> io::Result<()> {
+    let writer = StreamingWriter::create(filename, resolution[0], resolution[1])?;
+    capture_streaming(scene, (resolution[0], resolution[1]), &writer);
+    writer.finish()
+}
+
+/// Writes an image to a file a scanline at a time as a render finishes them,
+/// so that huge renders never have to be held in memory whole. Files with the
+/// .ppm extension are saved as binary PPM; all others are saved as PNG.
+///
+/// Tiles may be written in any order, such as by passing the writer to
+/// `capture_streaming` as its progress listener. Each is kept until all of
+/// its scanlines and those above them are finished, then written out.
+pub struct StreamingWriter {
+    stream: Mutex<Stream>
+}
+
+/// Scanlines written so far and tiles waiting to be
+struct Stream {
+    w: u32,
+    h: u32,
+
+    /// Index of the next scanline to write
+    row: u32,
+
+    /// Tiles with scanlines not yet written, along with their pixels
+    pending: Vec<(TileBounds, Vec<Pixel>)>,
+
+    encoder: Option<Encoder>,
+
+    /// First error met while writing tiles reported as progress, returned by
+    /// `finish`
+    error: Option<io::Error>
+}
+
+enum Encoder {
+    Ppm(BufWriter<File>),
+    Png(Box<png::StreamWriter<'static, BufWriter<File>>>)
+}
+
+impl StreamingWriter {
+    /// Create the given file for an image of the given dimensions and write
+    /// its header
+    pub fn create(filename: &str, width: u32, height: u32) -> io::Result<StreamingWriter> {
+        let mut file = BufWriter::new(File::create(filename)?);
+        let is_ppm = Path::new(filename).extension().is_some_and(|ext| ext == "ppm");
+        let encoder = if is_ppm {
+            write!(file, "P6\n{} {}\n255\n", width, height)?;
+            Encoder::Ppm(file)
+        } else {
+            let mut encoder = png::Encoder::new(file, width, height);
+            encoder.set_color(png::ColorType::RGB);
+            encoder.set_depth(png::BitDepth::Eight);
+            Encoder::Png(Box::new(encoder.write_header()?.into_stream_writer()))
+        };
+        let stream = Stream { w: width, h: height, row: 0, pending: vec![], encoder: Some(encoder), error: None };
+        Ok(StreamingWriter { stream: Mutex::new(stream) })
+    }
+
+    /// Add the pixels of the given tile, in row-major order, and write out
+    /// every scanline that is now finished
+    pub fn write_tile(&self, bounds: TileBounds, pixels: &[Pixel]) -> io::Result<()> {
+        debug_assert_eq!(pixels.len(), (bounds.w * bounds.h) as usize);
+        let mut stream = self.stream.lock().unwrap();
+        stream.pending.push((bounds, pixels.to_vec()));
+        stream.write_rows()
+    }
+
+    /// Number of scanlines written so far
+    pub fn rows_written(&self) -> u32 {
+        self.stream.lock().unwrap().row
+    }
 
-    // Pre-allocate traced image data
-    let rgba = RgbaImage::new(width, height);
-    let image = Box::new(Image(rgba));
-    Film::new_with_output(width, height, image)
+    /// Finish the file. Fails if writing any tile failed or if some
+    /// scanlines were never finished.
+    pub fn finish(self) -> io::Result<()> {
+        let mut stream = self.stream.into_inner().unwrap();
+        if let Some(error) = stream.error.take() { return Err(error) }
+        if stream.row < stream.h {
+            let message = format!("only {} of {} scanlines were rendered", stream.row, stream.h);
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, message))
+        }
+        match stream.encoder.take().unwrap() {
+            Encoder::Ppm(mut file) => file.flush(),
+            Encoder::Png(writer) => Ok(writer.finish()?)
+        }
+    }
 }
 
-struct Image(RgbaImage);
+impl Stream {
+    /// Write scanlines for as long as the pending tiles cover the next one
+    fn write_rows(&mut self) -> io::Result<()> {
+        while self.row < self.h {
+            let y = self.row;
+            let covers = |(bounds, _): &&(TileBounds, Vec<Pixel>)| bounds.y <= y && y < bounds.y + bounds.h;
+            let covered: u32 = self.pending.iter().filter(covers).map(|(bounds, _)| bounds.w).sum();
+            if covered < self.w { break }
+
+            let mut row = vec![0; 3 * self.w as usize];
+            for (bounds, pixels) in self.pending.iter().filter(covers) {
+                let start = ((y - bounds.y) * bounds.w) as usize;
+                for (i, pixel) in pixels[start..start + bounds.w as usize].iter().enumerate() {
+                    let x = bounds.x as usize + i;
+                    row[3 * x..3 * x + 3].copy_from_slice(&pixel[..3]);
+                }
+            }
+            match self.encoder.as_mut().unwrap() {
+                Encoder::Ppm(file) => file.write_all(&row)?,
+                Encoder::Png(writer) => writer.write_all(&row)?
+            }
+            self.row += 1;
+            let next = self.row;
+            self.pending.retain(|(bounds, _)| bounds.y + bounds.h > next);
+        }
+        Ok(())
+    }
+}
 
-impl Index<usize> for Image {
-    type Output = Pixel;
-    #[inline]
-    fn index(&self, index: usize) -> &Pixel {
-        let (x, y) = (
-            (index % self.0.width() as usize) as u32,
-            (index / self.0.width() as usize) as u32
-        );
-        &self.0.get_pixel(x, y).0
+impl RenderProgress for StreamingWriter {
+    fn on_tile_complete(&self, bounds: TileBounds, pixels: &[Pixel]) {
+        if let Err(err) = self.write_tile(bounds, pixels) {
+            self.stream.lock().unwrap().error.get_or_insert(err);
+        }
     }
 }
 
-impl IndexMut<usize> for Image {
-    #[inline]
-    fn index_mut(&mut self, index: usize) -> &mut Pixel {
-        let (x, y) = (
-            (index % self.0.width() as usize) as u32,
-            (index / self.0.width() as usize) as u32
-        );
-        &mut self.0.get_pixel_mut(x, y).0
+/// Render the given scene without clamping radiance and save it to the given
+/// file. Files with the .exr extension are saved as OpenEXR with 32-bit float
+/// channels; all others are saved as 16-bit PNG.
+pub fn render_hdr(scene: &Scene, resolution: [u32; 2], filename: &str) {
+    let mut film = HdrFilm::new(resolution[0], resolution[1]);
+    capture(scene, &mut film);
+
+    let is_exr = Path::new(filename).extension().is_some_and(|ext| ext == "exr");
+    if is_exr {
+        save_exr(&film, filename).unwrap()
+    } else {
+        save_png16(&film, filename).unwrap()
     }
 }
 
-impl PixelBuffer for Image {
-    fn save(&self, filename: &str) { self.0.save(filename).unwrap() }
+/// Render the given scene along with its auxiliary passes and save each as an
+/// OpenEXR image: the radiance to the given file, and the normal, depth,
+/// albedo and object ID passes next to it, named with the pass before the
+/// extension (e.g., render.normal.exr for render.exr). Depth is saved to all
+/// three channels; object IDs are saved as distinct colours.
+pub fn render_passes(scene: &Scene, resolution: [u32; 2], filename: &str) -> exr::error::UnitResult {
+    let mut film = FilmSet::new(resolution[0], resolution[1]);
+    capture(scene, &mut film);
+    save_passes(&film, filename)
+}
+
+/// Save the radiance and auxiliary passes of the given film set as OpenEXR
+/// images. See `render_passes`.
+pub fn save_passes(film: &FilmSet, filename: &str) -> exr::error::UnitResult {
+    save_exr(&film.beauty, filename)?;
+    save_pass(film, filename, "normal", |aovs| aovs.normal)?;
+    save_pass(film, filename, "depth", |aovs| [aovs.depth; 3])?;
+    save_pass(film, filename, "albedo", |aovs| aovs.albedo)?;
+    save_pass(film, filename, "id", |aovs| id_color(aovs.id))
+}
+
+/// Save one auxiliary pass of the given film set, next to the given file
+fn save_pass<F>(film: &FilmSet, filename: &str, pass: &str, f: F) -> exr::error::UnitResult
+where F: Fn(&Aovs) -> [f32; 3] + Sync {
+    let path = Path::new(filename).with_extension(format!("{}.exr", pass));
+    exr::prelude::write_rgb_file(path, film.beauty.w as usize, film.beauty.h as usize, |x, y| {
+        let [r, g, b] = f(&film.aovs_at(x as u32, y as u32));
+        (r, g, b)
+    })
+}
+
+/// Colour for the given object ID, scattered such that neighbouring IDs are
+/// easy to tell apart. Black for pixels without an object.
+fn id_color(id: u32) -> [f32; 3] {
+    if id == Aovs::NO_ID { return [0.0; 3] }
+    let hash = id.wrapping_add(1).wrapping_mul(0x9e3779b1);
+    let channel = |shift: u32| ((hash >> shift) & 0xff) as f32 / 255.0;
+    [channel(24), channel(16), channel(8)]
+}
+
+/// Save the given film as an OpenEXR image with 32-bit float RGB channels
+pub fn save_exr(film: &HdrFilm, filename: &str) -> exr::error::UnitResult {
+    exr::prelude::write_rgb_file(filename, film.w as usize, film.h as usize, |x, y| {
+        let [r, g, b] = film.get(x as u32, y as u32);
+        (r, g, b)
+    })
+}
+
+/// Encode the given film as an OpenEXR image in memory, as with `save_exr`
+pub fn encode_exr(film: &HdrFilm) -> exr::error::Result<Vec<u8>> {
+    use exr::prelude::*;
+    let channels = SpecificChannels::rgb(|Vec2(x, y)| {
+        let [r, g, b] = film.get(x as u32, y as u32);
+        (r, g, b)
+    });
+    let mut bytes = io::Cursor::new(Vec::new());
+    Image::from_channels((film.w as usize, film.h as usize), channels).write().to_buffered(&mut bytes)?;
+    Ok(bytes.into_inner())
+}
+
+/// Encode the given film as a PNG image in memory
+pub fn encode_png(film: &Film) -> ImageResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+    PngEncoder::new(&mut bytes).encode(&film.to_image(), film.w, film.h, ColorType::Rgba8)?;
+    Ok(bytes)
+}
+
+/// Save the given film as a PNG image with 16 bits per channel. Radiance is
+/// clamped to [0, 1].
+pub fn save_png16(film: &HdrFilm, filename: &str) -> ImageResult<()> {
+    let to_u16 = |channel: f32| (channel.clamp(0.0, 1.0) * 65535.0).round() as u16;
+    let image: ImageBuffer<Rgb<u16>, Vec<u16>> = ImageBuffer::from_fn(film.w, film.h, |x, y| {
+        let [r, g, b] = film.get(x, y);
+        Rgb([to_u16(r), to_u16(g), to_u16(b)])
+    });
+    image.save(filename)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn streams_tiles_in_any_order() {
+        // Four tiles of a 3x3 image, with the pixel at x, y coloured x, y, 7
+        let tile = |x: u32, y: u32, w: u32, h: u32| {
+            let pixels: Vec<Pixel> = (0..w * h).map(|i| [(x + i % w) as u8, (y + i / w) as u8, 7, 255]).collect();
+            (TileBounds { x, y, w, h }, pixels)
+        };
+        let tiles = [tile(2, 2, 1, 1), tile(2, 0, 1, 2), tile(0, 0, 2, 2), tile(0, 2, 2, 1)];
+
+        for ext in ["ppm", "png"] {
+            let path = std::env::temp_dir().join(format!("lasgun-stream-{}.{}", std::process::id(), ext));
+            let filename = path.to_str().unwrap();
+            let writer = StreamingWriter::create(filename, 3, 3).unwrap();
+            let rows = [0, 0, 2, 3];
+            for ((bounds, pixels), rows) in tiles.iter().zip(rows.iter()) {
+                writer.write_tile(*bounds, pixels).unwrap();
+                assert_eq!(writer.rows_written(), *rows);
+            }
+            writer.finish().unwrap();
+
+            let data = if ext == "png" {
+                ::image::open(&path).unwrap().into_rgb8().into_raw()
+            } else {
+                let bytes = fs::read(&path).unwrap();
+                assert!(bytes.starts_with(b"P6\n3 3\n255\n"));
+                bytes[11..].to_vec()
+            };
+            fs::remove_file(&path).unwrap();
+            let expected: Vec<u8> = (0..9).flat_map(|i| [i % 3, i / 3, 7]).collect();
+            assert_eq!(data, expected);
+        }
+    }
+
+    #[test]
+    fn unfinished_streams_fail() {
+        let path = std::env::temp_dir().join(format!("lasgun-unfinished-{}.ppm", std::process::id()));
+        let writer = StreamingWriter::create(path.to_str().unwrap(), 2, 2).unwrap();
+        writer.write_tile(TileBounds { x: 0, y: 0, w: 2, h: 1 }, &[[0; 4]; 2]).unwrap();
+        assert!(writer.finish().is_err());
+        fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn encodes_images_in_memory() {
+        let mut film = Film::new(2, 1);
+        film[1] = [10, 20, 30, 255];
+        let png = encode_png(&film).unwrap();
+        let image = ::image::load_from_memory(&png).unwrap().into_rgba8();
+        assert_eq!(image.into_raw(), vec![0, 0, 0, 0, 10, 20, 30, 255]);
+
+        let exr = encode_exr(&HdrFilm::new(2, 1)).unwrap();
+        assert!(exr.starts_with(&[0x76, 0x2f, 0x31, 0x01]));
+    }
 }
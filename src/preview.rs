@@ -0,0 +1,175 @@
+//! Interactive preview window that shows the image as its tiles finish.
+//!
+//! Drag with the left mouse button to orbit the camera around the point it
+//! looks at, scroll to move towards or away from that point and use W, A, S
+//! and D to move across the ground. Each move restarts the render, first at
+//! one sample per pixel for a quick look and then at the camera's own samples
+//! per pixel. Escape closes the window.
+
+use std::{f64::consts::FRAC_PI_2, iter, sync::{Mutex, mpsc}, thread};
+use minifb::{Key, MouseButton, MouseMode, Window, WindowOptions};
+use crate::{capture_tiles, space::*, Accel, Camera, Film, Pixel, RenderHandle, RenderProgress, Scene, TileBounds};
+
+/// Radians the camera turns for each pixel the mouse is dragged
+const ORBIT_SPEED: f64 = 0.01;
+
+/// Fraction of the distance to the look-at point moved each frame a movement
+/// key is held
+const MOVE_SPEED: f64 = 0.02;
+
+/// Factor by which each step of the scroll wheel brings the camera closer
+const ZOOM_SPEED: f64 = 0.9;
+
+/// Open a window of the given size showing the scene as seen through its
+/// camera, and re-render as the camera is moved around. Returns once the
+/// window is closed.
+pub fn show(scene: &Scene, width: u32, height: u32) -> Result<(), minifb::Error> {
+    let mut window = Window::new("lasgun", width as usize, height as usize, WindowOptions::default())?;
+    window.set_target_fps(60);
+
+    let root = Accel::from(scene);
+    let screen = Screen { width, height, buffer: Mutex::new(vec![0; (width * height) as usize]) };
+    let mut frame = vec![0; (width * height) as usize];
+    let mut orbit = Orbit::from(&scene.camera);
+    let (jobs, receiver) = mpsc::channel();
+
+    thread::scope(|s| {
+        let jobs = jobs;
+        s.spawn(|| render(&root, &screen, receiver));
+
+        let mut handle = RenderHandle::new();
+        jobs.send((orbit.camera(&scene.camera), handle.clone())).ok();
+
+        let mut mouse = None;
+        while window.is_open() && !window.is_key_down(Key::Escape) {
+            if orbit.update(&window, &mut mouse) {
+                handle.cancel();
+                handle = RenderHandle::new();
+                jobs.send((orbit.camera(&scene.camera), handle.clone())).ok();
+            }
+            frame.copy_from_slice(&screen.buffer.lock().unwrap());
+            if let Err(err) = window.update_with_buffer(&frame, width as usize, height as usize) {
+                handle.cancel();
+                return Err(err)
+            }
+        }
+
+        // Dropping the sender ends the render thread once it's done
+        handle.cancel();
+        Ok(())
+    })
+}
+
+/// Render each camera received in turn, skipping ahead to the latest one when
+/// several arrive during a render
+fn render(root: &Accel, screen: &Screen, jobs: mpsc::Receiver<(Camera, RenderHandle)>) {
+    let mut job = jobs.recv();
+    while let Ok((mut camera, handle)) = job {
+        let samples = camera.samples_per_pixel();
+        for spp in iter::once(1).chain((samples > 1).then_some(samples)) {
+            camera.set_samples_per_pixel(spp);
+            let mut film = Film::new(screen.width, screen.height);
+            if !capture_tiles(root, &camera, &mut film, Some(screen), Some(&handle), None) { break }
+        }
+        job = jobs.recv().map(|job| jobs.try_iter().last().unwrap_or(job));
+    }
+}
+
+/// Pixels shown in the window, as 0RGB for minifb, updated by the render
+/// thread as tiles finish
+struct Screen {
+    width: u32,
+    height: u32,
+    buffer: Mutex<Vec<u32>>
+}
+
+impl RenderProgress for Screen {
+    fn on_tile_complete(&self, bounds: TileBounds, pixels: &[Pixel]) {
+        let mut buffer = self.buffer.lock().unwrap();
+        for (y, row) in (bounds.y..).zip(pixels.chunks(bounds.w as usize)) {
+            let start = (y * self.width + bounds.x) as usize;
+            for (dest, &[r, g, b, _]) in buffer[start..start + row.len()].iter_mut().zip(row) {
+                *dest = u32::from_be_bytes([0, r, g, b])
+            }
+        }
+    }
+}
+
+/// Position of the camera as angles around the point it looks at, with the
+/// y-axis pointing up
+struct Orbit {
+    target: Point,
+    distance: f64,
+
+    /// Angle around the y-axis, from the z-axis towards the x-axis
+    yaw: f64,
+
+    /// Angle above the xz-plane
+    pitch: f64
+}
+
+impl Orbit {
+    fn from(camera: &Camera) -> Orbit {
+        let distance = camera.view.magnitude();
+        let back = -camera.view / distance;
+        Orbit {
+            target: camera.origin + camera.view,
+            distance,
+            yaw: back.x.atan2(back.z),
+            pitch: back.y.asin()
+        }
+    }
+
+    /// Direction from the target to the camera
+    fn back(&self) -> Vector {
+        Vector::new(self.pitch.cos() * self.yaw.sin(), self.pitch.sin(), self.pitch.cos() * self.yaw.cos())
+    }
+
+    /// Copy of the given camera placed at this orbit
+    fn camera(&self, camera: &Camera) -> Camera {
+        let mut camera = camera.clone();
+        let origin = self.target + self.back() * self.distance;
+        camera.look_at(origin.into(), self.target.into(), [0., 1., 0.]);
+        camera
+    }
+
+    /// Apply the mouse and keyboard input of the current frame, given the
+    /// mouse position while dragging at the last frame. Returns whether the
+    /// camera moved.
+    fn update(&mut self, window: &Window, mouse: &mut Option<(f32, f32)>) -> bool {
+        let mut moved = false;
+
+        let last = mouse.take();
+        if window.get_mouse_down(MouseButton::Left) {
+            *mouse = window.get_mouse_pos(MouseMode::Pass);
+            if let (Some((x0, y0)), Some((x1, y1))) = (last, *mouse) {
+                if (x0, y0) != (x1, y1) {
+                    // Stop short of the poles, where the up vector flips
+                    let limit = FRAC_PI_2 - 0.01;
+                    self.yaw -= (x1 - x0) as f64 * ORBIT_SPEED;
+                    self.pitch = (self.pitch + (y1 - y0) as f64 * ORBIT_SPEED).clamp(-limit, limit);
+                    moved = true
+                }
+            }
+        }
+
+        if let Some((_, scroll)) = window.get_scroll_wheel() {
+            if scroll != 0. {
+                self.distance *= ZOOM_SPEED.powf(scroll as f64);
+                moved = true
+            }
+        }
+
+        let forward = Vector::new(-self.yaw.sin(), 0., -self.yaw.cos());
+        let right = Vector::new(-forward.z, 0., forward.x);
+        let step = self.distance * MOVE_SPEED;
+        for (key, direction) in [(Key::W, forward), (Key::S, -forward), (Key::D, right), (Key::A, -right)] {
+            if window.is_key_down(key) {
+                self.target += direction * step;
+                moved = true
+            }
+        }
+
+        moved
+    }
+}
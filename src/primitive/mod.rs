@@ -1,11 +1,13 @@
-use crate::{ space::*, material::Material, interaction::RayIntersection };
+use std::sync::Arc;
+use crate::{ space::*, material::MaterialRef, interaction::RayIntersection };
 
 /// A primitive is a 3D shape placed in the scene. All primitives can intersect
 /// with a Ray defined by an origin point and (d)irection vector.
 ///
 /// The returned material reference must have at least the same lifetime as the
-/// Scene and the primitive to which it belongs.
-pub trait Primitive {
+/// Scene and the primitive to which it belongs. Primitives are shared between
+/// render threads, so must be thread-safe.
+pub trait Primitive: Send + Sync {
     /// Object-level bounds for this primitive
     fn bound(&self) -> Bounds;
 
@@ -23,10 +25,11 @@ pub trait Primitive {
     ///    surface, if applicable.
     fn intersect(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive;
 
-    /// Get a dynamic reference to the material this primitive uses. If None,
-    /// clients should use some pre-defined default material (such as the global
-    /// material on the parent Triangle mesh).
-    fn material(&self) -> Option<Material> { None }
+    /// Get a reference to the material this primitive uses, to be looked up
+    /// in the scene with `Scene::material`. If None, clients should use some
+    /// pre-defined default material (such as the global material on the
+    /// parent Triangle mesh).
+    fn material(&self) -> Option<MaterialRef> { None }
 
     /// Whether an intersection with the given ray exists. Default
     /// implementation calls `intersect`. Available so that more efficient
@@ -35,6 +38,32 @@ pub trait Primitive {
     fn intersects(&self, ray: &Ray) -> bool {
         self.intersect(ray, &mut RayIntersection::default()).is_some()
     }
+
+    /// Surface area in object space. Primitives that report a non-zero area
+    /// may be sampled as area lights when given an emissive material.
+    fn area(&self) -> f64 { 0.0 }
+
+    /// Choose a point on the surface, uniformly distributed by area, for the
+    /// given sample point u in [0, 1)^2. Returns the point and the surface
+    /// normal there, or None for primitives that don't support sampling.
+    fn sample_area(&self, _u: &Point2f) -> Option<(Point, Vector)> { None }
 }
 
 pub type OptionalPrimitive<'a> = Option<&'a dyn Primitive>;
+
+/// Primitives shared with other parts of the scene hierarchy
+impl<P: Primitive + ?Sized> Primitive for Arc<P> {
+    fn bound(&self) -> Bounds { (**self).bound() }
+
+    fn intersect(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive<'_> {
+        (**self).intersect(ray, isect)
+    }
+
+    fn material(&self) -> Option<MaterialRef> { (**self).material() }
+
+    fn intersects(&self, ray: &Ray) -> bool { (**self).intersects(ray) }
+
+    fn area(&self) -> f64 { (**self).area() }
+
+    fn sample_area(&self, u: &Point2f) -> Option<(Point, Vector)> { (**self).sample_area(u) }
+}
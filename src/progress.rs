@@ -0,0 +1,143 @@
+//! Progress reporting and cancellation for long-running renders.
+
+use std::{sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}}, time::{Duration, Instant}};
+use crate::{film::Tile, img::Pixel, tonemap::Tonemap};
+
+/// Receives updates as a render progresses, such as to draw a progress bar or
+/// stream tiles into a preview window. Tiles render in parallel, so methods
+/// may be called from any of the render threads, in any order.
+pub trait RenderProgress: Sync {
+    /// Called as each tile of the image finishes with the bounds of the tile
+    /// and its pixels in row-major order, tonemapped as for a Film.
+    fn on_tile_complete(&self, _bounds: TileBounds, _pixels: &[Pixel]) {}
+
+    /// Called after each tile with the progress of the whole render
+    fn on_progress(&self, _progress: &Progress) {}
+}
+
+/// Region of the image covered by a tile, in pixels
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TileBounds {
+    /// Top-left corner of the tile in the image
+    pub x: u32,
+    pub y: u32,
+
+    /// Dimensions of the tile
+    pub w: u32,
+    pub h: u32
+}
+
+/// Snapshot of how far a render has come
+#[derive(Debug, Copy, Clone)]
+pub struct Progress {
+    /// Number of tiles finished so far
+    pub tiles_done: usize,
+
+    /// Number of tiles in the image
+    pub tiles: usize,
+
+    /// Time since the render started
+    pub elapsed: Duration
+}
+
+impl Progress {
+    /// Percentage of the image finished so far, from 0 to 100
+    pub fn percent(&self) -> f64 {
+        if self.tiles == 0 { return 100.0 }
+        100.0 * self.tiles_done as f64 / self.tiles as f64
+    }
+
+    /// Estimated time until the render finishes, assuming the remaining tiles
+    /// take as long as the finished ones did on average. None until the first
+    /// tile is done.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.tiles_done == 0 { return None }
+        let remaining = (self.tiles - self.tiles_done) as f64 / self.tiles_done as f64;
+        Some(self.elapsed.mul_f64(remaining))
+    }
+}
+
+/// Token for stopping a render early, such as when the user aborts it from a
+/// GUI. Clones share the same state, so one may be kept to cancel the render
+/// while another is passed to `capture_cancelable` or
+/// `capture_subset_cancelable`, possibly on another thread. Renders check the
+/// token between tiles or scanlines, so they stop shortly after, not at once.
+#[derive(Debug, Clone, Default)]
+pub struct RenderHandle {
+    canceled: Arc<AtomicBool>
+}
+
+impl RenderHandle {
+    pub fn new() -> RenderHandle {
+        RenderHandle::default()
+    }
+
+    /// Ask renders using this handle to stop
+    pub fn cancel(&self) {
+        self.canceled.store(true, Ordering::Relaxed)
+    }
+
+    /// Whether the handle has been canceled
+    #[inline]
+    pub fn is_canceled(&self) -> bool {
+        self.canceled.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts finished tiles of a render and reports them to its listener
+pub(crate) struct ProgressTracker<'p> {
+    listener: &'p dyn RenderProgress,
+    tonemap: Tonemap,
+    tiles: usize,
+    done: AtomicUsize,
+    start: Instant
+}
+
+impl<'p> ProgressTracker<'p> {
+    pub fn new(listener: &'p dyn RenderProgress, tonemap: Tonemap, tiles: usize) -> ProgressTracker<'p> {
+        ProgressTracker { listener, tonemap, tiles, done: AtomicUsize::new(0), start: Instant::now() }
+    }
+
+    /// Report the given tile as finished
+    pub fn tile_complete(&self, tile: &Tile) {
+        self.listener.on_tile_complete(tile.bounds(), &tile.pixels(&self.tonemap));
+
+        let tiles_done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        let elapsed = self.start.elapsed();
+        self.listener.on_progress(&Progress { tiles_done, tiles: self.tiles, elapsed })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn estimates_remaining_time() {
+        let progress = Progress { tiles_done: 0, tiles: 8, elapsed: Duration::from_secs(1) };
+        assert_eq!(progress.percent(), 0.0);
+        assert_eq!(progress.eta(), None);
+
+        let progress = Progress { tiles_done: 2, tiles: 8, elapsed: Duration::from_secs(4) };
+        assert_eq!(progress.percent(), 25.0);
+        assert_eq!(progress.eta(), Some(Duration::from_secs(12)));
+    }
+
+    #[test]
+    fn canceled_renders_stop() {
+        use crate::{scene::Scene, film::HdrFilm, Accel, capture_subset_cancelable};
+        let mut scene = Scene::new();
+        scene.set_solid_background([1.0, 1.0, 1.0]);
+        let root = Accel::from(&scene);
+
+        let handle = RenderHandle::new();
+        let mut film = HdrFilm::new(4, 4);
+        assert!(capture_subset_cancelable(0, 1, &root, &mut film, &handle.clone()));
+        assert_eq!(film.get(3, 3), [1.0, 1.0, 1.0]);
+
+        handle.cancel();
+        let mut film = HdrFilm::new(4, 4);
+        assert!(!capture_subset_cancelable(0, 1, &root, &mut film, &handle));
+        assert_eq!(film.get(0, 0), [0.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,462 @@
+//! Sample points for the Monte-Carlo estimates of each pixel. Every sample of
+//! a pixel consumes a sequence of dimensions, e.g., two for the position
+//! within the pixel, two for the point on the lens, one for the time and two
+//! for each light or BSDF sample after that. Samplers that spread each
+//! dimension evenly over the samples of a pixel converge with fewer samples
+//! than independent random numbers.
+//!
+//! http://www.pbr-book.org/3ed-2018/Sampling_and_Reconstruction/Sampling_Interface.html
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+use crate::space::{Point2f, Color, luminance};
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON;
+
+/// Bases of the radical inverse for each dimension of Halton samples. Further
+/// dimensions get independent random numbers.
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311
+];
+
+/// Strategy for placing the samples of each pixel, selected with
+/// `Scene::set_sampler`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SamplerKind {
+    /// Pixel samples evenly spread over the pixel, at the centres of a square
+    /// grid for square sample counts or on a lattice with one sample in each
+    /// row and column otherwise, and independent random numbers for
+    /// everything else. The default
+    #[default]
+    Uniform,
+
+    /// Jittered samples, one in each cell of a grid over every pair of
+    /// dimensions. Pixel sample counts that aren't square stratify each axis
+    /// on its own instead
+    Stratified,
+
+    /// Halton low-discrepancy sequence, randomly shifted for each pixel
+    Halton,
+
+    /// Sobol (0, 2)-sequence over each pair of dimensions, randomly scrambled
+    /// for each pixel. Best with a power-of-two number of samples per pixel
+    Sobol
+}
+
+/// Pixels are never considered converged with fewer samples than this, since
+/// their variance estimate is unreliable
+const ADAPTIVE_MIN_SAMPLES: u32 = 4;
+
+/// Pixels darker than this luminance have their noise measured relative to it
+/// instead, so that near-black pixels don't keep sampling to resolve
+/// imperceptible noise
+const ADAPTIVE_MIN_LUMINANCE: f64 = 0.01;
+
+/// Settings for adaptive sampling, which keeps taking samples only for pixels
+/// that are still noisy. Set with `Scene::set_adaptive_sampling`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveSampling {
+    /// Largest acceptable standard error of the mean luminance of a pixel,
+    /// relative to that luminance, e.g., 0.01 for 1% noise
+    pub threshold: f64,
+
+    /// Most samples to take for any pixel
+    pub max_samples: u32
+}
+
+/// Running mean and variance of the luminance of the samples of a pixel
+///
+/// https://en.wikipedia.org/wiki/Algorithms_for_calculating_variance#Welford's_online_algorithm
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PixelVariance {
+    n: u32,
+    mean: f64,
+    m2: f64
+}
+
+impl PixelVariance {
+    pub fn add(&mut self, c: &Color) {
+        let y = luminance(c);
+        self.n += 1;
+        let delta = y - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (y - self.mean);
+    }
+
+    /// Whether the estimated error of the pixel is within the threshold of
+    /// the given settings
+    pub fn converged(&self, adaptive: &AdaptiveSampling) -> bool {
+        if self.n < ADAPTIVE_MIN_SAMPLES { return false }
+        let n = self.n as f64;
+        let std_error = (self.m2 / (n - 1.) / n).sqrt();
+        std_error <= adaptive.threshold * self.mean.max(ADAPTIVE_MIN_LUMINANCE)
+    }
+}
+
+/// Generates the sample points for the pixels of a render, one pixel at a
+/// time. Points for a given pixel and sample index are the same regardless of
+/// the order in which pixels are rendered.
+pub struct Sampler {
+    kind: SamplerKind,
+
+    /// Number of samples taken for each pixel, over which dimensions are
+    /// stratified
+    spp: u32,
+
+    /// Whether the uniform sampler places pixel samples at the centres of a
+    /// grid, rather than at random
+    grid: bool,
+
+    /// Step between the rows of successive pixel samples on the lattice used
+    /// by the uniform sampler for counts that aren't square
+    lattice: u32,
+
+    /// Seed of the current pixel, from which per-pixel randomization derives
+    seed: u64,
+
+    /// Index of the current sample within the pixel
+    index: u32,
+
+    /// Next dimension to be consumed by the current sample
+    dimension: u32,
+
+    /// Source of independent random numbers for the current sample
+    rng: SmallRng
+}
+
+impl Sampler {
+    /// Sampler for spp samples per pixel
+    pub fn new(kind: SamplerKind, spp: u32) -> Sampler {
+        debug_assert!(spp > 0);
+        let lattice = lattice_generator(spp);
+        Sampler { kind, spp, grid: true, lattice, seed: 0, index: 0, dimension: 0, rng: SmallRng::seed_from_u64(0) }
+    }
+
+    /// Sampler for passes of a progressive render that take spp samples per
+    /// pixel each. Uniform pixel samples are jittered so that each pass adds
+    /// new information.
+    pub fn progressive(kind: SamplerKind, spp: u32) -> Sampler {
+        Sampler { grid: false, ..Sampler::new(kind, spp) }
+    }
+
+    /// Number of samples per pixel
+    #[inline]
+    pub fn samples_per_pixel(&self) -> u32 { self.spp }
+
+    /// Begin sampling the pixel with the given seed, e.g., its index in the
+    /// image
+    pub fn start_pixel(&mut self, seed: u64) {
+        self.seed = mix_bits(seed);
+        self.start_sample(0)
+    }
+
+    /// Begin the sample of the current pixel with the given index. Indices
+    /// past the number of samples per pixel continue the sequence, e.g., for
+    /// subsequent passes of a progressive render
+    pub fn start_sample(&mut self, index: u32) {
+        self.index = index;
+        self.dimension = 0;
+        self.rng = SmallRng::seed_from_u64(self.seed ^ mix_bits(index as u64 + 1));
+    }
+
+    /// Position of the current sample within the pixel, from (0, 0) at its
+    /// bottom-left corner to (1, 1) at its top-right. Must be the first
+    /// dimensions consumed by each sample.
+    pub fn get_pixel_2d(&mut self) -> Point2f {
+        debug_assert!(self.dimension == 0);
+        match self.kind {
+            SamplerKind::Uniform if self.grid => {
+                self.dimension += 2;
+                let root = (self.spp as f64).sqrt() as u32;
+                if root * root != self.spp {
+                    let i = self.index % self.spp;
+                    let row = (i as u64 * self.lattice as u64) % self.spp as u64;
+                    let n = self.spp as f64;
+                    return Point2f::new((i as f64 + 0.5) / n, (row as f64 + 0.5) / n)
+                }
+                let i = self.index % (root * root);
+                let distance = 1. / root as f64;
+                Point2f::new(((i / root) as f64 + 0.5) * distance, ((i % root) as f64 + 0.5) * distance)
+            },
+            _ => self.get_2d()
+        }
+    }
+
+    /// Next dimension of the current sample, in [0, 1)
+    pub fn get_1d(&mut self) -> f64 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        match self.kind {
+            SamplerKind::Uniform => self.rng.gen(),
+            SamplerKind::Stratified => {
+                let i = self.stratum(dimension);
+                (i as f64 + self.rng.gen::<f64>()) / self.spp as f64
+            },
+            SamplerKind::Halton => self.halton(dimension),
+            SamplerKind::Sobol => {
+                let i = self.shuffled(dimension);
+                van_der_corput(i, self.scramble(dimension))
+            }
+        }
+    }
+
+    /// Next two dimensions of the current sample, each in [0, 1)
+    pub fn get_2d(&mut self) -> Point2f {
+        let dimension = self.dimension;
+        match self.kind {
+            SamplerKind::Stratified => {
+                self.dimension += 2;
+                let root = (self.spp as f64).sqrt() as u32;
+                if root * root == self.spp {
+                    // One sample in each cell of a root x root grid
+                    let i = self.stratum(dimension);
+                    let jitter = (self.rng.gen::<f64>(), self.rng.gen::<f64>());
+                    Point2f::new(
+                        ((i % root) as f64 + jitter.0) / root as f64,
+                        ((i / root) as f64 + jitter.1) / root as f64)
+                } else {
+                    // Latin hypercube; one sample in each row and column
+                    let (x, y) = (self.stratum(dimension), self.stratum(dimension + 1));
+                    let n = self.spp as f64;
+                    Point2f::new((x as f64 + self.rng.gen::<f64>()) / n, (y as f64 + self.rng.gen::<f64>()) / n)
+                }
+            },
+            SamplerKind::Sobol => {
+                self.dimension += 2;
+                let i = self.shuffled(dimension);
+                Point2f::new(
+                    van_der_corput(i, self.scramble(dimension)),
+                    sobol_2(i, self.scramble(dimension + 1)))
+            },
+            _ => Point2f::new(self.get_1d(), self.get_1d())
+        }
+    }
+
+    /// Stratum of the current sample in the given dimension, one of as many
+    /// as there are samples per pixel. The order of strata is randomly
+    /// permuted for each pixel, dimension and group of samples per pixel.
+    fn stratum(&self, dimension: u32) -> u32 {
+        let group = self.index / self.spp;
+        let seed = self.scramble(dimension) ^ mix_bits(group as u64) as u32;
+        permutation_element(self.index % self.spp, self.spp, seed)
+    }
+
+    /// Radical inverse of the sample index in the base of the given
+    /// dimension, with its digits randomly permuted for the pixel
+    fn halton(&mut self, dimension: u32) -> f64 {
+        if dimension as usize >= PRIMES.len() { return self.rng.gen() }
+        radical_inverse(PRIMES[dimension as usize], self.index as u64, self.scramble(dimension))
+    }
+
+    /// Index into the (0, 2)-sequence for the current sample in the given
+    /// dimension. Permuted within each group of samples per pixel so that
+    /// dimensions are not correlated with each other.
+    fn shuffled(&self, dimension: u32) -> u32 {
+        let n = self.spp.next_power_of_two();
+        let base = self.index - self.index % n;
+        base + permutation_element(self.index % n, n, self.scramble(dimension / 2 * 2) ^ 0x5bd1e995)
+    }
+
+    /// Random bits for the given dimension of the current pixel
+    #[inline]
+    fn scramble(&self, dimension: u32) -> u32 {
+        (mix_bits(self.seed ^ ((dimension as u64 + 1) << 32)) >> 32) as u32
+    }
+}
+
+/// Digits of i in the given base, mirrored about the decimal point. Each digit,
+/// including the infinitely many leading zeros, is mapped through the same
+/// random permutation chosen by the seed.
+/// Generator of the rank-1 lattice of n points that places point i in row
+/// i * g mod n: the number coprime to n nearest above n divided by the golden
+/// ratio, which spreads the rows of successive points evenly (a Fibonacci
+/// lattice)
+fn lattice_generator(n: u32) -> u32 {
+    let gcd = |mut a: u32, mut b: u32| {
+        while b != 0 { (a, b) = (b, a % b) }
+        a
+    };
+    let golden = (1. + 5f64.sqrt()) / 2.;
+    let mut g = ((n as f64 / golden).round() as u32).max(1);
+    while gcd(g, n) != 1 { g += 1 }
+    g
+}
+
+fn radical_inverse(base: u32, mut i: u64, seed: u32) -> f64 {
+    let inv_base = 1. / base as f64;
+    let permute = |digit: u64| permutation_element(digit as u32, base, seed) as u64;
+    let base = base as u64;
+    let (mut reversed, mut inv_base_n) = (0u64, 1.);
+    while i > 0 {
+        let next = i / base;
+        reversed = reversed * base + permute(i - next * base);
+        inv_base_n *= inv_base;
+        i = next;
+    }
+
+    // Permuted zeros form a geometric series
+    let zeros = inv_base * permute(0) as f64 / (1. - inv_base);
+    (inv_base_n * (reversed as f64 + zeros)).min(ONE_MINUS_EPSILON)
+}
+
+/// First dimension of the Sobol sequence, Owen-scrambled by the seed
+#[inline]
+fn van_der_corput(i: u32, seed: u32) -> f64 {
+    to_unit(owen_scramble(i.reverse_bits(), seed))
+}
+
+/// Second dimension of the Sobol sequence, Owen-scrambled by the seed
+#[inline]
+fn sobol_2(mut i: u32, seed: u32) -> f64 {
+    let (mut v, mut r) = (1u32 << 31, 0);
+    while i != 0 {
+        if i & 1 != 0 { r ^= v }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    to_unit(owen_scramble(r, seed))
+}
+
+/// Randomly flip each bit of v, depending on the bits above it. Points of a
+/// (0, 2)-sequence remain stratified, but neighbouring points are no longer
+/// mirror images of each other as with a plain XOR.
+///
+/// https://psychopath.io/post/2021_01_30_building_a_better_lk_hash
+#[inline]
+fn owen_scramble(v: u32, seed: u32) -> u32 {
+    let mut v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x05526c56);
+    v ^= v.wrapping_mul(0x53a22864);
+    v.reverse_bits()
+}
+
+/// Fixed-point fraction to [0, 1)
+#[inline]
+fn to_unit(v: u32) -> f64 {
+    (v as f64 / 4294967296.0).min(ONE_MINUS_EPSILON)
+}
+
+/// Element i of a random permutation of [0, n) chosen by the seed, without
+/// storing the permutation
+///
+/// https://graphics.pixar.com/library/MultiJitteredSampling/
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n { break }
+    }
+    (i + seed) % n
+}
+
+/// Scatter the bits of v, such that nearby inputs give unrelated outputs
+#[inline]
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Samples of the given sampler for a single pixel in a single 2D
+    /// dimension after the pixel position
+    fn samples(kind: SamplerKind, spp: u32) -> Vec<Point2f> {
+        let mut sampler = Sampler::new(kind, spp);
+        sampler.start_pixel(7);
+        (0..spp).map(|i| {
+            sampler.start_sample(i);
+            sampler.get_pixel_2d();
+            sampler.get_2d()
+        }).collect()
+    }
+
+    #[test]
+    fn stratifies_pixel_samples() {
+        for &kind in [SamplerKind::Stratified, SamplerKind::Sobol].iter() {
+            let points = samples(kind, 16);
+            let mut cells = [false; 16];
+            for p in points.iter() {
+                assert!(p.x >= 0. && p.x < 1. && p.y >= 0. && p.y < 1.);
+                cells[(p.x * 4.) as usize + 4 * (p.y * 4.) as usize] = true;
+            }
+            assert!(cells.iter().all(|&c| c), "{:?} leaves a stratum empty", kind);
+        }
+    }
+
+    #[test]
+    fn spreads_uneven_sample_counts() {
+        // Uniform samples fill every row and column of the pixel
+        for spp in [2, 3, 6, 24] {
+            let mut sampler = Sampler::new(SamplerKind::Uniform, spp);
+            sampler.start_pixel(0);
+            let (mut rows, mut columns) = (vec![false; spp as usize], vec![false; spp as usize]);
+            for i in 0..spp {
+                sampler.start_sample(i);
+                let p = sampler.get_pixel_2d();
+                columns[(p.x * spp as f64) as usize] = true;
+                rows[(p.y * spp as f64) as usize] = true;
+            }
+            assert!(rows.iter().chain(columns.iter()).all(|&c| c), "{} samples leave a row or column empty", spp);
+        }
+    }
+
+    #[test]
+    fn halton_is_evenly_spread() {
+        let points = samples(SamplerKind::Halton, 64);
+        let mean = points.iter().fold(0., |sum, p| sum + p.x + p.y) / 128.;
+        assert!((mean - 0.5).abs() < 0.02);
+        assert!(points.iter().all(|p| p.x >= 0. && p.x < 1. && p.y >= 0. && p.y < 1.));
+    }
+
+    #[test]
+    fn converges_with_low_variance() {
+        let adaptive = AdaptiveSampling { threshold: 0.05, max_samples: 64 };
+        let (mut flat, mut noisy) = (PixelVariance::default(), PixelVariance::default());
+        for i in 0..8 {
+            flat.add(&Color::new(0.5, 0.5, 0.5));
+            let y = if i % 2 == 0 { 0.0 } else { 1.0 };
+            noisy.add(&Color::new(y, y, y));
+            assert_eq!(flat.converged(&adaptive), i >= 3);
+        }
+        assert!(!noisy.converged(&adaptive));
+    }
+
+    #[test]
+    fn permutes_every_element() {
+        let mut seen = [false; 13];
+        for i in 0..13 { seen[permutation_element(i, 13, 0xdeadbeef) as usize] = true }
+        assert!(seen.iter().all(|&s| s));
+    }
+}
@@ -1,11 +1,23 @@
-use std::{f64, path::Path};
+use std::{f64, path::Path, collections::HashMap, sync::Arc};
 
 use obj::Obj;
+use cgmath::Matrix4;
 use crate::space::*;
 use crate::camera::Camera;
-use crate::light::{Light, point::PointLight};
-use crate::material::Background;
+use crate::light::{Light, LightVisibility, PointLight, LightPower, DirectionalLight, EnvironmentMap, EnvironmentLight, Portal};
+use crate::material::{Material, MaterialRef, Background};
+use crate::integrate::Integrator;
+use crate::tonemap::{Tonemap, Operator};
+use crate::texture::{Texture, TextureRef, Param, Checkerboard, Noise, NoisePattern};
 use crate::shape::triangle::*;
+use crate::shape::mesh::{TriangleMesh, Smoothing};
+use crate::shape::displace::displace;
+use crate::shape::subdiv::SubdivMesh;
+use crate::animation::Track;
+use crate::accelerators::{AcceleratorKind, BvhBuildStrategy, mesh::MeshCache};
+use crate::sampler::{SamplerKind, AdaptiveSampling};
+use crate::filter::Filter;
+use crate::medium::{Medium, HomogeneousMedium, GridMedium};
 
 /// Description of the world to render and how it should be rendered
 pub struct Scene {
@@ -22,27 +34,141 @@ pub struct Scene {
     /// Ambient lighting
     pub ambient: Color,
 
-    /// Enable normal smoothing for triangle meshes that support it
+    /// Whether triangle meshes added to the scene interpolate their normals.
+    /// Override it for individual meshes with `set_obj_smoothing`.
     pub smoothing: bool,
 
     /// Maximum depth of ray recursion, defaults to 3
     pub recursion: u32,
 
+    /// Number of bounces after which path tracing may cut paths short with
+    /// Russian roulette, defaults to 3
+    pub roulette_depth: u32,
+
+    /// Light transport algorithm used to render the scene, defaults to Whitted
+    pub integrator: Integrator,
+
+    /// Conversion from radiance to pixel colours for films created by the
+    /// render functions. Defaults to a hard clamp
+    pub tonemap: Tonemap,
+
     /// Number of parallel render threads, if applicable. Zero means use as many
-    /// threads as the system allows (bin feature required)
+    /// threads as the system allows (threads feature required)
     pub threads: usize,
 
+    /// Structure used to speed up ray intersection within groups and meshes,
+    /// defaults to a BVH
+    pub accelerator: AcceleratorKind,
+
+    /// How bounding volume hierarchies are built, defaults to HLBVH
+    pub bvh_build: BvhBuildStrategy,
+
+    /// Whether groups that only place their contents are flattened into the
+    /// hierarchy of their parent, with the transformations of the groups
+    /// composed once when it's built. Rays then skip the transformation into
+    /// each nested group, at the cost of one per primitive of the flattened
+    /// groups. Helps scenes of deeply nested groups with few primitives each.
+    /// Defaults to false
+    pub flatten_transforms: bool,
+
+    /// How the sample points of each pixel are placed, for the camera, lights
+    /// and BSDFs. Defaults to a uniform grid of camera samples
+    pub sampler: SamplerKind,
+
+    /// How samples are weighed into the pixels around them by `capture` and
+    /// the render functions. Defaults to a box over each pixel alone.
+    /// Progressive and subset renders always use the default.
+    pub filter: Filter,
+
+    /// Whether noisy pixels take more samples than set by the camera, and how
+    /// many. Defaults to None, i.e., every pixel takes the same number
+    pub adaptive: Option<AdaptiveSampling>,
+
+    /// Largest radiance that indirect light may contribute to a sample, if
+    /// any. Defaults to None, i.e., no clamping
+    pub max_radiance: Option<f64>,
+
+    /// Participating medium that fills the scene, such as fog or smoke.
+    /// Defaults to None, i.e., light travels through empty space
+    pub medium: Option<Medium>,
+
+    /// Distance within which surfaces occlude each other when rendering with
+    /// the ambient occlusion integrator, if limited. Defaults to None, i.e.,
+    /// surfaces at any distance occlude
+    pub ao_distance: Option<f64>,
+
+    /// Rays cast to gather the indirect light at each record of the
+    /// irradiance cache, defaults to 256
+    pub irradiance_samples: u32,
+
+    /// Largest error allowed when interpolating records of the irradiance
+    /// cache, defaults to 0.2
+    pub irradiance_error: f64,
+
+    /// Whether camera samples carry light of sampled wavelengths rather than
+    /// RGB, so that dispersive glass splits light into its colours. Defaults
+    /// to false
+    pub spectral: bool,
+
+    /// Number of point lights picked from the scene's light hierarchy to
+    /// light each hit, if any. Defaults to None, i.e., every light is added
+    /// up at every hit
+    pub light_samples: Option<usize>,
+
+    /// Whether `output::render` removes noise from the finished image.
+    /// Defaults to false
+    #[cfg(feature = "denoise")]
+    pub denoise: bool,
+
     // Point-light sources in the scene (more formats to come)
     lights: Vec<Box<dyn Light>>,
 
+    /// Parts of the render each light takes part in, in the same order
+    light_visibility: Vec<LightVisibility>,
+
+    /// Keyed intensities of lights over the frames of an animation
+    light_animation: Vec<(LightRef, Track<Color>)>,
+
+    /// Light of the environment map, if set, which setting another replaces
+    environment_light: Option<LightRef>,
+
+    /// Openings through which the environment map lights interiors
+    portals: Vec<Portal>,
+
     /// Available triangle mesh instances
-    meshes: Vec<Obj>,
+    meshes: Vec<Mesh>,
+
+    /// Textures referenced by materials in the scene
+    textures: Vec<Box<dyn Texture>>,
+
+    /// Materials referenced by other materials in the scene
+    materials: Vec<Material>,
+
+    /// Original mesh of each mesh added by `displace_meshes`
+    displaced: HashMap<ObjRef, ObjRef>,
+
+    /// Hierarchies of the triangles of the meshes, built along with the first
+    /// acceleration structure that uses them
+    mesh_cache: MeshCache,
 }
 
-/// Opaque reference to a .obj-powered file mesh in a scene
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Longest edge of a displaced mesh on the image, in pixels
+const MAX_EDGE_PIXELS: f64 = 2.0;
+
+/// Opaque reference to a triangle mesh in a scene
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct ObjRef(usize);
 
+/// Path from the root of a scene to one of its nodes, as found by
+/// `Scene::find_node`. Stays valid as long as the groups along it keep their
+/// contents in place.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeRef(Vec<usize>);
+
+/// Opaque reference to a light in a scene
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LightRef(usize);
+
 /// User-configurable description of the scene to render, passed to the scene
 /// contructor.
 
@@ -55,9 +181,35 @@ impl Scene {
             ambient: Color::new(0., 0., 0.),
             smoothing: true,
             recursion: 3,
+            integrator: Integrator::Whitted,
+            roulette_depth: 3,
+            tonemap: Tonemap::default(),
             threads: 0,
+            accelerator: AcceleratorKind::Bvh,
+            bvh_build: BvhBuildStrategy::Hlbvh,
+            flatten_transforms: false,
+            sampler: SamplerKind::Uniform,
+            filter: Filter::default(),
+            adaptive: None,
+            max_radiance: None,
+            medium: None,
+            ao_distance: None,
+            irradiance_samples: 256,
+            irradiance_error: 0.2,
+            spectral: false,
+            light_samples: None,
+            #[cfg(feature = "denoise")]
+            denoise: false,
             lights: vec![],
+            light_visibility: vec![],
+            light_animation: vec![],
+            environment_light: None,
+            portals: vec![],
             meshes: vec![],
+            textures: vec![],
+            materials: vec![Material::default()],
+            displaced: HashMap::new(),
+            mesh_cache: MeshCache::default(),
         }
     }
 
@@ -84,6 +236,45 @@ impl Scene {
         self.background = Background::radial(inner.into(), outer.into(), scale)
     }
 
+    /// Surround the scene with the given environment map, which is shown in
+    /// the background and also lights the scene. Each shading point traces the
+    /// given number of shadow rays toward bright parts of the map; use more
+    /// for smoother lighting at the cost of render time. Replaces any map set
+    /// before, both in the background and as a light.
+    pub fn set_environment_map(&mut self, map: EnvironmentMap, samples: usize) {
+        let map = Arc::new(map);
+        self.background = Background::Environment(map.clone());
+        let light = Box::new(EnvironmentLight::new(map, samples));
+        match self.environment_light {
+            Some(LightRef(index)) => self.lights[index] = light,
+            None => self.environment_light = Some(self.add_light(light))
+        }
+    }
+
+    /// Mark a rectangular opening, such as a window, through which the
+    /// environment map lights an interior. Takes the same centre, normal and
+    /// size as `Aggregate::add_plane`, with the normal facing the interior.
+    /// Shading points on that side of any portal sample the map only through
+    /// the portals, which finds the light of the sky much more often than
+    /// sampling the whole map when most of it is hidden by walls. The
+    /// portals should cover every opening to the outside, since light from
+    /// others is left out.
+    pub fn add_light_portal(&mut self, center: [f64; 3], normal: [f64; 3], size: [f64; 2]) {
+        self.portals.push(Portal::new(center, normal, size))
+    }
+
+    /// Light portals added to the scene, in order
+    pub(crate) fn portals(&self) -> &[Portal] { &self.portals }
+
+    /// Load the OpenEXR environment map at the given file-system path and
+    /// surround the scene with it. See `set_environment_map`.
+    #[cfg(feature = "bin")]
+    pub fn load_environment_map(&mut self, path: &Path, samples: usize) -> Result<(), exr::error::Error> {
+        let map = EnvironmentMap::open(path)?;
+        self.set_environment_map(map, samples);
+        Ok(())
+    }
+
     pub fn set_ambient_light(&mut self, color: [f64; 3]) {
         self.ambient = color.into()
     }
@@ -92,41 +283,530 @@ impl Scene {
         self.smoothing = enabled
     }
 
+    /// Choose how the given mesh finds its shading normals, such as
+    /// generating smooth normals for a mesh that has none. Panics if the
+    /// reference was created by a different scene.
+    pub fn set_obj_smoothing(&mut self, obj: ObjRef, smoothing: Smoothing) {
+        self.meshes[obj.0].set_smoothing(smoothing)
+    }
+
     pub fn set_max_recursion_depth(&mut self, max_depth: u32) {
         self.recursion = max_depth
     }
 
+    /// Let path tracing cut paths short with Russian roulette from the given
+    /// number of bounces on, with a chance that grows as less light is left
+    /// to carry. Together with a high recursion depth, deep paths such as
+    /// through stacks of glass keep their light without tracing every path
+    /// to the end. Lower depths render faster but noisier.
+    pub fn set_roulette_depth(&mut self, depth: u32) {
+        self.roulette_depth = depth
+    }
+
+    /// Select the light transport algorithm. Path tracing is considerably
+    /// slower but also captures indirect illumination; use more samples per
+    /// pixel to reduce noise.
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        self.integrator = integrator
+    }
+
+    /// Limit how far apart surfaces may be to occlude each other with the
+    /// ambient occlusion integrator. Shorter distances only darken creases
+    /// and contact points.
+    pub fn set_ao_distance(&mut self, distance: f64) {
+        debug_assert!(distance > 0.);
+        self.ao_distance = Some(distance)
+    }
+
+    /// Set up the irradiance cache of the irradiance integrator, which casts
+    /// the given number of rays to gather the indirect light at each record
+    /// and reuses records where the estimated error is below the given one.
+    /// More rays give smoother indirect light; smaller errors place records
+    /// closer together, which keeps detail such as in corners but takes
+    /// longer to fill the cache.
+    pub fn set_irradiance_cache(&mut self, samples: u32, error: f64) {
+        debug_assert!(samples > 0 && error > 0.);
+        self.irradiance_samples = samples;
+        self.irradiance_error = error
+    }
+
+    /// Render with light of sampled wavelengths, such that glass with
+    /// dispersion splits light into rainbows. Scenes without dispersive
+    /// materials look the same as in RGB but take more samples per pixel to
+    /// converge, as each sample only carries a few wavelengths. Has no effect
+    /// on ambient occlusion.
+    pub fn set_spectral(&mut self, spectral: bool) {
+        self.spectral = spectral
+    }
+
+    /// Light each hit with the given number of point lights, picked at random
+    /// with nearby and bright lights more likely, rather than with all of
+    /// them. Speeds up scenes with hundreds or thousands of point lights at
+    /// the cost of some noise, which more samples per pixel smooth out.
+    /// Scenes with no more point lights than that still add up all of them.
+    /// Point lights kept out of some parts of the render with
+    /// `set_light_visibility` are always added up.
+    pub fn set_light_samples(&mut self, samples: usize) {
+        debug_assert!(samples > 0);
+        self.light_samples = Some(samples)
+    }
+
+    /// Remove noise from images rendered with `output::render`, guided by the
+    /// albedo and normals of the surfaces in each pixel. Useful for previews
+    /// with few samples; fine details may be smoothed away.
+    #[cfg(feature = "denoise")]
+    pub fn set_denoise(&mut self, enabled: bool) {
+        self.denoise = enabled
+    }
+
+    /// Select the curve that compresses bright radiance into displayable range
+    pub fn set_tonemap(&mut self, operator: Operator) {
+        self.tonemap.operator = operator
+    }
+
+    /// Brighten (positive) or darken (negative) the image by the given number
+    /// of stops
+    pub fn set_exposure(&mut self, stops: f64) {
+        self.tonemap.exposure = stops
+    }
+
+    /// Display gamma for output images. Use 2.2 for typical displays
+    pub fn set_gamma(&mut self, gamma: f64) {
+        debug_assert!(gamma > 0.);
+        self.tonemap.gamma = gamma
+    }
+
     pub fn set_threads(&mut self, threads: usize) {
         self.threads = threads
     }
 
-    pub fn add_point_light(&mut self, position: [f64; 3], intensity: [f64; 3], falloff: [f64; 3]) {
+    pub fn set_accelerator(&mut self, accelerator: AcceleratorKind) {
+        self.accelerator = accelerator
+    }
+
+    pub fn set_bvh_build_strategy(&mut self, strategy: BvhBuildStrategy) {
+        self.bvh_build = strategy
+    }
+
+    /// Flatten groups into the hierarchies of their parents. See
+    /// `flatten_transforms`.
+    pub fn set_flatten_transforms(&mut self, enabled: bool) {
+        self.flatten_transforms = enabled
+    }
+
+    /// Place the samples of each pixel with the given strategy. The number of
+    /// samples per pixel is still set by the camera supersampling.
+    pub fn set_sampler(&mut self, sampler: SamplerKind) {
+        self.sampler = sampler
+    }
+
+    /// Reconstruct pixels from the samples around them with the given filter,
+    /// e.g., `Filter::mitchell()` for sharper antialiasing than the default
+    /// box at the same number of samples
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter
+    }
+
+    /// Keep sampling pixels whose noise exceeds the given threshold, up to
+    /// the given number of samples. The camera supersampling sets how many
+    /// samples are taken at a time; converged pixels stop early. The
+    /// threshold is the standard error relative to the brightness of the
+    /// pixel, e.g., 0.01 for 1% noise.
+    pub fn set_adaptive_sampling(&mut self, threshold: f64, max_samples: u32) {
+        debug_assert!(threshold > 0.);
+        self.adaptive = Some(AdaptiveSampling { threshold, max_samples })
+    }
+
+    /// Limit the radiance carried by reflected and indirect light to the
+    /// given value per colour channel. Removes fireflies, the isolated bright
+    /// pixels left by rare paths through glossy and specular surfaces, at the
+    /// cost of darkening the brightest indirect highlights.
+    pub fn set_max_radiance(&mut self, clamp: f64) {
+        debug_assert!(clamp > 0.);
+        self.max_radiance = Some(clamp)
+    }
+
+    /// Fill the scene with fog that absorbs and scatters the given fractions
+    /// of light per unit distance. The asymmetry g in (-1, 1) sets whether
+    /// light scatters mostly backward or forward; forward-scattering haze lit
+    /// through gaps shows shafts of light. Returns the fog to confine it to
+    /// bounds, which directional and environment lights require to shine into.
+    pub fn set_fog(&mut self, absorption: [f64; 3], scattering: [f64; 3], g: f64) -> &mut HomogeneousMedium {
+        self.medium = Some(Medium::Homogeneous(HomogeneousMedium::new(absorption, scattering, g)));
+        match &mut self.medium {
+            Some(Medium::Homogeneous(medium)) => medium,
+            _ => unreachable!()
+        }
+    }
+
+    /// Fill the scene with a volume of varying density, such as smoke or a
+    /// cloud, in place of any fog. See `GridMedium::new`, and
+    /// `formats::nanovdb::load` to read the grid from a simulation.
+    pub fn set_volume(&mut self, medium: GridMedium) {
+        self.medium = Some(Medium::Grid(medium))
+    }
+
+    /// Fraction of light that makes it through the scene medium along the
+    /// given ray, between its t_min and t_max
+    #[inline]
+    pub(crate) fn transmittance(&self, ray: &Ray) -> Color {
+        match &self.medium {
+            Some(medium) => medium.tr(ray),
+            None => Color::from_value(1.0)
+        }
+    }
+
+    /// Add a light at the given position, dimmed over distance d by
+    /// `falloff[0] + falloff[1]*d + falloff[2]*d*d`. A falloff of [1, 0, 0]
+    /// doesn't dim at all. Kept for compatibility with older scenes; prefer
+    /// `add_point_light_power`.
+    pub fn add_point_light(&mut self, position: [f64; 3], intensity: [f64; 3], falloff: [f64; 3]) -> LightRef {
         let light = PointLight::new(position, intensity, falloff);
-        self.lights.push(Box::new(light))
+        self.add_light(Box::new(light))
     }
 
-    /// Add the given loaded Obj instance to the scene
-    pub fn add_obj(&mut self, mesh: Obj) -> ObjRef {
-        let mut mesh = mesh;
-        if !self.smoothing { mesh.data.normal.clear() };
-        let reference = ObjRef(self.meshes.len());
-        self.meshes.push(mesh);
+    /// Add a light at the given position that gives off the given power in
+    /// the given colour, e.g., `LightPower::Watts(60.)` or
+    /// `LightPower::Lumens(800.)` for a household bulb. Light dims with the
+    /// square of the distance, with scene units taken as metres. Adjust the
+    /// exposure to bring the result into range.
+    pub fn add_point_light_power(&mut self, position: [f64; 3], color: [f64; 3], power: LightPower) -> LightRef {
+        let light = PointLight::with_power(position, color, power);
+        self.add_light(Box::new(light))
+    }
+
+    /// Add a light that shines in parallel rays in the given direction, such as
+    /// the sun. An angular radius (in degrees) above zero softens shadows.
+    pub fn add_directional_light(&mut self, direction: [f64; 3], intensity: [f64; 3], angular_radius: f64) -> LightRef {
+        let light = DirectionalLight::new(direction, intensity, angular_radius);
+        self.add_light(Box::new(light))
+    }
+
+    fn add_light(&mut self, light: Box<dyn Light>) -> LightRef {
+        let reference = LightRef(self.lights.len());
+        self.lights.push(light);
+        self.light_visibility.push(LightVisibility::ALL);
         reference
     }
 
+    /// Choose which parts of the render the given light takes part in, e.g.,
+    /// `LightVisibility::CAMERA | LightVisibility::SHADOWS` for a light that
+    /// only lights what the camera sees directly
+    pub fn set_light_visibility(&mut self, light: LightRef, visibility: LightVisibility) {
+        self.light_visibility[light.0] = visibility
+    }
+
+    /// Set the intensity of the given light at the given time of an
+    /// animation, in seconds. See `set_time`.
+    pub fn key_light_intensity(&mut self, light: LightRef, time: f64, intensity: [f64; 3]) {
+        match self.light_animation.iter_mut().find(|(l, _)| *l == light) {
+            Some((_, track)) => { track.key(time, intensity.into()); },
+            None => {
+                let mut track = Track::new();
+                track.key(time, intensity.into());
+                self.light_animation.push((light, track))
+            }
+        }
+    }
+
+    /// Pose the scene at the given time of its animation, in seconds. Moves
+    /// the camera and groups to their keyed transformations and sets the keyed
+    /// light intensities. Parameters without keys are left as they are.
+    pub fn set_time(&mut self, time: f64) {
+        self.camera.set_time(time);
+        self.root.set_time(time);
+        for (light, track) in self.light_animation.iter() {
+            if let Some(intensity) = track.value_at(time) {
+                self.lights[light.0].set_intensity(intensity)
+            }
+        }
+    }
+
+    /// Split and displace the meshes whose materials have displacement maps,
+    /// until their edges span a couple of pixels in an image of the given
+    /// resolution taken by the camera as it's posed now. Call before
+    /// rendering, and again after moving the camera or changing materials;
+    /// meshes are displaced from their original shapes each time.
+    pub fn displace_meshes(&mut self, resolution: (u32, u32)) {
+        let mut root = std::mem::replace(&mut self.root, Aggregate::new());
+        self.displace_group(&mut root, Matrix4::identity(), resolution.1);
+        self.root = root;
+    }
+
+    /// Displace the meshes in the given group, which is placed in the world
+    /// by the given transformation of its parent
+    fn displace_group(&mut self, group: &mut Aggregate, to_world: Matrix4<f64>, height: u32) {
+        let to_world = to_world * group.transform.matrix();
+        for node in group.contents.iter_mut() {
+            match node {
+                SceneNode::Group(group) => self.displace_group(group, to_world, height),
+                SceneNode::Mesh(obj, material) => {
+                    let original = self.displaced.get(obj).copied().unwrap_or(*obj);
+                    let camera = &self.camera;
+                    let split = |a: &Point, b: &Point| {
+                        let (a, b) = (to_world.transform_point(*a), to_world.transform_point(*b));
+                        let width = camera.pixel_width_at(a.midpoint(b), height);
+                        (b - a).magnitude() > MAX_EDGE_PIXELS * width
+                    };
+                    match displace(&self.meshes[original.0], *material, self, split) {
+                        Some(mesh) if original == *obj => {
+                            *obj = ObjRef(self.meshes.len());
+                            self.meshes.push(mesh);
+                            self.displaced.insert(*obj, original);
+                        },
+                        Some(mesh) => {
+                            self.meshes[obj.0] = mesh;
+                            self.mesh_cache.invalidate(*obj)
+                        },
+                        None => *obj = original
+                    }
+                },
+                SceneNode::Geometry(..) => ()
+            }
+        }
+    }
+
+    /// Find the node at the given path of names separated by slashes, such as
+    /// `"car/wheels/front"`, starting from the root. See `Aggregate::add_named`.
+    pub fn find_node(&self, path: &str) -> Option<NodeRef> {
+        let mut indices = vec![];
+        let mut group = &self.root;
+        let mut names = path.split('/').filter(|name| !name.is_empty()).peekable();
+        while let Some(name) = names.next() {
+            let index = group.find(name)?;
+            indices.push(index);
+            if names.peek().is_none() { break }
+            group = match &group.contents[index] {
+                SceneNode::Group(aggregate) => aggregate,
+                _ => return None
+            };
+        }
+        if indices.is_empty() { None } else { Some(NodeRef(indices)) }
+    }
+
+    /// The node at the given reference, if there still is one
+    pub fn node(&self, node: &NodeRef) -> Option<&SceneNode> {
+        let (last, path) = node.0.split_last()?;
+        let mut group = &self.root;
+        for index in path {
+            group = match group.contents.get(*index)? {
+                SceneNode::Group(aggregate) => aggregate,
+                _ => return None
+            };
+        }
+        group.contents.get(*last)
+    }
+
+    /// Mutable node at the given reference, if there still is one, to edit
+    /// between frames
+    pub fn node_mut(&mut self, node: &NodeRef) -> Option<&mut SceneNode> {
+        let (last, path) = node.0.split_last()?;
+        let mut group = &mut self.root;
+        for index in path {
+            group = match group.contents.get_mut(*index)? {
+                SceneNode::Group(aggregate) => aggregate,
+                _ => return None
+            };
+        }
+        group.contents.get_mut(*last)
+    }
+
+    /// Group that places the node at the given reference, to set its
+    /// transformation and shadows with the methods of `Aggregate`. Shapes and
+    /// meshes are first moved into a group of their own, which keeps their
+    /// name and reference.
+    pub fn node_group(&mut self, node: &NodeRef) -> Option<&mut Aggregate> {
+        let node = self.node_mut(node)?;
+        if !matches!(node, SceneNode::Group(_)) {
+            let mut group = Aggregate::new();
+            group.add(std::mem::replace(node, SceneNode::Group(Aggregate::new())));
+            *node = SceneNode::Group(group);
+        }
+        match node {
+            SceneNode::Group(aggregate) => Some(aggregate),
+            _ => None
+        }
+    }
+
+    /// Swap the material of the node at the given reference, or of all the
+    /// contents of a group. See `SceneNode::set_material`. Panics if the node
+    /// is no longer in the scene.
+    pub fn set_node_material(&mut self, node: &NodeRef, material: MaterialRef) {
+        self.node_mut(node).expect("node not in scene").set_material(material)
+    }
+
+    /// Add the given loaded Obj instance to the scene. Groups that reference
+    /// a material from a loaded .mtl library render with that material in
+    /// place of the one given to the scene node.
+    pub fn add_obj(&mut self, mesh: Obj) -> ObjRef {
+        self.add_colored_obj(mesh, vec![])
+    }
+
+    /// Add the given triangle mesh to the scene. Attributes that don't have an
+    /// entry for every vertex are dropped. Panics if the indices refer to
+    /// missing vertices; check untrusted meshes with `TriangleMesh::validate`.
+    pub fn add_mesh(&mut self, mesh: TriangleMesh) -> ObjRef {
+        let mesh = mesh.validate().expect("triangle mesh indices out of range");
+        self.push_mesh(Mesh::new(mesh))
+    }
+
     /// Generate triangle mesh from the given string contents of a .obj file and
     /// add it to the scene. If parsed correctly, returns a reference to the
     /// mesh for use in scene node construction.
     pub fn parse_obj(&mut self, obj: &str) -> Result<ObjRef, obj::ObjError> {
-        let obj = parse_obj(obj)?;
-        Ok(self.add_obj(obj))
+        let (obj, colors) = parse_colored_obj(obj)?;
+        Ok(self.add_colored_obj(obj, colors))
     }
 
     // Load the .obj file mesh at the given file-system path and add it to the
-    // scene.
+    // scene, along with its vertex colours and any .mtl material libraries it
+    // references. Missing or malformed libraries are skipped and the affected
+    // groups fall back to the scene node's material.
     pub fn load_obj(&mut self, obj_path: &Path) -> Result<ObjRef, obj::ObjError> {
-        let obj = load_obj(obj_path)?;
-        Ok(self.add_obj(obj))
+        let (mut obj, colors) = load_colored_obj(obj_path)?;
+        let _ = obj.load_mtls();
+        Ok(self.add_colored_obj(obj, colors))
+    }
+
+    /// Subdivide the given Catmull-Clark cage and add the resulting smooth
+    /// mesh to the scene
+    pub fn add_subdiv_mesh(&mut self, mesh: &SubdivMesh) -> ObjRef {
+        let (mesh, _) = mesh.subdivide();
+        self.push_mesh(Mesh::new(mesh))
+    }
+
+    /// Parse the given string contents of a .obj file as the cage of a
+    /// subdivision surface with the given number of levels and add the
+    /// subdivided mesh to the scene. See `SubdivMesh::from_obj`.
+    pub fn parse_subdiv_obj(&mut self, obj: &str, levels: u32) -> Result<ObjRef, obj::ObjError> {
+        let (obj, _) = parse_colored_obj(obj)?;
+        Ok(self.add_subdiv_obj(obj, levels))
+    }
+
+    /// Load the .obj file at the given file-system path as the cage of a
+    /// subdivision surface, along with any .mtl material libraries it
+    /// references, and add the subdivided mesh to the scene
+    pub fn load_subdiv_obj(&mut self, obj_path: &Path, levels: u32) -> Result<ObjRef, obj::ObjError> {
+        let (mut obj, _) = load_colored_obj(obj_path)?;
+        let _ = obj.load_mtls();
+        Ok(self.add_subdiv_obj(obj, levels))
+    }
+
+    /// Load the ASCII or binary .ply mesh at the given file-system path and add
+    /// it to the scene along with its vertex colours, if any
+    pub fn load_ply(&mut self, path: &Path) -> Result<ObjRef, crate::shape::mesh::Error> {
+        Ok(self.add_mesh(crate::formats::ply::load_ply(path)?))
+    }
+
+    /// Load the ASCII or binary .stl mesh at the given file-system path and add
+    /// it to the scene
+    pub fn load_stl(&mut self, path: &Path) -> Result<ObjRef, crate::shape::mesh::Error> {
+        Ok(self.add_mesh(crate::formats::stl::load_stl(path)?))
+    }
+
+    /// Import the meshes, node hierarchy, materials, first camera and punctual
+    /// lights of the glTF 2.0 (.gltf or .glb) file at the given file-system
+    /// path. Nodes are added to the root as nested aggregates with their
+    /// transformations. See `formats::gltf` for how materials are mapped.
+    pub fn load_gltf(&mut self, path: &Path) -> Result<(), crate::formats::gltf::Error> {
+        crate::formats::gltf::load(self, path)
+    }
+
+    /// Add the given .obj mesh along with the colour of each of its vertex
+    /// positions, if any
+    fn add_colored_obj(&mut self, obj: Obj, colors: Vec<[f32; 3]>) -> ObjRef {
+        let (mesh, groups) = TriangleMesh::from_obj(&obj, &colors);
+        let materials = self.obj_materials(&obj);
+        self.push_mesh(Mesh::with_materials(mesh, groups, materials))
+    }
+
+    /// Subdivide the polygons of the given .obj mesh and add the result
+    fn add_subdiv_obj(&mut self, obj: Obj, levels: u32) -> ObjRef {
+        let (mesh, groups) = SubdivMesh::from_obj(&obj, levels).subdivide();
+        let materials = self.obj_materials(&obj);
+        self.push_mesh(Mesh::with_materials(mesh, groups, materials))
+    }
+
+    /// Material from the .mtl libraries of each group of the given .obj mesh,
+    /// if it has one, added to the scene
+    fn obj_materials(&mut self, obj: &Obj) -> Vec<Option<MaterialRef>> {
+        // Add each library material once, even if many groups share it
+        let mut built: HashMap<String, MaterialRef> = HashMap::new();
+        let mut materials = vec![];
+        for object in obj.data.objects.iter() {
+            for group in object.groups.iter() {
+                materials.push(match &group.material {
+                    Some(obj::ObjMaterial::Mtl(mtl)) => Some(match built.get(&mtl.name) {
+                        Some(material) => *material,
+                        None => {
+                            let material = self.mtl_material(mtl, &obj.path);
+                            let material = self.add_material(material);
+                            built.insert(mtl.name.clone(), material);
+                            material
+                        }
+                    }),
+                    _ => None
+                })
+            }
+        }
+        materials
+    }
+
+    /// Add the given mesh, shaded flat if smoothing is disabled
+    fn push_mesh(&mut self, mut mesh: Mesh) -> ObjRef {
+        if !self.smoothing { mesh.set_smoothing(Smoothing::Flat) }
+        let reference = ObjRef(self.meshes.len());
+        self.meshes.push(mesh);
+        reference
+    }
+
+    /// Add the given texture to the scene. Returns a reference that may be
+    /// used in place of a constant colour in material constructors.
+    pub fn add_texture(&mut self, texture: Box<dyn Texture>) -> TextureRef {
+        let reference = TextureRef::new(self.textures.len());
+        self.textures.push(texture);
+        reference
+    }
+
+    /// Add a texture of alternating squares of two colours, with the given
+    /// number of squares along each UV axis.
+    pub fn add_checkerboard_texture(&mut self, even: [f64; 3], odd: [f64; 3], scale: f64) -> TextureRef {
+        self.add_texture(Box::new(Checkerboard::new(even, odd, scale)))
+    }
+
+    /// Add a procedural texture blending between the `low` and `high` colours
+    /// by the value of a noise pattern, with the given number of pattern
+    /// features per unit of object space.
+    pub fn add_noise_texture(&mut self, pattern: NoisePattern, low: [f64; 3], high: [f64; 3], scale: f64) -> TextureRef {
+        self.add_texture(Box::new(Noise::new(pattern, low, high, scale)))
+    }
+
+    /// Load the image file at the given file-system path and add it to the
+    /// scene as a texture.
+    #[cfg(feature = "bin")]
+    pub fn load_texture(&mut self, path: &Path) -> Result<TextureRef, ::image::ImageError> {
+        let texture = crate::texture::ImageTexture::open(path)?;
+        Ok(self.add_texture(Box::new(texture)))
+    }
+
+    /// Load the alpha channel of the image file at the given file-system path
+    /// and add it to the scene as a grayscale texture, for use as an alpha
+    /// mask with `Material::with_alpha`. Images without an alpha channel are
+    /// added as they are.
+    #[cfg(feature = "bin")]
+    pub fn load_alpha_texture(&mut self, path: &Path) -> Result<TextureRef, ::image::ImageError> {
+        let texture = crate::texture::ImageTexture::open_alpha(path)?;
+        Ok(self.add_texture(Box::new(texture)))
+    }
+
+    /// Add the given material to the scene. Returns a reference for the
+    /// shapes made of it, or to make other materials from it, such as with
+    /// `Material::mix`. Panics if the material blends another blend.
+    pub fn add_material(&mut self, material: Material) -> MaterialRef {
+        let reference = MaterialRef(self.materials.len());
+        self.check_blend(reference, &material);
+        self.materials.push(material);
+        reference
     }
 
     pub fn set_root(&mut self, node: Aggregate) {
@@ -135,12 +815,114 @@ impl Scene {
 
     pub fn lights(&self) -> &Vec<Box<dyn Light>> { &self.lights }
 
-    /// Return a reference to the object instance for the given ObjRef, if
-    /// available.
-    pub fn obj<'a>(&'a self, obj: ObjRef) -> Option<&'a Obj> {
+    /// Parts of the render each light takes part in, in the order of `lights`
+    pub fn light_visibility(&self) -> &[LightVisibility] { &self.light_visibility }
+
+    /// Return the mesh for the given ObjRef, including the materials built
+    /// from its material libraries, if available.
+    pub fn mesh(&self, obj: ObjRef) -> Option<&Mesh> {
         self.meshes.get(obj.0)
     }
+
+    /// Hierarchies of the triangles of the meshes kept for acceleration
+    /// structures built for this scene
+    pub(crate) fn mesh_cache(&self) -> &MeshCache {
+        &self.mesh_cache
+    }
+
+    /// Return the texture for the given TextureRef. Panics if the reference
+    /// was created by a different scene.
+    pub fn texture(&self, texture: TextureRef) -> &dyn Texture {
+        self.textures[texture.index].as_ref()
+    }
+
+    /// Return the material for the given MaterialRef. Panics if the reference
+    /// was created by a different scene.
+    pub fn material(&self, material: MaterialRef) -> Material {
+        self.materials[material.0]
+    }
+
+    /// Replace the material for the given MaterialRef, such as to change a
+    /// colour between frames. Every shape and blend made of it renders with
+    /// the new one. Panics if the reference was created by a different
+    /// scene, or if the change would make a blend of blends.
+    pub fn set_material(&mut self, material: MaterialRef, value: Material) {
+        self.check_blend(material, &value);
+        self.materials[material.0] = value
+    }
+
+    /// Panic unless the given material may be stored at the given reference
+    /// without either blending a blend or making a blend member a blend.
+    fn check_blend(&self, reference: MaterialRef, value: &Material) {
+        if let Material::Mix(mix) = value {
+            for member in mix.members().iter() {
+                assert!(*member != reference, "material blends itself");
+                assert!(!matches!(self.material(*member), Material::Mix(_)), "material blends a blend");
+            }
+            assert!(
+                !self.materials.iter().any(|m| match m {
+                    Material::Mix(other) => other.members().contains(&reference),
+                    _ => false
+                }),
+                "blended material replaced by a blend"
+            );
+        }
+    }
+
+    /// Approximate the given .mtl material with one of the lasgun materials.
+    /// Texture paths are resolved relative to the directory of the .obj file.
+    fn mtl_material(&mut self, mtl: &obj::Material, dir: &Path) -> Material {
+        let rgb = |c: [f32; 3]| [c[0] as f64, c[1] as f64, c[2] as f64];
+        let kd = mtl.kd.map(rgb).unwrap_or([0.5, 0.5, 0.5]);
+        let ks = mtl.ks.map(rgb).unwrap_or([0.0, 0.0, 0.0]);
+
+        // Non-zero emission makes the group an area light
+        let ke = mtl.ke.map(rgb).unwrap_or([0.0, 0.0, 0.0]);
+        if ke != [0.0, 0.0, 0.0] { return Material::emissive(ke) }
+
+        // Dissolve below 1 or one of the refraction illumination models
+        let transparent = mtl.d.is_some_and(|d| d < 1.0)
+            || mtl.illum.is_some_and(|illum| [4, 6, 7, 9].contains(&illum));
+        if transparent {
+            let kt = mtl.tf.map(rgb).unwrap_or([1.0, 1.0, 1.0]);
+            let kr = if ks == [0.0, 0.0, 0.0] { [1.0, 1.0, 1.0] } else { ks };
+            return Material::glass(kr, kt, mtl.ni.unwrap_or(1.5) as f64)
+        }
+
+        let diffuse = match &mtl.map_kd {
+            Some(map) => self.mtl_texture(&dir.join(map), false).map(Param::from),
+            None => None
+        }.unwrap_or(Param::from(kd));
+
+        let material = if ks == [0.0, 0.0, 0.0] {
+            Material::matte(diffuse, 0.0)
+        } else {
+            // Map the Phong specular exponent onto microfacet roughness
+            let ns = mtl.ns.unwrap_or(0.0).max(0.0) as f64;
+            Material::plastic(diffuse, ks, (2.0 / (ns + 2.0)).sqrt())
+        };
+
+        // Dissolve maps cut out parts of the surface, such as around leaves
+        match mtl.map_d.as_ref().and_then(|map| self.mtl_texture(&dir.join(map), true)) {
+            Some(mask) => material.with_alpha(mask),
+            None => material
+        }
+    }
+
+    /// Load a texture referenced by a material library, either as a colour
+    /// map or as an alpha mask
+    #[cfg(feature = "bin")]
+    fn mtl_texture(&mut self, path: &Path, alpha: bool) -> Option<TextureRef> {
+        if alpha { self.load_alpha_texture(path).ok() } else { self.load_texture(path).ok() }
+    }
+
+    /// Image textures cannot be loaded without the bin feature
+    #[cfg(not(feature = "bin"))]
+    fn mtl_texture(&mut self, _path: &Path, _alpha: bool) -> Option<TextureRef> {
+        None
+    }
 }
 
 pub mod node;
+pub mod io;
 pub use self::node::*;
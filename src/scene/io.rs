@@ -0,0 +1,962 @@
+//! Reading and writing scene descriptions as JSON files, so that scenes may be
+//! authored without writing Rust and shared between front-ends.
+//!
+//! A live `Scene` holds loaded meshes, textures and lights that can't be
+//! written back out, so files map onto `SceneDesc`, a plain description of the
+//! calls that build the scene. `load` reads a file and builds its scene;
+//! `save` writes a description.
+//!
+//! # Format
+//!
+//! Every field except `camera` is optional. Colours are `[r, g, b]` arrays,
+//! points and vectors are `[x, y, z]` and angles are in degrees. Paths are
+//! relative to the directory of the scene file.
+//!
+//! ```json
+//! {
+//!   "camera": {
+//!     "projection": { "perspective": 50 },
+//!     "eye": [0, 2, 8], "look": [0, 1, 0], "up": [0, 1, 0],
+//!     "samples": 4,
+//!     "aperture": 0.05, "focal_distance": 8
+//!   },
+//!   "background": { "type": "radial", "inner": [0.9, 0.9, 1], "outer": [0.4, 0.5, 0.8], "scale": 1 },
+//!   "ambient": [0.1, 0.1, 0.1],
+//!   "integrator": "path",
+//!   "recursion": 5,
+//!   "roulette_depth": 3,
+//!   "smoothing": true,
+//!   "tonemap": { "operator": "aces", "exposure": 0, "gamma": 2.2 },
+//!   "lights": [
+//!     { "type": "point", "position": [4, 6, 4], "intensity": [0.8, 0.8, 0.8], "falloff": [1, 0, 0] },
+//!     { "type": "point", "position": [0, 3, 0], "intensity": [1, 0.9, 0.8], "power": { "watts": 60 } },
+//!     { "type": "directional", "direction": [-1, -1, 0], "intensity": [1, 1, 1], "angular_radius": 0.5 },
+//!     { "type": "portal", "center": [0, 2, -5], "normal": [0, 0, 1], "size": [2, 1.5] }
+//!   ],
+//!   "textures": {
+//!     "checks": { "type": "checkerboard", "even": [1, 1, 1], "odd": [0.1, 0.1, 0.1], "scale": 8 },
+//!     "bricks": { "type": "image", "path": "bricks.png", "filter": "ewa" },
+//!     "leaf": { "type": "alpha", "path": "leaf.png" },
+//!     "veins": { "type": "noise", "pattern": "marble", "low": [0.9, 0.9, 0.85], "high": [0.3, 0.3, 0.35], "scale": 2 },
+//!     "tiles": { "type": "projected", "texture": "bricks", "projection": { "type": "triplanar", "scale": 0.5, "sharpness": 4 } }
+//!   },
+//!   "materials": {
+//!     "floor": { "type": "matte", "kd": "checks" },
+//!     "wall": { "type": "matte", "kd": [0.8, 0.8, 0.8], "bump": { "texture": "bricks", "scale": 0.02 } },
+//!     "rock": { "type": "matte", "kd": [0.5, 0.45, 0.4], "displacement": { "texture": "veins", "scale": 0.05 } },
+//!     "foliage": { "type": "matte", "kd": [0.2, 0.5, 0.1], "alpha": "leaf" },
+//!     "red": { "type": "plastic", "kd": [0.7, 0.1, 0.1], "ks": [0.3, 0.3, 0.3], "roughness": 0.2 },
+//!     "paint": { "type": "matte", "kd": [0.6, 0.1, 0.1], "coat": { "material": "glass", "thickness": 0.1 } },
+//!     "gold": { "type": "metal", "eta": [0.14, 0.37, 1.44], "k": [3.98, 2.39, 1.6], "u_roughness": 0.1, "v_roughness": 0.1 },
+//!     "glass": { "type": "glass", "kr": [1, 1, 1], "kt": [1, 1, 1], "eta": 1.5, "max_depth": 12 },
+//!     "mirror": { "type": "mirror", "kr": [0.9, 0.9, 0.9] },
+//!     "lamp": { "type": "emissive", "radiance": [10, 10, 10] },
+//!     "rusty": { "type": "mix", "a": "gold", "b": "wall", "factor": "checks" }
+//!   },
+//!   "meshes": {
+//!     "cow": "meshes/cow.obj",
+//!     "bunny": { "path": "meshes/bunny.obj", "smoothing": { "smooth": 60 } },
+//!     "head": { "path": "meshes/head.obj", "subdivide": 2 },
+//!     "triangle": { "obj": "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n" }
+//!   },
+//!   "root": {
+//!     "contents": [
+//!       { "type": "plane", "center": [0, 0, 0], "normal": [0, 1, 0], "size": [10, 10], "material": "floor" },
+//!       { "type": "sphere", "center": [0, 1, 0], "radius": 1, "material": "red" },
+//!       { "type": "cube", "origin": [2, 0, 0], "size": 1, "material": "gold" },
+//!       { "type": "box", "min": [-3, 0, -1], "max": [-2, 2, 1], "material": "glass" },
+//!       { "type": "disk", "center": [0, 4, 0], "normal": [0, -1, 0], "radius": 0.5, "material": "lamp" },
+//!       {
+//!         "type": "group",
+//!         "name": "cow",
+//!         "transform": [{ "translate": [0, 0, -3] }, { "rotate_y": 30 }, { "scale": [0.5, 0.5, 0.5] }],
+//!         "contents": [{ "type": "mesh", "mesh": "cow", "material": "mirror" }]
+//!       }
+//!     ]
+//!   }
+//! }
+//! ```
+//!
+//! The camera takes either a number of `samples` per pixel, which need not be
+//! square, or a `supersampling` root as for `Camera::set_supersampling`. The
+//! background is one of `solid` (`color`), `radial` (`inner`, `outer`,
+//! `scale`) or `environment` (`path` to an OpenEXR map and a number of light
+//! `samples`). The `irradiance` integrator takes the `irradiance_samples` and
+//! `irradiance_error` of its cache, as for `Scene::set_irradiance_cache`.
+//! Scenes with many point lights may set `light_samples`, as for
+//! `Scene::set_light_samples`.
+//! The debug integrator is written with its view, e.g., `{ "debug": "heatmap" }`,
+//! as for `DebugView`.
+//! Light `portal`s mark windows that the environment map shines
+//! through, as for `Scene::add_light_portal`. Noise texture patterns are one
+//! of `perlin`, `fbm`, `turbulence`, `marble`, `wood` or `worley`, with an optional number of
+//! `octaves`. Image textures take an optional `filter` of `bilinear`,
+//! `trilinear` (the default) or `ewa`. Projected textures look up another `texture` by a `projection`
+//! of one of the types `uv`, `planar` (`axis` and `scale`), `spherical`,
+//! `cylindrical` (`scale`) or `triplanar` (`scale` and `sharpness`), in place
+//! of surface UVs. Material `kd` parameters take either a colour or the name
+//! of a texture. Materials with a `coat` have another material laid over
+//! them, as for `Material::layered`. Blends (`mix`) take the names of two
+//! materials that aren't blends and a `factor` colour or texture. Materials
+//! with a `max_depth` let light bounce off them more or less deeply than the
+//! scene's `recursion`, as for `Material::with_max_depth`. Meshes are
+//! either the path to a .obj file or an object with the `path` or contents
+//! (`obj`) of one and an optional `smoothing`: `"flat"` or
+//! `{ "smooth": crease_angle }`, which generates normals for meshes without
+//! any. Meshes with `subdivide` set to a number of levels are smoothed as
+//! Catmull-Clark cages, as for `SubdivMesh`. Mesh nodes without a material
+//! use the ones from their .mtl libraries. Group transformations are applied
+//! in order, like the `Aggregate` methods of the same names; `rotate` takes
+//! an `angle` and an `axis`. Groups with a `name` can be found with
+//! `Scene::find_node`.
+
+use std::{fmt, fs, io, path::Path, collections::BTreeMap};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    camera::Camera,
+    integrate::Integrator,
+    light::LightPower,
+    material::{Material, MaterialRef},
+    shape::mesh::Smoothing,
+    texture::{Param, TextureRef, TextureFilter, Noise, NoisePattern, Projection},
+    tonemap::Tonemap
+};
+use super::{Scene, ObjRef, node::{Aggregate, SceneNode}};
+
+/// Plain description of a scene, as stored in scene files
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDesc {
+    pub camera: CameraDesc,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<BackgroundDesc>,
+    #[serde(default)]
+    pub ambient: [f64; 3],
+    #[serde(default)]
+    pub integrator: Integrator,
+    #[serde(default = "default_recursion")]
+    pub recursion: u32,
+
+    /// Bounces after which path tracing may cut paths short with Russian
+    /// roulette
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roulette_depth: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ao_distance: Option<f64>,
+
+    /// Rays gathered for each record of the irradiance cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub irradiance_samples: Option<u32>,
+
+    /// Largest error allowed when interpolating the irradiance cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub irradiance_error: Option<f64>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub spectral: bool,
+
+    /// Point lights picked to light each hit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light_samples: Option<usize>,
+    #[serde(default = "default_smoothing")]
+    pub smoothing: bool,
+    #[serde(default)]
+    pub tonemap: Tonemap,
+    #[serde(default)]
+    pub lights: Vec<LightDesc>,
+    #[serde(default)]
+    pub textures: BTreeMap<String, TextureDesc>,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDesc>,
+
+    #[serde(default)]
+    pub meshes: BTreeMap<String, MeshDesc>,
+    #[serde(default)]
+    pub root: GroupDesc
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDesc {
+    pub projection: ProjectionDesc,
+    pub eye: [f64; 3],
+    pub look: [f64; 3],
+    #[serde(default = "default_up")]
+    pub up: [f64; 3],
+    #[serde(default)]
+    pub supersampling: u8,
+
+    /// Samples per pixel, in place of the supersampling root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub samples: Option<u32>,
+    #[serde(default)]
+    pub aperture: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focal_distance: Option<f64>,
+
+    /// Horizontal and vertical lens shift, in units of the image height
+    #[serde(default)]
+    pub shift: [f64; 2],
+
+    /// Horizontal and vertical lens tilt, in degrees
+    #[serde(default)]
+    pub tilt: [f64; 2]
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectionDesc {
+    /// Vertical field of view in degrees
+    Perspective(f64),
+
+    /// Height of the focal plane in world units
+    Orthographic(f64),
+
+    /// Height of the focal plane in world units for a true isometric view of
+    /// the look-at point. The eye only sets the distance to that point.
+    Isometric(f64),
+
+    /// Field of view in degrees across the circle inscribed in the image
+    Fisheye(f64),
+
+    /// 360° panorama around the eye
+    Equirectangular
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BackgroundDesc {
+    Solid { color: [f64; 3] },
+    Radial { inner: [f64; 3], outer: [f64; 3], scale: f64 },
+    Environment { path: String, samples: usize }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LightDesc {
+    /// Light given either an intensity and legacy falloff, or a power (e.g.,
+    /// `{ "watts": 60 }`) in which case the intensity is its colour and it
+    /// dims with the square of the distance
+    Point {
+        position: [f64; 3],
+        intensity: [f64; 3],
+        #[serde(default = "default_falloff")]
+        falloff: [f64; 3],
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        power: Option<LightPower>
+    },
+    Directional {
+        direction: [f64; 3],
+        intensity: [f64; 3],
+        #[serde(default)]
+        angular_radius: f64
+    },
+
+    /// Opening through which the environment map lights an interior, with
+    /// the normal facing the interior
+    Portal { center: [f64; 3], normal: [f64; 3], size: [f64; 2] }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TextureDesc {
+    Checkerboard { even: [f64; 3], odd: [f64; 3], scale: f64 },
+    Image {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filter: Option<TextureFilter>
+    },
+
+    /// The alpha channel of an image, for use as an alpha mask
+    Alpha { path: String },
+
+    /// Procedural noise pattern blending between two colours
+    Noise {
+        pattern: NoisePattern,
+        low: [f64; 3],
+        high: [f64; 3],
+        scale: f64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        octaves: Option<u32>
+    },
+
+    /// Another texture, projected onto surfaces in place of their UVs
+    Projected { texture: String, projection: Projection }
+}
+
+/// A triangle mesh: either the path to a .obj file or the contents of one,
+/// for front-ends without a file system. The longer forms also choose how the
+/// mesh is smoothed, in place of the scene-wide `smoothing` setting, and may
+/// subdivide it as a Catmull-Clark cage.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum MeshDesc {
+    Path(String),
+    Source {
+        obj: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        smoothing: Option<Smoothing>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        subdivide: Option<u32>
+    },
+    File {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        smoothing: Option<Smoothing>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        subdivide: Option<u32>
+    }
+}
+
+/// A colour-valued material parameter: either an RGB colour or the name of a
+/// texture
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ParamDesc {
+    Color([f64; 3]),
+    Texture(String)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MaterialDesc {
+    #[serde(flatten)]
+    pub kind: MaterialKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bump: Option<BumpDesc>,
+
+    /// Height texture that moves the vertices of meshes with this material
+    /// when the loaded scene's `displace_meshes` is called
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub displacement: Option<BumpDesc>,
+
+    /// Name of the texture that cuts out parts of the surface where it
+    /// averages below one half
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alpha: Option<String>,
+
+    /// Whether the back of the surface scatters and emits light like the
+    /// front
+    #[serde(default = "default_two_sided", skip_serializing_if = "is_true")]
+    pub two_sided: bool,
+
+    /// Recursion depth at surfaces of this material, in place of the scene's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<u32>,
+
+    /// Abbe number of glass that splits light into its colours in spectral
+    /// renders
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dispersion: Option<f64>,
+
+    /// Layer of another material over this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coat: Option<CoatDesc>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialKind {
+    Matte {
+        kd: ParamDesc,
+        #[serde(default)]
+        sigma: f64
+    },
+    Plastic { kd: ParamDesc, ks: [f64; 3], roughness: f64 },
+    Metal { eta: [f64; 3], k: [f64; 3], u_roughness: f64, v_roughness: f64 },
+    Glass { kr: [f64; 3], kt: [f64; 3], eta: f64 },
+    Mirror { kr: [f64; 3] },
+    Emissive { radiance: [f64; 3] },
+
+    /// Blend of two other materials, neither of which may be a blend itself
+    Mix { a: String, b: String, factor: ParamDesc }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BumpDesc {
+    /// Name of the grayscale height texture
+    pub texture: String,
+    pub scale: f64
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CoatDesc {
+    /// Name of the glass or plastic material of the coat, without its own
+    /// coat, if any
+    pub material: String,
+    pub thickness: f64
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum NodeDesc {
+    Sphere { center: [f64; 3], radius: f64, material: String },
+    Cube { origin: [f64; 3], size: f64, material: String },
+    Box { min: [f64; 3], max: [f64; 3], material: String },
+    Plane { center: [f64; 3], normal: [f64; 3], size: [f64; 2], material: String },
+    Disk { center: [f64; 3], normal: [f64; 3], radius: f64, material: String },
+    Mesh {
+        mesh: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>
+    },
+    Group(GroupDesc)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GroupDesc {
+    /// Name of the group within its parent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transform: Vec<TransformDesc>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub swap_backface: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub cull_backface: bool,
+    #[serde(default = "default_shadows", skip_serializing_if = "is_true")]
+    pub cast_shadows: bool,
+    #[serde(default = "default_shadows", skip_serializing_if = "is_true")]
+    pub receive_shadows: bool,
+    #[serde(default)]
+    pub contents: Vec<NodeDesc>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransformDesc {
+    Translate([f64; 3]),
+    Scale([f64; 3]),
+    RotateX(f64),
+    RotateY(f64),
+    RotateZ(f64),
+    Rotate { angle: f64, axis: [f64; 3] }
+}
+
+fn default_recursion() -> u32 { 3 }
+fn default_smoothing() -> bool { true }
+fn default_up() -> [f64; 3] { [0.0, 1.0, 0.0] }
+fn default_falloff() -> [f64; 3] { [1.0, 0.0, 0.0] }
+fn default_shadows() -> bool { true }
+fn default_two_sided() -> bool { true }
+fn is_false(b: &bool) -> bool { !*b }
+fn is_true(b: &bool) -> bool { *b }
+
+/// Problems reading, writing or building a scene file
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Obj(obj::ObjError),
+    #[cfg(feature = "bin")]
+    Image(::image::ImageError),
+    #[cfg(feature = "bin")]
+    Exr(exr::error::Error),
+
+    /// A material, texture or mesh name that the file doesn't define
+    Undefined { kind: &'static str, name: String },
+
+    /// The file uses a feature this build can't provide, such as image
+    /// textures without the bin feature
+    Unsupported(&'static str)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Json(err) => write!(f, "invalid scene file: {}", err),
+            Error::Obj(err) => write!(f, "could not load mesh: {}", err),
+            #[cfg(feature = "bin")]
+            Error::Image(err) => write!(f, "could not load texture: {}", err),
+            #[cfg(feature = "bin")]
+            Error::Exr(err) => write!(f, "could not load environment map: {}", err),
+            Error::Undefined { kind, name } => write!(f, "undefined {} \"{}\"", kind, name),
+            Error::Unsupported(feature) => write!(f, "{} require the bin feature", feature)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::Io(err) }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error { Error::Json(err) }
+}
+
+impl From<obj::ObjError> for Error {
+    fn from(err: obj::ObjError) -> Error { Error::Obj(err) }
+}
+
+#[cfg(feature = "bin")]
+impl From<::image::ImageError> for Error {
+    fn from(err: ::image::ImageError) -> Error { Error::Image(err) }
+}
+
+#[cfg(feature = "bin")]
+impl From<exr::error::Error> for Error {
+    fn from(err: exr::error::Error) -> Error { Error::Exr(err) }
+}
+
+/// Parse a scene description from the given JSON text
+pub fn from_str(json: &str) -> Result<SceneDesc, Error> {
+    Ok(serde_json::from_str(json)?)
+}
+
+/// Format the given scene description as JSON text
+pub fn to_string(desc: &SceneDesc) -> Result<String, Error> {
+    Ok(serde_json::to_string_pretty(desc)?)
+}
+
+/// Read the scene description in the JSON file at the given path
+pub fn read(path: &Path) -> Result<SceneDesc, Error> {
+    from_str(&fs::read_to_string(path)?)
+}
+
+/// Read the JSON file at the given path and build the scene it describes.
+/// Meshes and textures are loaded relative to the directory of the file.
+pub fn load(path: &Path) -> Result<Scene, Error> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    read(path)?.build(dir)
+}
+
+/// Write the given scene description to a JSON file at the given path
+pub fn save(desc: &SceneDesc, path: &Path) -> Result<(), Error> {
+    Ok(fs::write(path, to_string(desc)?)?)
+}
+
+impl SceneDesc {
+    /// Construct the described scene. Relative mesh, texture and environment
+    /// map paths are resolved against the given directory.
+    pub fn build(&self, dir: &Path) -> Result<Scene, Error> {
+        let mut scene = Scene::new();
+        scene.set_camera(self.camera.build());
+        scene.set_ambient_light(self.ambient);
+        scene.set_integrator(self.integrator);
+        scene.set_max_recursion_depth(self.recursion);
+        if let Some(depth) = self.roulette_depth { scene.set_roulette_depth(depth) }
+        if let Some(distance) = self.ao_distance { scene.set_ao_distance(distance) }
+        if self.irradiance_samples.is_some() || self.irradiance_error.is_some() {
+            scene.set_irradiance_cache(
+                self.irradiance_samples.unwrap_or(scene.irradiance_samples),
+                self.irradiance_error.unwrap_or(scene.irradiance_error)
+            )
+        }
+        scene.set_spectral(self.spectral);
+        if let Some(samples) = self.light_samples { scene.set_light_samples(samples) }
+        scene.tonemap = self.tonemap;
+
+        // Smoothing applies to meshes as they're added
+        scene.set_mesh_smoothing(self.smoothing);
+
+        match &self.background {
+            Some(BackgroundDesc::Solid { color }) => scene.set_solid_background(*color),
+            Some(BackgroundDesc::Radial { inner, outer, scale }) =>
+                scene.set_radial_background(*inner, *outer, *scale),
+            Some(BackgroundDesc::Environment { path, samples }) =>
+                load_environment_map(&mut scene, &dir.join(path), *samples)?,
+            None => ()
+        }
+
+        for light in self.lights.iter() {
+            match light {
+                LightDesc::Point { position, intensity, power: Some(power), .. } =>
+                    scene.add_point_light_power(*position, *intensity, *power),
+                LightDesc::Point { position, intensity, falloff, power: None } =>
+                    scene.add_point_light(*position, *intensity, *falloff),
+                LightDesc::Directional { direction, intensity, angular_radius } =>
+                    scene.add_directional_light(*direction, *intensity, *angular_radius),
+                LightDesc::Portal { center, normal, size } => {
+                    scene.add_light_portal(*center, *normal, *size);
+                    continue
+                }
+            };
+        }
+
+        let mut textures = BTreeMap::new();
+        for (name, texture) in self.textures.iter() {
+            let reference = match texture {
+                TextureDesc::Checkerboard { even, odd, scale } =>
+                    scene.add_checkerboard_texture(*even, *odd, *scale),
+                TextureDesc::Image { path, filter } => load_image_texture(&mut scene, &dir.join(path), *filter)?,
+                TextureDesc::Alpha { path } => load_texture(&mut scene, &dir.join(path), true)?,
+                TextureDesc::Noise { pattern, low, high, scale, octaves } => {
+                    let noise = Noise::new(*pattern, *low, *high, *scale);
+                    let noise = match octaves { Some(octaves) => noise.with_octaves(*octaves), None => noise };
+                    scene.add_texture(Box::new(noise))
+                },
+                TextureDesc::Projected { .. } => continue
+            };
+            textures.insert(name.as_str(), reference);
+        }
+
+        // Projections refer to other textures, so add those first
+        for (name, texture) in self.textures.iter() {
+            if let TextureDesc::Projected { texture, projection } = texture {
+                let reference = lookup(&textures, "texture", texture)?.projected(*projection);
+                textures.insert(name.as_str(), reference);
+            }
+        }
+
+        // Coats and blends refer to other materials, so build those first
+        let is_mix = |material: &MaterialDesc| matches!(material.kind, MaterialKind::Mix { .. });
+        let (mixes, plain): (Vec<_>, Vec<_>) = self.materials.iter().partition(|(_, material)| is_mix(material));
+
+        let mut materials = BTreeMap::new();
+        for (name, material) in plain.iter() {
+            materials.insert(name.as_str(), material.build(&textures, &BTreeMap::new())?);
+        }
+        let uncoated = materials.clone();
+        coat(&mut materials, &uncoated, &plain)?;
+
+        let mut parts = BTreeMap::new();
+        for (name, material) in materials.iter() {
+            parts.insert(*name, scene.add_material(*material));
+        }
+        for (name, material) in mixes.iter() {
+            materials.insert(name.as_str(), material.build(&textures, &parts)?);
+        }
+        coat(&mut materials, &uncoated, &mixes)?;
+        for (name, _) in mixes.iter() {
+            parts.insert(name.as_str(), scene.add_material(materials[name.as_str()]));
+        }
+
+        let mut meshes = BTreeMap::new();
+        for (name, mesh) in self.meshes.iter() {
+            let (reference, smoothing) = match mesh {
+                MeshDesc::Path(path) => (scene.load_obj(&dir.join(path))?, None),
+                MeshDesc::Source { obj, smoothing, subdivide } => match subdivide {
+                    Some(levels) => (scene.parse_subdiv_obj(obj, *levels)?, *smoothing),
+                    None => (scene.parse_obj(obj)?, *smoothing)
+                },
+                MeshDesc::File { path, smoothing, subdivide } => match subdivide {
+                    Some(levels) => (scene.load_subdiv_obj(&dir.join(path), *levels)?, *smoothing),
+                    None => (scene.load_obj(&dir.join(path))?, *smoothing)
+                }
+            };
+            if let Some(smoothing) = smoothing { scene.set_obj_smoothing(reference, smoothing) }
+            meshes.insert(name.as_str(), reference);
+        }
+
+        let names = Names { materials: parts, meshes };
+        scene.set_root(self.root.build(&names)?);
+        Ok(scene)
+    }
+
+    /// Paths of the files that `build` reads, as given in the description:
+    /// the environment map, image textures and .obj meshes
+    pub fn paths(&self) -> Vec<&str> {
+        let mut paths = vec![];
+        if let Some(BackgroundDesc::Environment { path, .. }) = &self.background {
+            paths.push(path.as_str())
+        }
+        for texture in self.textures.values() {
+            match texture {
+                TextureDesc::Image { path, .. } | TextureDesc::Alpha { path } => paths.push(path.as_str()),
+                _ => ()
+            }
+        }
+        for mesh in self.meshes.values() {
+            match mesh {
+                MeshDesc::Path(path) | MeshDesc::File { path, .. } => paths.push(path.as_str()),
+                MeshDesc::Source { .. } => ()
+            }
+        }
+        paths
+    }
+}
+
+impl CameraDesc {
+    fn build(&self) -> Camera {
+        let mut camera = match self.projection {
+            ProjectionDesc::Perspective(fov) => Camera::perspective(fov),
+            ProjectionDesc::Orthographic(height) | ProjectionDesc::Isometric(height) => Camera::orthographic(height),
+            ProjectionDesc::Fisheye(fov) => Camera::fisheye(fov),
+            ProjectionDesc::Equirectangular => Camera::equirectangular()
+        };
+        match self.projection {
+            ProjectionDesc::Isometric(_) => {
+                let distance = (0..3).map(|i| (self.look[i] - self.eye[i]).powi(2)).sum::<f64>().sqrt();
+                camera.look_isometric(self.look, distance)
+            },
+            _ => camera.look_at(self.eye, self.look, self.up)
+        }
+        match self.samples {
+            Some(samples) => camera.set_samples_per_pixel(samples),
+            None => camera.set_supersampling(self.supersampling)
+        }
+        camera.set_aperture_radius(self.aperture);
+        if let Some(distance) = self.focal_distance { camera.set_focal_distance(distance) }
+        camera.set_lens_shift(self.shift[0], self.shift[1]);
+        camera.set_lens_tilt(self.tilt[0], self.tilt[1]);
+        camera
+    }
+}
+
+impl MaterialDesc {
+    fn build(&self, textures: &BTreeMap<&str, TextureRef>, materials: &BTreeMap<&str, MaterialRef>) -> Result<Material, Error> {
+        let param = |param: &ParamDesc| -> Result<Param, Error> {
+            match param {
+                ParamDesc::Color(color) => Ok(Param::from(*color)),
+                ParamDesc::Texture(name) => Ok(Param::from(lookup(textures, "texture", name)?))
+            }
+        };
+
+        let material = match &self.kind {
+            MaterialKind::Matte { kd, sigma } => Material::matte(param(kd)?, *sigma),
+            MaterialKind::Plastic { kd, ks, roughness } => Material::plastic(param(kd)?, *ks, *roughness),
+            MaterialKind::Metal { eta, k, u_roughness, v_roughness } =>
+                Material::metal(*eta, *k, *u_roughness, *v_roughness),
+            MaterialKind::Glass { kr, kt, eta } => Material::glass(*kr, *kt, *eta),
+            MaterialKind::Mirror { kr } => Material::mirror(*kr),
+            MaterialKind::Emissive { radiance } => Material::emissive(*radiance),
+            MaterialKind::Mix { a, b, factor } => {
+                let (a, b) = (lookup(materials, "material", a)?, lookup(materials, "material", b)?);
+                Material::mix(a, b, param(factor)?)
+            }
+        };
+
+        let material = match &self.bump {
+            Some(bump) => material.with_bump(lookup(textures, "texture", &bump.texture)?, bump.scale),
+            None => material
+        };
+
+        let material = match &self.displacement {
+            Some(height) => material.with_displacement(lookup(textures, "texture", &height.texture)?, height.scale),
+            None => material
+        };
+
+        let material = match &self.alpha {
+            Some(mask) => material.with_alpha(lookup(textures, "texture", mask)?),
+            None => material
+        };
+
+        let material = match self.dispersion {
+            Some(abbe) => material.with_dispersion(abbe),
+            None => material
+        };
+
+        let material = match self.max_depth {
+            Some(depth) => material.with_max_depth(depth),
+            None => material
+        };
+
+        Ok(material.with_two_sided(self.two_sided))
+    }
+}
+
+/// Lay the coats of the given material entries over their built materials
+fn coat<'d>(
+    materials: &mut BTreeMap<&'d str, Material>,
+    uncoated: &BTreeMap<&'d str, Material>,
+    entries: &[(&'d String, &'d MaterialDesc)]
+) -> Result<(), Error> {
+    for (name, material) in entries.iter() {
+        if let Some(coat) = &material.coat {
+            let base = materials[name.as_str()];
+            let layer = lookup(uncoated, "material", &coat.material)?;
+            materials.insert(name.as_str(), Material::layered(base, layer, coat.thickness));
+        }
+    }
+    Ok(())
+}
+
+/// Scene objects built from the named entries of a file
+struct Names<'d> {
+    materials: BTreeMap<&'d str, MaterialRef>,
+    meshes: BTreeMap<&'d str, ObjRef>
+}
+
+impl Default for GroupDesc {
+    fn default() -> GroupDesc {
+        GroupDesc {
+            name: None,
+            transform: vec![],
+            swap_backface: false,
+            cull_backface: false,
+            cast_shadows: true,
+            receive_shadows: true,
+            contents: vec![]
+        }
+    }
+}
+
+impl GroupDesc {
+    fn build(&self, names: &Names) -> Result<Aggregate, Error> {
+        let mut group = Aggregate::new();
+        for step in self.transform.iter() {
+            match step {
+                TransformDesc::Translate(delta) => group.translate(*delta),
+                TransformDesc::Scale([x, y, z]) => group.scale(*x, *y, *z),
+                TransformDesc::RotateX(theta) => group.rotate_x(*theta),
+                TransformDesc::RotateY(theta) => group.rotate_y(*theta),
+                TransformDesc::RotateZ(theta) => group.rotate_z(*theta),
+                TransformDesc::Rotate { angle, axis } => group.rotate(*angle, *axis)
+            };
+        }
+        group.swap_backface = self.swap_backface;
+        group.cull_backface = self.cull_backface;
+        group.cast_shadows = self.cast_shadows;
+        group.receive_shadows = self.receive_shadows;
+
+        let material = |name: &str| lookup(&names.materials, "material", name);
+        for node in self.contents.iter() {
+            match node {
+                NodeDesc::Sphere { center, radius, material: m } =>
+                    group.add_sphere(*center, *radius, material(m)?),
+                NodeDesc::Cube { origin, size, material: m } =>
+                    group.add_cube(*origin, *size, material(m)?),
+                NodeDesc::Box { min, max, material: m } =>
+                    group.add_box(*min, *max, material(m)?),
+                NodeDesc::Plane { center, normal, size, material: m } =>
+                    group.add_plane(*center, *normal, *size, material(m)?),
+                NodeDesc::Disk { center, normal, radius, material: m } =>
+                    group.add_disk(*center, *normal, *radius, material(m)?),
+                NodeDesc::Mesh { mesh, material: m } => {
+                    let mesh = lookup(&names.meshes, "mesh", mesh)?;
+                    match m {
+                        Some(m) => group.add_obj_of(mesh, material(m)?),
+                        None => group.add_obj(mesh)
+                    }
+                },
+                NodeDesc::Group(child) => match &child.name {
+                    Some(name) => group.add_named(name, SceneNode::Group(child.build(names)?)),
+                    None => group.add_group(child.build(names)?)
+                }
+            }
+        }
+        Ok(group)
+    }
+}
+
+fn lookup<T: Copy>(map: &BTreeMap<&str, T>, kind: &'static str, name: &str) -> Result<T, Error> {
+    map.get(name).copied().ok_or_else(|| Error::Undefined { kind, name: name.to_string() })
+}
+
+#[cfg(feature = "bin")]
+fn load_texture(scene: &mut Scene, path: &Path, alpha: bool) -> Result<TextureRef, Error> {
+    Ok(if alpha { scene.load_alpha_texture(path)? } else { scene.load_texture(path)? })
+}
+
+#[cfg(feature = "bin")]
+fn load_image_texture(scene: &mut Scene, path: &Path, filter: Option<TextureFilter>) -> Result<TextureRef, Error> {
+    match filter {
+        Some(filter) => Ok(scene.add_texture(Box::new(crate::texture::ImageTexture::open(path)?.with_filter(filter)))),
+        None => load_texture(scene, path, false)
+    }
+}
+
+#[cfg(not(feature = "bin"))]
+fn load_texture(_scene: &mut Scene, _path: &Path, _alpha: bool) -> Result<TextureRef, Error> {
+    Err(Error::Unsupported("image textures"))
+}
+
+#[cfg(not(feature = "bin"))]
+fn load_image_texture(_scene: &mut Scene, _path: &Path, _filter: Option<TextureFilter>) -> Result<TextureRef, Error> {
+    Err(Error::Unsupported("image textures"))
+}
+
+#[cfg(feature = "bin")]
+fn load_environment_map(scene: &mut Scene, path: &Path, samples: usize) -> Result<(), Error> {
+    Ok(scene.load_environment_map(path, samples)?)
+}
+
+#[cfg(not(feature = "bin"))]
+fn load_environment_map(_scene: &mut Scene, _path: &Path, _samples: usize) -> Result<(), Error> {
+    Err(Error::Unsupported("environment maps"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scene::node::Shape;
+
+    const SCENE: &str = r#"{
+        "camera": { "projection": { "perspective": 45 }, "eye": [0, 0, 5], "look": [0, 0, 0] },
+        "integrator": "path",
+        "roulette_depth": 5,
+        "tonemap": { "operator": "reinhard" },
+        "lights": [
+            { "type": "point", "position": [0, 5, 0], "intensity": [1, 1, 1] },
+            { "type": "point", "position": [0, 3, 3], "intensity": [1, 1, 1], "power": { "lumens": 800 } }
+        ],
+        "meshes": { "triangle": { "obj": "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n", "smoothing": { "smooth": 30 } } },
+        "textures": {
+            "checks": { "type": "checkerboard", "even": [1, 1, 1], "odd": [0, 0, 0], "scale": 4 },
+            "boxed": { "type": "projected", "texture": "checks", "projection": { "type": "triplanar", "scale": 1, "sharpness": 4 } }
+        },
+        "materials": {
+            "floor": { "type": "matte", "kd": "checks", "bump": { "texture": "checks", "scale": 0.1 } },
+            "red": {
+                "type": "plastic", "kd": [0.7, 0.1, 0.1], "ks": [0.3, 0.3, 0.3], "roughness": 0.2,
+                "coat": { "material": "clear", "thickness": 0.1 }, "max_depth": 8
+            },
+            "clear": { "type": "glass", "kr": [1, 1, 1], "kt": [0.9, 0.9, 0.8], "eta": 1.5 },
+            "worn": { "type": "mix", "a": "red", "b": "floor", "factor": "boxed" }
+        },
+        "root": {
+            "contents": [
+                { "type": "plane", "center": [0, -1, 0], "normal": [0, 1, 0], "size": [4, 4], "material": "floor" },
+                { "type": "group", "name": "ball", "transform": [{ "translate": [1, 0, 0] }, { "rotate_y": 90 }], "contents": [
+                    { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "red" }
+                ]},
+                { "type": "mesh", "mesh": "triangle" }
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn round_trip() {
+        let desc = from_str(SCENE).unwrap();
+        assert_eq!(desc.integrator, Integrator::Path);
+        assert_eq!(desc.recursion, 3);
+        assert_eq!(from_str(&to_string(&desc).unwrap()).unwrap(), desc);
+    }
+
+    #[test]
+    fn lists_paths() {
+        assert!(from_str(SCENE).unwrap().paths().is_empty());
+        let desc = from_str(r#"{
+            "camera": { "projection": { "perspective": 40 }, "eye": [0, 0, 5], "look": [0, 0, 0] },
+            "textures": { "bricks": { "type": "image", "path": "bricks.png" } },
+            "meshes": { "bunny": "meshes/bunny.obj", "head": { "path": "../head.obj", "subdivide": 2 } }
+        }"#).unwrap();
+        assert_eq!(desc.paths(), vec!["bricks.png", "meshes/bunny.obj", "../head.obj"]);
+    }
+
+    #[test]
+    fn builds_scene() {
+        let scene = from_str(SCENE).unwrap().build(Path::new("")).unwrap();
+        assert_eq!(scene.integrator, Integrator::Path);
+        assert_eq!(scene.roulette_depth, 5);
+        assert_eq!(scene.lights().len(), 2);
+        assert_eq!(scene.root.contents.len(), 3);
+        assert_eq!(scene.mesh(ObjRef(0)).unwrap().triangles.positions.len(), 9);
+        assert!(scene.mesh(ObjRef(0)).unwrap().triangles.normals.is_some());
+        match &scene.root.contents[1] {
+            SceneNode::Group(group) => match group.contents[0] {
+                SceneNode::Geometry(Shape::Sphere(_, radius), material) => {
+                    assert_eq!(radius, 1.0);
+                    assert_eq!(scene.material(material).max_depth(), Some(8));
+                },
+                _ => panic!("expected a sphere")
+            },
+            _ => panic!("expected a group")
+        }
+        assert!(matches!(scene.find_node("ball").and_then(|ball| scene.node(&ball)), Some(SceneNode::Group(_))));
+    }
+
+    #[test]
+    fn undefined_material() {
+        let json = SCENE.replace("\"material\": \"red\"", "\"material\": \"blue\"");
+        match from_str(&json).unwrap().build(Path::new("")) {
+            Err(Error::Undefined { kind: "material", name }) => assert_eq!(name, "blue"),
+            _ => panic!("expected an undefined material error")
+        }
+    }
+}
@@ -1,18 +1,37 @@
 // This module contains structures for providing a simple representation of the
 // contents of a scene. The elements here are later used to build up a full scene
-use cgmath::{prelude::*, Deg};
-use crate::{space::*, Material};
+use std::collections::HashMap;
+use cgmath::{Deg, Matrix4};
+use crate::{space::{*, animated::AnimatedTransform}, animation::Track, shape::{sdf::Sdf, curve::{Curve, CurveKind}}, MaterialRef};
 use super::{ObjRef as Obj};
 
+// Groups stay inline so that scenes can build and match them directly
+#[allow(clippy::large_enum_variant)]
 pub enum SceneNode {
-    /// A geometric shape its material
-    Geometry(Shape, Material),
-    /// Reference to a triangle mesh loaded in the scene
-    Mesh(Obj, Option<Material>),
+    /// A geometric shape and a reference to its material in the scene
+    Geometry(Shape, MaterialRef),
+    /// Reference to a triangle mesh loaded in the scene, with the material
+    /// for triangles that don't have their own
+    Mesh(Obj, Option<MaterialRef>),
     /// A collection of multiple scene nodes
     Group(Aggregate)
 }
 
+impl SceneNode {
+    /// Render the node with the given material. Groups pass it on to all of
+    /// their contents, and meshes keep any materials from their .mtl
+    /// libraries.
+    pub fn set_material(&mut self, material: MaterialRef) {
+        match self {
+            SceneNode::Geometry(_, m) => *m = material,
+            SceneNode::Mesh(_, m) => *m = Some(material),
+            SceneNode::Group(aggregate) => {
+                for node in aggregate.contents.iter_mut() { node.set_material(material) }
+            }
+        }
+    }
+}
+
 pub enum Shape {
     /// Sphere with origin and radius
     Sphere([f64; 3], f64),
@@ -20,6 +39,15 @@ pub enum Shape {
     Cube([f64; 3], f64),
     /// Similar to cube: a rectagular prism with start and end corners
     Cuboid([f64; 3], [f64; 3]),
+    /// Rectangle with centre, normal, and width and height
+    Plane([f64; 3], [f64; 3], [f64; 2]),
+    /// Circle with centre, normal and radius
+    Disk([f64; 3], [f64; 3], f64),
+    /// Surface of a signed distance function
+    Sdf(Sdf),
+    /// Cubic Bezier curve with control points, start and end width and
+    /// cross-section
+    Curve([[f64; 3]; 4], [f64; 2], CurveKind),
 }
 
 pub struct Aggregate {
@@ -29,7 +57,30 @@ pub struct Aggregate {
     /// If true, reverses orientation of normal shading vectors for all
     /// children. Useful for capturing the inside or backface of a shape/mesh.
     /// Also known as "swap handedness".
-    pub swap_backface: bool
+    pub swap_backface: bool,
+
+    /// If true, rays pass through the backs of all children as if they
+    /// weren't there, such as to see into a room through its outer wall
+    pub cull_backface: bool,
+
+    /// Transformation at time 0 of a moving group, set by `keyframe`. The
+    /// group then moves to `transform` at time 1.
+    pub start: Option<Transformation>,
+
+    /// Transformations of the group over the frames of an animation, set by
+    /// `key_transform`
+    pub animation: Track<Transformation>,
+
+    /// If false, the group's contents don't block light from reaching other
+    /// objects. Useful to hide light blockers or geometry that only appears
+    /// in reflections.
+    pub cast_shadows: bool,
+
+    /// If false, no objects shadow the group's contents, including each other
+    pub receive_shadows: bool,
+
+    /// Index in `contents` of each child added with a name
+    names: HashMap<String, usize>
 }
 
 impl Aggregate {
@@ -37,7 +88,13 @@ impl Aggregate {
         Aggregate {
             contents: vec![],
             transform: Transformation::identity(),
-            swap_backface: false
+            swap_backface: false,
+            cull_backface: false,
+            start: None,
+            animation: Track::new(),
+            cast_shadows: true,
+            receive_shadows: true,
+            names: HashMap::new()
         }
     }
 
@@ -46,41 +103,181 @@ impl Aggregate {
         self.contents.push(node)
     }
 
+    /// Add the given node under a name by which `Scene::find_node` can find
+    /// it. Names are unique within a group; a later node with the same name
+    /// takes it over.
+    pub fn add_named(&mut self, name: &str, node: SceneNode) {
+        self.names.insert(name.to_string(), self.contents.len());
+        self.add(node)
+    }
+
+    /// Index in `contents` of the child with the given name
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied().filter(|index| *index < self.contents.len())
+    }
+
     pub fn add_group(&mut self, aggregate: Aggregate) {
         self.add(SceneNode::Group(aggregate))
     }
 
-    pub fn add_sphere(&mut self, center: [f64; 3], radius: f64, material: Material) {
+    pub fn add_sphere(&mut self, center: [f64; 3], radius: f64, material: MaterialRef) {
         let shape = Shape::Sphere(center, radius);
         self.add(SceneNode::Geometry(shape, material))
     }
 
-    pub fn add_cube(&mut self, origin: [f64; 3], dim: f64, material: Material) {
+    pub fn add_cube(&mut self, origin: [f64; 3], dim: f64, material: MaterialRef) {
         let shape = Shape::Cube(origin, dim);
         self.add(SceneNode::Geometry(shape, material))
     }
 
-    pub fn add_box(&mut self, minbound: [f64; 3], maxbound: [f64; 3], material: Material) {
+    pub fn add_box(&mut self, minbound: [f64; 3], maxbound: [f64; 3], material: MaterialRef) {
         let shape = Shape::Cuboid(minbound, maxbound);
         self.add(SceneNode::Geometry(shape, material))
     }
 
+    /// Add a flat rectangle centred at the given point and facing the
+    /// direction of the normal. Floors facing +y span width along x and
+    /// height along z.
+    pub fn add_plane(&mut self, center: [f64; 3], normal: [f64; 3], size: [f64; 2], material: MaterialRef) {
+        let shape = Shape::Plane(center, normal, size);
+        self.add(SceneNode::Geometry(shape, material))
+    }
+
+    pub fn add_disk(&mut self, center: [f64; 3], normal: [f64; 3], radius: f64, material: MaterialRef) {
+        let shape = Shape::Disk(center, normal, radius);
+        self.add(SceneNode::Geometry(shape, material))
+    }
+
+    /// Add the surface where the given signed distance function is zero, such
+    /// as smoothly blended shapes or a fractal
+    pub fn add_sdf(&mut self, sdf: Sdf, material: MaterialRef) {
+        self.add(SceneNode::Geometry(Shape::Sdf(sdf), material))
+    }
+
+    /// Add a strand made of cubic Bezier curves, such as a hair or a blade of
+    /// grass. Takes 4 control points for the first curve and 3 more for each
+    /// following curve, which starts at the end of the previous one. The width
+    /// changes linearly from the start of the strand to its end.
+    pub fn add_curves(&mut self, points: &[[f64; 3]], width: [f64; 2], kind: CurveKind, material: MaterialRef) {
+        debug_assert!(points.len() >= 4 && (points.len() - 1).is_multiple_of(3));
+        let segments: Vec<[[f64; 3]; 4]> = points.windows(4).step_by(3)
+            .map(|p| [p[0], p[1], p[2], p[3]])
+            .collect();
+        self.add_segments(segments, width, kind, material)
+    }
+
+    /// Add a strand that follows the uniform cubic B-spline with the given
+    /// control points, of which there must be at least 4. See `add_curves`.
+    pub fn add_bspline_curves(&mut self, points: &[[f64; 3]], width: [f64; 2], kind: CurveKind, material: MaterialRef) {
+        debug_assert!(points.len() >= 4);
+        self.add_segments(Curve::bspline_segments(points), width, kind, material)
+    }
+
+    /// Add each of the given Bezier curves, splitting the width between them
+    fn add_segments(&mut self, segments: Vec<[[f64; 3]; 4]>, width: [f64; 2], kind: CurveKind, material: MaterialRef) {
+        let n = segments.len() as f64;
+        for (i, cp) in segments.into_iter().enumerate() {
+            let u = [i as f64 / n, (i + 1) as f64 / n];
+            let width = [lerp(u[0], width[0], width[1]), lerp(u[1], width[0], width[1])];
+            self.add(SceneNode::Geometry(Shape::Curve(cp, width, kind), material))
+        }
+    }
+
     /// Add a simple mesh that provides its own material properties (or defaults
     /// to a simple material provided by Material::default())
     pub fn add_obj(&mut self, mesh: Obj) {
         self.add(SceneNode::Mesh(mesh, None))
     }
 
-    /// Add a simple mesh that's made of a single material
-    pub fn add_obj_of(&mut self, mesh: Obj, material: Material) {
+    /// Add a simple mesh that's made of a single material of the scene
+    pub fn add_obj_of(&mut self, mesh: Obj, material: MaterialRef) {
         self.add(SceneNode::Mesh(mesh, Some(material)))
     }
 
+    /// Mark the transformations applied so far as the position of the group
+    /// at time 0. Transformations applied after this move the group over time,
+    /// reaching their full effect at time 1. The group appears blurred along
+    /// its motion while the camera shutter is open. See `Camera::set_shutter`.
+    #[inline]
+    pub fn keyframe(&mut self) -> &mut Self {
+        self.start = Some(self.transform.clone()); self
+    }
+
+    /// Record the transformations applied so far as the pose of the group at
+    /// the given time of an animation, in seconds. See `Scene::set_time`.
+    #[inline]
+    pub fn key_transform(&mut self, time: f64) -> &mut Self {
+        self.animation.key(time, self.transform.clone()); self
+    }
+
+    /// Pose this group and its descendants at the given time of their
+    /// animations. Groups without keys keep their transformation.
+    pub fn set_time(&mut self, time: f64) {
+        if let Some(transform) = self.animation.value_at(time) {
+            self.transform = transform
+        }
+        for node in self.contents.iter_mut() {
+            if let SceneNode::Group(aggregate) = node { aggregate.set_time(time) }
+        }
+    }
+
+    /// Whether the group only places its contents, without motion or changes
+    /// to their shading or shadows
+    pub(crate) fn is_plain(&self) -> bool {
+        !self.swap_backface && !self.cull_backface && self.start.is_none()
+            && self.cast_shadows && self.receive_shadows
+    }
+
+    /// Transformation that moves the group between its keyframes, if any
+    pub fn motion(&self) -> Option<AnimatedTransform> {
+        self.start.as_ref().map(|start| AnimatedTransform::new(start.clone(), 0.0, self.transform.clone(), 1.0))
+    }
+
     #[inline]
     pub fn swap_backface(&mut self) {
         self.swap_backface = !self.swap_backface
     }
 
+    /// Whether rays pass through the backs of the group's contents
+    #[inline]
+    pub fn set_cull_backface(&mut self, cull: bool) {
+        self.cull_backface = cull
+    }
+
+    /// Whether the group's contents block light from reaching other objects
+    #[inline]
+    pub fn set_cast_shadows(&mut self, cast: bool) {
+        self.cast_shadows = cast
+    }
+
+    /// Whether other objects may block light from reaching the group's
+    /// contents
+    #[inline]
+    pub fn set_receive_shadows(&mut self, receive: bool) {
+        self.receive_shadows = receive
+    }
+
+    /// Undo the transformations applied so far
+    #[inline]
+    pub fn reset_transform(&mut self) -> &mut Self {
+        self.transform = Transformation::identity(); self
+    }
+
+    /// Replace the transformations applied so far with the given matrix,
+    /// given as four columns the way glTF and cgmath lay them out. Panics if
+    /// the matrix can't be inverted, such as one that scales to nothing.
+    #[inline]
+    pub fn set_transform(&mut self, matrix: &[[f64; 4]; 4]) -> &mut Self {
+        self.transform = Transformation::from_slice(matrix); self
+    }
+
+    /// Apply the given matrix after the transformations so far, like the
+    /// other transformation methods. See `set_transform`.
+    #[inline]
+    pub fn apply_matrix(&mut self, matrix: &[[f64; 4]; 4]) -> &mut Self {
+        self.transform.concat_self(&Transformation::from_slice(matrix)); self
+    }
+
     #[inline]
     pub fn translate(&mut self, delta: [f64; 3]) -> &mut Self {
         let delta = Vector::new(delta[0], delta[1], delta[2]);
@@ -112,4 +309,26 @@ impl Aggregate {
         let axis = Vector { x: axis[0], y: axis[1], z: axis[2] };
         self.transform.concat_self(&Transformation::rotate(Deg(theta), axis)); self
     }
+
+    /// Turn the group so that its +z axis points from the eye toward the
+    /// target and its +y axis leans toward up, then move its origin to the
+    /// eye. Does nothing if the eye and target are the same point.
+    pub fn look_at(&mut self, eye: [f64; 3], target: [f64; 3], up: [f64; 3]) -> &mut Self {
+        let (eye, target) = (Point::from(eye), Point::from(target));
+        if eye == target { return self }
+        let z = (target - eye).normalize();
+        let x = Vector::from(up).cross(z);
+        let x = if x.magnitude2() > 0.0 { x.normalize() } else { coordinate_system(&z).0 };
+        let y = z.cross(x);
+        let m = Matrix4::from_cols(x.extend(0.0), y.extend(0.0), z.extend(0.0), eye.to_homogeneous());
+        self.transform.concat_self(&Transformation::from_matrix(m)); self
+    }
+
+    /// Rotate the group by the given angle in degrees around the axis through
+    /// the given centre, such as to move it along a circle around a subject
+    pub fn orbit(&mut self, center: [f64; 3], axis: [f64; 3], theta: f64) -> &mut Self {
+        self.translate([-center[0], -center[1], -center[2]])
+            .rotate(theta, axis)
+            .translate(center)
+    }
 }
@@ -0,0 +1,126 @@
+//! Ready-made scenes for checking the renderer and previewing materials. Each
+//! is an ordinary scene, so it may be changed further before rendering.
+//! Objects meant to be swapped out are named, e.g., to give the sphere of the
+//! furnace test another material:
+//!
+//! ```
+//! use lasgun::{scenes, Material};
+//!
+//! let mut scene = scenes::furnace_test();
+//! let sphere = scene.find_node(scenes::SUBJECT).unwrap();
+//! let red = scene.add_material(Material::plastic([0.8, 0.2, 0.2], [0.2, 0.2, 0.2], 0.1));
+//! scene.set_node_material(&sphere, red);
+//! ```
+
+use crate::{Integrator, Material, scene::{Scene, Aggregate, SceneNode}};
+
+/// Name of the object under test in the furnace test and material preview
+/// scenes, for `Scene::find_node`
+pub const SUBJECT: &str = "subject";
+
+/// The Cornell box: a white room two units wide, tall and deep with a red
+/// wall on the left, a green wall on the right and an open front, lit by a
+/// rectangular lamp in the ceiling and holding a tall and a short white box.
+/// Rendered with path tracing, since most of the room is lit indirectly.
+pub fn cornell_box() -> Scene {
+    let mut scene = Scene::new();
+    scene.set_integrator(Integrator::Path);
+    scene.set_max_recursion_depth(5);
+    scene.set_solid_background([0., 0., 0.]);
+    let camera = scene.set_perspective_camera(40.);
+    camera.look_at([0., 1., 3.8], [0., 1., 0.], [0., 1., 0.]);
+    camera.set_samples_per_pixel(16);
+
+    let white = scene.add_material(Material::matte([0.73, 0.73, 0.73], 0.));
+    let red = scene.add_material(Material::matte([0.63, 0.065, 0.05], 0.));
+    let green = scene.add_material(Material::matte([0.14, 0.45, 0.091], 0.));
+    scene.root.add_plane([0., 0., 0.], [0., 1., 0.], [2., 2.], white);
+    scene.root.add_plane([0., 2., 0.], [0., -1., 0.], [2., 2.], white);
+    scene.root.add_plane([0., 1., -1.], [0., 0., 1.], [2., 2.], white);
+    scene.root.add_plane([-1., 1., 0.], [1., 0., 0.], [2., 2.], red);
+    scene.root.add_plane([1., 1., 0.], [-1., 0., 0.], [2., 2.], green);
+
+    // Just below the ceiling so that the two don't overlap
+    let light = scene.add_material(Material::emissive([34., 24., 8.]));
+    let mut lamp = Aggregate::new();
+    lamp.add_plane([0., 1.999, 0.], [0., -1., 0.], [0.5, 0.4], light);
+    scene.root.add_named("lamp", SceneNode::Group(lamp));
+
+    let mut tall = Aggregate::new();
+    tall.add_box([-0.3, 0., -0.3], [0.3, 1.2, 0.3], white);
+    tall.rotate_y(15.).translate([-0.33, 0., -0.3]);
+    scene.root.add_named("tall", SceneNode::Group(tall));
+
+    let mut short = Aggregate::new();
+    short.add_box([-0.3, 0., -0.3], [0.3, 0.6, 0.3], white);
+    short.rotate_y(-18.).translate([0.35, 0., 0.3]);
+    scene.root.add_named("short", SceneNode::Group(short));
+
+    scene
+}
+
+/// The white furnace test: a white diffuse sphere lit by uniform white light
+/// from every direction. Surfaces that neither absorb nor emit light reflect
+/// exactly what arrives, so the sphere disappears into the background. Parts
+/// that show up darker lose energy and brighter parts gain it, e.g., from a
+/// BSDF that doesn't conserve energy. Give the sphere, named `SUBJECT`,
+/// other materials to test them, and raise the recursion depth for materials
+/// that transmit light into the sphere. `Integrator::Furnace` shows the light
+/// scattered at each point on its own, without the noise of path tracing.
+pub fn furnace_test() -> Scene {
+    let mut scene = Scene::new();
+    scene.set_integrator(Integrator::Path);
+    scene.set_max_recursion_depth(8);
+    scene.set_solid_background([1., 1., 1.]);
+    let camera = scene.set_perspective_camera(30.);
+    camera.look_at([0., 0., 4.], [0., 0., 0.], [0., 1., 0.]);
+    camera.set_samples_per_pixel(16);
+
+    let white = scene.add_material(Material::matte([1., 1., 1.], 0.));
+    let mut sphere = Aggregate::new();
+    sphere.add_sphere([0., 0., 0.], 1., white);
+    scene.root.add_named(SUBJECT, SceneNode::Group(sphere));
+    scene
+}
+
+/// A sphere of the given material on a checkered floor under a bright sky
+/// and a key light, like the material previews of modelling tools. The
+/// checks show off reflection and refraction. Rendered with the default
+/// Whitted-style integrator, which previews quickly without noise. The sphere
+/// is named `SUBJECT`.
+pub fn material_preview(material: Material) -> Scene {
+    let mut scene = Scene::new();
+    scene.set_max_recursion_depth(6);
+    scene.set_ambient_light([0.2, 0.2, 0.2]);
+    scene.set_radial_background([0.9, 0.95, 1.], [0.35, 0.45, 0.6], 1.);
+    let camera = scene.set_perspective_camera(35.);
+    camera.look_at([0., 1.6, 4.5], [0., 0.8, 0.], [0., 1., 0.]);
+    camera.set_samples_per_pixel(16);
+
+    scene.add_point_light([3., 5., 4.], [0.8, 0.8, 0.8], [1., 0., 0.]);
+    let checks = scene.add_checkerboard_texture([0.8, 0.8, 0.8], [0.2, 0.2, 0.2], 10.);
+    let floor = scene.add_material(Material::matte(checks, 0.));
+    scene.root.add_plane([0., 0., 0.], [0., 1., 0.], [10., 10.], floor);
+
+    let material = scene.add_material(material);
+    let mut sphere = Aggregate::new();
+    sphere.add_sphere([0., 1., 0.], 1., material);
+    scene.root.add_named(SUBJECT, SceneNode::Group(sphere));
+    scene
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{capture, HdrFilm};
+
+    #[test]
+    fn white_sphere_vanishes_in_furnace() {
+        let mut scene = furnace_test();
+        scene.camera.set_samples_per_pixel(4);
+        let mut film = HdrFilm::new(16, 16);
+        capture(&scene, &mut film);
+        let mean = film.data().iter().map(|p| p[0] + p[1] + p[2]).sum::<f32>() / (3. * 256.);
+        assert!((mean - 1.).abs() < 0.02, "mean radiance {}", mean);
+    }
+}
@@ -3,7 +3,7 @@ use std::f64;
 use crate::space::*;
 use crate::interaction::RayIntersection;
 use crate::primitive::{Primitive, OptionalPrimitive};
-use crate::material::Material;
+use crate::material::MaterialRef;
 
 /**
 aka "Box", aka "Rectangular prism"
@@ -11,17 +11,17 @@ aka "Box", aka "Rectangular prism"
 #[derive(Debug)]
 pub struct Cuboid {
     pub bounds: Bounds,
-    pub mat: Material
+    pub mat: MaterialRef
 }
 
 impl Cuboid {
-    pub fn new(minbound: [f64; 3], maxbound: [f64; 3], mat: Material) -> Cuboid {
+    pub fn new(minbound: [f64; 3], maxbound: [f64; 3], mat: MaterialRef) -> Cuboid {
         let minbound = Point::new(minbound[0], minbound[1], minbound[2]);
         let maxbound = Point::new(maxbound[0], maxbound[1], maxbound[2]);
         Cuboid { bounds: Bounds::new(minbound, maxbound), mat }
     }
 
-    pub fn cube(origin: [f64; 3], dim: f64, mat: Material) -> Cuboid {
+    pub fn cube(origin: [f64; 3], dim: f64, mat: MaterialRef) -> Cuboid {
         let origin = Point::new(origin[0], origin[1], origin[2]);
         Cuboid {
             bounds: Bounds::new(origin, origin + Vector::from_value(dim)),
@@ -46,7 +46,43 @@ impl Primitive for Cuboid {
         self.bounds.intersects(ray)
     }
 
-    fn material(&self) -> Option<Material> { Some(self.mat) }
+    fn material(&self) -> Option<MaterialRef> { Some(self.mat) }
+
+    fn area(&self) -> f64 {
+        self.bounds.surface_area()
+    }
+
+    fn sample_area(&self, u: &Point2f) -> Option<(Point, Vector)> {
+        let d = self.bounds.diagonal();
+        let area = self.area();
+        if area == 0.0 { return None }
+
+        // Choose one of the six faces in proportion to its area, then reuse
+        // what's left of u.x to pick a point within that face
+        let faces = [d.y * d.z, d.x * d.z, d.x * d.y];
+        let mut x = u.x * area;
+        let mut face = 0;
+        while face < 5 && x >= faces[face / 2] {
+            x -= faces[face / 2];
+            face += 1;
+        }
+
+        let axis = face / 2;
+        let (u0, u1) = ((x / faces[axis]).min(1.0), u.y);
+        let (a1, a2) = ((axis + 1) % 3, (axis + 2) % 3);
+
+        let mut p = self.bounds.min;
+        let mut n = Vector::zero();
+        if face % 2 == 0 {
+            n[axis] = -1.0;
+        } else {
+            p[axis] = self.bounds.max[axis];
+            n[axis] = 1.0;
+        }
+        p[a1] += u0 * d[a1];
+        p[a2] += u1 * d[a2];
+        Some((p, n))
+    }
 }
 
 impl Primitive for Bounds {
@@ -56,9 +92,11 @@ impl Primitive for Bounds {
         let mut tnear = f64::NEG_INFINITY;
         let mut tfar = f64::INFINITY;
 
-        // The dpdu and dpdv values for the near and far planes
+        // The dpdu and dpdv values for the near and far planes, along with
+        // the axis and position of each plane
         let mut near_differentials = CUBE_DIFFERENTIALS[0];
         let mut far_differentials = CUBE_DIFFERENTIALS[0];
+        let (mut near_plane, mut far_plane) = ((0, 0.0), (0, 0.0));
 
         // i ranges from X to Z
         for i in 0..3 {
@@ -66,37 +104,48 @@ impl Primitive for Bounds {
             let t1 = (self.min[i] - ray.origin[i]) * ray.dinv[i];
             let t2 = (self.max[i] - ray.origin[i]) * ray.dinv[i];
 
-            let (tmin, tmax, dp0, dp1) = if t1 < t2 {
-                (t1, t2, dp.1, dp.0)
+            let (tmin, tmax, dp0, dp1, pmin, pmax) = if t1 < t2 {
+                (t1, t2, dp.1, dp.0, self.min[i], self.max[i])
             } else {
-                (t2, t1, dp.0, dp.1)
+                (t2, t1, dp.0, dp.1, self.max[i], self.min[i])
             };
 
             // Check for better intersection axes
-            if tmin > tnear { near_differentials = (dp0, dp1) }
-            if tmax < tfar { far_differentials = (dp1, dp0) }
+            if tmin > tnear { near_differentials = (dp0, dp1); near_plane = (i, pmin) }
+            if tmax < tfar { far_differentials = (dp1, dp0); far_plane = (i, pmax) }
 
             tnear = tnear.max(tmin);
             tfar = tfar.min(tmax);
         }
 
         // Check if out of bounds
-        if tnear > tfar || tfar <= 0.0 { return None }
+        if tnear > tfar || tfar <= ray.t_min { return None }
 
         // Intersection, check if it happens behind the ray and set t and
         // differentials accordingly
-        let (t, dp) = if tnear <= 0.0 {
-            (tfar, far_differentials)
+        let (t, dp, (axis, plane)) = if tnear <= ray.t_min {
+            (tfar, far_differentials, far_plane)
         } else {
-            (tnear, near_differentials)
+            (tnear, near_differentials, near_plane)
         };
 
-        // Discard if a nearer intersection already exists
-        if t >= isect.t { return None }
+        // Discard if beyond the ray or a nearer intersection already exists
+        if t > ray.t_max || t >= isect.t { return None }
+
+        // The hit point lies exactly on the plane of the face it hits. The
+        // other two coordinates carry the error of the ray equation.
+        let mut p = ray.origin + ray.d * t;
+        let mut p_err = gamma::<f64>(5) * (abs(&ray.origin.to_vec()) + abs(&(ray.d * t)));
+        p[axis] = plane;
+        p_err[axis] = 0.0;
+
+        // Map the intersection point onto the face along its two differentials
+        let offset = self.offset(&p);
+        let uv = Point2f::new(offset.dot(dp.0), offset.dot(dp.1));
 
-        // TODO: uvs
-        *isect = RayIntersection::new(t, Point2f::new(0.0, 0.0), dp.0, dp.1);
-        isect.n = Some(normal::Normal3(dp.0.cross(dp.1)).face_forward(-ray.d));
+        *isect = RayIntersection::new(t, p, uv, dp.0, dp.1);
+        isect.p_err = p_err;
+        isect.n = Some(normal::Normal3(dp.0.cross(dp.1)));
 
         Some(self)
     }
@@ -117,7 +166,7 @@ impl Primitive for Bounds {
             tfar = tfar.min(tmax);
         }
 
-        tnear <= tfar && tfar > 0.0
+        tnear <= tfar && tfar > ray.t_min && tnear <= ray.t_max
     }
 }
 
@@ -135,7 +184,7 @@ mod test {
 
     #[test]
     fn straight_on_intersection() {
-        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], Material::default());
+        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], MaterialRef::default());
         let ray = Ray::new(Point::new(0.0, 0.0, -2.0), Vector::new(0.0, 0.0, 1.0));
         let mut isect = RayIntersection::default();
 
@@ -146,7 +195,7 @@ mod test {
 
     #[test]
     fn edge_intersection() {
-        let cube = Cuboid::new([-1.1, -1.1, -1.0], [1.1, 1.1, 1.0], Material::default());
+        let cube = Cuboid::new([-1.1, -1.1, -1.0], [1.1, 1.1, 1.0], MaterialRef::default());
         let ray = Ray::new(Point::new(0.0, 0.0, -2.0), Vector::new(1.0, 0.0, 1.0));
         let mut isect = RayIntersection::default();
 
@@ -157,7 +206,7 @@ mod test {
 
     #[test]
     fn corner_intersection() {
-        let cube = Cuboid::new([-1.1, -1.1, -1.0], [1.1, 1.1, 1.0], Material::default());
+        let cube = Cuboid::new([-1.1, -1.1, -1.0], [1.1, 1.1, 1.0], MaterialRef::default());
         let ray = Ray::new(Point::new(0.0, 0.0, -2.0), Vector::new(1.0, 1.0, 1.0));
         let mut isect = RayIntersection::default();
 
@@ -168,42 +217,39 @@ mod test {
 
     #[test]
     fn inside_intersection() {
-        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], Material::default());
+        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], MaterialRef::default());
         let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vector::unit_z());
         let mut isect = RayIntersection::default();
 
         assert!(cube.intersect(&ray, &mut isect).is_some());
         assert_eq!(isect.t, 1.0);
-        // I don't know why this fails but it works so I'm leaving it
-        // assert_eq!(isect.ns(), Vector::new(0.0, 0.0, 1.0)); // same as ng
+        assert_eq!(isect.ns(), Vector::new(0.0, 0.0, 1.0)); // same as ng
     }
 
     #[test]
     fn inside_behind_intersection() {
-        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], Material::default());
+        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], MaterialRef::default());
         let ray = Ray::new(Point::new(0.0, 0.0, 0.0), -Vector::unit_y());
         let mut isect = RayIntersection::default();
 
         assert!(cube.intersect(&ray, &mut isect).is_some());
         assert_eq!(isect.t, 1.0);
-        // I don't know why this fails but it works so I'm leaving it
-        // assert_eq!(isect.ns(), Vector::new(0.0, -1.0, 0.0)); // same as ng
+        assert_eq!(isect.ns(), Vector::new(0.0, -1.0, 0.0)); // same as ng
     }
 
     #[test]
     fn inside_intersection_offset() {
-        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], Material::default());
+        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], MaterialRef::default());
         let ray = Ray::new(Point::new(0.5, 0.5, 0.5), Vector::new(1.0, 0.0, 1.0));
         let mut isect = RayIntersection::default();
 
         assert!(cube.intersect(&ray, &mut isect).is_some());
-        // I don't know why this fails but it works so I'm leaving it
-        // assert_eq!(isect.ns(), Vector::new(1.0, 0.0, 0.0)); // same as ng
+        assert_eq!(isect.ns(), Vector::new(1.0, 0.0, 0.0)); // same as ng
     }
 
     #[test]
     fn behind_intersection() {
-        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], Material::default());
+        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], MaterialRef::default());
         let ray = Ray::new(Point::new(0.0, 0.0, 2.0), Vector::new(0.0, 0.0, -1.0));
         let mut isect = RayIntersection::default();
 
@@ -214,7 +260,7 @@ mod test {
 
     #[test]
     fn top_intersection() {
-        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], Material::default());
+        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], MaterialRef::default());
         let ray = Ray::new(Point::new(0.0, 2.0, 0.0), Vector::new(0.0, -1.0, 0.0));
         let mut isect = RayIntersection::default();
 
@@ -225,7 +271,7 @@ mod test {
 
     #[test]
     fn bottom_intersection() {
-        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], Material::default());
+        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], MaterialRef::default());
         let ray = Ray::new(Point::new(0.0, -2.0, 0.0), Vector::new(0.0, 1.0, 0.0));
         let mut isect = RayIntersection::default();
 
@@ -236,7 +282,7 @@ mod test {
 
     #[test]
     fn top_angled_intersection() {
-        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], Material::default());
+        let cube = Cuboid::new([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], MaterialRef::default());
         let ray = Ray::new(Point::new(0.0, 2.0, 2.0), Vector::new(0.0, -0.5, -1.0));
         let mut isect = RayIntersection::default();
 
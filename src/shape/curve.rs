@@ -0,0 +1,305 @@
+use crate::space::*;
+use crate::primitive::{Primitive, OptionalPrimitive};
+use crate::interaction::RayIntersection;
+use crate::MaterialRef;
+
+/// Deepest subdivision of a curve while looking for intersections
+const CURVE_MAX_DEPTH: i32 = 10;
+
+/// Cross-section of a curve as seen by rays
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CurveKind {
+    /// Flat ribbon that always faces the ray. Cheap and good for hair or fur
+    /// seen from a distance.
+    Flat,
+
+    /// Flat ribbon shaded as if it were round, like a thin tube
+    Cylinder
+}
+
+/// Cubic Bezier curve of varying width, such as a strand of hair or a blade
+/// of grass. Rays intersect it by recursively splitting the curve until its
+/// segments are nearly straight, in a coordinate system where the ray runs
+/// along the z axis.
+///
+/// http://www.pbr-book.org/3ed-2018/Shapes/Curves.html
+#[derive(Debug)]
+pub struct Curve {
+    /// Control points
+    pub cp: [Point; 4],
+
+    /// Width at the start and end of the curve
+    pub width: [f64; 2],
+
+    pub kind: CurveKind,
+    pub material: MaterialRef
+}
+
+/// Closest intersection with a curve found so far
+struct CurveHit {
+    /// Distance along the ray, which is the z coordinate in ray space
+    z: f64,
+    u: f64,
+    v: f64,
+    width: f64,
+
+    /// Unit normal in ray space
+    n: Vector
+}
+
+impl Curve {
+    pub fn new(cp: [[f64; 3]; 4], width: [f64; 2], kind: CurveKind, material: MaterialRef) -> Curve {
+        debug_assert!(width[0] >= 0. && width[1] >= 0.);
+        Curve {
+            cp: [cp[0].into(), cp[1].into(), cp[2].into(), cp[3].into()],
+            width,
+            kind,
+            material
+        }
+    }
+
+    /// Control points of the Bezier segments of the uniform cubic B-spline
+    /// with the given control points, which has three fewer segments than
+    /// points.
+    pub fn bspline_segments(points: &[[f64; 3]]) -> Vec<[[f64; 3]; 4]> {
+        points.windows(4).map(|p| {
+            let p: Vec<Point> = p.iter().map(|p| Point::from(*p)).collect();
+            let third = |a: Point, b: Point| a + (b - a) / 3.;
+            let (q1, q2) = (third(p[1], p[2]), third(p[2], p[1]));
+            let start = third(p[1], p[0]).midpoint(q1);
+            let end = q2.midpoint(third(p[2], p[3]));
+            [start.into(), q1.into(), q2.into(), end.into()]
+        }).collect()
+    }
+
+    #[inline]
+    fn width_at(&self, u: f64) -> f64 {
+        lerp(u, self.width[0], self.width[1])
+    }
+
+    /// Find the closest intersection of the given ray with the curve with ray
+    /// parameter below t_max
+    fn intersect_ray(&self, ray: &Ray, t_max: f64) -> Option<CurveHit> {
+        // Coordinate system with the ray origin at the origin and the ray
+        // direction along z. x is perpendicular to both the ray and the curve
+        // so that the curve mostly spans x-y.
+        let length = ray.d.magnitude();
+        let z = ray.d / length;
+        let x = z.cross(self.cp[3] - self.cp[0]);
+        let x = if x.magnitude2() > 0. { x.normalize() } else { coordinate_system(&z).0 };
+        let frame = RayFrame { origin: ray.origin, x, y: z.cross(x), z };
+        let cp = self.cp.map(|p| frame.point(&p));
+
+        let max_width = self.width[0].max(self.width[1]);
+        let (z_min, z_max) = (ray.t_min * length, t_max * length);
+        if !overlaps_ray(&cp, 0.5 * max_width, z_min, z_max) { return None }
+
+        // Split the curve until segments are within a small fraction of the
+        // width of straight
+        let mut l0: f64 = 0.;
+        for i in 0..2 {
+            let d = cp[i] - 2. * cp[i + 1].to_vec() + cp[i + 2].to_vec();
+            l0 = l0.max(d.x.abs()).max(d.y.abs()).max(d.z.abs());
+        }
+        let eps = max_width * 0.05;
+        let r0 = (std::f64::consts::SQRT_2 * 6. * l0 / (8. * eps)).log2().floor() as i32 / 2;
+        let depth = r0.clamp(0, CURVE_MAX_DEPTH);
+
+        let mut hit = None;
+        self.recursive_intersect(&cp, (0., 1.), depth, (z_min, z_max), &mut hit);
+        hit.map(|mut hit: CurveHit| { hit.n = frame.vector_to_world(&hit.n); hit })
+    }
+
+    /// Look for intersections with the part of the curve between parameters
+    /// u0 and u1 with the given control points, splitting it depth more times.
+    /// Hits closer than any found so far between z_min and z_max replace it.
+    fn recursive_intersect(
+        &self,
+        cp: &[Point; 4],
+        (u0, u1): (f64, f64),
+        depth: i32,
+        (z_min, z_max): (f64, f64),
+        hit: &mut Option<CurveHit>
+    ) {
+        if depth > 0 {
+            let split = subdivide_bezier(cp);
+            let u = [u0, 0.5 * (u0 + u1), u1];
+            for seg in 0..2 {
+                let cps = [split[3 * seg], split[3 * seg + 1], split[3 * seg + 2], split[3 * seg + 3]];
+                let half_width = 0.5 * self.width_at(u[seg]).max(self.width_at(u[seg + 1]));
+                let z_max = hit.as_ref().map_or(z_max, |hit| hit.z);
+                if !overlaps_ray(&cps, half_width, z_min, z_max) { continue }
+                self.recursive_intersect(&cps, (u[seg], u[seg + 1]), depth - 1, (z_min, z_max), hit);
+            }
+            return
+        }
+
+        // Beyond the ends of the segment, perpendicular to the curve there
+        let edge = (cp[1].y - cp[0].y) * -cp[0].y + cp[0].x * (cp[0].x - cp[1].x);
+        if edge < 0. { return }
+        let edge = (cp[2].y - cp[3].y) * -cp[3].y + cp[3].x * (cp[3].x - cp[2].x);
+        if edge < 0. { return }
+
+        // Parameter along the nearly-straight segment closest to the ray
+        let segment = Vector2f::new(cp[3].x - cp[0].x, cp[3].y - cp[0].y);
+        let denom = segment.magnitude2();
+        if denom == 0. { return }
+        let w = Vector2f::new(-cp[0].x, -cp[0].y).dot(segment) / denom;
+
+        // Within the width of the curve at that point
+        let u = lerp(w, u0, u1).max(u0).min(u1);
+        let width = self.width_at(u);
+        let (pc, dpcdw) = eval_bezier(cp, w.clamp(0., 1.));
+        let dist2 = pc.x * pc.x + pc.y * pc.y;
+        if dist2 > width * width * 0.25 { return }
+        if pc.z <= z_min || pc.z > hit.as_ref().map_or(z_max, |hit| hit.z) { return }
+
+        // v runs across the curve from one edge to the other
+        let dist = dist2.sqrt();
+        let side = dpcdw.x * -pc.y + pc.x * dpcdw.y;
+        let v = if side > 0. { 0.5 + dist / width } else { 0.5 - dist / width };
+
+        // Flat curves face the ray. Cylinders turn away from it toward their
+        // edges, in the direction from the centre line to the ray.
+        let n = match self.kind {
+            CurveKind::Flat => -Vector::unit_z(),
+            CurveKind::Cylinder => {
+                let s = (2. * dist / width).min(1.);
+                let across = if dist > 0. { Vector::new(-pc.x, -pc.y, 0.) / dist } else { Vector::zero() };
+                across * s - Vector::unit_z() * (1. - s * s).sqrt()
+            }
+        };
+        *hit = Some(CurveHit { z: pc.z, u, v, width, n })
+    }
+}
+
+impl Primitive for Curve {
+    fn bound(&self) -> Bounds {
+        let bounds = Bounds::new(self.cp[0], self.cp[1]).union(&Bounds::new(self.cp[2], self.cp[3]));
+        bounds.expand(0.5 * self.width[0].max(self.width[1]))
+    }
+
+    fn intersect(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive<'_> {
+        let hit = self.intersect_ray(ray, isect.t.min(ray.t_max))?;
+        let t = hit.z / ray.d.magnitude();
+
+        // Tangents whose cross product is the normal, along and across the
+        // curve
+        let (_, dpdu) = eval_bezier(&self.cp, hit.u);
+        let dpdu = if dpdu.magnitude2() > 0. { dpdu } else { self.cp[3] - self.cp[0] };
+        let dpdv = hit.n.cross(dpdu).normalize() * hit.width;
+
+        *isect = RayIntersection::new(t, ray.origin + ray.d * t, Point2f::new(hit.u, hit.v), dpdu, dpdv);
+
+        // The hit point is on the ribbon rather than the curve's surface
+        isect.p_err = Vector::from_value(2. * hit.width);
+        Some(self)
+    }
+
+    fn intersects(&self, ray: &Ray) -> bool {
+        self.intersect_ray(ray, ray.t_max).is_some()
+    }
+
+    fn material(&self) -> Option<MaterialRef> { Some(self.material) }
+}
+
+/// Orthonormal coordinate system of a ray, with the ray direction along z
+struct RayFrame {
+    origin: Point,
+    x: Vector,
+    y: Vector,
+    z: Vector
+}
+
+impl RayFrame {
+    #[inline]
+    fn point(&self, p: &Point) -> Point {
+        let v = p - self.origin;
+        Point::new(v.dot(self.x), v.dot(self.y), v.dot(self.z))
+    }
+
+    #[inline]
+    fn vector_to_world(&self, v: &Vector) -> Vector {
+        self.x * v.x + self.y * v.y + self.z * v.z
+    }
+}
+
+/// Whether the box around the given control points, expanded by the given
+/// distance, may contain points on the ray between z_min and z_max. Points are
+/// in the coordinate system of the ray.
+#[inline]
+fn overlaps_ray(cp: &[Point; 4], expand: f64, z_min: f64, z_max: f64) -> bool {
+    let min = |f: fn(&Point) -> f64| cp.iter().map(f).fold(f64::INFINITY, f64::min) - expand;
+    let max = |f: fn(&Point) -> f64| cp.iter().map(f).fold(f64::NEG_INFINITY, f64::max) + expand;
+    min(|p| p.x) <= 0. && max(|p| p.x) >= 0.
+        && min(|p| p.y) <= 0. && max(|p| p.y) >= 0.
+        && max(|p| p.z) >= z_min && min(|p| p.z) <= z_max
+}
+
+/// Split the Bezier curve with the given control points in half. Returns the
+/// control points of the two halves, which share the middle point.
+fn subdivide_bezier(cp: &[Point; 4]) -> [Point; 7] {
+    let mid = |a: Point, b: Point| a.midpoint(b);
+    let (p01, p12, p23) = (mid(cp[0], cp[1]), mid(cp[1], cp[2]), mid(cp[2], cp[3]));
+    let (p012, p123) = (mid(p01, p12), mid(p12, p23));
+    let center = mid(p012, p123);
+    [cp[0], p01, p012, center, p123, p23, cp[3]]
+}
+
+/// Point on the Bezier curve at parameter u and the derivative there
+fn eval_bezier(cp: &[Point; 4], u: f64) -> (Point, Vector) {
+    let lerp = |a: Point, b: Point| a + (b - a) * u;
+    let cp1 = [lerp(cp[0], cp[1]), lerp(cp[1], cp[2]), lerp(cp[2], cp[3])];
+    let cp2 = [lerp(cp1[0], cp1[1]), lerp(cp1[1], cp1[2])];
+    let d = cp2[1] - cp2[0];
+    let d = if d.magnitude2() > 0. { d * 3. } else { cp[3] - cp[0] };
+    (lerp(cp2[0], cp2[1]), d)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn straight(kind: CurveKind) -> Curve {
+        Curve::new([[0., 0., 0.], [1., 0., 0.], [2., 0., 0.], [3., 0., 0.]], [0.4, 0.2], kind, MaterialRef::default())
+    }
+
+    #[test]
+    fn intersects_within_width() {
+        let curve = straight(CurveKind::Flat);
+        let mut isect = RayIntersection::default();
+        let ray = Ray::new(Point::new(1.5, 0.1, 5.), Vector::new(0., 0., -2.));
+        assert!(curve.intersect(&ray, &mut isect).is_some());
+        assert!((isect.t - 2.5).abs() < 1e-9);
+        assert!((isect.uv.x - 0.5).abs() < 1e-6);
+        assert!((isect.ng().z.abs() - 1.).abs() < 1e-9);
+
+        // Narrower toward the end
+        let ray = Ray::new(Point::new(2.9, 0.15, 5.), Vector::new(0., 0., -1.));
+        assert!(!curve.intersects(&ray));
+        let ray = Ray::new(Point::new(0.1, 0.15, 5.), Vector::new(0., 0., -1.));
+        assert!(curve.intersects(&ray));
+    }
+
+    #[test]
+    fn cylinder_normals_turn_to_the_edges() {
+        let curve = straight(CurveKind::Cylinder);
+        let mut isect = RayIntersection::default();
+        let ray = Ray::new(Point::new(1., 0.1, 5.), Vector::new(0., 0., -1.));
+        assert!(curve.intersect(&ray, &mut isect).is_some());
+        let n = isect.ng();
+        let n = if n.z < 0. { -n } else { n };
+        assert!(n.y > 0.5 && n.z > 0.5, "{:?}", n);
+    }
+
+    #[test]
+    fn converts_bspline_to_bezier() {
+        let points = [[0., 0., 0.], [1., 1., 0.], [2., 0., 0.], [3., 1., 0.], [4., 0., 0.]];
+        let segments = Curve::bspline_segments(&points);
+        assert_eq!(segments.len(), 2);
+        // Segments join up, where the spline passes (p0 + 4 p1 + p2) / 6
+        assert_eq!(segments[0][3], segments[1][0]);
+        let start = Point::from(segments[0][0]);
+        assert!((start - Point::new(1., 2. / 3., 0.)).magnitude() < 1e-9);
+    }
+}
@@ -0,0 +1,115 @@
+use std::f64::consts::PI;
+use crate::space::*;
+use crate::primitive::{Primitive, OptionalPrimitive};
+use crate::interaction::RayIntersection;
+use crate::MaterialRef;
+use crate::core::bxdf::sampling::concentric_sample_disk;
+use super::plane::{tangent_basis, intersect_flat, flat_point};
+
+/**
+    A flat circle centred at the origin point and facing the direction of the
+    normal
+*/
+#[derive(Debug)]
+pub struct Disk {
+    pub origin: Point,
+    pub normal: Vector,
+    pub radius: f64,
+    pub material: MaterialRef,
+
+    // Unit tangents from which the angle ϕ is measured
+    s: Vector,
+    t: Vector
+}
+
+impl Disk {
+    pub fn new(origin: [f64; 3], normal: [f64; 3], radius: f64, material: MaterialRef) -> Disk {
+        let normal = Vector::from(normal).normalize();
+        let (s, t) = tangent_basis(&normal);
+        Disk { origin: Point::from(origin), normal, radius, material, s, t }
+    }
+}
+
+impl Primitive for Disk {
+    fn bound(&self) -> Bounds {
+        // Extent along each axis shrinks as the normal approaches that axis
+        let n = self.normal;
+        let extent = self.radius * Vector::new(
+            (1.0 - n.x * n.x).max(0.0).sqrt(),
+            (1.0 - n.y * n.y).max(0.0).sqrt(),
+            (1.0 - n.z * n.z).max(0.0).sqrt());
+        Bounds::new(self.origin - extent, self.origin + extent)
+    }
+
+    fn intersect(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive<'_> {
+        let t = intersect_flat(&self.origin, &self.normal, ray);
+        if !ray.in_range(t) || t >= isect.t { return None }
+
+        // Position on the disk relative to the centre, in tangent space
+        let (p, p_err) = flat_point(&self.origin, ray, t);
+        let d = p - self.origin;
+        let (mut x, y) = (d.dot(self.s), d.dot(self.t));
+        let r = (x * x + y * y).sqrt();
+        if r > self.radius { return None }
+
+        // Account for intersection right at the centre
+        if r == 0.0 { x = 1e-5 * self.radius }
+        let r = r.max(x.abs());
+
+        // Parametrized by (ϕ, r) with u = ϕ / 2π and v = 1 - r / radius, such
+        // that v increases toward the centre
+        let mut phi = y.atan2(x);
+        if phi < 0.0 { phi += 2.0 * PI }
+
+        let dpdu = 2.0 * PI * (self.t * x - self.s * y);
+        let dpdv = -(self.s * x + self.t * y) * (self.radius / r);
+
+        let uv = Point2f::new(phi / (2.0 * PI), 1.0 - r / self.radius);
+        *isect = RayIntersection::new(t, p, uv, dpdu, dpdv);
+        isect.p_err = p_err;
+
+        Some(self)
+    }
+
+    fn intersects(&self, ray: &Ray) -> bool {
+        self.intersect(ray, &mut RayIntersection::default()).is_some()
+    }
+
+    fn material(&self) -> Option<MaterialRef> { Some(self.material) }
+
+    fn area(&self) -> f64 {
+        PI * self.radius * self.radius
+    }
+
+    fn sample_area(&self, u: &Point2f) -> Option<(Point, Vector)> {
+        let d = concentric_sample_disk(u) * self.radius;
+        Some((self.origin + self.s * d.x + self.t * d.y, self.normal))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn facing_intersection() {
+        let disk = Disk::new([0.0, 0.0, -2.0], [0.0, 0.0, 1.0], 1.0, MaterialRef::default());
+        let ray = Ray::new(Point::new(0.0, 0.5, 0.0), Vector::new(0.0, 0.0, -1.0));
+        let mut isect = RayIntersection::default();
+
+        assert!(disk.intersect(&ray, &mut isect).is_some());
+        assert_eq!(isect.t, 2.0);
+        assert!((isect.ng() - Vector::unit_z()).magnitude() < 1e-9);
+        assert!((isect.uv.x - 0.25).abs() < 1e-9 && (isect.uv.y - 0.5).abs() < 1e-9);
+
+        // Straight through the centre
+        let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vector::new(0.0, 0.0, -1.0));
+        let mut isect = RayIntersection::default();
+        assert!(disk.intersect(&ray, &mut isect).is_some());
+        assert!((isect.ng() - Vector::unit_z()).magnitude() < 1e-9);
+
+        // Outside the radius
+        let ray = Ray::new(Point::new(0.8, 0.8, 0.0), Vector::new(0.0, 0.0, -1.0));
+        assert!(!disk.intersects(&ray));
+    }
+}
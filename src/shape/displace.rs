@@ -0,0 +1,289 @@
+//! Displacement mapping for triangle meshes. Triangles whose materials have
+//! displacement maps are split until their edges are short enough on the
+//! image, then the new vertices move along their normals by the height maps.
+//! Whether an edge is split only depends on its two ends, so neighbouring
+//! triangles split their shared edges the same way and no cracks open up
+//! between them.
+
+use std::collections::HashMap;
+
+use crate::{space::*, scene::Scene, material::Displacement, MaterialRef};
+use super::{mesh::TriangleMesh, triangle::Mesh};
+
+/// Most times the edges of a triangle are halved, bounding the number of
+/// triangles each one splits into
+const MAX_DEPTH: u32 = 8;
+
+/// Vertex of a triangle being split, with every attribute a mesh may have
+#[derive(Copy, Clone)]
+struct Vertex {
+    p: Point,
+    n: Vector,
+    uv: Point2f,
+    color: Color
+}
+
+impl Vertex {
+    /// Vertex halfway along the edge to another. The same either way around.
+    fn midpoint(&self, other: &Vertex) -> Vertex {
+        Vertex {
+            p: Point::from_vec((self.p.to_vec() + other.p.to_vec()) * 0.5),
+            n: (self.n + other.n).normalize(),
+            uv: Point2f::from_vec((self.uv.to_vec() + other.uv.to_vec()) * 0.5),
+            color: (self.color + other.color) * 0.5
+        }
+    }
+}
+
+/// Split and displace the triangles of the given mesh that have displacement
+/// maps, either in the materials of the mesh or in the given material for
+/// triangles without their own. Edges are split in half for as long as
+/// `split` is true for their ends. Returns None if no triangle is displaced.
+pub(crate) fn displace(mesh: &Mesh, material: Option<MaterialRef>, scene: &Scene, split: impl Fn(&Point, &Point) -> bool) -> Option<Mesh> {
+    let count = mesh.triangles.triangle_count();
+    let displacement = |t: usize| mesh.material(t).or(material).and_then(|m| scene.material(m).displacement());
+    if !(0..count).any(|t| displacement(t).is_some()) { return None }
+
+    // Displace along smooth normals, so that faces meeting at an edge move
+    // their shared vertices the same way
+    let mut source = mesh.triangles.clone();
+    let has_normals = source.normals.is_some();
+    if !has_normals { source.generate_normals(180.0) }
+
+    let mut tessellation = Tessellation {
+        scene,
+        split,
+        source: &source,
+        mesh: TriangleMesh::default(),
+        sources: vec![],
+        vertices: HashMap::new(),
+        displacements: vec![],
+        displaced: vec![]
+    };
+
+    for t in 0..count {
+        let mut triangle = [0, 1, 2].map(|i| tessellation.vertex(source.indices[3 * t + i]));
+        match displacement(t) {
+            Some(displacement) => {
+                let index = tessellation.displacement_index(displacement);
+                tessellation.tessellate(triangle, t as u32, index, 0)
+            },
+            None => {
+                // Meshes without normals keep their faces flat
+                if !has_normals {
+                    let n = face_normal(&triangle.map(|v| v.p));
+                    for v in triangle.iter_mut() { v.n = n }
+                }
+                tessellation.triangle(triangle, t as u32, None)
+            }
+        }
+    }
+
+    Some(tessellation.finish(mesh))
+}
+
+/// Unit normal of the triangle with the given corners, following their
+/// winding
+#[inline]
+fn face_normal(p: &[Point; 3]) -> Vector {
+    let n = (p[1] - p[0]).cross(p[2] - p[0]);
+    if n.magnitude2() > 0.0 { n.normalize() } else { Vector::zero() }
+}
+
+/// Triangles of a mesh being split and displaced
+struct Tessellation<'a, F> {
+    scene: &'a Scene,
+    split: F,
+
+    /// The mesh being split, with normals at every vertex
+    source: &'a TriangleMesh,
+
+    /// Split mesh along with the index of the source triangle that each of
+    /// its triangles came from
+    mesh: TriangleMesh,
+    sources: Vec<u32>,
+
+    /// Index of each vertex added to the mesh, by the bits of its position,
+    /// normal and UV coordinates and the index of its displacement map
+    vertices: HashMap<([u64; 8], Option<usize>), usize>,
+
+    /// Distinct displacement maps found so far
+    displacements: Vec<Displacement>,
+
+    /// Normal before displacement of each displaced vertex of the mesh, for
+    /// vertices whose faces all end up degenerate
+    displaced: Vec<Option<Vector>>
+}
+
+impl<'a, F: Fn(&Point, &Point) -> bool> Tessellation<'a, F> {
+    /// Vertex at the given index of the source mesh
+    fn vertex(&self, v: usize) -> Vertex {
+        let source = self.source;
+        let normals = source.normals.as_ref().unwrap();
+        Vertex {
+            p: Point::new(source.positions[3 * v], source.positions[3 * v + 1], source.positions[3 * v + 2]),
+            n: Vector::new(normals[3 * v], normals[3 * v + 1], normals[3 * v + 2]),
+            uv: match &source.uvs {
+                Some(uvs) => Point2f::new(uvs[2 * v], uvs[2 * v + 1]),
+                None => Point2f::new(0.0, 0.0)
+            },
+            color: match &source.colors {
+                Some(colors) => Color::new(colors[3 * v], colors[3 * v + 1], colors[3 * v + 2]),
+                None => Color::from_value(1.0)
+            }
+        }
+    }
+
+    fn displacement_index(&mut self, displacement: Displacement) -> usize {
+        match self.displacements.iter().position(|d| *d == displacement) {
+            Some(index) => index,
+            None => {
+                self.displacements.push(displacement);
+                self.displacements.len() - 1
+            }
+        }
+    }
+
+    /// Add the given triangle, splitting its edges that are too long and
+    /// splitting the parts again
+    fn tessellate(&mut self, v: [Vertex; 3], source: u32, displacement: usize, depth: u32) {
+        let split = [0, 1, 2].map(|i| depth < MAX_DEPTH && (self.split)(&v[i].p, &v[(i + 1) % 3].p));
+        let mut parts = vec![];
+        match split.iter().filter(|s| **s).count() {
+            0 => return self.triangle(v, source, Some(displacement)),
+            1 => {
+                // Halve the split edge, from corner r[0] to r[1]
+                let i = split.iter().position(|s| *s).unwrap();
+                let r = [v[i], v[(i + 1) % 3], v[(i + 2) % 3]];
+                let m = r[0].midpoint(&r[1]);
+                parts.push([r[0], m, r[2]]);
+                parts.push([m, r[1], r[2]]);
+            },
+            2 => {
+                // Halve the edges from r[0] to r[1] and r[1] to r[2]
+                let i = split.iter().position(|s| !*s).unwrap();
+                let r = [v[(i + 1) % 3], v[(i + 2) % 3], v[i]];
+                let (m0, m1) = (r[0].midpoint(&r[1]), r[1].midpoint(&r[2]));
+                parts.push([m0, r[1], m1]);
+                parts.push([r[0], m0, m1]);
+                parts.push([r[0], m1, r[2]]);
+            },
+            _ => {
+                let m = [v[0].midpoint(&v[1]), v[1].midpoint(&v[2]), v[2].midpoint(&v[0])];
+                parts.push([v[0], m[0], m[2]]);
+                parts.push([m[0], v[1], m[1]]);
+                parts.push([m[2], m[1], v[2]]);
+                parts.push([m[0], m[1], m[2]]);
+            }
+        }
+        for part in parts {
+            self.tessellate(part, source, displacement, depth + 1)
+        }
+    }
+
+    /// Add a triangle with the given corners as they are. Displaced triangles
+    /// add their face normals to the normals of their corners.
+    fn triangle(&mut self, v: [Vertex; 3], source: u32, displacement: Option<usize>) {
+        let indices = v.map(|v| self.add_vertex(&v, displacement));
+        self.mesh.indices.extend_from_slice(&indices);
+        self.sources.push(source);
+        if displacement.is_none() { return }
+
+        // Area-weighted, facing the same way as the surface before it moved
+        let positions = &self.mesh.positions;
+        let p = indices.map(|i| Point::new(positions[3 * i], positions[3 * i + 1], positions[3 * i + 2]));
+        let n = (p[1] - p[0]).cross(p[2] - p[0]);
+        let n = if n.dot(v[0].n + v[1].n + v[2].n) < 0.0 { -n } else { n };
+        let normals = self.mesh.normals.as_mut().unwrap();
+        for i in indices.iter() {
+            normals[3 * i] += n.x;
+            normals[3 * i + 1] += n.y;
+            normals[3 * i + 2] += n.z;
+        }
+    }
+
+    /// Index of the given vertex in the split mesh, adding it if it's new
+    fn add_vertex(&mut self, v: &Vertex, displacement: Option<usize>) -> usize {
+        let key = ([
+            v.p.x.to_bits(), v.p.y.to_bits(), v.p.z.to_bits(),
+            v.n.x.to_bits(), v.n.y.to_bits(), v.n.z.to_bits(),
+            v.uv.x.to_bits(), v.uv.y.to_bits()
+        ], displacement);
+        if let Some(index) = self.vertices.get(&key) { return *index }
+
+        let (p, n, before) = match displacement {
+            Some(index) => {
+                let n = v.n.normalize();
+                (v.p + n * self.displacements[index].offset(v.p, n, v.uv, self.scene), Vector::zero(), Some(n))
+            },
+            None => (v.p, v.n, None)
+        };
+        let mesh = &mut self.mesh;
+        let index = mesh.positions.len() / 3;
+        mesh.positions.extend_from_slice(&[p.x, p.y, p.z]);
+        mesh.normals.get_or_insert_with(Vec::new).extend_from_slice(&[n.x, n.y, n.z]);
+        if self.source.uvs.is_some() {
+            mesh.uvs.get_or_insert_with(Vec::new).extend_from_slice(&[v.uv.x, v.uv.y]);
+        }
+        if self.source.colors.is_some() {
+            mesh.colors.get_or_insert_with(Vec::new).extend_from_slice(&[v.color.x, v.color.y, v.color.z]);
+        }
+        self.displaced.push(before);
+        self.vertices.insert(key, index);
+        index
+    }
+
+    /// Normalize the summed normals of displaced vertices and build the mesh
+    /// with the materials of the given one
+    fn finish(mut self, mesh: &Mesh) -> Mesh {
+        let normals = self.mesh.normals.as_mut().unwrap();
+        for (v, before) in self.displaced.iter().enumerate() {
+            if let Some(before) = before {
+                let n = Vector::new(normals[3 * v], normals[3 * v + 1], normals[3 * v + 2]);
+                let n = if n.magnitude2() > 0.0 { n.normalize() } else { *before };
+                normals[3 * v..3 * v + 3].copy_from_slice(&[n.x, n.y, n.z]);
+            }
+        }
+        mesh.with_triangles(self.mesh, &self.sources)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::texture::Checkerboard;
+
+    #[test]
+    fn displaces_without_cracks() {
+        let mut scene = Scene::new();
+        let height = scene.add_texture(Box::new(Checkerboard::new([1.0; 3], [1.0; 3], 1.0)));
+        let material = scene.add_material(crate::Material::matte([0.5; 3], 0.0).with_displacement(height, 0.5));
+        let quad = Mesh::new(TriangleMesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+            normals: None,
+            uvs: None,
+            colors: None,
+            indices: vec![0, 1, 2, 0, 2, 3]
+        });
+
+        let split = |a: &Point, b: &Point| (b - a).magnitude() > 0.3;
+        assert!(displace(&quad, None, &scene, split).is_none());
+        let displaced = displace(&quad, Some(material), &scene, split).unwrap().triangles;
+        assert!(displaced.triangle_count() > 8);
+        assert!(displaced.positions.chunks(3).all(|p| p[2] == 0.5));
+
+        // Edges inside the quad are shared by two triangles
+        let mut edges = HashMap::new();
+        for t in displaced.indices.chunks(3) {
+            for i in 0..3 {
+                let (a, b) = (t[i].min(t[(i + 1) % 3]), t[i].max(t[(i + 1) % 3]));
+                *edges.entry((a, b)).or_insert(0) += 1;
+            }
+        }
+        let p = |v: usize| &displaced.positions[3 * v..3 * v + 2];
+        let on_border = |a: usize, b: usize| (0..2).any(|i| p(a)[i] == p(b)[i] && (p(a)[i] == 0.0 || p(a)[i] == 1.0));
+        for ((a, b), count) in edges {
+            assert!(count == 2 || (count == 1 && on_border(a, b)));
+        }
+    }
+}
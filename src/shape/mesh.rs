@@ -0,0 +1,253 @@
+//! The triangle mesh representation shared by every mesh format. Meshes from
+//! .obj files are converted with `TriangleMesh::from_obj`, and .ply and .stl
+//! files are read by `formats::ply` and `formats::stl`.
+
+use std::{fmt, io, collections::HashMap};
+use obj::Obj;
+use serde::{Serialize, Deserialize};
+
+use crate::space::*;
+
+/// Problems reading a mesh file
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+
+    /// Malformed or unsupported file contents
+    Format(String)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Format(message) => write!(f, "invalid mesh: {}", message)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::Io(err) }
+}
+
+pub(crate) fn format_error<T>(message: impl Into<String>) -> Result<T, Error> {
+    Err(Error::Format(message.into()))
+}
+
+/// How the shading normals of a triangle mesh are found
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Smoothing {
+    /// Shade every triangle with its geometric normal, ignoring any normals
+    /// in the mesh
+    Flat,
+
+    /// Interpolate the normals in the mesh. Meshes without any get normals
+    /// generated from their faces, keeping edges where the faces meet at more
+    /// than the given crease angle (in degrees) sharp.
+    Smooth(f64)
+}
+
+/// Triangles with optional per-vertex attributes, stored as flat arrays of
+/// components the way most mesh and scene formats lay them out. Every
+/// attribute shares the same vertex indices.
+#[derive(Debug, Clone, Default)]
+pub struct TriangleMesh {
+    /// x, y, z for each vertex
+    pub positions: Vec<f64>,
+
+    /// x, y, z for each vertex
+    pub normals: Option<Vec<f64>>,
+
+    /// u, v for each vertex
+    pub uvs: Option<Vec<f64>>,
+
+    /// Linear r, g, b for each vertex
+    pub colors: Option<Vec<f64>>,
+
+    /// Three vertex indices for each triangle
+    pub indices: Vec<usize>
+}
+
+impl TriangleMesh {
+    /// Convert the given .obj mesh, along with the colour of each of its
+    /// vertex positions, if any. Polygons with more than three vertices are
+    /// split into fans of triangles. Also returns the index of the .obj group
+    /// of each triangle, counting the groups of every object in order.
+    pub fn from_obj(obj: &Obj, colors: &[[f32; 3]]) -> (TriangleMesh, Vec<u32>) {
+        let data = &obj.data;
+        let mut mesh = TriangleMesh::default();
+        let (mut normals, mut uvs, mut vertex_colors) = (vec![], vec![], vec![]);
+        let (mut has_normals, mut has_uvs) = (!data.normal.is_empty(), !data.texture.is_empty());
+        let has_colors = !colors.is_empty();
+
+        // .obj faces index positions, normals and texture coordinates
+        // separately, so each distinct combination becomes one vertex
+        let mut vertices: HashMap<(usize, Option<usize>, Option<usize>), usize> = HashMap::new();
+        let mut groups = vec![];
+        let mut group = 0;
+        for object in data.objects.iter() {
+            for g in object.groups.iter() {
+                for poly in g.polys.iter() {
+                    let corners: Vec<usize> = poly.0.iter().map(|tuple| {
+                        let key = (tuple.0, tuple.1, tuple.2);
+                        *vertices.entry(key).or_insert_with(|| {
+                            mesh.positions.extend(data.position[key.0].iter().map(|c| *c as f64));
+                            match key.2 {
+                                Some(n) if has_normals => normals.extend(data.normal[n].iter().map(|c| *c as f64)),
+                                _ => has_normals = false
+                            }
+                            match key.1 {
+                                Some(t) if has_uvs => uvs.extend(data.texture[t].iter().map(|c| *c as f64)),
+                                _ => has_uvs = false
+                            }
+                            if has_colors {
+                                vertex_colors.extend(colors[key.0].iter().map(|c| *c as f64))
+                            }
+                            mesh.positions.len() / 3 - 1
+                        })
+                    }).collect();
+
+                    for k in 1..corners.len().saturating_sub(1) {
+                        mesh.indices.extend_from_slice(&[corners[0], corners[k], corners[k + 1]]);
+                        groups.push(group)
+                    }
+                }
+                group += 1;
+            }
+        }
+
+        // Attributes are only kept if every vertex has one
+        if has_normals { mesh.normals = Some(normals) }
+        if has_uvs { mesh.uvs = Some(uvs) }
+        if has_colors { mesh.colors = Some(vertex_colors) }
+        (mesh, groups)
+    }
+
+    /// Drop attributes that don't have an entry for every vertex. Returns
+    /// None if the indices don't make up whole triangles of existing vertices.
+    pub fn validate(mut self) -> Option<TriangleMesh> {
+        let vertices = self.positions.len() / 3;
+        if !self.indices.len().is_multiple_of(3) || self.indices.iter().any(|i| *i >= vertices) {
+            return None
+        }
+        self.normals = self.normals.filter(|n| n.len() == vertices * 3);
+        self.uvs = self.uvs.filter(|uv| uv.len() == vertices * 2);
+        self.colors = self.colors.filter(|c| c.len() == vertices * 3);
+        Some(self)
+    }
+
+    /// Replace the normals with ones averaged from the faces around each
+    /// vertex, weighted by their area. Faces only contribute where they meet
+    /// the face of the vertex at less than the given crease angle (in degrees).
+    /// Vertices along sharper edges are split so that each side gets its own
+    /// normal. Vertices with the same position are treated as one, so seams in
+    /// texture coordinates stay smooth.
+    pub fn generate_normals(&mut self, crease_angle: f64) {
+        let point = |v: usize| Vector::new(self.positions[3 * v], self.positions[3 * v + 1], self.positions[3 * v + 2]);
+        let key = |v: usize| [
+            self.positions[3 * v].to_bits(),
+            self.positions[3 * v + 1].to_bits(),
+            self.positions[3 * v + 2].to_bits()
+        ];
+
+        // Face normals, with lengths proportional to the area of each face
+        let faces: Vec<Vector> = self.indices.as_chunks::<3>().0.iter()
+            .map(|t| (point(t[1]) - point(t[0])).cross(point(t[2]) - point(t[0])))
+            .collect();
+        let units: Vec<Vector> = faces.iter()
+            .map(|n| if n.magnitude2() > 0.0 { n.normalize() } else { *n })
+            .collect();
+
+        let keys: Vec<[u64; 3]> = (0..self.positions.len() / 3).map(key).collect();
+        let mut around: HashMap<[u64; 3], Vec<usize>> = HashMap::new();
+        for (i, v) in self.indices.iter().enumerate() {
+            around.entry(keys[*v]).or_default().push(i / 3)
+        }
+
+        let cos_crease = crease_angle.to_radians().cos();
+        let mut normals: Vec<f64> = vec![0.0; self.positions.len()];
+        let mut assigned = vec![false; self.positions.len() / 3];
+        let mut split: HashMap<(usize, [u64; 3]), usize> = HashMap::new();
+        let mut indices = Vec::with_capacity(self.indices.len());
+        for (i, v) in self.indices.iter().copied().enumerate() {
+            let face = i / 3;
+            let mut n = Vector::zero();
+            for other in around[&keys[v]].iter() {
+                if *other == face || units[face].dot(units[*other]) >= cos_crease {
+                    n += faces[*other]
+                }
+            }
+            let n = if n.magnitude2() > 0.0 { n.normalize() } else { Vector::unit_z() };
+            let n_key = [n.x.to_bits(), n.y.to_bits(), n.z.to_bits()];
+
+            // Vertices keep the first normal found for them and are copied
+            // for every other one
+            let index = if !assigned[v] {
+                assigned[v] = true;
+                split.insert((v, n_key), v);
+                v
+            } else {
+                match split.get(&(v, n_key)) {
+                    Some(index) => *index,
+                    None => {
+                        let index = self.positions.len() / 3;
+                        copy_vertex(&mut self.positions, v, 3);
+                        if let Some(uvs) = &mut self.uvs { copy_vertex(uvs, v, 2) }
+                        if let Some(colors) = &mut self.colors { copy_vertex(colors, v, 3) }
+                        normals.extend_from_slice(&[0.0; 3]);
+                        split.insert((v, n_key), index);
+                        index
+                    }
+                }
+            };
+            normals[3 * index..3 * index + 3].copy_from_slice(&[n.x, n.y, n.z]);
+            indices.push(index)
+        }
+
+        self.indices = indices;
+        self.normals = Some(normals)
+    }
+
+    /// Number of triangles in the mesh
+    #[inline]
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+}
+
+/// Append a copy of the given vertex's attribute with the given number of
+/// components
+fn copy_vertex(values: &mut Vec<f64>, vertex: usize, components: usize) {
+    let start = components * vertex;
+    values.extend_from_within(start..start + components)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn generated_normals() {
+        // Two faces of a cube sharing the edge along the z axis
+        let mut mesh = TriangleMesh {
+            positions: vec![0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            indices: vec![0, 1, 2, 0, 3, 1],
+            ..TriangleMesh::default()
+        };
+
+        let mut smooth = mesh.clone();
+        smooth.generate_normals(100.0);
+        let normals = smooth.normals.unwrap();
+        assert_eq!(smooth.positions.len(), 12);
+        assert!((normals[0] - normals[1]).abs() < 1e-9 && normals[0] > 0.0);
+
+        mesh.generate_normals(60.0);
+        let normals = mesh.normals.unwrap();
+        assert_eq!(mesh.positions.len(), 18);
+        assert_eq!(normals[0..3], [0.0, 1.0, 0.0]);
+        assert_eq!(normals[3 * mesh.indices[3]..3 * mesh.indices[3] + 3], [1.0, 0.0, 0.0]);
+    }
+}
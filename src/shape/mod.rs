@@ -1,9 +1,20 @@
 pub use crate::primitive::Primitive;
 
 pub mod cuboid;
+pub mod curve;
+pub mod disk;
+pub(crate) mod displace;
+pub mod mesh;
+pub mod plane;
+pub mod sdf;
 pub mod sphere;
+pub mod subdiv;
 pub mod triangle;
 
 pub use self::cuboid::Cuboid;
+pub use self::curve::Curve;
+pub use self::disk::Disk;
+pub use self::plane::Plane;
+pub use self::sdf::SdfPrimitive;
 pub use self::sphere::Sphere;
 pub use self::triangle::*;
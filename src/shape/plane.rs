@@ -0,0 +1,132 @@
+use crate::space::*;
+use crate::primitive::{Primitive, OptionalPrimitive};
+use crate::interaction::RayIntersection;
+use crate::MaterialRef;
+
+/// Rays this close to parallel with a flat shape are treated as misses
+const PARALLEL_EPSILON: f64 = 1e-12;
+
+/**
+    A flat rectangle centred at the origin point and facing the direction of
+    the normal. Cheaper than a two-triangle mesh and a good fit for floors and
+    walls.
+*/
+#[derive(Debug)]
+pub struct Plane {
+    pub origin: Point,
+    pub normal: Vector,
+
+    /// Width and height of the rectangle along the u and v directions
+    pub size: [f64; 2],
+    pub material: MaterialRef,
+
+    // Unit tangents along which texture u and v increase
+    s: Vector,
+    t: Vector
+}
+
+impl Plane {
+    pub fn new(origin: [f64; 3], normal: [f64; 3], size: [f64; 2], material: MaterialRef) -> Plane {
+        let normal = Vector::from(normal).normalize();
+        let (s, t) = tangent_basis(&normal);
+        Plane { origin: Point::from(origin), normal, size, material, s, t }
+    }
+}
+
+impl Primitive for Plane {
+    fn bound(&self) -> Bounds {
+        let du = self.s * (0.5 * self.size[0]);
+        let dv = self.t * (0.5 * self.size[1]);
+        Bounds::new(self.origin - du - dv, self.origin + du + dv)
+            .point_union(&(self.origin - du + dv))
+            .point_union(&(self.origin + du - dv))
+    }
+
+    fn intersect(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive<'_> {
+        let t = intersect_flat(&self.origin, &self.normal, ray);
+        if !ray.in_range(t) || t >= isect.t { return None }
+
+        // Position on the plane relative to the centre, in tangent space
+        let (p, p_err) = flat_point(&self.origin, ray, t);
+        let d = p - self.origin;
+        let u = d.dot(self.s) / self.size[0] + 0.5;
+        let v = d.dot(self.t) / self.size[1] + 0.5;
+        if u < 0.0 || u > 1.0 || v < 0.0 || v > 1.0 { return None }
+
+        let dpdu = self.s * self.size[0];
+        let dpdv = self.t * self.size[1];
+        *isect = RayIntersection::new(t, p, Point2f::new(u, v), dpdu, dpdv);
+        isect.p_err = p_err;
+
+        Some(self)
+    }
+
+    fn intersects(&self, ray: &Ray) -> bool {
+        self.intersect(ray, &mut RayIntersection::default()).is_some()
+    }
+
+    fn material(&self) -> Option<MaterialRef> { Some(self.material) }
+
+    fn area(&self) -> f64 {
+        self.size[0] * self.size[1]
+    }
+
+    fn sample_area(&self, u: &Point2f) -> Option<(Point, Vector)> {
+        let p = self.origin
+            + self.s * ((u.x - 0.5) * self.size[0])
+            + self.t * ((u.y - 0.5) * self.size[1]);
+        Some((p, self.normal))
+    }
+}
+
+/// Returns unit vectors s and t perpendicular to the given normal such that
+/// s × t = n. For axis-aligned normals these line up with the world axes, e.g.,
+/// a floor facing +y gets s = +x and t = -z.
+pub(crate) fn tangent_basis(n: &Vector) -> (Vector, Vector) {
+    let s = if n.y.abs() < 0.9 {
+        Vector::unit_y().cross(*n)
+    } else {
+        n.cross(Vector::unit_z())
+    }.normalize();
+    (s, n.cross(s))
+}
+
+/// Parametric t at which the ray hits the infinite plane through the given
+/// point with the given normal. Negative if there is no intersection.
+#[inline]
+pub(crate) fn intersect_flat(origin: &Point, normal: &Vector, ray: &Ray) -> f64 {
+    let denom = normal.dot(ray.d);
+    if denom.abs() < PARALLEL_EPSILON { return -1.0 }
+    (origin - ray.origin).dot(*normal) / denom
+}
+
+/// Point at parameter t of the ray, as found by `intersect_flat` for the plane
+/// through the given point, along with a bound on its error. The rounding
+/// error of t grows with the magnitudes of the points involved.
+#[inline]
+pub(crate) fn flat_point(origin: &Point, ray: &Ray, t: f64) -> (Point, Vector) {
+    let p = ray.origin + ray.d * t;
+    let p_err = gamma::<f64>(7) * (abs(&ray.origin.to_vec()) + abs(&(ray.d * t)) + abs(&origin.to_vec()));
+    (p, p_err)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn floor_intersection() {
+        let plane = Plane::new([0.0, -1.0, 0.0], [0.0, 1.0, 0.0], [4.0, 2.0], MaterialRef::default());
+        let ray = Ray::new(Point::new(1.0, 1.0, 0.5), Vector::new(0.0, -1.0, 0.0));
+        let mut isect = RayIntersection::default();
+
+        assert!(plane.intersect(&ray, &mut isect).is_some());
+        assert_eq!(isect.t, 2.0);
+        assert_eq!(isect.ng(), Vector::unit_y());
+        assert_eq!(isect.uv, Point2f::new(0.75, 0.25));
+
+        // Outside the rectangle
+        let ray = Ray::new(Point::new(0.0, 1.0, 1.5), Vector::new(0.0, -1.0, 0.0));
+        assert!(!plane.intersects(&ray));
+    }
+}
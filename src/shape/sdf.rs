@@ -0,0 +1,335 @@
+use std::{f64::consts::PI, fmt, sync::Arc};
+use crate::space::*;
+use crate::primitive::{Primitive, OptionalPrimitive};
+use crate::interaction::RayIntersection;
+use crate::MaterialRef;
+
+/// Most steps taken along a ray before giving up on finding the surface
+const SDF_MAX_STEPS: usize = 512;
+
+/// Distance to the surface, relative to the size of the shape, within which a
+/// ray counts as hitting it
+const SDF_HIT_DISTANCE: f64 = 1e-6;
+
+/// Signed distance function: the distance from any point in space to the
+/// nearest point on the surface of a shape, negative inside it. Build shapes
+/// from primitives and combine them, e.g.,
+///
+///     use lasgun::Sdf;
+///     let blob = Sdf::sphere([0., 0., 0.], 1.)
+///         .smooth_union(Sdf::sphere([1., 0., 0.], 0.5), 0.25)
+///         .subtract(Sdf::cuboid([-2., -2., -2.], [2., 0., 2.]));
+///
+/// Built-in shapes have exact surface normals. `Sdf::custom` takes any
+/// distance function, such as the estimate for a fractal.
+#[derive(Clone)]
+pub enum Sdf {
+    /// Sphere with centre and radius
+    Sphere([f64; 3], f64),
+    /// Rectangular prism with start and end corners
+    Cuboid([f64; 3], [f64; 3]),
+    /// Ring around the y-axis with centre, radius of the ring and radius of
+    /// its tube
+    Torus([f64; 3], f64, f64),
+    /// Space inside either shape
+    Union(Box<Sdf>, Box<Sdf>),
+    /// Space inside both shapes
+    Intersection(Box<Sdf>, Box<Sdf>),
+    /// Space inside the first shape but not the second
+    Difference(Box<Sdf>, Box<Sdf>),
+    /// Union blended over the given distance where the shapes meet
+    SmoothUnion(Box<Sdf>, Box<Sdf>, f64),
+    /// User-provided distance function within the given bounds
+    Custom(Bounds, Arc<dyn Fn(Point) -> f64 + Send + Sync>)
+}
+
+impl Sdf {
+    pub fn sphere(center: [f64; 3], radius: f64) -> Sdf {
+        debug_assert!(radius > 0.);
+        Sdf::Sphere(center, radius)
+    }
+
+    pub fn cuboid(minbound: [f64; 3], maxbound: [f64; 3]) -> Sdf {
+        Sdf::Cuboid(minbound, maxbound)
+    }
+
+    pub fn torus(center: [f64; 3], radius: f64, tube_radius: f64) -> Sdf {
+        debug_assert!(radius > 0. && tube_radius > 0.);
+        Sdf::Torus(center, radius, tube_radius)
+    }
+
+    /// Shape with the given distance function. It must not overestimate the
+    /// distance to the surface and the shape must fit between the given
+    /// corners. Surface normals are estimated from nearby distances.
+    pub fn custom<F>(minbound: [f64; 3], maxbound: [f64; 3], f: F) -> Sdf
+    where F: Fn(Point) -> f64 + Send + Sync + 'static {
+        Sdf::Custom(Bounds::new(minbound.into(), maxbound.into()), Arc::new(f))
+    }
+
+    pub fn union(self, other: Sdf) -> Sdf {
+        Sdf::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersect(self, other: Sdf) -> Sdf {
+        Sdf::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtract(self, other: Sdf) -> Sdf {
+        Sdf::Difference(Box::new(self), Box::new(other))
+    }
+
+    /// Union of this shape and the other with a rounded seam the size of the
+    /// given blending distance
+    pub fn smooth_union(self, other: Sdf, blend: f64) -> Sdf {
+        debug_assert!(blend > 0.);
+        Sdf::SmoothUnion(Box::new(self), Box::new(other), blend)
+    }
+
+    /// Signed distance from p to the surface
+    pub fn distance(&self, p: &Point) -> f64 {
+        match self {
+            Sdf::Union(a, b) => a.distance(p).min(b.distance(p)),
+            Sdf::Intersection(a, b) => a.distance(p).max(b.distance(p)),
+            Sdf::Difference(a, b) => a.distance(p).max(-b.distance(p)),
+            Sdf::SmoothUnion(a, b, k) => {
+                let (da, db) = (a.distance(p), b.distance(p));
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0., 1.);
+                lerp(h, db, da) - k * h * (1. - h)
+            },
+            Sdf::Custom(_, f) => f(*p),
+            _ => self.eval(p).0
+        }
+    }
+
+    /// Signed distance from p to the surface and its gradient, which points
+    /// away from the surface on the outside
+    fn eval(&self, p: &Point) -> (f64, Vector) {
+        match self {
+            Sdf::Sphere(center, radius) => {
+                let v = p - Point::from(*center);
+                let d = v.magnitude();
+                let n = if d > 0. { v / d } else { Vector::unit_y() };
+                (d - radius, n)
+            },
+            Sdf::Cuboid(c0, c1) => {
+                let (c0, c1) = (Point::from(*c0), Point::from(*c1));
+                let v = p - c0.midpoint(c1);
+                let q = abs(&v) - (c1 - c0) * 0.5;
+                let outside = q.map(|c| c.max(0.));
+                let d = outside.magnitude();
+                if d > 0. {
+                    let n = outside / d;
+                    (d, Vector::new(n.x.copysign(v.x), n.y.copysign(v.y), n.z.copysign(v.z)))
+                } else {
+                    // Inside, closest to the face of the largest component
+                    let axis = if q.x >= q.y && q.x >= q.z { 0 } else if q.y >= q.z { 1 } else { 2 };
+                    let mut n = Vector::zero();
+                    n[axis] = 1f64.copysign(v[axis]);
+                    (q[axis], n)
+                }
+            },
+            Sdf::Torus(center, radius, tube_radius) => {
+                let v = p - Point::from(*center);
+                let ring = (v.x * v.x + v.z * v.z).sqrt();
+                let q = Vector2f::new(ring - radius, v.y);
+                let d = q.magnitude();
+                if d == 0. { return (-tube_radius, Vector::unit_y()) }
+                let (x, z) = if ring > 0. { (v.x / ring, v.z / ring) } else { (1., 0.) };
+                (d - tube_radius, Vector::new(q.x * x, q.y, q.x * z) / d)
+            },
+            Sdf::Union(a, b) => {
+                let (a, b) = (a.eval(p), b.eval(p));
+                if a.0 <= b.0 { a } else { b }
+            },
+            Sdf::Intersection(a, b) => {
+                let (a, b) = (a.eval(p), b.eval(p));
+                if a.0 >= b.0 { a } else { b }
+            },
+            Sdf::Difference(a, b) => {
+                let (a, (db, nb)) = (a.eval(p), b.eval(p));
+                if a.0 >= -db { a } else { (-db, -nb) }
+            },
+            Sdf::SmoothUnion(a, b, k) => {
+                // Polynomial smooth minimum. The blended distance doesn't
+                // change with h, so the gradient blends the same way.
+                let ((da, na), (db, nb)) = (a.eval(p), b.eval(p));
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0., 1.);
+                (lerp(h, db, da) - k * h * (1. - h), nb.lerp(na, h))
+            },
+            Sdf::Custom(bounds, f) => {
+                // Central differences over a small fraction of the size
+                let e = 1e-5 * bounds.diagonal().magnitude();
+                let (x, y, z) = (Vector::unit_x() * e, Vector::unit_y() * e, Vector::unit_z() * e);
+                let n = Vector::new(
+                    f(p + x) - f(p - x),
+                    f(p + y) - f(p - y),
+                    f(p + z) - f(p - z)
+                );
+                let n = if n.magnitude2() > 0. { n.normalize() } else { Vector::unit_y() };
+                (f(*p), n)
+            }
+        }
+    }
+
+    /// Box that contains the surface
+    pub fn bound(&self) -> Bounds {
+        match self {
+            Sdf::Sphere(center, radius) => {
+                let center = Point::from(*center);
+                Bounds::new(center - Vector::from_value(*radius), center + Vector::from_value(*radius))
+            },
+            Sdf::Cuboid(c0, c1) => Bounds::new((*c0).into(), (*c1).into()),
+            Sdf::Torus(center, radius, tube_radius) => {
+                let center = Point::from(*center);
+                let extent = Vector::new(radius + tube_radius, *tube_radius, radius + tube_radius);
+                Bounds::new(center - extent, center + extent)
+            },
+            Sdf::Union(a, b) => a.bound().union(&b.bound()),
+            Sdf::Intersection(a, b) => a.bound().intersection(&b.bound()),
+            Sdf::Difference(a, _) => a.bound(),
+            // Blending swells the surface by up to a quarter of the distance
+            Sdf::SmoothUnion(a, b, k) => a.bound().union(&b.bound()).expand(0.25 * k),
+            Sdf::Custom(bounds, _) => *bounds
+        }
+    }
+}
+
+impl fmt::Debug for Sdf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Sdf::Sphere(center, radius) => write!(f, "Sphere({:?}, {})", center, radius),
+            Sdf::Cuboid(c0, c1) => write!(f, "Cuboid({:?}, {:?})", c0, c1),
+            Sdf::Torus(center, r0, r1) => write!(f, "Torus({:?}, {}, {})", center, r0, r1),
+            Sdf::Union(a, b) => write!(f, "Union({:?}, {:?})", a, b),
+            Sdf::Intersection(a, b) => write!(f, "Intersection({:?}, {:?})", a, b),
+            Sdf::Difference(a, b) => write!(f, "Difference({:?}, {:?})", a, b),
+            Sdf::SmoothUnion(a, b, k) => write!(f, "SmoothUnion({:?}, {:?}, {})", a, b, k),
+            Sdf::Custom(bounds, _) => write!(f, "Custom({:?})", bounds)
+        }
+    }
+}
+
+/// Shape given by a signed distance function. Rays find its surface by sphere
+/// tracing: stepping along the ray by the distance to the nearest surface,
+/// which can't skip over it.
+///
+/// https://iquilezles.org/articles/distfunctions/
+#[derive(Debug)]
+pub struct SdfPrimitive {
+    pub sdf: Sdf,
+    pub material: MaterialRef,
+    bounds: Bounds,
+
+    /// Distance to the surface that counts as a hit
+    epsilon: f64
+}
+
+impl SdfPrimitive {
+    pub fn new(sdf: Sdf, material: MaterialRef) -> SdfPrimitive {
+        let bounds = sdf.bound();
+        let epsilon = SDF_HIT_DISTANCE * bounds.diagonal().magnitude();
+        SdfPrimitive { sdf, material, bounds, epsilon }
+    }
+
+    /// Range of parameters of the given ray within the bounds, if any
+    fn clip(&self, ray: &Ray, t_max: f64) -> Option<(f64, f64)> {
+        let (mut t0, mut t1) = (ray.t_min, t_max);
+        for i in 0..3 {
+            let near = (self.bounds.min[i] - ray.origin[i]) * ray.dinv[i];
+            let far = (self.bounds.max[i] - ray.origin[i]) * ray.dinv[i];
+            if near.is_nan() || far.is_nan() { continue }
+            t0 = t0.max(near.min(far));
+            t1 = t1.min(near.max(far));
+        }
+        if t0 <= t1 { Some((t0, t1)) } else { None }
+    }
+
+    /// Ray parameter at which the ray meets the surface before t_max, if any
+    fn march(&self, ray: &Ray, t_max: f64) -> Option<f64> {
+        let (mut t, t1) = self.clip(ray, t_max)?;
+        let length = ray.d.magnitude();
+        for _ in 0..SDF_MAX_STEPS {
+            // Step by the magnitude so that rays inside the shape find their
+            // way out as well
+            let d = self.sdf.distance(&(ray.origin + ray.d * t)).abs();
+            if d < self.epsilon && t > ray.t_min { return Some(t) }
+            t += d.max(self.epsilon) / length;
+            if t > t1 { return None }
+        }
+        None
+    }
+}
+
+impl Primitive for SdfPrimitive {
+    fn bound(&self) -> Bounds {
+        self.bounds
+    }
+
+    fn intersect(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive<'_> {
+        let t = self.march(ray, isect.t.min(ray.t_max))?;
+        let p = ray.origin + ray.d * t;
+        let n = self.sdf.eval(&p).1.normalize();
+
+        // Tangents whose cross product is the outward normal
+        let (dpdu, dpdv) = coordinate_system(&n);
+
+        // Map the normal direction onto the texture like a sphere
+        let mut phi = n.y.atan2(n.x);
+        if phi < 0. { phi += 2. * PI }
+        let uv = Point2f::new(phi / (2. * PI), n.z.clamp(-1., 1.).acos() / PI);
+
+        *isect = RayIntersection::new(t, p, uv, dpdu, dpdv);
+
+        // Rays leaving the surface start clear of the hit distance
+        isect.p_err = Vector::from_value(4. * self.epsilon);
+        Some(self)
+    }
+
+    fn intersects(&self, ray: &Ray) -> bool {
+        self.march(ray, ray.t_max).is_some()
+    }
+
+    fn material(&self) -> Option<MaterialRef> { Some(self.material) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn traces_blended_spheres() {
+        let sdf = Sdf::sphere([-0.5, 0., 0.], 0.5).smooth_union(Sdf::sphere([0.5, 0., 0.], 0.5), 0.2);
+        let prim = SdfPrimitive::new(sdf, MaterialRef::default());
+
+        // Straight on, the left sphere is unaffected by the blend
+        let ray = Ray::new(Point::new(-0.5, 0., 2.), Vector::new(0., 0., -1.));
+        let mut isect = RayIntersection::default();
+        assert!(prim.intersect(&ray, &mut isect).is_some());
+        assert!((isect.t - 1.5).abs() < 1e-5, "{}", isect.t);
+        assert!((isect.ng() - Vector::unit_z()).magnitude() < 1e-5);
+
+        // The seam is filled in between the spheres
+        let ray = Ray::new(Point::new(0., 2., 0.), Vector::new(0., -1., 0.));
+        assert!(prim.intersect(&ray, &mut RayIntersection::default()).is_some());
+        assert!(Sdf::sphere([-0.5, 0., 0.], 0.5).distance(&Point::new(0., 0.3, 0.)) > 0.);
+    }
+
+    #[test]
+    fn custom_normals_match_exact() {
+        let exact = SdfPrimitive::new(Sdf::torus([0., 0., 0.], 1., 0.25), MaterialRef::default());
+        let custom = SdfPrimitive::new(Sdf::custom([-1.25, -0.25, -1.25], [1.25, 0.25, 1.25], |p| {
+            Vector2f::new((p.x * p.x + p.z * p.z).sqrt() - 1., p.y).magnitude() - 0.25
+        }), MaterialRef::default());
+
+        let ray = Ray::new(Point::new(0.3, 2., 0.9), Vector::new(0.2, -1., 0.));
+        let (mut a, mut b) = (RayIntersection::default(), RayIntersection::default());
+        assert!(exact.intersect(&ray, &mut a).is_some());
+        assert!(custom.intersect(&ray, &mut b).is_some());
+        assert!((a.t - b.t).abs() < 1e-6);
+        assert!((a.ng() - b.ng()).magnitude() < 1e-4);
+
+        // The ray passes through the hole in the middle
+        let ray = Ray::new(Point::new(0., 2., 0.), Vector::new(0., -1., 0.));
+        assert!(!exact.intersects(&ray));
+    }
+}
@@ -1,9 +1,9 @@
 use std::f64::{NEG_INFINITY, consts::PI};
 use crate::core::math;
-use crate::space::*;
+use crate::space::{*, normal::Normal3};
 use crate::primitive::{Primitive, OptionalPrimitive};
 use crate::interaction::RayIntersection;
-use crate::Material;
+use crate::MaterialRef;
 
 /**
     A sphere of any size positioned somewhere in 3D space
@@ -12,11 +12,11 @@ use crate::Material;
 pub struct Sphere {
     pub origin: Point,
     pub radius: f64,
-    pub material: Material
+    pub material: MaterialRef
 }
 
 impl Sphere {
-    pub fn new(origin: [f64; 3], radius: f64, material: Material) -> Sphere {
+    pub fn new(origin: [f64; 3], radius: f64, material: MaterialRef) -> Sphere {
         Sphere {
             origin: Point::new(origin[0], origin[1], origin[2]),
             radius,
@@ -24,8 +24,8 @@ impl Sphere {
         }
     }
 
-    /// Returns the parametric t at the point of intersection. Negative values
-    /// mean no intersection. The second return parameter is true if the
+    /// Returns the parametric t at the point of intersection. Values outside
+    /// the range of the ray mean no intersection. The second return parameter is true if the
     /// intersection happens inside the sphere
     fn intersect_t(&self, ray: &Ray) -> (f64, bool) {
         let d = ray.d;
@@ -60,7 +60,7 @@ impl Sphere {
             let (t0, t1) = (roots[0].min(roots[1]), roots[0].max(roots[1]));
 
             // Check if ray origin is inside the sphere
-            if t0 < 0.0 { (t1, true) } else { (t0, false) }
+            if t0 <= ray.t_min { (t1, true) } else { (t0, false) }
         } else if numroots == 1 {
             (roots[0], false)
         } else {
@@ -77,10 +77,10 @@ impl Primitive for Sphere {
     }
 
     fn intersect(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive {
-        let (t, inside) = self.intersect_t(ray);
+        let (t, _) = self.intersect_t(ray);
 
-        // Intersection behind the ray, do nothing
-        if t < 0.0 { return None; }
+        // Intersection behind the ray or beyond its extent, do nothing
+        if !ray.in_range(t) { return None; }
 
         // A better intersection was already found, continue
         if t >= isect.t { return None }
@@ -88,8 +88,13 @@ impl Primitive for Sphere {
         // The ray definitely intersects with the sphere, calcuate shading
         // parameters.
 
-        // Subtract the origin to find intersection from the centre
+        // Subtract the origin to find intersection from the centre. Project
+        // the point back onto the surface, which bounds its error much more
+        // tightly than the ray equation does.
         let mut p = ray.origin + ray.d * t - self.origin;
+        p *= self.radius / p.magnitude();
+        let hit = self.origin + p;
+        let p_err = gamma::<f64>(5) * abs(&p) + gamma::<f64>(1) * abs(&hit.to_vec());
 
         // Account for intersection right at the top
         if p.x == 0.0 && p.y == 0.0 { p.x = 1e-5 * self.radius }
@@ -113,20 +118,39 @@ impl Primitive for Sphere {
             z: -self.radius * theta.sin()
         };
 
-        // Swap if outside the sphere
-        let (dpdu, dpdv) = if inside { (dpdu, dpdv) } else { (dpdv, dpdu) };
+        // Swap so that the geometric normal points away from the centre, also
+        // for rays from inside the sphere, which then hit its back
+        let uv = Point2f::new(phi / (2.0 * PI), theta / PI);
+        *isect = RayIntersection::new(t, hit, uv, dpdv, dpdu);
+        isect.p_err = p_err;
 
-        // FIXME: Get correct UVs
-        *isect = RayIntersection::new(t, Point2f::new(0.0, 0.0), dpdu, dpdv);
+        // Keep the surface shading differentials in (u, v) order so that the
+        // shading frame follows the texture coordinates, e.g., for anisotropic
+        // materials. The shading normal stays the same as the geometric one.
+        isect.n = Some(Normal3(isect.ng()));
+        isect.set_surface_shading(dpdu, dpdv);
 
         Some(self)
     }
 
     fn intersects(&self, ray: &Ray) -> bool {
-        self.intersect_t(ray).0 >= 0.0
+        ray.in_range(self.intersect_t(ray).0)
     }
 
-    fn material(&self) -> Option<Material> { Some(self.material) }
+    fn material(&self) -> Option<MaterialRef> { Some(self.material) }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn sample_area(&self, u: &Point2f) -> Option<(Point, Vector)> {
+        // Uniform over the unit sphere: z is uniform in [-1, 1]
+        let z = 1.0 - 2.0 * u.x;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.y;
+        let n = Vector::new(r * phi.cos(), r * phi.sin(), z);
+        Some((self.origin + n * self.radius, n))
+    }
 }
 
 #[cfg(test)]
@@ -135,7 +159,7 @@ mod test {
 
     #[test]
     fn straight_on_intersection() {
-        let sphere = Sphere::new([0.0, 0.0, 0.0], 1.0, Material::default());
+        let sphere = Sphere::new([0.0, 0.0, 0.0], 1.0, MaterialRef::default());
         let origin = Point::new(0.0, 0.0, 2.0);
         let ray = Ray::new(origin, Vector::new(0.0, 0.0, -1.0));
         let mut isect = RayIntersection::default();
@@ -145,20 +169,31 @@ mod test {
         assert_eq!(isect.ng(), Vector::new(0.0, 0.0, 1.0));
     }
 
+    #[test]
+    fn surface_shading_follows_uv() {
+        let sphere = Sphere::new([0.0, 0.0, 0.0], 1.0, MaterialRef::default());
+        let ray = Ray::new(Point::new(2.0, 0.0, 0.0), Vector::new(-1.0, 0.0, 0.0));
+        let mut isect = RayIntersection::default();
+
+        assert!(sphere.intersect(&ray, &mut isect).is_some());
+        assert!((isect.surface.dpdu.normalize() - Vector::unit_y()).magnitude() < 1e-9);
+        assert!((isect.ns() - Vector::unit_x()).magnitude() < 1e-9);
+    }
+
     #[test]
     fn inside_intersection() {
-        let sphere = Sphere::new([0.0, 0.0, 0.0], 1.0, Material::default());
+        let sphere = Sphere::new([0.0, 0.0, 0.0], 1.0, MaterialRef::default());
         let ray = Ray::new(Point::new(0.0, 0.0, 0.0), Vector::new(0.0, 0.0, 1.0));
         let mut isect = RayIntersection::default();
 
         assert!(sphere.intersect(&ray, &mut isect).is_some());
         assert_eq!(isect.t, 1.0);
-        assert_eq!(isect.ng(), Vector::new(0.0, 0.0, -1.0));
+        assert!((isect.ng() - Vector::new(0.0, 0.0, 1.0)).magnitude() < 1e-9);
     }
 
     #[test]
     fn behind_intersection() {
-        let sphere = Sphere::new([0.0, 0.0, 0.0], 1.0, Material::default());
+        let sphere = Sphere::new([0.0, 0.0, 0.0], 1.0, MaterialRef::default());
         let origin = Point::new(0.0, 0.0, -2.0);
         let ray = Ray::new(origin, Vector::new(0.0, 0.0, 1.0));
         let mut isect = RayIntersection::default();
@@ -0,0 +1,254 @@
+//! Catmull-Clark subdivision surfaces. A coarse cage of quads and other
+//! polygons is subdivided a fixed number of times when it's added to the
+//! scene and rendered as the resulting triangle mesh, so smooth models don't
+//! need to be exported as dense meshes.
+//!
+//! https://en.wikipedia.org/wiki/Catmull%E2%80%93Clark_subdivision_surface
+
+use std::collections::HashMap;
+use obj::Obj;
+
+use crate::space::*;
+use super::mesh::TriangleMesh;
+
+/// Control cage of a Catmull-Clark subdivision surface. Faces may have any
+/// number of sides, but quads subdivide the most evenly. Edges with only one
+/// face, such as around holes, become curves along the boundary of the cage,
+/// and vertices of only one face stay put as sharp corners.
+#[derive(Debug, Clone, Default)]
+pub struct SubdivMesh {
+    /// x, y, z for each vertex of the cage
+    pub positions: Vec<f64>,
+
+    /// Vertex indices around each face, in order
+    pub faces: Vec<Vec<usize>>,
+
+    /// Index of the material group of each face, or empty if there are none
+    pub groups: Vec<u32>,
+
+    /// Number of times the cage is subdivided. Each level splits every face
+    /// into quads, one per corner.
+    pub levels: u32
+}
+
+impl SubdivMesh {
+    pub fn new(positions: Vec<f64>, faces: Vec<Vec<usize>>, levels: u32) -> SubdivMesh {
+        SubdivMesh { positions, faces, groups: vec![], levels }
+    }
+
+    /// Cage made of the polygons of the given .obj mesh, with the index of
+    /// the .obj group of each face, counting the groups of every object in
+    /// order. Normals and texture coordinates are dropped since they don't
+    /// survive subdivision.
+    pub fn from_obj(obj: &Obj, levels: u32) -> SubdivMesh {
+        let data = &obj.data;
+        let positions = data.position.iter().flat_map(|p| p.iter().map(|c| *c as f64)).collect();
+        let mut mesh = SubdivMesh { positions, faces: vec![], groups: vec![], levels };
+        let mut group = 0;
+        for object in data.objects.iter() {
+            for g in object.groups.iter() {
+                for poly in g.polys.iter() {
+                    mesh.faces.push(poly.0.iter().map(|tuple| tuple.0).collect());
+                    mesh.groups.push(group);
+                }
+                group += 1;
+            }
+        }
+        mesh
+    }
+
+    /// Subdivide the cage and split the resulting quads into triangles with
+    /// smooth normals. Also returns the group of each triangle, which is
+    /// empty if the cage has no groups. Panics if the faces refer to missing
+    /// vertices.
+    pub fn subdivide(&self) -> (TriangleMesh, Vec<u32>) {
+        let mut points: Vec<Vector> = self.positions.chunks(3)
+            .map(|p| Vector::new(p[0], p[1], p[2]))
+            .collect();
+        let mut faces: Vec<Vec<usize>> = self.faces.iter().filter(|f| f.len() >= 3).cloned().collect();
+        let mut groups: Vec<u32> = if self.groups.is_empty() {
+            vec![]
+        } else {
+            self.faces.iter().zip(self.groups.iter()).filter(|(f, _)| f.len() >= 3).map(|(_, g)| *g).collect()
+        };
+
+        for _ in 0..self.levels {
+            let (p, f) = refine(&points, &faces);
+            if !groups.is_empty() {
+                groups = faces.iter().zip(groups.iter())
+                    .flat_map(|(face, g)| std::iter::repeat_n(*g, face.len()))
+                    .collect();
+            }
+            points = p;
+            faces = f;
+        }
+
+        let mut mesh = TriangleMesh {
+            positions: points.iter().flat_map(|p| [p.x, p.y, p.z]).collect(),
+            ..TriangleMesh::default()
+        };
+        let mut triangle_groups = vec![];
+        for (i, face) in faces.iter().enumerate() {
+            for k in 1..face.len() - 1 {
+                mesh.indices.extend_from_slice(&[face[0], face[k], face[k + 1]]);
+                if !groups.is_empty() { triangle_groups.push(groups[i]) }
+            }
+        }
+        mesh.generate_normals(180.0);
+        (mesh, triangle_groups)
+    }
+}
+
+/// Ends of an edge of the cage and the faces that share it
+struct Edge {
+    ends: (usize, usize),
+    faces: Vec<usize>
+}
+
+/// Sums of the points around a vertex of the cage
+struct Around {
+    faces: Vector,
+    face_count: usize,
+    midpoints: Vector,
+    edge_count: usize,
+
+    /// Other ends of the edges from the vertex with fewer or more than two
+    /// faces
+    boundary: Vec<usize>
+}
+
+/// Apply one level of subdivision to the given points and faces. Returns the
+/// new points, which are the moved vertices followed by a point for each face
+/// and then for each edge, along with the new quads.
+fn refine(points: &[Vector], faces: &[Vec<usize>]) -> (Vec<Vector>, Vec<Vec<usize>>) {
+    let face_points: Vec<Vector> = faces.iter()
+        .map(|f| f.iter().map(|v| points[*v]).sum::<Vector>() / f.len() as f64)
+        .collect();
+
+    let mut edges: Vec<Edge> = vec![];
+    let mut edge_index: HashMap<(usize, usize), usize> = HashMap::new();
+    for (f, face) in faces.iter().enumerate() {
+        for i in 0..face.len() {
+            let (a, b) = (face[i], face[(i + 1) % face.len()]);
+            let key = (a.min(b), a.max(b));
+            let e = *edge_index.entry(key).or_insert_with(|| {
+                edges.push(Edge { ends: key, faces: vec![] });
+                edges.len() - 1
+            });
+            edges[e].faces.push(f);
+        }
+    }
+
+    let mut around: Vec<Around> = points.iter().map(|_| Around {
+        faces: Vector::zero(),
+        face_count: 0,
+        midpoints: Vector::zero(),
+        edge_count: 0,
+        boundary: vec![]
+    }).collect();
+    for (f, face) in faces.iter().enumerate() {
+        for v in face.iter() {
+            around[*v].faces += face_points[f];
+            around[*v].face_count += 1;
+        }
+    }
+
+    // Edges shared by two faces move toward both face points; others stay on
+    // the line between their ends
+    let mut edge_points = Vec::with_capacity(edges.len());
+    for edge in edges.iter() {
+        let (a, b) = edge.ends;
+        let midpoint = (points[a] + points[b]) * 0.5;
+        for (v, other) in [(a, b), (b, a)] {
+            around[v].midpoints += midpoint;
+            around[v].edge_count += 1;
+            if edge.faces.len() != 2 { around[v].boundary.push(other) }
+        }
+        edge_points.push(match edge.faces.as_slice() {
+            [f0, f1] => (points[a] + points[b] + face_points[*f0] + face_points[*f1]) * 0.25,
+            _ => midpoint
+        });
+    }
+
+    let mut refined: Vec<Vector> = points.iter().zip(around.iter()).map(|(p, around)| {
+        match around.boundary.len() {
+            _ if around.edge_count == 0 => *p,
+            0 => {
+                let n = around.edge_count as f64;
+                let f = around.faces / around.face_count as f64;
+                let r = around.midpoints / n;
+                (f + r * 2.0 + p * (n - 3.0)) / n
+            },
+            2 if around.face_count > 1 => p * 0.75 + (points[around.boundary[0]] + points[around.boundary[1]]) * 0.125,
+            // Corners of single faces and where several boundaries meet stay
+            // put
+            _ => *p
+        }
+    }).collect();
+
+    let face_start = refined.len();
+    let edge_start = face_start + faces.len();
+    refined.extend(face_points);
+    refined.extend(edge_points);
+
+    let edge = |a: usize, b: usize| edge_start + edge_index[&(a.min(b), a.max(b))];
+    let mut quads = Vec::with_capacity(faces.iter().map(Vec::len).sum());
+    for (f, face) in faces.iter().enumerate() {
+        let n = face.len();
+        for i in 0..n {
+            let (prev, v, next) = (face[(i + n - 1) % n], face[i], face[(i + 1) % n]);
+            quads.push(vec![v, edge(v, next), face_start + f, edge(prev, v)]);
+        }
+    }
+    (refined, quads)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cube(levels: u32) -> SubdivMesh {
+        let positions = (0..8).flat_map(|i| [
+            if i & 1 == 0 { -1.0 } else { 1.0 },
+            if i & 2 == 0 { -1.0 } else { 1.0 },
+            if i & 4 == 0 { -1.0 } else { 1.0 }
+        ]).collect();
+        let faces = vec![
+            vec![0, 2, 3, 1], vec![4, 5, 7, 6], vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3], vec![0, 4, 6, 2], vec![1, 3, 7, 5]
+        ];
+        SubdivMesh::new(positions, faces, levels)
+    }
+
+    #[test]
+    fn subdivides_cube() {
+        let (mesh, groups) = cube(1).subdivide();
+        assert!(groups.is_empty());
+        assert_eq!(mesh.positions.len() / 3, 8 + 6 + 12);
+        assert_eq!(mesh.triangle_count(), 48);
+
+        // Corners move in to the average of their neighbourhood
+        let corner = &mesh.positions[21..24];
+        assert!(corner.iter().all(|c| (c - 5.0 / 9.0).abs() < 1e-12));
+
+        // Further levels stay symmetric about the centre
+        let (mesh, _) = cube(3).subdivide();
+        assert_eq!(mesh.triangle_count(), 6 * 4 * 4 * 4 * 2);
+        let sum = mesh.positions.chunks(3).fold(Vector::zero(), |s, p| s + Vector::new(p[0], p[1], p[2]));
+        assert!(sum.magnitude() < 1e-9);
+    }
+
+    #[test]
+    fn keeps_boundaries() {
+        // A flat square stays flat, and its edges stay on the border
+        let positions = vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0];
+        let mut square = SubdivMesh::new(positions, vec![vec![0, 1, 2, 3]], 2);
+        square.groups = vec![7];
+        let (mesh, groups) = square.subdivide();
+        assert_eq!(groups, vec![7; 32]);
+        assert!(mesh.positions.chunks(3).all(|p| p[2] == 0.0));
+        let on_border = mesh.positions.chunks(3).filter(|p| p[0] == 0.0 || p[0] == 1.0 || p[1] == 0.0 || p[1] == 1.0).count();
+        assert_eq!(on_border, 16);
+        assert!(mesh.positions.chunks(3).any(|p| p[0] == 0.0 && p[1] == 0.0));
+    }
+}
@@ -1,15 +1,106 @@
 // use std::ops::Index;
-use std::{path::{Path, PathBuf}, io::{self, BufRead, BufReader}, fs::File};
+use std::{path::{Path, PathBuf}, io::{self, BufRead}, fs};
 use obj::Obj;
 
 use crate::{
     space::*,
+    shape::mesh::{TriangleMesh, Smoothing},
     primitive::{Primitive, OptionalPrimitive},
     interaction::RayIntersection,
-    Material
+    MaterialRef
 };
 
-/// A triangle references its parent mesh and the index within the faces array.
+/// A triangle mesh along with the materials of its triangles, if any.
+pub struct Mesh {
+    pub triangles: TriangleMesh,
+
+    /// Index into `materials` of each triangle. Empty if every triangle uses
+    /// the default material.
+    groups: Vec<u32>,
+
+    /// Materials referenced by the triangles, such as those built from the
+    /// .mtl libraries of each group of a .obj file.
+    materials: Vec<Option<MaterialRef>>,
+
+    /// Whether to ignore the normals of the mesh
+    flat: bool
+}
+
+impl Mesh {
+    /// Create a mesh where every triangle uses the default material
+    pub fn new(triangles: TriangleMesh) -> Mesh {
+        Mesh { triangles, groups: vec![], materials: vec![], flat: false }
+    }
+
+    /// Create a mesh with the given index into the given materials for each
+    /// triangle
+    pub fn with_materials(triangles: TriangleMesh, groups: Vec<u32>, materials: Vec<Option<MaterialRef>>) -> Mesh {
+        debug_assert!(groups.len() == triangles.triangle_count());
+        debug_assert!(groups.iter().all(|g| (*g as usize) < materials.len()));
+        Mesh { triangles, groups, materials, flat: false }
+    }
+
+    /// Mesh of the given triangles with the materials and shading of this
+    /// one, where each triangle takes the material of the triangle of this
+    /// mesh at the given index
+    pub(crate) fn with_triangles(&self, triangles: TriangleMesh, sources: &[u32]) -> Mesh {
+        debug_assert!(sources.len() == triangles.triangle_count());
+        let groups = if self.groups.is_empty() {
+            vec![]
+        } else {
+            sources.iter().map(|t| self.groups[*t as usize]).collect()
+        };
+        Mesh { triangles, groups, materials: self.materials.clone(), flat: self.flat }
+    }
+
+    /// Choose how shading normals are found. Switching back from flat
+    /// shading restores the normals of the mesh.
+    pub fn set_smoothing(&mut self, smoothing: Smoothing) {
+        match smoothing {
+            Smoothing::Flat => self.flat = true,
+            Smoothing::Smooth(crease_angle) => {
+                self.flat = false;
+                if self.triangles.normals.is_none() {
+                    self.triangles.generate_normals(crease_angle)
+                }
+            }
+        }
+    }
+
+    // Whether this mesh has vertex colours
+    #[inline]
+    pub fn has_colors(&self) -> bool {
+        self.triangles.colors.is_some()
+    }
+
+    /// Material of the triangle at the given index, if any
+    #[inline]
+    pub fn material(&self, triangle: usize) -> Option<MaterialRef> {
+        match self.groups.get(triangle) {
+            Some(group) => self.materials[*group as usize],
+            None => None
+        }
+    }
+
+    /// Materials referenced by the groups of triangles in this mesh
+    pub fn materials(&self) -> impl Iterator<Item = MaterialRef> + '_ {
+        self.materials.iter().flatten().copied()
+    }
+
+    /// Index of the group of the triangle at the given index, if the
+    /// triangles have groups
+    #[inline]
+    pub(crate) fn group(&self, triangle: usize) -> Option<usize> {
+        self.groups.get(triangle).map(|group| *group as usize)
+    }
+
+    /// Material of each group of triangles, indexed as by `group`
+    pub(crate) fn group_materials(&self) -> &[Option<MaterialRef>] {
+        &self.materials
+    }
+}
+
+/// A triangle references its parent mesh and its index within the mesh.
 /// The triangle's lifetime depends on the mesh it references.
 ///
 /// This implementation ensures the smallest possible triangle implementation
@@ -17,63 +108,65 @@ use crate::{
 #[derive(Copy, Clone)]
 pub struct Triangle<'a> {
 
-    /// Object index within .obj mesh
-    object: u16,
-
-    /// Group index within the object
-    group: u16,
+    /// Triangle index within the mesh
+    index: u32,
 
-    /// Polygon index into the group. Contains face data as TriangleIndex
-    poly: u32,
-
-    /// Reference to the parsed .obj file mesh that contains this triangle
-    /// Used to extract information like vertex positions
-    obj: &'a Obj,
+    /// Reference to the mesh that contains this triangle. Used to extract
+    /// information like vertex positions
+    mesh: &'a Mesh,
 }
 
 impl<'a> Triangle<'a> {
-    pub fn new(obj: &'a Obj, object: u16, group: u16, poly: u32) -> Triangle<'a> {
-        Triangle { object, group, poly, obj }
+    pub fn new(mesh: &'a Mesh, index: u32) -> Triangle<'a> {
+        Triangle { index, mesh }
     }
 
+    /// Index of the given vertex (0 to 2) of this triangle within the mesh
     #[inline]
-    pub fn p0(&self) -> Point {
-        let v = self.obj.data.position[((self.poly().0)[0]).0];
-        Point::new(v[0].into(), v[1].into(), v[2].into())
+    fn vertex(&self, i: usize) -> usize {
+        self.mesh.triangles.indices[3 * self.index as usize + i]
     }
 
     #[inline]
-    pub fn p1(&self) -> Point {
-        let v = self.obj.data.position[((self.poly().0)[1]).0];
-        Point::new(v[0].into(), v[1].into(), v[2].into())
+    fn p(&self, i: usize) -> Point {
+        let v = 3 * self.vertex(i);
+        let p = &self.mesh.triangles.positions;
+        Point::new(p[v], p[v + 1], p[v + 2])
     }
 
     #[inline]
-    pub fn p2(&self) -> Point {
-        let v = self.obj.data.position[((self.poly().0)[2]).0];
-        Point::new(v[0].into(), v[1].into(), v[2].into())
+    fn n(&self, i: usize) -> Vector {
+        debug_assert!(self.has_n());
+        let v = 3 * self.vertex(i);
+        let n = self.mesh.triangles.normals.as_ref().unwrap();
+        Vector::new(n[v], n[v + 1], n[v + 2])
     }
 
     #[inline]
-    pub fn n0(&self) -> Vector {
-        debug_assert!(self.has_n());
-        let n = self.obj.data.normal[(((self.poly().0)[0]).2).unwrap()];
-        Vector::new(n[0].into(), n[1].into(), n[2].into())
+    fn uv_at(&self, i: usize) -> Point2f {
+        debug_assert!(self.has_uv());
+        let v = 2 * self.vertex(i);
+        let uv = self.mesh.triangles.uvs.as_ref().unwrap();
+        Point2f::new(uv[v], uv[v + 1])
     }
 
     #[inline]
-    pub fn n1(&self) -> Vector {
-        debug_assert!(self.has_n());
-        let n = self.obj.data.normal[(((self.poly().0)[1]).2).unwrap()];
-        Vector::new(n[0].into(), n[1].into(), n[2].into())
-    }
+    pub fn p0(&self) -> Point { self.p(0) }
 
     #[inline]
-    pub fn n2(&self) -> Vector {
-        debug_assert!(self.has_n());
-        let n = self.obj.data.normal[(((self.poly().0)[2]).2).unwrap()];
-        Vector::new(n[0].into(), n[1].into(), n[2].into())
-    }
+    pub fn p1(&self) -> Point { self.p(1) }
+
+    #[inline]
+    pub fn p2(&self) -> Point { self.p(2) }
+
+    #[inline]
+    pub fn n0(&self) -> Vector { self.n(0) }
+
+    #[inline]
+    pub fn n1(&self) -> Vector { self.n(1) }
+
+    #[inline]
+    pub fn n2(&self) -> Vector { self.n(2) }
 
     /// Find all triangle UV texture coordinates
     #[inline]
@@ -90,79 +183,44 @@ impl<'a> Triangle<'a> {
     }
 
     #[inline]
-    pub fn uv0(&self) -> Point2f {
-        debug_assert!(self.has_uv());
-        let tuple = (self.poly().0)[0];
-        let uv = self.obj.data.texture[(tuple.1).unwrap()];
-        Point2f::new(uv[0].into(), uv[1].into())
-    }
+    pub fn uv0(&self) -> Point2f { self.uv_at(0) }
 
     #[inline]
-    pub fn uv1(&self) -> Point2f {
-        debug_assert!(self.has_uv());
-        let tuple = (self.poly().0)[1];
-        let uv = self.obj.data.texture[(tuple.1).unwrap()];
-        Point2f::new(uv[0].into(), uv[1].into())
-    }
+    pub fn uv1(&self) -> Point2f { self.uv_at(1) }
 
     #[inline]
-    pub fn uv2(&self) -> Point2f {
-        debug_assert!(self.has_uv());
-        let tuple = (self.poly().0)[2];
-        let uv = self.obj.data.texture[(tuple.1).unwrap()];
-        Point2f::new(uv[0].into(), uv[1].into())
+    pub fn uv2(&self) -> Point2f { self.uv_at(2) }
+
+    /// Colours of the three vertices
+    #[inline]
+    pub fn colors(&self) -> [Color; 3] {
+        debug_assert!(self.mesh.has_colors());
+        let colors = self.mesh.triangles.colors.as_ref().unwrap();
+        let c = |i: usize| {
+            let v = 3 * self.vertex(i);
+            Color::new(colors[v], colors[v + 1], colors[v + 2])
+        };
+        [c(0), c(1), c(2)]
     }
 
     // Whether this mesh has normals mapped
     #[inline]
     pub fn has_n(&self) -> bool {
-        self.obj.data.normal.len() > 0
+        !self.mesh.flat && self.mesh.triangles.normals.is_some()
     }
 
     // Whether this mesh has UV texture coordinates mapped
     #[inline]
     pub fn has_uv(&self) -> bool {
-        self.obj.data.texture.len() > 0
+        self.mesh.triangles.uvs.is_some()
     }
 
-    #[inline]
-    fn object(&self) -> &obj::Object {
-        &self.obj.data.objects[self.object as usize]
-    }
-
-    #[inline]
-    fn group(&self) -> &obj::Group {
-        &self.object().groups[self.group as usize]
-    }
-
-    #[inline]
-    fn poly(&self) -> &obj::SimplePolygon {
-        &self.group().polys[self.poly as usize]
-    }
-}
-
-// TODO: Make it possible to return a &Point somehow
-/*
-impl<'a> Index<usize> for Triangle<'a> {
-    type Output = Point;
-    #[inline]
-    fn index(&self, index: usize) -> &Point {
-        debug_assert!(index < 3);
-        &self.p(index)
-    }
-}
-*/
-
-impl<'a> Primitive for Triangle<'a> {
-    fn bound(&self) -> Bounds {
-        Bounds::new(self.p0(), self.p1()).point_union(&self.p2())
-    }
-
-    fn intersect(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive {
-        // 1. Get triangle vertices
+    /// Ray parameter and barycentric coordinates of the intersection of the
+    /// given ray with this triangle, if any, nearer than t_max. Watertight, so
+    /// rays through an edge shared by two triangles hit one of them.
+    fn hit(&self, ray: &Ray, t_max: f64) -> Option<(f64, f64, f64, f64)> {
         let (p0, p1, p2) = (self.p0(), self.p1(), self.p2());
 
-        // 2. Perform ray-triangle intersection
         // Transform triangle vertices to ray coordinate space
         // This reduces the problem to determining whether the origin (0, 0) lies within
         // the triangle made up of the (x, y) components of the transformed 3D triangle
@@ -236,8 +294,12 @@ impl<'a> Primitive for Triangle<'a> {
         p2t.z *= sz;
         let tscaled = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
 
-        // Check for mismatched determinant and tscaled signs (triangle is behind ray)
-        if (det < 0.0 && tscaled >= 0.0) || (det > 0.0 && tscaled <= 0.0) {
+        // Check for mismatched determinant and tscaled signs (triangle is
+        // behind ray), or a hit beyond the end of the ray or the nearest hit
+        // so far, before paying for the division
+        let t_end = t_max.min(ray.t_max);
+        if (det < 0.0 && (tscaled >= 0.0 || tscaled < t_end * det))
+        || (det > 0.0 && (tscaled <= 0.0 || tscaled > t_end * det)) {
             return None
         }
 
@@ -248,9 +310,48 @@ impl<'a> Primitive for Triangle<'a> {
         let b1 = e1 * invdet;
         let b2 = e2 * invdet;
         let t = tscaled * invdet;
-        if t >= isect.t { return None };
+        if !ray.in_range(t) || t >= t_max { return None };
+
+        // Ensure that the computed t is conservatively greater than 0, given
+        // the rounding error accumulated by each of the above steps
+        let max_zt = p0t.z.abs().max(p1t.z.abs()).max(p2t.z.abs());
+        let delta_z = gamma::<f64>(3) * max_zt;
+        let max_xt = p0t.x.abs().max(p1t.x.abs()).max(p2t.x.abs());
+        let max_yt = p0t.y.abs().max(p1t.y.abs()).max(p2t.y.abs());
+        let delta_x = gamma::<f64>(5) * (max_xt + max_zt);
+        let delta_y = gamma::<f64>(5) * (max_yt + max_zt);
+        let delta_e = 2.0 * (gamma::<f64>(2) * max_xt * max_yt + delta_y * max_xt + delta_x * max_yt);
+        let max_e = e0.abs().max(e1.abs()).max(e2.abs());
+        let delta_t = 3.0 * (gamma::<f64>(3) * max_e * max_zt + delta_e * max_zt + delta_z * max_e) * invdet.abs();
+        if t <= delta_t { return None }
+
+        Some((t, b0, b1, b2))
+    }
+}
 
-        // TODO: ensure that computed triangle t is conservatively greater than 0
+// TODO: Make it possible to return a &Point somehow
+/*
+impl<'a> Index<usize> for Triangle<'a> {
+    type Output = Point;
+    #[inline]
+    fn index(&self, index: usize) -> &Point {
+        debug_assert!(index < 3);
+        &self.p(index)
+    }
+}
+*/
+
+impl<'a> Primitive for Triangle<'a> {
+    fn bound(&self) -> Bounds {
+        Bounds::new(self.p0(), self.p1()).point_union(&self.p2())
+    }
+
+    fn intersect(&self, ray: &Ray, isect: &mut RayIntersection) -> OptionalPrimitive<'_> {
+        // 1. Get triangle vertices
+        let (p0, p1, p2) = (self.p0(), self.p1(), self.p2());
+
+        // 2. Perform ray-triangle intersection
+        let (t, b0, b1, b2) = self.hit(ray, isect.t)?;
 
         // TODO: shading normals
 
@@ -269,17 +370,26 @@ impl<'a> Primitive for Triangle<'a> {
             )
         };
 
-        // Hit uv point
+        // 4. Interpolate hit point from the barycentric coordinates, which
+        // bounds its error more tightly than the ray equation does
+        let p = Point::from_vec(b0 * p0.to_vec() + b1 * p1.to_vec() + b2 * p2.to_vec());
+        let p_err = gamma::<f64>(7) * (abs(&(b0 * p0.to_vec())) + abs(&(b1 * p1.to_vec())) + abs(&(b2 * p2.to_vec())));
 
-        // TODO: 4. compute error bounds for triangle intersections
-        // 5. Interpolate (u, v) parametric coordinates (hit point determined later)
-        let uv = b0 * uv[0].add_element_wise(b1 * uv[1]).add_element_wise(b2 * uv[2]);
+        // 5. Interpolate (u, v) parametric coordinates
+        let uv = (b0 * uv[0]).add_element_wise(b1 * uv[1]).add_element_wise(b2 * uv[2]);
 
-        // TODO: 6. Test intersection against alpha texture, if present
+        // 6. Alpha textures are tested by the scene hierarchy once the
+        // material at the hit is known, see BVHAccel::intersect_opaque
 
         // 7. fill in Intersection from triangle hit
         // There is for sure an intersection at this point, compute the normal from original points
-        *isect = RayIntersection::new(t, uv, dpdu, dpdv);
+        *isect = RayIntersection::new(t, p, uv, dpdu, dpdv);
+        isect.p_err = p_err;
+
+        if self.mesh.has_colors() {
+            let c = self.colors();
+            isect.color = b0 * c[0] + b1 * c[1] + b2 * c[2];
+        }
 
         if self.has_n() {
             // Compute shading normal ns, surface tangent ss for triangle
@@ -306,30 +416,41 @@ impl<'a> Primitive for Triangle<'a> {
         Some(self)
     }
 
-    // TODO: Grab a material from the loaded Mtl libraries if one is available
-    fn material(&self) -> Option<Material> { None }
+    fn intersects(&self, ray: &Ray) -> bool {
+        self.hit(ray, f64::INFINITY).is_some()
+    }
+
+    /// Material from the loaded .mtl libraries, if one is available
+    fn material(&self) -> Option<MaterialRef> {
+        self.mesh.material(self.index as usize)
+    }
+
+    fn area(&self) -> f64 {
+        0.5 * (self.p1() - self.p0()).cross(self.p2() - self.p0()).magnitude()
+    }
+
+    fn sample_area(&self, u: &Point2f) -> Option<(Point, Vector)> {
+        // Uniformly distributed barycentric coordinates
+        let su0 = u.x.sqrt();
+        let (b0, b1) = (1.0 - su0, u.y * su0);
+        let (p0, p1, p2) = (self.p0(), self.p1(), self.p2());
+        let p = p0 * b0 + (p1 * b1).to_vec() + (p2 * (1.0 - b0 - b1)).to_vec();
+        let n = (p1 - p0).cross(p2 - p0).normalize();
+        Some((p, n))
+    }
 }
 
-/// Structure that allows using a obj as an iterator
-/// Each item in the iterator is a triangle that references the parent obj
+/// Structure that allows using a mesh as an iterator
+/// Each item in the iterator is a triangle that references the parent mesh
 pub struct TriangleIterator<'a> {
-    obj: &'a Obj,
-    // Current iteration indeces
-    size_hint: usize,
-    object_index: usize,
-    group_index: usize,
-    poly_index: usize
+    mesh: &'a Mesh,
+    // Current iteration index
+    index: usize
 }
 
 impl<'a> TriangleIterator<'a> {
-    pub fn new(obj: &'a Obj) -> TriangleIterator<'a> {
-        TriangleIterator {
-            obj,
-            size_hint: face_count(obj),
-            object_index: 0,
-            group_index: 0,
-            poly_index: 0,
-        }
+    pub fn new(mesh: &'a Mesh) -> TriangleIterator<'a> {
+        TriangleIterator { mesh, index: 0 }
     }
 }
 
@@ -337,49 +458,18 @@ impl<'a> Iterator for TriangleIterator<'a> {
     type Item = Triangle<'a>;
 
     fn next(&mut self) -> Option<Triangle<'a>> {
-        if self.size_hint == 0 { return None };
-        let triangle = Triangle::new(
-            &self.obj,
-            self.object_index as u16,
-            self.group_index as u16,
-            self.poly_index as u32);
-
-        self.poly_index += 1;
-
-        if self.poly_index == self.obj.data.objects[self.object_index].groups[self.group_index].polys.len() {
-            self.poly_index = 0;
-            self.group_index += 1;
-        }
-
-        if self.group_index == self.obj.data.objects[self.object_index].groups.len() {
-            self.group_index = 0;
-            self.object_index += 1;
-        }
-
-        if self.object_index == self.obj.data.objects.len() {
-            self.size_hint = 0;
-        } else {
-            self.size_hint -= 1;
-        }
-
-        return Some(triangle)
+        if self.index == self.mesh.triangles.triangle_count() { return None };
+        let triangle = Triangle::new(self.mesh, self.index as u32);
+        self.index += 1;
+        Some(triangle)
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.size_hint, Some(self.size_hint))
+        let remaining = self.mesh.triangles.triangle_count() - self.index;
+        (remaining, Some(remaining))
     }
 }
 
-/// Load from an object file at the given path
-#[inline]
-pub fn load_obj(path: &Path) -> Result<Obj, obj::ObjError> {
-    let f = File::open(path)?;
-    let mut obj = obj_from_buf(&mut BufReader::new(f))?;
-    // unwrap is safe as we've read this file before
-    obj.path = path.parent().unwrap().to_owned();
-    Ok(obj)
-}
-
 /// Parse the string contents of a .obj file into a `Obj` instance.
 #[inline]
 pub fn parse_obj(slice: &str) -> Result<Obj, obj::ObjError> {
@@ -387,6 +477,58 @@ pub fn parse_obj(slice: &str) -> Result<Obj, obj::ObjError> {
     obj_from_buf(&mut buf)
 }
 
+/// Load from an object file at the given path, along with its vertex colours.
+/// See `strip_vertex_colors`.
+pub fn load_colored_obj(path: &Path) -> Result<(Obj, Vec<[f32; 3]>), obj::ObjError> {
+    let source = fs::read_to_string(path)?;
+    let (mut obj, colors) = parse_colored_obj(&source)?;
+    // unwrap is safe as we've read this file before
+    obj.path = path.parent().unwrap().to_owned();
+    Ok((obj, colors))
+}
+
+/// Parse the string contents of a .obj file along with its vertex colours. The
+/// colours are empty if the file has none.
+pub fn parse_colored_obj(slice: &str) -> Result<(Obj, Vec<[f32; 3]>), obj::ObjError> {
+    match strip_vertex_colors(slice) {
+        Some((stripped, colors)) => Ok((parse_obj(&stripped)?, colors)),
+        None => Ok((parse_obj(slice)?, vec![]))
+    }
+}
+
+/// Scanning and sculpting tools commonly write vertex colours as three extra
+/// values on each position, i.e., `v x y z r g b`, which the .obj parser does
+/// not accept. Returns the contents with the colours removed along with the
+/// colour of each vertex, or None if no vertex has a colour. Vertices without
+/// a colour are white.
+pub fn strip_vertex_colors(slice: &str) -> Option<(String, Vec<[f32; 3]>)> {
+    let mut stripped = String::with_capacity(slice.len());
+    let mut colors = vec![];
+    let mut found = false;
+
+    for line in slice.lines() {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.first() != Some(&"v") {
+            stripped.push_str(line);
+        } else if let (7, Ok(r), Ok(g), Ok(b)) = (
+            args.len(),
+            args.get(4).unwrap_or(&"").parse::<f32>(),
+            args.get(5).unwrap_or(&"").parse::<f32>(),
+            args.get(6).unwrap_or(&"").parse::<f32>()
+        ) {
+            stripped.push_str(&args[..4].join(" "));
+            colors.push([r, g, b]);
+            found = true;
+        } else {
+            stripped.push_str(line);
+            colors.push([1.0, 1.0, 1.0]);
+        }
+        stripped.push('\n');
+    }
+
+    if found { Some((stripped, colors)) } else { None }
+}
+
 /// Parse the given readable buffer of a .obj file into a `Obj` instance.
 #[inline]
 pub fn obj_from_buf<B>(input: &mut B) -> Result<Obj, obj::ObjError> where B: BufRead {
@@ -394,15 +536,6 @@ pub fn obj_from_buf<B>(input: &mut B) -> Result<Obj, obj::ObjError> where B: Buf
     Ok(Obj { data, path: PathBuf::new() })
 }
 
-/// Number of faces on this obj
-pub fn face_count(obj: &Obj) -> usize {
-    obj.data.objects.iter().fold(0, |size, object| {
-        object.groups.iter().fold(size, |size, group| {
-            size + group.polys.len()
-        })
-    })
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
@@ -422,7 +555,8 @@ f 1 3 4
 
         let ray = Ray::new(Point::new(0.0, 1.0, 0.0), Vector::new(0.0, -1.0, 0.0));
         let mut isect = RayIntersection::default();
-        for triangle in TriangleIterator::new(&plane) {
+        let mesh = Mesh::new(TriangleMesh::from_obj(&plane, &[]).0);
+        for triangle in TriangleIterator::new(&mesh) {
             triangle.intersect(&ray, &mut isect);
         }
 
@@ -430,6 +564,46 @@ f 1 3 4
         assert_eq!(isect.ng(), Vector::unit_y());
     }
 
+    #[test]
+    fn occlusion() {
+        let triangle = parse_obj("v -1 0 -1\nv 1 0 -1\nv 0 0 1\nf 1 2 3\n").unwrap();
+        let mesh = Mesh::new(TriangleMesh::from_obj(&triangle, &[]).0);
+        let triangle = TriangleIterator::new(&mesh).next().unwrap();
+
+        let ray = Ray::new(Point::new(0.0, 1.0, 0.0), Vector::new(0.0, -1.0, 0.0));
+        assert!(triangle.intersects(&ray));
+        assert!(!triangle.intersects(&ray.within(0.0, 0.5)));
+        assert!(!triangle.intersects(&Ray::new(Point::new(2.0, 1.0, 0.0), Vector::new(0.0, -1.0, 0.0))));
+        assert!(!triangle.intersects(&Ray::new(Point::new(0.0, 1.0, 0.0), Vector::new(0.0, 1.0, 0.0))));
+    }
+
+    #[test]
+    fn vertex_colors() {
+        let (plane, colors) = parse_colored_obj(r#"o plane
+v -1 0 -1 1 0 0
+v 1 0 -1 0 1 0
+v 1 0 1 0 0 1
+v -1 0 1
+
+f 1 2 3
+f 1 3 4
+"#
+        ).unwrap();
+        assert_eq!(colors[3], [1.0, 1.0, 1.0]);
+
+        let mesh = Mesh::new(TriangleMesh::from_obj(&plane, &colors).0);
+
+        // Hit the middle of the edge between the red and blue vertices
+        let ray = Ray::new(Point::new(0.0, 1.0, 0.0), Vector::new(0.0, -1.0, 0.0));
+        let mut isect = RayIntersection::default();
+        for triangle in TriangleIterator::new(&mesh) {
+            triangle.intersect(&ray, &mut isect);
+        }
+
+        assert_eq!(isect.t, 1.0);
+        assert!((isect.color - Color::new(0.5, 0.0, 0.5)).magnitude() < 1e-9);
+    }
+
     #[test]
     fn plane_intersection_with_normals_and_texture() {
         let plane = parse_obj(r#"o plane
@@ -445,11 +619,37 @@ f 1 3 4
 
         let ray = Ray::new(Point::new(0.0, 1.0, 0.0), Vector::new(0.0, -1.0, 0.0));
         let mut isect = RayIntersection::default();
-        for triangle in TriangleIterator::new(&plane) {
+        let mesh = Mesh::new(TriangleMesh::from_obj(&plane, &[]).0);
+        for triangle in TriangleIterator::new(&mesh) {
             triangle.intersect(&ray, &mut isect);
         }
 
         assert_eq!(isect.t, 1.0);
         assert_eq!(isect.ng(), Vector::unit_y());
     }
+
+    #[test]
+    fn group_materials() {
+        let quad = parse_obj(r#"v -1 0 -1
+v 1 0 -1
+v 1 0 1
+v -1 0 1
+
+o first
+f 1 2 3
+o second
+f 1 3 4
+"#
+        ).unwrap();
+
+        let red = crate::Scene::new().add_material(crate::Material::matte([1.0, 0.0, 0.0], 0.0));
+        let (triangles, groups) = TriangleMesh::from_obj(&quad, &[]);
+        assert_eq!(groups, vec![0, 1]);
+        let mesh = Mesh::with_materials(triangles, groups, vec![Some(red), None]);
+        let materials: Vec<Option<MaterialRef>> = TriangleIterator::new(&mesh)
+            .map(|triangle| triangle.material())
+            .collect();
+
+        assert_eq!(materials, vec![Some(red), None]);
+    }
 }
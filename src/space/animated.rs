@@ -0,0 +1,116 @@
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Quaternion, SquareMatrix, Vector3};
+use super::{Bounds, Transformation, transform::Trans};
+
+/// Number of moments at which motion bounds are sampled
+const BOUNDS_STEPS: usize = 32;
+
+/// A transformation that changes over time between two keyframes. In between,
+/// translation and scale are interpolated linearly and rotation spherically,
+/// so that spinning objects keep their shape.
+///
+/// See http://www.pbr-book.org/3ed-2018/Geometry_and_Transformations/Animating_Transformations.html
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    start: Transformation,
+    end: Transformation,
+    start_time: f64,
+    end_time: f64,
+
+    /// Translation, rotation and scale of each keyframe
+    components: [(Vector3<f64>, Quaternion<f64>, Matrix3<f64>); 2]
+}
+
+impl AnimatedTransform {
+    pub fn new(start: Transformation, start_time: f64, end: Transformation, end_time: f64) -> AnimatedTransform {
+        debug_assert!(start_time <= end_time);
+        let (t0, r0, s0) = decompose(start.matrix());
+        let (t1, mut r1, s1) = decompose(end.matrix());
+
+        // Take the shorter way around
+        if r0.s * r1.s + r0.v.dot(r1.v) < 0.0 { r1 = -r1 }
+
+        AnimatedTransform {
+            start, end, start_time, end_time,
+            components: [(t0, r0, s0), (t1, r1, s1)]
+        }
+    }
+
+    /// Transformation at the given time. Times outside the keyframes hold the
+    /// nearest one.
+    pub fn interpolate(&self, time: f64) -> Transformation {
+        if time <= self.start_time { return self.start.clone() }
+        if time >= self.end_time { return self.end.clone() }
+
+        let dt = (time - self.start_time) / (self.end_time - self.start_time);
+        let [(t0, r0, s0), (t1, r1, s1)] = &self.components;
+        let t = t0 * (1.0 - dt) + t1 * dt;
+        let r = r0.slerp(*r1, dt);
+        let s = s0 * (1.0 - dt) + s1 * dt;
+
+        let m = Matrix4::from_translation(t) * Matrix4::from(Matrix3::from(r) * s);
+        match m.invert() {
+            Some(minv) => Transformation::new(m, minv),
+            None => self.start.clone()
+        }
+    }
+
+    /// Bounds of the given object-space bounds over the whole motion. Found by
+    /// sampling the motion, so bounds of fast rotations may be slightly loose.
+    pub fn motion_bounds(&self, bounds: Bounds) -> Bounds {
+        (0..=BOUNDS_STEPS).fold(Bounds::none(), |motion, i| {
+            let time = self.start_time + (self.end_time - self.start_time) * i as f64 / BOUNDS_STEPS as f64;
+            motion.union(&self.interpolate(time).transform_bounds(bounds))
+        })
+    }
+}
+
+/// Split the given affine matrix into translation, rotation and scale such
+/// that m = T R S. The rotation is found by polar decomposition, with any
+/// reflection left in the scale.
+fn decompose(m: &Matrix4<f64>) -> (Vector3<f64>, Quaternion<f64>, Matrix3<f64>) {
+    let t = m.w.truncate();
+    let m3 = Matrix3::from_cols(m.x.truncate(), m.y.truncate(), m.z.truncate());
+
+    // Average the matrix with its inverse transpose until it converges
+    let mut r = m3;
+    for _ in 0..100 {
+        let next = match r.transpose().invert() {
+            Some(inv) => (r + inv) * 0.5,
+            None => break
+        };
+        let norm = (0..3).map(|i| {
+            let d = r[i] - next[i];
+            d.x.abs() + d.y.abs() + d.z.abs()
+        }).fold(0.0, f64::max);
+        r = next;
+        if norm < 1e-10 { break }
+    }
+    if r.determinant() < 0.0 { r = -r }
+
+    let s = r.transpose() * m3;
+    (t, Quaternion::from(r), s)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cgmath::{Deg, Point3, Transform};
+
+    fn rigid(x: f64, angle: f64) -> Transformation {
+        Transformation::from_matrix(Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)) * Matrix4::from_angle_y(Deg(angle)))
+    }
+
+    #[test]
+    fn interpolates_rotation_and_translation() {
+        let end = rigid(3.0, 90.0);
+        let motion = AnimatedTransform::new(rigid(1.0, 0.0), 0.0, end.clone(), 1.0);
+
+        let p = Point3::new(0.0, 0.0, 1.0);
+        let halfway = motion.interpolate(0.5).transform_point(p);
+        let expected = rigid(2.0, 45.0).transform_point(p);
+        assert!((halfway - expected).magnitude() < 1e-9, "{:?} != {:?}", halfway, expected);
+
+        let last = motion.interpolate(2.0).transform_point(p);
+        assert!((last - end.transform_point(p)).magnitude() < 1e-9);
+    }
+}
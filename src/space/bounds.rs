@@ -71,15 +71,15 @@ impl<S: BaseNum> Bounds3<S> {
     /// True if this instance overlaps with the given
     #[inline]
     pub fn overlaps(&self, other: &Self) -> bool {
-        all_coords_match!(self.min, other.max, |min, max| min >= max) &&
-        all_coords_match!(self.max, other.min, |max, min| max <= min)
+        all_coords_match!(self.min, other.max, |min, max| min <= max) &&
+        all_coords_match!(self.max, other.min, |max, min| max >= min)
     }
 
     /// Return true if the point is within the given bounds
     #[inline]
     pub fn contains(&self, p: Point3<S>) -> bool {
         all_coords_match!(p, self.min, |coord, min| coord >= min) &&
-        all_coords_match!(p, self.max, |coord, max| coord >= max)
+        all_coords_match!(p, self.max, |coord, max| coord <= max)
     }
 
     /// Return true if the point is within the bounds but not at the max edges
@@ -124,7 +124,7 @@ impl<S: BaseNum> Bounds3<S> {
     #[inline]
     pub fn maximum_extent(&self) -> usize {
         let d = self.diagonal();
-        if d.x > d.y && d.z > d.z { 0 }
+        if d.x > d.y && d.x > d.z { 0 }
         else if d.y > d.z { 1 }
         else { 2 }
     }
@@ -176,3 +176,73 @@ fn min<S: BaseNum>(a: S, b: S) -> S {
 fn max<S: BaseNum>(a: S, b: S) -> S {
     if a < b { b } else { a }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{Rng, SeedableRng, rngs::SmallRng};
+
+    fn random_bounds(rng: &mut SmallRng) -> Bounds3<f64> {
+        let mut point = || Point3::new(rng.gen_range(-10.0, 10.0), rng.gen_range(-10.0, 10.0), rng.gen_range(-10.0, 10.0));
+        Bounds3::new(point(), point())
+    }
+
+    fn random_t(rng: &mut SmallRng) -> Point3<f64> {
+        Point3::new(rng.gen(), rng.gen(), rng.gen())
+    }
+
+    #[test]
+    fn union_and_intersection() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let (a, b) = (random_bounds(&mut rng), random_bounds(&mut rng));
+            let union = a.union(&b);
+            for i in 0..8 {
+                assert!(union.contains(a.corner(i)) && union.contains(b.corner(i)));
+            }
+            assert!(Bounds3::none().union(&a).min == a.min && Bounds3::none().union(&a).max == a.max);
+
+            // Boxes overlap exactly where their intersection isn't empty
+            let intersection = a.intersection(&b);
+            let empty = (0..3).any(|axis| intersection.min[axis] > intersection.max[axis]);
+            assert_eq!(a.overlaps(&b), !empty);
+            assert_eq!(a.overlaps(&b), b.overlaps(&a));
+            if !empty {
+                let p = intersection.lerp(&random_t(&mut rng));
+                assert!(a.contains(p) && b.contains(p));
+            }
+        }
+    }
+
+    #[test]
+    fn lerp_and_offset() {
+        let mut rng = SmallRng::seed_from_u64(2);
+        for _ in 0..1000 {
+            let bounds = random_bounds(&mut rng);
+            let t = random_t(&mut rng);
+            let p = bounds.lerp(&t);
+            assert!(bounds.contains(p));
+            assert!(bounds.point_union(&p).min == bounds.min && bounds.point_union(&p).max == bounds.max);
+            assert!((bounds.offset(&p) - t.to_vec()).magnitude() < 1e-9);
+            assert!(!bounds.contains_exclusive(bounds.max));
+        }
+    }
+
+    #[test]
+    fn measures() {
+        let mut rng = SmallRng::seed_from_u64(3);
+        for _ in 0..1000 {
+            let bounds = random_bounds(&mut rng);
+            let d = bounds.diagonal();
+            let axis = bounds.maximum_extent();
+            assert!((0..3).all(|other| d[axis] >= d[other]), "{:?} {}", d, axis);
+            assert!((bounds.volume() - d.x * d.y * d.z).abs() < 1e-9);
+            assert!((bounds.surface_area() - 2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)).abs() < 1e-9);
+
+            // Doubling the size of a box quadruples its area
+            let doubled = Bounds3::new(bounds.min, bounds.max + d);
+            assert!((doubled.surface_area() - 4.0 * bounds.surface_area()).abs() < 1e-6);
+            assert!((bounds.expand(1.0).diagonal() - d - Vector3::from_value(2.0)).magnitude() < 1e-9);
+        }
+    }
+}
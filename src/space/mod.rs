@@ -5,6 +5,7 @@ pub mod normal;
 pub mod bounds;
 pub mod transform;
 pub mod ray;
+pub mod animated;
 
 pub use self::transform::Trans;
 pub use self::ray::Ray;
@@ -24,6 +25,12 @@ pub fn abs(v: &Vector) -> Vector {
     v.map(|c| c.abs())
 }
 
+/// Perceived brightness of a linear RGB colour
+#[inline]
+pub fn luminance(c: &Color) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
 #[inline]
 pub fn lerp<N: BaseFloat>(t: N, p0: N, p1: N) -> N {
     p0 * (N::one() - t) + p1 * t
@@ -45,3 +52,66 @@ pub fn coordinate_system(v1: &Vector) -> (Vector, Vector) {
     let v3 = v1.cross(v2);
     (v2, v3)
 }
+
+/// Conservative bound on the relative error accumulated over n floating-point
+/// operations, each of which rounds its result by at most half an ulp
+#[inline]
+pub fn gamma<N: BaseFloat>(n: u32) -> N {
+    let n = N::from(n).unwrap();
+    let e = N::epsilon() / (N::one() + N::one());
+    (n * e) / (N::one() - n * e)
+}
+
+/// Smallest float greater than v
+#[inline]
+pub fn next_float_up(v: f64) -> f64 {
+    if v.is_infinite() && v > 0.0 { return v }
+    let v = if v == -0.0 { 0.0 } else { v };
+    let bits = v.to_bits();
+    f64::from_bits(if v >= 0.0 { bits + 1 } else { bits - 1 })
+}
+
+/// Largest float less than v
+#[inline]
+pub fn next_float_down(v: f64) -> f64 {
+    if v.is_infinite() && v < 0.0 { return v }
+    let v = if v == 0.0 { -0.0 } else { v };
+    let bits = v.to_bits();
+    f64::from_bits(if v > 0.0 { bits - 1 } else { bits + 1 })
+}
+
+/// The given value in single precision, rounded down, for bounds stored in
+/// single precision that must still enclose their contents
+#[cfg(any(feature = "f32-bvh", feature = "gpu"))]
+#[inline]
+pub(crate) fn f32_round_down(x: f64) -> f32 {
+    let y = x as f32;
+    if f64::from(y) > x { y.next_down() } else { y }
+}
+
+/// The given value in single precision, rounded up
+#[cfg(any(feature = "f32-bvh", feature = "gpu"))]
+#[inline]
+pub(crate) fn f32_round_up(x: f64) -> f32 {
+    let y = x as f32;
+    if f64::from(y) < x { y.next_up() } else { y }
+}
+
+/// Origin for a ray leaving point p with absolute error bounds p_err in
+/// direction w. Moved along the geometric normal n just far enough that the
+/// ray cannot intersect the surface it leaves from, on the side of w.
+///
+/// http://www.pbr-book.org/3ed-2018/Shapes/Managing_Rounding_Error.html#RobustSpawnedRayOrigins
+#[inline]
+pub fn offset_ray_origin(p: &Point, p_err: &Vector, n: &Vector, w: &Vector) -> Point {
+    let d = abs(n).dot(*p_err);
+    let offset = if w.dot(*n) < 0.0 { -d * n } else { d * n };
+    let mut po = p + offset;
+
+    // Round away from p so the offset is not lost to rounding
+    for i in 0..3 {
+        if offset[i] > 0.0 { po[i] = next_float_up(po[i]) }
+        else if offset[i] < 0.0 { po[i] = next_float_down(po[i]) }
+    }
+    po
+}
@@ -21,7 +21,47 @@ pub struct Ray3<N: BaseFloat> {
     Ray direction except each component is inverted
     Used for optimizations
     */
-    pub dinv: Vector3<N>
+    pub dinv: Vector3<N>,
+
+    /**
+    Moment at which the ray is cast, for rendering moving objects. Within
+    the camera shutter interval
+    */
+    pub time: N,
+
+    /**
+    Wavelength in nanometres of the light carried by rays of spectral renders.
+    Zero for RGB renders
+    */
+    pub wavelength: N,
+
+    /**
+    Nearest ray equation parameter at which intersections count. Defaults to 0
+    */
+    pub t_min: N,
+
+    /**
+    Farthest ray equation parameter at which intersections count, e.g., the
+    distance to a light for shadow rays. Defaults to infinity
+    */
+    pub t_max: N,
+
+    /**
+    Rays through the neighbouring pixels, for estimating the footprint of the
+    ray on the surfaces it hits. None for rays that don't come from the
+    camera, which look up textures without filtering
+    */
+    pub differentials: Option<RayDifferentials<N>>
+}
+
+/// Offset rays one pixel over along the x and y axes of the image from a ray
+/// of the camera. See PBRT v3 section 2.5.1
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RayDifferentials<N: BaseFloat> {
+    pub rx_origin: Point3<N>,
+    pub ry_origin: Point3<N>,
+    pub rx_d: Vector3<N>,
+    pub ry_d: Vector3<N>
 }
 
 impl<N: BaseFloat> Ray3<N> {
@@ -29,7 +69,55 @@ impl<N: BaseFloat> Ray3<N> {
         let (zero, one) = (N::zero(), N::one());
         debug_assert!(d.x != zero || d.y != zero || d.z != zero);
         let dinv = Vector3::new(one/d.x, one/d.y, one/d.z);
-        Ray3 { origin, d, dinv }
+        Ray3 { origin, d, dinv, time: zero, wavelength: zero, t_min: zero, t_max: N::infinity(), differentials: None }
+    }
+
+    /// The same ray, cast at the given time
+    #[inline]
+    pub fn at_time(self, time: N) -> Ray3<N> {
+        Ray3 { time, ..self }
+    }
+
+    /// The same ray, carrying light of the given wavelength in nanometres
+    #[inline]
+    pub fn at_wavelength(self, wavelength: N) -> Ray3<N> {
+        Ray3 { wavelength, ..self }
+    }
+
+    /// The same ray, limited to intersections with parameter t in the given
+    /// range
+    #[inline]
+    pub fn within(self, t_min: N, t_max: N) -> Ray3<N> {
+        debug_assert!(t_min <= t_max);
+        Ray3 { t_min, t_max, ..self }
+    }
+
+    /// The same ray, with the given rays through the neighbouring pixels
+    #[inline]
+    pub fn with_differentials(self, rx: &Ray3<N>, ry: &Ray3<N>) -> Ray3<N> {
+        let differentials = RayDifferentials {
+            rx_origin: rx.origin, ry_origin: ry.origin, rx_d: rx.d, ry_d: ry.d
+        };
+        Ray3 { differentials: Some(differentials), ..self }
+    }
+
+    /// Move the differential rays closer to this one by the given factor, for
+    /// pixels sampled many times where each sample covers part of the pixel
+    pub fn scale_differentials(self, s: N) -> Ray3<N> {
+        let differentials = self.differentials.map(|diff| RayDifferentials {
+            rx_origin: self.origin + (diff.rx_origin - self.origin) * s,
+            ry_origin: self.origin + (diff.ry_origin - self.origin) * s,
+            rx_d: self.d + (diff.rx_d - self.d) * s,
+            ry_d: self.d + (diff.ry_d - self.d) * s
+        });
+        Ray3 { differentials, ..self }
+    }
+
+    /// Whether an intersection at the given parameter lies within the range
+    /// of the ray
+    #[inline]
+    pub fn in_range(&self, t: N) -> bool {
+        t > self.t_min && t <= self.t_max
     }
 }
 
@@ -39,7 +127,7 @@ impl<N: BaseFloat> Default for Ray3<N> {
         let origin = Point3::new(zero, zero, zero);
         let d = Vector3::new(one, one, one);
         let dinv = Vector3::new(one, one, one);
-        Ray3 { origin, d, dinv }
+        Ray3 { origin, d, dinv, time: zero, wavelength: zero, t_min: zero, t_max: N::infinity(), differentials: None }
     }
 }
 
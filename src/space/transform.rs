@@ -4,7 +4,7 @@ use cgmath::{
     BaseFloat, Deg,
     InnerSpace, num_traits::identities::Zero
 };
-use super::{normal::Normal3, ray::Ray3, bounds::Bounds3};
+use super::{normal::Normal3, ray::Ray3, bounds::Bounds3, gamma};
 use crate::interaction::surface::RayIntersection;
 
 /// Identity transformation
@@ -45,7 +45,7 @@ pub trait Trans<N: BaseFloat>: Transform<Point3<N>> {
 }
 
 /// A transformation for three-space constructs
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Transform3<N: BaseFloat> {
     m: Matrix4<N>,
     minv: Matrix4<N>
@@ -78,6 +78,12 @@ impl<N: BaseFloat> Transform3<N> {
         Transform3 { m, minv }
     }
 
+    /// The matrix that applies this transformation
+    #[inline]
+    pub fn matrix(&self) -> &Matrix4<N> {
+        &self.m
+    }
+
     pub fn inverse(t: &Self) -> Self {
         Transform3 { m: t.minv, minv: t.m }
     }
@@ -212,7 +218,7 @@ impl<N: BaseFloat> Trans<N> for Transform3<N> {
     fn transform_ray(&self, ray: Ray3<N>) -> Ray3<N> {
         let origin = self.m.transform_point(ray.origin);
         let d = self.m.transform_vector(ray.d);
-        Ray3::new(origin, d)
+        Ray3::new(origin, d).at_time(ray.time).within(ray.t_min, ray.t_max)
     }
 
     #[inline]
@@ -244,8 +250,17 @@ impl<N: BaseFloat> Trans<N> for Transform3<N> {
     -> RayIntersection<N> {
         let dpdu = self.transform_vector(isect.geometry.dpdu);
         let dpdv = self.transform_vector(isect.geometry.dpdv);
-        let mut isect_t = RayIntersection::new(isect.t, isect.uv, dpdu, dpdv);
+        let (p, p_err) = transform_point_with_error(&self.m, isect.p, isect.p_err);
+        let mut isect_t = RayIntersection::new(isect.t, p, isect.uv, dpdu, dpdv);
+        isect_t.p_err = p_err;
+        isect_t.p_object = isect.p_object;
+        isect_t.n_object = isect.n_object.or_else(|| Some(Normal3(isect.ns())));
         isect_t.set_material(isect.material);
+        isect_t.color = isect.color;
+        isect_t.id = isect.id;
+        isect_t.cull_backface = isect.cull_backface;
+        isect_t.casts_shadows = isect.casts_shadows;
+        isect_t.receives_shadows = isect.receives_shadows;
 
         // Transform surface shading if required
         if isect.geometry.dpdu != isect.surface.dpdu
@@ -279,7 +294,7 @@ impl<N: BaseFloat> Trans<N> for Transform3<N> {
     fn inverse_transform_ray(&self, ray: Ray3<N>) -> Ray3<N> {
         let origin = self.minv.transform_point(ray.origin);
         let d = self.minv.transform_vector(ray.d);
-        Ray3::new(origin, d)
+        Ray3::new(origin, d).at_time(ray.time).within(ray.t_min, ray.t_max)
     }
 
     #[inline]
@@ -287,7 +302,10 @@ impl<N: BaseFloat> Trans<N> for Transform3<N> {
     -> RayIntersection<N> {
         let dpdu = self.inverse_transform_vector(isect.geometry.dpdu).unwrap_or(Vector3::zero());
         let dpdv = self.inverse_transform_vector(isect.geometry.dpdv).unwrap_or(Vector3::zero());
-        let mut isect_inv = RayIntersection::new(isect.t, isect.uv, dpdu, dpdv);
+        let (p, p_err) = transform_point_with_error(&self.minv, isect.p, isect.p_err);
+        let mut isect_inv = RayIntersection::new(isect.t, p, isect.uv, dpdu, dpdv);
+        isect_inv.p_err = p_err;
+        isect_inv.color = isect.color;
 
         // Transform surface shading if required
         if isect.geometry.dpdu != isect.surface.dpdu
@@ -306,5 +324,20 @@ impl<N: BaseFloat> Trans<N> for Transform3<N> {
 
 }
 
+/// Transform point p with absolute error p_err by affine matrix m. Returns the
+/// transformed point and a conservative bound on its error, which includes
+/// both the carried error and the rounding error of the transformation.
+fn transform_point_with_error<N: BaseFloat>(m: &Matrix4<N>, p: Point3<N>, p_err: Vector3<N>)
+-> (Point3<N>, Vector3<N>) {
+    let g3: N = gamma(3);
+    let mut err = Vector3::zero();
+    for i in 0..3 {
+        let rounding = (m[0][i] * p.x).abs() + (m[1][i] * p.y).abs() + (m[2][i] * p.z).abs() + m[3][i].abs();
+        let carried = m[0][i].abs() * p_err.x + m[1][i].abs() * p_err.y + m[2][i].abs() * p_err.z;
+        err[i] = (g3 + N::one()) * carried + g3 * rounding;
+    }
+    (m.transform_point(p), err)
+}
+
 #[inline] fn min<S: BaseFloat>(a: S, b: S) -> S { if a < b { a } else { b } }
 #[inline] fn max<S: BaseFloat>(a: S, b: S) -> S { if a < b { b } else { a } }
@@ -0,0 +1,125 @@
+//! Spectral rendering with hero wavelength sampling. Each camera sample
+//! carries light of a few wavelengths spread evenly over the visible
+//! spectrum, starting from a randomly chosen hero wavelength. Paths are traced
+//! once for all of them, in RGB, and filtered by the colours of the sampled
+//! wavelengths. Where a path passes through a dispersive surface, it bends by
+//! the refractive index at the hero wavelength and only the hero's colour is
+//! kept.
+//!
+//! See "Hero Wavelength Spectral Sampling" by Wilkie et al.
+
+use crate::space::*;
+
+/// Shortest visible wavelength sampled, in nanometres
+pub(crate) const LAMBDA_MIN: f64 = 380.0;
+
+/// Longest visible wavelength sampled, in nanometres
+pub(crate) const LAMBDA_MAX: f64 = 720.0;
+
+/// Number of wavelengths carried by each camera sample
+const SAMPLED_WAVELENGTHS: usize = 4;
+
+/// Scales each channel of `wavelength_rgb` such that light of equal energy
+/// at all sampled wavelengths comes out white
+const WHITE_BALANCE: [f64; 3] = [2.64878, 3.34850, 3.50332];
+
+/// Wavelengths carried by a camera sample, the hero first
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Wavelengths {
+    lambda: [f64; SAMPLED_WAVELENGTHS],
+
+    /// Whether the path has passed through a dispersive surface, leaving only
+    /// the hero wavelength
+    dispersed: bool
+}
+
+impl Wavelengths {
+    /// Choose the hero wavelength for the given sample in [0, 1), followed by
+    /// the others at equal steps, wrapping around the visible range
+    pub fn sample(u: f64) -> Wavelengths {
+        let mut lambda = [0.0; SAMPLED_WAVELENGTHS];
+        for (i, lambda) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f64 / SAMPLED_WAVELENGTHS as f64).fract();
+            *lambda = LAMBDA_MIN + offset * (LAMBDA_MAX - LAMBDA_MIN);
+        }
+        Wavelengths { lambda, dispersed: false }
+    }
+
+    /// Wavelength that decides the direction of dispersed paths
+    #[inline]
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    /// Colour of the light of all wavelengths still carried, by which
+    /// radiance found for the sample is filtered
+    pub fn filter(&self) -> Color {
+        if self.dispersed { return wavelength_rgb(self.hero()) }
+        self.lambda.iter()
+            .fold(Color::zero(), |sum, lambda| sum + wavelength_rgb(*lambda))
+            / SAMPLED_WAVELENGTHS as f64
+    }
+
+    /// Wavelengths left after passing through a dispersive surface, along
+    /// with the factor that turns radiance filtered for these wavelengths
+    /// into radiance filtered for the remaining ones
+    pub fn disperse(&self) -> (Wavelengths, Color) {
+        if self.dispersed { return (*self, Color::from_value(1.0)) }
+        let dispersed = Wavelengths { dispersed: true, ..*self };
+        let (all, hero) = (self.filter(), dispersed.filter());
+        let ratio = Color::new(ratio(hero.x, all.x), ratio(hero.y, all.y), ratio(hero.z, all.z));
+        (dispersed, ratio)
+    }
+}
+
+#[inline]
+fn ratio(a: f64, b: f64) -> f64 {
+    if b == 0.0 { 0.0 } else { a / b }
+}
+
+/// Linear sRGB colour of light of the given wavelength in nanometres, white
+/// balanced for the sampled range. Some wavelengths are outside the sRGB
+/// gamut and have negative channels.
+pub(crate) fn wavelength_rgb(lambda: f64) -> Color {
+    // Multi-lobe fit of the CIE 1931 colour matching functions by Wyman,
+    // Sloan and Shirley
+    let x = 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8);
+
+    // XYZ to linear sRGB
+    Color::new(
+        (3.2406 * x - 1.5372 * y - 0.4986 * z) * WHITE_BALANCE[0],
+        (-0.9689 * x + 1.8758 * y + 0.0415 * z) * WHITE_BALANCE[1],
+        (0.0557 * x - 0.2040 * y + 1.0570 * z) * WHITE_BALANCE[2]
+    )
+}
+
+/// Piecewise Gaussian with different widths on either side of its mean
+#[inline]
+fn lobe(x: f64, mean: f64, below: f64, above: f64) -> f64 {
+    let t = (x - mean) / if x < mean { below } else { above };
+    (-0.5 * t * t).exp()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn white_light_stays_white() {
+        let n = 1000;
+        let mean = (0..n).fold(Color::zero(), |sum, i| {
+            sum + Wavelengths::sample((i as f64 + 0.5) / n as f64).filter()
+        }) / n as f64;
+        for channel in &[mean.x, mean.y, mean.z] {
+            assert!((channel - 1.0).abs() < 1e-3, "{:?}", mean);
+        }
+
+        let (dispersed, ratio) = Wavelengths::sample(0.3).disperse();
+        let all = Wavelengths::sample(0.3).filter();
+        let hero = dispersed.filter();
+        assert!((all.mul_element_wise(ratio) - hero).magnitude() < 1e-9);
+    }
+}
@@ -0,0 +1,164 @@
+//! Statistics about a scene's acceleration structure and the work done to
+//! render it, for finding out why a scene is slow. See `capture_with_stats`.
+
+use std::{cell::Cell, fmt, sync::Mutex, time::Duration};
+
+/// Shape of the bounding volume hierarchies built for a scene, including
+/// those of its groups and of each shared triangle mesh
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct BvhStats {
+    /// Number of hierarchies, one for the scene and each group and mesh
+    pub hierarchies: usize,
+
+    /// Number of interior nodes, each with up to four children, or eight with
+    /// the f32-bvh feature
+    pub nodes: usize,
+
+    /// Number of leaves
+    pub leaves: usize,
+
+    /// Number of primitives in the leaves. Each instance of a mesh counts as
+    /// one primitive, and the triangles of the mesh are counted once.
+    pub primitives: usize,
+
+    /// Most primitives in a single leaf
+    pub max_leaf_primitives: usize,
+
+    /// Depth of the deepest leaf in any one hierarchy, where the children of
+    /// the root are at depth 1
+    pub max_depth: usize
+}
+
+/// Statistics of a render, returned by `capture_with_stats`. Displays as a
+/// report of several lines.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RenderStats {
+    pub bvh: BvhStats,
+
+    /// Rays traced from the camera, one per sample
+    pub primary_rays: u64,
+
+    /// Rays traced for their nearest hit other than those from the camera,
+    /// such as reflections and bounces of a path, and the rays of auxiliary
+    /// passes
+    pub secondary_rays: u64,
+
+    /// Rays traced to test whether a light is visible from a point
+    pub shadow_rays: u64,
+
+    /// Number of BVH nodes visited, each testing the ray against the bounds
+    /// of all its children at once
+    pub node_tests: u64,
+
+    /// Number of ray-primitive intersection tests in the leaves of BVHs
+    pub primitive_tests: u64,
+
+    /// Time taken to build the acceleration structure
+    pub build_time: Duration,
+
+    /// Time taken to render the image, once the acceleration structure was
+    /// built
+    pub render_time: Duration
+}
+
+impl RenderStats {
+    /// Total number of rays traced
+    pub fn rays(&self) -> u64 {
+        self.primary_rays + self.secondary_rays + self.shadow_rays
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bvh = &self.bvh;
+        let rays = self.rays();
+        let per_ray = |count: u64| if rays > 0 { count as f64 / rays as f64 } else { 0. };
+        let seconds = self.render_time.as_secs_f64();
+        let rate = if seconds > 0. { rays as f64 / seconds / 1e6 } else { 0. };
+        writeln!(f, "Acceleration structure")?;
+        writeln!(f, "  Hierarchies        {}", bvh.hierarchies)?;
+        writeln!(f, "  Nodes              {} ({} leaves)", bvh.nodes, bvh.leaves)?;
+        writeln!(f, "  Primitives         {} (up to {} per leaf)", bvh.primitives, bvh.max_leaf_primitives)?;
+        writeln!(f, "  Max depth          {}", bvh.max_depth)?;
+        writeln!(f, "Rays")?;
+        writeln!(f, "  Primary            {}", self.primary_rays)?;
+        writeln!(f, "  Secondary          {}", self.secondary_rays)?;
+        writeln!(f, "  Shadow             {}", self.shadow_rays)?;
+        writeln!(f, "  Total              {} ({:.2}M per second)", rays, rate)?;
+        writeln!(f, "Intersection tests")?;
+        writeln!(f, "  BVH nodes          {} ({:.1} per ray)", self.node_tests, per_ray(self.node_tests))?;
+        writeln!(f, "  Primitives         {} ({:.1} per ray)", self.primitive_tests, per_ray(self.primitive_tests))?;
+        writeln!(f, "Time")?;
+        writeln!(f, "  Build              {:.3}s", self.build_time.as_secs_f64())?;
+        write!(f, "  Render             {:.3}s", seconds)
+    }
+}
+
+/// Work counted on a thread. Rays are counted where they're traced and tests
+/// in the BVH traversals; renders collect the counts of each tile.
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct Counts {
+    pub primary_rays: u64,
+
+    /// Rays traced for their nearest hit, including primary rays
+    pub rays: u64,
+    pub shadow_rays: u64,
+    pub node_tests: u64,
+    pub primitive_tests: u64
+}
+
+thread_local! {
+    static COUNTS: Cell<Counts> = const { Cell::new(Counts {
+        primary_rays: 0, rays: 0, shadow_rays: 0, node_tests: 0, primitive_tests: 0
+    }) };
+}
+
+/// Add to the counts of the current thread
+#[inline]
+pub(crate) fn record(f: impl FnOnce(&mut Counts)) {
+    COUNTS.with(|counts| {
+        let mut c = counts.get();
+        f(&mut c);
+        counts.set(c)
+    })
+}
+
+/// Counts of the current thread since the last call to `take`
+pub(crate) fn current() -> Counts {
+    COUNTS.with(Cell::get)
+}
+
+/// Counts of the current thread since the last call, which are reset
+pub(crate) fn take() -> Counts {
+    COUNTS.with(Cell::take)
+}
+
+/// Sum of the counts of the tiles of a render, from all its threads
+#[derive(Debug, Default)]
+pub(crate) struct Tally(Mutex<Counts>);
+
+impl Tally {
+    pub fn add(&self, counts: Counts) {
+        let mut sum = self.0.lock().unwrap();
+        sum.primary_rays += counts.primary_rays;
+        sum.rays += counts.rays;
+        sum.shadow_rays += counts.shadow_rays;
+        sum.node_tests += counts.node_tests;
+        sum.primitive_tests += counts.primitive_tests;
+    }
+
+    /// Statistics of the counted work
+    pub fn stats(self, bvh: BvhStats, build_time: Duration, render_time: Duration) -> RenderStats {
+        let counts = self.0.into_inner().unwrap();
+        RenderStats {
+            bvh,
+            primary_rays: counts.primary_rays,
+            secondary_rays: counts.rays.saturating_sub(counts.primary_rays),
+            shadow_rays: counts.shadow_rays,
+            node_tests: counts.node_tests,
+            primitive_tests: counts.primitive_tests,
+            build_time,
+            render_time
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use crate::{space::*, interaction::SurfaceInteraction};
+use super::Texture;
+
+/// Alternating squares of two colours in UV space. Box-filtered over the
+/// footprint of each lookup so that distant squares blend into grey rather
+/// than alias. See PBRT v3 section 10.5.1
+#[derive(Debug, Copy, Clone)]
+pub struct Checkerboard {
+    even: Color,
+    odd: Color,
+
+    /// Number of squares along each of the u and v axes
+    scale: f64
+}
+
+impl Checkerboard {
+    pub fn new(even: [f64; 3], odd: [f64; 3], scale: f64) -> Checkerboard {
+        Checkerboard { even: even.into(), odd: odd.into(), scale }
+    }
+}
+
+impl Texture for Checkerboard {
+    fn evaluate(&self, interaction: &SurfaceInteraction) -> Color {
+        let uv = interaction.uv * self.scale;
+        let (dudx, dvdx) = (interaction.duvdx.x, interaction.duvdx.y);
+        let (dudy, dvdy) = (interaction.duvdy.x, interaction.duvdy.y);
+        let du = dudx.abs().max(dudy.abs()) * self.scale;
+        let dv = dvdx.abs().max(dvdy.abs()) * self.scale;
+
+        // Point sample footprints within a single square
+        if (uv.x - du).floor() == (uv.x + du).floor() && (uv.y - dv).floor() == (uv.y + dv).floor() {
+            let square = uv.x.floor() + uv.y.floor();
+            return if square.rem_euclid(2.0) == 0.0 { self.even } else { self.odd }
+        }
+
+        // Fraction of the footprint in odd squares, from the fractions of it
+        // in odd columns and odd rows. Footprints spanning more than a square
+        // each way are left at an even mix.
+        let (u_odd, v_odd) = (odd_fraction(uv.x, du), odd_fraction(uv.y, dv));
+        let odd = if du > 1.0 || dv > 1.0 { 0.5 } else { u_odd + v_odd - 2.0 * u_odd * v_odd };
+        self.even * (1.0 - odd) + self.odd * odd
+    }
+}
+
+/// Fraction of the interval [x - dx, x + dx] that falls in odd unit
+/// intervals, such as [1, 2) and [3, 4)
+#[inline]
+fn odd_fraction(x: f64, dx: f64) -> f64 {
+    if dx == 0.0 { return x.floor().rem_euclid(2.0) }
+
+    // Length of odd intervals in [0, x]
+    let odd_length = |x: f64| (x / 2.0).floor() + 2.0 * (x / 2.0 - (x / 2.0).floor() - 0.5).max(0.0);
+    (odd_length(x + dx) - odd_length(x - dx)) / (2.0 * dx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interaction::RayIntersection;
+
+    #[test]
+    fn alternates() {
+        let ray = Ray::new(Point::new(0.0, 0.0, 1.0), -Vector::unit_z());
+        let checkers = Checkerboard::new([1.0, 1.0, 1.0], [0.0, 0.0, 0.0], 2.0);
+        let mut isect = RayIntersection::new(1.0, Point::new(0.0, 0.0, 0.0), Point2f::new(0.25, 0.25), Vector::unit_x(), Vector::unit_y());
+        assert_eq!(checkers.evaluate(&SurfaceInteraction::from(&ray, &isect)), Color::from_value(1.0));
+
+        isect.uv = Point2f::new(0.75, 0.25);
+        assert_eq!(checkers.evaluate(&SurfaceInteraction::from(&ray, &isect)), Color::zero());
+
+        isect.uv = Point2f::new(0.75, 0.75);
+        assert_eq!(checkers.evaluate(&SurfaceInteraction::from(&ray, &isect)), Color::from_value(1.0));
+    }
+
+    #[test]
+    fn blends_over_footprint() {
+        let checkers = Checkerboard::new([1.0, 1.0, 1.0], [0.0, 0.0, 0.0], 2.0);
+        let isect = RayIntersection::new(1.0, Point::new(0.0, 0.0, 0.0), Point2f::new(0.25, 0.25), Vector::unit_x(), Vector::unit_y());
+        let mut interaction = SurfaceInteraction::from(&Ray::new(Point::new(0.0, 0.0, 1.0), -Vector::unit_z()), &isect);
+
+        // A footprint within one square sees only that square
+        interaction.duvdx = Vector2f::new(0.1, 0.0);
+        interaction.duvdy = Vector2f::new(0.0, 0.1);
+        assert_eq!(checkers.evaluate(&interaction), Color::from_value(1.0));
+
+        // One spanning many squares sees an even mix of both
+        interaction.duvdx = Vector2f::new(4.0, 0.0);
+        interaction.duvdy = Vector2f::new(0.0, 4.0);
+        assert_eq!(checkers.evaluate(&interaction), Color::from_value(0.5));
+
+        // One straddling the edge between two columns sees each half
+        interaction.uv = Point2f::new(0.5, 0.25);
+        interaction.duvdx = Vector2f::new(0.1, 0.0);
+        interaction.duvdy = Vector2f::new(0.0, 0.01);
+        assert!((checkers.evaluate(&interaction) - Color::from_value(0.5)).magnitude() < 1e-9);
+    }
+}
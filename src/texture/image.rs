@@ -0,0 +1,298 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{space::*, interaction::SurfaceInteraction};
+use super::Texture;
+
+/// Longest the footprint of an EWA lookup may be relative to its width.
+/// Longer footprints are widened, blurring the texture a little along their
+/// length, to bound the number of texels read.
+const MAX_ANISOTROPY: f64 = 8.0;
+
+/// How image textures are filtered over the footprint of each lookup on the
+/// image, to avoid aliasing where the texture is seen from afar or at grazing
+/// angles. See PBRT v3 section 10.4
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureFilter {
+    /// The four texels nearest each lookup, regardless of its footprint
+    Bilinear,
+
+    /// Bilinear lookups in the two levels of the mipmap whose texels are
+    /// closest in size to the footprint, blended between. Blurs footprints
+    /// much longer than they are wide.
+    Trilinear,
+
+    /// Elliptically weighted average over the footprint in the level of the
+    /// mipmap matching its width. Keeps detail across footprints at grazing
+    /// angles, at a higher cost.
+    Ewa
+}
+
+/// Texture backed by a 2D grid of colour texels, mapped to UV coordinates in
+/// the range [0, 1] with (0, 0) at the bottom-left corner of the image. UVs
+/// outside that range repeat the image. Looked up from a mipmap of the image,
+/// filtered over the footprint of the ray on the surface.
+pub struct ImageTexture {
+    /// The image followed by versions of half the size of the one before,
+    /// down to a single texel
+    levels: Vec<Level>,
+
+    filter: TextureFilter
+}
+
+/// One level of the mipmap of an image texture
+struct Level {
+    width: u32,
+    height: u32,
+
+    /// Texels in row-major order, with the first row at the top of the image
+    texels: Vec<Color>
+}
+
+impl ImageTexture {
+    /// Create a new image texture from linear RGB texel values arranged in
+    /// row-major order. Must contain exactly width * height texels.
+    pub fn new(width: u32, height: u32, texels: Vec<Color>) -> ImageTexture {
+        debug_assert!(width > 0 && height > 0);
+        debug_assert!(texels.len() == width as usize * height as usize);
+        let mut levels = vec![Level { width, height, texels }];
+        while let Some(level) = levels.last().filter(|level| level.width > 1 || level.height > 1) {
+            let next = level.downsample();
+            levels.push(next);
+        }
+        ImageTexture { levels, filter: TextureFilter::Trilinear }
+    }
+
+    /// Create a new image texture from 8-bit RGBA pixel data in row-major
+    /// order. The alpha channel is ignored. Byte values are mapped linearly to
+    /// [0, 1], the same way rendered colours are mapped to pixels.
+    pub fn from_rgba(width: u32, height: u32, rgba: &[u8]) -> ImageTexture {
+        let texels = rgba.as_chunks::<4>().0.iter()
+            .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64) / 255.0)
+            .collect();
+        ImageTexture::new(width, height, texels)
+    }
+
+    /// Load an image file (such as a PNG) from the file system
+    #[cfg(feature = "bin")]
+    pub fn open(path: &std::path::Path) -> Result<ImageTexture, ::image::ImageError> {
+        let rgba = ::image::open(path)?.into_rgba8();
+        let (width, height) = rgba.dimensions();
+        Ok(ImageTexture::from_rgba(width, height, &rgba.into_raw()))
+    }
+
+    /// Load the alpha channel of an image file as a grayscale texture, for
+    /// use as an alpha mask. Images without an alpha channel are used as
+    /// they are.
+    #[cfg(feature = "bin")]
+    pub fn open_alpha(path: &std::path::Path) -> Result<ImageTexture, ::image::ImageError> {
+        let image = ::image::open(path)?;
+        if !image.color().has_alpha() { return ImageTexture::open(path) }
+        let rgba = image.into_rgba8();
+        let (width, height) = rgba.dimensions();
+        let texels = rgba.pixels().map(|p| Color::from_value(p[3] as f64 / 255.0)).collect();
+        Ok(ImageTexture::new(width, height, texels))
+    }
+
+    /// Set how lookups are filtered. Trilinear by default.
+    pub fn with_filter(self, filter: TextureFilter) -> ImageTexture {
+        ImageTexture { filter, ..self }
+    }
+
+    #[inline] pub fn width(&self) -> u32 { self.levels[0].width }
+    #[inline] pub fn height(&self) -> u32 { self.levels[0].height }
+    #[inline] pub fn filter(&self) -> TextureFilter { self.filter }
+
+    /// Bilinearly-filtered lookup of the full-size image at the given UV
+    /// coordinates
+    pub fn lookup(&self, uv: &Point2f) -> Color {
+        self.levels[0].bilinear(uv)
+    }
+
+    /// Lookup at the given UV coordinates, filtered over the footprint
+    /// spanned by the given changes in UV from one pixel of the image to the
+    /// next
+    pub fn filtered(&self, uv: &Point2f, duvdx: &Vector2f, duvdy: &Vector2f) -> Color {
+        match self.filter {
+            TextureFilter::Bilinear => self.lookup(uv),
+            TextureFilter::Trilinear => {
+                let width = 2.0 * duvdx.x.abs().max(duvdx.y.abs()).max(duvdy.x.abs()).max(duvdy.y.abs());
+                self.trilinear(uv, width)
+            },
+            TextureFilter::Ewa => self.ewa(uv, *duvdx, *duvdy)
+        }
+    }
+
+    /// Continuous mipmap level whose texels span the given width in UV space
+    #[inline]
+    fn level_of(&self, width: f64) -> f64 {
+        let size = self.width().max(self.height()) as f64;
+        let last = (self.levels.len() - 1) as f64;
+        (width * size).max(1e-8).log2().clamp(0.0, last)
+    }
+
+    /// Blend of bilinear lookups in the two levels nearest the one whose
+    /// texels span the given width in UV space
+    fn trilinear(&self, uv: &Point2f, width: f64) -> Color {
+        let level = self.level_of(width);
+        let (below, t) = (level.floor() as usize, level.fract());
+        let sample = self.levels[below].bilinear(uv);
+        if t == 0.0 { return sample }
+        sample * (1.0 - t) + self.levels[below + 1].bilinear(uv) * t
+    }
+
+    /// Elliptically weighted average over the ellipse with the given axes in
+    /// UV space, blended between the two levels nearest its width
+    fn ewa(&self, uv: &Point2f, duvdx: Vector2f, duvdy: Vector2f) -> Color {
+        let (mut major, mut minor) = if duvdx.magnitude2() < duvdy.magnitude2() {
+            (duvdy, duvdx)
+        } else {
+            (duvdx, duvdy)
+        };
+        let (major_length, mut minor_length) = (major.magnitude(), minor.magnitude());
+
+        // Widen overly eccentric ellipses
+        if minor_length * MAX_ANISOTROPY < major_length && minor_length > 0.0 {
+            let scale = major_length / (minor_length * MAX_ANISOTROPY);
+            minor *= scale;
+            minor_length *= scale;
+        }
+        if minor_length == 0.0 { return self.lookup(uv) }
+
+        // Flip v to match the rows of the texels
+        let st = Point2f::new(uv.x, 1.0 - uv.y);
+        major.y = -major.y;
+        minor.y = -minor.y;
+
+        let level = self.level_of(minor_length);
+        let (below, t) = (level.floor() as usize, level.fract());
+        let sample = self.levels[below].ewa(st, major, minor);
+        if t == 0.0 { return sample }
+        sample * (1.0 - t) + self.levels[below + 1].ewa(st, major, minor) * t
+    }
+}
+
+impl Level {
+    /// Texel at the given integer coordinates, wrapping around the edges
+    #[inline]
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.texels[y * self.width as usize + x]
+    }
+
+    /// Level of half the size, each texel the average of the two by two
+    /// texels it covers. Odd sizes round up, wrapping around the edges.
+    fn downsample(&self) -> Level {
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let texels = (0..height as i64)
+            .flat_map(|y| (0..width as i64).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (x, y) = (2 * x, 2 * y);
+                (self.texel(x, y) + self.texel(x + 1, y) + self.texel(x, y + 1) + self.texel(x + 1, y + 1)) * 0.25
+            })
+            .collect();
+        Level { width, height, texels }
+    }
+
+    /// Bilinearly-filtered lookup at the given UV coordinates
+    fn bilinear(&self, uv: &Point2f) -> Color {
+        // Continuous texel coordinates, flipped so that v points up
+        let x = uv.x * self.width as f64 - 0.5;
+        let y = (1.0 - uv.y) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        self.texel(x0, y0) * ((1.0 - dx) * (1.0 - dy))
+        + self.texel(x0 + 1, y0) * (dx * (1.0 - dy))
+        + self.texel(x0, y0 + 1) * ((1.0 - dx) * dy)
+        + self.texel(x0 + 1, y0 + 1) * (dx * dy)
+    }
+
+    /// Gaussian-weighted average of the texels within the ellipse centred at
+    /// st, in texture coordinates with t pointing down, with the given axes
+    fn ewa(&self, st: Point2f, major: Vector2f, minor: Vector2f) -> Color {
+        let (w, h) = (self.width as f64, self.height as f64);
+        let (s, t) = (st.x * w - 0.5, st.y * h - 0.5);
+        let (major, minor) = (Vector2f::new(major.x * w, major.y * h), Vector2f::new(minor.x * w, minor.y * h));
+
+        // Implicit equation of the ellipse, a s² + b s t + c t² = 1, widened
+        // by a texel so that it covers at least one
+        let a = major.y * major.y + minor.y * minor.y + 1.0;
+        let b = -2.0 * (major.x * major.y + minor.x * minor.y);
+        let c = major.x * major.x + minor.x * minor.x + 1.0;
+        let f = a * c - b * b * 0.25;
+        let (a, b, c) = (a / f, b / f, c / f);
+
+        // Bounding box of the ellipse in texels
+        let det = 4.0 * a * c - b * b;
+        let (s_radius, t_radius) = (2.0 * (det * c).sqrt() / det, 2.0 * (det * a).sqrt() / det);
+        let (s0, s1) = ((s - s_radius).ceil() as i64, (s + s_radius).floor() as i64);
+        let (t0, t1) = ((t - t_radius).ceil() as i64, (t + t_radius).floor() as i64);
+
+        let (mut sum, mut weights) = (Color::zero(), 0.0);
+        for y in t0..=t1 {
+            let dt = y as f64 - t;
+            for x in s0..=s1 {
+                let ds = x as f64 - s;
+                let r2 = a * ds * ds + b * ds * dt + c * dt * dt;
+                if r2 < 1.0 {
+                    let weight = (-2.0 * r2).exp() - (-2.0f64).exp();
+                    sum += self.texel(x, y) * weight;
+                    weights += weight;
+                }
+            }
+        }
+        if weights > 0.0 { sum / weights } else { self.bilinear(&Point2f::new(st.x, 1.0 - st.y)) }
+    }
+}
+
+impl Texture for ImageTexture {
+    #[inline]
+    fn evaluate(&self, interaction: &SurfaceInteraction) -> Color {
+        self.filtered(&interaction.uv, &interaction.duvdx, &interaction.duvdy)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookup_texel_centers() {
+        let rgba = [
+            255, 0, 0, 255,  0, 255, 0, 255,
+            0, 0, 255, 255,  255, 255, 255, 255
+        ];
+        let texture = ImageTexture::from_rgba(2, 2, &rgba);
+
+        // Top-left texel is at the top of UV space
+        assert_eq!(texture.lookup(&Point2f::new(0.25, 0.75)), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(texture.lookup(&Point2f::new(0.75, 0.75)), Color::new(0.0, 1.0, 0.0));
+        assert_eq!(texture.lookup(&Point2f::new(0.25, 0.25)), Color::new(0.0, 0.0, 1.0));
+        assert_eq!(texture.lookup(&Point2f::new(0.75, 0.25)), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn filters_over_footprint() {
+        // Checkerboard of black and white texels, 8 on each side
+        let checkers = |filter| {
+            let texels = (0..64).map(|i| Color::from_value(((i / 8 + i % 8) % 2) as f64)).collect();
+            ImageTexture::new(8, 8, texels).with_filter(filter)
+        };
+        let uv = Point2f::new(0.0625, 0.0625);
+        let (small, large) = (Vector2f::new(0.01, 0.0), Vector2f::new(0.0, 0.5));
+        for &filter in [TextureFilter::Trilinear, TextureFilter::Ewa].iter() {
+            let texture = checkers(filter);
+
+            // Small footprints see the texel, large ones the average of many
+            let near = texture.filtered(&uv, &small, &Vector2f::new(0.0, 0.01));
+            assert!((near - texture.lookup(&uv)).magnitude() < 0.1, "{:?}", filter);
+            let far = texture.filtered(&uv, &large, &Vector2f::new(0.5, 0.0));
+            assert!((far - Color::from_value(0.5)).magnitude() < 0.05, "{:?}", filter);
+        }
+        let texture = checkers(TextureFilter::Bilinear);
+        assert_eq!(texture.filtered(&uv, &large, &large), texture.lookup(&uv));
+    }
+}
@@ -0,0 +1,87 @@
+//! Textures provide spatially-varying material parameters, looked up at the
+//! point of a surface interaction (usually using its UV coordinates, or by a
+//! projection of the point onto the texture).
+
+use crate::{space::*, interaction::SurfaceInteraction, scene::Scene};
+
+pub mod checkerboard;
+pub mod image;
+pub mod noise;
+pub mod projection;
+
+pub use self::checkerboard::Checkerboard;
+pub use self::image::{ImageTexture, TextureFilter};
+pub use self::noise::{Noise, NoisePattern};
+pub use self::projection::{Projection, Axis};
+
+/// A colour-valued function over the surface of a shape
+pub trait Texture: Send + Sync {
+    /// Evaluate the texture at the given point of interaction
+    fn evaluate(&self, interaction: &SurfaceInteraction) -> Color;
+}
+
+/// Opaque reference to a texture added to a scene, along with how the texture
+/// is projected onto the surfaces that use it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureRef {
+    pub(crate) index: usize,
+    projection: Projection
+}
+
+impl TextureRef {
+    pub(crate) fn new(index: usize) -> TextureRef {
+        TextureRef { index, projection: Projection::Uv }
+    }
+
+    /// The same texture, projected onto surfaces in the given way rather than
+    /// by their UV coordinates
+    pub fn projected(self, projection: Projection) -> TextureRef {
+        TextureRef { projection, ..self }
+    }
+
+    #[inline]
+    pub fn projection(&self) -> Projection { self.projection }
+
+    /// Look up the texture at the given point of interaction
+    #[inline]
+    pub fn evaluate(&self, interaction: &SurfaceInteraction, scene: &Scene) -> Color {
+        self.projection.evaluate(scene.texture(*self), interaction)
+    }
+}
+
+/// A texture that evaluates to the same colour everywhere. Mostly useful for
+/// custom textures that compose other textures.
+#[derive(Debug, Copy, Clone)]
+pub struct ConstantTexture(pub Color);
+
+impl Texture for ConstantTexture {
+    #[inline]
+    fn evaluate(&self, _interaction: &SurfaceInteraction) -> Color { self.0 }
+}
+
+/// A colour-valued material parameter. Either a constant colour or a reference
+/// to a texture in the scene that gets evaluated at each point of interaction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Param {
+    Constant(Color),
+    Texture(TextureRef)
+}
+
+impl Param {
+    /// Look up the value of the parameter at the given point of interaction
+    #[inline]
+    pub fn evaluate(&self, interaction: &SurfaceInteraction, scene: &Scene) -> Color {
+        match self {
+            Param::Constant(color) => *color,
+            Param::Texture(texture) => texture.evaluate(interaction, scene)
+        }
+    }
+}
+
+impl From<[f64; 3]> for Param {
+    fn from(color: [f64; 3]) -> Param { Param::Constant(color.into()) }
+}
+
+impl From<TextureRef> for Param {
+    fn from(texture: TextureRef) -> Param { Param::Texture(texture) }
+}
@@ -0,0 +1,222 @@
+//! Procedural textures made of noise, evaluated at the point of interaction in
+//! object space so that patterns stay on surfaces as they move and need no UV
+//! coordinates. Based on PBRT v3 section 10.6 and "A Cellular Texture Basis
+//! Function" by Worley.
+
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+
+use crate::{space::*, interaction::SurfaceInteraction};
+use super::Texture;
+
+/// Default number of octaves of noise summed by fractal patterns
+const DEFAULT_OCTAVES: u32 = 6;
+
+/// Gradients of Perlin noise at lattice points, the vectors from the centre
+/// of a cube to the middle of its edges
+const GRADIENTS: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [1.0, -1.0, 0.0], [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0], [-1.0, 0.0, 1.0], [1.0, 0.0, -1.0], [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0], [0.0, -1.0, 1.0], [0.0, 1.0, -1.0], [0.0, -1.0, -1.0]
+];
+
+/// Shape of the pattern given by a noise texture
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoisePattern {
+    /// Smooth random bumps about one unit across
+    Perlin,
+
+    /// Fractal Brownian motion: octaves of Perlin noise of ever smaller size,
+    /// such as for clouds
+    Fbm,
+
+    /// Sum of the absolute values of octaves of Perlin noise, with sharp
+    /// creases such as for flames
+    Turbulence,
+
+    /// Veins of turbulence along the x axis
+    Marble,
+
+    /// Rings of slightly irregular width around the y axis
+    Wood,
+
+    /// Distance to the nearest of points scattered one per unit cube, giving
+    /// cells such as for scales or cobblestones
+    Worley
+}
+
+/// Procedural texture blending between two colours by the value of a noise
+/// pattern at each point
+#[derive(Debug, Copy, Clone)]
+pub struct Noise {
+    pattern: NoisePattern,
+
+    /// Colours where the pattern is zero and one
+    low: Color,
+    high: Color,
+
+    /// Number of pattern features per unit of object space
+    scale: f64,
+
+    /// Octaves of noise summed by fractal patterns
+    octaves: u32
+}
+
+impl Noise {
+    pub fn new(pattern: NoisePattern, low: [f64; 3], high: [f64; 3], scale: f64) -> Noise {
+        Noise { pattern, low: low.into(), high: high.into(), scale, octaves: DEFAULT_OCTAVES }
+    }
+
+    /// Set the number of octaves of noise summed by the fractal patterns:
+    /// fractal Brownian motion, turbulence, marble and wood. More octaves add
+    /// finer detail.
+    pub fn with_octaves(self, octaves: u32) -> Noise {
+        Noise { octaves: octaves.max(1), ..self }
+    }
+
+    /// Value of the pattern at the given point in object space, in [0, 1]
+    pub fn value(&self, p: Point) -> f64 {
+        let p = p * self.scale;
+        let value = match self.pattern {
+            NoisePattern::Perlin => 0.5 + 0.5 * perlin(p),
+            NoisePattern::Fbm => 0.5 + 0.5 * fbm(p, self.octaves),
+            NoisePattern::Turbulence => turbulence(p, self.octaves),
+            NoisePattern::Marble => 0.5 + 0.5 * (PI * (p.x + 2.0 * turbulence(p, self.octaves))).sin(),
+            NoisePattern::Wood => {
+                let rings = (p.x * p.x + p.z * p.z).sqrt() + 0.2 * fbm(p, self.octaves);
+                rings.rem_euclid(1.0)
+            },
+            NoisePattern::Worley => worley(p)
+        };
+        value.clamp(0.0, 1.0)
+    }
+}
+
+impl Texture for Noise {
+    fn evaluate(&self, interaction: &SurfaceInteraction) -> Color {
+        let t = self.value(interaction.p_object);
+        self.low * (1.0 - t) + self.high * t
+    }
+}
+
+/// Gradient noise with value zero at each integer lattice point, roughly in
+/// [-1, 1]
+pub fn perlin(p: Point) -> f64 {
+    let cell = [p.x.floor(), p.y.floor(), p.z.floor()];
+    let (d, i) = (p - Point::new(cell[0], cell[1], cell[2]), cell.map(|c| c as i32));
+
+    // Contribution of the gradient at each corner of the cell
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let g = GRADIENTS[(hash(i[0] + dx, i[1] + dy, i[2] + dz) % 12) as usize];
+        let offset = d - Vector::new(dx as f64, dy as f64, dz as f64);
+        g[0] * offset.x + g[1] * offset.y + g[2] * offset.z
+    };
+
+    let (wx, wy, wz) = (smootherstep(d.x), smootherstep(d.y), smootherstep(d.z));
+    let lerp = |t: f64, a: f64, b: f64| a + t * (b - a);
+    let x00 = lerp(wx, corner(0, 0, 0), corner(1, 0, 0));
+    let x10 = lerp(wx, corner(0, 1, 0), corner(1, 1, 0));
+    let x01 = lerp(wx, corner(0, 0, 1), corner(1, 0, 1));
+    let x11 = lerp(wx, corner(0, 1, 1), corner(1, 1, 1));
+    lerp(wz, lerp(wy, x00, x10), lerp(wy, x01, x11))
+}
+
+/// Fractal Brownian motion: octaves of Perlin noise, each at twice the
+/// frequency and half the amplitude of the last
+pub fn fbm(p: Point, octaves: u32) -> f64 {
+    octaves_of(p, octaves, perlin)
+}
+
+/// Sum of the absolute values of octaves of Perlin noise
+pub fn turbulence(p: Point, octaves: u32) -> f64 {
+    octaves_of(p, octaves, |p| perlin(p).abs())
+}
+
+fn octaves_of(p: Point, octaves: u32, noise: impl Fn(Point) -> f64) -> f64 {
+    let (mut sum, mut frequency, mut amplitude) = (0.0, 1.0, 0.5);
+    for _ in 0..octaves {
+        sum += amplitude * noise(Point::from_vec(p.to_vec() * frequency));
+        frequency *= 2.0;
+        amplitude *= 0.5;
+    }
+    sum
+}
+
+/// Distance from the given point to the nearest feature point, with one
+/// feature point at a random place in each unit cube of the lattice
+pub fn worley(p: Point) -> f64 {
+    let cell = [p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32];
+    let mut nearest = f64::INFINITY;
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            for dz in -1..=1 {
+                let (x, y, z) = (cell[0] + dx, cell[1] + dy, cell[2] + dz);
+                let h = hash(x, y, z);
+                let feature = Point::new(
+                    x as f64 + unit(h),
+                    y as f64 + unit(h.rotate_left(11)),
+                    z as f64 + unit(h.rotate_left(22)));
+                nearest = nearest.min((feature - p).magnitude());
+            }
+        }
+    }
+    nearest
+}
+
+/// Pseudo-random integer for the lattice point at the given coordinates
+#[inline]
+fn hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^ (h >> 16)
+}
+
+/// Map the low bits of a hash to [0, 1)
+#[inline]
+fn unit(h: u32) -> f64 {
+    (h & 0x3ff) as f64 / 1024.0
+}
+
+/// Weight for interpolating between lattice points with continuous first and
+/// second derivatives
+#[inline]
+fn smootherstep(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn perlin_vanishes_on_lattice() {
+        for &(x, y, z) in &[(0.0, 0.0, 0.0), (3.0, -2.0, 7.0), (-5.0, 1.0, -1.0)] {
+            assert_eq!(perlin(Point::new(x, y, z)), 0.0);
+        }
+        assert!(perlin(Point::new(0.5, 0.3, 0.2)) != 0.0);
+    }
+
+    #[test]
+    fn patterns_stay_in_range() {
+        let patterns = [
+            NoisePattern::Perlin, NoisePattern::Fbm, NoisePattern::Turbulence,
+            NoisePattern::Marble, NoisePattern::Wood, NoisePattern::Worley
+        ];
+        for &pattern in patterns.iter() {
+            let noise = Noise::new(pattern, [0.0; 3], [1.0; 3], 3.0);
+            let values: Vec<f64> = (0..500)
+                .map(|i| i as f64 * 0.173)
+                .map(|t| noise.value(Point::new(t.sin() * 4.0, t * 0.1 - 2.0, t.cos() * 3.0)))
+                .collect();
+            assert!(values.iter().all(|v| (0.0..=1.0).contains(v)), "{:?}", pattern);
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            assert!(values.iter().any(|v| (v - mean).abs() > 0.05), "{:?} is flat", pattern);
+        }
+    }
+}
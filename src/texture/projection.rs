@@ -0,0 +1,135 @@
+//! Ways of mapping textures onto surfaces other than by their UV coordinates,
+//! for meshes without any and for shapes whose own UVs don't suit a texture.
+//! Projections work in the object space of the group that holds the surface,
+//! so textures stay put on surfaces as groups move.
+
+use std::f64::consts::PI;
+use serde::{Serialize, Deserialize};
+
+use crate::{space::*, interaction::SurfaceInteraction};
+use super::Texture;
+
+/// Axis of object space along or around which a texture is projected
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Axis { X, Y, Z }
+
+/// How the UV coordinates at which a texture is looked up are found for each
+/// point of a surface. Scales give the number of times the texture repeats
+/// per unit of object space.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Projection {
+    /// The UV coordinates of the surface itself
+    Uv,
+
+    /// Straight along the given axis, as by a slide projector
+    Planar { axis: Axis, scale: f64 },
+
+    /// From the origin outward, with u going around the y axis and v from
+    /// the bottom to the top
+    Spherical,
+
+    /// Outward from the y axis, with u going around it and v along it
+    Cylindrical { scale: f64 },
+
+    /// Along each of the three axes, blended by how much the surface faces
+    /// each. Larger sharpness narrows the blend between faces.
+    Triplanar { scale: f64, sharpness: f64 }
+}
+
+impl Projection {
+    /// Look up the texture at the given point of interaction. Projected
+    /// lookups aren't filtered, since the footprint of the ray is only known
+    /// in surface UVs.
+    pub fn evaluate(&self, texture: &dyn Texture, interaction: &SurfaceInteraction) -> Color {
+        let p = interaction.p_object;
+        let at = |uv: Point2f| {
+            let mut projected = *interaction;
+            projected.uv = uv;
+            projected.duvdx = Vector2f::zero();
+            projected.duvdy = Vector2f::zero();
+            texture.evaluate(&projected)
+        };
+
+        match *self {
+            Projection::Uv => texture.evaluate(interaction),
+            Projection::Planar { axis, scale } => at(planar(p, axis) * scale),
+            Projection::Spherical => {
+                let r = p.to_vec().magnitude();
+                let v = if r > 0.0 { 1.0 - (p.y / r).clamp(-1.0, 1.0).acos() / PI } else { 0.5 };
+                at(Point2f::new(around_y(p), v))
+            },
+            Projection::Cylindrical { scale } => at(Point2f::new(around_y(p), p.y * scale)),
+            Projection::Triplanar { scale, sharpness } => {
+                let n = interaction.n_object.0;
+                let weights = [n.x, n.y, n.z].map(|w| w.abs().powf(sharpness.max(1.0)));
+                let total: f64 = weights.iter().sum();
+                if total == 0.0 { return at(planar(p, Axis::Z) * scale) }
+                [Axis::X, Axis::Y, Axis::Z].iter().zip(weights.iter())
+                    .filter(|(_, &weight)| weight > 0.0)
+                    .fold(Color::zero(), |sum, (&axis, &weight)| {
+                        sum + at(planar(p, axis) * scale) * (weight / total)
+                    })
+            }
+        }
+    }
+}
+
+/// Coordinates of the point on the plane perpendicular to the given axis, in
+/// the order of the axes that follow it
+#[inline]
+fn planar(p: Point, axis: Axis) -> Point2f {
+    match axis {
+        Axis::X => Point2f::new(p.y, p.z),
+        Axis::Y => Point2f::new(p.z, p.x),
+        Axis::Z => Point2f::new(p.x, p.y)
+    }
+}
+
+/// Fraction of the way around the y axis to the given point, from the +z axis
+/// toward +x
+#[inline]
+fn around_y(p: Point) -> f64 {
+    let phi = p.x.atan2(p.z);
+    (if phi < 0.0 { phi + 2.0 * PI } else { phi }) / (2.0 * PI)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interaction::RayIntersection;
+
+    /// Evaluates to the UV coordinates of the interaction
+    struct Coordinates;
+    impl Texture for Coordinates {
+        fn evaluate(&self, interaction: &SurfaceInteraction) -> Color {
+            Color::new(interaction.uv.x, interaction.uv.y, 0.0)
+        }
+    }
+
+    fn lookup(projection: Projection, p: Point, n: Vector) -> Color {
+        let ray = Ray::new(p + n, -n);
+        let isect = RayIntersection::new(1.0, p, Point2f::new(0.0, 0.0), Vector::unit_x(), Vector::unit_y());
+        let mut interaction = SurfaceInteraction::from(&ray, &isect);
+        interaction.n_object = normal::Normal3(n);
+        projection.evaluate(&Coordinates, &interaction)
+    }
+
+    #[test]
+    fn projections() {
+        let p = Point::new(0.5, 0.25, 2.0);
+        let n = Vector::unit_z();
+        assert_eq!(lookup(Projection::Uv, p, n), Color::zero());
+        assert_eq!(lookup(Projection::Planar { axis: Axis::Z, scale: 2.0 }, p, n), Color::new(1.0, 0.5, 0.0));
+        assert_eq!(lookup(Projection::Planar { axis: Axis::Y, scale: 1.0 }, p, n), Color::new(2.0, 0.5, 0.0));
+        assert_eq!(lookup(Projection::Cylindrical { scale: 1.0 }, Point::new(0.0, 0.25, 1.0), n), Color::new(0.0, 0.25, 0.0));
+        assert_eq!(lookup(Projection::Spherical, Point::new(0.0, 1.0, 0.0), n), Color::new(0.0, 1.0, 0.0));
+
+        // Surfaces facing along one axis only take the projection along it
+        let triplanar = Projection::Triplanar { scale: 1.0, sharpness: 4.0 };
+        assert_eq!(lookup(triplanar, p, n), Color::new(0.5, 0.25, 0.0));
+        let diagonal = lookup(triplanar, p, Vector::new(1.0, 0.0, 1.0).normalize());
+        assert!((diagonal - Color::new(0.375, 1.125, 0.0)).magnitude() < 1e-9);
+    }
+}
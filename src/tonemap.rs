@@ -0,0 +1,78 @@
+use serde::{Serialize, Deserialize};
+
+/// Curve that maps scene radiance with unbounded range onto displayable
+/// values in [0, 1]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operator {
+    /// Clamp each channel to [0, 1]. Bright areas blow out to white
+    Clamp,
+
+    /// Reinhard's global operator, x / (1 + x). Compresses highlights evenly
+    /// but tends to desaturate the image
+    Reinhard,
+
+    /// Narkowicz's fit of the ACES filmic curve. Higher contrast than
+    /// Reinhard with a gentle highlight roll-off
+    Aces
+}
+
+/// Converts linear radiance into display values before they are quantized to
+/// pixel bytes. Applied in order: exposure, tone curve, gamma.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tonemap {
+    pub operator: Operator,
+
+    /// Exposure adjustment in stops. Each stop doubles the brightness
+    pub exposure: f64,
+
+    /// Display gamma. 1 leaves values linear; 2.2 approximates sRGB
+    pub gamma: f64
+}
+
+impl Tonemap {
+    pub fn new(operator: Operator, exposure: f64, gamma: f64) -> Tonemap {
+        debug_assert!(gamma > 0.);
+        Tonemap { operator, exposure, gamma }
+    }
+
+    /// Map the given linear RGB radiance to display values in [0, 1]
+    pub fn apply(&self, color: &[f64; 3]) -> [f64; 3] {
+        let scale = self.exposure.exp2();
+        let gamma_inv = 1. / self.gamma;
+        let map = |channel: f64| {
+            let x = (channel * scale).max(0.0);
+            let x = match self.operator {
+                Operator::Clamp => x,
+                Operator::Reinhard => x / (1.0 + x),
+                Operator::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+            };
+            x.min(1.0).powf(gamma_inv)
+        };
+        [map(color[0]), map(color[1]), map(color[2])]
+    }
+}
+
+impl Default for Tonemap {
+    /// Clamps linear radiance with no exposure or gamma adjustment
+    fn default() -> Self {
+        Tonemap::new(Operator::Clamp, 0., 1.)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn maps_into_display_range() {
+        let bright = [0.0, 0.5, 100.0];
+        for operator in [Operator::Clamp, Operator::Reinhard, Operator::Aces].iter() {
+            let mapped = Tonemap::new(*operator, 1., 2.2).apply(&bright);
+            assert!(mapped.iter().all(|c| *c >= 0.0 && *c <= 1.0));
+            assert!(mapped[0] < mapped[1] && mapped[1] <= mapped[2]);
+        }
+        assert_eq!(Tonemap::default().apply(&[0.25, 2.0, -1.0]), [0.25, 1.0, 0.0]);
+    }
+}
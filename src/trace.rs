@@ -0,0 +1,226 @@
+//! Traces of the paths taken by the samples of a single pixel, for finding out
+//! why it renders wrong. See `trace_pixel`.
+//!
+//! While a trace is running on a thread, the integrators record what happens
+//! to each ray they follow on that thread as a list of events. Depths count
+//! the bounces from the camera, so the events of the branching rays of the
+//! Whitted integrator form a tree in depth-first order.
+
+use std::cell::RefCell;
+use serde::Serialize;
+use crate::{space::*, img::Img, interaction::{SurfaceInteraction, RayIntersection}, sample_pixel, new_sampler, Accel, Scene};
+
+/// Record of the samples of a pixel, returned by `trace_pixel`. Serializes to
+/// JSON with serde.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PixelTrace {
+    pub x: u32,
+    pub y: u32,
+
+    /// Average radiance of the samples, as rendered before tonemapping
+    pub radiance: [f64; 3],
+    pub samples: Vec<SampleTrace>
+}
+
+impl PixelTrace {
+    /// The trace as pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// Events of a single camera sample of a pixel, in the order they happened
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SampleTrace {
+    /// Point within the pixel, from its bottom-left corner
+    pub position: [f64; 2],
+
+    /// Radiance the sample contributes to the pixel
+    pub radiance: [f64; 3],
+    pub events: Vec<TraceEvent>
+}
+
+/// Something that happened to a ray of a traced sample
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TraceEvent {
+    /// The ray hit a surface. The shading normal is the interpolated one,
+    /// before any bump mapping by the material.
+    Hit {
+        depth: u32,
+        origin: [f64; 3],
+        direction: [f64; 3],
+        point: [f64; 3],
+        distance: f64,
+        normal: [f64; 3],
+        shading_normal: [f64; 3],
+        uv: [f64; 2]
+    },
+
+    /// The ray left the scene, where the background has the given radiance
+    Miss {
+        depth: u32,
+        origin: [f64; 3],
+        direction: [f64; 3],
+        background: [f64; 3]
+    },
+
+    /// The ray scattered at a point within the scene's medium
+    Scatter {
+        depth: u32,
+        point: [f64; 3]
+    },
+
+    /// A sample point on a light that isn't in shadow, lighting the last hit.
+    /// The radiance is the light scattered toward the ray's origin. Lights
+    /// are numbered as in `Accel::lights`, the scene's lights followed by
+    /// emissive surfaces.
+    Light {
+        depth: u32,
+        light: usize,
+        position: [f64; 3],
+        radiance: [f64; 3]
+    },
+
+    /// A direction sampled from the BSDF at the last hit to continue along,
+    /// with the value of the BSDF and the probability density of sampling it
+    Bsdf {
+        depth: u32,
+        direction: [f64; 3],
+        f: [f64; 3],
+        pdf: f64,
+        specular: bool,
+        transmission: bool
+    },
+
+    /// Radiance added to the sample. With the path integrator, it's scaled
+    /// by the throughput of the path so far; with the Whitted integrator, it's
+    /// the light leaving the last hit before scaling by the reflections and
+    /// transmissions that led there.
+    Contribution {
+        depth: u32,
+        source: RadianceSource,
+        radiance: [f64; 3]
+    },
+
+    /// The path ended by Russian roulette
+    Roulette {
+        depth: u32
+    },
+
+    /// The path reached its maximum number of bounces
+    MaxDepth {
+        depth: u32
+    }
+}
+
+impl TraceEvent {
+    /// Event of the given ray hitting a surface at the given depth
+    pub(crate) fn hit(depth: u32, ray: &Ray, isect: &RayIntersection, interaction: &SurfaceInteraction) -> TraceEvent {
+        TraceEvent::Hit {
+            depth,
+            origin: ray.origin.into(),
+            direction: ray.d.normalize().into(),
+            point: interaction.p.into(),
+            distance: isect.t * ray.d.magnitude(),
+            normal: interaction.ng().into(),
+            shading_normal: interaction.ns().into(),
+            uv: interaction.uv.into()
+        }
+    }
+}
+
+/// Where the radiance of a contribution comes from
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RadianceSource {
+    Background,
+    Emission,
+    DirectLighting,
+    Ambient,
+    IrradianceCache
+}
+
+thread_local! {
+    static EVENTS: RefCell<Option<Vec<TraceEvent>>> = const { RefCell::new(None) };
+}
+
+/// Record the event made by the given function if a trace is running on the
+/// current thread
+#[inline]
+pub(crate) fn record(event: impl FnOnce() -> TraceEvent) {
+    EVENTS.with(|events| {
+        if let Some(events) = events.borrow_mut().as_mut() { events.push(event()) }
+    })
+}
+
+/// Call the given function without recording the events of the rays it
+/// traces, such as those gathering the light of a cache entry
+pub(crate) fn untraced<T>(f: impl FnOnce() -> T) -> T {
+    let events = EVENTS.with(|events| events.borrow_mut().take());
+    let result = f();
+    EVENTS.with(|slot| *slot.borrow_mut() = events);
+    result
+}
+
+/// Trace the samples of the pixel at the given x/y coordinates of an image of
+/// the scene at the given resolution. Samples are taken as when rendering the
+/// image, so the trace follows the same paths.
+pub fn trace_pixel(scene: &Scene, resolution: (u32, u32), x: u32, y: u32) -> PixelTrace {
+    let root = Accel::from(scene);
+    let frame = Frame { w: resolution.0, h: resolution.1 };
+    let mut sampler = new_sampler(scene, &scene.camera);
+    sampler.start_pixel((y as usize * frame.w as usize + x as usize) as u64);
+
+    let mut samples = vec![];
+    EVENTS.with(|events| *events.borrow_mut() = Some(vec![]));
+    let (radiance, _) = sample_pixel(&root, &scene.camera, x, y, &frame, &mut sampler, |u, radiance| {
+        let events = EVENTS.with(|events| events.borrow_mut().replace(vec![]).unwrap_or_default());
+        samples.push(SampleTrace { position: [u.x, u.y], radiance: (*radiance).into(), events })
+    });
+    EVENTS.with(|events| *events.borrow_mut() = None);
+
+    PixelTrace { x, y, radiance: radiance.into(), samples }
+}
+
+/// Dimensions of an image, for taking samples of it without a film
+struct Frame {
+    w: u32,
+    h: u32
+}
+
+impl Img for Frame {
+    fn w(&self) -> u32 { self.w }
+    fn h(&self) -> u32 { self.h }
+    fn set(&mut self, _x: u32, _y: u32, _color: &[f64; 3]) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Material, HdrFilm, capture};
+
+    #[test]
+    fn traces_samples_as_rendered() {
+        let mut scene = Scene::new();
+        scene.set_perspective_camera(30.0).look_at([0.0, 0.0, 5.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        scene.camera.set_samples_per_pixel(4);
+        let red = scene.add_material(Material::matte([0.8, 0.2, 0.2], 0.0));
+        scene.root.add_sphere([0.0, 0.0, 0.0], 1.0, red);
+        scene.add_point_light([2.0, 2.0, 4.0], [1.0, 1.0, 1.0], [1.0, 0.0, 0.0]);
+        let mut film = HdrFilm::new(8, 8);
+        capture(&scene, &mut film);
+
+        let trace = trace_pixel(&scene, (8, 8), 4, 4);
+        assert_eq!(trace.samples.len(), 4);
+        for (channel, value) in film.get(4, 4).iter().enumerate() {
+            assert!((trace.radiance[channel] - *value as f64).abs() < 1e-5);
+        }
+        for sample in &trace.samples {
+            assert!(matches!(sample.events[0], TraceEvent::Hit { depth: 0, .. }));
+            assert!(sample.events.iter().any(|event| matches!(event, TraceEvent::Light { depth: 0, light: 0, .. })));
+            assert!(sample.events.iter().any(|event| matches!(event, TraceEvent::Bsdf { depth: 0, specular: true, .. })));
+        }
+        assert!(trace.to_json().contains(r#""event": "contribution""#));
+    }
+}
@@ -15,18 +15,17 @@ async function main() {
     const setupWorker = () => {
         const worker = new Worker('./worker.js')
         worker.addEventListener('message', function (event) {
-            let imageData: ImageData
-
             switch (event.data.type) {
                 case 'status':
                     if (event.data.value !== 'ready') break
                     renderButton.removeAttribute('disabled')
                     break
-                case 'progress':
-                    const { output } = event.data.value
-                    imageData = new ImageData(new Uint8ClampedArray(output), width, height)
-                    requestAnimationFrame(() => ctx.putImageData(imageData, 0, 0))
+                case 'hunk': {
+                    const { x, y, w, h, data } = event.data.value
+                    const imageData = new ImageData(new Uint8ClampedArray(data), w, h)
+                    requestAnimationFrame(() => ctx.putImageData(imageData, x, y))
                     break
+                }
                 case 'done':
                     const { start, end } = event.data.value
                     console.log(`Render time: ${end - start}ms (${(end - start) / 1000}) sec`)
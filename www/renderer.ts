@@ -3,7 +3,7 @@ declare const self: DedicatedWorkerGlobalScope
 type Vec3f = [number, number, number]
 
 // @ts-ignore
-Promise.all([import('./lasgun'), import('./lasgun/index_bg')]).then(([lasgun, wasm]) => {
+import('./lasgun').then((lasgun) => {
     async function capture(sceneFunctionBody: string, width: number, height: number) {
         // Can't call directly because webpack rewrites this :(
         const AsyncFunction = new Function('return Object.getPrototypeOf(async function () {}).constructor')()
@@ -23,6 +23,17 @@ Promise.all([import('./lasgun'), import('./lasgun/index_bg')]).then(([lasgun, wa
             async obj(url: string): Promise<string> {
                 return await (await fetch(url)).text()
             },
+            // Decoded pixels of the image at the given URL, which may be a
+            // blob URL of an uploaded file. Pass its width, height and data
+            // to scene.add_image_texture or scene.set_environment_image.
+            async image(url: string): Promise<ImageData> {
+                const bitmap = await createImageBitmap(await (await fetch(url)).blob())
+                const { width, height } = bitmap
+                const ctx = new OffscreenCanvas(width, height).getContext('2d')
+                ctx.drawImage(bitmap, 0, 0)
+                bitmap.close()
+                return ctx.getImageData(0, 0, width, height)
+            },
             scene(settings: any): Lasgun.Scene {
                 let scene = lasgun.scene(settings)
                 allocations.push(scene)
@@ -48,6 +59,16 @@ Promise.all([import('./lasgun'), import('./lasgun/index_bg')]).then(([lasgun, wa
                 allocations.push(mat)
                 return mat
             },
+            matte_texture(kd: Lasgun.TextureRef, sigma?: number): Lasgun.Material {
+                let mat = lasgun.Material.matte_texture(kd, sigma || 0)
+                allocations.push(mat)
+                return mat
+            },
+            plastic_texture(kd: Lasgun.TextureRef, ks: Vec3f, roughness?: number): Lasgun.Material {
+                let mat = lasgun.Material.plastic_texture(kd, ks, roughness || 0)
+                allocations.push(mat)
+                return mat
+            },
             metal(settings: {
                 eta: Vec3f
                 k: Vec3f
@@ -98,26 +119,32 @@ Promise.all([import('./lasgun'), import('./lasgun/index_bg')]).then(([lasgun, wa
         const scene: Lasgun.Scene = exports.scene
         const start = Date.now()
 
-        const area = width * height
-        const subsets = shuffle(Array.from(Array(Math.min(100, area)).keys()))
-        const film = lasgun.film(width, height)
-        const startPtr = film.data_ptr()
-        const endPtr = startPtr + area * 4 // *4 because each pixel is 4 bytes
+        const hunks = shuffle(Array.from(Array(lasgun.hunk_count(width, height)).keys()))
+        // The accelerator takes over the scene and frees it along with itself
         const root = lasgun.Accel.from(scene)
 
-        // Stream bits of the scene over to main as we go
-        for (const k of subsets) {
-            lasgun.capture_subset(k, subsets.length, root, film)
-            self.postMessage({
-                type: 'progress',
-                value: {
-                    output: wasm.memory.buffer.slice(startPtr, endPtr),
-                    progress: (k + 1) / subsets.length
-                }
-            })
-        }
+        // Stream each hunk of the image over to main as it's done, handing
+        // over its buffer rather than copying it
+        hunks.forEach((k, i) => {
+            const hunk = lasgun.capture_hunk(k, root, width, height)
+            const data = hunk.data()
+            self.postMessage(
+                {
+                    type: 'hunk',
+                    value: {
+                        x: hunk.x,
+                        y: hunk.y,
+                        w: hunk.w,
+                        h: hunk.h,
+                        data: data.buffer,
+                        progress: (i + 1) / hunks.length
+                    }
+                },
+                [data.buffer]
+            )
+            hunk.free()
+        })
 
-        film.free()
         root.free()
         let end = Date.now()
 
@@ -163,7 +190,6 @@ Promise.all([import('./lasgun'), import('./lasgun/index_bg')]).then(([lasgun, wa
         'AsyncFunction',
         'console',
         // Just to be safe
-        'wasm',
         'capture',
         'unsafeGlobals',
         'sceneFunction'